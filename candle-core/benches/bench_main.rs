@@ -3,7 +3,9 @@ mod benchmarks;
 use criterion::criterion_main;
 criterion_main!(
     benchmarks::affine::benches,
+    benchmarks::binary::benches,
     benchmarks::matmul::benches,
     benchmarks::random::benches,
+    benchmarks::unary::benches,
     benchmarks::where_cond::benches
 );
@@ -0,0 +1,89 @@
+use crate::benchmarks::{BenchDevice, BenchDeviceHandler};
+use candle_core::{DType, Device, Tensor};
+use criterion::{black_box, criterion_group, Criterion, Throughput};
+use std::time::Instant;
+
+#[derive(Clone, Copy)]
+enum Rhs {
+    Contiguous,
+    Transposed,
+    BroadcastRow,
+    BroadcastCol,
+}
+
+fn run(a: &Tensor, b: &Tensor) {
+    a.broadcast_add(b).unwrap();
+}
+
+fn run_cmp(a: &Tensor, b: &Tensor) {
+    a.broadcast_ge(b).unwrap();
+}
+
+fn run_binary_benchmark(
+    c: &mut Criterion,
+    device: &Device,
+    dtype: DType,
+    rhs: Rhs,
+    cmp: bool,
+    name: &str,
+) {
+    let b = 1;
+    let m = 1024;
+    let k = 1024;
+
+    let lhs = Tensor::zeros((b, m, k), dtype, &device).unwrap();
+    let rhs = match rhs {
+        Rhs::Contiguous => Tensor::ones((b, m, k), dtype, &device).unwrap(),
+        Rhs::Transposed => Tensor::ones((b, k, m), dtype, &device)
+            .unwrap()
+            .transpose(1, 2)
+            .unwrap(),
+        Rhs::BroadcastRow => Tensor::ones((b, 1, k), dtype, &device).unwrap(),
+        Rhs::BroadcastCol => Tensor::ones((b, m, 1), dtype, &device).unwrap(),
+    };
+
+    let flops = 2 * b * m * k * dtype.size_in_bytes();
+
+    let mut group = c.benchmark_group(device.bench_name(name));
+    group.throughput(Throughput::Bytes(flops as u64));
+    group.bench_function("iter", move |b| {
+        b.iter_custom(|iters| {
+            let start = Instant::now();
+            for _i in 0..iters {
+                if cmp {
+                    run_cmp(black_box(&lhs), black_box(&rhs));
+                } else {
+                    run(black_box(&lhs), black_box(&rhs));
+                }
+            }
+            device.sync().unwrap();
+            start.elapsed()
+        })
+    });
+    group.finish();
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let handler = BenchDeviceHandler::new().unwrap();
+    for device in handler.devices {
+        for (rhs, name) in [
+            (Rhs::Contiguous, "binary_add_f32"),
+            (Rhs::Transposed, "binary_add_f32_transposed"),
+            (Rhs::BroadcastRow, "binary_add_f32_broadcast_row"),
+            (Rhs::BroadcastCol, "binary_add_f32_broadcast_col"),
+        ] {
+            run_binary_benchmark(c, &device, DType::F32, rhs, false, name);
+        }
+        run_binary_benchmark(
+            c,
+            &device,
+            DType::BF16,
+            Rhs::Contiguous,
+            false,
+            "binary_add_bf16",
+        );
+        run_binary_benchmark(c, &device, DType::F32, Rhs::Contiguous, true, "cmp_ge_f32");
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
@@ -1,6 +1,8 @@
 pub(crate) mod affine;
+pub(crate) mod binary;
 pub(crate) mod matmul;
 pub(crate) mod random;
+pub(crate) mod unary;
 pub(crate) mod where_cond;
 
 use candle_core::{Device, Result};
@@ -27,6 +29,12 @@ impl BenchDevice for Device {
                 #[cfg(not(feature = "metal"))]
                 panic!("Metal device without metal feature enabled: {:?}", device)
             }
+            Device::Wgpu(device) => {
+                #[cfg(feature = "wgpu")]
+                return device.synchronize();
+                #[cfg(not(feature = "wgpu"))]
+                panic!("Wgpu device without wgpu feature enabled: {:?}", device)
+            }
         }
     }
 
@@ -44,6 +52,7 @@ impl BenchDevice for Device {
             }
             Device::Cuda(_) => format!("cuda_{}", name.into()),
             Device::Metal(_) => format!("metal_{}", name.into()),
+            Device::Wgpu(_) => format!("wgpu_{}", name.into()),
         }
     }
 }
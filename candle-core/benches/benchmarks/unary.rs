@@ -0,0 +1,54 @@
+use crate::benchmarks::{BenchDevice, BenchDeviceHandler};
+use candle_core::{DType, Device, Tensor};
+use criterion::{black_box, criterion_group, Criterion, Throughput};
+use std::time::Instant;
+
+fn run(a: &Tensor) {
+    a.exp().unwrap();
+}
+
+fn run_unary_benchmark(
+    c: &mut Criterion,
+    device: &Device,
+    dtype: DType,
+    transposed: bool,
+    name: &str,
+) {
+    let b = 1;
+    let m = 1024;
+    let k = 1024;
+
+    let tensor = Tensor::zeros((b, m, k), dtype, &device).unwrap();
+    let tensor = if transposed {
+        tensor.transpose(1, 2).unwrap()
+    } else {
+        tensor
+    };
+
+    let flops = b * m * k * dtype.size_in_bytes();
+
+    let mut group = c.benchmark_group(device.bench_name(name));
+    group.throughput(Throughput::Bytes(flops as u64));
+    group.bench_function("iter", move |b| {
+        b.iter_custom(|iters| {
+            let start = Instant::now();
+            for _i in 0..iters {
+                run(black_box(&tensor));
+            }
+            device.sync().unwrap();
+            start.elapsed()
+        })
+    });
+    group.finish();
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let handler = BenchDeviceHandler::new().unwrap();
+    for device in handler.devices {
+        run_unary_benchmark(c, &device, DType::F32, false, "unary_exp_f32");
+        run_unary_benchmark(c, &device, DType::F32, true, "unary_exp_f32_transposed");
+        run_unary_benchmark(c, &device, DType::BF16, false, "unary_exp_bf16");
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
@@ -2,6 +2,7 @@ use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
 use candle_core::{Device, Result};
 use clap::{Parser, Subcommand, ValueEnum};
 use rayon::prelude::*;
+use std::collections::HashMap;
 
 #[derive(ValueEnum, Debug, Clone)]
 enum QuantizationMode {
@@ -11,18 +12,36 @@ enum QuantizationMode {
 }
 
 impl QuantizationMode {
-    fn quantize(&self, name: &str, tensor: QTensor, dtype: GgmlDType) -> Result<QTensor> {
+    fn quantize(
+        &self,
+        name: &str,
+        tensor: QTensor,
+        dtype: GgmlDType,
+        imatrix: Option<&[f32]>,
+    ) -> Result<QTensor> {
         match self {
             Self::Llama => {
                 // Same behavior as the llama.cpp quantization.
                 let should_quantize = name.ends_with(".weight") && tensor.rank() == 2;
                 if should_quantize {
                     let tensor = tensor.dequantize(&Device::Cpu)?;
-                    if name == "output.weight" {
-                        QTensor::quantize(&tensor, GgmlDType::Q6K)
+                    let dtype = if name == "output.weight" {
+                        GgmlDType::Q6K
                     } else {
-                        QTensor::quantize(&tensor, dtype)
-                    }
+                        dtype
+                    };
+                    let qtensor = match imatrix {
+                        Some(imatrix) => QTensor::quantize_with_imatrix(&tensor, dtype, imatrix)?,
+                        None => QTensor::quantize(&tensor, dtype)?,
+                    };
+                    let err = qtensor.quantization_error(&tensor)?;
+                    println!(
+                        "  {name} {dtype:?} rmse {:.6} (relative {:.4}) max error {:.6}",
+                        err.rmse,
+                        err.relative_rmse(),
+                        err.max_abs_error
+                    );
+                    Ok(qtensor)
                 } else {
                     Ok(tensor)
                 }
@@ -51,6 +70,10 @@ enum Quantization {
     Q5k,
     Q6k,
     Q8k,
+    #[value(name = "iq4_nl")]
+    Iq4Nl,
+    #[value(name = "iq4_xs")]
+    Iq4Xs,
     F16,
     F32,
 }
@@ -70,6 +93,8 @@ impl Quantization {
             Quantization::Q5k => GgmlDType::Q5K,
             Quantization::Q6k => GgmlDType::Q6K,
             Quantization::Q8k => GgmlDType::Q8K,
+            Quantization::Iq4Nl => GgmlDType::IQ4NL,
+            Quantization::Iq4Xs => GgmlDType::IQ4XS,
             Quantization::F16 => GgmlDType::F16,
             Quantization::F32 => GgmlDType::F32,
         }
@@ -132,6 +157,50 @@ enum Command {
         /// Which tensor to quantize.
         #[arg(long, value_enum, default_value_t = QuantizationMode::Llama)]
         mode: QuantizationMode,
+
+        /// An importance matrix as generated by llama.cpp's imatrix tool, used to weight the
+        /// quantization of the tensors that it covers. Only used when quantizing gguf files.
+        #[arg(long)]
+        imatrix: Option<std::path::PathBuf>,
+    },
+
+    /// Convert safetensors weights to a gguf file, quantizing each tensor according to some
+    /// rules. When a llama config is provided, the tensor names and metadata follow the
+    /// llama.cpp conventions.
+    Convert {
+        /// The input file(s), in safetensors format, e.g. the shards of a hub checkpoint or the
+        /// file written by `VarMap::save`.
+        in_file: Vec<std::path::PathBuf>,
+
+        /// The output file, in gguf format.
+        #[arg(long)]
+        out_file: std::path::PathBuf,
+
+        /// The quantization to use for the 2d weights that do not match any rule.
+        #[arg(long, value_enum)]
+        quantization: Quantization,
+
+        /// Per tensor quantization rules of the form `pattern=quantization`, e.g.
+        /// `--rule 'token_embd.*=q8_0'`. Patterns can use `*` wildcards and are matched against
+        /// the gguf tensor names, the first matching rule applies.
+        #[arg(long = "rule")]
+        rules: Vec<String>,
+
+        /// The config.json file of the model, used for the architecture metadata.
+        #[arg(long)]
+        config: Option<std::path::PathBuf>,
+
+        /// The tokenizer.json file of the model, used for the tokenizer metadata.
+        #[arg(long)]
+        tokenizer: Option<std::path::PathBuf>,
+
+        /// The alignment of the tensor data in bytes.
+        #[arg(long, default_value_t = gguf_file::DEFAULT_ALIGNMENT)]
+        alignment: u64,
+
+        /// The model name stored in the `general.name` metadata.
+        #[arg(long)]
+        name: Option<String>,
     },
 
     Dequantize {
@@ -286,6 +355,356 @@ fn run_quantize_safetensors(
     Ok(())
 }
 
+// Matches a tensor name against a pattern where `*` matches any sequence of characters.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let name = match name.strip_prefix(prefix) {
+                None => return false,
+                Some(name) => name,
+            };
+            (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| glob_match(rest, &name[i..]))
+        }
+    }
+}
+
+struct QuantizationRules {
+    rules: Vec<(String, GgmlDType)>,
+    default: GgmlDType,
+}
+
+impl QuantizationRules {
+    fn new(rules: &[String], default: Quantization) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let (pattern, q) = match rule.rsplit_once('=') {
+                    Some(v) => v,
+                    None => candle_core::bail!("rule {rule} is not of the form pattern=dtype"),
+                };
+                let q = match Quantization::from_str(q, true) {
+                    Ok(q) => q,
+                    Err(err) => candle_core::bail!("invalid quantization in rule {rule}: {err}"),
+                };
+                Ok((pattern.to_string(), q.dtype()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            rules,
+            default: default.dtype(),
+        })
+    }
+
+    // The user specified rules apply first, otherwise this uses the same defaults as llama.cpp:
+    // 1d tensors are kept in f32, the output tensor uses q6k, and the other 2d tensors use the
+    // default quantization. Tensors that cannot be split in blocks fall back to f16.
+    fn dtype(&self, name: &str, tensor: &candle_core::Tensor) -> GgmlDType {
+        let dtype = self
+            .rules
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, name))
+            .map(|(_, dtype)| *dtype);
+        let dtype = match dtype {
+            Some(dtype) => dtype,
+            None if tensor.rank() < 2 => GgmlDType::F32,
+            None if name == "output.weight" => GgmlDType::Q6K,
+            None => self.default,
+        };
+        let row_size = tensor.dims().last().copied().unwrap_or(1);
+        if row_size % dtype.block_size() == 0 {
+            dtype
+        } else {
+            GgmlDType::F16
+        }
+    }
+}
+
+fn llama_tensor_name(name: &str) -> Option<String> {
+    let name = match name {
+        "model.embed_tokens.weight" => "token_embd.weight".to_string(),
+        "model.norm.weight" => "output_norm.weight".to_string(),
+        "lm_head.weight" => "output.weight".to_string(),
+        name => {
+            let name = name.strip_prefix("model.layers.")?;
+            let (layer_idx, name) = name.split_once('.')?;
+            let name = match name {
+                "self_attn.q_proj.weight" => "attn_q.weight",
+                "self_attn.k_proj.weight" => "attn_k.weight",
+                "self_attn.v_proj.weight" => "attn_v.weight",
+                "self_attn.o_proj.weight" => "attn_output.weight",
+                "mlp.gate_proj.weight" => "ffn_gate.weight",
+                "mlp.up_proj.weight" => "ffn_up.weight",
+                "mlp.down_proj.weight" => "ffn_down.weight",
+                "input_layernorm.weight" => "attn_norm.weight",
+                "post_attention_layernorm.weight" => "ffn_norm.weight",
+                _ => return None,
+            };
+            format!("blk.{layer_idx}.{name}")
+        }
+    };
+    Some(name)
+}
+
+// The hub checkpoints use rotary embeddings on the two halves of each head whereas llama.cpp
+// uses interleaved rotary embeddings, so the rows of the query and key weights get permuted the
+// same way as in the llama.cpp conversion scripts.
+fn permute_for_rope(w: &candle_core::Tensor, n_head: usize) -> Result<candle_core::Tensor> {
+    let (out_dim, in_dim) = w.dims2()?;
+    w.reshape((n_head, 2, out_dim / n_head / 2, in_dim))?
+        .transpose(1, 2)?
+        .reshape((out_dim, in_dim))
+}
+
+fn json_usize(config: &serde_json::Value, key: &str) -> Result<usize> {
+    match config.get(key).and_then(|v| v.as_u64()) {
+        Some(v) => Ok(v as usize),
+        None => candle_core::bail!("missing or invalid {key} in config"),
+    }
+}
+
+fn llama_metadata(builder: &mut gguf_file::Builder, config: &serde_json::Value) -> Result<()> {
+    use gguf_file::Value;
+    let head_count = json_usize(config, "num_attention_heads")?;
+    let head_count_kv = json_usize(config, "num_key_value_heads").unwrap_or(head_count);
+    let hidden_size = json_usize(config, "hidden_size")?;
+    let rms_norm_eps = config["rms_norm_eps"].as_f64().unwrap_or(1e-5);
+    let rope_theta = config["rope_theta"].as_f64().unwrap_or(10000.);
+    let u32_entries = [
+        (
+            "context_length",
+            json_usize(config, "max_position_embeddings")?,
+        ),
+        ("embedding_length", hidden_size),
+        ("block_count", json_usize(config, "num_hidden_layers")?),
+        (
+            "feed_forward_length",
+            json_usize(config, "intermediate_size")?,
+        ),
+        ("rope.dimension_count", hidden_size / head_count),
+        ("attention.head_count", head_count),
+        ("attention.head_count_kv", head_count_kv),
+        ("vocab_size", json_usize(config, "vocab_size")?),
+    ];
+    builder.set_metadata("general.architecture", Value::String("llama".to_string()))?;
+    for (key, value) in u32_entries {
+        builder.set_metadata(format!("llama.{key}"), Value::U32(value as u32))?;
+    }
+    builder
+        .set_metadata(
+            "llama.attention.layer_norm_rms_epsilon",
+            Value::F32(rms_norm_eps as f32),
+        )?
+        .set_metadata("llama.rope.freq_base", Value::F32(rope_theta as f32))?;
+    for (key, gguf_key) in [
+        ("bos_token_id", "tokenizer.ggml.bos_token_id"),
+        ("eos_token_id", "tokenizer.ggml.eos_token_id"),
+        ("pad_token_id", "tokenizer.ggml.padding_token_id"),
+    ] {
+        if let Ok(id) = json_usize(config, key) {
+            builder.set_metadata(gguf_key, Value::U32(id as u32))?;
+        }
+    }
+    Ok(())
+}
+
+// Converts a tokenizer.json file using a BPE model to the llama.cpp tokenizer metadata. The
+// `llama` tokenizer (sentencepiece with byte fallback) relies on scores to prioritize merges,
+// these are approximated using the token ids.
+fn tokenizer_metadata(
+    builder: &mut gguf_file::Builder,
+    tokenizer: &serde_json::Value,
+) -> Result<()> {
+    use gguf_file::Value;
+    let model = &tokenizer["model"];
+    if model["type"].as_str() != Some("BPE") {
+        candle_core::bail!("only BPE tokenizers are supported, got {:?}", model["type"])
+    }
+    let byte_fallback = model["byte_fallback"].as_bool().unwrap_or(false);
+    let mut tokens: Vec<Option<(String, i32)>> = vec![];
+    let mut set_token = |id: usize, token: &str, token_type: i32| {
+        if tokens.len() <= id {
+            tokens.resize(id + 1, None)
+        }
+        tokens[id] = Some((token.to_string(), token_type))
+    };
+    if let Some(vocab) = model["vocab"].as_object() {
+        for (token, id) in vocab.iter() {
+            let id = match id.as_u64() {
+                Some(id) => id as usize,
+                None => candle_core::bail!("invalid id for token {token}"),
+            };
+            let is_byte = token.len() == 6 && token.starts_with("<0x") && token.ends_with('>');
+            let token_type = if byte_fallback && is_byte { 6 } else { 1 };
+            set_token(id, token, token_type)
+        }
+    }
+    let mut special_ids = HashMap::new();
+    if let Some(added_tokens) = tokenizer["added_tokens"].as_array() {
+        for added_token in added_tokens.iter() {
+            let (id, content) = match (added_token["id"].as_u64(), added_token["content"].as_str())
+            {
+                (Some(id), Some(content)) => (id as usize, content),
+                _ => continue,
+            };
+            let special = added_token["special"].as_bool().unwrap_or(false);
+            set_token(id, content, if special { 3 } else { 4 });
+            special_ids.insert(content.to_string(), id);
+        }
+    }
+    let (tokens, token_types): (Vec<_>, Vec<_>) = tokens
+        .into_iter()
+        .enumerate()
+        .map(|(id, token)| match token {
+            Some((token, token_type)) => (Value::String(token), Value::I32(token_type)),
+            // Unused ids are marked as such, similar to the llama.cpp padding tokens.
+            None => (Value::String(format!("[PAD{id}]")), Value::I32(5)),
+        })
+        .unzip();
+    let scores = (0..tokens.len())
+        .map(|id| Value::F32(-(id as f32)))
+        .collect::<Vec<_>>();
+    let model_name = if byte_fallback { "llama" } else { "gpt2" };
+    builder
+        .set_metadata(
+            "tokenizer.ggml.model",
+            Value::String(model_name.to_string()),
+        )?
+        .set_metadata("tokenizer.ggml.tokens", Value::Array(tokens))?
+        .set_metadata("tokenizer.ggml.token_type", Value::Array(token_types))?;
+    if byte_fallback {
+        builder.set_metadata("tokenizer.ggml.scores", Value::Array(scores))?;
+    }
+    if let Some(merges) = model["merges"].as_array() {
+        let merges = merges
+            .iter()
+            .filter_map(|merge| match merge {
+                serde_json::Value::String(merge) => Some(merge.to_string()),
+                serde_json::Value::Array(pair) => match (pair.first(), pair.get(1)) {
+                    (Some(serde_json::Value::String(a)), Some(serde_json::Value::String(b))) => {
+                        Some(format!("{a} {b}"))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .map(Value::String)
+            .collect::<Vec<_>>();
+        builder.set_metadata("tokenizer.ggml.merges", Value::Array(merges))?;
+    }
+    let unk_id = model["unk_token"]
+        .as_str()
+        .and_then(|t| model["vocab"][t].as_u64());
+    if let Some(unk_id) = unk_id {
+        builder.set_metadata("tokenizer.ggml.unknown_token_id", Value::U32(unk_id as u32))?;
+    }
+    // Only used when the config does not specify these ids.
+    for (token, key) in [
+        ("<s>", "tokenizer.ggml.bos_token_id"),
+        ("</s>", "tokenizer.ggml.eos_token_id"),
+    ] {
+        let is_set = builder.metadata().iter().any(|(k, _)| k == key);
+        if let (false, Some(id)) = (is_set, special_ids.get(token)) {
+            builder.set_metadata(key, Value::U32(*id as u32))?;
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_convert(
+    in_files: &[std::path::PathBuf],
+    out_file: std::path::PathBuf,
+    q: Quantization,
+    rules: &[String],
+    config: Option<std::path::PathBuf>,
+    tokenizer: Option<std::path::PathBuf>,
+    alignment: u64,
+    name: Option<String>,
+) -> Result<()> {
+    if in_files.is_empty() {
+        candle_core::bail!("no specified input files")
+    }
+    let rules = QuantizationRules::new(rules, q)?;
+    let read_json = |p: &std::path::Path| -> Result<serde_json::Value> {
+        let json = std::fs::read(p)?;
+        serde_json::from_slice(&json).map_err(candle_core::Error::wrap)
+    };
+    let mut builder = gguf_file::Builder::new();
+    builder.set_alignment(alignment)?;
+    if let Some(name) = name {
+        builder.set_metadata("general.name", gguf_file::Value::String(name))?;
+    }
+    // The number of query and key heads when converting a llama checkpoint.
+    let llama_heads = match config {
+        None => None,
+        Some(config) => {
+            let config = read_json(&config)?;
+            let is_llama = config["model_type"].as_str() == Some("llama");
+            if !is_llama {
+                candle_core::bail!("unsupported model type {:?}", config["model_type"])
+            }
+            llama_metadata(&mut builder, &config)?;
+            let n_head = json_usize(&config, "num_attention_heads")?;
+            let n_kv_head = json_usize(&config, "num_key_value_heads").unwrap_or(n_head);
+            Some((n_head, n_kv_head))
+        }
+    };
+    if let Some(tokenizer) = tokenizer {
+        tokenizer_metadata(&mut builder, &read_json(&tokenizer)?)?;
+    }
+
+    let st = unsafe { candle_core::safetensors::MmapedSafetensors::multi(in_files)? };
+    let mut names = vec![];
+    for (st_name, _) in st.tensors() {
+        let name = match llama_heads {
+            None => Some(st_name.clone()),
+            Some(_) => llama_tensor_name(&st_name),
+        };
+        match name {
+            Some(name) => names.push((st_name, name)),
+            None => println!("  skipping {st_name}"),
+        }
+    }
+    // Models with tied embeddings do not have a separate output tensor.
+    if llama_heads.is_some() && !names.iter().any(|(_, n)| n == "output.weight") {
+        names.push((
+            "model.embed_tokens.weight".to_string(),
+            "output.weight".to_string(),
+        ))
+    }
+    names.sort_by(|a, b| a.1.cmp(&b.1));
+    println!("tensors: {}", names.len());
+
+    let qtensors = names
+        .par_iter()
+        .map(|(st_name, name)| {
+            let tensor = st.load(st_name, &Device::Cpu)?;
+            let tensor = match llama_heads {
+                Some((n_head, _)) if name.ends_with(".attn_q.weight") => {
+                    permute_for_rope(&tensor, n_head)?
+                }
+                Some((_, n_kv_head)) if name.ends_with(".attn_k.weight") => {
+                    permute_for_rope(&tensor, n_kv_head)?
+                }
+                _ => tensor,
+            };
+            let tensor = tensor.to_dtype(candle_core::DType::F32)?;
+            let dtype = rules.dtype(name, &tensor);
+            println!("  quantizing {name} {:?} {dtype:?}", tensor.shape());
+            Ok((name, QTensor::quantize(&tensor, dtype)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for (name, qtensor) in qtensors {
+        builder.add_tensor(name.as_str(), qtensor)?;
+    }
+    let mut out_file = std::io::BufWriter::new(std::fs::File::create(out_file)?);
+    builder.write(&mut out_file)?;
+    Ok(())
+}
+
 fn run_dequantize(
     in_file: std::path::PathBuf,
     out_file: std::path::PathBuf,
@@ -308,6 +727,7 @@ fn run_quantize(
     out_file: std::path::PathBuf,
     q: Quantization,
     qmode: QuantizationMode,
+    imatrix: Option<std::path::PathBuf>,
     device: &Device,
 ) -> Result<()> {
     if in_files.is_empty() {
@@ -334,6 +754,17 @@ fn run_quantize(
     let content = gguf_file::Content::read(&mut in_)?;
     println!("tensors: {}", content.tensor_infos.len());
 
+    let imatrix = match imatrix {
+        None => HashMap::new(),
+        Some(imatrix) => {
+            let imatrix = candle_core::quantized::imatrix_file::read(
+                &mut std::io::BufReader::new(std::fs::File::open(imatrix)?),
+            )?;
+            println!("imatrix entries: {}", imatrix.len());
+            imatrix
+        }
+    };
+
     let dtype = q.dtype();
     let qtensors = content
         .tensor_infos
@@ -342,7 +773,8 @@ fn run_quantize(
             println!("  quantizing {name}");
             let mut in_file = std::fs::File::open(&in_files[0])?;
             let tensor = content.tensor(&mut in_file, name, device)?;
-            let tensor = qmode.quantize(name, tensor, dtype)?;
+            let imatrix = imatrix.get(name).map(|v| v.as_slice());
+            let tensor = qmode.quantize(name, tensor, dtype, imatrix)?;
             Ok((name, tensor))
         })
         .collect::<Result<Vec<_>>>()?;
@@ -382,7 +814,27 @@ fn main() -> anyhow::Result<()> {
             out_file,
             quantization,
             mode,
-        } => run_quantize(&in_file, out_file, quantization, mode, &device)?,
+            imatrix,
+        } => run_quantize(&in_file, out_file, quantization, mode, imatrix, &device)?,
+        Command::Convert {
+            in_file,
+            out_file,
+            quantization,
+            rules,
+            config,
+            tokenizer,
+            alignment,
+            name,
+        } => run_convert(
+            &in_file,
+            out_file,
+            quantization,
+            &rules,
+            config,
+            tokenizer,
+            alignment,
+            name,
+        )?,
         Command::Dequantize { in_file, out_file } => run_dequantize(in_file, out_file, &device)?,
     }
     Ok(())
@@ -0,0 +1,147 @@
+//! Functional interface to automatic differentiation.
+//!
+//! Contrary to [`Tensor::backward`], the functions from this module return gradients that are
+//! part of the computation graph so they can be differentiated again. This makes it possible to
+//! compute gradient penalties, hessian-vector products, or to differentiate through an inner
+//! optimization loop.
+//!
+//! ```rust
+//! use candle_core::{autograd, Device, Tensor, Var};
+//! let x = Var::new(&[1f32, 2., 3.], &Device::Cpu)?;
+//! let ys = x.powf(3.)?.sum_all()?;
+//! let dx = autograd::grad(&ys, &[x.as_tensor()])?;
+//! assert_eq!(dx[0].to_vec1::<f32>()?, [3., 12., 27.]);
+//! // Second order derivative.
+//! let dx2 = autograd::grad(&dx[0].sum_all()?, &[x.as_tensor()])?;
+//! assert_eq!(dx2[0].to_vec1::<f32>()?, [6., 12., 18.]);
+//! # Ok::<(), candle_core::Error>(())
+//! ```
+use crate::op::BackpropOp;
+use crate::{bail, Result, Tensor};
+
+// Returns tensors sharing the storage of `xs` that are tracked as variables so that they can
+// be differentiated.
+fn as_vars(xs: &[Tensor]) -> Vec<Tensor> {
+    xs.iter()
+        .map(|x| x.share_storage(BackpropOp::none(), true))
+        .collect()
+}
+
+fn check_same_shape(xs: &Tensor, ys: &Tensor, op: &'static str) -> Result<()> {
+    if xs.shape() != ys.shape() {
+        Err(crate::Error::ShapeMismatchBinaryOp {
+            lhs: xs.shape().clone(),
+            rhs: ys.shape().clone(),
+            op,
+        }
+        .bt())?
+    }
+    Ok(())
+}
+
+/// Computes the gradients of the sum of `output` with respect to each of the `inputs`.
+///
+/// The inputs can be variables or intermediary tensors of the graph that leads to `output`, if
+/// `output` does not depend on some input the returned gradient is zero. The gradients are
+/// tracked so can be used to compute higher order derivatives, use `detach` on them if this is
+/// not needed.
+pub fn grad(output: &Tensor, inputs: &[&Tensor]) -> Result<Vec<Tensor>> {
+    grad_with_grad_output(output, inputs, &output.ones_like()?)
+}
+
+/// Computes the gradients of `output` with respect to `inputs` when the gradient of some scalar
+/// value with respect to `output` is `grad_output`.
+pub fn grad_with_grad_output(
+    output: &Tensor,
+    inputs: &[&Tensor],
+    grad_output: &Tensor,
+) -> Result<Vec<Tensor>> {
+    check_same_shape(output, grad_output, "grad")?;
+    let retain = inputs.iter().map(|t| t.id()).collect::<Vec<_>>();
+    let mut grads = output.backward_impl(grad_output.clone(), true, &retain)?;
+    inputs
+        .iter()
+        .map(|input| match grads.remove(input) {
+            Some(grad) => Ok(grad),
+            None => input.zeros_like(),
+        })
+        .collect()
+}
+
+/// Evaluates `f` on `inputs` and returns the output together with the vector-Jacobian product
+/// of `v` with the Jacobian of `f`, i.e. the gradients of `(f(inputs) * v).sum()`.
+pub fn vjp<F>(f: F, inputs: &[Tensor], v: &Tensor) -> Result<(Tensor, Vec<Tensor>)>
+where
+    F: Fn(&[Tensor]) -> Result<Tensor>,
+{
+    let vars = as_vars(inputs);
+    let output = f(&vars)?;
+    let vars = vars.iter().collect::<Vec<_>>();
+    let grads = grad_with_grad_output(&output, &vars, v)?;
+    Ok((output, grads))
+}
+
+/// Evaluates `f` on `inputs` and returns the output together with the Jacobian-vector product
+/// of the Jacobian of `f` with `tangents`, i.e. the directional derivative of `f` along
+/// `tangents`.
+///
+/// This uses the double-backward trick: the vector-Jacobian product with some auxiliary vector
+/// `u` is linear in `u`, differentiating it with respect to `u` gives the Jacobian-vector
+/// product.
+pub fn jvp<F>(f: F, inputs: &[Tensor], tangents: &[Tensor]) -> Result<(Tensor, Tensor)>
+where
+    F: Fn(&[Tensor]) -> Result<Tensor>,
+{
+    if inputs.len() != tangents.len() {
+        bail!(
+            "jvp expects as many tangents as inputs, got {} tangents for {} inputs",
+            tangents.len(),
+            inputs.len()
+        )
+    }
+    let vars = as_vars(inputs);
+    let output = f(&vars)?;
+    let u = output.zeros_like()?.share_storage(BackpropOp::none(), true);
+    let var_refs = vars.iter().collect::<Vec<_>>();
+    let vjps = grad_with_grad_output(&output, &var_refs, &u)?;
+    let mut dot = u.zeros_like()?.sum_all()?;
+    for (vjp, tangent) in vjps.iter().zip(tangents.iter()) {
+        check_same_shape(vjp, tangent, "jvp")?;
+        dot = (dot + (vjp * tangent)?.sum_all()?)?
+    }
+    let jvp = grad(&dot, &[&u])?.remove(0);
+    Ok((output.detach(), jvp))
+}
+
+/// Computes the product of the Hessian of the scalar function `f` evaluated at `inputs` with
+/// the vector `v`, `v` containing one tensor per input.
+pub fn hessian_vector_product<F>(f: F, inputs: &[Tensor], v: &[Tensor]) -> Result<Vec<Tensor>>
+where
+    F: Fn(&[Tensor]) -> Result<Tensor>,
+{
+    if inputs.len() != v.len() {
+        bail!(
+            "hessian_vector_product expects as many vectors as inputs, got {} for {} inputs",
+            v.len(),
+            inputs.len()
+        )
+    }
+    let vars = as_vars(inputs);
+    let output = f(&vars)?;
+    if output.rank() != 0 {
+        Err(crate::Error::UnexpectedNumberOfDims {
+            expected: 0,
+            got: output.rank(),
+            shape: output.shape().clone(),
+        }
+        .bt())?
+    }
+    let var_refs = vars.iter().collect::<Vec<_>>();
+    let grads = grad(&output, &var_refs)?;
+    let mut dot = output.zeros_like()?;
+    for (grad, v) in grads.iter().zip(v.iter()) {
+        check_same_shape(grad, v, "hessian_vector_product")?;
+        dot = (dot + (grad * v)?.sum_all()?)?
+    }
+    grad(&dot, &var_refs)
+}
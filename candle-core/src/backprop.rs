@@ -1,5 +1,5 @@
-use crate::op::{BinaryOp, Op, ReduceOp, UnaryOp};
-use crate::{Error, Result, Tensor, TensorId};
+use crate::op::{BackpropOp, BinaryOp, Op, ReduceOp, UnaryOp};
+use crate::{DType, Result, Tensor, TensorId, Var};
 use std::collections::HashMap;
 
 // arg has been reduced to node via reduce_dims, expand it back to arg.
@@ -15,6 +15,60 @@ fn broadcast_back(arg: &Tensor, node: &Tensor, reduced_dims: &[usize]) -> Result
     }
 }
 
+// Returns the elements at indexes `offset + i * step` for `i < len` along dimension `dim`.
+fn strided_select(
+    xs: &Tensor,
+    dim: usize,
+    offset: usize,
+    step: usize,
+    len: usize,
+) -> Result<Tensor> {
+    let size = xs.dim(dim)?;
+    let xs = xs.pad_with_zeros(dim, 0, (offset + len * step).saturating_sub(size))?;
+    let mut dims = xs.dims().to_vec();
+    dims[dim] = step;
+    dims.insert(dim, len);
+    xs.narrow(dim, offset, len * step)?
+        .reshape(dims)?
+        .narrow(dim + 1, 0, 1)?
+        .squeeze(dim + 1)
+}
+
+// The adjoint of `strided_select`, the values of `xs` are written at indexes `offset + i * step`
+// of a zero tensor of size `size` along dimension `dim`.
+fn strided_scatter(
+    xs: &Tensor,
+    dim: usize,
+    offset: usize,
+    step: usize,
+    size: usize,
+) -> Result<Tensor> {
+    let len = xs.dim(dim)?;
+    let mut dims = xs.dims().to_vec();
+    dims[dim] = len * step;
+    let xs = xs
+        .unsqueeze(dim + 1)?
+        .pad_with_zeros(dim + 1, 0, step - 1)?
+        .reshape(dims)?
+        .pad_with_zeros(dim, offset, 0)?;
+    let total = offset + len * step;
+    if total > size {
+        xs.narrow(dim, 0, size)
+    } else {
+        xs.pad_with_zeros(dim, 0, size - total)
+    }
+}
+
+// The source indexes used by the nearest neighbor upsampling, see `UpsampleNearest2D` in the cpu
+// backend.
+fn upsample_nearest_indexes(src: usize, dst: usize, device: &crate::Device) -> Result<Tensor> {
+    let scale = src as f64 / dst as f64;
+    let idxs = (0..dst)
+        .map(|idx| usize::min(src - 1, (idx as f64 * scale) as usize) as u32)
+        .collect::<Vec<_>>();
+    Tensor::from_vec(idxs, dst, device)
+}
+
 thread_local! {
     static CANDLE_GRAD_DO_NOT_DETACH: bool = {
         match std::env::var("CANDLE_GRAD_DO_NOT_DETACH") {
@@ -83,6 +137,16 @@ impl Tensor {
                         kernel: rhs,
                         ..
                     }
+                    | Op::Conv3D {
+                        arg: lhs,
+                        kernel: rhs,
+                        ..
+                    }
+                    | Op::ConvTranspose3D {
+                        arg: lhs,
+                        kernel: rhs,
+                        ..
+                    }
                     | Op::CustomOp2(lhs, rhs, _)
                     | Op::Binary(lhs, rhs, _)
                     | Op::Gather(lhs, rhs, _)
@@ -100,6 +164,13 @@ impl Tensor {
                         track_grad |= tg;
                         nodes
                     }),
+                    Op::Checkpoint { args, params, .. } => {
+                        args.iter().chain(params.iter()).fold(nodes, |nodes, arg| {
+                            let (tg, nodes) = walk(arg, nodes, already_seen);
+                            track_grad |= tg;
+                            nodes
+                        })
+                    }
                     Op::Affine { arg, mul, .. } => {
                         if *mul == 0. {
                             nodes
@@ -109,6 +180,8 @@ impl Tensor {
                             nodes
                         }
                     }
+                    // The gradient of the rounding ops is zero so there is no need to
+                    // backpropagate through them.
                     Op::Unary(_node, UnaryOp::Ceil)
                     | Op::Unary(_node, UnaryOp::Floor)
                     | Op::Unary(_node, UnaryOp::Round) => nodes,
@@ -117,6 +190,8 @@ impl Tensor {
                     | Op::UpsampleNearest2D { arg: node, .. }
                     | Op::AvgPool2D { arg: node, .. }
                     | Op::MaxPool2D { arg: node, .. }
+                    | Op::AvgPool3D { arg: node, .. }
+                    | Op::MaxPool3D { arg: node, .. }
                     | Op::Copy(node)
                     | Op::Broadcast(node)
                     | Op::Cmp(node, _)
@@ -124,7 +199,10 @@ impl Tensor {
                     | Op::ToDevice(node)
                     | Op::Transpose(node, _, _)
                     | Op::Permute(node, _)
+                    | Op::Flip(node, _)
+                    | Op::Unfold { arg: node, .. }
                     | Op::Narrow(node, _, _, _)
+                    | Op::NarrowWithStep { arg: node, .. }
                     | Op::Unary(node, _)
                     | Op::Elu(node, _)
                     | Op::Powf(node, _)
@@ -159,9 +237,29 @@ impl Tensor {
     }
 
     pub fn backward(&self) -> Result<GradStore> {
+        self.backward_impl(self.ones_like()?.contiguous()?, false, &[])
+    }
+
+    /// Similar to `backward` but the returned gradients are themselves tracked in the
+    /// computation graph, so they can be differentiated again to compute higher order
+    /// derivatives, e.g. for gradient penalties or hessian-vector products.
+    pub fn backward_create_graph(&self) -> Result<GradStore> {
+        self.backward_impl(self.ones_like()?.contiguous()?, true, &[])
+    }
+
+    // Backpropagates `grad`, the gradient of some scalar value with respect to `self`. The
+    // gradients of the intermediary nodes are dropped once used, except for the ones listed in
+    // `retain`.
+    pub(crate) fn backward_impl(
+        &self,
+        grad: Tensor,
+        create_graph: bool,
+        retain: &[TensorId],
+    ) -> Result<GradStore> {
         let sorted_nodes = self.sorted_nodes();
         let mut grads = GradStore::new();
-        grads.insert(self, self.ones_like()?.contiguous()?);
+        let mut retained = vec![];
+        grads.insert(self, grad);
         for node in sorted_nodes.iter() {
             if node.is_variable() {
                 continue;
@@ -169,12 +267,15 @@ impl Tensor {
             let grad = grads
                 .remove(node)
                 .expect("candle internal error - grad not populated");
+            if retain.contains(&node.id()) {
+                retained.push((*node, grad.clone()))
+            }
             // https://github.com/huggingface/candle/issues/1241
             // Ideally, we would make these operations in place where possible to ensure that we
             // do not have to allocate too often. Here we just call `.detach` to avoid computing
-            // the backprop graph of the backprop itself. This would be an issue for second order
-            // derivatives but these are out of scope at the moment.
-            let do_not_detach = CANDLE_GRAD_DO_NOT_DETACH.with(|b| *b);
+            // the backprop graph of the backprop itself, unless higher order derivatives have
+            // been requested.
+            let do_not_detach = create_graph || CANDLE_GRAD_DO_NOT_DETACH.with(|b| *b);
             let grad = if do_not_detach { grad } else { grad.detach() };
             if let Some(op) = node.op() {
                 match op {
@@ -307,24 +408,163 @@ impl Tensor {
                         };
                         *sum_grad = sum_grad.add(&grad_kernel)?;
                     }
-                    Op::ConvTranspose1D { .. } => Err(Error::BackwardNotSupported {
-                        op: "conv-transpose1d",
-                    })?,
-                    Op::ConvTranspose2D { .. } => Err(Error::BackwardNotSupported {
-                        op: "conv-transpose2d",
-                    })?,
+                    Op::ConvTranspose1D {
+                        arg,
+                        kernel,
+                        padding,
+                        output_padding: _,
+                        stride,
+                        dilation,
+                    } => {
+                        // The transposed convolution is the adjoint of the convolution so the
+                        // gradient of the argument is obtained by a convolution with the same
+                        // kernel.
+                        let grad_arg = grad.conv1d(kernel, *padding, *stride, *dilation, 1)?;
+                        let sum_grad = grads.or_insert(arg)?;
+                        *sum_grad = sum_grad.add(&grad_arg)?;
+
+                        let grad_kernel = grad
+                            .transpose(0, 1)?
+                            .conv1d(&arg.transpose(0, 1)?, *padding, *dilation, *stride, 1)?
+                            .transpose(0, 1)?;
+                        let sum_grad = grads.or_insert(kernel)?;
+                        let (_, _, k0) = kernel.dims3()?;
+                        let (_, _, g_k0) = grad_kernel.dims3()?;
+                        let grad_kernel = if g_k0 != k0 {
+                            grad_kernel.narrow(2, 0, k0)?
+                        } else {
+                            grad_kernel
+                        };
+                        *sum_grad = sum_grad.add(&grad_kernel)?;
+                    }
+                    Op::ConvTranspose2D {
+                        arg,
+                        kernel,
+                        padding,
+                        output_padding: _,
+                        stride,
+                        dilation,
+                    } => {
+                        let grad_arg = grad.conv2d(kernel, *padding, *stride, *dilation, 1)?;
+                        let sum_grad = grads.or_insert(arg)?;
+                        *sum_grad = sum_grad.add(&grad_arg)?;
+
+                        let grad_kernel = grad
+                            .transpose(0, 1)?
+                            .conv2d(&arg.transpose(0, 1)?, *padding, *dilation, *stride, 1)?
+                            .transpose(0, 1)?;
+                        let sum_grad = grads.or_insert(kernel)?;
+                        let (_, _, k0, k1) = kernel.dims4()?;
+                        let (_, _, g_k0, g_k1) = grad_kernel.dims4()?;
+                        let grad_kernel = if g_k0 != k0 || g_k1 != k1 {
+                            grad_kernel.narrow(2, 0, k0)?.narrow(3, 0, k1)?
+                        } else {
+                            grad_kernel
+                        };
+                        *sum_grad = sum_grad.add(&grad_kernel)?;
+                    }
+                    Op::Conv3D {
+                        arg,
+                        kernel,
+                        padding,
+                        stride,
+                        dilation,
+                    } => {
+                        // The output depth for conv_transpose3d is:
+                        // (i_d - 1) * stride - 2 * padding + dilation * (k_d - 1) + out_padding + 1
+                        // All the spatial dimensions share the same output padding so this only
+                        // handles the case where they have the same remainder, larger outputs
+                        // are narrowed below.
+                        let out_size =
+                            |dim: usize| -> Result<usize> {
+                                let grad_size = grad.dim(dim)?;
+                                let k_size = kernel.dim(dim)?;
+                                Ok((grad_size - 1) * stride + dilation * (k_size - 1) + 1
+                                    - 2 * padding)
+                            };
+                        let out_padding = (2..5)
+                            .map(|dim| Ok(arg.dim(dim)? - out_size(dim)?))
+                            .collect::<Result<Vec<_>>>()?
+                            .into_iter()
+                            .max()
+                            .unwrap_or(0);
+                        let grad_arg = grad.conv_transpose3d(
+                            kernel,
+                            *padding,
+                            out_padding,
+                            *stride,
+                            *dilation,
+                        )?;
+                        let (_, _, d, h, w) = arg.dims5()?;
+                        let grad_arg =
+                            grad_arg.narrow(2, 0, d)?.narrow(3, 0, h)?.narrow(4, 0, w)?;
+                        let sum_grad = grads.or_insert(arg)?;
+                        *sum_grad = sum_grad.add(&grad_arg)?;
+
+                        let grad_kernel = arg
+                            .transpose(0, 1)?
+                            .conv3d(&grad.transpose(0, 1)?, *padding, *dilation, *stride, 1)?
+                            .transpose(0, 1)?;
+                        let sum_grad = grads.or_insert(kernel)?;
+                        let (_, _, k0, k1, k2) = kernel.dims5()?;
+                        let grad_kernel = grad_kernel
+                            .narrow(2, 0, k0)?
+                            .narrow(3, 0, k1)?
+                            .narrow(4, 0, k2)?;
+                        *sum_grad = sum_grad.add(&grad_kernel)?;
+                    }
+                    Op::ConvTranspose3D {
+                        arg,
+                        kernel,
+                        padding,
+                        output_padding: _,
+                        stride,
+                        dilation,
+                    } => {
+                        let grad_arg = grad.conv3d(kernel, *padding, *stride, *dilation, 1)?;
+                        let sum_grad = grads.or_insert(arg)?;
+                        *sum_grad = sum_grad.add(&grad_arg)?;
+
+                        let grad_kernel = grad
+                            .transpose(0, 1)?
+                            .conv3d(&arg.transpose(0, 1)?, *padding, *dilation, *stride, 1)?
+                            .transpose(0, 1)?;
+                        let sum_grad = grads.or_insert(kernel)?;
+                        let (_, _, k0, k1, k2) = kernel.dims5()?;
+                        let grad_kernel = grad_kernel
+                            .narrow(2, 0, k0)?
+                            .narrow(3, 0, k1)?
+                            .narrow(4, 0, k2)?;
+                        *sum_grad = sum_grad.add(&grad_kernel)?;
+                    }
                     Op::AvgPool2D {
                         arg,
                         kernel_size,
                         stride,
                     } => {
-                        if kernel_size != stride {
-                            crate::bail!("backward not supported for avgpool2d if ksize {kernel_size:?} != stride {stride:?}")
-                        }
                         let (_n, _c, h, w) = arg.dims4()?;
-                        let grad_arg = grad.upsample_nearest2d(h, w)?;
-                        let grad_arg =
-                            (grad_arg * (1f64 / (kernel_size.0 * kernel_size.1) as f64))?;
+                        let scale = 1f64 / (kernel_size.0 * kernel_size.1) as f64;
+                        let grad_arg = if kernel_size == stride {
+                            let grad_arg = grad.upsample_nearest2d(
+                                grad.dim(2)? * stride.0,
+                                grad.dim(3)? * stride.1,
+                            )?;
+                            let grad_arg = grad_arg.pad_with_zeros(2, 0, h - grad_arg.dim(2)?)?;
+                            grad_arg.pad_with_zeros(3, 0, w - grad_arg.dim(3)?)?
+                        } else {
+                            // Overlapping or disjoint windows, the gradient of each window is
+                            // scattered back for each position within the kernel.
+                            let mut grad_arg = arg.zeros_like()?;
+                            for k_h in 0..kernel_size.0 {
+                                for k_w in 0..kernel_size.1 {
+                                    let g = strided_scatter(&grad, 2, k_h, stride.0, h)?;
+                                    let g = strided_scatter(&g, 3, k_w, stride.1, w)?;
+                                    grad_arg = grad_arg.add(&g)?
+                                }
+                            }
+                            grad_arg
+                        };
+                        let grad_arg = (grad_arg * scale)?;
                         let sum_grad = grads.or_insert(arg)?;
                         *sum_grad = sum_grad.add(&grad_arg)?;
                     }
@@ -333,53 +573,132 @@ impl Tensor {
                         kernel_size,
                         stride,
                     } => {
-                        if kernel_size != stride {
-                            crate::bail!("backward not supported for maxpool2d if ksize {kernel_size:?} != stride {stride:?}")
-                        }
                         let (_n, _c, h, w) = arg.dims4()?;
-                        // For computing the max-pool gradient, we compute a mask where a 1 means
-                        // that the element is the maximum, then we apply this mask to the
-                        // upsampled gradient (taking into account that multiple max may exist so
-                        // we scale the gradient for this case).
-                        let node_upsampled = node.upsample_nearest2d(h, w)?;
-                        let mask = arg.eq(&node_upsampled)?.to_dtype(arg.dtype())?;
-                        let avg = mask.avg_pool2d_with_stride(*kernel_size, *stride)?;
-                        let grad_arg = ((grad * avg)?.upsample_nearest2d(h, w)? * mask)?;
+                        let (_n, _c, o_h, o_w) = node.dims4()?;
+                        // For computing the max-pool gradient, we compute for each position
+                        // within the kernel a mask where a 1 means that the element is the
+                        // maximum of its window. The gradient is then split between the maximum
+                        // values of each window as multiple max may exist.
+                        let mut masks = Vec::with_capacity(kernel_size.0 * kernel_size.1);
+                        for k_h in 0..kernel_size.0 {
+                            for k_w in 0..kernel_size.1 {
+                                let xs = strided_select(arg, 2, k_h, stride.0, o_h)?;
+                                let xs = strided_select(&xs, 3, k_w, stride.1, o_w)?;
+                                masks.push(xs.eq(*node)?.to_dtype(arg.dtype())?)
+                            }
+                        }
+                        let count = Tensor::stack(&masks, 0)?.sum(0)?;
+                        let grad = grad.div(&count)?;
+                        let mut grad_arg = arg.zeros_like()?;
+                        for (idx, mask) in masks.iter().enumerate() {
+                            let (k_h, k_w) = (idx / kernel_size.1, idx % kernel_size.1);
+                            let g = grad.mul(mask)?;
+                            let g = strided_scatter(&g, 2, k_h, stride.0, h)?;
+                            let g = strided_scatter(&g, 3, k_w, stride.1, w)?;
+                            grad_arg = grad_arg.add(&g)?
+                        }
+                        let sum_grad = grads.or_insert(arg)?;
+                        *sum_grad = sum_grad.add(&grad_arg)?;
+                    }
+                    Op::AvgPool3D {
+                        arg,
+                        kernel_size,
+                        stride,
+                    } => {
+                        // The gradient of each window is scattered back for each position
+                        // within the kernel.
+                        let (_n, _c, d, h, w) = arg.dims5()?;
+                        let (k_d, k_h, k_w) = *kernel_size;
+                        let scale = 1f64 / (k_d * k_h * k_w) as f64;
+                        let mut grad_arg = arg.zeros_like()?;
+                        for i_d in 0..k_d {
+                            let g = strided_scatter(&grad, 2, i_d, stride.0, d)?;
+                            for i_h in 0..k_h {
+                                let g = strided_scatter(&g, 3, i_h, stride.1, h)?;
+                                for i_w in 0..k_w {
+                                    let g = strided_scatter(&g, 4, i_w, stride.2, w)?;
+                                    grad_arg = grad_arg.add(&g)?
+                                }
+                            }
+                        }
+                        let grad_arg = (grad_arg * scale)?;
+                        let sum_grad = grads.or_insert(arg)?;
+                        *sum_grad = sum_grad.add(&grad_arg)?;
+                    }
+                    Op::MaxPool3D {
+                        arg,
+                        kernel_size,
+                        stride,
+                    } => {
+                        // Same as for MaxPool2D, the gradient is split between the maximum
+                        // values of each window.
+                        let (_n, _c, d, h, w) = arg.dims5()?;
+                        let (_n, _c, o_d, o_h, o_w) = node.dims5()?;
+                        let (k_d, k_h, k_w) = *kernel_size;
+                        let mut masks = Vec::with_capacity(k_d * k_h * k_w);
+                        for i_d in 0..k_d {
+                            for i_h in 0..k_h {
+                                for i_w in 0..k_w {
+                                    let xs = strided_select(arg, 2, i_d, stride.0, o_d)?;
+                                    let xs = strided_select(&xs, 3, i_h, stride.1, o_h)?;
+                                    let xs = strided_select(&xs, 4, i_w, stride.2, o_w)?;
+                                    masks.push(xs.eq(*node)?.to_dtype(arg.dtype())?)
+                                }
+                            }
+                        }
+                        let count = Tensor::stack(&masks, 0)?.sum(0)?;
+                        let grad = grad.div(&count)?;
+                        let mut grad_arg = arg.zeros_like()?;
+                        for (idx, mask) in masks.iter().enumerate() {
+                            let i_d = idx / (k_h * k_w);
+                            let i_h = (idx / k_w) % k_h;
+                            let i_w = idx % k_w;
+                            let g = grad.mul(mask)?;
+                            let g = strided_scatter(&g, 2, i_d, stride.0, d)?;
+                            let g = strided_scatter(&g, 3, i_h, stride.1, h)?;
+                            let g = strided_scatter(&g, 4, i_w, stride.2, w)?;
+                            grad_arg = grad_arg.add(&g)?
+                        }
                         let sum_grad = grads.or_insert(arg)?;
                         *sum_grad = sum_grad.add(&grad_arg)?;
                     }
                     Op::UpsampleNearest1D { arg, target_size } => {
                         let (_n, c, size) = arg.dims3()?;
-                        if target_size % size != 0 {
-                            crate::bail!("backward not supported for non integer upscaling factors")
-                        }
-                        let scale = target_size / size;
-
-                        let kernel = Tensor::ones((c, 1, scale), arg.dtype(), arg.device())?;
-                        let conv_sum = grad.conv1d(&kernel, 0, scale, 1, c)?;
+                        let grad_arg = if target_size % size == 0 {
+                            let scale = target_size / size;
+                            let kernel = Tensor::ones((c, 1, scale), arg.dtype(), arg.device())?;
+                            grad.conv1d(&kernel, 0, scale, 1, c)?
+                        } else {
+                            let idxs = upsample_nearest_indexes(size, *target_size, arg.device())?;
+                            arg.zeros_like()?.index_add(&idxs, &grad, 2)?
+                        };
                         let sum_grad = grads.or_insert(arg)?;
-                        *sum_grad = conv_sum;
+                        *sum_grad = sum_grad.add(&grad_arg)?;
                     }
                     Op::UpsampleNearest2D {
                         arg,
                         target_h,
                         target_w,
                     } => {
-                        let (_n, c, h, w) = arg.dims4()?;
-                        if target_h % h != 0 || target_w % w != 0 {
-                            crate::bail!("backward not supported for non integer upscaling factors")
-                        }
-                        let scale_h = target_h / h;
-                        let scale_w = target_w / w;
-
-                        if scale_h != scale_w {
-                            crate::bail!("backward not supported for non uniform upscaling factors")
+                        let (n, c, h, w) = arg.dims4()?;
+                        let grad_arg = if target_h % h == 0
+                            && target_w % w == 0
+                            && target_h / h == target_w / w
+                        {
+                            let scale = target_h / h;
+                            let kernel =
+                                Tensor::ones((c, 1, scale, scale), arg.dtype(), arg.device())?;
+                            grad.conv2d(&kernel, 0, scale, 1, c)?
+                        } else {
+                            let idxs_h = upsample_nearest_indexes(h, *target_h, arg.device())?;
+                            let idxs_w = upsample_nearest_indexes(w, *target_w, arg.device())?;
+                            let zeros =
+                                Tensor::zeros((n, c, *target_h, w), arg.dtype(), arg.device())?;
+                            let grad = zeros.index_add(&idxs_w, &grad, 3)?;
+                            arg.zeros_like()?.index_add(&idxs_h, &grad, 2)?
                         };
-                        let kernel =
-                            Tensor::ones((c, 1, scale_h, scale_w), arg.dtype(), arg.device())?;
-                        let conv_sum = grad.conv2d(&kernel, 0, scale_h, 1, c)?;
                         let sum_grad = grads.or_insert(arg)?;
-                        *sum_grad = conv_sum;
+                        *sum_grad = sum_grad.add(&grad_arg)?;
                     }
                     Op::SliceScatter0(lhs, rhs, start_rhs) => {
                         let rhs_sum_grad = grads.or_insert(rhs)?;
@@ -554,6 +873,25 @@ impl Tensor {
                         let sum_grad = grads.or_insert(arg)?;
                         *sum_grad = sum_grad.add(&arg_grad)?
                     }
+                    &Op::NarrowWithStep {
+                        ref arg,
+                        dim,
+                        start,
+                        len,
+                        step,
+                    } => {
+                        // Scatter the gradient back in increasing index order.
+                        let (grad, first) = if step < 0 {
+                            let last = start - len.saturating_sub(1) * step.unsigned_abs();
+                            (grad.flip(dim)?, last)
+                        } else {
+                            (grad, start)
+                        };
+                        let arg_grad =
+                            strided_scatter(&grad, dim, first, step.unsigned_abs(), arg.dim(dim)?)?;
+                        let sum_grad = grads.or_insert(arg)?;
+                        *sum_grad = sum_grad.add(&arg_grad)?
+                    }
                     Op::Reduce(_, ReduceOp::ArgMin, _) => {}
                     Op::Reduce(_, ReduceOp::ArgMax, _) => {}
                     Op::Reshape(arg) => {
@@ -561,13 +899,11 @@ impl Tensor {
                         let sum_grad = grads.or_insert(arg)?;
                         *sum_grad = sum_grad.add(&arg_grad)?
                     }
-                    Op::Unary(_, UnaryOp::Ceil) => Err(Error::BackwardNotSupported { op: "ceil" })?,
-                    Op::Unary(_, UnaryOp::Floor) => {
-                        Err(Error::BackwardNotSupported { op: "floor" })?
-                    }
-                    Op::Unary(_, UnaryOp::Round) => {
-                        Err(Error::BackwardNotSupported { op: "round" })?
-                    }
+                    // The rounding ops have a zero gradient almost everywhere, use the
+                    // `*_ste` variants to get a straight-through estimator instead.
+                    Op::Unary(_, UnaryOp::Ceil)
+                    | Op::Unary(_, UnaryOp::Floor)
+                    | Op::Unary(_, UnaryOp::Round) => {}
                     Op::Unary(arg, UnaryOp::Gelu) => {
                         let sum_grad = grads.or_insert(arg)?;
                         let cube = arg.powf(3.)?;
@@ -683,13 +1019,119 @@ impl Tensor {
                         let sum_grad = grads.or_insert(arg)?;
                         *sum_grad = sum_grad.add(&arg_grad)?
                     }
+                    &Op::Unfold {
+                        ref arg,
+                        dim,
+                        size,
+                        step,
+                    } => {
+                        // Each element of a slice is scattered back to its position in the input.
+                        let len = arg.dim(dim)?;
+                        let last_dim = grad.rank() - 1;
+                        let sum_grad = grads.or_insert(arg)?;
+                        for offset in 0..size {
+                            let grad = grad.narrow(last_dim, offset, 1)?.squeeze(last_dim)?;
+                            let arg_grad = strided_scatter(&grad, dim, offset, step, len)?;
+                            *sum_grad = sum_grad.add(&arg_grad)?
+                        }
+                    }
+                    Op::Flip(arg, dims) => {
+                        let arg_grad = grad.flip(dims.as_slice())?;
+                        let sum_grad = grads.or_insert(arg)?;
+                        *sum_grad = sum_grad.add(&arg_grad)?
+                    }
+                    Op::Checkpoint { args, params, f } => {
+                        if create_graph {
+                            crate::bail!("higher order gradients are not supported for checkpoint")
+                        }
+                        // Recompute the forward pass using fresh variables for the arguments so
+                        // that the intermediary values only live for the duration of this step.
+                        let vars = args
+                            .iter()
+                            .map(|arg| {
+                                if arg.track_op() {
+                                    arg.share_storage(BackpropOp::none(), true)
+                                } else {
+                                    arg.clone()
+                                }
+                            })
+                            .collect::<Vec<_>>();
+                        let res = f(&vars)?;
+                        let inner_grads = res.backward_impl(grad, false, &[])?;
+                        for (arg, var) in args.iter().zip(vars.iter()) {
+                            if !var.is_variable() {
+                                continue;
+                            }
+                            if let Some(arg_grad) = inner_grads.get(var) {
+                                let sum_grad = grads.or_insert(arg)?;
+                                *sum_grad = sum_grad.add(arg_grad)?
+                            }
+                        }
+                        for param in params.iter() {
+                            if let Some(param_grad) = inner_grads.get(param) {
+                                let sum_grad = grads.or_insert(param)?;
+                                *sum_grad = sum_grad.add(param_grad)?
+                            }
+                        }
+                    }
                 };
             }
         }
+        for (node, grad) in retained {
+            grads.insert(node, grad);
+        }
         Ok(grads)
     }
 }
 
+/// Runs `f` on `args` without keeping the intermediary values alive, these values are
+/// recomputed when running the backward pass. This trades some compute for a lower memory usage
+/// when training, typically by checkpointing each block of a transformer model.
+///
+/// The variables captured by `f` get their gradients populated as usual. Other tensors that
+/// track gradients should be passed via `args` rather than being captured. `f` is run a second
+/// time during the backward pass so it should be deterministic.
+///
+/// ```rust
+/// use candle_core::{checkpoint, Device, Tensor, Var};
+/// let w = Var::new(&[2f32, 3.], &Device::Cpu)?;
+/// let x = Var::new(&[1f32, -1.], &Device::Cpu)?;
+/// let block = {
+///     let w = w.as_tensor().clone();
+///     move |xs: &[Tensor]| xs[0].mul(&w)?.sqr()
+/// };
+/// let ys = checkpoint(block, &[x.as_tensor()])?;
+/// let grads = ys.sum_all()?.backward()?;
+/// assert_eq!(grads.get(&x).unwrap().to_vec1::<f32>()?, [8., -18.]);
+/// assert_eq!(grads.get(&w).unwrap().to_vec1::<f32>()?, [4., 6.]);
+/// # Ok::<(), candle_core::Error>(())
+/// ```
+pub fn checkpoint<A, F>(f: F, args: &[A]) -> Result<Tensor>
+where
+    A: AsRef<Tensor>,
+    F: Fn(&[Tensor]) -> Result<Tensor> + Send + Sync + 'static,
+{
+    let f: std::sync::Arc<crate::op::CheckpointFn> = std::sync::Arc::new(f);
+    let args = args.iter().map(|a| a.as_ref().clone()).collect::<Vec<_>>();
+    let detached = args.iter().map(|a| a.detach()).collect::<Vec<_>>();
+    let res = f(&detached)?;
+    // As the arguments have been detached, the only variables reachable from `res` are the ones
+    // that have been captured by `f`.
+    let params = res
+        .sorted_nodes()
+        .into_iter()
+        .filter(|node| node.is_variable())
+        .cloned()
+        .collect::<Vec<_>>();
+    let tracked = args.iter().chain(params.iter()).collect::<Vec<_>>();
+    let op = BackpropOp::new(&tracked, |_| Op::Checkpoint {
+        args: args.clone(),
+        params: params.clone(),
+        f: f.clone(),
+    });
+    Ok(res.share_storage(op, false))
+}
+
 #[derive(Debug)]
 pub struct GradStore(HashMap<TensorId, Tensor>);
 
@@ -725,4 +1167,149 @@ impl GradStore {
         };
         Ok(grad)
     }
+
+    /// The global L2 norm of the gradients of `vars`, as if all the gradients were concatenated
+    /// into a single vector. Variables without a gradient are skipped.
+    pub fn grad_norm(&self, vars: &[Var]) -> Result<f64> {
+        let mut sum_sq = 0f64;
+        for var in vars.iter() {
+            if let Some(grad) = self.get(var) {
+                // Half precision gradients are upcast as their squares easily overflow. The
+                // per-variable sums are accumulated on the host in f64, some backends such as
+                // metal do not support f64 tensors.
+                let grad = match grad.dtype() {
+                    DType::F16 | DType::BF16 => grad.to_dtype(DType::F32)?,
+                    _ => grad.clone(),
+                };
+                sum_sq += scalar_to_f64(&grad.sqr()?.sum_all()?)?;
+            }
+        }
+        Ok(sum_sq.sqrt())
+    }
+
+    /// Rescales the gradients of `vars` so that their global L2 norm is at most `max_norm`, this
+    /// behaves like PyTorch's `clip_grad_norm_`.
+    ///
+    /// Returns the norm before clipping. When this norm is not finite the gradients are left
+    /// unchanged, [`GradStore::non_finite_vars`] can be used to find the culprits.
+    pub fn clip_grad_norm(&mut self, vars: &[Var], max_norm: f64) -> Result<f64> {
+        let total_norm = self.grad_norm(vars)?;
+        if !total_norm.is_finite() {
+            return Ok(total_norm);
+        }
+        let clip_coef = max_norm / (total_norm + 1e-6);
+        if clip_coef < 1. {
+            for var in vars.iter() {
+                if let Some(grad) = self.0.get_mut(&var.id()) {
+                    *grad = grad.affine(clip_coef, 0.)?
+                }
+            }
+        }
+        Ok(total_norm)
+    }
+
+    /// Clamps each element of the gradients of `vars` to the range `[-clip_value, clip_value]`.
+    pub fn clip_grad_value(&mut self, vars: &[Var], clip_value: f64) -> Result<()> {
+        for var in vars.iter() {
+            if let Some(grad) = self.0.get_mut(&var.id()) {
+                *grad = grad.clamp(-clip_value, clip_value)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the variables of `vars` which gradients contain NaN or infinite values.
+    pub fn non_finite_vars<'a>(&self, vars: &'a [Var]) -> Result<Vec<&'a Var>> {
+        let mut non_finite = vec![];
+        for var in vars.iter() {
+            let grad = match self.get(var) {
+                Some(grad) if grad.dtype().is_float() => grad,
+                _ => continue,
+            };
+            // 0 * x is 0 for finite values and NaN for NaN or infinite values.
+            let zeros = grad.affine(0., 0.)?.sum_all()?;
+            if scalar_to_f64(&zeros)? != 0. {
+                non_finite.push(var)
+            }
+        }
+        Ok(non_finite)
+    }
+}
+
+// Reads a float scalar tensor without going through an f64 tensor.
+fn scalar_to_f64(t: &Tensor) -> Result<f64> {
+    match t.dtype() {
+        DType::F64 => t.to_scalar::<f64>(),
+        _ => Ok(t.to_dtype(DType::F32)?.to_scalar::<f32>()? as f64),
+    }
+}
+
+/// Sums the gradients of some variables over multiple backward passes, e.g. to accumulate the
+/// gradients of several micro-batches before applying an optimizer step.
+///
+/// ```rust
+/// use candle_core::{backprop::GradAccumulator, Device, Var};
+/// let w = Var::new(&[1f32, 2.], &Device::Cpu)?;
+/// let mut acc = GradAccumulator::new(&[w.clone()]);
+/// for scale in [1f64, 3.] {
+///     let loss = (w.as_tensor() * scale)?.sum_all()?;
+///     acc.accumulate(&loss.backward()?)?;
+/// }
+/// let grads = acc.mean()?;
+/// assert_eq!(grads.get(&w).unwrap().to_vec1::<f32>()?, [2., 2.]);
+/// # Ok::<(), candle_core::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct GradAccumulator {
+    vars: Vec<Var>,
+    grads: GradStore,
+    steps: usize,
+}
+
+impl GradAccumulator {
+    pub fn new(vars: &[Var]) -> Self {
+        Self {
+            vars: vars.to_vec(),
+            grads: GradStore::new(),
+            steps: 0,
+        }
+    }
+
+    /// Adds the gradients of the tracked variables from `grads`, the gradients of the other
+    /// tensors are ignored.
+    pub fn accumulate(&mut self, grads: &GradStore) -> Result<()> {
+        for var in self.vars.iter() {
+            if let Some(grad) = grads.get(var) {
+                let grad = grad.detach();
+                let sum = match self.grads.remove(var) {
+                    None => grad,
+                    Some(sum) => (sum + grad)?,
+                };
+                self.grads.insert(var, sum);
+            }
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// The number of calls to `accumulate` since the last reset.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Returns the summed gradients and resets the accumulator.
+    pub fn sum(&mut self) -> GradStore {
+        self.steps = 0;
+        std::mem::replace(&mut self.grads, GradStore::new())
+    }
+
+    /// Returns the gradients averaged over the accumulation steps and resets the accumulator.
+    pub fn mean(&mut self) -> Result<GradStore> {
+        let steps = self.steps.max(1) as f64;
+        let mut grads = self.sum();
+        for grad in grads.0.values_mut() {
+            *grad = grad.affine(1. / steps, 0.)?
+        }
+        Ok(grads)
+    }
 }
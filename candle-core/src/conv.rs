@@ -125,6 +125,90 @@ impl ParamsConvTranspose2D {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamsConv3D {
+    pub(crate) b_size: usize,
+    pub(crate) i_d: usize,
+    pub(crate) i_h: usize,
+    pub(crate) i_w: usize,
+    pub(crate) k_d: usize,
+    pub(crate) k_h: usize,
+    pub(crate) k_w: usize,
+    pub(crate) c_out: usize,
+    pub(crate) c_in: usize,
+    pub(crate) padding: usize,
+    pub(crate) stride: usize,
+    pub(crate) dilation: usize,
+}
+
+impl ParamsConv3D {
+    pub(crate) fn out_d(&self) -> usize {
+        (self.i_d + 2 * self.padding - self.dilation * (self.k_d - 1) - 1) / self.stride + 1
+    }
+
+    pub(crate) fn out_h(&self) -> usize {
+        (self.i_h + 2 * self.padding - self.dilation * (self.k_h - 1) - 1) / self.stride + 1
+    }
+
+    pub(crate) fn out_w(&self) -> usize {
+        (self.i_w + 2 * self.padding - self.dilation * (self.k_w - 1) - 1) / self.stride + 1
+    }
+
+    pub(crate) fn out_dims(&self) -> Vec<usize> {
+        vec![
+            self.b_size,
+            self.c_out,
+            self.out_d(),
+            self.out_h(),
+            self.out_w(),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamsConvTranspose3D {
+    pub(crate) b_size: usize,
+    pub(crate) i_d: usize,
+    pub(crate) i_h: usize,
+    pub(crate) i_w: usize,
+    pub(crate) k_d: usize,
+    pub(crate) k_h: usize,
+    pub(crate) k_w: usize,
+    pub(crate) c_out: usize,
+    pub(crate) c_in: usize,
+    pub(crate) padding: usize,
+    pub(crate) output_padding: usize,
+    pub(crate) stride: usize,
+    pub(crate) dilation: usize,
+}
+
+impl ParamsConvTranspose3D {
+    pub(crate) fn out_d(&self) -> usize {
+        (self.i_d - 1) * self.stride + self.dilation * (self.k_d - 1) + self.output_padding + 1
+            - 2 * self.padding
+    }
+
+    pub(crate) fn out_h(&self) -> usize {
+        (self.i_h - 1) * self.stride + self.dilation * (self.k_h - 1) + self.output_padding + 1
+            - 2 * self.padding
+    }
+
+    pub(crate) fn out_w(&self) -> usize {
+        (self.i_w - 1) * self.stride + self.dilation * (self.k_w - 1) + self.output_padding + 1
+            - 2 * self.padding
+    }
+
+    pub(crate) fn out_dims(&self) -> Vec<usize> {
+        vec![
+            self.b_size,
+            self.c_out,
+            self.out_d(),
+            self.out_h(),
+            self.out_w(),
+        ]
+    }
+}
+
 impl Tensor {
     fn conv1d_single_group(&self, kernel: &Self, params: &ParamsConv1D) -> Result<Self> {
         let storage =
@@ -355,4 +439,205 @@ impl Tensor {
         let out_dims = params.out_dims();
         Ok(crate::tensor::from_storage(storage, out_dims, op, false))
     }
+
+    fn conv3d_single_group(&self, kernel: &Self, params: &ParamsConv3D) -> Result<Self> {
+        let storage =
+            self.storage()
+                .conv3d(self.layout(), &kernel.storage(), kernel.layout(), params)?;
+        let op = BackpropOp::new2(self, kernel, |arg, kernel| Op::Conv3D {
+            arg,
+            kernel,
+            padding: params.padding,
+            stride: params.stride,
+            dilation: params.dilation,
+        });
+        let out_dims = params.out_dims();
+        Ok(crate::tensor::from_storage(storage, out_dims, op, false))
+    }
+
+    /// Applies a 3D convolution over the input tensor.
+    ///
+    /// The input has shape `(b, c_in, d, h, w)` and the kernel `(c_out, c_in / groups, k_d, k_h,
+    /// k_w)`.
+    pub fn conv3d(
+        &self,
+        kernel: &Self,
+        padding: usize,
+        stride: usize,
+        dilation: usize,
+        groups: usize,
+    ) -> Result<Self> {
+        let (b_size, c_in, i_d, i_h, i_w) = self.dims5()?;
+        let (c_out, c_in_k, k_d, k_h, k_w) = kernel.dims5()?;
+        if c_in != c_in_k * groups {
+            crate::bail!(
+                "in_channel mismatch between input ({c_in}, groups {groups}) and kernel ({c_in_k})"
+            )
+        }
+        let params = ParamsConv3D {
+            b_size,
+            i_d,
+            i_h,
+            i_w,
+            k_d,
+            k_h,
+            k_w,
+            c_out: c_out / groups,
+            c_in: c_in / groups,
+            padding,
+            stride,
+            dilation,
+        };
+        if groups == 1 {
+            self.conv3d_single_group(kernel, &params)
+        } else {
+            let blocks = self.chunk(groups, 1)?;
+            let kernel = kernel.chunk(groups, 0)?;
+            let blocks = blocks
+                .iter()
+                .zip(&kernel)
+                .map(|(block, kernel)| block.conv3d_single_group(kernel, &params))
+                .collect::<Result<Vec<_>>>()?;
+            Tensor::cat(&blocks, 1)
+        }
+    }
+
+    /// Applies a 3D transposed convolution over the input tensor.
+    ///
+    /// The input has shape `(b, c_in, d, h, w)` and the kernel `(c_in, c_out, k_d, k_h, k_w)`.
+    pub fn conv_transpose3d(
+        &self,
+        kernel: &Self,
+        padding: usize,
+        output_padding: usize,
+        stride: usize,
+        dilation: usize,
+    ) -> Result<Self> {
+        let (b_size, c_in, i_d, i_h, i_w) = self.dims5()?;
+        let (c_in_k, c_out, k_d, k_h, k_w) = kernel.dims5()?;
+        if c_in != c_in_k {
+            crate::bail!("in_channel mismatch between input ({c_in}) and kernel ({c_in_k})")
+        }
+        let params = ParamsConvTranspose3D {
+            b_size,
+            i_d,
+            i_h,
+            i_w,
+            k_d,
+            k_h,
+            k_w,
+            c_out,
+            c_in,
+            padding,
+            output_padding,
+            stride,
+            dilation,
+        };
+        let storage = self.storage().conv_transpose3d(
+            self.layout(),
+            &kernel.storage(),
+            kernel.layout(),
+            &params,
+        )?;
+        let op = BackpropOp::new2(self, kernel, |arg, kernel| Op::ConvTranspose3D {
+            arg,
+            kernel,
+            padding: params.padding,
+            output_padding: params.output_padding,
+            stride: params.stride,
+            dilation: params.dilation,
+        });
+        let out_dims = params.out_dims();
+        Ok(crate::tensor::from_storage(storage, out_dims, op, false))
+    }
+
+    /// Extracts the sliding blocks of a batched input tensor, also known as im2col. This has the
+    /// same semantics as the PyTorch `nn.Unfold` module.
+    ///
+    /// The input tensor has shape `(b, c, h, w)`, the returned tensor has shape
+    /// `(b, c * k_h * k_w, l)` where `l` is the number of blocks, i.e. `h_out * w_out` with these
+    /// being the output dimensions of a convolution using the same parameters.
+    pub fn unfold2d(
+        &self,
+        kernel_size: (usize, usize),
+        padding: usize,
+        stride: usize,
+        dilation: usize,
+    ) -> Result<Self> {
+        let (b, c, _h, _w) = self.dims4()?;
+        let (k_h, k_w) = kernel_size;
+        if k_h == 0 || k_w == 0 || dilation == 0 {
+            crate::bail!("unfold2d expects non-zero kernel size and dilation")
+        }
+        let xs = self
+            .pad_with_zeros(2, padding, padding)?
+            .pad_with_zeros(3, padding, padding)?;
+        // Windows of shape (b, c, h_out, w_out, dilated_h, dilated_w).
+        let xs = xs
+            .unfold(2, dilation * (k_h - 1) + 1, stride)?
+            .unfold(3, dilation * (k_w - 1) + 1, stride)?
+            .narrow_with_step(4, 0, k_h, dilation as isize)?
+            .narrow_with_step(5, 0, k_w, dilation as isize)?;
+        let (h_out, w_out) = (xs.dim(2)?, xs.dim(3)?);
+        xs.permute((0, 1, 4, 5, 2, 3))?
+            .reshape((b, c * k_h * k_w, h_out * w_out))
+    }
+
+    /// Combines an array of sliding blocks into a batched tensor, also known as col2im. This has
+    /// the same semantics as the PyTorch `nn.Fold` module, the values of overlapping blocks are
+    /// summed.
+    ///
+    /// The input tensor has shape `(b, c * k_h * k_w, l)` and the returned tensor has shape
+    /// `(b, c, h, w)` where `(h, w)` is `output_size`. This is the adjoint of `unfold2d`.
+    pub fn fold(
+        &self,
+        output_size: (usize, usize),
+        kernel_size: (usize, usize),
+        padding: usize,
+        stride: usize,
+        dilation: usize,
+    ) -> Result<Self> {
+        let (b, ckk, l) = self.dims3()?;
+        let (h, w) = output_size;
+        let (k_h, k_w) = kernel_size;
+        if k_h == 0 || k_w == 0 || dilation == 0 || stride == 0 {
+            crate::bail!("fold expects non-zero kernel size, stride and dilation")
+        }
+        if ckk % (k_h * k_w) != 0 {
+            crate::bail!("fold expects the input dim 1 ({ckk}) to be divisible by {k_h}x{k_w}")
+        }
+        let c = ckk / (k_h * k_w);
+        let (h_p, w_p) = (h + 2 * padding, w + 2 * padding);
+        let (dk_h, dk_w) = (dilation * (k_h - 1) + 1, dilation * (k_w - 1) + 1);
+        if h_p < dk_h || w_p < dk_w {
+            crate::bail!("fold kernel {kernel_size:?} is too large for output {output_size:?}")
+        }
+        let (h_out, w_out) = ((h_p - dk_h) / stride + 1, (w_p - dk_w) / stride + 1);
+        if h_out * w_out != l {
+            crate::bail!("fold expects {h_out}x{w_out} blocks, got {l}")
+        }
+        // The position in the padded output of each element of the input.
+        let mut ids = Vec::with_capacity(ckk * l);
+        for c_idx in 0..c {
+            for i_k in 0..k_h {
+                for j_k in 0..k_w {
+                    for i_out in 0..h_out {
+                        for j_out in 0..w_out {
+                            let i = i_out * stride + i_k * dilation;
+                            let j = j_out * stride + j_k * dilation;
+                            ids.push(((c_idx * h_p + i) * w_p + j) as u32)
+                        }
+                    }
+                }
+            }
+        }
+        let ids = Tensor::from_vec(ids, ckk * l, self.device())?;
+        let zeros = Tensor::zeros((b, c * h_p * w_p), self.dtype(), self.device())?;
+        let xs = self.reshape((b, ckk * l))?;
+        zeros
+            .index_add(&ids, &xs, 1)?
+            .reshape((b, c, h_p, w_p))?
+            .narrow(2, padding, h)?
+            .narrow(3, padding, w)
+    }
 }
@@ -1,8 +1,10 @@
 use crate::backend::{BackendDevice, BackendStorage};
+use crate::layout::ContiguousOffsetsWithBroadcast;
 use crate::op::{BinaryOpT, CmpOp, ReduceOp, UnaryOpT};
-use crate::{DType, Error, IntDType, Layout, Result, Shape, WithDType};
+use crate::{CpuBuffer, DType, Error, IntDType, Layout, Result, Shape, StridedIndex, WithDType};
 use half::{bf16, f16};
 use rayon::prelude::*;
+use std::borrow::Cow;
 use std::mem::take;
 
 const USE_IM2COL_CONV1D: bool = true;
@@ -12,30 +14,61 @@ const USE_IM2COL_CONV2D: bool = true;
 // intercept the oom errors to avoid panicking and provide a proper error.
 #[derive(Debug, Clone)]
 pub enum CpuStorage {
-    U8(Vec<u8>),
-    U32(Vec<u32>),
-    I64(Vec<i64>),
-    BF16(Vec<bf16>),
-    F16(Vec<f16>),
-    F32(Vec<f32>),
-    F64(Vec<f64>),
+    U8(CpuBuffer<u8>),
+    U32(CpuBuffer<u32>),
+    I64(CpuBuffer<i64>),
+    BF16(CpuBuffer<bf16>),
+    F16(CpuBuffer<f16>),
+    F32(CpuBuffer<f32>),
+    F64(CpuBuffer<f64>),
+}
+
+impl<T: WithDType> From<Vec<T>> for CpuStorage {
+    fn from(vs: Vec<T>) -> Self {
+        T::to_cpu_storage_owned(vs)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct CpuDevice;
 
+// The kernels that index the storage directly rather than going through `StridedIndex` only
+// support non-negative strides. The `map` functions below make a contiguous copy of the inputs
+// that have some negative strides so that these kernels never see such layouts.
+fn unsigned_stride(layout: &Layout) -> Result<Vec<usize>> {
+    match layout.unsigned_stride() {
+        Some(stride) => Ok(stride),
+        None => crate::bail!("unexpected negative stride {:?}", layout.stride()),
+    }
+}
+
+fn non_negative_strides<'a>(
+    vs: &'a CpuStorage,
+    layout: &'a Layout,
+) -> Result<(Cow<'a, CpuStorage>, Cow<'a, Layout>)> {
+    if !layout.has_negative_stride() {
+        return Ok((Cow::Borrowed(vs), Cow::Borrowed(layout)));
+    }
+    let mut dst = CpuDevice.zeros_impl(layout.shape(), vs.dtype())?;
+    vs.copy_strided_src(&mut dst, 0, layout)?;
+    let layout = Layout::contiguous(layout.shape());
+    Ok((Cow::Owned(dst), Cow::Owned(layout)))
+}
+
 pub trait Map1 {
     fn f<T: WithDType>(&self, vs: &[T], layout: &Layout) -> Result<Vec<T>>;
 
     fn map(&self, vs: &CpuStorage, layout: &Layout) -> Result<CpuStorage> {
-        match vs {
-            CpuStorage::U8(vs) => Ok(CpuStorage::U8(self.f(vs, layout)?)),
-            CpuStorage::U32(vs) => Ok(CpuStorage::U32(self.f(vs, layout)?)),
-            CpuStorage::I64(vs) => Ok(CpuStorage::I64(self.f(vs, layout)?)),
-            CpuStorage::BF16(vs) => Ok(CpuStorage::BF16(self.f(vs, layout)?)),
-            CpuStorage::F16(vs) => Ok(CpuStorage::F16(self.f(vs, layout)?)),
-            CpuStorage::F32(vs) => Ok(CpuStorage::F32(self.f(vs, layout)?)),
-            CpuStorage::F64(vs) => Ok(CpuStorage::F64(self.f(vs, layout)?)),
+        let (vs, layout) = non_negative_strides(vs, layout)?;
+        let layout = layout.as_ref();
+        match vs.as_ref() {
+            CpuStorage::U8(vs) => Ok(CpuStorage::U8(self.f(vs, layout)?.into())),
+            CpuStorage::U32(vs) => Ok(CpuStorage::U32(self.f(vs, layout)?.into())),
+            CpuStorage::I64(vs) => Ok(CpuStorage::I64(self.f(vs, layout)?.into())),
+            CpuStorage::BF16(vs) => Ok(CpuStorage::BF16(self.f(vs, layout)?.into())),
+            CpuStorage::F16(vs) => Ok(CpuStorage::F16(self.f(vs, layout)?.into())),
+            CpuStorage::F32(vs) => Ok(CpuStorage::F32(self.f(vs, layout)?.into())),
+            CpuStorage::F64(vs) => Ok(CpuStorage::F64(self.f(vs, layout)?.into())),
         }
     }
 }
@@ -49,14 +82,16 @@ pub trait Map1Any {
     ) -> Result<CpuStorage>;
 
     fn map(&self, vs: &CpuStorage, layout: &Layout) -> Result<CpuStorage> {
-        match vs {
-            CpuStorage::U8(vs) => Ok(self.f(vs, layout, CpuStorage::U8)?),
-            CpuStorage::U32(vs) => Ok(self.f(vs, layout, CpuStorage::U32)?),
-            CpuStorage::I64(vs) => Ok(self.f(vs, layout, CpuStorage::I64)?),
-            CpuStorage::BF16(vs) => Ok(self.f(vs, layout, CpuStorage::BF16)?),
-            CpuStorage::F16(vs) => Ok(self.f(vs, layout, CpuStorage::F16)?),
-            CpuStorage::F32(vs) => Ok(self.f(vs, layout, CpuStorage::F32)?),
-            CpuStorage::F64(vs) => Ok(self.f(vs, layout, CpuStorage::F64)?),
+        let (vs, layout) = non_negative_strides(vs, layout)?;
+        let layout = layout.as_ref();
+        match vs.as_ref() {
+            CpuStorage::U8(vs) => Ok(self.f(vs, layout, |vs| CpuStorage::U8(vs.into()))?),
+            CpuStorage::U32(vs) => Ok(self.f(vs, layout, |vs| CpuStorage::U32(vs.into()))?),
+            CpuStorage::I64(vs) => Ok(self.f(vs, layout, |vs| CpuStorage::I64(vs.into()))?),
+            CpuStorage::BF16(vs) => Ok(self.f(vs, layout, |vs| CpuStorage::BF16(vs.into()))?),
+            CpuStorage::F16(vs) => Ok(self.f(vs, layout, |vs| CpuStorage::F16(vs.into()))?),
+            CpuStorage::F32(vs) => Ok(self.f(vs, layout, |vs| CpuStorage::F32(vs.into()))?),
+            CpuStorage::F64(vs) => Ok(self.f(vs, layout, |vs| CpuStorage::F64(vs.into()))?),
         }
     }
 }
@@ -73,14 +108,17 @@ pub trait Map2 {
         v2: &CpuStorage,
         l2: &Layout,
     ) -> Result<CpuStorage> {
-        match (v1, v2) {
-            (C::U8(v1), C::U8(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::U32(v1), C::U32(v2)) => Ok(C::U32(self.f(v1, l1, v2, l2)?)),
-            (C::I64(v1), C::I64(v2)) => Ok(C::I64(self.f(v1, l1, v2, l2)?)),
-            (C::BF16(v1), C::BF16(v2)) => Ok(C::BF16(self.f(v1, l1, v2, l2)?)),
-            (C::F16(v1), C::F16(v2)) => Ok(C::F16(self.f(v1, l1, v2, l2)?)),
-            (C::F32(v1), C::F32(v2)) => Ok(C::F32(self.f(v1, l1, v2, l2)?)),
-            (C::F64(v1), C::F64(v2)) => Ok(C::F64(self.f(v1, l1, v2, l2)?)),
+        let (v1, l1) = non_negative_strides(v1, l1)?;
+        let (v2, l2) = non_negative_strides(v2, l2)?;
+        let (l1, l2) = (l1.as_ref(), l2.as_ref());
+        match (v1.as_ref(), v2.as_ref()) {
+            (C::U8(v1), C::U8(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?.into())),
+            (C::U32(v1), C::U32(v2)) => Ok(C::U32(self.f(v1, l1, v2, l2)?.into())),
+            (C::I64(v1), C::I64(v2)) => Ok(C::I64(self.f(v1, l1, v2, l2)?.into())),
+            (C::BF16(v1), C::BF16(v2)) => Ok(C::BF16(self.f(v1, l1, v2, l2)?.into())),
+            (C::F16(v1), C::F16(v2)) => Ok(C::F16(self.f(v1, l1, v2, l2)?.into())),
+            (C::F32(v1), C::F32(v2)) => Ok(C::F32(self.f(v1, l1, v2, l2)?.into())),
+            (C::F64(v1), C::F64(v2)) => Ok(C::F64(self.f(v1, l1, v2, l2)?.into())),
             _ => Err(Error::DTypeMismatchBinaryOp {
                 lhs: v1.dtype(),
                 rhs: v2.dtype(),
@@ -103,13 +141,13 @@ pub trait Map2U8 {
         l2: &Layout,
     ) -> Result<CpuStorage> {
         match (v1, v2) {
-            (C::U8(v1), C::U8(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::U32(v1), C::U32(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::I64(v1), C::I64(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::BF16(v1), C::BF16(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::F16(v1), C::F16(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::F32(v1), C::F32(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
-            (C::F64(v1), C::F64(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?)),
+            (C::U8(v1), C::U8(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?.into())),
+            (C::U32(v1), C::U32(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?.into())),
+            (C::I64(v1), C::I64(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?.into())),
+            (C::BF16(v1), C::BF16(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?.into())),
+            (C::F16(v1), C::F16(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?.into())),
+            (C::F32(v1), C::F32(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?.into())),
+            (C::F64(v1), C::F64(v2)) => Ok(C::U8(self.f(v1, l1, v2, l2)?.into())),
             _ => Err(Error::DTypeMismatchBinaryOp {
                 lhs: v1.dtype(),
                 rhs: v2.dtype(),
@@ -132,12 +170,12 @@ impl Map2U8 for Cmp {
         rhs_l: &Layout,
     ) -> Result<Vec<u8>> {
         let dst = match self.0 {
-            CmpOp::Eq => binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x == y)),
-            CmpOp::Ne => binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x != y)),
-            CmpOp::Lt => binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x < y)),
-            CmpOp::Le => binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x <= y)),
-            CmpOp::Gt => binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x > y)),
-            CmpOp::Ge => binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x >= y)),
+            CmpOp::Eq => par_binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x == y)),
+            CmpOp::Ne => par_binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x != y)),
+            CmpOp::Lt => par_binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x < y)),
+            CmpOp::Le => par_binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x <= y)),
+            CmpOp::Gt => par_binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x > y)),
+            CmpOp::Ge => par_binary_map(lhs_l, rhs_l, lhs, rhs, |x, y| u8::from(x >= y)),
         };
         Ok(dst)
     }
@@ -197,7 +235,7 @@ impl ReduceIndex {
         G: Fn(T, usize) -> U,
     {
         let reduce_dim_size = src_l.dims()[self.reduce_dim_index];
-        let reduce_dim_stride = src_l.stride()[self.reduce_dim_index];
+        let reduce_dim_stride = unsigned_stride(src_l)?[self.reduce_dim_index];
         let dst_len = src_l.shape().elem_count() / reduce_dim_size;
         let mut dst: Vec<U> = Vec::with_capacity(dst_len);
         let dst_to_set = dst.spare_capacity_mut();
@@ -277,12 +315,14 @@ impl Map1Any for ReduceIndex {
         let dst = match (self.return_index, self.use_min) {
             (false, true) => wrap(self.fold_impl(src, src_l, |x, y| x > y, |v, _i| v)?),
             (false, false) => wrap(self.fold_impl(src, src_l, |x, y| x < y, |v, _i| v)?),
-            (true, true) => {
-                CpuStorage::U32(self.fold_impl(src, src_l, |x, y| x > y, |_v, i| i as u32)?)
-            }
-            (true, false) => {
-                CpuStorage::U32(self.fold_impl(src, src_l, |x, y| x < y, |_v, i| i as u32)?)
-            }
+            (true, true) => CpuStorage::U32(
+                self.fold_impl(src, src_l, |x, y| x > y, |_v, i| i as u32)?
+                    .into(),
+            ),
+            (true, false) => CpuStorage::U32(
+                self.fold_impl(src, src_l, |x, y| x < y, |_v, i| i as u32)?
+                    .into(),
+            ),
         };
         Ok(dst)
     }
@@ -366,86 +406,378 @@ impl<'a> Map1 for ReduceSum<'a> {
     }
 }
 
-pub fn unary_map<T: Copy, U: Copy, F: FnMut(T) -> U>(
+// Elementwise kernels are only split between multiple threads when each thread gets at least
+// this many values, for smaller tensors the scheduling overhead outweighs the gains.
+const MIN_ELEMENTS_PER_THREAD: usize = 16384;
+
+// Returns a vector of `el_count` values set by a single call to `f(0, dst)`.
+fn seq_fill<U: Copy, F: FnOnce(usize, &mut [U])>(el_count: usize, f: F) -> Vec<U> {
+    let mut ys: Vec<U> = Vec::with_capacity(el_count);
+    if el_count == 0 {
+        return ys;
+    }
+    let ys_to_set = ys.spare_capacity_mut();
+    let ys_to_set =
+        unsafe { std::mem::transmute::<&mut [std::mem::MaybeUninit<U>], &mut [U]>(ys_to_set) };
+    f(0, &mut ys_to_set[..el_count]);
+    // SAFETY: values are all set by f.
+    unsafe { ys.set_len(el_count) };
+    ys
+}
+
+// Returns a vector of `el_count` values set by calling `f(dst_start, dst)` on disjoint chunks of
+// the vector, `dst_start` being the index of the first element of `dst`. The chunk lengths are
+// multiples of `align` and the chunks are processed in parallel on up to `get_num_threads()`
+// threads.
+fn par_fill<U: Copy + Send, F: Fn(usize, &mut [U]) + Sync>(
+    el_count: usize,
+    align: usize,
+    f: F,
+) -> Vec<U> {
+    let num_threads = if el_count < 2 * MIN_ELEMENTS_PER_THREAD {
+        1
+    } else {
+        crate::utils::get_num_threads().min(el_count / MIN_ELEMENTS_PER_THREAD)
+    };
+    if num_threads <= 1 {
+        return seq_fill(el_count, f);
+    }
+    let mut ys: Vec<U> = Vec::with_capacity(el_count);
+    let ys_to_set = ys.spare_capacity_mut();
+    let ys_to_set =
+        unsafe { std::mem::transmute::<&mut [std::mem::MaybeUninit<U>], &mut [U]>(ys_to_set) };
+    let align = align.max(1);
+    let chunk_len = el_count.div_ceil(num_threads).div_ceil(align) * align;
+    ys_to_set[..el_count]
+        .par_chunks_mut(chunk_len)
+        .enumerate()
+        .for_each(|(chunk_idx, ys)| f(chunk_idx * chunk_len, ys));
+    // SAFETY: values are all set by f.
+    unsafe { ys.set_len(el_count) };
+    ys
+}
+
+// The chunks passed to the `*_chunk` functions below have to start on a block boundary, i.e.
+// `dst_start` must be a multiple of this.
+fn blocks_align(blocks: &crate::StridedBlocks) -> usize {
+    match blocks {
+        crate::StridedBlocks::SingleBlock { .. } => 1,
+        crate::StridedBlocks::MultipleBlocks { block_len, .. } => *block_len,
+    }
+}
+
+// Sets `ys`, the chunk of the output that starts at index `dst_start`, to `f` applied to the
+// matching values of `vs`. This is shared by `unary_map` and `par_unary_map`.
+fn unary_map_chunk<T: Copy, U: Copy, F: FnMut(T) -> U>(
     vs: &[T],
-    layout: &Layout,
+    blocks: &crate::StridedBlocks,
+    dst_start: usize,
+    ys: &mut [U],
     mut f: F,
-) -> Vec<U> {
-    match layout.strided_blocks() {
-        crate::StridedBlocks::SingleBlock { start_offset, len } => vs
-            [start_offset..start_offset + len]
-            .iter()
-            .map(|&v| f(v))
-            .collect(),
+) {
+    match blocks {
+        crate::StridedBlocks::SingleBlock { start_offset, .. } => {
+            let vs = &vs[start_offset + dst_start..];
+            for (y, &v) in ys.iter_mut().zip(vs.iter()) {
+                *y = f(v)
+            }
+        }
         crate::StridedBlocks::MultipleBlocks {
             block_start_index,
             block_len,
         } => {
-            let mut result = Vec::with_capacity(layout.shape().elem_count());
+            let block_len = *block_len;
+            let block_start_index = block_start_index.skip_ahead(dst_start / block_len);
             // Specialize the case where block_len is one to avoid the second loop.
             if block_len == 1 {
-                for index in block_start_index {
+                for (y, index) in ys.iter_mut().zip(block_start_index) {
                     let v = unsafe { vs.get_unchecked(index) };
-                    result.push(f(*v))
+                    *y = f(*v)
                 }
             } else {
-                for index in block_start_index {
-                    for offset in 0..block_len {
+                for (ys, index) in ys.chunks_exact_mut(block_len).zip(block_start_index) {
+                    for (offset, y) in ys.iter_mut().enumerate() {
                         let v = unsafe { vs.get_unchecked(index + offset) };
-                        result.push(f(*v))
+                        *y = f(*v)
                     }
                 }
             }
-            result
         }
     }
 }
 
+// Same as `unary_map_chunk` but processes whole blocks with `f_vec`.
+fn unary_map_vec_chunk<T: Copy, U: Copy, F: FnMut(T) -> U, FV: FnMut(&[T], &mut [U])>(
+    vs: &[T],
+    blocks: &crate::StridedBlocks,
+    dst_start: usize,
+    ys: &mut [U],
+    f: F,
+    mut f_vec: FV,
+) {
+    match blocks {
+        crate::StridedBlocks::SingleBlock { start_offset, .. } => {
+            let start = start_offset + dst_start;
+            f_vec(&vs[start..start + ys.len()], ys)
+        }
+        // Specialize the case where block_len is one to avoid the second loop.
+        crate::StridedBlocks::MultipleBlocks { block_len: 1, .. } => {
+            unary_map_chunk(vs, blocks, dst_start, ys, f)
+        }
+        crate::StridedBlocks::MultipleBlocks {
+            block_start_index,
+            block_len,
+        } => {
+            let block_len = *block_len;
+            let block_start_index = block_start_index.skip_ahead(dst_start / block_len);
+            for (ys, src_index) in ys.chunks_exact_mut(block_len).zip(block_start_index) {
+                f_vec(&vs[src_index..src_index + block_len], ys)
+            }
+        }
+    }
+}
+
+pub fn unary_map<T: Copy, U: Copy, F: FnMut(T) -> U>(
+    vs: &[T],
+    layout: &Layout,
+    mut f: F,
+) -> Vec<U> {
+    let blocks = layout.strided_blocks();
+    seq_fill(layout.shape().elem_count(), |dst_start, ys| {
+        unary_map_chunk(vs, &blocks, dst_start, ys, &mut f)
+    })
+}
+
 pub fn unary_map_vec<T: Copy, U: Copy, F: FnMut(T) -> U, FV: FnMut(&[T], &mut [U])>(
     vs: &[T],
     layout: &Layout,
     mut f: F,
     mut f_vec: FV,
 ) -> Vec<U> {
-    match layout.strided_blocks() {
-        crate::StridedBlocks::SingleBlock { start_offset, len } => {
-            let mut ys: Vec<U> = Vec::with_capacity(len);
-            let ys_to_set = ys.spare_capacity_mut();
-            let ys_to_set = unsafe { std::mem::transmute::<_, &mut [U]>(ys_to_set) };
-            f_vec(&vs[start_offset..start_offset + len], ys_to_set);
-            // SAFETY: values are all set by f_vec.
-            unsafe { ys.set_len(len) };
-            ys
+    let blocks = layout.strided_blocks();
+    seq_fill(layout.shape().elem_count(), |dst_start, ys| {
+        unary_map_vec_chunk(vs, &blocks, dst_start, ys, &mut f, &mut f_vec)
+    })
+}
+
+// Parallel version of `unary_map`, the closure is called from multiple threads.
+pub fn par_unary_map<T: Copy + Sync, U: Copy + Send, F: Fn(T) -> U + Sync>(
+    vs: &[T],
+    layout: &Layout,
+    f: F,
+) -> Vec<U> {
+    let blocks = layout.strided_blocks();
+    let align = blocks_align(&blocks);
+    par_fill(layout.shape().elem_count(), align, |dst_start, ys| {
+        unary_map_chunk(vs, &blocks, dst_start, ys, &f)
+    })
+}
+
+// Parallel version of `unary_map_vec`.
+pub fn par_unary_map_vec<
+    T: Copy + Sync,
+    U: Copy + Send,
+    F: Fn(T) -> U + Sync,
+    FV: Fn(&[T], &mut [U]) + Sync,
+>(
+    vs: &[T],
+    layout: &Layout,
+    f: F,
+    f_vec: FV,
+) -> Vec<U> {
+    let blocks = layout.strided_blocks();
+    let align = blocks_align(&blocks);
+    par_fill(layout.shape().elem_count(), align, |dst_start, ys| {
+        unary_map_vec_chunk(vs, &blocks, dst_start, ys, &f, &f_vec)
+    })
+}
+
+// The ways of walking through the two inputs of a binary op, from the fastest to the slowest.
+enum BinaryBlocks<'a, T> {
+    // Both inputs are contiguous.
+    Contiguous {
+        lhs: &'a [T],
+        rhs: &'a [T],
+    },
+    // The lhs is contiguous and the rhs is the contiguous block `rhs` broadcast over `ob`.
+    RhsBroadcast {
+        lhs: &'a [T],
+        rhs: &'a [T],
+        ob: ContiguousOffsetsWithBroadcast,
+    },
+    // The rhs is contiguous and the lhs is the contiguous block `lhs` broadcast over `ob`.
+    LhsBroadcast {
+        lhs: &'a [T],
+        rhs: &'a [T],
+        ob: ContiguousOffsetsWithBroadcast,
+    },
+    // There is no simpler layout to take advantage of.
+    Strided {
+        lhs: &'a [T],
+        rhs: &'a [T],
+        lhs_index: StridedIndex<'a>,
+        rhs_index: StridedIndex<'a>,
+    },
+}
+
+impl<'a, T: Copy> BinaryBlocks<'a, T> {
+    fn new(lhs_l: &'a Layout, rhs_l: &'a Layout, lhs: &'a [T], rhs: &'a [T]) -> Self {
+        let strided = || Self::Strided {
+            lhs,
+            rhs,
+            lhs_index: lhs_l.strided_index(),
+            rhs_index: rhs_l.strided_index(),
+        };
+        // TODO: Maybe we want to avoid going through the layout twice.
+        match (lhs_l.contiguous_offsets(), rhs_l.contiguous_offsets()) {
+            (Some((o_l1, o_l2)), Some((o_r1, o_r2))) => Self::Contiguous {
+                lhs: &lhs[o_l1..o_l2],
+                rhs: &rhs[o_r1..o_r2],
+            },
+            (Some((o_l1, o_l2)), None) => match rhs_l.offsets_b() {
+                Some(ob) => Self::RhsBroadcast {
+                    lhs: &lhs[o_l1..o_l2],
+                    rhs: &rhs[ob.start..ob.start + ob.len],
+                    ob,
+                },
+                None => strided(),
+            },
+            (None, Some((o_r1, o_r2))) => match lhs_l.offsets_b() {
+                Some(ob) => Self::LhsBroadcast {
+                    lhs: &lhs[ob.start..ob.start + ob.len],
+                    rhs: &rhs[o_r1..o_r2],
+                    ob,
+                },
+                None => strided(),
+            },
+            _ => strided(),
         }
-        crate::StridedBlocks::MultipleBlocks {
-            block_start_index,
-            block_len,
+    }
+
+    // The chunk alignment required by `binary_map_vec_chunk`, `binary_map_chunk` accepts any
+    // chunk.
+    fn vec_align(&self) -> usize {
+        match self {
+            Self::RhsBroadcast { ob, .. } | Self::LhsBroadcast { ob, .. } => {
+                ob.len * ob.right_broadcast
+            }
+            Self::Contiguous { .. } | Self::Strided { .. } => 1,
+        }
+    }
+}
+
+// Sets `ys`, the chunk of the output that starts at index `dst_start`, to `f` applied to the
+// matching values of the two inputs. This is shared by `binary_map` and `par_binary_map`.
+fn binary_map_chunk<T: Copy, U: Copy, F: FnMut(T, T) -> U>(
+    blocks: &BinaryBlocks<T>,
+    dst_start: usize,
+    ys: &mut [U],
+    mut f: F,
+) {
+    match blocks {
+        BinaryBlocks::Contiguous { lhs, rhs } => {
+            let lhs = lhs[dst_start..].iter();
+            let rhs = rhs[dst_start..].iter();
+            for ((y, &l), &r) in ys.iter_mut().zip(lhs).zip(rhs) {
+                *y = f(l, r)
+            }
+        }
+        BinaryBlocks::RhsBroadcast { lhs, rhs, ob } => {
+            let mut i_in_block = (dst_start / ob.right_broadcast) % ob.len;
+            let mut i_right_broadcast = dst_start % ob.right_broadcast;
+            for (y, &l) in ys.iter_mut().zip(lhs[dst_start..].iter()) {
+                let r = unsafe { rhs.get_unchecked(i_in_block) };
+                i_right_broadcast += 1;
+                if i_right_broadcast >= ob.right_broadcast {
+                    i_in_block += 1;
+                    i_right_broadcast = 0;
+                }
+                if i_in_block >= ob.len {
+                    i_in_block = 0
+                }
+                *y = f(l, *r)
+            }
+        }
+        BinaryBlocks::LhsBroadcast { lhs, rhs, ob } => {
+            let mut i_in_block = (dst_start / ob.right_broadcast) % ob.len;
+            let mut i_right_broadcast = dst_start % ob.right_broadcast;
+            for (y, &r) in ys.iter_mut().zip(rhs[dst_start..].iter()) {
+                let l = unsafe { lhs.get_unchecked(i_in_block) };
+                i_right_broadcast += 1;
+                if i_right_broadcast >= ob.right_broadcast {
+                    i_in_block += 1;
+                    i_right_broadcast = 0;
+                }
+                if i_in_block >= ob.len {
+                    i_in_block = 0
+                }
+                *y = f(*l, r)
+            }
+        }
+        BinaryBlocks::Strided {
+            lhs,
+            rhs,
+            lhs_index,
+            rhs_index,
         } => {
-            let el_count = layout.shape().elem_count();
-            // Specialize the case where block_len is one to avoid the second loop.
-            if block_len == 1 {
-                let mut result = Vec::with_capacity(el_count);
-                for index in block_start_index {
-                    let v = unsafe { vs.get_unchecked(index) };
-                    result.push(f(*v))
+            let lhs_index = lhs_index.skip_ahead(dst_start);
+            let rhs_index = rhs_index.skip_ahead(dst_start);
+            for ((y, lhs_i), rhs_i) in ys.iter_mut().zip(lhs_index).zip(rhs_index) {
+                *y = f(lhs[lhs_i], rhs[rhs_i])
+            }
+        }
+    }
+}
+
+// Same as `binary_map_chunk` but processes whole blocks with `f_vec` when possible, `dst_start`
+// has to be a multiple of `blocks.vec_align()`.
+fn binary_map_vec_chunk<T: Copy, F: FnMut(T, T) -> T, FV: FnMut(&[T], &[T], &mut [T])>(
+    blocks: &BinaryBlocks<T>,
+    dst_start: usize,
+    ys: &mut [T],
+    mut f: F,
+    mut f_vec: FV,
+) {
+    let dst_end = dst_start + ys.len();
+    match blocks {
+        BinaryBlocks::Contiguous { lhs, rhs } => {
+            f_vec(&lhs[dst_start..dst_end], &rhs[dst_start..dst_end], ys)
+        }
+        BinaryBlocks::RhsBroadcast { lhs, rhs, ob } if ob.right_broadcast == 1 => {
+            let lhs = &lhs[dst_start..dst_end];
+            for (lhs, ys) in lhs.chunks_exact(ob.len).zip(ys.chunks_exact_mut(ob.len)) {
+                f_vec(lhs, rhs, ys)
+            }
+        }
+        BinaryBlocks::LhsBroadcast { lhs, rhs, ob } if ob.right_broadcast == 1 => {
+            let rhs = &rhs[dst_start..dst_end];
+            for (rhs, ys) in rhs.chunks_exact(ob.len).zip(ys.chunks_exact_mut(ob.len)) {
+                f_vec(lhs, rhs, ys)
+            }
+        }
+        BinaryBlocks::RhsBroadcast { lhs, rhs, ob } => {
+            ys.copy_from_slice(&lhs[dst_start..dst_end]);
+            for ys in ys.chunks_exact_mut(ob.len * ob.right_broadcast) {
+                for (i, &r) in rhs.iter().enumerate() {
+                    let start = i * ob.right_broadcast;
+                    for v in ys[start..start + ob.right_broadcast].iter_mut() {
+                        *v = f(*v, r)
+                    }
                 }
-                result
-            } else {
-                let mut ys: Vec<U> = Vec::with_capacity(el_count);
-                let ys_to_set = ys.spare_capacity_mut();
-                let ys_to_set = unsafe { std::mem::transmute::<_, &mut [U]>(ys_to_set) };
-                let mut dst_index = 0;
-                for src_index in block_start_index {
-                    let vs = &vs[src_index..src_index + block_len];
-                    let ys = &mut ys_to_set[dst_index..dst_index + block_len];
-                    f_vec(vs, ys);
-                    dst_index += block_len;
+            }
+        }
+        BinaryBlocks::LhsBroadcast { lhs, rhs, ob } => {
+            ys.copy_from_slice(&rhs[dst_start..dst_end]);
+            for ys in ys.chunks_exact_mut(ob.len * ob.right_broadcast) {
+                for (i, &l) in lhs.iter().enumerate() {
+                    let start = i * ob.right_broadcast;
+                    for v in ys[start..start + ob.right_broadcast].iter_mut() {
+                        *v = f(l, *v)
+                    }
                 }
-                // SAFETY: values are all set by f_vec.
-                unsafe { ys.set_len(el_count) };
-                ys
             }
         }
+        BinaryBlocks::Strided { .. } => binary_map_chunk(blocks, dst_start, ys, f),
     }
 }
 
@@ -457,79 +789,12 @@ pub fn binary_map<T: Copy, U: Copy, F: FnMut(T, T) -> U>(
     rhs: &[T],
     mut f: F,
 ) -> Vec<U> {
-    match (lhs_l.contiguous_offsets(), rhs_l.contiguous_offsets()) {
-        (Some((o_l1, o_l2)), Some((o_r1, o_r2))) => lhs[o_l1..o_l2]
-            .iter()
-            .zip(rhs[o_r1..o_r2].iter())
-            .map(|(&l, &r)| f(l, r))
-            .collect(),
-        (Some((o_l1, o_l2)), None) => {
-            // TODO: Maybe we want to avoid going through the layout twice.
-            match rhs_l.offsets_b() {
-                Some(ob) => {
-                    let mut i_in_block = 0;
-                    let mut i_right_broadcast = 0;
-                    lhs[o_l1..o_l2]
-                        .iter()
-                        .map(|&l| {
-                            let r = unsafe { rhs.get_unchecked(i_in_block + ob.start) };
-                            i_right_broadcast += 1;
-                            if i_right_broadcast >= ob.right_broadcast {
-                                i_in_block += 1;
-                                i_right_broadcast = 0;
-                            }
-                            if i_in_block >= ob.len {
-                                i_in_block = 0
-                            }
-                            f(l, *r)
-                        })
-                        .collect()
-                }
-                None => lhs_l
-                    .strided_index()
-                    .zip(rhs_l.strided_index())
-                    .map(|(lhs_i, rhs_i)| f(lhs[lhs_i], rhs[rhs_i]))
-                    .collect(),
-            }
-        }
-        (None, Some((o_r1, o_r2))) => {
-            // TODO: Maybe we want to avoid going through the layout twice.
-            match lhs_l.offsets_b() {
-                Some(ob) => {
-                    let mut i_in_block = 0;
-                    let mut i_right_broadcast = 0;
-                    rhs[o_r1..o_r2]
-                        .iter()
-                        .map(|&r| {
-                            let l = unsafe { lhs.get_unchecked(i_in_block + ob.start) };
-                            i_right_broadcast += 1;
-                            if i_right_broadcast >= ob.right_broadcast {
-                                i_in_block += 1;
-                                i_right_broadcast = 0;
-                            }
-                            if i_in_block >= ob.len {
-                                i_in_block = 0
-                            }
-                            f(*l, r)
-                        })
-                        .collect()
-                }
-                None => lhs_l
-                    .strided_index()
-                    .zip(rhs_l.strided_index())
-                    .map(|(lhs_i, rhs_i)| f(lhs[lhs_i], rhs[rhs_i]))
-                    .collect(),
-            }
-        }
-        _ => lhs_l
-            .strided_index()
-            .zip(rhs_l.strided_index())
-            .map(|(lhs_i, rhs_i)| f(lhs[lhs_i], rhs[rhs_i]))
-            .collect(),
-    }
+    let blocks = BinaryBlocks::new(lhs_l, rhs_l, lhs, rhs);
+    seq_fill(lhs_l.shape().elem_count(), |dst_start, ys| {
+        binary_map_chunk(&blocks, dst_start, ys, &mut f)
+    })
 }
 
-// Similar to binary_map but with vectorized variants.
 pub fn binary_map_vec<T: Copy, F: FnMut(T, T) -> T, FV: FnMut(&[T], &[T], &mut [T])>(
     lhs_l: &Layout,
     rhs_l: &Layout,
@@ -538,101 +803,45 @@ pub fn binary_map_vec<T: Copy, F: FnMut(T, T) -> T, FV: FnMut(&[T], &[T], &mut [
     mut f: F,
     mut f_vec: FV,
 ) -> Vec<T> {
-    let el_count = lhs_l.shape().elem_count();
-    match (lhs_l.contiguous_offsets(), rhs_l.contiguous_offsets()) {
-        (Some((o_l1, o_l2)), Some((o_r1, o_r2))) => {
-            let mut ys: Vec<T> = Vec::with_capacity(el_count);
-            let ys_to_set = ys.spare_capacity_mut();
-            let ys_to_set = unsafe { std::mem::transmute::<_, &mut [T]>(ys_to_set) };
-            f_vec(&lhs[o_l1..o_l2], &rhs[o_r1..o_r2], ys_to_set);
-            // SAFETY: values are all set by f_vec.
-            unsafe { ys.set_len(el_count) };
-            ys
-        }
-        (Some((o_l1, o_l2)), None) => match rhs_l.offsets_b() {
-            Some(ob) if ob.right_broadcast == 1 => {
-                let rhs = &rhs[ob.start..ob.start + ob.len];
-                let mut ys: Vec<T> = Vec::with_capacity(el_count);
-                let ys_to_set = ys.spare_capacity_mut();
-                let ys_to_set = unsafe { std::mem::transmute::<_, &mut [T]>(ys_to_set) };
-                let mut dst_i = 0;
-                for src_i in (o_l1..o_l2).step_by(ob.len) {
-                    f_vec(
-                        &lhs[src_i..src_i + ob.len],
-                        rhs,
-                        &mut ys_to_set[dst_i..dst_i + ob.len],
-                    );
-                    dst_i += ob.len;
-                }
-                // SAFETY: values are all set by f_vec.
-                unsafe { ys.set_len(el_count) };
-                ys
-            }
-            Some(ob) => {
-                let rhs = &rhs[ob.start..ob.start + ob.len];
-                let mut ys = lhs[o_l1..o_l2].to_vec();
-                for idx_l in 0..ob.left_broadcast {
-                    let start = idx_l * ob.len * ob.right_broadcast;
-                    for (i, &r) in rhs.iter().enumerate() {
-                        let start = start + i * ob.right_broadcast;
-                        for v in ys[start..start + ob.right_broadcast].iter_mut() {
-                            *v = f(*v, r)
-                        }
-                    }
-                }
-                ys
-            }
-            None => lhs_l
-                .strided_index()
-                .zip(rhs_l.strided_index())
-                .map(|(lhs_i, rhs_i)| f(lhs[lhs_i], rhs[rhs_i]))
-                .collect(),
-        },
-        (None, Some((o_r1, o_r2))) => match lhs_l.offsets_b() {
-            Some(ob) if ob.right_broadcast == 1 => {
-                let lhs = &lhs[ob.start..ob.start + ob.len];
-                let mut ys: Vec<T> = Vec::with_capacity(el_count);
-                let ys_to_set = ys.spare_capacity_mut();
-                let ys_to_set = unsafe { std::mem::transmute::<_, &mut [T]>(ys_to_set) };
-                let mut dst_i = 0;
-                for src_i in (o_r1..o_r2).step_by(ob.len) {
-                    f_vec(
-                        lhs,
-                        &rhs[src_i..src_i + ob.len],
-                        &mut ys_to_set[dst_i..dst_i + ob.len],
-                    );
-                    dst_i += ob.len;
-                }
-                // SAFETY: values are all set by f_vec.
-                unsafe { ys.set_len(el_count) };
-                ys
-            }
-            Some(ob) => {
-                let lhs = &lhs[ob.start..ob.start + ob.len];
-                let mut ys = rhs[o_r1..o_r2].to_vec();
-                for idx_l in 0..ob.left_broadcast {
-                    let start = idx_l * ob.len * ob.right_broadcast;
-                    for (i, &l) in lhs.iter().enumerate() {
-                        let start = start + i * ob.right_broadcast;
-                        for v in ys[start..start + ob.right_broadcast].iter_mut() {
-                            *v = f(l, *v)
-                        }
-                    }
-                }
-                ys
-            }
-            None => lhs_l
-                .strided_index()
-                .zip(rhs_l.strided_index())
-                .map(|(lhs_i, rhs_i)| f(lhs[lhs_i], rhs[rhs_i]))
-                .collect(),
-        },
-        _ => lhs_l
-            .strided_index()
-            .zip(rhs_l.strided_index())
-            .map(|(lhs_i, rhs_i)| f(lhs[lhs_i], rhs[rhs_i]))
-            .collect(),
-    }
+    let blocks = BinaryBlocks::new(lhs_l, rhs_l, lhs, rhs);
+    seq_fill(lhs_l.shape().elem_count(), |dst_start, ys| {
+        binary_map_vec_chunk(&blocks, dst_start, ys, &mut f, &mut f_vec)
+    })
+}
+
+// Parallel version of `binary_map`, the closure is called from multiple threads.
+pub fn par_binary_map<T: Copy + Sync, U: Copy + Send, F: Fn(T, T) -> U + Sync>(
+    lhs_l: &Layout,
+    rhs_l: &Layout,
+    lhs: &[T],
+    rhs: &[T],
+    f: F,
+) -> Vec<U> {
+    let blocks = BinaryBlocks::new(lhs_l, rhs_l, lhs, rhs);
+    par_fill(lhs_l.shape().elem_count(), 1, |dst_start, ys| {
+        binary_map_chunk(&blocks, dst_start, ys, &f)
+    })
+}
+
+// Parallel version of `binary_map_vec`.
+pub fn par_binary_map_vec<
+    T: Copy + Send + Sync,
+    F: Fn(T, T) -> T + Sync,
+    FV: Fn(&[T], &[T], &mut [T]) + Sync,
+>(
+    lhs_l: &Layout,
+    rhs_l: &Layout,
+    lhs: &[T],
+    rhs: &[T],
+    f: F,
+    f_vec: FV,
+) -> Vec<T> {
+    let blocks = BinaryBlocks::new(lhs_l, rhs_l, lhs, rhs);
+    par_fill(
+        lhs_l.shape().elem_count(),
+        blocks.vec_align(),
+        |dst_start, ys| binary_map_vec_chunk(&blocks, dst_start, ys, &f, &f_vec),
+    )
 }
 
 struct Affine(f64, f64);
@@ -641,7 +850,7 @@ impl Map1 for Affine {
     fn f<T: WithDType>(&self, vs: &[T], layout: &Layout) -> Result<Vec<T>> {
         let mul = T::from_f64(self.0);
         let add = T::from_f64(self.1);
-        Ok(unary_map(vs, layout, |v| v * mul + add))
+        Ok(par_unary_map(vs, layout, |v| v * mul + add))
     }
 }
 
@@ -653,7 +862,7 @@ impl Map1 for AvgPool2D {
         let (k_h, k_w) = self.0;
         let (s_h, s_w) = self.1;
         let (b_sz, c, h, w) = layout.shape().dims4()?;
-        let stride = layout.stride();
+        let stride = unsigned_stride(layout)?;
         let (stride_h, stride_w) = (stride[2], stride[3]);
         let h_out = (h - k_h) / s_h + 1;
         let w_out = (w - k_w) / s_w + 1;
@@ -694,7 +903,7 @@ impl Map1 for MaxPool2D {
         let (k_h, k_w) = self.0;
         let (s_h, s_w) = self.1;
         let (b_sz, c, h, w) = layout.shape().dims4()?;
-        let stride = layout.stride();
+        let stride = unsigned_stride(layout)?;
         let (stride_h, stride_w) = (stride[2], stride[3]);
         let h_out = (h - k_h) / s_h + 1;
         let w_out = (w - k_w) / s_w + 1;
@@ -728,6 +937,108 @@ impl Map1 for MaxPool2D {
     }
 }
 
+struct AvgPool3D((usize, usize, usize), (usize, usize, usize));
+
+impl Map1 for AvgPool3D {
+    fn f<T: WithDType>(&self, src: &[T], layout: &Layout) -> Result<Vec<T>> {
+        // https://pytorch.org/docs/stable/generated/torch.nn.AvgPool3d.html
+        let (k_d, k_h, k_w) = self.0;
+        let (s_d, s_h, s_w) = self.1;
+        let (b_sz, c, d, h, w) = layout.shape().dims5()?;
+        let stride = unsigned_stride(layout)?;
+        let (stride_d, stride_h, stride_w) = (stride[2], stride[3], stride[4]);
+        let d_out = (d - k_d) / s_d + 1;
+        let h_out = (h - k_h) / s_h + 1;
+        let w_out = (w - k_w) / s_w + 1;
+        let src_index = layout.start_offset();
+        let mut dst = vec![T::zero(); b_sz * c * d_out * h_out * w_out];
+        let scale = 1f64 / (k_d * k_h * k_w) as f64;
+        let scale = T::from_f64(scale);
+        for b_idx in 0..b_sz {
+            let dst = &mut dst[b_idx * c * d_out * h_out * w_out..];
+            let src_index = src_index + b_idx * stride[0];
+            for c_idx in 0..c {
+                let dst = &mut dst[c_idx * d_out * h_out * w_out..];
+                let src_index = src_index + c_idx * stride[1];
+                for d_idx in 0..d_out {
+                    for h_idx in 0..h_out {
+                        for w_idx in 0..w_out {
+                            let mut sum = T::zero();
+                            for l in 0..k_d {
+                                for m in 0..k_h {
+                                    for n in 0..k_w {
+                                        let l = s_d * d_idx + l;
+                                        let m = s_h * h_idx + m;
+                                        let n = s_w * w_idx + n;
+                                        sum += src
+                                            [src_index + l * stride_d + m * stride_h + n * stride_w]
+                                    }
+                                }
+                            }
+                            dst[(d_idx * h_out + h_idx) * w_out + w_idx] = sum * scale;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(dst)
+    }
+}
+
+struct MaxPool3D((usize, usize, usize), (usize, usize, usize));
+
+impl Map1 for MaxPool3D {
+    fn f<T: WithDType>(&self, src: &[T], layout: &Layout) -> Result<Vec<T>> {
+        // https://pytorch.org/docs/stable/generated/torch.nn.MaxPool3d.html
+        let (k_d, k_h, k_w) = self.0;
+        let (s_d, s_h, s_w) = self.1;
+        let (b_sz, c, d, h, w) = layout.shape().dims5()?;
+        let stride = unsigned_stride(layout)?;
+        let (stride_d, stride_h, stride_w) = (stride[2], stride[3], stride[4]);
+        let d_out = (d - k_d) / s_d + 1;
+        let h_out = (h - k_h) / s_h + 1;
+        let w_out = (w - k_w) / s_w + 1;
+        let src_index = layout.start_offset();
+        let mut dst = vec![T::zero(); b_sz * c * d_out * h_out * w_out];
+        for b_idx in 0..b_sz {
+            let dst = &mut dst[b_idx * c * d_out * h_out * w_out..];
+            let src_index = src_index + b_idx * stride[0];
+            for c_idx in 0..c {
+                let dst = &mut dst[c_idx * d_out * h_out * w_out..];
+                let src_index = src_index + c_idx * stride[1];
+                for d_idx in 0..d_out {
+                    for h_idx in 0..h_out {
+                        for w_idx in 0..w_out {
+                            let mut largest = src[src_index
+                                + s_d * d_idx * stride_d
+                                + s_h * h_idx * stride_h
+                                + s_w * w_idx * stride_w];
+                            for l in 0..k_d {
+                                for m in 0..k_h {
+                                    for n in 0..k_w {
+                                        let l = s_d * d_idx + l;
+                                        let m = s_h * h_idx + m;
+                                        let n = s_w * w_idx + n;
+                                        let v = src[src_index
+                                            + l * stride_d
+                                            + m * stride_h
+                                            + n * stride_w];
+                                        if largest < v {
+                                            largest = v
+                                        }
+                                    }
+                                }
+                            }
+                            dst[(d_idx * h_out + h_idx) * w_out + w_idx] = largest;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(dst)
+    }
+}
+
 struct UpsampleNearest1D(usize);
 
 impl Map1 for UpsampleNearest1D {
@@ -735,7 +1046,7 @@ impl Map1 for UpsampleNearest1D {
         // TODO: Specialized implementation for the case 2*sz?
         let dst_sz = self.0;
         let (b_sz, c, src_sz) = layout.shape().dims3()?;
-        let stride = layout.stride();
+        let stride = unsigned_stride(layout)?;
         let stride_sz = stride[2];
         let src_index = layout.start_offset();
         let scale_sz = src_sz as f64 / dst_sz as f64;
@@ -765,7 +1076,7 @@ impl Map1 for UpsampleNearest2D {
         // TODO: Specialized implementation for the case 2*h, 2*w?
         let (dst_h, dst_w) = (self.0, self.1);
         let (b_sz, c, src_h, src_w) = layout.shape().dims4()?;
-        let stride = layout.stride();
+        let stride = unsigned_stride(layout)?;
         let (stride_h, stride_w) = (stride[2], stride[3]);
         let src_index = layout.start_offset();
         let scale_h = src_h as f64 / dst_h as f64;
@@ -870,7 +1181,7 @@ impl<'a, I: IntDType> Map1 for IndexSelect<'a, I> {
             }
             .bt())?,
         };
-        let stride_ids = self.ids_l.stride()[0];
+        let stride_ids = unsigned_stride(self.ids_l)?[0];
         let mut dst_dims = layout.dims().to_vec();
         let src_dim = dst_dims[dim];
         dst_dims[dim] = n_ids;
@@ -1069,8 +1380,8 @@ impl<'a> Map2 for Conv1D<'a> {
         let p = self.0;
         let inp = &inp[inp_l.start_offset()..];
         let k = &k[k_l.start_offset()..];
-        let (inp_s0, inp_s1, inp_s2) = crate::shape::dims3(inp_l.stride())?;
-        let (k_s0, k_s1, k_s2) = crate::shape::dims3(k_l.stride())?;
+        let (inp_s0, inp_s1, inp_s2) = crate::shape::dims3(&unsigned_stride(inp_l)?)?;
+        let (k_s0, k_s1, k_s2) = crate::shape::dims3(&unsigned_stride(k_l)?)?;
         let l_out = p.l_out();
         let dst_elems = p.c_out * l_out * p.b_size;
         // The output shape is [b_size, c_out, l_out]
@@ -1149,7 +1460,7 @@ impl Map1 for Im2Col1D {
         let src = &vs[layout.start_offset()..];
         let mut dst = vec![T::zero(); b * l_out * c * l_k];
         let (src_s0, src_s1, src_s2) = {
-            let s = layout.stride();
+            let s = unsigned_stride(layout)?;
             (s[0], s[1], s[2])
         };
         // TODO: provide specialized kernels for the common use cases.
@@ -1212,7 +1523,7 @@ impl Map1 for Im2Col {
         let src = &vs[layout.start_offset()..];
         let mut dst = vec![T::zero(); b * h_out * w_out * c * h_k * w_k];
         let (src_s0, src_s1, src_s2, src_s3) = {
-            let s = layout.stride();
+            let s = unsigned_stride(layout)?;
             (s[0], s[1], s[2], s[3])
         };
         // TODO: provide specialized kernels for the common use cases.
@@ -1265,8 +1576,8 @@ impl<'a> Map2 for ConvTranspose1D<'a> {
         let p = self.0;
         let inp = &inp[inp_l.start_offset()..];
         let k = &k[k_l.start_offset()..];
-        let (inp_s0, inp_s1, inp_s2) = crate::shape::dims3(inp_l.stride())?;
-        let (k_s0, k_s1, k_s2) = crate::shape::dims3(k_l.stride())?;
+        let (inp_s0, inp_s1, inp_s2) = crate::shape::dims3(&unsigned_stride(inp_l)?)?;
+        let (k_s0, k_s1, k_s2) = crate::shape::dims3(&unsigned_stride(k_l)?)?;
         let l_out = p.l_out();
 
         // Output shape: [b_size, c_out, l_out].
@@ -1333,9 +1644,9 @@ impl<'a> Map2 for Conv2D<'a> {
     fn f<T: WithDType>(&self, inp: &[T], inp_l: &Layout, k: &[T], k_l: &Layout) -> Result<Vec<T>> {
         let p = self.0;
         let inp = &inp[inp_l.start_offset()..];
-        let (inp_s0, inp_s1, inp_s2, inp_s3) = crate::shape::dims4(inp_l.stride())?;
+        let (inp_s0, inp_s1, inp_s2, inp_s3) = crate::shape::dims4(&unsigned_stride(inp_l)?)?;
         let k = &k[k_l.start_offset()..];
-        let (k_s0, k_s1, k_s2, k_s3) = crate::shape::dims4(k_l.stride())?;
+        let (k_s0, k_s1, k_s2, k_s3) = crate::shape::dims4(&unsigned_stride(k_l)?)?;
         let (out_h, out_w) = (p.out_h(), p.out_w());
 
         // Output shape: [b_size, c_out, out_h, out_w].
@@ -1421,9 +1732,9 @@ impl<'a> Map2 for ConvTranspose2D<'a> {
     fn f<T: WithDType>(&self, inp: &[T], inp_l: &Layout, k: &[T], k_l: &Layout) -> Result<Vec<T>> {
         let p = self.0;
         let inp = &inp[inp_l.start_offset()..];
-        let (inp_s0, inp_s1, inp_s2, inp_s3) = crate::shape::dims4(inp_l.stride())?;
+        let (inp_s0, inp_s1, inp_s2, inp_s3) = crate::shape::dims4(&unsigned_stride(inp_l)?)?;
         let k = &k[k_l.start_offset()..];
-        let (k_s0, k_s1, k_s2, k_s3) = crate::shape::dims4(k_l.stride())?;
+        let (k_s0, k_s1, k_s2, k_s3) = crate::shape::dims4(&unsigned_stride(k_l)?)?;
         let (out_h, out_w) = (p.out_h(), p.out_w());
 
         // Output shape: [b_size, c_out, out_h, out_w].
@@ -1504,6 +1815,208 @@ impl<'a> Map2 for ConvTranspose2D<'a> {
     }
 }
 
+struct Im2Col3D {
+    d_k: usize,
+    h_k: usize,
+    w_k: usize,
+    stride: usize,
+    dilation: usize,
+    padding: usize,
+}
+
+impl Im2Col3D {
+    fn dhw_out(&self, d: usize, h: usize, w: usize) -> (usize, usize, usize) {
+        let out = |i: usize, k: usize| {
+            (i + 2 * self.padding - self.dilation * (k - 1) - 1) / self.stride + 1
+        };
+        (out(d, self.d_k), out(h, self.h_k), out(w, self.w_k))
+    }
+}
+
+impl Map1 for Im2Col3D {
+    fn f<T: WithDType>(&self, vs: &[T], layout: &Layout) -> Result<Vec<T>> {
+        let &Self {
+            d_k,
+            h_k,
+            w_k,
+            stride,
+            dilation,
+            padding,
+        } = self;
+        let (b, c, d, h, w) = layout.shape().dims5()?;
+        let (d_out, h_out, w_out) = self.dhw_out(d, h, w);
+        let src = &vs[layout.start_offset()..];
+        let k_el = c * d_k * h_k * w_k;
+        let mut dst = vec![T::zero(); b * d_out * h_out * w_out * k_el];
+        let (src_s0, src_s1, src_s2, src_s3, src_s4) =
+            crate::shape::dims5(&unsigned_stride(layout)?)?;
+        // Returns the unpadded source index for an output position and a kernel offset, or None
+        // when this falls in the padding.
+        let src_pos = |out_idx: usize, k_idx: usize, size: usize| {
+            let pos = out_idx * stride + k_idx * dilation;
+            if pos < padding || pos >= size + padding {
+                None
+            } else {
+                Some(pos - padding)
+            }
+        };
+        // dst: (b, d_out, h_out, w_out, c, d_k, h_k, w_k)
+        let mut dst_idx = 0;
+        for b_idx in 0..b {
+            for d_idx in 0..d_out {
+                for h_idx in 0..h_out {
+                    for w_idx in 0..w_out {
+                        for c_idx in 0..c {
+                            let src_idx = b_idx * src_s0 + c_idx * src_s1;
+                            for d_k_idx in 0..d_k {
+                                for h_k_idx in 0..h_k {
+                                    for w_k_idx in 0..w_k {
+                                        let pos = (
+                                            src_pos(d_idx, d_k_idx, d),
+                                            src_pos(h_idx, h_k_idx, h),
+                                            src_pos(w_idx, w_k_idx, w),
+                                        );
+                                        if let (Some(s_d), Some(s_h), Some(s_w)) = pos {
+                                            dst[dst_idx] = src[src_idx
+                                                + s_d * src_s2
+                                                + s_h * src_s3
+                                                + s_w * src_s4]
+                                        }
+                                        dst_idx += 1
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(dst)
+    }
+}
+
+struct ConvTranspose3D<'a>(&'a crate::conv::ParamsConvTranspose3D);
+
+impl<'a> Map2 for ConvTranspose3D<'a> {
+    const OP: &'static str = "conv_transpose3d";
+    fn f<T: WithDType>(&self, inp: &[T], inp_l: &Layout, k: &[T], k_l: &Layout) -> Result<Vec<T>> {
+        let p = self.0;
+        let inp = &inp[inp_l.start_offset()..];
+        let (inp_s0, inp_s1, inp_s2, inp_s3, inp_s4) =
+            crate::shape::dims5(&unsigned_stride(inp_l)?)?;
+        let k = &k[k_l.start_offset()..];
+        let (k_s0, k_s1, k_s2, k_s3, k_s4) = crate::shape::dims5(&unsigned_stride(k_l)?)?;
+        let (out_d, out_h, out_w) = (p.out_d(), p.out_h(), p.out_w());
+
+        // Output shape: [b_size, c_out, out_d, out_h, out_w].
+        let dst = vec![T::zero(); p.b_size * p.c_out * out_d * out_h * out_w];
+        let dst_s0 = p.c_out * out_d * out_h * out_w;
+        let dst_s1 = out_d * out_h * out_w;
+        let dst_s2 = out_h * out_w;
+        let dst_s3 = out_w;
+
+        // TODO: Avoid making this copy if `inp` already has the appropriate layout.
+        let mut inp_cont = vec![T::zero(); p.b_size * p.c_in * p.i_d * p.i_h * p.i_w];
+        let cont_s0 = p.i_d * p.i_h * p.i_w * p.c_in;
+        let cont_s1 = p.i_h * p.i_w * p.c_in;
+        let cont_s2 = p.i_w * p.c_in;
+        let cont_s3 = p.c_in;
+        for b_idx in 0..p.b_size {
+            for d_idx in 0..p.i_d {
+                for h_idx in 0..p.i_h {
+                    for w_idx in 0..p.i_w {
+                        for c_idx in 0..p.c_in {
+                            let src_idx = b_idx * inp_s0
+                                + c_idx * inp_s1
+                                + d_idx * inp_s2
+                                + h_idx * inp_s3
+                                + w_idx * inp_s4;
+                            let dst_idx = b_idx * cont_s0
+                                + d_idx * cont_s1
+                                + h_idx * cont_s2
+                                + w_idx * cont_s3
+                                + c_idx;
+                            inp_cont[dst_idx] = inp[src_idx]
+                        }
+                    }
+                }
+            }
+        }
+
+        // Returns the output position for an input position and a kernel offset, or None when
+        // this falls in the padding or past the output size.
+        let out_pos = |inp_idx: usize, k_idx: usize, size: usize| {
+            let pos = inp_idx * p.stride + k_idx * p.dilation;
+            if pos < p.padding || pos - p.padding >= size {
+                None
+            } else {
+                Some(pos - p.padding)
+            }
+        };
+        for k_z in 0..p.k_d {
+            for k_y in 0..p.k_h {
+                for k_x in 0..p.k_w {
+                    (0..p.c_out).into_par_iter().for_each(|dst_c_idx| {
+                        let k_cont = (0..p.c_in)
+                            .map(|c_in_idx| {
+                                k[c_in_idx * k_s0
+                                    + dst_c_idx * k_s1
+                                    + k_z * k_s2
+                                    + k_y * k_s3
+                                    + k_x * k_s4]
+                            })
+                            .collect::<Vec<_>>();
+                        for b_idx in 0..p.b_size {
+                            for inp_z in 0..p.i_d {
+                                let Some(out_z) = out_pos(inp_z, k_z, out_d) else {
+                                    continue;
+                                };
+                                for inp_y in 0..p.i_h {
+                                    let Some(out_y) = out_pos(inp_y, k_y, out_h) else {
+                                        continue;
+                                    };
+                                    for inp_x in 0..p.i_w {
+                                        let Some(out_x) = out_pos(inp_x, k_x, out_w) else {
+                                            continue;
+                                        };
+                                        let inp_cont = &inp_cont[b_idx * cont_s0
+                                            + inp_z * cont_s1
+                                            + inp_y * cont_s2
+                                            + inp_x * cont_s3..];
+                                        let dst_idx = b_idx * dst_s0
+                                            + dst_c_idx * dst_s1
+                                            + out_z * dst_s2
+                                            + out_y * dst_s3
+                                            + out_x;
+                                        let mut d = T::zero();
+                                        unsafe {
+                                            T::vec_dot(
+                                                inp_cont.as_ptr(),
+                                                k_cont.as_ptr(),
+                                                &mut d,
+                                                p.c_in,
+                                            )
+                                        }
+                                        let dst_p = dst.as_ptr();
+                                        // Safety: dst_idx are uniques per dst_c_idx which is used
+                                        // to parallelise the different tasks so no two threads can
+                                        // try to write at the same location.
+                                        unsafe {
+                                            let ptr = dst_p.add(dst_idx) as *mut T;
+                                            *ptr += d
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    })
+                }
+            }
+        }
+        Ok(dst)
+    }
+}
+
 struct MatMul((usize, usize, usize, usize));
 
 impl MatMul {
@@ -1540,8 +2053,8 @@ impl Map2 for MatMul {
         let lhs = &lhs[lhs_l.start_offset()..];
         let rhs = &rhs[rhs_l.start_offset()..];
 
-        let lhs_stride = lhs_l.stride();
-        let rhs_stride = rhs_l.stride();
+        let lhs_stride = unsigned_stride(lhs_l)?;
+        let rhs_stride = unsigned_stride(rhs_l)?;
         let rank = lhs_stride.len();
         let lhs_cs = lhs_stride[rank - 1];
         let lhs_rs = lhs_stride[rank - 2];
@@ -1618,8 +2131,8 @@ impl Map2 for MatMul {
         let lhs = &lhs[lhs_l.start_offset()..];
         let rhs = &rhs[rhs_l.start_offset()..];
 
-        let lhs_stride = lhs_l.stride();
-        let rhs_stride = rhs_l.stride();
+        let lhs_stride = unsigned_stride(lhs_l)?;
+        let rhs_stride = unsigned_stride(rhs_l)?;
         let rank = lhs_stride.len();
 
         let a_skip: usize = match lhs_stride[..rank - 2] {
@@ -1721,8 +2234,8 @@ impl Map2 for MatMul {
         let lhs = &lhs[lhs_l.start_offset()..];
         let rhs = &rhs[rhs_l.start_offset()..];
 
-        let lhs_stride = lhs_l.stride();
-        let rhs_stride = rhs_l.stride();
+        let lhs_stride = unsigned_stride(lhs_l)?;
+        let rhs_stride = unsigned_stride(rhs_l)?;
         let rank = lhs_stride.len();
 
         let a_skip: usize = match lhs_stride[..rank - 2] {
@@ -1865,7 +2378,7 @@ impl CpuStorage {
                     })
                     .collect::<Result<Vec<_>>>()?
                     .concat();
-                Self::U8(storages)
+                Self::U8(storages.into())
             }
             Self::U32(_) => {
                 let storages = storages
@@ -1876,7 +2389,7 @@ impl CpuStorage {
                     })
                     .collect::<Result<Vec<_>>>()?
                     .concat();
-                Self::U32(storages)
+                Self::U32(storages.into())
             }
             Self::I64(_) => {
                 let storages = storages
@@ -1887,7 +2400,7 @@ impl CpuStorage {
                     })
                     .collect::<Result<Vec<_>>>()?
                     .concat();
-                Self::I64(storages)
+                Self::I64(storages.into())
             }
             Self::BF16(_) => {
                 let storages = storages
@@ -1898,7 +2411,7 @@ impl CpuStorage {
                     })
                     .collect::<Result<Vec<_>>>()?
                     .concat();
-                Self::BF16(storages)
+                Self::BF16(storages.into())
             }
             Self::F16(_) => {
                 let storages = storages
@@ -1909,7 +2422,7 @@ impl CpuStorage {
                     })
                     .collect::<Result<Vec<_>>>()?
                     .concat();
-                Self::F16(storages)
+                Self::F16(storages.into())
             }
             Self::F32(_) => {
                 let storages = storages
@@ -1920,7 +2433,7 @@ impl CpuStorage {
                     })
                     .collect::<Result<Vec<_>>>()?
                     .concat();
-                Self::F32(storages)
+                Self::F32(storages.into())
             }
             Self::F64(_) => {
                 let storages = storages
@@ -1931,7 +2444,7 @@ impl CpuStorage {
                     })
                     .collect::<Result<Vec<_>>>()?
                     .concat();
-                Self::F64(storages)
+                Self::F64(storages.into())
             }
         };
         Ok(s)
@@ -2063,200 +2576,200 @@ impl BackendStorage for CpuStorage {
         // TODO: find a way around the quadratic number of cases below.
         match (self, dtype) {
             (Self::U8(storage), DType::BF16) => {
-                let data = unary_map(storage, layout, |v| bf16::from_f32(v as f32));
-                Ok(Self::BF16(data))
+                let data = par_unary_map(storage, layout, |v| bf16::from_f32(v as f32));
+                Ok(Self::BF16(data.into()))
             }
             (Self::U32(storage), DType::BF16) => {
-                let data = unary_map(storage, layout, |v| bf16::from_f32(v as f32));
-                Ok(Self::BF16(data))
+                let data = par_unary_map(storage, layout, |v| bf16::from_f32(v as f32));
+                Ok(Self::BF16(data.into()))
             }
             (Self::I64(storage), DType::BF16) => {
-                let data = unary_map(storage, layout, |v| bf16::from_f32(v as f32));
-                Ok(Self::BF16(data))
+                let data = par_unary_map(storage, layout, |v| bf16::from_f32(v as f32));
+                Ok(Self::BF16(data.into()))
             }
             (Self::BF16(storage), DType::BF16) => {
-                let data = unary_map(storage, layout, |v| v);
-                Ok(Self::BF16(data))
+                let data = par_unary_map(storage, layout, |v| v);
+                Ok(Self::BF16(data.into()))
             }
             (Self::F16(storage), DType::BF16) => {
-                let data = unary_map(storage, layout, |v| bf16::from_f32(v.to_f32()));
-                Ok(Self::BF16(data))
+                let data = par_unary_map(storage, layout, |v| bf16::from_f32(v.to_f32()));
+                Ok(Self::BF16(data.into()))
             }
             (Self::F32(storage), DType::BF16) => {
-                let data = unary_map(storage, layout, bf16::from_f32);
-                Ok(Self::BF16(data))
+                let data = par_unary_map(storage, layout, bf16::from_f32);
+                Ok(Self::BF16(data.into()))
             }
             (Self::F64(storage), DType::BF16) => {
-                let data = unary_map(storage, layout, bf16::from_f64);
-                Ok(Self::BF16(data))
+                let data = par_unary_map(storage, layout, bf16::from_f64);
+                Ok(Self::BF16(data.into()))
             }
             (Self::U8(storage), DType::F16) => {
-                let data = unary_map(storage, layout, |v| f16::from_f32(v as f32));
-                Ok(Self::F16(data))
+                let data = par_unary_map(storage, layout, |v| f16::from_f32(v as f32));
+                Ok(Self::F16(data.into()))
             }
             (Self::U32(storage), DType::F16) => {
-                let data = unary_map(storage, layout, |v| f16::from_f32(v as f32));
-                Ok(Self::F16(data))
+                let data = par_unary_map(storage, layout, |v| f16::from_f32(v as f32));
+                Ok(Self::F16(data.into()))
             }
             (Self::I64(storage), DType::F16) => {
-                let data = unary_map(storage, layout, |v| f16::from_f32(v as f32));
-                Ok(Self::F16(data))
+                let data = par_unary_map(storage, layout, |v| f16::from_f32(v as f32));
+                Ok(Self::F16(data.into()))
             }
             (Self::BF16(storage), DType::F16) => {
-                let data = unary_map(storage, layout, |v| f16::from_f32(v.to_f32()));
-                Ok(Self::F16(data))
+                let data = par_unary_map(storage, layout, |v| f16::from_f32(v.to_f32()));
+                Ok(Self::F16(data.into()))
             }
             (Self::F16(storage), DType::F16) => {
-                let data = unary_map(storage, layout, |v| v);
-                Ok(Self::F16(data))
+                let data = par_unary_map(storage, layout, |v| v);
+                Ok(Self::F16(data.into()))
             }
             (Self::F32(storage), DType::F16) => {
-                let data = unary_map(storage, layout, f16::from_f32);
-                Ok(Self::F16(data))
+                let data = par_unary_map(storage, layout, f16::from_f32);
+                Ok(Self::F16(data.into()))
             }
             (Self::F64(storage), DType::F16) => {
-                let data = unary_map(storage, layout, f16::from_f64);
-                Ok(Self::F16(data))
+                let data = par_unary_map(storage, layout, f16::from_f64);
+                Ok(Self::F16(data.into()))
             }
             (Self::U8(storage), DType::F32) => {
-                let data = unary_map(storage, layout, |v| v as f32);
-                Ok(Self::F32(data))
+                let data = par_unary_map(storage, layout, |v| v as f32);
+                Ok(Self::F32(data.into()))
             }
             (Self::U32(storage), DType::F32) => {
-                let data = unary_map(storage, layout, |v| v as f32);
-                Ok(Self::F32(data))
+                let data = par_unary_map(storage, layout, |v| v as f32);
+                Ok(Self::F32(data.into()))
             }
             (Self::I64(storage), DType::F32) => {
-                let data = unary_map(storage, layout, |v| v as f32);
-                Ok(Self::F32(data))
+                let data = par_unary_map(storage, layout, |v| v as f32);
+                Ok(Self::F32(data.into()))
             }
             (Self::BF16(storage), DType::F32) => {
-                let data = unary_map(storage, layout, |v| v.to_f32());
-                Ok(Self::F32(data))
+                let data = par_unary_map(storage, layout, |v| v.to_f32());
+                Ok(Self::F32(data.into()))
             }
             (Self::F16(storage), DType::F32) => {
-                let data = unary_map(storage, layout, |v| v.to_f32());
-                Ok(Self::F32(data))
+                let data = par_unary_map(storage, layout, |v| v.to_f32());
+                Ok(Self::F32(data.into()))
             }
             (Self::F32(storage), DType::F32) => {
-                let data = unary_map(storage, layout, |v| v);
-                Ok(Self::F32(data))
+                let data = par_unary_map(storage, layout, |v| v);
+                Ok(Self::F32(data.into()))
             }
             (Self::F64(storage), DType::F32) => {
-                let data = unary_map(storage, layout, |v| v as f32);
-                Ok(Self::F32(data))
+                let data = par_unary_map(storage, layout, |v| v as f32);
+                Ok(Self::F32(data.into()))
             }
             (Self::U8(storage), DType::U8) => {
-                let data = unary_map(storage, layout, |v| v);
-                Ok(Self::U8(data))
+                let data = par_unary_map(storage, layout, |v| v);
+                Ok(Self::U8(data.into()))
             }
             (Self::BF16(storage), DType::U8) => {
-                let data = unary_map(storage, layout, |v| v.to_f32() as u8);
-                Ok(Self::U8(data))
+                let data = par_unary_map(storage, layout, |v| v.to_f32() as u8);
+                Ok(Self::U8(data.into()))
             }
             (Self::F16(storage), DType::U8) => {
-                let data = unary_map(storage, layout, |v| v.to_f32() as u8);
-                Ok(Self::U8(data))
+                let data = par_unary_map(storage, layout, |v| v.to_f32() as u8);
+                Ok(Self::U8(data.into()))
             }
             (Self::F32(storage), DType::U8) => {
-                let data = unary_map(storage, layout, |v| v as u8);
-                Ok(Self::U8(data))
+                let data = par_unary_map(storage, layout, |v| v as u8);
+                Ok(Self::U8(data.into()))
             }
             (Self::F64(storage), DType::U8) => {
-                let data = unary_map(storage, layout, |v| v as u8);
-                Ok(Self::U8(data))
+                let data = par_unary_map(storage, layout, |v| v as u8);
+                Ok(Self::U8(data.into()))
             }
             (Self::U32(storage), DType::U8) => {
-                let data = unary_map(storage, layout, |v| v as u8);
-                Ok(Self::U8(data))
+                let data = par_unary_map(storage, layout, |v| v as u8);
+                Ok(Self::U8(data.into()))
             }
             (Self::I64(storage), DType::U8) => {
-                let data = unary_map(storage, layout, |v| v as u8);
-                Ok(Self::U8(data))
+                let data = par_unary_map(storage, layout, |v| v as u8);
+                Ok(Self::U8(data.into()))
             }
             (Self::U8(storage), DType::U32) => {
-                let data = unary_map(storage, layout, |v| v as u32);
-                Ok(Self::U32(data))
+                let data = par_unary_map(storage, layout, |v| v as u32);
+                Ok(Self::U32(data.into()))
             }
             (Self::U32(storage), DType::U32) => {
-                let data = unary_map(storage, layout, |v| v);
-                Ok(Self::U32(data))
+                let data = par_unary_map(storage, layout, |v| v);
+                Ok(Self::U32(data.into()))
             }
             (Self::I64(storage), DType::U32) => {
-                let data = unary_map(storage, layout, |v| v as u32);
-                Ok(Self::U32(data))
+                let data = par_unary_map(storage, layout, |v| v as u32);
+                Ok(Self::U32(data.into()))
             }
             (Self::BF16(storage), DType::U32) => {
-                let data = unary_map(storage, layout, |v| v.to_f32() as u32);
-                Ok(Self::U32(data))
+                let data = par_unary_map(storage, layout, |v| v.to_f32() as u32);
+                Ok(Self::U32(data.into()))
             }
             (Self::F16(storage), DType::U32) => {
-                let data = unary_map(storage, layout, |v| v.to_f32() as u32);
-                Ok(Self::U32(data))
+                let data = par_unary_map(storage, layout, |v| v.to_f32() as u32);
+                Ok(Self::U32(data.into()))
             }
             (Self::F32(storage), DType::U32) => {
-                let data = unary_map(storage, layout, |v| v as u32);
-                Ok(Self::U32(data))
+                let data = par_unary_map(storage, layout, |v| v as u32);
+                Ok(Self::U32(data.into()))
             }
             (Self::F64(storage), DType::U32) => {
-                let data = unary_map(storage, layout, |v| v as u32);
-                Ok(Self::U32(data))
+                let data = par_unary_map(storage, layout, |v| v as u32);
+                Ok(Self::U32(data.into()))
             }
             (Self::U8(storage), DType::I64) => {
-                let data = unary_map(storage, layout, |v| v as i64);
-                Ok(Self::I64(data))
+                let data = par_unary_map(storage, layout, |v| v as i64);
+                Ok(Self::I64(data.into()))
             }
             (Self::U32(storage), DType::I64) => {
-                let data = unary_map(storage, layout, |v| v as i64);
-                Ok(Self::I64(data))
+                let data = par_unary_map(storage, layout, |v| v as i64);
+                Ok(Self::I64(data.into()))
             }
             (Self::I64(storage), DType::I64) => {
-                let data = unary_map(storage, layout, |v| v);
-                Ok(Self::I64(data))
+                let data = par_unary_map(storage, layout, |v| v);
+                Ok(Self::I64(data.into()))
             }
             (Self::BF16(storage), DType::I64) => {
-                let data = unary_map(storage, layout, |v| v.to_f32() as i64);
-                Ok(Self::I64(data))
+                let data = par_unary_map(storage, layout, |v| v.to_f32() as i64);
+                Ok(Self::I64(data.into()))
             }
             (Self::F16(storage), DType::I64) => {
-                let data = unary_map(storage, layout, |v| v.to_f32() as i64);
-                Ok(Self::I64(data))
+                let data = par_unary_map(storage, layout, |v| v.to_f32() as i64);
+                Ok(Self::I64(data.into()))
             }
             (Self::F32(storage), DType::I64) => {
-                let data = unary_map(storage, layout, |v| v as i64);
-                Ok(Self::I64(data))
+                let data = par_unary_map(storage, layout, |v| v as i64);
+                Ok(Self::I64(data.into()))
             }
             (Self::F64(storage), DType::I64) => {
-                let data = unary_map(storage, layout, |v| v as i64);
-                Ok(Self::I64(data))
+                let data = par_unary_map(storage, layout, |v| v as i64);
+                Ok(Self::I64(data.into()))
             }
             (Self::U8(storage), DType::F64) => {
-                let data = unary_map(storage, layout, |v| v as f64);
-                Ok(Self::F64(data))
+                let data = par_unary_map(storage, layout, |v| v as f64);
+                Ok(Self::F64(data.into()))
             }
             (Self::U32(storage), DType::F64) => {
-                let data = unary_map(storage, layout, |v| v as f64);
-                Ok(Self::F64(data))
+                let data = par_unary_map(storage, layout, |v| v as f64);
+                Ok(Self::F64(data.into()))
             }
             (Self::I64(storage), DType::F64) => {
-                let data = unary_map(storage, layout, |v| v as f64);
-                Ok(Self::F64(data))
+                let data = par_unary_map(storage, layout, |v| v as f64);
+                Ok(Self::F64(data.into()))
             }
             (Self::BF16(storage), DType::F64) => {
-                let data = unary_map(storage, layout, |v| v.to_f64());
-                Ok(Self::F64(data))
+                let data = par_unary_map(storage, layout, |v| v.to_f64());
+                Ok(Self::F64(data.into()))
             }
             (Self::F16(storage), DType::F64) => {
-                let data = unary_map(storage, layout, |v| v.to_f64());
-                Ok(Self::F64(data))
+                let data = par_unary_map(storage, layout, |v| v.to_f64());
+                Ok(Self::F64(data.into()))
             }
             (Self::F32(storage), DType::F64) => {
-                let data = unary_map(storage, layout, |v| v as f64);
-                Ok(Self::F64(data))
+                let data = par_unary_map(storage, layout, |v| v as f64);
+                Ok(Self::F64(data.into()))
             }
             (Self::F64(storage), DType::F64) => {
-                let data = unary_map(storage, layout, |v| v);
-                Ok(Self::F64(data))
+                let data = par_unary_map(storage, layout, |v| v);
+                Ok(Self::F64(data.into()))
             }
         }
     }
@@ -2343,6 +2856,24 @@ impl BackendStorage for CpuStorage {
         MaxPool2D(kernel_size, stride).map(self, layout)
     }
 
+    fn avg_pool3d(
+        &self,
+        layout: &Layout,
+        kernel_size: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    ) -> Result<Self> {
+        AvgPool3D(kernel_size, stride).map(self, layout)
+    }
+
+    fn max_pool3d(
+        &self,
+        layout: &Layout,
+        kernel_size: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    ) -> Result<Self> {
+        MaxPool3D(kernel_size, stride).map(self, layout)
+    }
+
     fn upsample_nearest1d(&self, layout: &Layout, sz: usize) -> Result<Self> {
         UpsampleNearest1D(sz).map(self, layout)
     }
@@ -2356,20 +2887,20 @@ impl BackendStorage for CpuStorage {
         // TODO: Have some generic map for functions that apply on num_traits::Float elements.
         match self {
             Self::BF16(storage) => {
-                let data = unary_map(storage, layout, |v| v.powf(bf16::from_f64(e)));
-                Ok(Self::BF16(data))
+                let data = par_unary_map(storage, layout, |v| v.powf(bf16::from_f64(e)));
+                Ok(Self::BF16(data.into()))
             }
             Self::F16(storage) => {
-                let data = unary_map(storage, layout, |v| v.powf(f16::from_f64(e)));
-                Ok(Self::F16(data))
+                let data = par_unary_map(storage, layout, |v| v.powf(f16::from_f64(e)));
+                Ok(Self::F16(data.into()))
             }
             Self::F32(storage) => {
-                let data = unary_map(storage, layout, |v| v.powf(e as f32));
-                Ok(Self::F32(data))
+                let data = par_unary_map(storage, layout, |v| v.powf(e as f32));
+                Ok(Self::F32(data.into()))
             }
             Self::F64(storage) => {
-                let data = unary_map(storage, layout, |v| v.powf(e));
-                Ok(Self::F64(data))
+                let data = par_unary_map(storage, layout, |v| v.powf(e));
+                Ok(Self::F64(data.into()))
             }
             Self::U8(_) => Err(Error::UnsupportedDTypeForOp(DType::U8, "elu").bt()),
             Self::U32(_) => Err(Error::UnsupportedDTypeForOp(DType::U32, "elu").bt()),
@@ -2381,20 +2912,20 @@ impl BackendStorage for CpuStorage {
         // TODO: Have some generic map for functions that apply on num_traits::Float elements.
         match self {
             Self::BF16(storage) => {
-                let data = unary_map(storage, layout, |v| elu(v, bf16::from_f64(alpha)));
-                Ok(Self::BF16(data))
+                let data = par_unary_map(storage, layout, |v| elu(v, bf16::from_f64(alpha)));
+                Ok(Self::BF16(data.into()))
             }
             Self::F16(storage) => {
-                let data = unary_map(storage, layout, |v| elu(v, f16::from_f64(alpha)));
-                Ok(Self::F16(data))
+                let data = par_unary_map(storage, layout, |v| elu(v, f16::from_f64(alpha)));
+                Ok(Self::F16(data.into()))
             }
             Self::F32(storage) => {
-                let data = unary_map(storage, layout, |v| elu(v, f32::from_f64(alpha)));
-                Ok(Self::F32(data))
+                let data = par_unary_map(storage, layout, |v| elu(v, f32::from_f64(alpha)));
+                Ok(Self::F32(data.into()))
             }
             Self::F64(storage) => {
-                let data = unary_map(storage, layout, |v| elu(v, alpha));
-                Ok(Self::F64(data))
+                let data = par_unary_map(storage, layout, |v| elu(v, alpha));
+                Ok(Self::F64(data.into()))
             }
             Self::U8(_) => Err(Error::UnsupportedDTypeForOp(DType::U8, "elu").bt()),
             Self::U32(_) => Err(Error::UnsupportedDTypeForOp(DType::U32, "elu").bt()),
@@ -2406,51 +2937,51 @@ impl BackendStorage for CpuStorage {
         match self {
             Self::BF16(storage) => {
                 if B::BF16_VEC {
-                    let data = unary_map_vec(storage, layout, B::bf16, B::bf16_vec);
-                    Ok(Self::BF16(data))
+                    let data = par_unary_map_vec(storage, layout, B::bf16, B::bf16_vec);
+                    Ok(Self::BF16(data.into()))
                 } else {
-                    let data = unary_map(storage, layout, B::bf16);
-                    Ok(Self::BF16(data))
+                    let data = par_unary_map(storage, layout, B::bf16);
+                    Ok(Self::BF16(data.into()))
                 }
             }
             Self::F16(storage) => {
                 if B::F16_VEC {
-                    let data = unary_map_vec(storage, layout, B::f16, B::f16_vec);
-                    Ok(Self::F16(data))
+                    let data = par_unary_map_vec(storage, layout, B::f16, B::f16_vec);
+                    Ok(Self::F16(data.into()))
                 } else {
-                    let data = unary_map(storage, layout, B::f16);
-                    Ok(Self::F16(data))
+                    let data = par_unary_map(storage, layout, B::f16);
+                    Ok(Self::F16(data.into()))
                 }
             }
             Self::F32(storage) => {
                 if B::F32_VEC {
-                    let data = unary_map_vec(storage, layout, B::f32, B::f32_vec);
-                    Ok(Self::F32(data))
+                    let data = par_unary_map_vec(storage, layout, B::f32, B::f32_vec);
+                    Ok(Self::F32(data.into()))
                 } else {
-                    let data = unary_map(storage, layout, B::f32);
-                    Ok(Self::F32(data))
+                    let data = par_unary_map(storage, layout, B::f32);
+                    Ok(Self::F32(data.into()))
                 }
             }
             Self::F64(storage) => {
                 if B::F64_VEC {
-                    let data = unary_map_vec(storage, layout, B::f64, B::f64_vec);
-                    Ok(Self::F64(data))
+                    let data = par_unary_map_vec(storage, layout, B::f64, B::f64_vec);
+                    Ok(Self::F64(data.into()))
                 } else {
-                    let data = unary_map(storage, layout, B::f64);
-                    Ok(Self::F64(data))
+                    let data = par_unary_map(storage, layout, B::f64);
+                    Ok(Self::F64(data.into()))
                 }
             }
             Self::U8(storage) => {
-                let data = unary_map(storage, layout, B::u8);
-                Ok(Self::U8(data))
+                let data = par_unary_map(storage, layout, B::u8);
+                Ok(Self::U8(data.into()))
             }
             Self::U32(storage) => {
-                let data = unary_map(storage, layout, B::u32);
-                Ok(Self::U32(data))
+                let data = par_unary_map(storage, layout, B::u32);
+                Ok(Self::U32(data.into()))
             }
             Self::I64(storage) => {
-                let data = unary_map(storage, layout, B::i64);
-                Ok(Self::I64(data))
+                let data = par_unary_map(storage, layout, B::i64);
+                Ok(Self::I64(data.into()))
             }
         }
     }
@@ -2464,59 +2995,59 @@ impl BackendStorage for CpuStorage {
         match (self, rhs) {
             (Self::BF16(lhs), Self::BF16(rhs)) => {
                 let data = if B::BF16_VEC {
-                    binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::bf16, B::bf16_vec)
+                    par_binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::bf16, B::bf16_vec)
                 } else {
-                    binary_map(lhs_l, rhs_l, lhs, rhs, B::bf16)
+                    par_binary_map(lhs_l, rhs_l, lhs, rhs, B::bf16)
                 };
-                Ok(Self::BF16(data))
+                Ok(Self::BF16(data.into()))
             }
             (Self::F16(lhs), Self::F16(rhs)) => {
                 let data = if B::F16_VEC {
-                    binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::f16, B::f16_vec)
+                    par_binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::f16, B::f16_vec)
                 } else {
-                    binary_map(lhs_l, rhs_l, lhs, rhs, B::f16)
+                    par_binary_map(lhs_l, rhs_l, lhs, rhs, B::f16)
                 };
-                Ok(Self::F16(data))
+                Ok(Self::F16(data.into()))
             }
             (Self::F32(lhs), Self::F32(rhs)) => {
                 let data = if B::F32_VEC {
-                    binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::f32, B::f32_vec)
+                    par_binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::f32, B::f32_vec)
                 } else {
-                    binary_map(lhs_l, rhs_l, lhs, rhs, B::f32)
+                    par_binary_map(lhs_l, rhs_l, lhs, rhs, B::f32)
                 };
-                Ok(Self::F32(data))
+                Ok(Self::F32(data.into()))
             }
             (Self::F64(lhs), Self::F64(rhs)) => {
                 let data = if B::F64_VEC {
-                    binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::f64, B::f64_vec)
+                    par_binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::f64, B::f64_vec)
                 } else {
-                    binary_map(lhs_l, rhs_l, lhs, rhs, B::f64)
+                    par_binary_map(lhs_l, rhs_l, lhs, rhs, B::f64)
                 };
-                Ok(Self::F64(data))
+                Ok(Self::F64(data.into()))
             }
             (Self::U32(lhs), Self::U32(rhs)) => {
                 let data = if B::U32_VEC {
-                    binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::u32, B::u32_vec)
+                    par_binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::u32, B::u32_vec)
                 } else {
-                    binary_map(lhs_l, rhs_l, lhs, rhs, B::u32)
+                    par_binary_map(lhs_l, rhs_l, lhs, rhs, B::u32)
                 };
-                Ok(Self::U32(data))
+                Ok(Self::U32(data.into()))
             }
             (Self::I64(lhs), Self::I64(rhs)) => {
                 let data = if B::I64_VEC {
-                    binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::i64, B::i64_vec)
+                    par_binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::i64, B::i64_vec)
                 } else {
-                    binary_map(lhs_l, rhs_l, lhs, rhs, B::i64)
+                    par_binary_map(lhs_l, rhs_l, lhs, rhs, B::i64)
                 };
-                Ok(Self::I64(data))
+                Ok(Self::I64(data.into()))
             }
             (Self::U8(lhs), Self::U8(rhs)) => {
                 let data = if B::U8_VEC {
-                    binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::u8, B::u8_vec)
+                    par_binary_map_vec(lhs_l, rhs_l, lhs, rhs, B::u8, B::u8_vec)
                 } else {
-                    binary_map(lhs_l, rhs_l, lhs, rhs, B::u8)
+                    par_binary_map(lhs_l, rhs_l, lhs, rhs, B::u8)
                 };
-                Ok(Self::U8(data))
+                Ok(Self::U8(data.into()))
             }
             _ => {
                 // This should be covered by the dtype check above.
@@ -2600,10 +3131,10 @@ impl BackendStorage for CpuStorage {
             // Make the kernel contiguous if not already the case.
             let mut kernel_c = self.device().zeros_impl(kernel_l.shape(), kernel.dtype())?;
             kernel.copy_strided_src(&mut kernel_c, 0, kernel_l)?;
-            let kernel_l = Layout::contiguous_with_offset((1, n, k), kernel_l.start_offset())
+            let kernel_l = Layout::contiguous((1, n, k))
                 .transpose(1, 2)?
                 .broadcast_as((b, k, n))?;
-            col.matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
+            col.matmul(&kernel_c, (b, m, n, k), &col_l, &kernel_l)?
         };
         let res_l = Layout::contiguous((b, l_out, params.c_out)).transpose(1, 2)?;
         let mut res_t = self.device().zeros_impl(res_l.shape(), res.dtype())?;
@@ -2654,10 +3185,10 @@ impl BackendStorage for CpuStorage {
             // Make the kernel contiguous if not already the case.
             let mut kernel_c = self.device().zeros_impl(kernel_l.shape(), kernel.dtype())?;
             kernel.copy_strided_src(&mut kernel_c, 0, kernel_l)?;
-            let kernel_l = Layout::contiguous_with_offset((1, n, k), kernel_l.start_offset())
+            let kernel_l = Layout::contiguous((1, n, k))
                 .transpose(1, 2)?
                 .broadcast_as((b, k, n))?;
-            col.matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
+            col.matmul(&kernel_c, (b, m, n, k), &col_l, &kernel_l)?
         };
         let res_l = Layout::contiguous((b, h_out, w_out, params.c_out))
             .transpose(1, 2)?
@@ -2677,8 +3208,62 @@ impl BackendStorage for CpuStorage {
         ConvTranspose2D(params).map(self, l, kernel, kernel_l)
     }
 
+    fn conv3d(
+        &self,
+        l: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &crate::conv::ParamsConv3D,
+    ) -> Result<Self> {
+        let op = Im2Col3D {
+            d_k: params.k_d,
+            h_k: params.k_h,
+            w_k: params.k_w,
+            padding: params.padding,
+            stride: params.stride,
+            dilation: params.dilation,
+        };
+        let col = op.map(self, l)?;
+        let b = params.b_size;
+        let n = params.c_out;
+        let (d_out, h_out, w_out) = (params.out_d(), params.out_h(), params.out_w());
+        let k = op.d_k * op.h_k * op.w_k * params.c_in;
+        let m = d_out * h_out * w_out;
+        let col_l = Layout::contiguous((b, m, k));
+        let res = if kernel_l.is_contiguous() {
+            let kernel_l = Layout::contiguous_with_offset((1, n, k), kernel_l.start_offset())
+                .transpose(1, 2)?
+                .broadcast_as((b, k, n))?;
+            col.matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
+        } else {
+            // Make the kernel contiguous if not already the case.
+            let mut kernel_c = self.device().zeros_impl(kernel_l.shape(), kernel.dtype())?;
+            kernel.copy_strided_src(&mut kernel_c, 0, kernel_l)?;
+            let kernel_l = Layout::contiguous((1, n, k))
+                .transpose(1, 2)?
+                .broadcast_as((b, k, n))?;
+            col.matmul(&kernel_c, (b, m, n, k), &col_l, &kernel_l)?
+        };
+        let res_l = Layout::contiguous((b, d_out, h_out, w_out, n)).permute(&[0, 4, 1, 2, 3])?;
+        let mut res_t = self.device().zeros_impl(res_l.shape(), res.dtype())?;
+        res.copy_strided_src(&mut res_t, 0, &res_l)?;
+        Ok(res_t)
+    }
+
+    fn conv_transpose3d(
+        &self,
+        l: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &crate::conv::ParamsConvTranspose3D,
+    ) -> Result<Self> {
+        ConvTranspose3D(params).map(self, l, kernel, kernel_l)
+    }
+
     fn index_select(&self, ids: &Self, l: &Layout, ids_l: &Layout, dim: usize) -> Result<Self> {
-        match ids {
+        let (ids, ids_l) = non_negative_strides(ids, ids_l)?;
+        let ids_l = ids_l.as_ref();
+        match ids.as_ref() {
             Self::U8(ids) => IndexSelect { ids, ids_l, dim }.map(self, l),
             Self::U32(ids) => IndexSelect { ids, ids_l, dim }.map(self, l),
             Self::I64(ids) => IndexSelect { ids, ids_l, dim }.map(self, l),
@@ -2773,48 +3358,34 @@ impl BackendStorage for CpuStorage {
         let output_size = new_shape.dims().iter().product::<usize>();
 
         match self {
-            CpuStorage::U8(inp) => Ok(Self::U8(self.do_repeat(
-                inp,
-                vec![0u8; output_size],
-                layout,
-                shape,
-            )?)),
-            CpuStorage::U32(inp) => Ok(Self::U32(self.do_repeat(
-                inp,
-                vec![0u32; output_size],
-                layout,
-                shape,
-            )?)),
-            CpuStorage::I64(inp) => Ok(Self::I64(self.do_repeat(
-                inp,
-                vec![0i64; output_size],
-                layout,
-                shape,
-            )?)),
-            CpuStorage::BF16(inp) => Ok(Self::BF16(self.do_repeat(
-                inp,
-                vec![bf16::from(0u8); output_size],
-                layout,
-                shape,
-            )?)),
-            CpuStorage::F16(inp) => Ok(Self::F16(self.do_repeat(
-                inp,
-                vec![f16::from(0u8); output_size],
-                layout,
-                shape,
-            )?)),
-            CpuStorage::F32(inp) => Ok(Self::F32(self.do_repeat(
-                inp,
-                vec![0f32; output_size],
-                layout,
-                shape,
-            )?)),
-            CpuStorage::F64(inp) => Ok(Self::F64(self.do_repeat(
-                inp,
-                vec![0f64; output_size],
-                layout,
-                shape,
-            )?)),
+            CpuStorage::U8(inp) => Ok(Self::U8(
+                self.do_repeat(inp, vec![0u8; output_size], layout, shape)?
+                    .into(),
+            )),
+            CpuStorage::U32(inp) => Ok(Self::U32(
+                self.do_repeat(inp, vec![0u32; output_size], layout, shape)?
+                    .into(),
+            )),
+            CpuStorage::I64(inp) => Ok(Self::I64(
+                self.do_repeat(inp, vec![0i64; output_size], layout, shape)?
+                    .into(),
+            )),
+            CpuStorage::BF16(inp) => Ok(Self::BF16(
+                self.do_repeat(inp, vec![bf16::from(0u8); output_size], layout, shape)?
+                    .into(),
+            )),
+            CpuStorage::F16(inp) => Ok(Self::F16(
+                self.do_repeat(inp, vec![f16::from(0u8); output_size], layout, shape)?
+                    .into(),
+            )),
+            CpuStorage::F32(inp) => Ok(Self::F32(
+                self.do_repeat(inp, vec![0f32; output_size], layout, shape)?
+                    .into(),
+            )),
+            CpuStorage::F64(inp) => Ok(Self::F64(
+                self.do_repeat(inp, vec![0f64; output_size], layout, shape)?
+                    .into(),
+            )),
         }
     }
 }
@@ -2858,7 +3429,7 @@ impl BackendDevice for CpuDevice {
                 for _i in 0..elem_count {
                     data.push(rng.sample::<bf16, _>(uniform))
                 }
-                Ok(CpuStorage::BF16(data))
+                Ok(CpuStorage::BF16(data.into()))
             }
             DType::F16 => {
                 let mut data = Vec::with_capacity(elem_count);
@@ -2867,7 +3438,7 @@ impl BackendDevice for CpuDevice {
                 for _i in 0..elem_count {
                     data.push(rng.sample::<f16, _>(uniform))
                 }
-                Ok(CpuStorage::F16(data))
+                Ok(CpuStorage::F16(data.into()))
             }
             DType::F32 => {
                 let mut data = Vec::with_capacity(elem_count);
@@ -2875,7 +3446,7 @@ impl BackendDevice for CpuDevice {
                 for _i in 0..elem_count {
                     data.push(rng.sample::<f32, _>(uniform))
                 }
-                Ok(CpuStorage::F32(data))
+                Ok(CpuStorage::F32(data.into()))
             }
             DType::F64 => {
                 let mut data = Vec::with_capacity(elem_count);
@@ -2883,7 +3454,7 @@ impl BackendDevice for CpuDevice {
                 for _i in 0..elem_count {
                     data.push(rng.sample::<f64, _>(uniform))
                 }
-                Ok(CpuStorage::F64(data))
+                Ok(CpuStorage::F64(data.into()))
             }
         }
     }
@@ -2904,7 +3475,7 @@ impl BackendDevice for CpuDevice {
                 for _i in 0..elem_count {
                     data.push(normal.sample(&mut rng))
                 }
-                Ok(CpuStorage::BF16(data))
+                Ok(CpuStorage::BF16(data.into()))
             }
             DType::F16 => {
                 let mut data = Vec::with_capacity(elem_count);
@@ -2913,7 +3484,7 @@ impl BackendDevice for CpuDevice {
                 for _i in 0..elem_count {
                     data.push(normal.sample(&mut rng))
                 }
-                Ok(CpuStorage::F16(data))
+                Ok(CpuStorage::F16(data.into()))
             }
             DType::F32 => {
                 let mut data = Vec::with_capacity(elem_count);
@@ -2922,7 +3493,7 @@ impl BackendDevice for CpuDevice {
                 for _i in 0..elem_count {
                     data.push(normal.sample(&mut rng))
                 }
-                Ok(CpuStorage::F32(data))
+                Ok(CpuStorage::F32(data.into()))
             }
             DType::F64 => {
                 let mut data = Vec::with_capacity(elem_count);
@@ -2930,7 +3501,7 @@ impl BackendDevice for CpuDevice {
                 for _i in 0..elem_count {
                     data.push(normal.sample(&mut rng))
                 }
-                Ok(CpuStorage::F64(data))
+                Ok(CpuStorage::F64(data.into()))
             }
         }
     }
@@ -2938,13 +3509,13 @@ impl BackendDevice for CpuDevice {
     fn ones_impl(&self, shape: &Shape, dtype: DType) -> Result<CpuStorage> {
         let elem_count = shape.elem_count();
         let storage = match dtype {
-            DType::U8 => CpuStorage::U8(vec![1u8; elem_count]),
-            DType::U32 => CpuStorage::U32(vec![1u32; elem_count]),
-            DType::I64 => CpuStorage::I64(vec![1i64; elem_count]),
-            DType::BF16 => CpuStorage::BF16(vec![bf16::ONE; elem_count]),
-            DType::F16 => CpuStorage::F16(vec![f16::ONE; elem_count]),
-            DType::F32 => CpuStorage::F32(vec![1f32; elem_count]),
-            DType::F64 => CpuStorage::F64(vec![1f64; elem_count]),
+            DType::U8 => CpuStorage::U8(vec![1u8; elem_count].into()),
+            DType::U32 => CpuStorage::U32(vec![1u32; elem_count].into()),
+            DType::I64 => CpuStorage::I64(vec![1i64; elem_count].into()),
+            DType::BF16 => CpuStorage::BF16(vec![bf16::ONE; elem_count].into()),
+            DType::F16 => CpuStorage::F16(vec![f16::ONE; elem_count].into()),
+            DType::F32 => CpuStorage::F32(vec![1f32; elem_count].into()),
+            DType::F64 => CpuStorage::F64(vec![1f64; elem_count].into()),
         };
         Ok(storage)
     }
@@ -2952,13 +3523,13 @@ impl BackendDevice for CpuDevice {
     fn zeros_impl(&self, shape: &Shape, dtype: DType) -> Result<CpuStorage> {
         let elem_count = shape.elem_count();
         let storage = match dtype {
-            DType::U8 => CpuStorage::U8(vec![0u8; elem_count]),
-            DType::U32 => CpuStorage::U32(vec![0u32; elem_count]),
-            DType::I64 => CpuStorage::I64(vec![0i64; elem_count]),
-            DType::BF16 => CpuStorage::BF16(vec![bf16::ZERO; elem_count]),
-            DType::F16 => CpuStorage::F16(vec![f16::ZERO; elem_count]),
-            DType::F32 => CpuStorage::F32(vec![0f32; elem_count]),
-            DType::F64 => CpuStorage::F64(vec![0f64; elem_count]),
+            DType::U8 => CpuStorage::U8(vec![0u8; elem_count].into()),
+            DType::U32 => CpuStorage::U32(vec![0u32; elem_count].into()),
+            DType::I64 => CpuStorage::I64(vec![0i64; elem_count].into()),
+            DType::BF16 => CpuStorage::BF16(vec![bf16::ZERO; elem_count].into()),
+            DType::F16 => CpuStorage::F16(vec![f16::ZERO; elem_count].into()),
+            DType::F32 => CpuStorage::F32(vec![0f32; elem_count].into()),
+            DType::F64 => CpuStorage::F64(vec![0f64; elem_count].into()),
         };
         Ok(storage)
     }
@@ -2968,7 +3539,7 @@ impl BackendDevice for CpuDevice {
 macro_rules! map_dtype {
     ($name:expr, $storage:ident, $fn:expr, ($($dtypes:ident),+)) => {
         match $storage {
-            $(CpuStorage::$dtypes(__e) => CpuStorage::$dtypes($fn(__e)),)*
+            $(CpuStorage::$dtypes(__e) => CpuStorage::$dtypes($fn(__e).into()),)*
             s => Err(Error::UnsupportedDTypeForOp(s.dtype(), $name).bt())?,
         }
     };
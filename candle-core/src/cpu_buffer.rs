@@ -0,0 +1,103 @@
+//! The buffers holding the elements of cpu tensors.
+use std::sync::Arc;
+
+#[derive(Clone)]
+enum Data<T> {
+    Owned(Vec<T>),
+    Mapped {
+        mmap: Arc<memmap2::Mmap>,
+        offset: usize,
+        len: usize,
+    },
+}
+
+/// The elements of a cpu storage.
+///
+/// The elements are either stored in an owned vector or read directly from a memory mapped
+/// file, the latter avoids copying the weights of a model when loading them. Memory mapped
+/// buffers are read-only and get copied to an owned vector the first time they are mutated.
+#[derive(Clone)]
+pub struct CpuBuffer<T>(Data<T>);
+
+impl<T> CpuBuffer<T> {
+    /// Creates a buffer made of the `len` elements starting at byte `offset` in `mmap`. This
+    /// returns `None` if this range is out of bounds or not properly aligned for `T`.
+    ///
+    /// # Safety
+    ///
+    /// The bytes in the range must be valid values of `T`, and the underlying file must not be
+    /// modified while the buffer is in use, see [`memmap2::Mmap`].
+    pub unsafe fn from_mmap(mmap: Arc<memmap2::Mmap>, offset: usize, len: usize) -> Option<Self> {
+        let end = len
+            .checked_mul(std::mem::size_of::<T>())
+            .and_then(|size| size.checked_add(offset))?;
+        let addr = (mmap.as_ptr() as usize).checked_add(offset)?;
+        if end > mmap.len() || addr % std::mem::align_of::<T>() != 0 {
+            return None;
+        }
+        Some(Self(Data::Mapped { mmap, offset, len }))
+    }
+
+    /// Returns true if the elements are read from a memory mapped file rather than being owned.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.0, Data::Mapped { .. })
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        match &self.0 {
+            Data::Owned(vs) => vs.as_slice(),
+            Data::Mapped { mmap, offset, len } => {
+                // SAFETY: the bounds, the alignment, and the validity of the values have been
+                // checked or guaranteed by the caller when creating the buffer.
+                unsafe { std::slice::from_raw_parts(mmap.as_ptr().add(*offset) as *const T, *len) }
+            }
+        }
+    }
+}
+
+impl<T: Clone> CpuBuffer<T> {
+    /// Returns a mutable reference to the owned elements, memory mapped elements are copied
+    /// first.
+    pub fn to_mut(&mut self) -> &mut Vec<T> {
+        if let Data::Mapped { .. } = self.0 {
+            self.0 = Data::Owned(self.as_slice().to_vec())
+        }
+        match &mut self.0 {
+            Data::Owned(vs) => vs,
+            Data::Mapped { .. } => unreachable!(),
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        match self.0 {
+            Data::Owned(vs) => vs,
+            Data::Mapped { .. } => self.as_slice().to_vec(),
+        }
+    }
+}
+
+impl<T> std::ops::Deref for CpuBuffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T: Clone> std::ops::DerefMut for CpuBuffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.to_mut()
+    }
+}
+
+impl<T> From<Vec<T>> for CpuBuffer<T> {
+    fn from(vs: Vec<T>) -> Self {
+        Self(Data::Owned(vs))
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for CpuBuffer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
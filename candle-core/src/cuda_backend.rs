@@ -9,7 +9,8 @@ use cudarc::driver::{
     ValidAsZeroBits,
 };
 use half::{bf16, f16};
-use std::sync::{Arc, Mutex};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, OnceLock};
 
 /// cudarc related errors
 #[derive(thiserror::Error, Debug)]
@@ -206,6 +207,49 @@ impl CudaDevice {
             })
             .w()
     }
+
+    /// Same as `get_or_load_func` but for kernels generated at runtime, `src` is compiled with
+    /// nvrtc the first time that `module_name` is requested. The kernel function must be named
+    /// `module_name`.
+    ///
+    /// cudarc requires `'static` function names so the name is leaked, this happens at most once
+    /// per distinct `module_name` over the lifetime of the process, whatever the number of
+    /// devices. The leaked memory is bounded by the number of distinct generated kernels, which
+    /// stay loaded in the device module cache anyway.
+    pub fn get_or_compile_func(&self, module_name: &str, src: &str) -> Result<CudaFunction> {
+        if !self.has_func(module_name, module_name) {
+            let ptx = cudarc::nvrtc::compile_ptx(src).w()?;
+            let static_module_name = intern_kernel_name(module_name);
+            self.load_ptx(ptx, module_name, &[static_module_name])
+                .map_err(|cuda| CudaError::Load {
+                    cuda,
+                    module_name: module_name.to_string(),
+                })
+                .w()?;
+        }
+        self.get_func(module_name, module_name)
+            .ok_or(CudaError::MissingKernel {
+                module_name: module_name.to_string(),
+            })
+            .w()
+    }
+}
+
+// Returns a `'static` copy of `name`, each distinct name is only leaked once.
+fn intern_kernel_name(name: &str) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let mut names = NAMES
+        .get_or_init(|| Mutex::new(HashSet::new()))
+        .lock()
+        .unwrap();
+    match names.get(name) {
+        Some(name) => name,
+        None => {
+            let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+            names.insert(name);
+            name
+        }
+    }
 }
 
 impl BackendDevice for CudaDevice {
@@ -564,7 +608,9 @@ impl Map1 for Affine {
         let dims = shape.dims();
         let el = shape.elem_count();
         let cfg = LaunchConfig::for_num_elems(el as u32);
-        let ds = dev.htod_copy([dims, layout.stride()].concat()).w()?;
+        let ds = dev
+            .htod_copy([dims, &layout.wrapping_stride()].concat())
+            .w()?;
         let src = &src.slice(layout.start_offset()..);
         let func = dev.get_or_load_func(&kernel_name::<T>("affine"), kernels::AFFINE)?;
         // SAFETY: Set later by running the kernel.
@@ -596,7 +642,9 @@ impl Map1 for Elu {
         let dims = shape.dims();
         let el = shape.elem_count();
         let cfg = LaunchConfig::for_num_elems(el as u32);
-        let ds = dev.htod_copy([dims, layout.stride()].concat()).w()?;
+        let ds = dev
+            .htod_copy([dims, &layout.wrapping_stride()].concat())
+            .w()?;
         let src = &src.slice(layout.start_offset()..);
         let func = dev.get_or_load_func(&kernel_name::<T>("uelu"), kernels::UNARY)?;
         // SAFETY: Set later by running the kernel.
@@ -633,7 +681,9 @@ impl Map1 for Im2Col1D {
         let l_out = self.l_out(dims[2]);
         let dst_el = dims[0] * l_out * dims[1] * self.l_k;
         let cfg = LaunchConfig::for_num_elems(dst_el as u32);
-        let ds = dev.htod_copy([dims, layout.stride()].concat()).w()?;
+        let ds = dev
+            .htod_copy([dims, &layout.wrapping_stride()].concat())
+            .w()?;
         let src = &src.slice(layout.start_offset()..);
         let func = dev.get_or_load_func(&kernel_name::<T>("im2col1d"), kernels::CONV)?;
         // SAFETY: Set later by running the kernel.
@@ -683,7 +733,9 @@ impl Map1 for Im2Col {
         let (h_out, w_out) = self.hw_out(dims[2], dims[3]);
         let dst_el = dims[0] * h_out * w_out * dims[1] * self.h_k * self.w_k;
         let cfg = LaunchConfig::for_num_elems(dst_el as u32);
-        let ds = dev.htod_copy([dims, layout.stride()].concat()).w()?;
+        let ds = dev
+            .htod_copy([dims, &layout.wrapping_stride()].concat())
+            .w()?;
         let src = &src.slice(layout.start_offset()..);
         let func = dev.get_or_load_func(&kernel_name::<T>("im2col"), kernels::CONV)?;
         // SAFETY: Set later by running the kernel.
@@ -707,6 +759,68 @@ impl Map1 for Im2Col {
     }
 }
 
+struct Im2Col3D {
+    d_k: usize,
+    h_k: usize,
+    w_k: usize,
+    stride: usize,
+    dilation: usize,
+    padding: usize,
+}
+
+impl Im2Col3D {
+    fn dhw_out(&self, d: usize, h: usize, w: usize) -> (usize, usize, usize) {
+        let out = |i: usize, k: usize| {
+            (i + 2 * self.padding - self.dilation * (k - 1) - 1) / self.stride + 1
+        };
+        (out(d, self.d_k), out(h, self.h_k), out(w, self.w_k))
+    }
+}
+
+impl Map1 for Im2Col3D {
+    fn f<T: DeviceRepr + WithDType>(
+        &self,
+        src: &CudaSlice<T>,
+        dev: &CudaDevice,
+        layout: &Layout,
+    ) -> Result<CudaSlice<T>> {
+        let shape = layout.shape();
+        let dims = shape.dims();
+        let (d_out, h_out, w_out) = self.dhw_out(dims[2], dims[3], dims[4]);
+        let dst_el = dims[0] * d_out * h_out * w_out * dims[1] * self.d_k * self.h_k * self.w_k;
+        let cfg = LaunchConfig::for_num_elems(dst_el as u32);
+        let ds = dev
+            .htod_copy(
+                [
+                    dims,
+                    &layout.wrapping_stride(),
+                    &[self.d_k, self.h_k, self.w_k],
+                ]
+                .concat(),
+            )
+            .w()?;
+        let src = &src.slice(layout.start_offset()..);
+        let func = dev.get_or_load_func(&kernel_name::<T>("im2col3d"), kernels::CONV)?;
+        // SAFETY: Set later by running the kernel.
+        let dst = unsafe { dev.alloc::<T>(dst_el) }.w()?;
+        let params = (
+            dst_el,
+            d_out,
+            h_out,
+            w_out,
+            self.stride,
+            self.padding,
+            self.dilation,
+            &ds,
+            src,
+            &dst,
+        );
+        // SAFETY: ffi.
+        unsafe { func.launch(cfg, params) }.w()?;
+        Ok(dst)
+    }
+}
+
 struct Powf(f64);
 impl Map1 for Powf {
     fn f<T: DeviceRepr + WithDType>(
@@ -719,7 +833,9 @@ impl Map1 for Powf {
         let dims = shape.dims();
         let el = shape.elem_count();
         let cfg = LaunchConfig::for_num_elems(el as u32);
-        let ds = dev.htod_copy([dims, layout.stride()].concat()).w()?;
+        let ds = dev
+            .htod_copy([dims, &layout.wrapping_stride()].concat())
+            .w()?;
         let src = &src.slice(layout.start_offset()..);
         let func = dev.get_or_load_func(&kernel_name::<T>("upowf"), kernels::UNARY)?;
         // SAFETY: Set later by running the kernel.
@@ -757,7 +873,15 @@ impl<'a> Map1 for Sum<'a> {
             .collect();
         let cfg = LaunchConfig::for_num_elems(el as u32);
         let ds = dev
-            .htod_copy([src_dims, layout.stride(), &sum_dims_l, &sum_dims_s].concat())
+            .htod_copy(
+                [
+                    src_dims,
+                    &layout.wrapping_stride(),
+                    &sum_dims_l,
+                    &sum_dims_s,
+                ]
+                .concat(),
+            )
             .w()?;
         let src = &src.slice(layout.start_offset()..);
         let func = dev.get_or_load_func(&kernel_name::<T>("sum"), kernels::REDUCE)?;
@@ -778,7 +902,7 @@ impl<'a> Map1Any for FastReduce<'a> {
         layout: &Layout,
         wrap: W,
     ) -> Result<S> {
-        let src_stride = layout.stride();
+        let src_stride = layout.wrapping_stride();
         let src_dims = layout.shape().dims();
         let src_el: usize = src_dims.iter().product();
         // Source dims and strides with the sum dims at the end.
@@ -852,7 +976,9 @@ impl<U: UnaryOpT> Map1 for U {
         let dims = shape.dims();
         let el_count = shape.elem_count();
         let cfg = LaunchConfig::for_num_elems(el_count as u32);
-        let ds = dev.htod_copy([dims, layout.stride()].concat()).w()?;
+        let ds = dev
+            .htod_copy([dims, &layout.wrapping_stride()].concat())
+            .w()?;
         let src = &src.slice(layout.start_offset()..);
         let func = dev.get_or_load_func(&kernel_name::<T>(U::KERNEL), kernels::UNARY)?;
         // SAFETY: Set later by running the kernel.
@@ -892,7 +1018,9 @@ impl<'a> Map1 for IndexSelect<'a> {
         };
         let ids_shape = ids_l.shape();
         let ids_dims = ids_shape.dims();
-        let ds = dev.htod_copy([ids_dims, ids_l.stride()].concat()).w()?;
+        let ds = dev
+            .htod_copy([ids_dims, &ids_l.wrapping_stride()].concat())
+            .w()?;
         let src = match src_l.contiguous_offsets() {
             Some((o1, o2)) => src.slice(o1..o2),
             None => Err(crate::Error::RequiresContiguous { op: "index-select" }.bt())?,
@@ -1093,9 +1221,23 @@ impl<'a> Map2 for Conv1D<'a> {
         // SAFETY: Set later by running the kernel.
         let out = unsafe { dev.alloc::<T>(dst_el) }.w()?;
         let ds = if dims.len() == 3 {
-            [dims, inp_l.stride(), k_l.dims(), k_l.stride()].concat()
+            [
+                dims,
+                &inp_l.wrapping_stride(),
+                k_l.dims(),
+                &k_l.wrapping_stride(),
+            ]
+            .concat()
         } else if dims.len() == 2 {
-            [&[1], dims, &[1], inp_l.stride(), k_l.dims(), k_l.stride()].concat()
+            [
+                &[1],
+                dims,
+                &[1],
+                &inp_l.wrapping_stride(),
+                k_l.dims(),
+                &k_l.wrapping_stride(),
+            ]
+            .concat()
         } else {
             crate::bail!("unexpected input shape for conv1d {dims:?}")
         };
@@ -1135,7 +1277,13 @@ impl<'a> Map2 for Conv2D<'a> {
         let cfg = LaunchConfig::for_num_elems(dst_el as u32);
         let func = dev.get_or_load_func(&kernel_name::<T>("conv2d"), kernels::CONV)?;
         let ds = if dims.len() == 4 {
-            [dims, inp_l.stride(), k_l.dims(), k_l.stride()].concat()
+            [
+                dims,
+                &inp_l.wrapping_stride(),
+                k_l.dims(),
+                &k_l.wrapping_stride(),
+            ]
+            .concat()
         } else {
             crate::bail!("unexpected input shape for conv2d {dims:?}")
         };
@@ -1175,7 +1323,13 @@ impl<'a> Map2 for ConvTranspose1D<'a> {
         let cfg = LaunchConfig::for_num_elems(dst_el as u32);
         let func = dev.get_or_load_func(&kernel_name::<T>("conv_transpose1d"), kernels::CONV)?;
         let ds = if dims.len() == 3 {
-            [dims, inp_l.stride(), k_l.dims(), k_l.stride()].concat()
+            [
+                dims,
+                &inp_l.wrapping_stride(),
+                k_l.dims(),
+                &k_l.wrapping_stride(),
+            ]
+            .concat()
         } else {
             crate::bail!("unexpected input shape for conv_transpose1d {dims:?}")
         };
@@ -1224,7 +1378,13 @@ impl<'a> Map2 for ConvTranspose2D<'a> {
         let cfg = LaunchConfig::for_num_elems(dst_el as u32);
         let func = dev.get_or_load_func(&kernel_name::<T>("conv_transpose2d"), kernels::CONV)?;
         let ds = if dims.len() == 4 {
-            [dims, inp_l.stride(), k_l.dims(), k_l.stride()].concat()
+            [
+                dims,
+                &inp_l.wrapping_stride(),
+                k_l.dims(),
+                &k_l.wrapping_stride(),
+            ]
+            .concat()
         } else {
             crate::bail!("unexpected input shape for conv_transpose2d {dims:?}")
         };
@@ -1248,6 +1408,51 @@ impl<'a> Map2 for ConvTranspose2D<'a> {
     }
 }
 
+struct ConvTranspose3D<'a>(&'a crate::conv::ParamsConvTranspose3D);
+impl<'a> Map2 for ConvTranspose3D<'a> {
+    fn f<T: DeviceRepr + WithDType + ValidAsZeroBits>(
+        &self,
+        inp: &CudaSlice<T>,
+        inp_l: &Layout,
+        k: &CudaSlice<T>,
+        k_l: &Layout,
+        dev: &CudaDevice,
+    ) -> Result<CudaSlice<T>> {
+        // Kernel shape: (c_in_k, c_out, d_k, h_k, w_k)
+        // Input shape: (b_size, c_in, d_in, h_in, w_in)
+        let p = &self.0;
+        let (out_d, out_h, out_w) = (p.out_d(), p.out_h(), p.out_w());
+        let dst_el = p.c_out * out_d * out_h * out_w * p.b_size;
+        let inp = &inp.slice(inp_l.start_offset()..);
+        let k = &k.slice(k_l.start_offset()..);
+        let shape = inp_l.shape();
+        let dims = shape.dims();
+        let el = shape.elem_count();
+
+        // SAFETY: Set later by running the kernel.
+        let out = unsafe { dev.alloc::<T>(dst_el) }.w()?;
+        let cfg = LaunchConfig::for_num_elems(dst_el as u32);
+        let func = dev.get_or_load_func(&kernel_name::<T>("conv_transpose3d"), kernels::CONV)?;
+        let ds = if dims.len() == 5 {
+            [
+                dims,
+                &inp_l.wrapping_stride(),
+                k_l.dims(),
+                &k_l.wrapping_stride(),
+                &[out_d, out_h, out_w],
+            ]
+            .concat()
+        } else {
+            crate::bail!("unexpected input shape for conv_transpose3d {dims:?}")
+        };
+        let ds = dev.htod_copy(ds).w()?;
+        let params = (el, p.stride, p.padding, p.dilation, &ds, inp, k, &out);
+        // SAFETY: ffi.
+        unsafe { func.launch(cfg, params) }.w()?;
+        Ok(out)
+    }
+}
+
 enum PoolOp {
     Max,
     Avg,
@@ -1273,7 +1478,7 @@ impl Map1 for Pool2D {
         let shape = inp_l.shape();
         let dims = shape.dims();
         let ds = if dims.len() == 4 {
-            [dims, inp_l.stride()].concat()
+            [dims, &inp_l.wrapping_stride()].concat()
         } else {
             crate::bail!("unexpected input shape for pool {dims:?}")
         };
@@ -1306,6 +1511,55 @@ impl Map1 for Pool2D {
     }
 }
 
+struct Pool3D {
+    k: (usize, usize, usize),
+    stride: (usize, usize, usize),
+    op: PoolOp,
+}
+
+impl Map1 for Pool3D {
+    fn f<T: DeviceRepr + WithDType + ValidAsZeroBits>(
+        &self,
+        inp: &CudaSlice<T>,
+        dev: &CudaDevice,
+        inp_l: &Layout,
+    ) -> Result<CudaSlice<T>> {
+        // Input shape: (b_size, c, d, h, w)
+        let inp = &inp.slice(inp_l.start_offset()..);
+        let shape = inp_l.shape();
+        let dims = shape.dims();
+        let (k, s) = (self.k, self.stride);
+        let ds = if dims.len() == 5 {
+            [
+                dims,
+                &inp_l.wrapping_stride(),
+                &[k.0, k.1, k.2, s.0, s.1, s.2],
+            ]
+            .concat()
+        } else {
+            crate::bail!("unexpected input shape for pool3d {dims:?}")
+        };
+        let el = shape.elem_count();
+        let out_d = (dims[2] - k.0) / s.0 + 1;
+        let out_h = (dims[3] - k.1) / s.1 + 1;
+        let out_w = (dims[4] - k.2) / s.2 + 1;
+        let dst_el = out_d * out_h * out_w * dims[0] * dims[1];
+        let cfg = LaunchConfig::for_num_elems(dst_el as u32);
+        let kname = match self.op {
+            PoolOp::Max => "max_pool3d",
+            PoolOp::Avg => "avg_pool3d",
+        };
+        let func = dev.get_or_load_func(&kernel_name::<T>(kname), kernels::CONV)?;
+        // SAFETY: Set later by running the kernel.
+        let out = unsafe { dev.alloc::<T>(dst_el) }.w()?;
+        let ds = dev.htod_copy(ds).w()?;
+        let params = (el, &ds, inp, &out);
+        // SAFETY: ffi.
+        unsafe { func.launch(cfg, params) }.w()?;
+        Ok(out)
+    }
+}
+
 struct UpsampleNearest2D(usize, usize);
 impl Map1 for UpsampleNearest2D {
     fn f<T: DeviceRepr + WithDType + ValidAsZeroBits>(
@@ -1319,7 +1573,7 @@ impl Map1 for UpsampleNearest2D {
         let shape = inp_l.shape();
         let dims = shape.dims();
         let ds = if dims.len() == 4 {
-            [dims, inp_l.stride()].concat()
+            [dims, &inp_l.wrapping_stride()].concat()
         } else {
             crate::bail!("unexpected input shape for upsample {dims:?}")
         };
@@ -1375,7 +1629,15 @@ impl<'a> Map2 for WhereCond<'a> {
         let el = shape.elem_count();
         let cfg = LaunchConfig::for_num_elems(el as u32);
         let ds = dev
-            .htod_copy([dims, ids_l.stride(), layout_t.stride(), layout_f.stride()].concat())
+            .htod_copy(
+                [
+                    dims,
+                    &ids_l.wrapping_stride(),
+                    &layout_t.wrapping_stride(),
+                    &layout_f.wrapping_stride(),
+                ]
+                .concat(),
+            )
             .w()?;
         let t = &t.slice(layout_t.start_offset()..);
         let f = &f.slice(layout_f.start_offset()..);
@@ -1403,7 +1665,7 @@ impl<U: crate::op::BinaryOpT> Map2 for U {
         let elem_count = shape.elem_count();
         let cfg = LaunchConfig::for_num_elems(elem_count as u32);
         let dims_and_strides = dev
-            .htod_copy([dims, lhs_l.stride(), rhs_l.stride()].concat())
+            .htod_copy([dims, &lhs_l.wrapping_stride(), &rhs_l.wrapping_stride()].concat())
             .w()?;
         let lhs = &lhs.slice(lhs_l.start_offset()..);
         let rhs = &rhs.slice(rhs_l.start_offset()..);
@@ -1432,7 +1694,7 @@ impl Map2Any for Cmp {
         let elem_count = shape.elem_count();
         let cfg = LaunchConfig::for_num_elems(elem_count as u32);
         let dims_and_strides = dev
-            .htod_copy([dims, lhs_l.stride(), rhs_l.stride()].concat())
+            .htod_copy([dims, &lhs_l.wrapping_stride(), &rhs_l.wrapping_stride()].concat())
             .w()?;
         let lhs = &lhs.slice(lhs_l.start_offset()..);
         let rhs = &rhs.slice(rhs_l.start_offset()..);
@@ -1522,6 +1784,19 @@ impl CudaStorage {
     pub fn as_cuda_slice<T: CudaDType>(&self) -> Result<&CudaSlice<T>> {
         T::as_cuda_slice(self)
     }
+
+    // The elementwise, reduce and copy kernels go through `get_strided_index` which supports
+    // negative strides. The other kernels, e.g. convolutions or matmul, index the storage
+    // directly so inputs with negative strides are first copied to a contiguous storage. This
+    // returns `None` when the layout has no negative strides.
+    fn non_negative_strides(&self, layout: &Layout) -> Result<Option<(Self, Layout)>> {
+        if !layout.has_negative_stride() {
+            return Ok(None);
+        }
+        let mut dst = self.device.zeros_impl(layout.shape(), self.dtype())?;
+        self.copy_strided_src(&mut dst, 0, layout)?;
+        Ok(Some((dst, Layout::contiguous(layout.shape()))))
+    }
 }
 
 fn gemm_config<T>(
@@ -1534,8 +1809,8 @@ fn gemm_config<T>(
     // https://docs.nvidia.com/cuda/cublas/index.html#cublas-t-gemm
     use cudarc::cublas::sys::cublasOperation_t;
 
-    let lhs_stride = lhs_l.stride();
-    let rhs_stride = rhs_l.stride();
+    let lhs_stride = lhs_l.wrapping_stride();
+    let rhs_stride = rhs_l.wrapping_stride();
     let rhs_m1 = rhs_stride[rhs_stride.len() - 1];
     let rhs_m2 = rhs_stride[rhs_stride.len() - 2];
     let lhs_m1 = lhs_stride[lhs_stride.len() - 1];
@@ -1640,7 +1915,9 @@ impl BackendStorage for CudaStorage {
         let el = shape.elem_count();
         let cfg = LaunchConfig::for_num_elems(el as u32);
         let dev = self.device();
-        let ds = dev.htod_copy([dims, layout.stride()].concat()).w()?;
+        let ds = dev
+            .htod_copy([dims, &layout.wrapping_stride()].concat())
+            .w()?;
         let start_o = layout.start_offset();
         // This returns an i64 rather than a &i64, this is useful to get around some temporary
         // lifetime issue and is safe as long as self.slice does not go out of scope before inp
@@ -1760,37 +2037,37 @@ impl BackendStorage for CudaStorage {
             CudaStorageSlice::U8(slice) => {
                 let dev = slice.device();
                 let cpu_storage = dev.dtoh_sync_copy(slice).w()?;
-                Ok(CpuStorage::U8(cpu_storage))
+                Ok(CpuStorage::U8(cpu_storage.into()))
             }
             CudaStorageSlice::U32(slice) => {
                 let dev = slice.device();
                 let cpu_storage = dev.dtoh_sync_copy(slice).w()?;
-                Ok(CpuStorage::U32(cpu_storage))
+                Ok(CpuStorage::U32(cpu_storage.into()))
             }
             CudaStorageSlice::I64(slice) => {
                 let dev = slice.device();
                 let cpu_storage = dev.dtoh_sync_copy(slice).w()?;
-                Ok(CpuStorage::I64(cpu_storage))
+                Ok(CpuStorage::I64(cpu_storage.into()))
             }
             CudaStorageSlice::BF16(slice) => {
                 let dev = slice.device();
                 let cpu_storage = dev.dtoh_sync_copy(slice).w()?;
-                Ok(CpuStorage::BF16(cpu_storage))
+                Ok(CpuStorage::BF16(cpu_storage.into()))
             }
             CudaStorageSlice::F16(slice) => {
                 let dev = slice.device();
                 let cpu_storage = dev.dtoh_sync_copy(slice).w()?;
-                Ok(CpuStorage::F16(cpu_storage))
+                Ok(CpuStorage::F16(cpu_storage.into()))
             }
             CudaStorageSlice::F32(slice) => {
                 let dev = slice.device();
                 let cpu_storage = dev.dtoh_sync_copy(slice).w()?;
-                Ok(CpuStorage::F32(cpu_storage))
+                Ok(CpuStorage::F32(cpu_storage.into()))
             }
             CudaStorageSlice::F64(slice) => {
                 let dev = slice.device();
                 let cpu_storage = dev.dtoh_sync_copy(slice).w()?;
-                Ok(CpuStorage::F64(cpu_storage))
+                Ok(CpuStorage::F64(cpu_storage.into()))
             }
         }
     }
@@ -1815,6 +2092,12 @@ impl BackendStorage for CudaStorage {
         kernel_l: &Layout,
         params: &crate::conv::ParamsConv1D,
     ) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.conv1d(&l, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv1d(l, &kernel, &kernel_l, params);
+        }
         const USE_IM2COL_CONV1D: bool = true;
 
         let device = self.device().clone();
@@ -1864,6 +2147,12 @@ impl BackendStorage for CudaStorage {
         kernel_l: &Layout,
         params: &crate::conv::ParamsConvTranspose1D,
     ) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.conv_transpose1d(&l, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv_transpose1d(l, &kernel, &kernel_l, params);
+        }
         let device = self.device().clone();
         let slice =
             ConvTranspose1D(params).map(&self.slice, l, &kernel.slice, kernel_l, &device)?;
@@ -1878,6 +2167,12 @@ impl BackendStorage for CudaStorage {
         kernel_l: &Layout,
         params: &crate::conv::ParamsConv2D,
     ) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.conv2d(&l, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv2d(l, &kernel, &kernel_l, params);
+        }
         const USE_IM2COL_CONV2D: bool = true;
 
         let device = self.device().clone();
@@ -1924,6 +2219,8 @@ impl BackendStorage for CudaStorage {
         Ok(res_t)
     }
 
+    // TODO: Add a cudnn path for conv3d next to this one, this requires the nd tensor, filter
+    // and convolution descriptors that cudarc does not expose yet, see `conv3d` below.
     #[cfg(feature = "cudnn")]
     fn conv2d(
         &self,
@@ -1932,6 +2229,12 @@ impl BackendStorage for CudaStorage {
         kernel_l: &Layout,
         params: &crate::conv::ParamsConv2D,
     ) -> Result<Self> {
+        if let Some((inp, inp_l)) = self.non_negative_strides(inp_l)? {
+            return inp.conv2d(&inp_l, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv2d(inp_l, &kernel, &kernel_l, params);
+        }
         let device = self.device().clone();
         if !kernel_l.is_contiguous() {
             let slice = Conv2D(params).map(&self.slice, inp_l, &kernel.slice, kernel_l, &device)?;
@@ -1994,13 +2297,96 @@ impl BackendStorage for CudaStorage {
         kernel_l: &Layout,
         params: &crate::conv::ParamsConvTranspose2D,
     ) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.conv_transpose2d(&l, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv_transpose2d(l, &kernel, &kernel_l, params);
+        }
         let device = self.device().clone();
         let slice =
             ConvTranspose2D(params).map(&self.slice, l, &kernel.slice, kernel_l, &device)?;
         Ok(Self { slice, device })
     }
 
+    fn conv3d(
+        &self,
+        l: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &crate::conv::ParamsConv3D,
+    ) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.conv3d(&l, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv3d(l, &kernel, &kernel_l, params);
+        }
+        // There is no cudnn path for conv3d: the safe cudnn api from cudarc 0.10 only provides 4d
+        // tensor, filter and convolution descriptors (`create_4d_tensor`, `create_conv2d`, ...)
+        // and `Conv2dForward` is tied to these. Using the nd descriptors would mean managing the
+        // raw cudnn handles through `cudarc::cudnn::sys` which is left for when cudarc exposes
+        // them. So conv3d always goes through im2col followed by a cublas matmul.
+        let device = self.device().clone();
+        let col = Im2Col3D {
+            d_k: params.k_d,
+            h_k: params.k_h,
+            w_k: params.k_w,
+            stride: params.stride,
+            dilation: params.dilation,
+            padding: params.padding,
+        }
+        .map(&self.slice, &device, l)?;
+        let col = Self { slice: col, device };
+        let (d_out, h_out, w_out) = (params.out_d(), params.out_h(), params.out_w());
+        let b = params.b_size;
+        let n = params.c_out;
+        let k = params.k_d * params.k_h * params.k_w * params.c_in;
+        let m = d_out * h_out * w_out;
+        let col_l = Layout::contiguous((b, m, k));
+        let res = if kernel_l.is_contiguous() {
+            let kernel_l = Layout::contiguous_with_offset((1, n, k), kernel_l.start_offset())
+                .transpose(1, 2)?
+                .broadcast_as((b, k, n))?;
+            col.matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
+        } else {
+            // Make the kernel contiguous if not already the case.
+            let mut kernel_c = self.device().zeros_impl(kernel_l.shape(), kernel.dtype())?;
+            kernel.copy_strided_src(&mut kernel_c, 0, kernel_l)?;
+            let kernel_l = Layout::contiguous((1, n, k))
+                .transpose(1, 2)?
+                .broadcast_as((b, k, n))?;
+            col.matmul(&kernel_c, (b, m, n, k), &col_l, &kernel_l)?
+        };
+        let res_l = Layout::contiguous((b, d_out, h_out, w_out, n)).permute(&[0, 4, 1, 2, 3])?;
+        let mut res_t = self.device().zeros_impl(res_l.shape(), res.dtype())?;
+        res.copy_strided_src(&mut res_t, 0, &res_l)?;
+        Ok(res_t)
+    }
+
+    fn conv_transpose3d(
+        &self,
+        l: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &crate::conv::ParamsConvTranspose3D,
+    ) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.conv_transpose3d(&l, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv_transpose3d(l, &kernel, &kernel_l, params);
+        }
+        let device = self.device().clone();
+        let slice =
+            ConvTranspose3D(params).map(&self.slice, l, &kernel.slice, kernel_l, &device)?;
+        Ok(Self { slice, device })
+    }
+
     fn avg_pool2d(&self, l: &Layout, k: (usize, usize), stride: (usize, usize)) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.avg_pool2d(&l, k, stride);
+        }
         let device = self.device().clone();
         let slice = Pool2D {
             w_k: k.0,
@@ -2014,6 +2400,9 @@ impl BackendStorage for CudaStorage {
     }
 
     fn max_pool2d(&self, l: &Layout, k: (usize, usize), stride: (usize, usize)) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.max_pool2d(&l, k, stride);
+        }
         let device = self.device().clone();
         let slice = Pool2D {
             w_k: k.0,
@@ -2026,22 +2415,75 @@ impl BackendStorage for CudaStorage {
         Ok(Self { slice, device })
     }
 
+    fn avg_pool3d(
+        &self,
+        l: &Layout,
+        k: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    ) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.avg_pool3d(&l, k, stride);
+        }
+        let device = self.device().clone();
+        let slice = Pool3D {
+            k,
+            stride,
+            op: PoolOp::Avg,
+        }
+        .map(&self.slice, &device, l)?;
+        Ok(Self { slice, device })
+    }
+
+    fn max_pool3d(
+        &self,
+        l: &Layout,
+        k: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    ) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.max_pool3d(&l, k, stride);
+        }
+        let device = self.device().clone();
+        let slice = Pool3D {
+            k,
+            stride,
+            op: PoolOp::Max,
+        }
+        .map(&self.slice, &device, l)?;
+        Ok(Self { slice, device })
+    }
+
     fn upsample_nearest1d(&self, _: &Layout, _out_sz: usize) -> Result<Self> {
         crate::bail!("upsample-nearest1d is not supported on cuda")
     }
 
     fn upsample_nearest2d(&self, l: &Layout, out_w: usize, out_h: usize) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.upsample_nearest2d(&l, out_w, out_h);
+        }
         let device = self.device().clone();
         let slice = UpsampleNearest2D(out_w, out_h).map(&self.slice, &device, l)?;
         Ok(Self { slice, device })
     }
 
     fn index_select(&self, ids: &Self, l: &Layout, ids_l: &Layout, dim: usize) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.index_select(ids, &l, ids_l, dim);
+        }
+        if let Some((ids, ids_l)) = ids.non_negative_strides(ids_l)? {
+            return self.index_select(&ids, l, &ids_l, dim);
+        }
         let device = self.device().clone();
         let slice = IndexSelect(ids, ids_l, dim).map(&self.slice, &device, l)?;
         Ok(Self { slice, device })
     }
     fn gather(&self, l: &Layout, ids: &Self, ids_l: &Layout, dim: usize) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.gather(&l, ids, ids_l, dim);
+        }
+        if let Some((ids, ids_l)) = ids.non_negative_strides(ids_l)? {
+            return self.gather(l, &ids, &ids_l, dim);
+        }
         let device = self.device().clone();
         let slice = Gather(ids, ids_l, dim).map(&self.slice, &device, l)?;
         Ok(Self { slice, device })
@@ -2055,6 +2497,12 @@ impl BackendStorage for CudaStorage {
         src_l: &Layout,
         dim: usize,
     ) -> Result<Self> {
+        if let Some((ids, ids_l)) = ids.non_negative_strides(ids_l)? {
+            return self.scatter_add(l, &ids, &ids_l, src, src_l, dim);
+        }
+        if let Some((src, src_l)) = src.non_negative_strides(src_l)? {
+            return self.scatter_add(l, ids, ids_l, &src, &src_l, dim);
+        }
         let device = self.device().clone();
         let mut acc = device.zeros_impl(l.shape(), self.dtype())?;
         self.copy_strided_src(&mut acc, 0, l)?;
@@ -2070,6 +2518,12 @@ impl BackendStorage for CudaStorage {
         src_l: &Layout,
         dim: usize,
     ) -> Result<Self> {
+        if let Some((ids, ids_l)) = ids.non_negative_strides(ids_l)? {
+            return self.index_add(l, &ids, &ids_l, src, src_l, dim);
+        }
+        if let Some((src, src_l)) = src.non_negative_strides(src_l)? {
+            return self.index_add(l, ids, ids_l, &src, &src_l, dim);
+        }
         let device = self.device().clone();
         let mut acc = device.zeros_impl(l.shape(), self.dtype())?;
         self.copy_strided_src(&mut acc, 0, l)?;
@@ -2084,6 +2538,12 @@ impl BackendStorage for CudaStorage {
         lhs_l: &Layout,
         rhs_l: &Layout,
     ) -> Result<Self> {
+        if let Some((lhs, lhs_l)) = self.non_negative_strides(lhs_l)? {
+            return lhs.matmul(rhs, (b, m, n, k), &lhs_l, rhs_l);
+        }
+        if let Some((rhs, rhs_l)) = rhs.non_negative_strides(rhs_l)? {
+            return self.matmul(&rhs, (b, m, n, k), lhs_l, &rhs_l);
+        }
         let elem_count = b * m * n;
         let dev = &self.device;
         let slice = match (&self.slice, &rhs.slice) {
@@ -2154,7 +2614,9 @@ impl BackendStorage for CudaStorage {
         }
         let cfg = LaunchConfig::for_num_elems(el_count as u32);
         let dev = &self.device;
-        let ds = dev.htod_copy([dims, src_l.stride()].concat()).w()?;
+        let ds = dev
+            .htod_copy([dims, &src_l.wrapping_stride()].concat())
+            .w()?;
         match (&self.slice, &mut dst.slice) {
             (CudaStorageSlice::BF16(src), CudaStorageSlice::BF16(dst)) => {
                 let (src, mut dst) = slice_src_and_dst(src, src_l, dst, dst_offset);
@@ -179,6 +179,10 @@ impl Device {
         matches!(self, Self::Metal(_))
     }
 
+    pub fn is_wgpu(&self) -> bool {
+        matches!(self, Self::Wgpu(_))
+    }
+
     pub fn cuda_if_available(ordinal: usize) -> Result<Self> {
         if crate::utils::cuda_is_available() {
             Self::new_cuda(ordinal)
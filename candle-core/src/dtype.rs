@@ -122,12 +122,12 @@ macro_rules! with_dtype {
             }
 
             fn to_cpu_storage_owned(data: Vec<Self>) -> CpuStorage {
-                CpuStorage::$dtype(data)
+                CpuStorage::$dtype(data.into())
             }
 
             fn cpu_storage_data(s: CpuStorage) -> Result<Vec<Self>> {
                 match s {
-                    CpuStorage::$dtype(data) => Ok(data),
+                    CpuStorage::$dtype(data) => Ok(data.into_vec()),
                     _ => Err(Error::UnexpectedDType {
                         expected: DType::$dtype,
                         got: s.dtype(),
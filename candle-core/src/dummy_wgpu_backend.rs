@@ -122,6 +122,26 @@ impl crate::backend::BackendStorage for WgpuStorage {
         Err(Error::NotCompiledWithMetalSupport)
     }
 
+    fn conv3d(
+        &self,
+        _: &Layout,
+        _: &Self,
+        _: &Layout,
+        _: &crate::conv::ParamsConv3D,
+    ) -> Result<Self> {
+        Err(Error::NotCompiledWithWgpuSupport)
+    }
+
+    fn conv_transpose3d(
+        &self,
+        _l: &Layout,
+        _kernel: &Self,
+        _kernel_l: &Layout,
+        _params: &crate::conv::ParamsConvTranspose3D,
+    ) -> Result<Self> {
+        Err(Error::NotCompiledWithWgpuSupport)
+    }
+
     fn index_select(&self, _: &Self, _: &Layout, _: &Layout, _: usize) -> Result<Self> {
         Err(Error::NotCompiledWithMetalSupport)
     }
@@ -175,6 +195,24 @@ impl crate::backend::BackendStorage for WgpuStorage {
         Err(Error::NotCompiledWithWgpuSupport)
     }
 
+    fn avg_pool3d(
+        &self,
+        _: &Layout,
+        _: (usize, usize, usize),
+        _: (usize, usize, usize),
+    ) -> Result<Self> {
+        Err(Error::NotCompiledWithWgpuSupport)
+    }
+
+    fn max_pool3d(
+        &self,
+        _: &Layout,
+        _: (usize, usize, usize),
+        _: (usize, usize, usize),
+    ) -> Result<Self> {
+        Err(Error::NotCompiledWithWgpuSupport)
+    }
+
     fn upsample_nearest1d(&self, _: &Layout, _: usize) -> Result<Self> {
         Err(Error::NotCompiledWithWgpuSupport)
     }
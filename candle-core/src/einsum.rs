@@ -0,0 +1,463 @@
+//! Einstein summation over an arbitrary number of tensors.
+//!
+//! The contraction is lowered to existing tensor operations (`permute`, `reshape`, `matmul`,
+//! `broadcast_mul` and `sum`) so that the result supports backpropagation without having to
+//! implement any dedicated gradient.
+use crate::{bail, Error, Result, Tensor};
+use std::collections::HashMap;
+
+// Labels generated for the dimensions covered by an ellipsis are taken from the unicode private
+// use area so that they cannot clash with user provided labels.
+const ELLIPSIS_LABEL_START: u32 = 0xE000;
+
+fn ellipsis_label(idx_from_right: usize) -> char {
+    // The private use area has more than 6000 code points, way more than the maximum rank.
+    char::from_u32(ELLIPSIS_LABEL_START + idx_from_right as u32).unwrap()
+}
+
+fn is_ellipsis_label(c: char) -> bool {
+    (c as u32) >= ELLIPSIS_LABEL_START && (c as u32) < ELLIPSIS_LABEL_START + 0x1000
+}
+
+/// The labels of a single term of an equation, `ellipsis` is the position at which the `...`
+/// appeared, if any.
+#[derive(Debug, Clone)]
+struct Term {
+    labels: Vec<char>,
+    ellipsis: Option<usize>,
+}
+
+impl Term {
+    fn parse(term: &str, equation: &str) -> Result<Self> {
+        let mut labels = vec![];
+        let mut ellipsis = None;
+        let mut chars = term.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {
+                    if chars.next() != Some('.') || chars.next() != Some('.') {
+                        bail!("einsum: invalid ellipsis in equation '{equation}'")
+                    }
+                    if ellipsis.is_some() {
+                        bail!("einsum: multiple ellipsis in term '{term}' of equation '{equation}'")
+                    }
+                    ellipsis = Some(labels.len())
+                }
+                c if c.is_ascii_alphabetic() => labels.push(c),
+                c if c.is_whitespace() => {}
+                c => bail!("einsum: invalid label '{c}' in equation '{equation}'"),
+            }
+        }
+        Ok(Self { labels, ellipsis })
+    }
+
+    /// Returns the full list of labels for an operand of rank `rank`, the dimensions covered by
+    /// the ellipsis are given generated labels aligned on the right.
+    fn expand(&self, rank: usize, shape: &crate::Shape) -> Result<Vec<char>> {
+        match self.ellipsis {
+            None => {
+                if rank != self.labels.len() {
+                    Err(Error::UnexpectedNumberOfDims {
+                        expected: self.labels.len(),
+                        got: rank,
+                        shape: shape.clone(),
+                    }
+                    .bt())?
+                }
+                Ok(self.labels.clone())
+            }
+            Some(pos) => {
+                if rank < self.labels.len() {
+                    Err(Error::UnexpectedNumberOfDims {
+                        expected: self.labels.len(),
+                        got: rank,
+                        shape: shape.clone(),
+                    }
+                    .bt())?
+                }
+                let n_ellipsis = rank - self.labels.len();
+                let mut labels = self.labels[..pos].to_vec();
+                labels.extend((0..n_ellipsis).rev().map(ellipsis_label));
+                labels.extend_from_slice(&self.labels[pos..]);
+                Ok(labels)
+            }
+        }
+    }
+}
+
+/// An intermediary value during the contraction, each dimension of the tensor is associated with
+/// a distinct label.
+#[derive(Debug, Clone)]
+struct Operand {
+    tensor: Tensor,
+    labels: Vec<char>,
+}
+
+impl Operand {
+    fn dims_for(&self, labels: &[char]) -> Vec<usize> {
+        labels
+            .iter()
+            .map(|l| {
+                let idx = self.labels.iter().position(|v| v == l).unwrap();
+                self.tensor.dims()[idx]
+            })
+            .collect()
+    }
+
+    fn permute_to(&self, labels: &[char]) -> Result<Tensor> {
+        let perm: Vec<usize> = labels
+            .iter()
+            .map(|l| self.labels.iter().position(|v| v == l).unwrap())
+            .collect();
+        if perm.iter().enumerate().all(|(i, &p)| i == p) {
+            Ok(self.tensor.clone())
+        } else {
+            self.tensor.permute(perm)
+        }
+    }
+
+    /// Takes the diagonal for labels that appear multiple times in the operand, e.g. `ii->i`.
+    fn diagonal(mut self) -> Result<Self> {
+        loop {
+            let repeated = self.labels.iter().enumerate().find_map(|(i, l)| {
+                self.labels[i + 1..]
+                    .iter()
+                    .position(|v| v == l)
+                    .map(|j| (i, i + 1 + j))
+            });
+            let (i, j) = match repeated {
+                None => return Ok(self),
+                Some(ij) => ij,
+            };
+            let dims = self.tensor.dims();
+            if dims[i] != dims[j] {
+                Err(Error::ShapeMismatchBinaryOp {
+                    lhs: self.tensor.shape().clone(),
+                    rhs: self.tensor.shape().clone(),
+                    op: "einsum-diagonal",
+                }
+                .bt())?
+            }
+            let n = dims[i];
+            let mut mask_dims = vec![1; dims.len()];
+            mask_dims[i] = n;
+            mask_dims[j] = n;
+            let mask =
+                Tensor::eye(n, self.tensor.dtype(), self.tensor.device())?.reshape(mask_dims)?;
+            let tensor = self.tensor.broadcast_mul(&mask)?.sum(j)?;
+            self.labels.remove(j);
+            self.tensor = tensor
+        }
+    }
+
+    /// Sums over the labels that are not in `keep` and do not appear in `other`.
+    fn sum_unused(self, other: &[char], keep: &[char]) -> Result<Self> {
+        let (sum_dims, labels): (Vec<_>, Vec<_>) = self
+            .labels
+            .iter()
+            .enumerate()
+            .partition(|(_, l)| !other.contains(l) && !keep.contains(l));
+        if sum_dims.is_empty() {
+            return Ok(self);
+        }
+        let sum_dims: Vec<usize> = sum_dims.into_iter().map(|(i, _)| i).collect();
+        let tensor = self.tensor.sum(sum_dims)?;
+        let labels = labels.into_iter().map(|(_, &l)| l).collect();
+        Ok(Self { tensor, labels })
+    }
+}
+
+/// Contracts two operands together, the labels in `keep` are preserved in the result while the
+/// other labels are summed over.
+fn contract_pair(lhs: Operand, rhs: Operand, keep: &[char]) -> Result<Operand> {
+    let lhs = lhs.sum_unused(&rhs.labels, keep)?;
+    let rhs = rhs.sum_unused(&lhs.labels, keep)?;
+
+    let shared: Vec<char> = lhs
+        .labels
+        .iter()
+        .filter(|l| rhs.labels.contains(l))
+        .copied()
+        .collect();
+    let batch: Vec<char> = shared
+        .iter()
+        .filter(|l| keep.contains(l))
+        .copied()
+        .collect();
+    let contracted: Vec<char> = shared
+        .iter()
+        .filter(|l| !keep.contains(l))
+        .copied()
+        .collect();
+    let lhs_only: Vec<char> = lhs
+        .labels
+        .iter()
+        .filter(|l| !shared.contains(l))
+        .copied()
+        .collect();
+    let rhs_only: Vec<char> = rhs
+        .labels
+        .iter()
+        .filter(|l| !shared.contains(l))
+        .copied()
+        .collect();
+
+    // Dimensions covered by an ellipsis can be broadcasted when they have a size of 1.
+    let lhs_shared = lhs.dims_for(&shared);
+    let rhs_shared = rhs.dims_for(&shared);
+    let (lhs, rhs) = if lhs_shared == rhs_shared {
+        (lhs, rhs)
+    } else {
+        let mut lhs_target = lhs.tensor.dims().to_vec();
+        let mut rhs_target = rhs.tensor.dims().to_vec();
+        for ((l, &d1), &d2) in shared.iter().zip(lhs_shared.iter()).zip(rhs_shared.iter()) {
+            if d1 == d2 {
+                continue;
+            }
+            if !is_ellipsis_label(*l) || (d1 != 1 && d2 != 1) {
+                Err(Error::ShapeMismatchBinaryOp {
+                    lhs: lhs.tensor.shape().clone(),
+                    rhs: rhs.tensor.shape().clone(),
+                    op: "einsum",
+                }
+                .bt())?
+            }
+            let d = usize::max(d1, d2);
+            lhs_target[lhs.labels.iter().position(|v| v == l).unwrap()] = d;
+            rhs_target[rhs.labels.iter().position(|v| v == l).unwrap()] = d;
+        }
+        let lhs = Operand {
+            tensor: lhs.tensor.broadcast_as(lhs_target)?,
+            labels: lhs.labels,
+        };
+        let rhs = Operand {
+            tensor: rhs.tensor.broadcast_as(rhs_target)?,
+            labels: rhs.labels,
+        };
+        (lhs, rhs)
+    };
+
+    let batch_dims = lhs.dims_for(&batch);
+    let lhs_only_dims = lhs.dims_for(&lhs_only);
+    let rhs_only_dims = rhs.dims_for(&rhs_only);
+    let mut labels = batch.clone();
+    labels.extend_from_slice(&lhs_only);
+    labels.extend_from_slice(&rhs_only);
+
+    let tensor = if contracted.is_empty() {
+        // No summation required, this is an elementwise product with broadcasting.
+        let lhs_t = lhs.permute_to(&[batch.as_slice(), &lhs_only].concat())?;
+        let rhs_t = rhs.permute_to(&[batch.as_slice(), &rhs_only].concat())?;
+        let lhs_dims = [
+            batch_dims.as_slice(),
+            &lhs_only_dims,
+            &vec![1; rhs_only.len()],
+        ]
+        .concat();
+        let rhs_dims = [
+            batch_dims.as_slice(),
+            &vec![1; lhs_only.len()],
+            &rhs_only_dims,
+        ]
+        .concat();
+        lhs_t
+            .reshape(lhs_dims)?
+            .broadcast_mul(&rhs_t.reshape(rhs_dims)?)?
+    } else {
+        let b: usize = batch_dims.iter().product();
+        let m: usize = lhs_only_dims.iter().product();
+        let n: usize = rhs_only_dims.iter().product();
+        let k: usize = lhs.dims_for(&contracted).iter().product();
+        let lhs_t = lhs
+            .permute_to(&[batch.as_slice(), &lhs_only, &contracted].concat())?
+            .reshape((b, m, k))?;
+        let rhs_t = rhs
+            .permute_to(&[batch.as_slice(), &contracted, &rhs_only].concat())?
+            .reshape((b, k, n))?;
+        let dims = [batch_dims.as_slice(), &lhs_only_dims, &rhs_only_dims].concat();
+        lhs_t.matmul(&rhs_t)?.reshape(dims)?
+    };
+    Ok(Operand { tensor, labels })
+}
+
+/// Returns the labels that have to be kept when contracting operands `i` and `j` together.
+fn labels_to_keep(operands: &[Operand], i: usize, j: usize, output: &[char]) -> Vec<char> {
+    let mut keep = output.to_vec();
+    for (k, op) in operands.iter().enumerate() {
+        if k != i && k != j {
+            keep.extend_from_slice(&op.labels)
+        }
+    }
+    keep
+}
+
+/// Greedily picks the pair of operands to contract next: the pair producing the smallest
+/// intermediary result is selected, ties are broken using the number of multiply-adds.
+fn next_pair(
+    operands: &[Operand],
+    output: &[char],
+    sizes: &HashMap<char, usize>,
+) -> (usize, usize) {
+    let mut best = (0, 1);
+    let mut best_cost = (usize::MAX, usize::MAX);
+    for i in 0..operands.len() {
+        for j in i + 1..operands.len() {
+            let keep = labels_to_keep(operands, i, j, output);
+            let mut all_labels = operands[i].labels.clone();
+            for l in operands[j].labels.iter() {
+                if !all_labels.contains(l) {
+                    all_labels.push(*l)
+                }
+            }
+            let size = |labels: &mut dyn Iterator<Item = &char>| {
+                labels.fold(1usize, |acc, l| acc.saturating_mul(sizes[l]))
+            };
+            let flops = size(&mut all_labels.iter());
+            let result_size = size(&mut all_labels.iter().filter(|l| keep.contains(l)));
+            let cost = (result_size, flops);
+            if cost < best_cost {
+                best_cost = cost;
+                best = (i, j);
+            }
+        }
+    }
+    best
+}
+
+impl Tensor {
+    /// Evaluates the Einstein summation convention on the operands.
+    ///
+    /// The equation uses one letter per dimension for each operand, terms are separated by commas
+    /// and the output labels can be given after `->`. When the output is omitted, it is made of
+    /// the labels that appear exactly once, sorted alphabetically. An ellipsis `...` can be used
+    /// to cover multiple dimensions, these dimensions are broadcasted across operands.
+    ///
+    /// When more than two operands are provided, the contraction order is selected greedily so as
+    /// to keep the intermediary results small.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let q = Tensor::arange(0f32, 24., &Device::Cpu)?.reshape((1, 2, 3, 4))?;
+    /// let k = Tensor::arange(0f32, 40., &Device::Cpu)?.reshape((1, 2, 5, 4))?;
+    /// let attn = Tensor::einsum("bhqd,bhkd->bhqk", &[&q, &k])?;
+    /// assert_eq!(attn.dims(), &[1, 2, 3, 5]);
+    ///
+    /// let trace = Tensor::einsum("ii", &[&Tensor::eye(3, candle_core::DType::F32, &Device::Cpu)?])?;
+    /// assert_eq!(trace.to_scalar::<f32>()?, 3.);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn einsum<A: AsRef<Tensor>>(equation: &str, operands: &[A]) -> Result<Self> {
+        if operands.is_empty() {
+            Err(Error::OpRequiresAtLeastOneTensor { op: "einsum" }.bt())?
+        }
+        let (inputs, output) = match equation.split_once("->") {
+            Some((inputs, output)) => (inputs, Some(output)),
+            None => (equation, None),
+        };
+        let inputs = inputs
+            .split(',')
+            .map(|t| Term::parse(t, equation))
+            .collect::<Result<Vec<_>>>()?;
+        if inputs.len() != operands.len() {
+            bail!(
+                "einsum: equation '{equation}' has {} terms but {} operands were provided",
+                inputs.len(),
+                operands.len()
+            )
+        }
+
+        let mut sizes: HashMap<char, usize> = HashMap::new();
+        let mut label_shapes: HashMap<char, crate::Shape> = HashMap::new();
+        let mut max_ellipsis_rank = 0;
+        let mut ops = Vec::with_capacity(operands.len());
+        for (term, operand) in inputs.iter().zip(operands.iter()) {
+            let tensor = operand.as_ref();
+            let labels = term.expand(tensor.rank(), tensor.shape())?;
+            if term.ellipsis.is_some() {
+                max_ellipsis_rank = usize::max(max_ellipsis_rank, labels.len() - term.labels.len())
+            }
+            let op = Operand {
+                tensor: tensor.clone(),
+                labels,
+            }
+            .diagonal()?;
+            for (&l, &d) in op.labels.iter().zip(op.tensor.dims().iter()) {
+                match sizes.get(&l) {
+                    None => {
+                        sizes.insert(l, d);
+                        label_shapes.insert(l, tensor.shape().clone());
+                    }
+                    Some(&prev) if prev == d => {}
+                    Some(&prev) if is_ellipsis_label(l) && (prev == 1 || d == 1) => {
+                        sizes.insert(l, usize::max(prev, d));
+                    }
+                    Some(_) => Err(Error::ShapeMismatchBinaryOp {
+                        lhs: label_shapes[&l].clone(),
+                        rhs: tensor.shape().clone(),
+                        op: "einsum",
+                    }
+                    .bt())?,
+                }
+            }
+            ops.push(op)
+        }
+
+        let output: Vec<char> = match output {
+            Some(output) => {
+                let term = Term::parse(output, equation)?;
+                let mut labels = term.labels[..term.ellipsis.unwrap_or(0)].to_vec();
+                if term.ellipsis.is_some() {
+                    labels.extend((0..max_ellipsis_rank).rev().map(ellipsis_label));
+                }
+                labels.extend_from_slice(&term.labels[term.ellipsis.unwrap_or(0)..]);
+                for (i, l) in labels.iter().enumerate() {
+                    if labels[i + 1..].contains(l) {
+                        bail!("einsum: output label '{l}' appears multiple times in '{equation}'")
+                    }
+                    if !sizes.contains_key(l) {
+                        bail!("einsum: output label '{l}' does not appear in the inputs of '{equation}'")
+                    }
+                }
+                labels
+            }
+            None => {
+                let mut labels: Vec<char> =
+                    (0..max_ellipsis_rank).rev().map(ellipsis_label).collect();
+                let mut named = vec![];
+                for term in inputs.iter() {
+                    for l in term.labels.iter() {
+                        let count: usize = inputs
+                            .iter()
+                            .map(|t| t.labels.iter().filter(|v| *v == l).count())
+                            .sum();
+                        if count == 1 {
+                            named.push(*l)
+                        }
+                    }
+                }
+                named.sort();
+                labels.extend(named);
+                labels
+            }
+        };
+
+        while ops.len() > 1 {
+            let (i, j) = next_pair(&ops, &output, &sizes);
+            let keep = labels_to_keep(&ops, i, j, &output);
+            // j > i so removing j first does not shift i.
+            let rhs = ops.remove(j);
+            let lhs = ops.remove(i);
+            ops.push(contract_pair(lhs, rhs, &keep)?);
+        }
+        let op = ops.remove(0).sum_unused(&[], &output)?;
+        let tensor = op.permute_to(&output)?;
+        // Ellipsis dimensions that were only of size 1 in the last remaining operand may still
+        // need broadcasting.
+        let target: Vec<usize> = output.iter().map(|l| sizes[l]).collect();
+        if tensor.dims() != target.as_slice() {
+            tensor.broadcast_as(target)?.contiguous()
+        } else {
+            Ok(tensor)
+        }
+    }
+}
@@ -3,13 +3,14 @@ use crate::{Error, Result, Shape};
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Layout {
     shape: Shape,
-    // The strides are given in number of elements and not in bytes.
-    stride: Vec<usize>,
+    // The strides are given in number of elements and not in bytes. Negative strides are used
+    // for reversed views such as the ones returned by `Tensor::flip`.
+    stride: Vec<isize>,
     start_offset: usize,
 }
 
 impl Layout {
-    pub fn new(shape: Shape, stride: Vec<usize>, start_offset: usize) -> Self {
+    pub fn new(shape: Shape, stride: Vec<isize>, start_offset: usize) -> Self {
         Self {
             shape,
             stride,
@@ -19,7 +20,11 @@ impl Layout {
 
     pub fn contiguous_with_offset<S: Into<Shape>>(shape: S, start_offset: usize) -> Self {
         let shape = shape.into();
-        let stride = shape.stride_contiguous();
+        let stride = shape
+            .stride_contiguous()
+            .into_iter()
+            .map(|s| s as isize)
+            .collect();
         Self {
             shape,
             stride,
@@ -39,10 +44,39 @@ impl Layout {
         &self.shape
     }
 
-    pub fn stride(&self) -> &[usize] {
+    pub fn stride(&self) -> &[isize] {
         &self.stride
     }
 
+    /// Returns true if some of the strides are negative, i.e. some dimensions are traversed in
+    /// reverse order.
+    pub fn has_negative_stride(&self) -> bool {
+        self.stride.iter().any(|&s| s < 0)
+    }
+
+    /// The strides converted to unsigned values, negative strides wrap around. This is the
+    /// representation used to pass strides to the cuda and metal kernels, these reinterpret the
+    /// values as signed integers when computing offsets.
+    pub fn wrapping_stride(&self) -> Vec<usize> {
+        self.stride.iter().map(|&s| s as usize).collect()
+    }
+
+    /// The strides converted to unsigned values, this returns `None` if some strides are
+    /// negative.
+    pub fn unsigned_stride(&self) -> Option<Vec<usize>> {
+        self.stride
+            .iter()
+            .map(|&s| if s < 0 { None } else { Some(s as usize) })
+            .collect()
+    }
+
+    // The storage offset for the element at index `index` along dimension `dim`, starting from
+    // `start_offset`.
+    fn offset_along(&self, dim: usize, index: usize) -> usize {
+        self.start_offset
+            .wrapping_add_signed(self.stride[dim] * index as isize)
+    }
+
     pub fn start_offset(&self) -> usize {
         self.start_offset
     }
@@ -95,7 +129,116 @@ impl Layout {
         Ok(Self {
             shape: Shape::from(dims),
             stride: self.stride.clone(),
-            start_offset: self.start_offset + self.stride[dim] * start,
+            start_offset: self.offset_along(dim, start),
+        })
+    }
+
+    /// Returns `len` elements along dimension `dim`, the i-th element being at index
+    /// `start + i * step`. A negative `step` results in a reversed view.
+    pub(crate) fn narrow_with_step(
+        &self,
+        dim: usize,
+        start: usize,
+        len: usize,
+        step: isize,
+    ) -> Result<Self> {
+        let dims = self.shape().dims();
+        if dim >= dims.len() {
+            Err(Error::DimOutOfRange {
+                shape: self.shape().clone(),
+                dim: dim as i32,
+                op: "narrow_with_step",
+            }
+            .bt())?
+        }
+        let msg = if step == 0 {
+            Some("step cannot be 0")
+        } else if len > 0 && start >= dims[dim] {
+            Some("start >= dim_len")
+        } else {
+            let last = start as isize + (len as isize - 1) * step;
+            if len > 0 && (last < 0 || last >= dims[dim] as isize) {
+                Some("start + (len - 1) * step is out of bounds")
+            } else {
+                None
+            }
+        };
+        if let Some(msg) = msg {
+            Err(Error::NarrowInvalidArgs {
+                shape: self.shape.clone(),
+                dim,
+                start,
+                len,
+                msg,
+            }
+            .bt())?
+        }
+        let mut dims = dims.to_vec();
+        dims[dim] = len;
+        let mut stride = self.stride.clone();
+        stride[dim] *= step;
+        let start_offset = if len == 0 {
+            self.start_offset
+        } else {
+            self.offset_along(dim, start)
+        };
+        Ok(Self {
+            shape: Shape::from(dims),
+            stride,
+            start_offset,
+        })
+    }
+
+    /// Returns the slices of `size` elements along dimension `dim` with `step` elements between
+    /// the starts of consecutive slices. Dimension `dim` indexes the slices and a new last
+    /// dimension of size `size` indexes the elements within each slice.
+    pub(crate) fn unfold(&self, dim: usize, size: usize, step: usize) -> Result<Self> {
+        let dims = self.shape().dims();
+        if dim >= dims.len() {
+            Err(Error::DimOutOfRange {
+                shape: self.shape().clone(),
+                dim: dim as i32,
+                op: "unfold",
+            }
+            .bt())?
+        }
+        if step == 0 {
+            crate::bail!("unfold step cannot be 0")
+        }
+        if size > dims[dim] {
+            crate::bail!(
+                "unfold size {size} is larger than the size of dim {dim} in {:?}",
+                self.shape()
+            )
+        }
+        let mut dims = dims.to_vec();
+        dims[dim] = (dims[dim] - size) / step + 1;
+        dims.push(size);
+        let mut stride = self.stride.clone();
+        stride.push(stride[dim]);
+        stride[dim] *= step as isize;
+        Ok(Self {
+            shape: Shape::from(dims),
+            stride,
+            start_offset: self.start_offset,
+        })
+    }
+
+    /// Reverses the order of the elements along the given dimensions.
+    pub(crate) fn flip(&self, dims: &[usize]) -> Result<Self> {
+        let mut stride = self.stride.clone();
+        let mut start_offset = self.start_offset;
+        for &dim in dims.iter() {
+            let size = self.shape.dims()[dim];
+            if size > 0 {
+                start_offset = start_offset.wrapping_add_signed(stride[dim] * (size as isize - 1));
+            }
+            stride[dim] = -stride[dim]
+        }
+        Ok(Self {
+            shape: self.shape.clone(),
+            stride,
+            start_offset,
         })
     }
 
@@ -188,7 +331,7 @@ impl Layout {
         let mut block_len = 1;
         let mut contiguous_dims = 0; // These are counted from the right.
         for (&stride, &dim) in self.stride().iter().zip(self.dims().iter()).rev() {
-            if stride != block_len {
+            if stride != block_len as isize {
                 break;
             }
             block_len *= dim;
@@ -248,7 +391,7 @@ impl Layout {
         let dims = &dims[start_cont..end_cont];
         let mut len = 1;
         for (&stride, &dim) in strides.iter().zip(dims.iter()).rev() {
-            if stride != len {
+            if stride != len as isize {
                 return None;
             }
             len *= dim;
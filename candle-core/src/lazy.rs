@@ -0,0 +1,1155 @@
+//! Lazy evaluation and fusion of elementwise operations.
+//!
+//! Each eager tensor operation allocates a new storage and, on accelerators, launches a kernel.
+//! For chains of elementwise operations such as `silu(x) * y + z` this results in multiple
+//! allocations and passes over memory. A [`LazyTensor`] records the unary, binary and affine
+//! operations applied to some input tensors without running them, the recorded graph is only
+//! evaluated when calling [`LazyTensor::realize`].
+//!
+//! ```rust
+//! use candle_core::{Tensor, Device};
+//! let x = Tensor::new(&[-1f32, 0., 1., 2.], &Device::Cpu)?;
+//! let y = Tensor::new(&[2f32, 3., 4., 5.], &Device::Cpu)?;
+//! let (lx, ly) = (x.lazy(), y.lazy());
+//! let res = lx.silu()?.mul(&ly)?.add(&lx)?.realize()?;
+//! let eager = ((x.silu()? * &y)? + &x)?;
+//! assert_eq!(res.to_vec1::<f32>()?, eager.to_vec1::<f32>()?);
+//! # Ok::<(), candle_core::Error>(())
+//! ```
+//!
+//! The whole graph is evaluated by a single fused kernel that only allocates the resulting
+//! storage. On cpu this kernel processes the output in blocks, on cuda and metal the kernel source
+//! is generated from the graph and compiled at runtime with nvrtc or the metal compiler, the
+//! compiled kernels are cached by the device. The wgpu backend has no fused kernels, the recorded
+//! operations are replayed eagerly there, launching one kernel per operation as the eager api
+//! would. The same applies when some of the inputs require tracking gradients so that the
+//! backprop graph gets built. [`LazyTensor::is_fusable`] can be used to check which path
+//! `realize` takes.
+use crate::op::{BackpropOp, BinaryOp, BinaryOpT, UnaryOp, UnaryOpT};
+use crate::{CpuStorage, DType, Device, Error, Layout, Result, Shape, Storage, Tensor, WithDType};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+// The number of elements processed at once by the fused cpu kernel, each intermediary value uses
+// a buffer of this size.
+const BLOCK_SIZE: usize = 4096;
+
+#[derive(Clone)]
+enum LazyOp {
+    Input(Tensor),
+    Unary(LazyTensor, UnaryOp),
+    Binary(LazyTensor, LazyTensor, BinaryOp),
+    Affine { arg: LazyTensor, mul: f64, add: f64 },
+}
+
+struct LazyNode {
+    op: LazyOp,
+    shape: Shape,
+    dtype: DType,
+    device: Device,
+    // Cache for the materialized value, this ensures that realizing a node twice does not
+    // trigger a second evaluation.
+    realized: OnceLock<Tensor>,
+}
+
+/// A tensor whose value is described by a graph of elementwise operations that have not been
+/// evaluated yet.
+///
+/// `LazyTensor` values are reference counted so cloning them is cheap, a node that is used
+/// multiple times in a graph is only evaluated once.
+#[derive(Clone)]
+pub struct LazyTensor(Arc<LazyNode>);
+
+impl std::fmt::Debug for LazyTensor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "LazyTensor[{:?}, {:?}, {:?}]",
+            self.dims(),
+            self.dtype(),
+            self.device().location()
+        )
+    }
+}
+
+macro_rules! unary_op {
+    ($fn_name:ident, $op_name:ident) => {
+        pub fn $fn_name(&self) -> Result<Self> {
+            self.unary(UnaryOp::$op_name, stringify!($fn_name))
+        }
+    };
+}
+
+macro_rules! binary_op {
+    ($fn_name:ident, $op_name:ident) => {
+        pub fn $fn_name(&self, rhs: &Self) -> Result<Self> {
+            self.binary(rhs, BinaryOp::$op_name, stringify!($fn_name))
+        }
+    };
+}
+
+impl LazyTensor {
+    fn from_op(op: LazyOp, shape: Shape, dtype: DType, device: Device) -> Self {
+        Self(Arc::new(LazyNode {
+            op,
+            shape,
+            dtype,
+            device,
+            realized: OnceLock::new(),
+        }))
+    }
+
+    /// Creates a lazy tensor holding the value of an already materialized tensor.
+    pub fn new(t: &Tensor) -> Self {
+        Self::from_op(
+            LazyOp::Input(t.clone()),
+            t.shape().clone(),
+            t.dtype(),
+            t.device().clone(),
+        )
+    }
+
+    /// The shape of the tensor that would be returned by `realize`.
+    pub fn shape(&self) -> &Shape {
+        &self.0.shape
+    }
+
+    /// The dimensions of the tensor that would be returned by `realize`.
+    pub fn dims(&self) -> &[usize] {
+        self.0.shape.dims()
+    }
+
+    pub fn dtype(&self) -> DType {
+        self.0.dtype
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.0.device
+    }
+
+    /// Returns true if the value of this node has already been computed.
+    pub fn is_realized(&self) -> bool {
+        matches!(self.0.op, LazyOp::Input(_)) || self.0.realized.get().is_some()
+    }
+
+    fn unary(&self, op: UnaryOp, name: &'static str) -> Result<Self> {
+        if self.dtype().is_int() {
+            Err(Error::UnsupportedDTypeForOp(self.dtype(), name).bt())?
+        }
+        Ok(Self::from_op(
+            LazyOp::Unary(self.clone(), op),
+            self.shape().clone(),
+            self.dtype(),
+            self.device().clone(),
+        ))
+    }
+
+    fn binary(&self, rhs: &Self, op: BinaryOp, name: &'static str) -> Result<Self> {
+        if self.dtype() != rhs.dtype() {
+            Err(Error::DTypeMismatchBinaryOp {
+                lhs: self.dtype(),
+                rhs: rhs.dtype(),
+                op: name,
+            }
+            .bt())?
+        }
+        if self.device().location() != rhs.device().location() {
+            Err(Error::DeviceMismatchBinaryOp {
+                lhs: self.device().location(),
+                rhs: rhs.device().location(),
+                op: name,
+            }
+            .bt())?
+        }
+        // Broadcasting is applied implicitly, this is similar to `Tensor::broadcast_add` etc.
+        let shape = self.shape().broadcast_shape_binary_op(rhs.shape(), name)?;
+        Ok(Self::from_op(
+            LazyOp::Binary(self.clone(), rhs.clone(), op),
+            shape,
+            self.dtype(),
+            self.device().clone(),
+        ))
+    }
+
+    unary_op!(recip, Recip);
+    unary_op!(neg, Neg);
+    unary_op!(exp, Exp);
+    unary_op!(log, Log);
+    unary_op!(sin, Sin);
+    unary_op!(cos, Cos);
+    unary_op!(tanh, Tanh);
+    unary_op!(abs, Abs);
+    unary_op!(sqr, Sqr);
+    unary_op!(sqrt, Sqrt);
+    unary_op!(gelu, Gelu);
+    unary_op!(gelu_erf, GeluErf);
+    unary_op!(erf, Erf);
+    unary_op!(relu, Relu);
+    unary_op!(silu, Silu);
+    unary_op!(ceil, Ceil);
+    unary_op!(floor, Floor);
+    unary_op!(round, Round);
+
+    // Compared to `Tensor`, the binary operations on lazy tensors always broadcast their
+    // arguments.
+    binary_op!(add, Add);
+    binary_op!(mul, Mul);
+    binary_op!(sub, Sub);
+    binary_op!(div, Div);
+    binary_op!(maximum, Maximum);
+    binary_op!(minimum, Minimum);
+
+    /// Multiplies the input by `mul` then adds `add`, see `Tensor::affine`.
+    pub fn affine(&self, mul: f64, add: f64) -> Result<Self> {
+        Ok(Self::from_op(
+            LazyOp::Affine {
+                arg: self.clone(),
+                mul,
+                add,
+            },
+            self.shape().clone(),
+            self.dtype(),
+            self.device().clone(),
+        ))
+    }
+
+    fn node_id(&self) -> *const LazyNode {
+        Arc::as_ptr(&self.0)
+    }
+
+    fn track_op(&self) -> bool {
+        if self.0.realized.get().is_some() {
+            return false;
+        }
+        match &self.0.op {
+            LazyOp::Input(t) => t.track_op(),
+            LazyOp::Unary(arg, _) | LazyOp::Affine { arg, .. } => arg.track_op(),
+            LazyOp::Binary(lhs, rhs, _) => lhs.track_op() || rhs.track_op(),
+        }
+    }
+
+    /// Returns true if `realize` evaluates this graph with a single fused kernel. This is the
+    /// case on cpu, cuda and metal when none of the inputs track gradients.
+    pub fn is_fusable(&self) -> bool {
+        !self.device().is_wgpu() && !self.track_op()
+    }
+
+    /// Evaluates the recorded operations and returns the resulting tensor. The result is cached
+    /// so calling this multiple times only runs the computation once.
+    ///
+    /// The operations are fused only when [`Self::is_fusable`] returns true, otherwise they are
+    /// replayed eagerly.
+    pub fn realize(&self) -> Result<Tensor> {
+        if let LazyOp::Input(t) = &self.0.op {
+            return Ok(t.clone());
+        }
+        if let Some(t) = self.0.realized.get() {
+            return Ok(t.clone());
+        }
+        // Gradient tracking is not supported by the fused kernels, so we use the eager path
+        // in this case to get the proper backprop graph.
+        let t = if self.is_fusable() {
+            Program::compile(self).run_on(self.device())?
+        } else {
+            self.realize_eager()?
+        };
+        let _ = self.0.realized.set(t.clone());
+        Ok(t)
+    }
+
+    /// Evaluates the recorded operations one by one using the eager tensor api. This is mostly
+    /// useful to compare with the fused evaluation.
+    pub fn realize_eager(&self) -> Result<Tensor> {
+        fn walk(node: &LazyTensor, cache: &mut HashMap<*const LazyNode, Tensor>) -> Result<Tensor> {
+            if let Some(t) = node.0.realized.get() {
+                return Ok(t.clone());
+            }
+            if let Some(t) = cache.get(&node.node_id()) {
+                return Ok(t.clone());
+            }
+            let t = match &node.0.op {
+                LazyOp::Input(t) => t.clone(),
+                LazyOp::Affine { arg, mul, add } => walk(arg, cache)?.affine(*mul, *add)?,
+                LazyOp::Unary(arg, op) => {
+                    let arg = walk(arg, cache)?;
+                    match op {
+                        UnaryOp::Exp => arg.exp()?,
+                        UnaryOp::Log => arg.log()?,
+                        UnaryOp::Sin => arg.sin()?,
+                        UnaryOp::Cos => arg.cos()?,
+                        UnaryOp::Abs => arg.abs()?,
+                        UnaryOp::Neg => arg.neg()?,
+                        UnaryOp::Recip => arg.recip()?,
+                        UnaryOp::Sqr => arg.sqr()?,
+                        UnaryOp::Sqrt => arg.sqrt()?,
+                        UnaryOp::Gelu => arg.gelu()?,
+                        UnaryOp::GeluErf => arg.gelu_erf()?,
+                        UnaryOp::Erf => arg.erf()?,
+                        UnaryOp::Relu => arg.relu()?,
+                        UnaryOp::Silu => arg.silu()?,
+                        UnaryOp::Tanh => arg.tanh()?,
+                        UnaryOp::Floor => arg.floor()?,
+                        UnaryOp::Ceil => arg.ceil()?,
+                        UnaryOp::Round => arg.round()?,
+                    }
+                }
+                LazyOp::Binary(lhs, rhs, op) => {
+                    let lhs = walk(lhs, cache)?;
+                    let rhs = walk(rhs, cache)?;
+                    match op {
+                        BinaryOp::Add => lhs.broadcast_add(&rhs)?,
+                        BinaryOp::Mul => lhs.broadcast_mul(&rhs)?,
+                        BinaryOp::Sub => lhs.broadcast_sub(&rhs)?,
+                        BinaryOp::Div => lhs.broadcast_div(&rhs)?,
+                        BinaryOp::Maximum => lhs.broadcast_maximum(&rhs)?,
+                        BinaryOp::Minimum => lhs.broadcast_minimum(&rhs)?,
+                    }
+                }
+            };
+            cache.insert(node.node_id(), t.clone());
+            Ok(t)
+        }
+        walk(self, &mut HashMap::new())
+    }
+
+    /// The number of operations that would be fused in a single kernel when realizing this
+    /// tensor, nodes that have already been realized are not counted. This is zero when the
+    /// graph is not fusable, e.g. on wgpu.
+    pub fn fused_op_count(&self) -> usize {
+        if !self.is_fusable() {
+            return 0;
+        }
+        Program::compile(self)
+            .instrs
+            .iter()
+            .filter(|i| !matches!(i, Instr::Load { .. }))
+            .count()
+    }
+}
+
+impl From<&Tensor> for LazyTensor {
+    fn from(t: &Tensor) -> Self {
+        Self::new(t)
+    }
+}
+
+impl From<Tensor> for LazyTensor {
+    fn from(t: Tensor) -> Self {
+        Self::new(&t)
+    }
+}
+
+impl Tensor {
+    /// Returns a lazy version of this tensor, operations on the result are only recorded and
+    /// get evaluated when calling `realize`, in a single fused kernel.
+    pub fn lazy(&self) -> LazyTensor {
+        LazyTensor::new(self)
+    }
+}
+
+// A fused kernel is compiled into a list of instructions operating on registers, each register
+// holding a block of values.
+#[derive(Debug, Clone, Copy)]
+enum Instr {
+    Load {
+        input: usize,
+        dst: usize,
+    },
+    Unary {
+        op: UnaryOp,
+        src: usize,
+        dst: usize,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: usize,
+        rhs: usize,
+        dst: usize,
+    },
+    Affine {
+        mul: f64,
+        add: f64,
+        src: usize,
+        dst: usize,
+    },
+}
+
+struct Program {
+    inputs: Vec<Tensor>,
+    instrs: Vec<Instr>,
+    out_reg: usize,
+    shape: Shape,
+    dtype: DType,
+}
+
+impl Program {
+    fn compile(node: &LazyTensor) -> Self {
+        fn walk(
+            node: &LazyTensor,
+            regs: &mut HashMap<*const LazyNode, usize>,
+            inputs: &mut Vec<Tensor>,
+            instrs: &mut Vec<Instr>,
+        ) -> usize {
+            if let Some(&reg) = regs.get(&node.node_id()) {
+                return reg;
+            }
+            let instr = match (node.0.realized.get(), &node.0.op) {
+                (Some(t), _) | (None, LazyOp::Input(t)) => {
+                    let input = match inputs.iter().position(|v| v.id() == t.id()) {
+                        Some(input) => input,
+                        None => {
+                            inputs.push(t.clone());
+                            inputs.len() - 1
+                        }
+                    };
+                    Instr::Load { input, dst: 0 }
+                }
+                (None, LazyOp::Unary(arg, op)) => {
+                    let src = walk(arg, regs, inputs, instrs);
+                    Instr::Unary {
+                        op: *op,
+                        src,
+                        dst: 0,
+                    }
+                }
+                (None, LazyOp::Affine { arg, mul, add }) => {
+                    let src = walk(arg, regs, inputs, instrs);
+                    Instr::Affine {
+                        mul: *mul,
+                        add: *add,
+                        src,
+                        dst: 0,
+                    }
+                }
+                (None, LazyOp::Binary(lhs, rhs, op)) => {
+                    let lhs = walk(lhs, regs, inputs, instrs);
+                    let rhs = walk(rhs, regs, inputs, instrs);
+                    Instr::Binary {
+                        op: *op,
+                        lhs,
+                        rhs,
+                        dst: 0,
+                    }
+                }
+            };
+            // Each node gets its own register, this could be optimized by reusing registers
+            // once their last use has been reached.
+            let dst = instrs.len();
+            let instr = match instr {
+                Instr::Load { input, .. } => Instr::Load { input, dst },
+                Instr::Unary { op, src, .. } => Instr::Unary { op, src, dst },
+                Instr::Affine { mul, add, src, .. } => Instr::Affine { mul, add, src, dst },
+                Instr::Binary { op, lhs, rhs, .. } => Instr::Binary { op, lhs, rhs, dst },
+            };
+            instrs.push(instr);
+            regs.insert(node.node_id(), dst);
+            dst
+        }
+        let mut inputs = vec![];
+        let mut instrs = vec![];
+        let out_reg = walk(node, &mut HashMap::new(), &mut inputs, &mut instrs);
+        Self {
+            inputs,
+            instrs,
+            out_reg,
+            shape: node.shape().clone(),
+            dtype: node.dtype(),
+        }
+    }
+
+    fn run_on(&self, device: &Device) -> Result<Tensor> {
+        match device {
+            Device::Cpu => self.run_cpu(),
+            #[cfg(feature = "cuda")]
+            Device::Cuda(dev) => cuda::run(self, dev),
+            #[cfg(feature = "metal")]
+            Device::Metal(dev) => metal::run(self, dev),
+            _ => crate::bail!("fused kernels are not available on {:?}", device.location()),
+        }
+    }
+
+    fn run_cpu(&self) -> Result<Tensor> {
+        let storage = match self.dtype {
+            DType::U8 => CpuStorage::U8(self.run::<u8>()?.into()),
+            DType::U32 => CpuStorage::U32(self.run::<u32>()?.into()),
+            DType::I64 => CpuStorage::I64(self.run::<i64>()?.into()),
+            DType::BF16 => CpuStorage::BF16(self.run::<half::bf16>()?.into()),
+            DType::F16 => CpuStorage::F16(self.run::<half::f16>()?.into()),
+            DType::F32 => CpuStorage::F32(self.run::<f32>()?.into()),
+            DType::F64 => CpuStorage::F64(self.run::<f64>()?.into()),
+        };
+        Ok(crate::tensor::from_storage(
+            Storage::Cpu(storage),
+            self.shape.clone(),
+            BackpropOp::none(),
+            false,
+        ))
+    }
+
+    fn run<T: FusedElem>(&self) -> Result<Vec<T>> {
+        let storages = self.inputs.iter().map(|t| t.storage()).collect::<Vec<_>>();
+        let layouts = self
+            .inputs
+            .iter()
+            .map(|t| t.layout().broadcast_as(&self.shape))
+            .collect::<Result<Vec<Layout>>>()?;
+        let mut srcs = Vec::with_capacity(storages.len());
+        for storage in storages.iter() {
+            match &**storage {
+                Storage::Cpu(storage) => srcs.push(T::cpu_storage_as_slice(storage)?),
+                _ => crate::bail!("fused kernels on cpu require all the inputs to be on cpu"),
+            }
+        }
+        let mut src_indexes = layouts
+            .iter()
+            .map(|l| {
+                if l.is_contiguous() {
+                    None
+                } else {
+                    Some(l.strided_index())
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let elem_count = self.shape.elem_count();
+        let mut dst = Vec::with_capacity(elem_count);
+        let mut regs = vec![vec![T::zero(); BLOCK_SIZE]; self.instrs.len()];
+        let mut offset = 0;
+        while offset < elem_count {
+            let len = usize::min(BLOCK_SIZE, elem_count - offset);
+            for instr in self.instrs.iter() {
+                match *instr {
+                    Instr::Load { input, dst } => {
+                        let dst = &mut regs[dst][..len];
+                        let src = srcs[input];
+                        match &mut src_indexes[input] {
+                            None => {
+                                let start = layouts[input].start_offset() + offset;
+                                dst.copy_from_slice(&src[start..start + len])
+                            }
+                            Some(index) => {
+                                for (d, i) in dst.iter_mut().zip(index) {
+                                    *d = src[i]
+                                }
+                            }
+                        }
+                    }
+                    Instr::Unary { op, src, dst } => {
+                        let mut d = std::mem::take(&mut regs[dst]);
+                        unary_block(op, &regs[src][..len], &mut d[..len]);
+                        regs[dst] = d;
+                    }
+                    Instr::Binary { op, lhs, rhs, dst } => {
+                        let mut d = std::mem::take(&mut regs[dst]);
+                        binary_block(op, &regs[lhs][..len], &regs[rhs][..len], &mut d[..len]);
+                        regs[dst] = d;
+                    }
+                    Instr::Affine { mul, add, src, dst } => {
+                        let mut d = std::mem::take(&mut regs[dst]);
+                        let (mul, add) = (T::from_f64(mul), T::from_f64(add));
+                        for (d, &s) in d[..len].iter_mut().zip(regs[src][..len].iter()) {
+                            *d = s * mul + add
+                        }
+                        regs[dst] = d;
+                    }
+                }
+            }
+            dst.extend_from_slice(&regs[self.out_reg][..len]);
+            offset += len;
+        }
+        Ok(dst)
+    }
+}
+
+// The fused kernels for cuda and metal are generated from the program instructions and compiled
+// at runtime. Each thread computes a single output element, the intermediary values are held in
+// local variables using a float accumulator for the half precision dtypes. These values get
+// rounded to the storage dtype after each instruction so that the results match the eager ops.
+#[cfg(any(feature = "cuda", feature = "metal", test))]
+#[cfg_attr(not(all(feature = "cuda", feature = "metal")), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Cuda,
+    Metal,
+}
+
+#[cfg(any(feature = "cuda", feature = "metal", test))]
+const CUDA_PRELUDE: &str = r#"
+__device__ __forceinline__ float f16_to_f32(unsigned short h) {
+  float f;
+  asm("cvt.f32.f16 %0, %1;" : "=f"(f) : "h"(h));
+  return f;
+}
+
+__device__ __forceinline__ unsigned short f32_to_f16(float f) {
+  unsigned short h;
+  asm("cvt.rn.f16.f32 %0, %1;" : "=h"(h) : "f"(f));
+  return h;
+}
+
+__device__ __forceinline__ float bf16_to_f32(unsigned short h) {
+  return __uint_as_float(((unsigned int)h) << 16);
+}
+
+__device__ __forceinline__ unsigned short f32_to_bf16(float f) {
+  unsigned int u = __float_as_uint(f);
+  if ((u & 0x7fffffff) > 0x7f800000) {
+    return (unsigned short)((u >> 16) | 0x40);
+  }
+  u += 0x7fff + ((u >> 16) & 1);
+  return (unsigned short)(u >> 16);
+}
+
+__device__ __forceinline__ long long strided_index(
+    unsigned long long i,
+    const long long num_dims,
+    const long long *dims,
+    const long long *strides
+) {
+  long long idx = 0;
+  for (long long d = num_dims - 1; d >= 0; d--) {
+    const unsigned long long dim = dims[d];
+    idx += (long long)(i % dim) * strides[d];
+    i /= dim;
+  }
+  return idx;
+}
+"#;
+
+#[cfg(any(feature = "cuda", feature = "metal", test))]
+const METAL_PRELUDE: &str = r#"
+#include <metal_stdlib>
+using namespace metal;
+
+METAL_FUNC float erf_(float x) {
+  // A&S formula 7.1.26, as used by the eager erf kernel.
+  const float a1 = 0.254829592;
+  const float a2 = -0.284496736;
+  const float a3 = 1.421413741;
+  const float a4 = -1.453152027;
+  const float a5 = 1.061405429;
+  const float p = 0.3275911;
+  const float sign = x < 0 ? -1.0 : 1.0;
+  x = fabs(x);
+  const float t = 1.0 / (1.0 + p * x);
+  const float y = 1.0 - (((((a5 * t + a4) * t) + a3) * t + a2) * t + a1) * t * exp(-x * x);
+  return sign * y;
+}
+
+METAL_FUNC float gelu_(float x) {
+  if (x > 5) {
+    return x;
+  }
+  const float alpha = x + 0.044715f * x * x * x;
+  return 0.5f * x * (1.0f + tanh(M_2_SQRTPI_F * M_SQRT1_2_F * alpha));
+}
+
+METAL_FUNC long strided_index(
+    ulong i,
+    const long num_dims,
+    constant long *dims,
+    constant long *strides
+) {
+  long idx = 0;
+  for (long d = num_dims - 1; d >= 0; d--) {
+    const ulong dim = dims[d];
+    idx += long(i % dim) * strides[d];
+    i /= dim;
+  }
+  return idx;
+}
+"#;
+
+#[cfg(any(feature = "cuda", feature = "metal", test))]
+impl Program {
+    // The storage and accumulator types for the elements of `dtype`.
+    fn kernel_types(&self, target: Target) -> Result<(&'static str, &'static str)> {
+        let types = match (target, self.dtype) {
+            (Target::Cuda, DType::U8) => ("unsigned char", "unsigned char"),
+            (Target::Cuda, DType::U32) => ("unsigned int", "unsigned int"),
+            (Target::Cuda, DType::I64) => ("long long", "long long"),
+            (Target::Cuda, DType::BF16 | DType::F16) => ("unsigned short", "float"),
+            (Target::Cuda, DType::F32) => ("float", "float"),
+            (Target::Cuda, DType::F64) => ("double", "double"),
+            (Target::Metal, DType::U8) => ("uchar", "uchar"),
+            (Target::Metal, DType::U32) => ("uint", "uint"),
+            (Target::Metal, DType::I64) => ("long", "long"),
+            (Target::Metal, DType::BF16) => ("bfloat", "float"),
+            (Target::Metal, DType::F16) => ("half", "float"),
+            (Target::Metal, DType::F32) => ("float", "float"),
+            (Target::Metal, DType::F64) => crate::bail!("fused metal kernels do not support f64"),
+        };
+        Ok(types)
+    }
+
+    // Converts `v` from the storage type to the accumulator type, or back when `store` is set.
+    fn convert(&self, target: Target, v: &str, store: bool) -> String {
+        match (target, self.dtype, store) {
+            (Target::Cuda, DType::F16, false) => format!("f16_to_f32({v})"),
+            (Target::Cuda, DType::F16, true) => format!("f32_to_f16({v})"),
+            (Target::Cuda, DType::BF16, false) => format!("bf16_to_f32({v})"),
+            (Target::Cuda, DType::BF16, true) => format!("f32_to_bf16({v})"),
+            (Target::Metal, DType::F16 | DType::BF16, false) => format!("float({v})"),
+            (Target::Metal, DType::F16, true) => format!("half({v})"),
+            (Target::Metal, DType::BF16, true) => format!("bfloat({v})"),
+            _ => v.to_string(),
+        }
+    }
+
+    // Rounds an accumulator value to the precision of the storage dtype.
+    fn round_to_dtype(&self, target: Target, v: String) -> String {
+        match self.dtype {
+            DType::F16 | DType::BF16 => {
+                self.convert(target, &self.convert(target, &v, true), false)
+            }
+            _ => v,
+        }
+    }
+
+    fn literal(&self, target: Target, v: f64) -> String {
+        match (self.dtype, target) {
+            (DType::U8, Target::Cuda) => format!("((unsigned char){})", v as u8),
+            (DType::U8, Target::Metal) => format!("((uchar){})", v as u8),
+            (DType::U32, _) => format!("{}u", v as u32),
+            (DType::I64, Target::Cuda) => format!("((long long){})", v as i64),
+            (DType::I64, Target::Metal) => format!("((long){})", v as i64),
+            _ => self.float_literal(v),
+        }
+    }
+
+    fn float_literal(&self, v: f64) -> String {
+        match self.dtype {
+            DType::F64 if v.is_finite() => format!("{v:?}"),
+            DType::F64 if v.is_nan() => "(0.0 / 0.0)".to_string(),
+            DType::F64 if v > 0. => "(1.0 / 0.0)".to_string(),
+            DType::F64 => "(-1.0 / 0.0)".to_string(),
+            _ if v.is_finite() => format!("{:?}f", v as f32),
+            _ if v.is_nan() => "(0.0f / 0.0f)".to_string(),
+            _ if v > 0. => "(1.0f / 0.0f)".to_string(),
+            _ => "(-1.0f / 0.0f)".to_string(),
+        }
+    }
+
+    fn unary_expr(&self, target: Target, op: UnaryOp, x: &str) -> Result<String> {
+        // Unary ops are only recorded for float dtypes.
+        if !self.dtype.is_float() {
+            crate::bail!("fused kernels do not support {op:?} for {:?}", self.dtype)
+        }
+        // Single precision functions have a `f` suffix in cuda, metal uses overloads.
+        let f = |name: &str| match (target, self.dtype) {
+            (Target::Cuda, DType::F64) | (Target::Metal, _) => name.to_string(),
+            (Target::Cuda, _) => format!("{name}f"),
+        };
+        let l = |v: f64| self.literal(target, v);
+        let expr = match (target, op) {
+            (_, UnaryOp::Exp) => format!("{}({x})", f("exp")),
+            (_, UnaryOp::Log) => format!("{}({x})", f("log")),
+            (_, UnaryOp::Sin) => format!("{}({x})", f("sin")),
+            (_, UnaryOp::Cos) => format!("{}({x})", f("cos")),
+            (_, UnaryOp::Abs) => format!("{}({x})", f("fabs")),
+            (_, UnaryOp::Neg) => format!("-{x}"),
+            (_, UnaryOp::Recip) => format!("{} / {x}", l(1.)),
+            (_, UnaryOp::Sqr) => format!("{x} * {x}"),
+            (_, UnaryOp::Sqrt) => format!("{}({x})", f("sqrt")),
+            (_, UnaryOp::Tanh) => format!("{}({x})", f("tanh")),
+            (_, UnaryOp::Floor) => format!("{}({x})", f("floor")),
+            (_, UnaryOp::Ceil) => format!("{}({x})", f("ceil")),
+            (_, UnaryOp::Round) => format!("{}({x})", f("round")),
+            (_, UnaryOp::Relu) => format!("{}({x}, {})", f("fmax"), l(0.)),
+            (_, UnaryOp::Silu) => format!("{x} / ({} + {}(-{x}))", l(1.), f("exp")),
+            (Target::Cuda, UnaryOp::Erf) => format!("{}({x})", f("erf")),
+            (Target::Cuda, UnaryOp::GeluErf) => format!("{x} * {}({x})", f("normcdf")),
+            (Target::Cuda, UnaryOp::Gelu) => format!(
+                "{} * {x} * ({} + {}({} * ({x} + {} * {x} * {x} * {x})))",
+                l(0.5),
+                l(1.),
+                f("tanh"),
+                l(0.7978845608028654),
+                l(0.044715),
+            ),
+            (Target::Metal, UnaryOp::Erf) => format!("erf_({x})"),
+            (Target::Metal, UnaryOp::GeluErf) => {
+                format!("{x} * (1.0f + erf_({x} * M_SQRT1_2_F)) / 2.0f")
+            }
+            (Target::Metal, UnaryOp::Gelu) => format!("gelu_({x})"),
+        };
+        Ok(expr)
+    }
+
+    fn binary_expr(&self, target: Target, op: BinaryOp, lhs: &str, rhs: &str) -> String {
+        let (max, min) = match (target, self.dtype) {
+            (Target::Cuda, DType::F32 | DType::F16 | DType::BF16) => (Some("fmaxf"), Some("fminf")),
+            (Target::Cuda, DType::F64) => (Some("fmax"), Some("fmin")),
+            (Target::Cuda, _) => (None, None),
+            (Target::Metal, _) => (Some("max"), Some("min")),
+        };
+        match (op, max, min) {
+            (BinaryOp::Add, _, _) => format!("{lhs} + {rhs}"),
+            (BinaryOp::Sub, _, _) => format!("{lhs} - {rhs}"),
+            (BinaryOp::Mul, _, _) => format!("{lhs} * {rhs}"),
+            (BinaryOp::Div, _, _) => format!("{lhs} / {rhs}"),
+            (BinaryOp::Maximum, Some(max), _) => format!("{max}({lhs}, {rhs})"),
+            (BinaryOp::Minimum, _, Some(min)) => format!("{min}({lhs}, {rhs})"),
+            (BinaryOp::Maximum, None, _) => format!("({lhs} < {rhs} ? {rhs} : {lhs})"),
+            (BinaryOp::Minimum, _, None) => format!("({lhs} < {rhs} ? {lhs} : {rhs})"),
+        }
+    }
+
+    // Returns the name and the source of the kernel. `contiguous[i]` indicates that input `i`
+    // can be indexed with the output index, other inputs go through the strides stored in the
+    // info array: the number of dimensions, the dimensions and the strides of each input.
+    fn kernel_source(&self, target: Target, contiguous: &[bool]) -> Result<(String, String)> {
+        use std::fmt::Write;
+        let (ty, acc) = self.kernel_types(target)?;
+        let mut body = String::new();
+        for instr in self.instrs.iter() {
+            let (dst, expr) = match *instr {
+                Instr::Load { input, dst } => {
+                    let index = if contiguous[input] {
+                        "i".to_string()
+                    } else {
+                        format!(
+                            "strided_index(i, num_dims, dims, dims + num_dims * {})",
+                            input + 1
+                        )
+                    };
+                    let v = match target {
+                        Target::Cuda => format!("((const {ty} *)inputs[{input}])[{index}]"),
+                        Target::Metal => format!("in{input}[{index}]"),
+                    };
+                    (dst, self.convert(target, &v, false))
+                }
+                Instr::Unary { op, src, dst } => {
+                    let expr = self.unary_expr(target, op, &format!("r{src}"))?;
+                    (dst, self.round_to_dtype(target, expr))
+                }
+                Instr::Binary { op, lhs, rhs, dst } => {
+                    let expr = self.binary_expr(target, op, &format!("r{lhs}"), &format!("r{rhs}"));
+                    (dst, self.round_to_dtype(target, expr))
+                }
+                Instr::Affine { mul, add, src, dst } => {
+                    let expr = format!("r{src} * {}", self.literal(target, mul));
+                    let expr = format!(
+                        "{} + {}",
+                        self.round_to_dtype(target, expr),
+                        self.literal(target, add)
+                    );
+                    (dst, self.round_to_dtype(target, expr))
+                }
+            };
+            writeln!(body, "  const {acc} r{dst} = {expr};").unwrap();
+        }
+        let store = self.convert(target, &format!("r{}", self.out_reg), true);
+        writeln!(body, "  out[i] = {store};").unwrap();
+
+        let mut args = String::new();
+        let (prelude, header) = match target {
+            Target::Cuda => {
+                args.push_str("    const unsigned long long numel,\n");
+                args.push_str("    const long long *info,\n");
+                args.push_str("    const unsigned long long *inputs,\n");
+                writeln!(args, "    {ty} *out").unwrap();
+                let header = r#"
+  const unsigned long long i = (unsigned long long)blockIdx.x * blockDim.x + threadIdx.x;
+  if (i >= numel) {
+    return;
+  }
+  const long long num_dims = info[0];
+  const long long *dims = info + 1;
+"#;
+                (CUDA_PRELUDE, header)
+            }
+            Target::Metal => {
+                args.push_str("    constant size_t &numel,\n");
+                args.push_str("    constant long *info,\n");
+                for input in 0..self.inputs.len() {
+                    writeln!(args, "    device const {ty} *in{input},").unwrap();
+                }
+                writeln!(args, "    device {ty} *out,").unwrap();
+                args.push_str("    uint i [[ thread_position_in_grid ]]\n");
+                let header = r#"
+  if (i >= numel) {
+    return;
+  }
+  const long num_dims = info[0];
+  constant long *dims = info + 1;
+"#;
+                (METAL_PRELUDE, header)
+            }
+        };
+        // The kernels are cached by name so the name is derived from a hash of the source.
+        let kernel = format!("(\n{args}) {{{header}{body}}}\n");
+        let name = {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            (target == Target::Cuda).hash(&mut hasher);
+            kernel.hash(&mut hasher);
+            format!("fused_{:016x}", hasher.finish())
+        };
+        let src = match target {
+            Target::Cuda => format!("{prelude}\nextern \"C\" __global__ void {name}{kernel}"),
+            Target::Metal => format!("{prelude}\nkernel void {name}{kernel}"),
+        };
+        Ok((name, src))
+    }
+
+    // The broadcast layouts of the inputs together with the info array used by the generated
+    // kernels.
+    fn kernel_info(&self) -> Result<(Vec<Layout>, Vec<i64>)> {
+        let layouts = self
+            .inputs
+            .iter()
+            .map(|t| t.layout().broadcast_as(&self.shape))
+            .collect::<Result<Vec<Layout>>>()?;
+        let dims = self.shape.dims();
+        let mut info = Vec::with_capacity(1 + dims.len() * (layouts.len() + 1));
+        info.push(dims.len() as i64);
+        info.extend(dims.iter().map(|&d| d as i64));
+        for layout in layouts.iter() {
+            info.extend(layout.stride().iter().map(|&s| s as i64));
+        }
+        Ok((layouts, info))
+    }
+}
+
+// Gives access to the per-dtype functions of the unary and binary ops in a generic way.
+trait FusedElem: WithDType {
+    fn unary<O: UnaryOpT>(v: Self) -> Self;
+    fn binary<O: BinaryOpT>(v1: Self, v2: Self) -> Self;
+}
+
+macro_rules! fused_elem {
+    ($ty:ty, $fn_name:ident) => {
+        impl FusedElem for $ty {
+            #[inline(always)]
+            fn unary<O: UnaryOpT>(v: Self) -> Self {
+                O::$fn_name(v)
+            }
+            #[inline(always)]
+            fn binary<O: BinaryOpT>(v1: Self, v2: Self) -> Self {
+                O::$fn_name(v1, v2)
+            }
+        }
+    };
+}
+
+fused_elem!(u8, u8);
+fused_elem!(u32, u32);
+fused_elem!(i64, i64);
+fused_elem!(half::bf16, bf16);
+fused_elem!(half::f16, f16);
+fused_elem!(f32, f32);
+fused_elem!(f64, f64);
+
+fn unary_block<T: FusedElem>(op: UnaryOp, xs: &[T], ys: &mut [T]) {
+    fn apply<T: FusedElem, O: UnaryOpT>(xs: &[T], ys: &mut [T]) {
+        for (y, &x) in ys.iter_mut().zip(xs.iter()) {
+            *y = T::unary::<O>(x)
+        }
+    }
+    use crate::op as o;
+    match op {
+        UnaryOp::Exp => apply::<T, o::Exp>(xs, ys),
+        UnaryOp::Log => apply::<T, o::Log>(xs, ys),
+        UnaryOp::Sin => apply::<T, o::Sin>(xs, ys),
+        UnaryOp::Cos => apply::<T, o::Cos>(xs, ys),
+        UnaryOp::Abs => apply::<T, o::Abs>(xs, ys),
+        UnaryOp::Neg => apply::<T, o::Neg>(xs, ys),
+        UnaryOp::Recip => apply::<T, o::Recip>(xs, ys),
+        UnaryOp::Sqr => apply::<T, o::Sqr>(xs, ys),
+        UnaryOp::Sqrt => apply::<T, o::Sqrt>(xs, ys),
+        UnaryOp::Gelu => apply::<T, o::Gelu>(xs, ys),
+        UnaryOp::GeluErf => apply::<T, o::GeluErf>(xs, ys),
+        UnaryOp::Erf => apply::<T, o::Erf>(xs, ys),
+        UnaryOp::Relu => apply::<T, o::Relu>(xs, ys),
+        UnaryOp::Silu => apply::<T, o::Silu>(xs, ys),
+        UnaryOp::Tanh => apply::<T, o::Tanh>(xs, ys),
+        UnaryOp::Floor => apply::<T, o::Floor>(xs, ys),
+        UnaryOp::Ceil => apply::<T, o::Ceil>(xs, ys),
+        UnaryOp::Round => apply::<T, o::Round>(xs, ys),
+    }
+}
+
+fn binary_block<T: FusedElem>(op: BinaryOp, lhs: &[T], rhs: &[T], ys: &mut [T]) {
+    fn apply<T: FusedElem, O: BinaryOpT>(lhs: &[T], rhs: &[T], ys: &mut [T]) {
+        for ((y, &l), &r) in ys.iter_mut().zip(lhs.iter()).zip(rhs.iter()) {
+            *y = T::binary::<O>(l, r)
+        }
+    }
+    use crate::op as o;
+    match op {
+        BinaryOp::Add => apply::<T, o::Add>(lhs, rhs, ys),
+        BinaryOp::Mul => apply::<T, o::Mul>(lhs, rhs, ys),
+        BinaryOp::Sub => apply::<T, o::Sub>(lhs, rhs, ys),
+        BinaryOp::Div => apply::<T, o::Div>(lhs, rhs, ys),
+        BinaryOp::Maximum => apply::<T, o::Maximum>(lhs, rhs, ys),
+        BinaryOp::Minimum => apply::<T, o::Minimum>(lhs, rhs, ys),
+    }
+}
+
+#[cfg(feature = "cuda")]
+mod cuda {
+    use super::{Program, Target};
+    use crate::cuda_backend::cudarc::driver::{DevicePtr, LaunchAsync, LaunchConfig};
+    use crate::cuda_backend::{CudaStorageSlice as S, WrapErr};
+    use crate::op::BackpropOp;
+    use crate::{CudaDevice, CudaStorage, DType, Result, Storage, Tensor};
+
+    // The device pointer of the element at `offset`.
+    fn device_ptr(slice: &S, offset: usize) -> u64 {
+        match slice {
+            S::U8(s) => *s.slice(offset..).device_ptr(),
+            S::U32(s) => *s.slice(offset..).device_ptr(),
+            S::I64(s) => *s.slice(offset..).device_ptr(),
+            S::BF16(s) => *s.slice(offset..).device_ptr(),
+            S::F16(s) => *s.slice(offset..).device_ptr(),
+            S::F32(s) => *s.slice(offset..).device_ptr(),
+            S::F64(s) => *s.slice(offset..).device_ptr(),
+        }
+    }
+
+    pub(super) fn run(program: &Program, dev: &CudaDevice) -> Result<Tensor> {
+        let (layouts, info) = program.kernel_info()?;
+        let contiguous = layouts
+            .iter()
+            .map(|l| l.is_contiguous())
+            .collect::<Vec<_>>();
+        let storages = program
+            .inputs
+            .iter()
+            .map(|t| t.storage())
+            .collect::<Vec<_>>();
+        let mut inputs = Vec::with_capacity(storages.len());
+        for (storage, layout) in storages.iter().zip(layouts.iter()) {
+            match &**storage {
+                Storage::Cuda(s) => inputs.push(device_ptr(&s.slice, layout.start_offset())),
+                _ => crate::bail!("fused kernels on cuda require all the inputs to be on cuda"),
+            }
+        }
+        let numel = program.shape.elem_count();
+        // SAFETY: Set later by running the fused kernel.
+        let slice = match program.dtype {
+            DType::U8 => S::U8(unsafe { dev.alloc::<u8>(numel) }.w()?),
+            DType::U32 => S::U32(unsafe { dev.alloc::<u32>(numel) }.w()?),
+            DType::I64 => S::I64(unsafe { dev.alloc::<i64>(numel) }.w()?),
+            DType::BF16 => S::BF16(unsafe { dev.alloc::<half::bf16>(numel) }.w()?),
+            DType::F16 => S::F16(unsafe { dev.alloc::<half::f16>(numel) }.w()?),
+            DType::F32 => S::F32(unsafe { dev.alloc::<f32>(numel) }.w()?),
+            DType::F64 => S::F64(unsafe { dev.alloc::<f64>(numel) }.w()?),
+        };
+        if numel > 0 {
+            let (name, src) = program.kernel_source(Target::Cuda, &contiguous)?;
+            let func = dev.get_or_compile_func(&name, &src)?;
+            let info = dev.htod_copy(info).w()?;
+            let inputs = dev.htod_copy(inputs).w()?;
+            let out = device_ptr(&slice, 0);
+            let cfg = LaunchConfig::for_num_elems(numel as u32);
+            let params = (numel, &info, &inputs, out);
+            unsafe { func.launch(cfg, params) }.w()?;
+        }
+        let storage = CudaStorage {
+            slice,
+            device: dev.clone(),
+        };
+        Ok(crate::tensor::from_storage(
+            Storage::Cuda(storage),
+            program.shape.clone(),
+            BackpropOp::none(),
+            false,
+        ))
+    }
+}
+
+#[cfg(feature = "metal")]
+mod metal {
+    use super::{Program, Target};
+    use crate::op::BackpropOp;
+    use crate::{MetalDevice, MetalError, MetalStorage, Result, Storage, Tensor};
+
+    pub(super) fn run(program: &Program, dev: &MetalDevice) -> Result<Tensor> {
+        let (layouts, info) = program.kernel_info()?;
+        let contiguous = layouts
+            .iter()
+            .map(|l| l.is_contiguous())
+            .collect::<Vec<_>>();
+        let storages = program
+            .inputs
+            .iter()
+            .map(|t| t.storage())
+            .collect::<Vec<_>>();
+        let dtype_size = program.dtype.size_in_bytes();
+        let mut inputs = Vec::with_capacity(storages.len());
+        for (storage, layout) in storages.iter().zip(layouts.iter()) {
+            match &**storage {
+                Storage::Metal(s) => inputs.push((s.buffer(), layout.start_offset() * dtype_size)),
+                _ => crate::bail!("fused kernels on metal require all the inputs to be on metal"),
+            }
+        }
+        let numel = program.shape.elem_count();
+        let buffer = dev.new_buffer(numel, program.dtype, "fused")?;
+        if numel > 0 {
+            let (name, src) = program.kernel_source(Target::Metal, &contiguous)?;
+            let command_buffer = dev.command_buffer()?;
+            candle_metal_kernels::call_generated_elementwise(
+                dev.metal_device(),
+                &command_buffer,
+                dev.kernels(),
+                &src,
+                &name,
+                numel,
+                &info,
+                &inputs,
+                &buffer,
+            )
+            .map_err(MetalError::from)?;
+        }
+        let storage = MetalStorage::new(buffer, dev.clone(), program.dtype);
+        Ok(crate::tensor::from_storage(
+            Storage::Metal(storage),
+            program.shape.clone(),
+            BackpropOp::none(),
+            false,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Program, Target};
+    use crate::{DType, Device, Result, Tensor};
+
+    #[test]
+    fn kernel_source() -> Result<()> {
+        let x = Tensor::new(&[[1f32, 2.], [3., 4.]], &Device::Cpu)?;
+        let b = Tensor::new(&[5f32, 6.], &Device::Cpu)?;
+        let fused = x.lazy().silu()?.add(&b.lazy())?.affine(2., 1.)?;
+        let program = Program::compile(&fused);
+        let (name, src) = program.kernel_source(Target::Cuda, &[true, false])?;
+        assert!(src.contains(&format!("extern \"C\" __global__ void {name}(")));
+        assert!(src.contains("const float r1 = r0 / (1.0f + expf(-r0));"));
+        assert!(src.contains("const float r4 = r3 * 2.0f + 1.0f;"));
+        assert!(src.contains("strided_index(i, num_dims, dims, dims + num_dims * 2)"));
+        // The name identifies the source.
+        let (name2, _) = program.kernel_source(Target::Cuda, &[true, false])?;
+        let (name3, _) = program.kernel_source(Target::Cuda, &[true, true])?;
+        assert_eq!(name, name2);
+        assert_ne!(name, name3);
+
+        let (name, src) = program.kernel_source(Target::Metal, &[true, true])?;
+        assert!(src.contains(&format!("kernel void {name}(")));
+        assert!(src.contains("device const float *in1,"));
+        assert!(src.contains("const float r1 = r0 / (1.0f + exp(-r0));"));
+
+        // Half precision values get rounded after each operation.
+        let program = Program::compile(&x.to_dtype(DType::F16)?.lazy().sqrt()?);
+        let (_, src) = program.kernel_source(Target::Cuda, &[true])?;
+        assert!(src.contains("const float r1 = f16_to_f32(f32_to_f16(sqrtf(r0)));"));
+        assert!(src.contains("out[i] = f32_to_f16(r1);"));
+        let (_, src) = program.kernel_source(Target::Metal, &[true])?;
+        assert!(src.contains("const float r1 = float(half(sqrt(r0)));"));
+
+        let program = Program::compile(&x.to_dtype(DType::F64)?.lazy().exp()?);
+        assert!(program.kernel_source(Target::Metal, &[true]).is_err());
+        Ok(())
+    }
+}
@@ -35,12 +35,14 @@
 
 #[cfg(feature = "accelerate")]
 mod accelerate;
+pub mod autograd;
 pub mod backend;
 pub mod backprop;
 mod conv;
 mod convert;
 pub mod cpu;
 pub mod cpu_backend;
+mod cpu_buffer;
 #[cfg(feature = "cuda")]
 pub mod cuda_backend;
 #[cfg(feature = "cudnn")]
@@ -51,9 +53,11 @@ mod dtype;
 mod dummy_cuda_backend;
 mod dummy_metal_backend;
 mod dummy_wgpu_backend;
+mod einsum;
 pub mod error;
 mod indexer;
 pub mod layout;
+pub mod lazy;
 #[cfg(feature = "metal")]
 pub mod metal_backend;
 #[cfg(feature = "mkl")]
@@ -62,6 +66,7 @@ pub mod npy;
 mod op;
 pub mod pickle;
 pub mod quantized;
+mod resample;
 pub mod safetensors;
 pub mod scalar;
 pub mod shape;
@@ -74,13 +79,17 @@ mod variable;
 #[cfg(feature = "wgpu")]
 pub mod wgpu_backend;
 
+pub use backprop::checkpoint;
 pub use cpu_backend::CpuStorage;
+pub use cpu_buffer::CpuBuffer;
 pub use device::{Device, DeviceLocation, NdArray};
 pub use dtype::{DType, FloatDType, IntDType, WithDType};
 pub use error::{Error, Result};
 pub use indexer::IndexOp;
 pub use layout::Layout;
+pub use lazy::LazyTensor;
 pub use op::{CustomOp1, CustomOp2, CustomOp3};
+pub use resample::{GridSampleMode, GridSamplePadding, InterpolateMode};
 pub use shape::{Shape, D};
 pub use storage::Storage;
 pub use strided_index::{StridedBlocks, StridedIndex};
@@ -128,6 +137,22 @@ impl ToUsize2 for (usize, usize) {
     }
 }
 
+pub trait ToUsize3 {
+    fn to_usize3(self) -> (usize, usize, usize);
+}
+
+impl ToUsize3 for usize {
+    fn to_usize3(self) -> (usize, usize, usize) {
+        (self, self, self)
+    }
+}
+
+impl ToUsize3 for (usize, usize, usize) {
+    fn to_usize3(self) -> (usize, usize, usize) {
+        self
+    }
+}
+
 // A simple trait defining a module with forward method using a single argument.
 pub trait Module {
     fn forward(&self, xs: &Tensor) -> Result<Tensor>;
@@ -1,5 +1,8 @@
 use crate::backend::{BackendDevice, BackendStorage};
-use crate::conv::{ParamsConv1D, ParamsConv2D, ParamsConvTranspose1D, ParamsConvTranspose2D};
+use crate::conv::{
+    ParamsConv1D, ParamsConv2D, ParamsConv3D, ParamsConvTranspose1D, ParamsConvTranspose2D,
+    ParamsConvTranspose3D,
+};
 use crate::op::{BinaryOpT, CmpOp, ReduceOp, UnaryOpT};
 use crate::{CpuStorage, DType, Layout, Result, Shape};
 use candle_metal_kernels;
@@ -326,13 +329,13 @@ impl BackendStorage for MetalStorage {
 
     fn to_cpu_storage(&self) -> Result<CpuStorage> {
         match self.dtype {
-            DType::U8 => Ok(CpuStorage::U8(self.to_cpu()?)),
-            DType::U32 => Ok(CpuStorage::U32(self.to_cpu()?)),
-            DType::I64 => Ok(CpuStorage::I64(self.to_cpu()?)),
-            DType::F16 => Ok(CpuStorage::F16(self.to_cpu()?)),
-            DType::BF16 => Ok(CpuStorage::BF16(self.to_cpu()?)),
-            DType::F32 => Ok(CpuStorage::F32(self.to_cpu()?)),
-            DType::F64 => Ok(CpuStorage::F64(self.to_cpu()?)),
+            DType::U8 => Ok(CpuStorage::U8(self.to_cpu()?.into())),
+            DType::U32 => Ok(CpuStorage::U32(self.to_cpu()?.into())),
+            DType::I64 => Ok(CpuStorage::I64(self.to_cpu()?.into())),
+            DType::F16 => Ok(CpuStorage::F16(self.to_cpu()?.into())),
+            DType::BF16 => Ok(CpuStorage::BF16(self.to_cpu()?.into())),
+            DType::F32 => Ok(CpuStorage::F32(self.to_cpu()?.into())),
+            DType::F64 => Ok(CpuStorage::F64(self.to_cpu()?.into())),
         }
     }
 
@@ -378,7 +381,7 @@ impl BackendStorage for MetalStorage {
                 name,
                 layout.dims(),
                 &self.buffer,
-                layout.stride(),
+                &layout.stride(),
                 layout.start_offset() * dtype.size_in_bytes(),
                 &buffer,
                 mul as f32,
@@ -428,7 +431,7 @@ impl BackendStorage for MetalStorage {
                 name,
                 layout.dims(),
                 &self.buffer,
-                layout.stride(),
+                &layout.stride(),
                 layout.start_offset() * dtype.size_in_bytes(),
                 &buffer,
                 pow as f32,
@@ -477,7 +480,7 @@ impl BackendStorage for MetalStorage {
                 name,
                 layout.dims(),
                 &self.buffer,
-                layout.stride(),
+                &layout.stride(),
                 layout.start_offset() * dtype.size_in_bytes(),
                 &buffer,
                 alpha as f32,
@@ -647,7 +650,7 @@ impl BackendStorage for MetalStorage {
                 kernel_name,
                 layout.dims(),
                 &self.buffer,
-                layout.stride(),
+                &layout.stride(),
                 layout.start_offset() * self.dtype.size_in_bytes(),
                 &buffer,
             )
@@ -767,7 +770,7 @@ impl BackendStorage for MetalStorage {
                 kernel_name,
                 layout.dims(),
                 &self.buffer,
-                layout.stride(),
+                &layout.stride(),
                 layout.start_offset() * self.dtype.size_in_bytes(),
                 &buffer,
                 0,
@@ -825,13 +828,13 @@ impl BackendStorage for MetalStorage {
             dims,
             &self.buffer,
             (
-                layout.stride(),
+                &layout.stride(),
                 layout.start_offset() * self.dtype.size_in_bytes(),
             ),
             &t.buffer,
-            (t_l.stride(), t_l.start_offset() * t.dtype.size_in_bytes()),
+            (&t_l.stride(), t_l.start_offset() * t.dtype.size_in_bytes()),
             &f.buffer,
-            (f_l.stride(), f_l.start_offset() * f.dtype.size_in_bytes()),
+            (&f_l.stride(), f_l.start_offset() * f.dtype.size_in_bytes()),
             &buffer,
         )
         .map_err(MetalError::from)?;
@@ -845,10 +848,16 @@ impl BackendStorage for MetalStorage {
         kernel_l: &Layout,
         params: &ParamsConv1D,
     ) -> Result<Self> {
+        if let Some((inp, layout)) = self.non_negative_strides(layout)? {
+            return inp.conv1d(&layout, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv1d(layout, &kernel, &kernel_l, params);
+        }
         let device = self.device().clone();
         let shape = layout.shape();
         let dims = shape.dims();
-        let strides = layout.stride();
+        let strides = layout.wrapping_stride();
 
         let stride = params.stride;
         let dilation = params.dilation;
@@ -862,6 +871,8 @@ impl BackendStorage for MetalStorage {
         let command_buffer = self.device.command_buffer()?;
         let name = match self.dtype {
             DType::F32 => "im2col1d_f32",
+            DType::F16 => "im2col1d_f16",
+            DType::BF16 => "im2col1d_bf16",
             dtype => crate::bail!("Metal conv1d {dtype:?} not implemented"),
         };
         candle_metal_kernels::call_im2col1d_strided(
@@ -870,7 +881,7 @@ impl BackendStorage for MetalStorage {
             &self.device.kernels,
             name,
             layout.shape().dims(),
-            strides,
+            &strides,
             (k_size, stride, padding, dilation),
             &self.buffer,
             layout.start_offset() * self.dtype.size_in_bytes(),
@@ -910,12 +921,55 @@ impl BackendStorage for MetalStorage {
 
     fn conv_transpose1d(
         &self,
-        _l: &Layout,
-        _kernel: &Self,
-        _kernel_l: &Layout,
-        _params: &ParamsConvTranspose1D,
+        layout: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &ParamsConvTranspose1D,
     ) -> Result<Self> {
-        crate::bail!("Metal conv_transpose1d not implemented")
+        if let Some((inp, layout)) = self.non_negative_strides(layout)? {
+            return inp.conv_transpose1d(&layout, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv_transpose1d(layout, &kernel, &kernel_l, params);
+        }
+        let l_out = params.l_out();
+        let dst_el = params.c_out * l_out * params.b_size;
+        let buffer = self
+            .device
+            .new_buffer(dst_el, self.dtype, "conv_transpose1d")?;
+        let name = match self.dtype {
+            DType::F32 => "conv_transpose1d_f32",
+            DType::F16 => "conv_transpose1d_f16",
+            DType::BF16 => "conv_transpose1d_bf16",
+            dtype => crate::bail!("Metal conv_transpose1d {dtype:?} not implemented"),
+        };
+        let command_buffer = self.device.command_buffer()?;
+        candle_metal_kernels::call_conv_transpose1d(
+            &self.device.device,
+            &command_buffer,
+            &self.device.kernels,
+            name,
+            l_out,
+            (
+                params.stride,
+                params.padding,
+                params.output_padding,
+                params.dilation,
+            ),
+            (layout.dims(), &layout.wrapping_stride()),
+            (kernel_l.dims(), &kernel_l.wrapping_stride()),
+            (
+                &self.buffer,
+                layout.start_offset() * self.dtype.size_in_bytes(),
+            ),
+            (
+                &kernel.buffer,
+                kernel_l.start_offset() * kernel.dtype.size_in_bytes(),
+            ),
+            &buffer,
+        )
+        .map_err(MetalError::from)?;
+        Ok(Self::new(buffer, self.device.clone(), self.dtype))
     }
 
     fn conv2d(
@@ -925,6 +979,12 @@ impl BackendStorage for MetalStorage {
         kernel_l: &Layout,
         params: &ParamsConv2D,
     ) -> Result<Self> {
+        if let Some((inp, layout)) = self.non_negative_strides(layout)? {
+            return inp.conv2d(&layout, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv2d(layout, &kernel, &kernel_l, params);
+        }
         let device = self.device().clone();
         let shape = layout.shape();
         let dims = shape.dims();
@@ -946,6 +1006,8 @@ impl BackendStorage for MetalStorage {
         let command_buffer = self.device.command_buffer()?;
         let name = match self.dtype {
             DType::F32 => "im2col_f32",
+            DType::F16 => "im2col_f16",
+            DType::BF16 => "im2col_bf16",
             dtype => crate::bail!("Metal conv2d {dtype:?} not implemented"),
         };
         candle_metal_kernels::call_im2col_strided(
@@ -954,7 +1016,7 @@ impl BackendStorage for MetalStorage {
             &self.device.kernels,
             name,
             layout.shape().dims(),
-            layout.stride(),
+            &layout.wrapping_stride(),
             (h_k, w_k, stride, padding, dilation),
             &self.buffer,
             layout.start_offset() * self.dtype.size_in_bytes(),
@@ -997,20 +1059,231 @@ impl BackendStorage for MetalStorage {
 
     fn conv_transpose2d(
         &self,
-        _l: &Layout,
-        _kernel: &Self,
-        _kernel_l: &Layout,
-        _params: &ParamsConvTranspose2D,
+        layout: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &ParamsConvTranspose2D,
     ) -> Result<Self> {
-        crate::bail!("Metal conv_tranpose2d not implemented")
+        if let Some((inp, layout)) = self.non_negative_strides(layout)? {
+            return inp.conv_transpose2d(&layout, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv_transpose2d(layout, &kernel, &kernel_l, params);
+        }
+        let (out_w, out_h) = (params.out_w(), params.out_h());
+        let dst_el = params.c_out * out_w * out_h * params.b_size;
+        let buffer = self
+            .device
+            .new_buffer(dst_el, self.dtype, "conv_transpose2d")?;
+        let name = match self.dtype {
+            DType::F32 => "conv_transpose2d_f32",
+            DType::F16 => "conv_transpose2d_f16",
+            DType::BF16 => "conv_transpose2d_bf16",
+            dtype => crate::bail!("Metal conv_transpose2d {dtype:?} not implemented"),
+        };
+        let command_buffer = self.device.command_buffer()?;
+        candle_metal_kernels::call_conv_transpose2d(
+            &self.device.device,
+            &command_buffer,
+            &self.device.kernels,
+            name,
+            (out_w, out_h),
+            (
+                params.stride,
+                params.padding,
+                params.output_padding,
+                params.dilation,
+            ),
+            (layout.dims(), &layout.wrapping_stride()),
+            (kernel_l.dims(), &kernel_l.wrapping_stride()),
+            (
+                &self.buffer,
+                layout.start_offset() * self.dtype.size_in_bytes(),
+            ),
+            (
+                &kernel.buffer,
+                kernel_l.start_offset() * kernel.dtype.size_in_bytes(),
+            ),
+            &buffer,
+        )
+        .map_err(MetalError::from)?;
+        Ok(Self::new(buffer, self.device.clone(), self.dtype))
     }
 
-    fn avg_pool2d(&self, _: &Layout, _: (usize, usize), _: (usize, usize)) -> Result<Self> {
-        crate::bail!("Metal avg_pool2d not implemented")
+    fn conv3d(
+        &self,
+        layout: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &ParamsConv3D,
+    ) -> Result<Self> {
+        if let Some((inp, layout)) = self.non_negative_strides(layout)? {
+            return inp.conv3d(&layout, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv3d(layout, &kernel, &kernel_l, params);
+        }
+        let device = self.device().clone();
+        let (d_out, h_out, w_out) = (params.out_d(), params.out_h(), params.out_w());
+        let (d_k, h_k, w_k) = (params.k_d, params.k_h, params.k_w);
+        let b = params.b_size;
+        let n = params.c_out;
+        let k = d_k * h_k * w_k * params.c_in;
+        let m = d_out * h_out * w_out;
+        let dst_el = b * m * k;
+
+        let dst = self
+            .device
+            .new_buffer(dst_el, self.dtype, "conv3d_im2col")?;
+        let command_buffer = self.device.command_buffer()?;
+        let name = match self.dtype {
+            DType::F32 => "im2col3d_f32",
+            DType::F16 => "im2col3d_f16",
+            DType::BF16 => "im2col3d_bf16",
+            dtype => crate::bail!("Metal conv3d {dtype:?} not implemented"),
+        };
+        candle_metal_kernels::call_im2col3d_strided(
+            &self.device.device,
+            &command_buffer,
+            &self.device.kernels,
+            name,
+            layout.shape().dims(),
+            &layout.wrapping_stride(),
+            (
+                d_k,
+                h_k,
+                w_k,
+                params.stride,
+                params.padding,
+                params.dilation,
+            ),
+            &self.buffer,
+            layout.start_offset() * self.dtype.size_in_bytes(),
+            &dst,
+        )
+        .map_err(MetalError::from)?;
+        let col = Self {
+            buffer: dst,
+            device,
+            dtype: self.dtype,
+        };
+        let col_l = Layout::contiguous((b, m, k));
+        let res = if kernel_l.is_contiguous() {
+            let kernel_l = Layout::contiguous_with_offset((1, n, k), kernel_l.start_offset())
+                .transpose(1, 2)?
+                .broadcast_as((b, k, n))?;
+            col.matmul(kernel, (b, m, n, k), &col_l, &kernel_l)?
+        } else {
+            // Make the kernel contiguous if not already the case.
+            let mut kernel_c = self.device().zeros_impl(kernel_l.shape(), kernel.dtype())?;
+            kernel.copy_strided_src(&mut kernel_c, 0, kernel_l)?;
+            let kernel_l = Layout::contiguous((1, n, k))
+                .transpose(1, 2)?
+                .broadcast_as((b, k, n))?;
+            col.matmul(&kernel_c, (b, m, n, k), &col_l, &kernel_l)?
+        };
+        let res_l = Layout::contiguous((b, d_out, h_out, w_out, n)).permute(&[0, 4, 1, 2, 3])?;
+        let mut res_t = self.device().zeros_impl(res_l.shape(), res.dtype())?;
+        res.copy_strided_src(&mut res_t, 0, &res_l)?;
+        Ok(res_t)
     }
 
-    fn max_pool2d(&self, _: &Layout, _: (usize, usize), _: (usize, usize)) -> Result<Self> {
-        crate::bail!("Metal max_pool2d not implemented")
+    fn conv_transpose3d(
+        &self,
+        layout: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &ParamsConvTranspose3D,
+    ) -> Result<Self> {
+        if let Some((inp, layout)) = self.non_negative_strides(layout)? {
+            return inp.conv_transpose3d(&layout, kernel, kernel_l, params);
+        }
+        if let Some((kernel, kernel_l)) = kernel.non_negative_strides(kernel_l)? {
+            return self.conv_transpose3d(layout, &kernel, &kernel_l, params);
+        }
+        let (out_d, out_h, out_w) = (params.out_d(), params.out_h(), params.out_w());
+        let dst_el = params.c_out * out_d * out_h * out_w * params.b_size;
+        let buffer = self
+            .device
+            .new_buffer(dst_el, self.dtype, "conv_transpose3d")?;
+        let name = match self.dtype {
+            DType::F32 => "conv_transpose3d_f32",
+            DType::F16 => "conv_transpose3d_f16",
+            DType::BF16 => "conv_transpose3d_bf16",
+            dtype => crate::bail!("Metal conv_transpose3d {dtype:?} not implemented"),
+        };
+        let command_buffer = self.device.command_buffer()?;
+        candle_metal_kernels::call_conv_transpose3d(
+            &self.device.device,
+            &command_buffer,
+            &self.device.kernels,
+            name,
+            (out_d, out_h, out_w),
+            (params.stride, params.padding, params.dilation),
+            (layout.dims(), &layout.wrapping_stride()),
+            (kernel_l.dims(), &kernel_l.wrapping_stride()),
+            (
+                &self.buffer,
+                layout.start_offset() * self.dtype.size_in_bytes(),
+            ),
+            (
+                &kernel.buffer,
+                kernel_l.start_offset() * kernel.dtype.size_in_bytes(),
+            ),
+            &buffer,
+        )
+        .map_err(MetalError::from)?;
+        Ok(Self::new(buffer, self.device.clone(), self.dtype))
+    }
+
+    fn avg_pool2d(&self, l: &Layout, k: (usize, usize), stride: (usize, usize)) -> Result<Self> {
+        let name = match self.dtype {
+            DType::F32 => "avg_pool2d_f32",
+            DType::F16 => "avg_pool2d_f16",
+            DType::BF16 => "avg_pool2d_bf16",
+            dtype => crate::bail!("Metal avg_pool2d {dtype:?} not implemented"),
+        };
+        self.pool2d(name, l, k, stride)
+    }
+
+    fn max_pool2d(&self, l: &Layout, k: (usize, usize), stride: (usize, usize)) -> Result<Self> {
+        let name = match self.dtype {
+            DType::F32 => "max_pool2d_f32",
+            DType::F16 => "max_pool2d_f16",
+            DType::BF16 => "max_pool2d_bf16",
+            dtype => crate::bail!("Metal max_pool2d {dtype:?} not implemented"),
+        };
+        self.pool2d(name, l, k, stride)
+    }
+
+    fn avg_pool3d(
+        &self,
+        l: &Layout,
+        k: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    ) -> Result<Self> {
+        let name = match self.dtype {
+            DType::F32 => "avg_pool3d_f32",
+            DType::F16 => "avg_pool3d_f16",
+            DType::BF16 => "avg_pool3d_bf16",
+            dtype => crate::bail!("Metal avg_pool3d {dtype:?} not implemented"),
+        };
+        self.pool3d(name, l, k, stride)
+    }
+
+    fn max_pool3d(
+        &self,
+        l: &Layout,
+        k: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    ) -> Result<Self> {
+        let name = match self.dtype {
+            DType::F32 => "max_pool3d_f32",
+            DType::F16 => "max_pool3d_f16",
+            DType::BF16 => "max_pool3d_bf16",
+            dtype => crate::bail!("Metal max_pool3d {dtype:?} not implemented"),
+        };
+        self.pool3d(name, l, k, stride)
     }
 
     fn upsample_nearest1d(&self, _: &Layout, _: usize) -> Result<Self> {
@@ -1018,10 +1291,13 @@ impl BackendStorage for MetalStorage {
     }
 
     fn upsample_nearest2d(&self, inp_l: &Layout, out_w: usize, out_h: usize) -> Result<Self> {
+        if let Some((inp, inp_l)) = self.non_negative_strides(inp_l)? {
+            return inp.upsample_nearest2d(&inp_l, out_w, out_h);
+        }
         // let inp = &inp.slice(inp_l.start_offset()..);
         let shape = inp_l.shape();
         let dims = shape.dims();
-        let strides = inp_l.stride();
+        let strides = inp_l.wrapping_stride();
         if dims.len() != 4 {
             crate::bail!("unexpected input shape for upsample {dims:?}")
         }
@@ -1041,7 +1317,7 @@ impl BackendStorage for MetalStorage {
             &self.device.kernels,
             name,
             dims,
-            strides,
+            &strides,
             out_w,
             out_h,
             &self.buffer,
@@ -1053,6 +1329,9 @@ impl BackendStorage for MetalStorage {
     }
 
     fn gather(&self, src_l: &Layout, ids: &Self, ids_l: &Layout, dim: usize) -> Result<Self> {
+        if let Some((src, src_l)) = self.non_negative_strides(src_l)? {
+            return src.gather(&src_l, ids, ids_l, dim);
+        }
         let (ids_o1, _) = match ids_l.contiguous_offsets() {
             Some(o12) => o12,
             None => Err(crate::Error::RequiresContiguous { op: "gather" }.bt())?,
@@ -1095,6 +1374,9 @@ impl BackendStorage for MetalStorage {
         src_l: &Layout,
         dim: usize,
     ) -> Result<Self> {
+        if let Some((src, src_l)) = src.non_negative_strides(src_l)? {
+            return self.scatter_add(l, ids, ids_l, &src, &src_l, dim);
+        }
         let mut acc = self.device.zeros_impl(l.shape(), self.dtype())?;
         self.copy_strided_src(&mut acc, 0, l)?;
         let (ids_offset, _) = match ids_l.contiguous_offsets() {
@@ -1133,6 +1415,9 @@ impl BackendStorage for MetalStorage {
     }
 
     fn index_select(&self, ids: &Self, src_l: &Layout, ids_l: &Layout, dim: usize) -> Result<Self> {
+        if let Some((src, src_l)) = self.non_negative_strides(src_l)? {
+            return src.index_select(ids, &src_l, ids_l, dim);
+        }
         if !(src_l.is_contiguous()
             && src_l.start_offset() == 0
             && ids_l.is_contiguous()
@@ -1184,6 +1469,9 @@ impl BackendStorage for MetalStorage {
         src_l: &Layout,
         dim: usize,
     ) -> Result<Self> {
+        if let Some((src, src_l)) = src.non_negative_strides(src_l)? {
+            return self.index_add(l, ids, ids_l, &src, &src_l, dim);
+        }
         let mut acc = self.device.zeros_impl(l.shape(), self.dtype())?;
         self.copy_strided_src(&mut acc, 0, l)?;
         let (ids_offset, _) = match ids_l.contiguous_offsets() {
@@ -1228,6 +1516,21 @@ impl BackendStorage for MetalStorage {
         lhs_l: &Layout,
         rhs_l: &Layout,
     ) -> Result<Self> {
+        if let Some((lhs, lhs_l)) = self.non_negative_strides(lhs_l)? {
+            return lhs.matmul(rhs, (b, m, n, k), &lhs_l, rhs_l);
+        }
+        if let Some((rhs, rhs_l)) = rhs.non_negative_strides(rhs_l)? {
+            return self.matmul(&rhs, (b, m, n, k), lhs_l, &rhs_l);
+        }
+        if self.dtype == DType::BF16 {
+            // There is no bf16 gemm kernel, the operands are upcast to f32.
+            let lhs = self.to_dtype(lhs_l, DType::F32)?;
+            let rhs = rhs.to_dtype(rhs_l, DType::F32)?;
+            let lhs_l = Layout::contiguous(lhs_l.shape());
+            let rhs_l = Layout::contiguous(rhs_l.shape());
+            let res = lhs.matmul(&rhs, (b, m, n, k), &lhs_l, &rhs_l)?;
+            return res.to_dtype(&Layout::contiguous((b, m, n)), DType::BF16);
+        }
         let buffer = self.device.new_buffer(b * m * n, self.dtype, "matmul")?;
         let name = match self.dtype {
             DType::F32 => "sgemm",
@@ -1245,10 +1548,10 @@ impl BackendStorage for MetalStorage {
             &self.device.kernels,
             name,
             (b, m, n, k),
-            lhs_l.stride(),
+            &lhs_l.wrapping_stride(),
             lhs_l.start_offset() * self.dtype.size_in_bytes(),
             &self.buffer,
-            rhs_l.stride(),
+            &rhs_l.wrapping_stride(),
             rhs_l.start_offset() * rhs.dtype.size_in_bytes(),
             &rhs.buffer,
             &buffer,
@@ -1290,7 +1593,7 @@ impl BackendStorage for MetalStorage {
                 kernel_name,
                 src_l.dims(),
                 &self.buffer,
-                src_l.stride(),
+                &src_l.stride(),
                 src_l.start_offset() * self.dtype.size_in_bytes(),
                 &dst.buffer,
                 dst_offset * dst.dtype.size_in_bytes(),
@@ -1315,6 +1618,92 @@ impl MetalStorage {
         &self.buffer
     }
 
+    // The elementwise, reduce and copy kernels go through `get_strided_index` which supports
+    // negative strides. The other kernels, e.g. convolutions or matmul, index the buffer
+    // directly so inputs with negative strides are first copied to a contiguous buffer. This
+    // returns `None` when the layout has no negative strides.
+    fn non_negative_strides(&self, layout: &Layout) -> Result<Option<(Self, Layout)>> {
+        if !layout.has_negative_stride() {
+            return Ok(None);
+        }
+        let mut dst = self.device.zeros_impl(layout.shape(), self.dtype())?;
+        self.copy_strided_src(&mut dst, 0, layout)?;
+        Ok(Some((dst, Layout::contiguous(layout.shape()))))
+    }
+
+    fn pool2d(
+        &self,
+        name: &'static str,
+        l: &Layout,
+        k: (usize, usize),
+        stride: (usize, usize),
+    ) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.pool2d(name, &l, k, stride);
+        }
+        let dims = l.dims();
+        if dims.len() != 4 {
+            crate::bail!("unexpected input shape for pool {dims:?}")
+        }
+        let out_w = (dims[2] - k.0) / stride.0 + 1;
+        let out_h = (dims[3] - k.1) / stride.1 + 1;
+        let dst_el = out_w * out_h * dims[0] * dims[1];
+        let buffer = self.device.new_buffer(dst_el, self.dtype, "pool2d")?;
+        let command_buffer = self.device.command_buffer()?;
+        candle_metal_kernels::call_pool2d(
+            &self.device.device,
+            &command_buffer,
+            &self.device.kernels,
+            name,
+            dims,
+            &l.wrapping_stride(),
+            k,
+            stride,
+            &self.buffer,
+            l.start_offset() * self.dtype.size_in_bytes(),
+            &buffer,
+        )
+        .map_err(MetalError::from)?;
+        Ok(Self::new(buffer, self.device.clone(), self.dtype))
+    }
+
+    fn pool3d(
+        &self,
+        name: &'static str,
+        l: &Layout,
+        k: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    ) -> Result<Self> {
+        if let Some((inp, l)) = self.non_negative_strides(l)? {
+            return inp.pool3d(name, &l, k, stride);
+        }
+        let dims = l.dims();
+        if dims.len() != 5 {
+            crate::bail!("unexpected input shape for pool3d {dims:?}")
+        }
+        let out_d = (dims[2] - k.0) / stride.0 + 1;
+        let out_h = (dims[3] - k.1) / stride.1 + 1;
+        let out_w = (dims[4] - k.2) / stride.2 + 1;
+        let dst_el = out_d * out_h * out_w * dims[0] * dims[1];
+        let buffer = self.device.new_buffer(dst_el, self.dtype, "pool3d")?;
+        let command_buffer = self.device.command_buffer()?;
+        candle_metal_kernels::call_pool3d(
+            &self.device.device,
+            &command_buffer,
+            &self.device.kernels,
+            name,
+            dims,
+            &l.wrapping_stride(),
+            k,
+            stride,
+            &self.buffer,
+            l.start_offset() * self.dtype.size_in_bytes(),
+            &buffer,
+        )
+        .map_err(MetalError::from)?;
+        Ok(Self::new(buffer, self.device.clone(), self.dtype))
+    }
+
     pub fn binary(
         &self,
         op: &'static str,
@@ -1510,10 +1899,10 @@ impl MetalStorage {
                 kernel_name,
                 lhs_l.dims(),
                 &self.buffer,
-                lhs_l.stride(),
+                &lhs_l.stride(),
                 lhs_l.start_offset() * self.dtype.size_in_bytes(),
                 &rhs.buffer,
-                rhs_l.stride(),
+                &rhs_l.stride(),
                 rhs_l.start_offset() * rhs.dtype.size_in_bytes(),
                 &buffer,
             )
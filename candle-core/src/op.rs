@@ -120,6 +120,25 @@ pub enum Op {
         dilation: usize,
     },
 
+    #[allow(dead_code)]
+    Conv3D {
+        arg: Tensor,
+        kernel: Tensor,
+        padding: usize,
+        stride: usize,
+        dilation: usize,
+    },
+
+    #[allow(dead_code)]
+    ConvTranspose3D {
+        arg: Tensor,
+        kernel: Tensor,
+        padding: usize,
+        output_padding: usize,
+        stride: usize,
+        dilation: usize,
+    },
+
     AvgPool2D {
         arg: Tensor,
         kernel_size: (usize, usize),
@@ -132,6 +151,18 @@ pub enum Op {
         stride: (usize, usize),
     },
 
+    AvgPool3D {
+        arg: Tensor,
+        kernel_size: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    },
+
+    MaxPool3D {
+        arg: Tensor,
+        kernel_size: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    },
+
     UpsampleNearest1D {
         arg: Tensor,
         target_size: usize,
@@ -154,11 +185,25 @@ pub enum Op {
     Copy(Tensor),
     Broadcast(Tensor),
     Narrow(Tensor, usize, usize, usize),
+    NarrowWithStep {
+        arg: Tensor,
+        dim: usize,
+        start: usize,
+        len: usize,
+        step: isize,
+    },
     SliceScatter0(Tensor, Tensor, usize),
     Reshape(Tensor),
     ToDevice(Tensor),
     Transpose(Tensor, usize, usize),
     Permute(Tensor, Vec<usize>),
+    Flip(Tensor, Vec<usize>),
+    Unfold {
+        arg: Tensor,
+        dim: usize,
+        size: usize,
+        step: usize,
+    },
     Elu(Tensor, f64),
     Powf(Tensor, f64),
     CustomOp1(Tensor, std::sync::Arc<Box<dyn CustomOp1 + Send + Sync>>),
@@ -173,8 +218,18 @@ pub enum Op {
         Tensor,
         std::sync::Arc<Box<dyn CustomOp3 + Send + Sync>>,
     ),
+    // The output of a checkpointed function, the intermediary values are not stored and get
+    // recomputed during the backward pass. `params` holds the variables captured by `f`.
+    Checkpoint {
+        args: Vec<Tensor>,
+        params: Vec<Tensor>,
+        f: std::sync::Arc<CheckpointFn>,
+    },
 }
 
+/// The type of the functions that can be used with [`crate::checkpoint`].
+pub type CheckpointFn = dyn Fn(&[Tensor]) -> Result<Tensor> + Send + Sync;
+
 /// Unary ops that can be defined in user-land.
 pub trait CustomOp1 {
     // Box<dyn> does not support const yet, so use a function to get the name.
@@ -1,4 +1,4 @@
-// Just enough pickle support to be able to read PyTorch checkpoints.
+// Just enough pickle support to be able to read and write PyTorch checkpoints.
 // This hardcodes objects that are required for tensor reading, we may want to make this a bit more
 // composable/tensor agnostic at some point.
 use crate::{DType, Error as E, Layout, Result, Tensor};
@@ -45,6 +45,18 @@ pub enum OpCode {
     BinFloat = b'G',
     Append = b'a',
     Appends = b'e',
+    Long1 = 0x8a,
+    BinString = b'T',
+    ShortBinString = b'U',
+    BinBytes = b'B',
+    ShortBinBytes = b'C',
+    ShortBinUnicode = 0x8c,
+    StackGlobal = 0x93,
+    Memoize = 0x94,
+    Frame = 0x95,
+    Pop = b'0',
+    PopMark = b'1',
+    Dup = b'2',
 }
 
 // Avoid using FromPrimitive so as not to drag another dependency.
@@ -76,7 +88,7 @@ impl TryFrom<u8> for OpCode {
             b's' => Ok(Self::SetItem),
             b'u' => Ok(Self::SetItems),
             b'}' => Ok(Self::EmptyDict),
-            b'd' => Ok(Self::EmptyDict),
+            b'd' => Ok(Self::Dict),
             b'b' => Ok(Self::Build),
             b'.' => Ok(Self::Stop),
             0x81 => Ok(Self::NewObj),
@@ -84,6 +96,18 @@ impl TryFrom<u8> for OpCode {
             b'G' => Ok(Self::BinFloat),
             b'a' => Ok(Self::Append),
             b'e' => Ok(Self::Appends),
+            0x8a => Ok(Self::Long1),
+            b'T' => Ok(Self::BinString),
+            b'U' => Ok(Self::ShortBinString),
+            b'B' => Ok(Self::BinBytes),
+            b'C' => Ok(Self::ShortBinBytes),
+            0x8c => Ok(Self::ShortBinUnicode),
+            0x93 => Ok(Self::StackGlobal),
+            0x94 => Ok(Self::Memoize),
+            0x95 => Ok(Self::Frame),
+            b'0' => Ok(Self::Pop),
+            b'1' => Ok(Self::PopMark),
+            b'2' => Ok(Self::Dup),
             value => Err(value),
         }
     }
@@ -106,8 +130,10 @@ pub enum Object {
         class_name: String,
     },
     Int(i32),
+    Long(i64),
     Float(f64),
     Unicode(String),
+    Bytes(Vec<u8>),
     Bool(bool),
     None,
     Tuple(Vec<Object>),
@@ -259,6 +285,7 @@ impl TryFrom<Object> for usize {
     fn try_from(value: Object) -> std::result::Result<Self, Self::Error> {
         match value {
             Object::Int(s) if s >= 0 => Ok(s as usize),
+            Object::Long(s) if s >= 0 => Ok(s as usize),
             other => Err(other),
         }
     }
@@ -530,14 +557,14 @@ impl Stack {
                 }
             }
             OpCode::SetItems => {
-                let mut objs = self.pop_to_marker()?;
+                let objs = self.pop_to_marker()?;
                 let pydict = self.last()?;
                 if let Object::Dict(d) = pydict {
                     if objs.len() % 2 != 0 {
                         crate::bail!("setitems: not an even number of objects")
                     }
-                    while let Some(value) = objs.pop() {
-                        let key = objs.pop().unwrap();
+                    let mut objs = objs.into_iter();
+                    while let (Some(key), Some(value)) = (objs.next(), objs.next()) {
                         d.push((key, value))
                     }
                 } else {
@@ -551,13 +578,13 @@ impl Stack {
             OpCode::Build => self.build()?,
             OpCode::EmptyDict => self.push(Object::Dict(vec![])),
             OpCode::Dict => {
-                let mut objs = self.pop_to_marker()?;
+                let objs = self.pop_to_marker()?;
                 let mut pydict = vec![];
                 if objs.len() % 2 != 0 {
-                    crate::bail!("setitems: not an even number of objects")
+                    crate::bail!("dict: not an even number of objects")
                 }
-                while let Some(value) = objs.pop() {
-                    let key = objs.pop().unwrap();
+                let mut objs = objs.into_iter();
+                while let (Some(key), Some(value)) = (objs.next(), objs.next()) {
                     pydict.push((key, value))
                 }
                 self.push(Object::Dict(pydict))
@@ -590,6 +617,74 @@ impl Stack {
                 let obj = self.new_obj(class, args)?;
                 self.push(obj)
             }
+            OpCode::Long1 => {
+                let len = r.read_u8()? as usize;
+                if len > 8 {
+                    crate::bail!("long1: unsupported integer length {len}")
+                }
+                let mut data = [0u8; 8];
+                r.read_exact(&mut data[..len])?;
+                // Sign extend the two's complement little endian representation.
+                if len > 0 && data[len - 1] & 0x80 != 0 {
+                    data[len..].fill(0xff)
+                }
+                let arg = i64::from_le_bytes(data);
+                match i32::try_from(arg) {
+                    Ok(arg) => self.push(Object::Int(arg)),
+                    Err(_) => self.push(Object::Long(arg)),
+                }
+            }
+            OpCode::BinString | OpCode::ShortBinString => {
+                let len = match op_code {
+                    OpCode::BinString => r.read_u32::<LittleEndian>()? as usize,
+                    _ => r.read_u8()? as usize,
+                };
+                let mut data = vec![0u8; len];
+                r.read_exact(&mut data)?;
+                self.push(Object::Unicode(String::from_utf8_lossy(&data).to_string()))
+            }
+            OpCode::BinBytes | OpCode::ShortBinBytes => {
+                let len = match op_code {
+                    OpCode::BinBytes => r.read_u32::<LittleEndian>()? as usize,
+                    _ => r.read_u8()? as usize,
+                };
+                let mut data = vec![0u8; len];
+                r.read_exact(&mut data)?;
+                self.push(Object::Bytes(data))
+            }
+            OpCode::ShortBinUnicode => {
+                let len = r.read_u8()?;
+                let mut data = vec![0u8; len as usize];
+                r.read_exact(&mut data)?;
+                let data = String::from_utf8(data).map_err(E::wrap)?;
+                self.push(Object::Unicode(data))
+            }
+            OpCode::StackGlobal => {
+                let class_name = self.pop()?.unicode()?;
+                let module_name = self.pop()?.unicode()?;
+                self.push(Object::Class {
+                    module_name,
+                    class_name,
+                })
+            }
+            OpCode::Memoize => {
+                let id = self.memo.len() as u32;
+                self.memo_put(id)?
+            }
+            OpCode::Frame => {
+                // Frames are only a hint for buffering, the frame size can be ignored.
+                let _frame_size = r.read_u64::<LittleEndian>()?;
+            }
+            OpCode::Pop => {
+                self.pop()?;
+            }
+            OpCode::PopMark => {
+                self.pop_to_marker()?;
+            }
+            OpCode::Dup => {
+                let obj = self.last()?.clone();
+                self.push(obj)
+            }
         }
         Ok(false)
     }
@@ -606,11 +701,12 @@ impl From<Object> for E {
 fn rebuild_args(args: Object) -> Result<(Layout, DType, String, usize)> {
     let mut args = args.tuple()?;
     let stride = Vec::<usize>::try_from(args.remove(3))?;
+    let stride = stride.into_iter().map(|s| s as isize).collect();
     let size = Vec::<usize>::try_from(args.remove(2))?;
-    let offset = args.remove(1).int()? as usize;
+    let offset = usize::try_from(args.remove(1))?;
     let storage = args.remove(0).persistent_load()?;
     let mut storage = storage.tuple()?;
-    let storage_size = storage.remove(4).int()? as usize;
+    let storage_size = usize::try_from(storage.remove(4))?;
     let path = storage.remove(2).unicode()?;
     let (_module_name, class_name) = storage.remove(1).class()?;
     let dtype = match class_name.as_str() {
@@ -619,6 +715,8 @@ fn rebuild_args(args: Object) -> Result<(Layout, DType, String, usize)> {
         "HalfStorage" => DType::F16,
         "BFloat16Storage" => DType::BF16,
         "ByteStorage" => DType::U8,
+        // Booleans are stored as a single byte with value 0 or 1.
+        "BoolStorage" => DType::U8,
         "LongStorage" => DType::I64,
         other => {
             crate::bail!("unsupported storage type {other}")
@@ -702,9 +800,34 @@ pub fn read_pth_tensor_info<P: AsRef<std::path::Path>>(
 
         // If the object is a dict, then we can extract the tensor info from it.
         // NOTE: We are assuming that the `obj` is state_dict by this stage.
-        if let Object::Dict(key_values) = obj {
-            for (name, value) in key_values.into_iter() {
-                match value.into_tensor_info(name, &dir_name) {
+        collect_tensor_infos(obj, "", &dir_name, &mut tensor_infos);
+    }
+    Ok(tensor_infos)
+}
+
+// Extracts the tensor infos from a dict, nested dicts are flattened by joining the keys with a
+// dot, e.g. `{"encoder": {"weight": ..}}` results in a tensor named `encoder.weight`.
+fn collect_tensor_infos(
+    obj: Object,
+    prefix: &str,
+    dir_name: &std::path::Path,
+    tensor_infos: &mut Vec<TensorInfo>,
+) {
+    let key_values = match obj {
+        Object::Dict(key_values) => key_values,
+        _ => return,
+    };
+    for (name, value) in key_values.into_iter() {
+        match (name, value) {
+            (Object::Unicode(name), value @ Object::Dict(_)) => {
+                collect_tensor_infos(value, &format!("{prefix}{name}."), dir_name, tensor_infos)
+            }
+            (name, value) => {
+                let name = match name {
+                    Object::Unicode(name) => Object::Unicode(format!("{prefix}{name}")),
+                    name => name,
+                };
+                match value.into_tensor_info(name, dir_name) {
                     Ok(Some(tensor_info)) => tensor_infos.push(tensor_info),
                     Ok(None) => {}
                     Err(err) => eprintln!("skipping: {err:?}"),
@@ -712,7 +835,6 @@ pub fn read_pth_tensor_info<P: AsRef<std::path::Path>>(
             }
         }
     }
-    Ok(tensor_infos)
 }
 
 /// Lazy tensor loader.
@@ -748,42 +870,59 @@ impl PthTensors {
         let zip_reader = std::io::BufReader::new(std::fs::File::open(&self.path)?);
         let mut zip = zip::ZipArchive::new(zip_reader)?;
         let mut reader = zip.by_name(&tensor_info.path)?;
-        let is_fortran_contiguous = tensor_info.layout.is_fortran_contiguous();
-        let rank = tensor_info.layout.shape().rank();
-
-        // Reading the data is a bit tricky as it can be strided, for now only support the basic
-        // case and when the tensor is fortran contiguous.
-        if !tensor_info.layout.is_contiguous() && !is_fortran_contiguous {
-            crate::bail!(
-                "cannot retrieve non-contiguous tensors {:?}",
-                tensor_info.layout
-            )
-        }
-        let start_offset = tensor_info.layout.start_offset();
-        if start_offset > 0 {
-            std::io::copy(
-                &mut reader.by_ref().take(start_offset as u64),
-                &mut std::io::sink(),
-            )?;
+        let layout = &tensor_info.layout;
+        let dtype = tensor_info.dtype;
+        let is_fortran_contiguous = layout.is_fortran_contiguous();
+        let rank = layout.shape().rank();
+
+        // Contiguous and fortran contiguous tensors are read directly, the offset is in number
+        // of elements of the storage which can be shared between multiple tensors.
+        if layout.is_contiguous() || is_fortran_contiguous {
+            let start_offset = layout.start_offset() * dtype.size_in_bytes();
+            if start_offset > 0 {
+                std::io::copy(
+                    &mut reader.by_ref().take(start_offset as u64),
+                    &mut std::io::sink(),
+                )?;
+            }
+            let tensor = Tensor::from_reader(layout.shape().clone(), dtype, &mut reader)?;
+
+            if rank > 1 && is_fortran_contiguous {
+                // Reverse the shape, e.g. Shape(2, 3, 4) -> Shape(4, 3, 2)
+                let shape_reversed: Vec<_> = layout.dims().iter().rev().cloned().collect();
+                let tensor = tensor.reshape(shape_reversed)?;
+
+                // Permute (transpose) the dimensions, e.g. Shape(4, 3, 2) -> Shape(2, 3, 4)
+                let dim_indeces_reversed: Vec<_> = (0..rank).rev().collect();
+                let tensor = tensor.permute(dim_indeces_reversed)?;
+                return Ok(Some(tensor));
+            }
+            return Ok(Some(tensor));
         }
-        let tensor = Tensor::from_reader(
-            tensor_info.layout.shape().clone(),
-            tensor_info.dtype,
-            &mut reader,
-        )?;
-
-        if rank > 1 && is_fortran_contiguous {
-            // Reverse the shape, e.g. Shape(2, 3, 4) -> Shape(4, 3, 2)
-            let shape_reversed: Vec<_> = tensor_info.layout.dims().iter().rev().cloned().collect();
-            let tensor = tensor.reshape(shape_reversed)?;
-
-            // Permute (transpose) the dimensions, e.g. Shape(4, 3, 2) -> Shape(2, 3, 4)
-            let dim_indeces_reversed: Vec<_> = (0..rank).rev().collect();
-            let tensor = tensor.permute(dim_indeces_reversed)?;
-            Ok(Some(tensor))
-        } else {
-            Ok(Some(tensor))
+
+        // For other strided layouts, the whole storage is read and the tensor is extracted from
+        // it as a strided view.
+        if layout.shape().elem_count() > 0 {
+            let (min_offset, max_offset) = layout.dims().iter().zip(layout.stride().iter()).fold(
+                (
+                    layout.start_offset() as isize,
+                    layout.start_offset() as isize,
+                ),
+                |(lo, hi), (&d, &s)| {
+                    let delta = s * (d as isize - 1);
+                    (lo + delta.min(0), hi + delta.max(0))
+                },
+            );
+            if min_offset < 0 || max_offset as usize >= tensor_info.storage_size {
+                crate::bail!(
+                    "layout {layout:?} is out of bounds for a storage of size {}",
+                    tensor_info.storage_size
+                )
+            }
         }
+        let storage = Tensor::from_reader(tensor_info.storage_size.into(), dtype, &mut reader)?;
+        let tensor = storage.with_layout(layout.clone())?.contiguous()?;
+        Ok(Some(tensor))
     }
 }
 
@@ -815,3 +954,145 @@ pub fn read_all_with_key<P: AsRef<std::path::Path>>(
 pub fn read_all<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<(String, Tensor)>> {
     read_all_with_key(path, None)
 }
+
+// Serializes the small subset of pickle objects used in PyTorch checkpoints.
+struct Pickler {
+    buf: Vec<u8>,
+}
+
+impl Pickler {
+    fn op(&mut self, op_code: OpCode) {
+        self.buf.push(op_code as u8)
+    }
+
+    fn global(&mut self, module_name: &str, class_name: &str) {
+        self.op(OpCode::Global);
+        for name in [module_name, class_name] {
+            self.buf.extend_from_slice(name.as_bytes());
+            self.buf.push(b'\n');
+        }
+    }
+
+    fn int(&mut self, v: usize) {
+        if v < 0x100 {
+            self.op(OpCode::BinInt1);
+            self.buf.push(v as u8)
+        } else if v < 0x10000 {
+            self.op(OpCode::BinInt2);
+            self.buf.extend_from_slice(&(v as u16).to_le_bytes())
+        } else if v <= i32::MAX as usize {
+            self.op(OpCode::BinInt);
+            self.buf.extend_from_slice(&(v as i32).to_le_bytes())
+        } else {
+            self.op(OpCode::Long1);
+            self.buf.push(8);
+            self.buf.extend_from_slice(&(v as i64).to_le_bytes())
+        }
+    }
+
+    fn unicode(&mut self, s: &str) {
+        self.op(OpCode::BinUnicode);
+        self.buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(s.as_bytes())
+    }
+
+    fn int_tuple(&mut self, vs: &[usize]) {
+        self.op(OpCode::Mark);
+        for &v in vs.iter() {
+            self.int(v)
+        }
+        self.op(OpCode::Tuple)
+    }
+
+    fn ordered_dict(&mut self) {
+        self.global("collections", "OrderedDict");
+        self.op(OpCode::EmptyTuple);
+        self.op(OpCode::Reduce)
+    }
+}
+
+fn storage_class_name(dtype: DType) -> Result<&'static str> {
+    let class_name = match dtype {
+        DType::F32 => "FloatStorage",
+        DType::F64 => "DoubleStorage",
+        DType::F16 => "HalfStorage",
+        DType::BF16 => "BFloat16Storage",
+        DType::U8 => "ByteStorage",
+        DType::I64 => "LongStorage",
+        DType::U32 => crate::bail!("u32 tensors cannot be saved as PyTorch does not support them"),
+    };
+    Ok(class_name)
+}
+
+/// Write some tensors to a PyTorch checkpoint that can be loaded with `torch.load`.
+///
+/// The tensors are stored in an `OrderedDict`, i.e. the format used for a `state_dict`, using
+/// the zip based serialization format of PyTorch. Each tensor gets its own storage.
+///
+/// # Arguments
+/// * `path` - Path to the pt file to create.
+/// * `tensors` - The names and values of the tensors to write.
+pub fn write_pth<K: AsRef<str>, P: AsRef<std::path::Path>>(
+    path: P,
+    tensors: &[(K, Tensor)],
+) -> Result<()> {
+    use std::io::Write;
+
+    let path = path.as_ref();
+    let archive_name = match path.file_stem() {
+        Some(stem) => stem.to_string_lossy().to_string(),
+        None => "archive".to_string(),
+    };
+
+    // https://github.com/pytorch/pytorch/blob/4eac43d046ded0f0a5a5fa8db03eb40f45bf656e/torch/serialization.py#L651
+    let mut p = Pickler { buf: vec![] };
+    p.op(OpCode::Proto);
+    p.buf.push(2);
+    p.ordered_dict();
+    p.op(OpCode::Mark);
+    for (index, (name, tensor)) in tensors.iter().enumerate() {
+        p.unicode(name.as_ref());
+        p.global("torch._utils", "_rebuild_tensor_v2");
+        p.op(OpCode::Mark);
+        // Persistent id: ('storage', storage_type, key, location, numel)
+        p.op(OpCode::Mark);
+        p.unicode("storage");
+        p.global("torch", storage_class_name(tensor.dtype())?);
+        p.unicode(&index.to_string());
+        p.unicode("cpu");
+        p.int(tensor.elem_count());
+        p.op(OpCode::Tuple);
+        p.op(OpCode::BinPersId);
+        // Arguments: storage_offset, size, stride, requires_grad, backward_hooks
+        p.int(0);
+        p.int_tuple(tensor.dims());
+        p.int_tuple(&tensor.shape().stride_contiguous());
+        p.op(OpCode::NewFalse);
+        p.ordered_dict();
+        p.op(OpCode::Tuple);
+        p.op(OpCode::Reduce);
+    }
+    p.op(OpCode::SetItems);
+    p.op(OpCode::Stop);
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut zip = zip::ZipWriter::new(file);
+    // The records are not compressed and are aligned to 64 bytes, as done by PyTorch so that the
+    // checkpoint can be memory mapped.
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    zip.start_file_aligned(format!("{archive_name}/data.pkl"), options, 64)?;
+    zip.write_all(&p.buf)?;
+    zip.start_file_aligned(format!("{archive_name}/byteorder"), options, 64)?;
+    zip.write_all(b"little")?;
+    for (index, (_, tensor)) in tensors.iter().enumerate() {
+        let size_in_bytes = tensor.elem_count() * tensor.dtype().size_in_bytes();
+        let options = options.large_file(size_in_bytes >= u32::MAX as usize);
+        zip.start_file_aligned(format!("{archive_name}/data/{index}"), options, 64)?;
+        tensor.write_bytes(&mut zip)?;
+    }
+    zip.start_file_aligned(format!("{archive_name}/version"), options, 64)?;
+    zip.write_all(b"3\n")?;
+    zip.finish()?.flush()?;
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use super::k_quants::{
-    BlockQ2K, BlockQ3K, BlockQ4K, BlockQ4_0, BlockQ5K, BlockQ6K, BlockQ8K, BlockQ8_0, QK8_0, QK_K,
+    BlockIQ4NL, BlockIQ4XS, BlockQ2K, BlockQ3K, BlockQ4K, BlockQ4_0, BlockQ5K, BlockQ6K, BlockQ8K,
+    BlockQ8_0, KVALUES_IQ4NL, QK4_NL, QK8_0, QK_K,
 };
 use crate::Result;
 use byteorder::{ByteOrder, LittleEndian};
@@ -665,3 +666,58 @@ pub(crate) fn vec_dot_q8k_q8k(n: usize, xs: &[BlockQ8K], ys: &[BlockQ8K]) -> Res
         Ok(hsum_float_8(acc))
     }
 }
+
+/// Expands 32 packed 4 bits indexes into the corresponding non-linear IQ4_NL values, the low
+/// nibbles end up in the first 16 bytes and the high nibbles in the last 16 bytes.
+#[inline(always)]
+unsafe fn iq4nl_values_32(values: __m128i, rsi: *const u8) -> __m256i {
+    let m4b = _mm_set1_epi8(0xF);
+    let q4 = _mm_loadu_si128(rsi as *const __m128i);
+    let lo = _mm_shuffle_epi8(values, _mm_and_si128(q4, m4b));
+    let hi = _mm_shuffle_epi8(values, _mm_and_si128(_mm_srli_epi16(q4, 4), m4b));
+    _mm256_set_m128i(hi, lo)
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4nl_q8_0(n: usize, xs: &[BlockIQ4NL], ys: &[BlockQ8_0]) -> Result<f32> {
+    if n % QK4_NL != 0 {
+        crate::bail!("vec_dot_iq4nl_q8_0: {n} is not divisible by {QK4_NL}")
+    }
+    unsafe {
+        let values = _mm_loadu_si128(KVALUES_IQ4NL.as_ptr() as *const __m128i);
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = _mm256_set1_ps(f16::to_f32(x.d) * f16::to_f32(y.d));
+            let bx = iq4nl_values_32(values, x.qs.as_ptr());
+            let by = _mm256_loadu_si256(y.qs.as_ptr() as *const __m256i);
+            let q = mul_sum_i8_pairs_float(bx, by);
+            acc = _mm256_fmadd_ps(d, q, acc);
+        }
+        Ok(hsum_float_8(acc))
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4xs_q8k(n: usize, xs: &[BlockIQ4XS], ys: &[BlockQ8K]) -> Result<f32> {
+    if n % QK_K != 0 {
+        crate::bail!("vec_dot_iq4xs_q8k: {n} is not divisible by {QK_K}")
+    }
+    unsafe {
+        let values = _mm_loadu_si128(KVALUES_IQ4NL.as_ptr() as *const __m128i);
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumb = _mm256_setzero_ps();
+            for ib in 0..QK_K / 32 {
+                let ls = _mm256_set1_ps((x.scale(ib) - 32) as f32);
+                let bx = iq4nl_values_32(values, x.qs.as_ptr().add(16 * ib));
+                let by = _mm256_loadu_si256(y.qs.as_ptr().add(32 * ib) as *const __m256i);
+                // q8k values can be -128 so they are the ones taken in absolute value.
+                let q = mul_sum_i8_pairs_float(by, bx);
+                sumb = _mm256_fmadd_ps(ls, q, sumb);
+            }
+            let d = _mm256_set1_ps(f16::to_f32(x.d) * y.d);
+            acc = _mm256_fmadd_ps(d, sumb, acc);
+        }
+        Ok(hsum_float_8(acc))
+    }
+}
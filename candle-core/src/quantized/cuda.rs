@@ -57,6 +57,8 @@ fn dequantize(
         GgmlDType::Q5K => ("dequantize_block_q5_K", true, 64, nb),
         GgmlDType::Q6K => ("dequantize_block_q6_K", true, 64, nb),
         GgmlDType::Q8K => ("dequantize_block_q8_K", true, 32, nb),
+        GgmlDType::IQ4NL => ("dequantize_block_iq4_nl", false, 32, nb),
+        GgmlDType::IQ4XS => ("dequantize_block_iq4_xs", true, 32, nb),
         _ => crate::bail!("unsupported dtype for dequantize {dtype:?}"),
     };
     let func = dev.get_or_load_func(kernel_name, candle_kernels::QUANTIZED)?;
@@ -104,6 +106,8 @@ fn dequantize_mut_mal_vec(
         GgmlDType::Q4K => "dequantize_mul_mat_vec_q4_k",
         GgmlDType::Q5K => "dequantize_mul_mat_vec_q5_k",
         GgmlDType::Q6K => "dequantize_mul_mat_vec_q6_k",
+        GgmlDType::IQ4NL => "dequantize_mul_mat_vec_iq4_nl_cuda",
+        GgmlDType::IQ4XS => "dequantize_mul_mat_vec_iq4_xs",
         _ => crate::bail!("unsupported dtype for quantized matmul {dtype:?}"),
     };
     let func = dev.get_or_load_func(kernel_name, candle_kernels::QUANTIZED)?;
@@ -153,6 +157,8 @@ impl QCudaStorage {
                 | GgmlDType::Q5K
                 | GgmlDType::Q6K
                 | GgmlDType::Q8K
+                | GgmlDType::IQ4NL
+                | GgmlDType::IQ4XS
         );
         if fast_kernel {
             return dequantize(&self.data, self.dtype, elem_count, self.device());
@@ -221,10 +227,18 @@ impl QCudaStorage {
                 let vec: Vec<crate::quantized::BlockQ8K> = read_to_vec(&buffer, block_len);
                 crate::quantized::BlockQ8K::to_float(&vec, &mut out)?;
             }
+            GgmlDType::IQ4NL => {
+                let vec: Vec<crate::quantized::BlockIQ4NL> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ4NL::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ4XS => {
+                let vec: Vec<crate::quantized::BlockIQ4XS> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ4XS::to_float(&vec, &mut out)?;
+            }
         }
 
         self.device
-            .storage_from_cpu_storage(&crate::CpuStorage::F32(out))
+            .storage_from_cpu_storage(&crate::CpuStorage::F32(out.into()))
     }
 
     pub fn quantize(&mut self, src: &CudaStorage) -> Result<()> {
@@ -236,7 +250,7 @@ impl QCudaStorage {
             _ => crate::bail!("only f32 can be quantized"),
         };
         let src_len = src.len();
-        let src = crate::Storage::Cpu(crate::CpuStorage::F32(src));
+        let src = crate::Storage::Cpu(crate::CpuStorage::F32(src.into()));
         let mut qcpu_storage = crate::Device::Cpu.qzeros(src_len, self.dtype)?;
         qcpu_storage.quantize(&src)?;
         let data = qcpu_storage.data()?;
@@ -245,6 +259,29 @@ impl QCudaStorage {
         Ok(())
     }
 
+    pub fn quantize_imatrix(
+        &mut self,
+        src: &CudaStorage,
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        // Run the quantization on cpu.
+        let src = match &src.slice {
+            crate::cuda_backend::CudaStorageSlice::F32(data) => {
+                self.device.dtoh_sync_copy(data).w()?
+            }
+            _ => crate::bail!("only f32 can be quantized"),
+        };
+        let src_len = src.len();
+        let src = crate::Storage::Cpu(crate::CpuStorage::F32(src.into()));
+        let mut qcpu_storage = crate::Device::Cpu.qzeros(src_len, self.dtype)?;
+        qcpu_storage.quantize_imatrix(&src, imatrix_weights, n_per_row)?;
+        let data = qcpu_storage.data()?;
+        let data = self.device.htod_sync_copy(data.as_ref()).w()?;
+        self.data = data;
+        Ok(())
+    }
+
     pub fn storage_size_in_bytes(&self) -> usize {
         self.data.len()
     }
@@ -313,7 +350,7 @@ impl QCudaStorage {
         }
 
         let data_f32 = self.dequantize(n * k)?;
-        let rhs_l = crate::Layout::new((k, n).into(), vec![1, k], 0);
+        let rhs_l = crate::Layout::new((k, n).into(), vec![1, k as isize], 0);
         let out = storage.matmul(&data_f32, (b, m, n, k), layout, &rhs_l)?;
         let mut out_shape = layout.shape().dims().to_vec();
         out_shape.pop();
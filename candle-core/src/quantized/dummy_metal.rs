@@ -28,6 +28,10 @@ impl QMetalStorage {
         Err(Error::NotCompiledWithMetalSupport)
     }
 
+    pub fn quantize_imatrix(&mut self, _src: &MetalStorage, _: &[f32], _: usize) -> Result<()> {
+        Err(Error::NotCompiledWithMetalSupport)
+    }
+
     pub fn storage_size_in_bytes(&self) -> usize {
         0
     }
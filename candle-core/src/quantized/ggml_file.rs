@@ -4,6 +4,7 @@ use super::{k_quants, GgmlDType, QStorage};
 use crate::{Device, Result};
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 // https://github.com/ggerganov/llama.cpp/blob/468ea24fb4633a0d681f7ac84089566c1c6190cb/llama.h#L37
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -184,10 +185,85 @@ pub fn qtensor_from_ggml(
         GgmlDType::Q6K => {
             from_raw_data::<k_quants::BlockQ6K>(raw_data, size_in_bytes, dims, device)
         }
+        GgmlDType::IQ4NL => {
+            from_raw_data::<k_quants::BlockIQ4NL>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ4XS => {
+            from_raw_data::<k_quants::BlockIQ4XS>(raw_data, size_in_bytes, dims, device)
+        }
         _ => crate::bail!("quantized type {ggml_dtype:?} is not supported yet"),
     }
 }
 
+fn mmaped_blocks<T: super::GgmlType + Send + Sync + 'static>(
+    mmap: &Arc<memmap2::Mmap>,
+    offset: usize,
+    size_in_bytes: usize,
+) -> Option<Box<dyn super::QuantizedType>> {
+    let n_blocks = size_in_bytes / std::mem::size_of::<T>();
+    // SAFETY: the quantized blocks are plain data for which all bit patterns are valid, and the
+    // file cannot be modified per the safety contract of `qtensor_from_mmap`.
+    let data = unsafe { crate::CpuBuffer::<T>::from_mmap(mmap.clone(), offset, n_blocks)? };
+    Some(Box::new(data))
+}
+
+/// Creates a [Tensor] from a raw GGML tensor stored at byte `offset` in a memory mapped file.
+///
+/// On the cpu, the quantized blocks are read directly from the memory mapped file rather than
+/// being copied when they are properly aligned.
+///
+/// # Safety
+///
+/// The underlying file must not be modified while the tensor is in use, see [`memmap2::Mmap`].
+pub unsafe fn qtensor_from_mmap(
+    ggml_dtype: GgmlDType,
+    mmap: &Arc<memmap2::Mmap>,
+    offset: usize,
+    dims: Vec<usize>,
+    device: &Device,
+) -> Result<super::QTensor> {
+    let tensor_elems = dims.iter().product::<usize>();
+    let block_size = ggml_dtype.block_size();
+    if tensor_elems % block_size != 0 {
+        crate::bail!(
+            "the number of elements {tensor_elems} is not divisible by the block size {block_size}"
+        )
+    }
+    let size_in_bytes = tensor_elems / block_size * ggml_dtype.type_size();
+    let raw_data = match offset
+        .checked_add(size_in_bytes)
+        .and_then(|end| mmap.get(offset..end))
+    {
+        Some(raw_data) => raw_data,
+        None => crate::bail!("tensor data at offset {offset} is out of the mapped file bounds"),
+    };
+    if !device.is_cpu() {
+        return qtensor_from_ggml(ggml_dtype, raw_data, dims, device);
+    }
+    let data = match ggml_dtype {
+        GgmlDType::F32 => mmaped_blocks::<f32>(mmap, offset, size_in_bytes),
+        GgmlDType::F16 => mmaped_blocks::<half::f16>(mmap, offset, size_in_bytes),
+        GgmlDType::Q4_0 => mmaped_blocks::<k_quants::BlockQ4_0>(mmap, offset, size_in_bytes),
+        GgmlDType::Q4_1 => mmaped_blocks::<k_quants::BlockQ4_1>(mmap, offset, size_in_bytes),
+        GgmlDType::Q5_0 => mmaped_blocks::<k_quants::BlockQ5_0>(mmap, offset, size_in_bytes),
+        GgmlDType::Q5_1 => mmaped_blocks::<k_quants::BlockQ5_1>(mmap, offset, size_in_bytes),
+        GgmlDType::Q8_0 => mmaped_blocks::<k_quants::BlockQ8_0>(mmap, offset, size_in_bytes),
+        GgmlDType::Q2K => mmaped_blocks::<k_quants::BlockQ2K>(mmap, offset, size_in_bytes),
+        GgmlDType::Q3K => mmaped_blocks::<k_quants::BlockQ3K>(mmap, offset, size_in_bytes),
+        GgmlDType::Q4K => mmaped_blocks::<k_quants::BlockQ4K>(mmap, offset, size_in_bytes),
+        GgmlDType::Q5K => mmaped_blocks::<k_quants::BlockQ5K>(mmap, offset, size_in_bytes),
+        GgmlDType::Q6K => mmaped_blocks::<k_quants::BlockQ6K>(mmap, offset, size_in_bytes),
+        GgmlDType::IQ4NL => mmaped_blocks::<k_quants::BlockIQ4NL>(mmap, offset, size_in_bytes),
+        GgmlDType::IQ4XS => mmaped_blocks::<k_quants::BlockIQ4XS>(mmap, offset, size_in_bytes),
+        _ => crate::bail!("quantized type {ggml_dtype:?} is not supported yet"),
+    };
+    match data {
+        Some(data) => super::QTensor::new(QStorage::Cpu(data), dims),
+        // The data is not aligned for the block type, fall back to copying it.
+        None => qtensor_from_ggml(ggml_dtype, raw_data, dims, device),
+    }
+}
+
 fn read_one_tensor<R: std::io::Seek + std::io::Read>(
     reader: &mut R,
     magic: VersionedMagic,
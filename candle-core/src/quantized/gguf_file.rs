@@ -6,6 +6,7 @@ use super::{GgmlDType, QTensor};
 use crate::{Device, Result};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub const DEFAULT_ALIGNMENT: u64 = 32;
 
@@ -79,6 +80,29 @@ impl TensorInfo {
             device,
         )
     }
+
+    /// Loads the tensor from a memory mapped gguf file, on the cpu the tensor data is not copied
+    /// when properly aligned.
+    ///
+    /// # Safety
+    ///
+    /// The underlying file must not be modified while the tensor is in use, see
+    /// [`memmap2::Mmap`].
+    pub unsafe fn read_mmaped(
+        &self,
+        mmap: &Arc<memmap2::Mmap>,
+        tensor_data_offset: u64,
+        device: &Device,
+    ) -> Result<QTensor> {
+        let offset = (tensor_data_offset + self.offset) as usize;
+        super::ggml_file::qtensor_from_mmap(
+            self.ggml_dtype,
+            mmap,
+            offset,
+            self.shape.dims().to_vec(),
+            device,
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -474,6 +498,27 @@ impl Content {
         };
         tensor_info.read(reader, self.tensor_data_offset, device)
     }
+
+    /// Loads the tensor named `name` from the memory mapped gguf file that this content has been
+    /// read from. On the cpu, the quantized blocks are read directly from the memory mapped file
+    /// so loading a model does not require copying its weights.
+    ///
+    /// # Safety
+    ///
+    /// The underlying file must not be modified while the tensor is in use, see
+    /// [`memmap2::Mmap`].
+    pub unsafe fn tensor_mmaped(
+        &self,
+        mmap: &Arc<memmap2::Mmap>,
+        name: &str,
+        device: &Device,
+    ) -> Result<QTensor> {
+        let tensor_info = match self.tensor_infos.get(name) {
+            Some(tensor_info) => tensor_info,
+            None => crate::bail!("cannot find tensor info for {name}"),
+        };
+        tensor_info.read_mmaped(mmap, self.tensor_data_offset, device)
+    }
 }
 
 fn write_string<W: std::io::Write>(w: &mut W, str: &str) -> Result<()> {
@@ -487,9 +532,24 @@ pub fn write<W: std::io::Seek + std::io::Write>(
     w: &mut W,
     metadata: &[(&str, &Value)],
     tensors: &[(&str, &QTensor)],
+) -> Result<()> {
+    write_with_alignment(w, metadata, tensors, DEFAULT_ALIGNMENT)
+}
+
+// The number of bytes to add after `size` bytes to reach a multiple of `alignment`.
+fn padding(size: usize, alignment: u64) -> usize {
+    let alignment = alignment as usize;
+    (alignment - size % alignment) % alignment
+}
+
+fn write_with_alignment<W: std::io::Seek + std::io::Write>(
+    w: &mut W,
+    metadata: &[(&str, &Value)],
+    tensors: &[(&str, &QTensor)],
+    alignment: u64,
 ) -> Result<()> {
     w.write_u32::<LittleEndian>(0x46554747)?;
-    w.write_u32::<LittleEndian>(2)?; // version 2.
+    w.write_u32::<LittleEndian>(3)?; // version 3.
     w.write_u64::<LittleEndian>(tensors.len() as u64)?;
     w.write_u64::<LittleEndian>(metadata.len() as u64)?;
     for (name, value) in metadata.iter() {
@@ -510,12 +570,10 @@ pub fn write<W: std::io::Seek + std::io::Write>(
         w.write_u64::<LittleEndian>(offset as u64)?;
         offsets.push(offset);
         let size_in_bytes = tensor.storage_size_in_bytes();
-        let padding = 31 - (31 + size_in_bytes) % 32;
-        offset += size_in_bytes + padding;
+        offset += size_in_bytes + padding(size_in_bytes, alignment);
     }
     let pos = w.stream_position()? as usize;
-    let padding = 31 - (31 + pos) % 32;
-    w.write_all(&vec![0u8; padding])?;
+    w.write_all(&vec![0u8; padding(pos, alignment)])?;
     let tensor_start_pos = w.stream_position()? as usize;
     for (offset, (_name, tensor)) in offsets.iter().zip(tensors.iter()) {
         let pos = w.stream_position()? as usize;
@@ -527,8 +585,142 @@ pub fn write<W: std::io::Seek + std::io::Write>(
         let data = tensor.data()?;
         let size_in_bytes = data.len();
         w.write_all(&data)?;
-        let padding = 31 - (31 + size_in_bytes) % 32;
-        w.write_all(&vec![0u8; padding])?;
+        w.write_all(&vec![0u8; padding(size_in_bytes, alignment)])?;
     }
     Ok(())
 }
+
+/// A builder for gguf files, the files are written using version 3 of the format.
+///
+/// The metadata entries are written in insertion order. The tensor data is aligned on
+/// [`Builder::alignment`] bytes and this alignment is stored in the `general.alignment` metadata
+/// entry so that the file can be read back by other gguf implementations such as llama.cpp.
+///
+/// ```rust
+/// use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+/// use candle_core::{Device, Tensor};
+/// # fn main() -> candle_core::Result<()> {
+/// let weight = Tensor::zeros((4, 256), candle_core::DType::F32, &Device::Cpu)?;
+/// let mut builder = gguf_file::Builder::new();
+/// builder
+///     .set_metadata("general.architecture", gguf_file::Value::String("llama".to_string()))?
+///     .set_alignment(64)?
+///     .add_tensor("output.weight", QTensor::quantize(&weight, GgmlDType::Q8_0)?)?;
+/// let mut file = std::io::Cursor::new(vec![]);
+/// builder.write(&mut file)?;
+/// file.set_position(0);
+/// let content = gguf_file::Content::read(&mut file)?;
+/// assert_eq!(content.tensor_data_offset % 64, 0);
+/// # Ok(()) }
+/// ```
+pub struct Builder {
+    alignment: u64,
+    metadata: Vec<(String, Value)>,
+    tensors: Vec<(String, QTensor)>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    // The limits used by ggml, files that exceed them cannot be loaded by llama.cpp.
+    const MAX_DIMS: usize = 4;
+    const MAX_NAME_LEN: usize = 63;
+
+    pub fn new() -> Self {
+        Self {
+            alignment: DEFAULT_ALIGNMENT,
+            metadata: vec![],
+            tensors: vec![],
+        }
+    }
+
+    pub fn alignment(&self) -> u64 {
+        self.alignment
+    }
+
+    /// Sets the alignment of the tensor data in bytes, this must be a non-zero multiple of 8.
+    pub fn set_alignment(&mut self, alignment: u64) -> Result<&mut Self> {
+        if alignment == 0 || alignment % 8 != 0 || alignment > u32::MAX as u64 {
+            crate::bail!("gguf alignment has to be a non-zero multiple of 8, got {alignment}")
+        }
+        self.alignment = alignment;
+        Ok(self)
+    }
+
+    pub fn metadata(&self) -> &[(String, Value)] {
+        &self.metadata
+    }
+
+    /// Sets a metadata entry, replacing the previous value for this key if any. Setting
+    /// `general.alignment` is equivalent to calling [`Builder::set_alignment`].
+    pub fn set_metadata<K: Into<String>>(&mut self, key: K, value: Value) -> Result<&mut Self> {
+        let key = key.into();
+        if key == "general.alignment" {
+            let alignment = match value {
+                Value::U8(v) => v as u64,
+                Value::U16(v) => v as u64,
+                Value::U32(v) => v as u64,
+                Value::U64(v) => v,
+                Value::I8(v) if v >= 0 => v as u64,
+                Value::I16(v) if v >= 0 => v as u64,
+                Value::I32(v) if v >= 0 => v as u64,
+                Value::I64(v) if v >= 0 => v as u64,
+                value => crate::bail!("unexpected value for general.alignment {value:?}"),
+            };
+            return self.set_alignment(alignment);
+        }
+        match self.metadata.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.metadata.push((key, value)),
+        }
+        Ok(self)
+    }
+
+    pub fn tensors(&self) -> &[(String, QTensor)] {
+        &self.tensors
+    }
+
+    /// Adds a tensor to be written, tensor names have to be unique and tensors can have at most
+    /// 4 dimensions.
+    pub fn add_tensor<K: Into<String>>(&mut self, name: K, tensor: QTensor) -> Result<&mut Self> {
+        let name = name.into();
+        if name.len() > Self::MAX_NAME_LEN {
+            crate::bail!(
+                "gguf tensor name {name} is longer than {}",
+                Self::MAX_NAME_LEN
+            )
+        }
+        if tensor.rank() > Self::MAX_DIMS {
+            crate::bail!(
+                "gguf tensor {name} has more than {} dims {:?}",
+                Self::MAX_DIMS,
+                tensor.shape()
+            )
+        }
+        if self.tensors.iter().any(|(n, _)| *n == name) {
+            crate::bail!("duplicate gguf tensor {name}")
+        }
+        self.tensors.push((name, tensor));
+        Ok(self)
+    }
+
+    pub fn write<W: std::io::Seek + std::io::Write>(&self, w: &mut W) -> Result<()> {
+        let alignment = Value::U32(self.alignment as u32);
+        let mut metadata = self
+            .metadata
+            .iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .collect::<Vec<_>>();
+        metadata.push(("general.alignment", &alignment));
+        let tensors = self
+            .tensors
+            .iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .collect::<Vec<_>>();
+        write_with_alignment(w, &metadata, &tensors, self.alignment)
+    }
+}
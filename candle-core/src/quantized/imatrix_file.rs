@@ -0,0 +1,57 @@
+//! Support for the importance matrix files produced by llama.cpp's `imatrix` tool.
+//!
+//! The legacy `.dat` layout is a sequence of little-endian records:
+//! `n_entries: i32`, then for each entry `name_len: i32`, the name bytes, `ncall: i32`,
+//! `nval: i32` and `nval` f32 values holding the accumulated squared activations. The
+//! values are divided by `ncall` on load so that they can be passed directly to
+//! [`super::QTensor::quantize_with_imatrix`].
+
+use crate::Result;
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::HashMap;
+
+fn read_len<R: std::io::Read>(reader: &mut R, what: &str) -> Result<usize> {
+    let v = reader.read_i32::<LittleEndian>()?;
+    if v < 0 {
+        crate::bail!("imatrix: negative {what} {v}")
+    }
+    Ok(v as usize)
+}
+
+/// Reads an importance matrix, returning the per-column weights for each tensor name.
+pub fn read<R: std::io::Read>(reader: &mut R) -> Result<HashMap<String, Vec<f32>>> {
+    let n_entries = read_len(reader, "entry count")?;
+    let mut imatrix = HashMap::with_capacity(n_entries);
+    for _ in 0..n_entries {
+        let name_len = read_len(reader, "name length")?;
+        let mut name = vec![0u8; name_len];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let ncall = read_len(reader, "call count")?;
+        let nval = read_len(reader, "value count")?;
+        let mut values = vec![0f32; nval];
+        reader.read_f32_into::<LittleEndian>(&mut values)?;
+        if ncall > 0 {
+            let ncall = ncall as f32;
+            values.iter_mut().for_each(|v| *v /= ncall);
+        }
+        imatrix.insert(name, values);
+    }
+    Ok(imatrix)
+}
+
+/// Writes an importance matrix in the llama.cpp `.dat` layout with a call count of one.
+pub fn write<W: std::io::Write>(writer: &mut W, imatrix: &[(&str, &[f32])]) -> Result<()> {
+    use byteorder::WriteBytesExt;
+    writer.write_i32::<LittleEndian>(imatrix.len() as i32)?;
+    for (name, values) in imatrix.iter() {
+        writer.write_i32::<LittleEndian>(name.len() as i32)?;
+        writer.write_all(name.as_bytes())?;
+        writer.write_i32::<LittleEndian>(1)?;
+        writer.write_i32::<LittleEndian>(values.len() as i32)?;
+        for v in values.iter() {
+            writer.write_f32::<LittleEndian>(*v)?;
+        }
+    }
+    Ok(())
+}
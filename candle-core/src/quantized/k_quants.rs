@@ -1,6 +1,7 @@
 use super::utils::{
-    get_scale_min_k4, group_for_dequantization, group_for_quantization, make_q3_quants,
-    make_qkx1_quants, make_qx_quants, nearest_int,
+    best_index_int8, get_scale_min_k4, group_for_dequantization, group_for_quantization,
+    group_for_quantization_imatrix, imatrix_weights, make_iq4_scale, make_q3_quants,
+    make_qkx1_quants, make_qkx2_quants, make_qx_quants, nearest_int,
 };
 use super::GgmlDType;
 use crate::Result;
@@ -18,6 +19,12 @@ pub const QK5_0: usize = 32;
 pub const QK5_1: usize = 32;
 pub const QK8_0: usize = 32;
 pub const QK8_1: usize = 32;
+pub const QK4_NL: usize = 32;
+
+/// The non-linear grid shared by the IQ4_NL and IQ4_XS quantizations.
+pub(crate) const KVALUES_IQ4NL: [i8; 16] = [
+    -127, -104, -83, -65, -49, -35, -22, -10, 1, 13, 25, 38, 53, 69, 89, 113,
+];
 
 pub trait GgmlType: Sized + Clone + Send + Sync {
     const DTYPE: GgmlDType;
@@ -31,6 +38,20 @@ pub trait GgmlType: Sized + Clone + Send + Sync {
     fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()>;
     fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()>;
 
+    /// Quantizes `xs`, made of rows of `n_per_row` values, using per-column importance weights
+    /// such as the ones produced by llama.cpp's imatrix tool to drive the search for the block
+    /// scales. Types that do not support this fall back to `from_float`.
+    fn from_float_imatrix(
+        xs: &[f32],
+        ys: &mut [Self],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        // The weights are not used but the inputs are still validated.
+        group_for_quantization_imatrix(xs, ys, imatrix_weights, n_per_row)?;
+        Self::from_float(xs, ys)
+    }
+
     /// Dot product used as a building block for quantized mat-mul.
     /// n is the number of elements to be considered.
     fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32>;
@@ -154,6 +175,24 @@ pub struct BlockQ8K {
 }
 const _: () = assert!(4 + QK_K + QK_K / 16 * 2 == std::mem::size_of::<BlockQ8K>());
 
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ4NL {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK4_NL / 2],
+}
+const _: () = assert!(std::mem::size_of::<BlockIQ4NL>() == 18);
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ4XS {
+    pub(crate) d: f16,
+    pub(crate) scales_h: u16,
+    pub(crate) scales_l: [u8; QK_K / 64],
+    pub(crate) qs: [u8; QK_K / 2],
+}
+const _: () = assert!(2 + 2 + QK_K / 64 + QK_K / 2 == std::mem::size_of::<BlockIQ4XS>());
+
 impl GgmlType for BlockQ4_0 {
     const DTYPE: GgmlDType = GgmlDType::Q4_0;
     const BLCK_SIZE: usize = QK4_0;
@@ -695,6 +734,77 @@ impl GgmlType for BlockQ8_1 {
     }
 }
 
+impl BlockQ2K {
+    fn quantize_block(block: &mut Self, x: &[f32], qw: Option<&[f32]>) {
+        const Q4SCALE: f32 = 15.0;
+
+        //calculate scales and mins
+        let mut mins: [f32; QK_K / 16] = [0.0; QK_K / 16];
+        let mut scales: [f32; QK_K / 16] = [0.0; QK_K / 16];
+
+        let weights = qw.map(|qw| imatrix_weights(x, qw));
+        for (j, x_scale_slice) in x.chunks(16).enumerate() {
+            (scales[j], mins[j]) = match &weights {
+                None => make_qkx1_quants(3, 5, x_scale_slice),
+                Some(w) => {
+                    let w = &w[16 * j..16 * (j + 1)];
+                    make_qkx2_quants(3, x_scale_slice, w, -0.5, 0.1, 15, true)
+                }
+            };
+        }
+        // get max scale and max min and ensure they are >= 0.0
+        let max_scale = scales.iter().fold(0.0, |max, &val| val.max(max));
+        let max_min = mins.iter().fold(0.0, |max, &val| val.max(max));
+
+        if max_scale > 0.0 {
+            let iscale = Q4SCALE / max_scale;
+            for (j, scale) in scales.iter().enumerate().take(QK_K / 16) {
+                block.scales[j] = nearest_int(iscale * scale) as u8;
+            }
+            block.d = f16::from_f32(max_scale / Q4SCALE);
+        } else {
+            for j in 0..QK_K / 16 {
+                block.scales[j] = 0;
+            }
+            block.d = f16::from_f32(0.0);
+        }
+
+        if max_min > 0.0 {
+            let iscale = Q4SCALE / max_min;
+            for (j, scale) in block.scales.iter_mut().enumerate() {
+                let l = nearest_int(iscale * mins[j]) as u8;
+                *scale |= l << 4;
+            }
+            block.dmin = f16::from_f32(max_min / Q4SCALE);
+        } else {
+            block.dmin = f16::from_f32(0.0);
+        }
+
+        let mut big_l: [u8; QK_K] = [0; QK_K];
+
+        for j in 0..QK_K / 16 {
+            let d = block.d.to_f32() * (block.scales[j] & 0xF) as f32;
+            if d == 0.0 {
+                continue;
+            }
+            let dm = block.dmin.to_f32() * (block.scales[j] >> 4) as f32;
+            for ii in 0..16 {
+                let ll = nearest_int((x[16 * j + ii] + dm) / d).clamp(0, 3);
+                big_l[16 * j + ii] = ll as u8;
+            }
+        }
+
+        for j in (0..QK_K).step_by(128) {
+            for ll in 0..32 {
+                block.qs[j / 4 + ll] = big_l[j + ll]
+                    | (big_l[j + ll + 32] << 2)
+                    | (big_l[j + ll + 64] << 4)
+                    | (big_l[j + ll + 96] << 6);
+            }
+        }
+    }
+}
+
 impl GgmlType for BlockQ2K {
     const DTYPE: GgmlDType = GgmlDType::Q2K;
     const BLCK_SIZE: usize = QK_K;
@@ -767,66 +877,20 @@ impl GgmlType for BlockQ2K {
 
     // https://github.com/ggerganov/llama.cpp/blob/8183159cf3def112f6d1fe94815fce70e1bffa12/k_quants.c#L279
     fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
-        const Q4SCALE: f32 = 15.0;
-
         for (block, x) in group_for_quantization(xs, ys)? {
-            //calculate scales and mins
-            let mut mins: [f32; QK_K / 16] = [0.0; QK_K / 16];
-            let mut scales: [f32; QK_K / 16] = [0.0; QK_K / 16];
-
-            for (j, x_scale_slice) in x.chunks(16).enumerate() {
-                (scales[j], mins[j]) = make_qkx1_quants(3, 5, x_scale_slice);
-            }
-            // get max scale and max min and ensure they are >= 0.0
-            let max_scale = scales.iter().fold(0.0, |max, &val| val.max(max));
-            let max_min = mins.iter().fold(0.0, |max, &val| val.max(max));
-
-            if max_scale > 0.0 {
-                let iscale = Q4SCALE / max_scale;
-                for (j, scale) in scales.iter().enumerate().take(QK_K / 16) {
-                    block.scales[j] = nearest_int(iscale * scale) as u8;
-                }
-                block.d = f16::from_f32(max_scale / Q4SCALE);
-            } else {
-                for j in 0..QK_K / 16 {
-                    block.scales[j] = 0;
-                }
-                block.d = f16::from_f32(0.0);
-            }
-
-            if max_min > 0.0 {
-                let iscale = Q4SCALE / max_min;
-                for (j, scale) in block.scales.iter_mut().enumerate() {
-                    let l = nearest_int(iscale * mins[j]) as u8;
-                    *scale |= l << 4;
-                }
-                block.dmin = f16::from_f32(max_min / Q4SCALE);
-            } else {
-                block.dmin = f16::from_f32(0.0);
-            }
-
-            let mut big_l: [u8; QK_K] = [0; QK_K];
-
-            for j in 0..QK_K / 16 {
-                let d = block.d.to_f32() * (block.scales[j] & 0xF) as f32;
-                if d == 0.0 {
-                    continue;
-                }
-                let dm = block.dmin.to_f32() * (block.scales[j] >> 4) as f32;
-                for ii in 0..16 {
-                    let ll = nearest_int((x[16 * j + ii] + dm) / d).clamp(0, 3);
-                    big_l[16 * j + ii] = ll as u8;
-                }
-            }
+            Self::quantize_block(block, x, None)
+        }
+        Ok(())
+    }
 
-            for j in (0..QK_K).step_by(128) {
-                for ll in 0..32 {
-                    block.qs[j / 4 + ll] = big_l[j + ll]
-                        | (big_l[j + ll + 32] << 2)
-                        | (big_l[j + ll + 64] << 4)
-                        | (big_l[j + ll + 96] << 6);
-                }
-            }
+    fn from_float_imatrix(
+        xs: &[f32],
+        ys: &mut [Self],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        for (block, x, qw) in group_for_quantization_imatrix(xs, ys, imatrix_weights, n_per_row)? {
+            Self::quantize_block(block, x, Some(qw))
         }
         Ok(())
     }
@@ -871,6 +935,96 @@ impl GgmlType for BlockQ2K {
     }
 }
 
+impl BlockQ3K {
+    fn quantize_block(block: &mut Self, x: &[f32], qw: Option<&[f32]>) {
+        let mut scales: [f32; QK_K / 16] = [0.0; QK_K / 16];
+        let weights = qw.map(|qw| imatrix_weights(x, qw));
+        for (j, x_scale_slice) in x.chunks_exact(16).enumerate() {
+            scales[j] = match &weights {
+                None => make_q3_quants(x_scale_slice, 4, true),
+                Some(w) => {
+                    let w = &w[16 * j..16 * (j + 1)];
+                    let mut l = [0i8; 16];
+                    unsafe {
+                        make_qx_quants(16, 4, x_scale_slice.as_ptr(), l.as_mut_ptr(), 1, Some(w))
+                    }
+                }
+            };
+        }
+
+        // Get max scale by absolute value.
+        let mut max_scale: f32 = 0.0;
+        for &scale in scales.iter() {
+            if scale.abs() > max_scale.abs() {
+                max_scale = scale;
+            }
+        }
+
+        block.scales.fill(0);
+
+        if max_scale != 0.0 {
+            let iscale = -32.0 / max_scale;
+            for (j, scale) in scales.iter().enumerate() {
+                let l_val = nearest_int(iscale * scale);
+                let l_val = l_val.clamp(-32, 31) + 32;
+                if j < 8 {
+                    block.scales[j] = (l_val & 0xF) as u8;
+                } else {
+                    block.scales[j - 8] |= ((l_val & 0xF) << 4) as u8;
+                }
+                let l_val = l_val >> 4;
+                block.scales[j % 4 + 8] |= (l_val << (2 * (j / 4))) as u8;
+            }
+            block.d = f16::from_f32(1.0 / iscale);
+        } else {
+            block.d = f16::from_f32(0.0);
+        }
+
+        let mut l: [i8; QK_K] = [0; QK_K];
+
+        for j in 0..QK_K / 16 {
+            let sc = if j < 8 {
+                block.scales[j] & 0xF
+            } else {
+                block.scales[j - 8] >> 4
+            };
+            let sc = (sc | (((block.scales[8 + j % 4] >> (2 * (j / 4))) & 3) << 4)) as i8 - 32;
+            let d = block.d.to_f32() * sc as f32;
+            if d != 0.0 {
+                for ii in 0..16 {
+                    let l_val = nearest_int(x[16 * j + ii] / d);
+                    l[16 * j + ii] = (l_val.clamp(-4, 3) + 4) as i8;
+                }
+            }
+        }
+
+        block.hmask.fill(0);
+        let mut m = 0;
+        let mut hm = 1;
+
+        for ll in l.iter_mut() {
+            if *ll > 3 {
+                block.hmask[m] |= hm;
+                *ll -= 4;
+            }
+            m += 1;
+            if m == QK_K / 8 {
+                m = 0;
+                hm <<= 1;
+            }
+        }
+
+        for j in (0..QK_K).step_by(128) {
+            for l_val in 0..32 {
+                block.qs[j / 4 + l_val] = (l[j + l_val]
+                    | (l[j + l_val + 32] << 2)
+                    | (l[j + l_val + 64] << 4)
+                    | (l[j + l_val + 96] << 6)) as u8;
+            }
+        }
+    }
+}
+
 impl GgmlType for BlockQ3K {
     const DTYPE: GgmlDType = GgmlDType::Q3K;
     const BLCK_SIZE: usize = QK_K;
@@ -1010,84 +1164,20 @@ impl GgmlType for BlockQ3K {
 
     fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
         for (block, x) in group_for_quantization(xs, ys)? {
-            let mut scales: [f32; QK_K / 16] = [0.0; QK_K / 16];
-            for (j, x_scale_slice) in x.chunks_exact(16).enumerate() {
-                scales[j] = make_q3_quants(x_scale_slice, 4, true);
-            }
-
-            // Get max scale by absolute value.
-            let mut max_scale: f32 = 0.0;
-            for &scale in scales.iter() {
-                if scale.abs() > max_scale.abs() {
-                    max_scale = scale;
-                }
-            }
-
-            block.scales.fill(0);
-
-            if max_scale != 0.0 {
-                let iscale = -32.0 / max_scale;
-                for (j, scale) in scales.iter().enumerate() {
-                    let l_val = nearest_int(iscale * scale);
-                    let l_val = l_val.clamp(-32, 31) + 32;
-                    if j < 8 {
-                        block.scales[j] = (l_val & 0xF) as u8;
-                    } else {
-                        block.scales[j - 8] |= ((l_val & 0xF) << 4) as u8;
-                    }
-                    let l_val = l_val >> 4;
-                    block.scales[j % 4 + 8] |= (l_val << (2 * (j / 4))) as u8;
-                }
-                block.d = f16::from_f32(1.0 / iscale);
-            } else {
-                block.d = f16::from_f32(0.0);
-            }
-
-            let mut l: [i8; QK_K] = [0; QK_K];
-
-            for j in 0..QK_K / 16 {
-                let sc = if j < 8 {
-                    block.scales[j] & 0xF
-                } else {
-                    block.scales[j - 8] >> 4
-                };
-                let sc = (sc | (((block.scales[8 + j % 4] >> (2 * (j / 4))) & 3) << 4)) as i8 - 32;
-                let d = block.d.to_f32() * sc as f32;
-                if d != 0.0 {
-                    for ii in 0..16 {
-                        let l_val = nearest_int(x[16 * j + ii] / d);
-                        l[16 * j + ii] = (l_val.clamp(-4, 3) + 4) as i8;
-                    }
-                }
-            }
-
-            block.hmask.fill(0);
-            let mut m = 0;
-            let mut hm = 1;
-
-            for ll in l.iter_mut() {
-                if *ll > 3 {
-                    block.hmask[m] |= hm;
-                    *ll -= 4;
-                }
-                m += 1;
-                if m == QK_K / 8 {
-                    m = 0;
-                    hm <<= 1;
-                }
-            }
-
-            for j in (0..QK_K).step_by(128) {
-                for l_val in 0..32 {
-                    block.qs[j / 4 + l_val] = (l[j + l_val]
-                        | (l[j + l_val + 32] << 2)
-                        | (l[j + l_val + 64] << 4)
-                        | (l[j + l_val + 96] << 6))
-                        as u8;
-                }
-            }
+            Self::quantize_block(block, x, None)
         }
+        Ok(())
+    }
 
+    fn from_float_imatrix(
+        xs: &[f32],
+        ys: &mut [Self],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        for (block, x, qw) in group_for_quantization_imatrix(xs, ys, imatrix_weights, n_per_row)? {
+            Self::quantize_block(block, x, Some(qw))
+        }
         Ok(())
     }
 
@@ -1149,6 +1239,73 @@ impl GgmlType for BlockQ3K {
     }
 }
 
+impl BlockQ4K {
+    fn quantize_block(block: &mut Self, x: &[f32], qw: Option<&[f32]>) {
+        let mut mins: [f32; QK_K / 32] = [0.0; QK_K / 32];
+        let mut scales: [f32; QK_K / 32] = [0.0; QK_K / 32];
+
+        let weights = qw.map(|qw| imatrix_weights(x, qw));
+        for (j, x_scale_slice) in x.chunks_exact(32).enumerate() {
+            (scales[j], mins[j]) = match &weights {
+                None => make_qkx1_quants(15, 5, x_scale_slice),
+                Some(w) => {
+                    let w = &w[32 * j..32 * (j + 1)];
+                    make_qkx2_quants(15, x_scale_slice, w, -1.0, 0.1, 20, false)
+                }
+            };
+        }
+
+        // get max scale and max min and ensure they are >= 0.0
+        let max_scale = scales.iter().fold(0.0, |max, &val| val.max(max));
+        let max_min = mins.iter().fold(0.0, |max, &val| val.max(max));
+
+        let inv_scale = if max_scale > 0.0 {
+            63.0 / max_scale
+        } else {
+            0.0
+        };
+        let inv_min = if max_min > 0.0 { 63.0 / max_min } else { 0.0 };
+
+        for j in 0..QK_K / 32 {
+            let ls = nearest_int(inv_scale * scales[j]).min(63) as u8;
+            let lm = nearest_int(inv_min * mins[j]).min(63) as u8;
+            if j < 4 {
+                block.scales[j] = ls;
+                block.scales[j + 4] = lm;
+            } else {
+                block.scales[j + 4] = (ls & 0xF) | ((lm & 0xF) << 4);
+                block.scales[j - 4] |= (ls >> 4) << 6;
+                block.scales[j] |= (lm >> 4) << 6;
+            }
+        }
+
+        block.d = f16::from_f32(max_scale / 63.0);
+        block.dmin = f16::from_f32(max_min / 63.0);
+
+        let mut l: [u8; QK_K] = [0; QK_K];
+
+        for j in 0..QK_K / 32 {
+            let (sc, m) = get_scale_min_k4(j, &block.scales);
+            let d = block.d.to_f32() * sc as f32;
+            if d != 0.0 {
+                let dm = block.dmin.to_f32() * m as f32;
+                for ii in 0..32 {
+                    let l_val = nearest_int((x[32 * j + ii] + dm) / d);
+                    l[32 * j + ii] = l_val.clamp(0, 15) as u8;
+                }
+            }
+        }
+
+        let q = &mut block.qs;
+        for j in (0..QK_K).step_by(64) {
+            for l_val in 0..32 {
+                let offset_index = (j / 64) * 32 + l_val;
+                q[offset_index] = l[j + l_val] | (l[j + l_val + 32] << 4);
+            }
+        }
+    }
+}
+
 impl GgmlType for BlockQ4K {
     const DTYPE: GgmlDType = GgmlDType::Q4K;
     const BLCK_SIZE: usize = QK_K;
@@ -1251,61 +1408,19 @@ impl GgmlType for BlockQ4K {
 
     fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
         for (block, x) in group_for_quantization(xs, ys)? {
-            let mut mins: [f32; QK_K / 32] = [0.0; QK_K / 32];
-            let mut scales: [f32; QK_K / 32] = [0.0; QK_K / 32];
-
-            for (j, x_scale_slice) in x.chunks_exact(32).enumerate() {
-                (scales[j], mins[j]) = make_qkx1_quants(15, 5, x_scale_slice);
-            }
-
-            // get max scale and max min and ensure they are >= 0.0
-            let max_scale = scales.iter().fold(0.0, |max, &val| val.max(max));
-            let max_min = mins.iter().fold(0.0, |max, &val| val.max(max));
-
-            let inv_scale = if max_scale > 0.0 {
-                63.0 / max_scale
-            } else {
-                0.0
-            };
-            let inv_min = if max_min > 0.0 { 63.0 / max_min } else { 0.0 };
-
-            for j in 0..QK_K / 32 {
-                let ls = nearest_int(inv_scale * scales[j]).min(63) as u8;
-                let lm = nearest_int(inv_min * mins[j]).min(63) as u8;
-                if j < 4 {
-                    block.scales[j] = ls;
-                    block.scales[j + 4] = lm;
-                } else {
-                    block.scales[j + 4] = (ls & 0xF) | ((lm & 0xF) << 4);
-                    block.scales[j - 4] |= (ls >> 4) << 6;
-                    block.scales[j] |= (lm >> 4) << 6;
-                }
-            }
-
-            block.d = f16::from_f32(max_scale / 63.0);
-            block.dmin = f16::from_f32(max_min / 63.0);
-
-            let mut l: [u8; QK_K] = [0; QK_K];
-
-            for j in 0..QK_K / 32 {
-                let (sc, m) = get_scale_min_k4(j, &block.scales);
-                let d = block.d.to_f32() * sc as f32;
-                if d != 0.0 {
-                    let dm = block.dmin.to_f32() * m as f32;
-                    for ii in 0..32 {
-                        let l_val = nearest_int((x[32 * j + ii] + dm) / d);
-                        l[32 * j + ii] = l_val.clamp(0, 15) as u8;
-                    }
-                }
-            }
+            Self::quantize_block(block, x, None)
+        }
+        Ok(())
+    }
 
-            let q = &mut block.qs;
-            for j in (0..QK_K).step_by(64) {
-                for l_val in 0..32 {
-                    let offset_index = (j / 64) * 32 + l_val;
-                    q[offset_index] = l[j + l_val] | (l[j + l_val + 32] << 4);
-                }
-            }
+    fn from_float_imatrix(
+        xs: &[f32],
+        ys: &mut [Self],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        for (block, x, qw) in group_for_quantization_imatrix(xs, ys, imatrix_weights, n_per_row)? {
+            Self::quantize_block(block, x, Some(qw))
         }
         Ok(())
     }
@@ -1342,6 +1457,88 @@ impl GgmlType for BlockQ4K {
 }
 
 // https://github.com/ggerganov/llama.cpp/blob/8183159cf3def112f6d1fe94815fce70e1bffa12/k_quants.c#L928
+impl BlockQ5K {
+    fn quantize_block(block: &mut Self, x: &[f32], qw: Option<&[f32]>) {
+        let mut mins: [f32; QK_K / 32] = [0.0; QK_K / 32];
+        let mut scales: [f32; QK_K / 32] = [0.0; QK_K / 32];
+
+        let weights = qw.map(|qw| imatrix_weights(x, qw));
+        for (j, x_scale_slice) in x.chunks_exact(32).enumerate() {
+            (scales[j], mins[j]) = match &weights {
+                None => make_qkx1_quants(31, 5, x_scale_slice),
+                Some(w) => {
+                    let w = &w[32 * j..32 * (j + 1)];
+                    make_qkx2_quants(31, x_scale_slice, w, -0.5, 0.1, 15, false)
+                }
+            };
+        }
+
+        // get max scale and max min and ensure they are >= 0.0
+        let max_scale = scales.iter().fold(0.0, |max, &val| val.max(max));
+        let max_min = mins.iter().fold(0.0, |max, &val| val.max(max));
+
+        let inv_scale = if max_scale > 0.0 {
+            63.0 / max_scale
+        } else {
+            0.0
+        };
+        let inv_min = if max_min > 0.0 { 63.0 / max_min } else { 0.0 };
+        for j in 0..QK_K / 32 {
+            let ls = nearest_int(inv_scale * scales[j]).min(63) as u8;
+            let lm = nearest_int(inv_min * mins[j]).min(63) as u8;
+            if j < 4 {
+                block.scales[j] = ls;
+                block.scales[j + 4] = lm;
+            } else {
+                block.scales[j + 4] = (ls & 0xF) | ((lm & 0xF) << 4);
+                block.scales[j - 4] |= (ls >> 4) << 6;
+                block.scales[j] |= (lm >> 4) << 6;
+            }
+        }
+        block.d = f16::from_f32(max_scale / 63.0);
+        block.dmin = f16::from_f32(max_min / 63.0);
+
+        let mut l: [u8; QK_K] = [0; QK_K];
+        for j in 0..QK_K / 32 {
+            let (sc, m) = get_scale_min_k4(j, &block.scales);
+            let d = block.d.to_f32() * sc as f32;
+            if d == 0.0 {
+                continue;
+            }
+            let dm = block.dmin.to_f32() * m as f32;
+            for ii in 0..32 {
+                let ll = nearest_int((x[32 * j + ii] + dm) / d);
+                l[32 * j + ii] = ll.clamp(0, 31) as u8;
+            }
+        }
+
+        let qh = &mut block.qh;
+        let ql = &mut block.qs;
+        qh.fill(0);
+
+        let mut m1 = 1;
+        let mut m2 = 2;
+        for n in (0..QK_K).step_by(64) {
+            let offset = (n / 64) * 32;
+            for j in 0..32 {
+                let mut l1 = l[n + j];
+                if l1 > 15 {
+                    l1 -= 16;
+                    qh[j] |= m1;
+                }
+                let mut l2 = l[n + j + 32];
+                if l2 > 15 {
+                    l2 -= 16;
+                    qh[j] |= m2;
+                }
+                ql[offset + j] = l1 | (l2 << 4);
+            }
+            m1 <<= 2;
+            m2 <<= 2;
+        }
+    }
+}
+
 impl GgmlType for BlockQ5K {
     const DTYPE: GgmlDType = GgmlDType::Q5K;
     const BLCK_SIZE: usize = QK_K;
@@ -1449,78 +1646,20 @@ impl GgmlType for BlockQ5K {
     // https://github.com/ggerganov/llama.cpp/blob/8183159cf3def112f6d1fe94815fce70e1bffa12/k_quants.c#L793
     fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
         for (block, x) in group_for_quantization(xs, ys)? {
-            let mut mins: [f32; QK_K / 32] = [0.0; QK_K / 32];
-            let mut scales: [f32; QK_K / 32] = [0.0; QK_K / 32];
-
-            for (j, x_scale_slice) in x.chunks_exact(32).enumerate() {
-                (scales[j], mins[j]) = make_qkx1_quants(31, 5, x_scale_slice);
-            }
-
-            // get max scale and max min and ensure they are >= 0.0
-            let max_scale = scales.iter().fold(0.0, |max, &val| val.max(max));
-            let max_min = mins.iter().fold(0.0, |max, &val| val.max(max));
-
-            let inv_scale = if max_scale > 0.0 {
-                63.0 / max_scale
-            } else {
-                0.0
-            };
-            let inv_min = if max_min > 0.0 { 63.0 / max_min } else { 0.0 };
-            for j in 0..QK_K / 32 {
-                let ls = nearest_int(inv_scale * scales[j]).min(63) as u8;
-                let lm = nearest_int(inv_min * mins[j]).min(63) as u8;
-                if j < 4 {
-                    block.scales[j] = ls;
-                    block.scales[j + 4] = lm;
-                } else {
-                    block.scales[j + 4] = (ls & 0xF) | ((lm & 0xF) << 4);
-                    block.scales[j - 4] |= (ls >> 4) << 6;
-                    block.scales[j] |= (lm >> 4) << 6;
-                }
-            }
-            block.d = f16::from_f32(max_scale / 63.0);
-            block.dmin = f16::from_f32(max_min / 63.0);
-
-            let mut l: [u8; QK_K] = [0; QK_K];
-            for j in 0..QK_K / 32 {
-                let (sc, m) = get_scale_min_k4(j, &block.scales);
-                let d = block.d.to_f32() * sc as f32;
-                if d == 0.0 {
-                    continue;
-                }
-                let dm = block.dmin.to_f32() * m as f32;
-                for ii in 0..32 {
-                    let ll = nearest_int((x[32 * j + ii] + dm) / d);
-                    l[32 * j + ii] = ll.clamp(0, 31) as u8;
-                }
-            }
-
-            let qh = &mut block.qh;
-            let ql = &mut block.qs;
-            qh.fill(0);
-
-            let mut m1 = 1;
-            let mut m2 = 2;
-            for n in (0..QK_K).step_by(64) {
-                let offset = (n / 64) * 32;
-                for j in 0..32 {
-                    let mut l1 = l[n + j];
-                    if l1 > 15 {
-                        l1 -= 16;
-                        qh[j] |= m1;
-                    }
-                    let mut l2 = l[n + j + 32];
-                    if l2 > 15 {
-                        l2 -= 16;
-                        qh[j] |= m2;
-                    }
-                    ql[offset + j] = l1 | (l2 << 4);
-                }
-                m1 <<= 2;
-                m2 <<= 2;
-            }
+            Self::quantize_block(block, x, None)
         }
+        Ok(())
+    }
 
+    fn from_float_imatrix(
+        xs: &[f32],
+        ys: &mut [Self],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        for (block, x, qw) in group_for_quantization_imatrix(xs, ys, imatrix_weights, n_per_row)? {
+            Self::quantize_block(block, x, Some(qw))
+        }
         Ok(())
     }
 
@@ -1563,6 +1702,69 @@ impl GgmlType for BlockQ5K {
     }
 }
 
+impl BlockQ6K {
+    fn quantize_block(y: &mut Self, xs: &[f32], qw: Option<&[f32]>) {
+        let mut l = [0i8; QK_K];
+        let mut scales = [0f32; QK_K / 16];
+        let weights = qw.map(|qw| imatrix_weights(xs, qw));
+        let x = xs.as_ptr();
+        let l = l.as_mut_ptr();
+        unsafe {
+            let mut max_scale = 0f32;
+            let mut max_abs_scale = 0f32;
+            for (ib, scale_) in scales.iter_mut().enumerate() {
+                let qw = weights.as_ref().map(|w| &w[16 * ib..16 * (ib + 1)]);
+                let scale = make_qx_quants(16, 32, x.add(16 * ib), l.add(16 * ib), 1, qw);
+                *scale_ = scale;
+                let abs_scale = scale.abs();
+                if abs_scale > max_abs_scale {
+                    max_abs_scale = abs_scale;
+                    max_scale = scale
+                }
+            }
+
+            let iscale = -128f32 / max_scale;
+            y.d = f16::from_f32(1.0 / iscale);
+
+            for (y_scale, scale) in y.scales.iter_mut().zip(scales.iter()) {
+                *y_scale = nearest_int(iscale * scale).min(127) as i8
+            }
+
+            for (j, &y_scale) in y.scales.iter().enumerate() {
+                let d = y.d.to_f32() * y_scale as f32;
+                if d == 0. {
+                    continue;
+                }
+                for ii in 0..16 {
+                    let ll = nearest_int(*x.add(16 * j + ii) / d).clamp(-32, 31);
+                    *l.add(16 * j + ii) = (ll + 32) as i8
+                }
+            }
+
+            let mut ql = y.ql.as_mut_ptr();
+            let mut qh = y.qh.as_mut_ptr();
+
+            for j in (0..QK_K).step_by(128) {
+                for l_idx in 0..32 {
+                    let q1 = *l.add(j + l_idx) & 0xF;
+                    let q2 = *l.add(j + l_idx + 32) & 0xF;
+                    let q3 = *l.add(j + l_idx + 64) & 0xF;
+                    let q4 = *l.add(j + l_idx + 96) & 0xF;
+                    *ql.add(l_idx) = (q1 | (q3 << 4)) as u8;
+                    *ql.add(l_idx + 32) = (q2 | (q4 << 4)) as u8;
+                    *qh.add(l_idx) = ((*l.add(j + l_idx) >> 4)
+                        | ((*l.add(j + l_idx + 32) >> 4) << 2)
+                        | ((*l.add(j + l_idx + 64) >> 4) << 4)
+                        | ((*l.add(j + l_idx + 96) >> 4) << 6))
+                        as u8;
+                }
+                ql = ql.add(64);
+                qh = qh.add(32);
+            }
+        }
+    }
+}
+
 impl GgmlType for BlockQ6K {
     const DTYPE: GgmlDType = GgmlDType::Q6K;
     const BLCK_SIZE: usize = QK_K;
@@ -1649,65 +1851,20 @@ impl GgmlType for BlockQ6K {
                 Self::BLCK_SIZE
             )
         }
-        let mut l = [0i8; QK_K];
-        let mut scales = [0f32; QK_K / 16];
-        let mut x = xs.as_ptr();
-        let l = l.as_mut_ptr();
-        unsafe {
-            for y in ys.iter_mut() {
-                let mut max_scale = 0f32;
-                let mut max_abs_scale = 0f32;
-                for (ib, scale_) in scales.iter_mut().enumerate() {
-                    let scale = make_qx_quants(16, 32, x.add(16 * ib), l.add(16 * ib), 1);
-                    *scale_ = scale;
-                    let abs_scale = scale.abs();
-                    if abs_scale > max_abs_scale {
-                        max_abs_scale = abs_scale;
-                        max_scale = scale
-                    }
-                }
-
-                let iscale = -128f32 / max_scale;
-                y.d = f16::from_f32(1.0 / iscale);
-
-                for (y_scale, scale) in y.scales.iter_mut().zip(scales.iter()) {
-                    *y_scale = nearest_int(iscale * scale).min(127) as i8
-                }
-
-                for (j, &y_scale) in y.scales.iter().enumerate() {
-                    let d = y.d.to_f32() * y_scale as f32;
-                    if d == 0. {
-                        continue;
-                    }
-                    for ii in 0..16 {
-                        let ll = nearest_int(*x.add(16 * j + ii) / d).clamp(-32, 31);
-                        *l.add(16 * j + ii) = (ll + 32) as i8
-                    }
-                }
-
-                let mut ql = y.ql.as_mut_ptr();
-                let mut qh = y.qh.as_mut_ptr();
-
-                for j in (0..QK_K).step_by(128) {
-                    for l_idx in 0..32 {
-                        let q1 = *l.add(j + l_idx) & 0xF;
-                        let q2 = *l.add(j + l_idx + 32) & 0xF;
-                        let q3 = *l.add(j + l_idx + 64) & 0xF;
-                        let q4 = *l.add(j + l_idx + 96) & 0xF;
-                        *ql.add(l_idx) = (q1 | (q3 << 4)) as u8;
-                        *ql.add(l_idx + 32) = (q2 | (q4 << 4)) as u8;
-                        *qh.add(l_idx) = ((*l.add(j + l_idx) >> 4)
-                            | ((*l.add(j + l_idx + 32) >> 4) << 2)
-                            | ((*l.add(j + l_idx + 64) >> 4) << 4)
-                            | ((*l.add(j + l_idx + 96) >> 4) << 6))
-                            as u8;
-                    }
-                    ql = ql.add(64);
-                    qh = qh.add(32);
-                }
+        for (y, x) in ys.iter_mut().zip(xs.chunks_exact(QK_K)) {
+            Self::quantize_block(y, x, None)
+        }
+        Ok(())
+    }
 
-                x = x.add(QK_K)
-            }
+    fn from_float_imatrix(
+        xs: &[f32],
+        ys: &mut [Self],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        for (block, x, qw) in group_for_quantization_imatrix(xs, ys, imatrix_weights, n_per_row)? {
+            Self::quantize_block(block, x, Some(qw))
         }
         Ok(())
     }
@@ -1838,6 +1995,239 @@ impl GgmlType for BlockQ8K {
     }
 }
 
+impl BlockIQ4XS {
+    /// The 6 bits scale for sub-block `ib`, the bias of 32 is not removed.
+    #[inline(always)]
+    pub(crate) fn scale(&self, ib: usize) -> i32 {
+        let ls_l = (self.scales_l[ib / 2] >> (4 * (ib % 2))) & 0xf;
+        let ls_h = ((self.scales_h >> (2 * ib)) & 3) as u8;
+        (ls_l | (ls_h << 4)) as i32
+    }
+}
+
+impl BlockIQ4NL {
+    fn quantize_block(block: &mut Self, x: &[f32], qw: Option<&[f32]>) {
+        // quantize_row_iq4_nl
+        let weights = match qw {
+            None => x.iter().map(|x| x * x).collect(),
+            Some(qw) => imatrix_weights(x, qw),
+        };
+        let d = make_iq4_scale(x, &weights, &KVALUES_IQ4NL, 7);
+        let id = if d != 0. { 1. / d } else { 0. };
+        block.d = f16::from_f32(d);
+        for (j, q) in block.qs.iter_mut().enumerate() {
+            let l0 = best_index_int8(&KVALUES_IQ4NL, id * x[j]) as u8;
+            let l1 = best_index_int8(&KVALUES_IQ4NL, id * x[j + QK4_NL / 2]) as u8;
+            *q = l0 | (l1 << 4)
+        }
+    }
+}
+
+impl GgmlType for BlockIQ4NL {
+    const DTYPE: GgmlDType = GgmlDType::IQ4NL;
+    const BLCK_SIZE: usize = QK4_NL;
+    type VecDotType = BlockQ8_0;
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK4_NL != 0 {
+            crate::bail!("dequantize_row_iq4_nl: {k} is not divisible by {QK4_NL}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK4_NL)) {
+            let d = x.d.to_f32();
+            for (j, &q) in x.qs.iter().enumerate() {
+                ys[j] = d * KVALUES_IQ4NL[(q & 0xf) as usize] as f32;
+                ys[j + QK4_NL / 2] = d * KVALUES_IQ4NL[(q >> 4) as usize] as f32;
+            }
+        }
+        Ok(())
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        for (block, x) in group_for_quantization(xs, ys)? {
+            Self::quantize_block(block, x, None)
+        }
+        Ok(())
+    }
+
+    fn from_float_imatrix(
+        xs: &[f32],
+        ys: &mut [Self],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        for (block, x, qw) in group_for_quantization_imatrix(xs, ys, imatrix_weights, n_per_row)? {
+            Self::quantize_block(block, x, Some(qw))
+        }
+        Ok(())
+    }
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        #[cfg(target_feature = "avx")]
+        return super::avx::vec_dot_iq4nl_q8_0(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq4nl_q8_0(n, xs, ys);
+
+        #[cfg(target_feature = "simd128")]
+        return super::simd128::vec_dot_iq4nl_q8_0(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK4_NL != 0 {
+            crate::bail!("vec_dot_iq4nl_q8_0: {n} is not divisible by {QK4_NL}")
+        }
+        let mut sumf = 0f32;
+        for (xs, ys) in xs.iter().zip(ys.iter()) {
+            let mut sum_i = 0;
+            for j in 0..QK4_NL / 2 {
+                let v0 = KVALUES_IQ4NL[(xs.qs[j] & 0xf) as usize] as i32;
+                let v1 = KVALUES_IQ4NL[(xs.qs[j] >> 4) as usize] as i32;
+                sum_i += v0 * ys.qs[j] as i32 + v1 * ys.qs[j + QK4_NL / 2] as i32
+            }
+            sumf += sum_i as f32 * f16::to_f32(xs.d) * f16::to_f32(ys.d)
+        }
+        Ok(sumf)
+    }
+}
+
+impl BlockIQ4XS {
+    fn quantize_block(block: &mut Self, x: &[f32], qw: Option<&[f32]>) {
+        // quantize_row_iq4_xs
+        let weights = match qw {
+            None => x.iter().map(|x| x * x).collect(),
+            Some(qw) => imatrix_weights(x, qw),
+        };
+        let mut scales = [0f32; QK_K / 32];
+        let mut max_scale = 0f32;
+        let mut amax_scale = 0f32;
+        for ((scale, x), w) in scales
+            .iter_mut()
+            .zip(x.chunks_exact(32))
+            .zip(weights.chunks_exact(32))
+        {
+            *scale = make_iq4_scale(x, w, &KVALUES_IQ4NL, 7);
+            if scale.abs() > amax_scale {
+                amax_scale = scale.abs();
+                max_scale = *scale;
+            }
+        }
+        let d = -max_scale / 32.;
+        let id = if d != 0. { 1. / d } else { 0. };
+        block.d = f16::from_f32(d);
+        block.scales_h = 0;
+        block.scales_l = [0; QK_K / 64];
+        for (ib, (qs, x)) in block
+            .qs
+            .chunks_exact_mut(16)
+            .zip(x.chunks_exact(32))
+            .enumerate()
+        {
+            let l = nearest_int(id * scales[ib]).clamp(-32, 31);
+            let dl = d * l as f32;
+            let idl = if dl != 0. { 1. / dl } else { 0. };
+            for (j, q) in qs.iter_mut().enumerate() {
+                let l0 = best_index_int8(&KVALUES_IQ4NL, idl * x[j]) as u8;
+                let l1 = best_index_int8(&KVALUES_IQ4NL, idl * x[j + 16]) as u8;
+                *q = l0 | (l1 << 4)
+            }
+            let l = (l + 32) as u8;
+            block.scales_l[ib / 2] |= (l & 0xf) << (4 * (ib % 2));
+            block.scales_h |= ((l >> 4) as u16) << (2 * ib);
+        }
+    }
+}
+
+impl GgmlType for BlockIQ4XS {
+    const DTYPE: GgmlDType = GgmlDType::IQ4XS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        let k = ys.len();
+        if k % QK_K != 0 {
+            crate::bail!("dequantize_row_iq4_xs: {k} is not divisible by {QK_K}")
+        }
+        for (x, ys) in xs.iter().zip(ys.chunks_exact_mut(QK_K)) {
+            let d = x.d.to_f32();
+            for (ib, (qs, ys)) in
+                x.qs.chunks_exact(16)
+                    .zip(ys.chunks_exact_mut(32))
+                    .enumerate()
+            {
+                let dl = d * (x.scale(ib) - 32) as f32;
+                for (j, &q) in qs.iter().enumerate() {
+                    ys[j] = dl * KVALUES_IQ4NL[(q & 0xf) as usize] as f32;
+                    ys[j + 16] = dl * KVALUES_IQ4NL[(q >> 4) as usize] as f32;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        for (block, x) in group_for_quantization(xs, ys)? {
+            Self::quantize_block(block, x, None)
+        }
+        Ok(())
+    }
+
+    fn from_float_imatrix(
+        xs: &[f32],
+        ys: &mut [Self],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        for (block, x, qw) in group_for_quantization_imatrix(xs, ys, imatrix_weights, n_per_row)? {
+            Self::quantize_block(block, x, Some(qw))
+        }
+        Ok(())
+    }
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        #[cfg(target_feature = "avx")]
+        return super::avx::vec_dot_iq4xs_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq4xs_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "simd128")]
+        return super::simd128::vec_dot_iq4xs_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq4xs_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (xs, ys) in xs.iter().zip(ys.iter()) {
+            let mut sum_i = 0;
+            for (ib, (qs, q8)) in xs
+                .qs
+                .chunks_exact(16)
+                .zip(ys.qs.chunks_exact(32))
+                .enumerate()
+            {
+                let mut sumb = 0;
+                for j in 0..16 {
+                    let v0 = KVALUES_IQ4NL[(qs[j] & 0xf) as usize] as i32;
+                    let v1 = KVALUES_IQ4NL[(qs[j] >> 4) as usize] as i32;
+                    sumb += v0 * q8[j] as i32 + v1 * q8[j + 16] as i32
+                }
+                sum_i += sumb * (xs.scale(ib) - 32)
+            }
+            sumf += sum_i as f32 * f16::to_f32(xs.d) * ys.d
+        }
+        Ok(sumf)
+    }
+}
+
 // https://github.com/ggerganov/llama.cpp/blob/b5ffb2849d23afe73647f68eec7b68187af09be6/ggml.c#L10605
 pub fn matmul<T: GgmlType>(
     mkn: (usize, usize, usize),
@@ -103,6 +103,14 @@ impl QMetalStorage {
                 let vec: Vec<crate::quantized::BlockQ8K> = read_to_vec(&buffer, block_len);
                 crate::quantized::BlockQ8K::to_float(&vec, &mut out)?;
             }
+            GgmlDType::IQ4NL => {
+                let vec: Vec<crate::quantized::BlockIQ4NL> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ4NL::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ4XS => {
+                let vec: Vec<crate::quantized::BlockIQ4XS> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ4XS::to_float(&vec, &mut out)?;
+            }
         }
 
         let buffer = self.device.new_buffer_with_data(&out)?;
@@ -113,7 +121,7 @@ impl QMetalStorage {
         // Quantization only happens on CPU for now.
         let src = src.to_cpu::<f32>()?;
         let elem_count = src.len();
-        let src = crate::Storage::Cpu(crate::CpuStorage::F32(src));
+        let src = crate::Storage::Cpu(crate::CpuStorage::F32(src.into()));
         let mut qcpu_storage = crate::Device::Cpu.qzeros(elem_count, self.dtype)?;
         qcpu_storage.quantize(&src)?;
         let buffer = self.device.new_buffer_with_data(&qcpu_storage.data()?)?;
@@ -121,6 +129,23 @@ impl QMetalStorage {
         Ok(())
     }
 
+    pub fn quantize_imatrix(
+        &mut self,
+        src: &MetalStorage,
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        // Quantization only happens on CPU for now.
+        let src = src.to_cpu::<f32>()?;
+        let elem_count = src.len();
+        let src = crate::Storage::Cpu(crate::CpuStorage::F32(src.into()));
+        let mut qcpu_storage = crate::Device::Cpu.qzeros(elem_count, self.dtype)?;
+        qcpu_storage.quantize_imatrix(&src, imatrix_weights, n_per_row)?;
+        let buffer = self.device.new_buffer_with_data(&qcpu_storage.data()?)?;
+        self.buffer = buffer;
+        Ok(())
+    }
+
     pub fn storage_size_in_bytes(&self) -> usize {
         self.buffer.length() as usize
     }
@@ -210,6 +235,8 @@ impl From<GgmlDType> for candle_metal_kernels::GgmlDType {
             GgmlDType::Q5K => candle_metal_kernels::GgmlDType::Q5K,
             GgmlDType::Q6K => candle_metal_kernels::GgmlDType::Q6K,
             GgmlDType::Q8K => candle_metal_kernels::GgmlDType::Q8K,
+            GgmlDType::IQ4NL => candle_metal_kernels::GgmlDType::IQ4NL,
+            GgmlDType::IQ4XS => candle_metal_kernels::GgmlDType::IQ4XS,
             GgmlDType::F16 => candle_metal_kernels::GgmlDType::F16,
             GgmlDType::F32 => candle_metal_kernels::GgmlDType::F32,
         }
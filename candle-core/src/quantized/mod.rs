@@ -8,6 +8,7 @@ mod dummy_cuda;
 mod dummy_metal;
 pub mod ggml_file;
 pub mod gguf_file;
+pub mod imatrix_file;
 pub mod k_quants;
 #[cfg(feature = "metal")]
 pub mod metal;
@@ -87,6 +88,13 @@ impl QStorage {
         }
     }
 
+    fn is_mapped(&self) -> bool {
+        match self {
+            QStorage::Cpu(storage) => storage.is_mapped(),
+            QStorage::Metal(_) | QStorage::Cuda(_) => false,
+        }
+    }
+
     fn size_in_bytes(&self) -> usize {
         match self {
             QStorage::Cpu(storage) => storage.storage_size_in_bytes(),
@@ -107,6 +115,27 @@ impl QStorage {
         Ok(())
     }
 
+    fn quantize_imatrix(
+        &mut self,
+        src: &Storage,
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        match (self, src) {
+            (QStorage::Cpu(storage), Storage::Cpu(src)) => {
+                storage.from_float_imatrix(src.as_slice::<f32>()?, imatrix_weights, n_per_row)?;
+            }
+            (QStorage::Metal(storage), Storage::Metal(src)) => {
+                storage.quantize_imatrix(src, imatrix_weights, n_per_row)?
+            }
+            (QStorage::Cuda(storage), Storage::Cuda(src)) => {
+                storage.quantize_imatrix(src, imatrix_weights, n_per_row)?
+            }
+            _ => crate::bail!("Invalid quantize storage locations do not match"),
+        }
+        Ok(())
+    }
+
     fn dequantize(&self, elem_count: usize) -> Result<Storage> {
         match self {
             QStorage::Cpu(storage) => Ok(Storage::Cpu(storage.dequantize(elem_count)?)),
@@ -146,6 +175,8 @@ pub enum GgmlDType {
     Q5K,
     Q6K,
     Q8K,
+    IQ4NL,
+    IQ4XS,
 }
 
 impl GgmlDType {
@@ -165,6 +196,22 @@ impl GgmlDType {
             13 => Self::Q5K,
             14 => Self::Q6K,
             15 => Self::Q8K,
+            20 => Self::IQ4NL,
+            23 => Self::IQ4XS,
+            // These i-quants rely on the lattice codebooks from ggml-common.h which are not
+            // vendored yet, their support is left to a separate follow-up. Only IQ4_NL and
+            // IQ4_XS are supported for now.
+            16..=19 | 21 | 22 => {
+                let name = match u {
+                    16 => "IQ2_XXS",
+                    17 => "IQ2_XS",
+                    18 => "IQ3_XXS",
+                    19 => "IQ1_S",
+                    21 => "IQ3_S",
+                    _ => "IQ2_S",
+                };
+                crate::bail!("unsupported i-quant dtype for tensor {name} ({u}), only IQ4_NL and IQ4_XS are supported")
+            }
             _ => crate::bail!("unknown dtype for tensor {u}"),
         };
         Ok(dtype)
@@ -186,6 +233,8 @@ impl GgmlDType {
             Self::Q5K => 13,
             Self::Q6K => 14,
             Self::Q8K => 15,
+            Self::IQ4NL => 20,
+            Self::IQ4XS => 23,
         }
     }
 
@@ -206,6 +255,14 @@ impl GgmlDType {
             Self::Q5K => Box::new(vec![BlockQ5K::zeros(); elem_count / BlockQ5K::BLCK_SIZE]),
             Self::Q6K => Box::new(vec![BlockQ6K::zeros(); elem_count / BlockQ6K::BLCK_SIZE]),
             Self::Q8K => Box::new(vec![BlockQ8K::zeros(); elem_count / BlockQ8K::BLCK_SIZE]),
+            Self::IQ4NL => Box::new(vec![
+                BlockIQ4NL::zeros();
+                elem_count / BlockIQ4NL::BLCK_SIZE
+            ]),
+            Self::IQ4XS => Box::new(vec![
+                BlockIQ4XS::zeros();
+                elem_count / BlockIQ4XS::BLCK_SIZE
+            ]),
         }
     }
     /// The type size for blocks in bytes.
@@ -227,6 +284,8 @@ impl GgmlDType {
             Self::Q5K => std::mem::size_of::<BlockQ5K>(),
             Self::Q6K => std::mem::size_of::<BlockQ6K>(),
             Self::Q8K => std::mem::size_of::<BlockQ8K>(),
+            Self::IQ4NL => std::mem::size_of::<BlockIQ4NL>(),
+            Self::IQ4XS => std::mem::size_of::<BlockIQ4XS>(),
         }
     }
 
@@ -241,7 +300,10 @@ impl GgmlDType {
             Self::Q5_1 => k_quants::QK5_1,
             Self::Q8_0 => k_quants::QK8_0,
             Self::Q8_1 => k_quants::QK8_1,
-            Self::Q2K | Self::Q3K | Self::Q4K | Self::Q5K | Self::Q6K | Self::Q8K => k_quants::QK_K,
+            Self::IQ4NL => k_quants::QK4_NL,
+            Self::Q2K | Self::Q3K | Self::Q4K | Self::Q5K | Self::Q6K | Self::Q8K | Self::IQ4XS => {
+                k_quants::QK_K
+            }
         }
     }
 }
@@ -256,7 +318,16 @@ pub trait QuantizedType: Send + Sync {
     fn block_size(&self) -> usize;
     #[allow(clippy::wrong_self_convention)]
     fn from_float(&mut self, xs: &[f32]) -> Result<()>;
+    #[allow(clippy::wrong_self_convention)]
+    fn from_float_imatrix(
+        &mut self,
+        xs: &[f32],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()>;
     fn size(&self) -> usize;
+    /// Returns true if the blocks are read from a memory mapped file rather than being owned.
+    fn is_mapped(&self) -> bool;
 }
 
 impl<T: k_quants::GgmlType + Send + Sync> QuantizedType for Vec<T> {
@@ -272,6 +343,15 @@ impl<T: k_quants::GgmlType + Send + Sync> QuantizedType for Vec<T> {
         T::from_float(xs, self)
     }
 
+    fn from_float_imatrix(
+        &mut self,
+        xs: &[f32],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        T::from_float_imatrix(xs, self, imatrix_weights, n_per_row)
+    }
+
     fn dtype(&self) -> GgmlDType {
         T::DTYPE
     }
@@ -283,7 +363,7 @@ impl<T: k_quants::GgmlType + Send + Sync> QuantizedType for Vec<T> {
     fn dequantize(&self, elem_count: usize) -> Result<CpuStorage> {
         let mut ys = vec![0.0f32; elem_count];
         T::to_float(self.as_slice(), &mut ys)?;
-        Ok(CpuStorage::F32(ys))
+        Ok(CpuStorage::F32(ys.into()))
     }
 
     fn storage_size_in_bytes(&self) -> usize {
@@ -293,6 +373,60 @@ impl<T: k_quants::GgmlType + Send + Sync> QuantizedType for Vec<T> {
     fn as_ptr(&self) -> *const u8 {
         self.as_ptr() as *const u8
     }
+
+    fn is_mapped(&self) -> bool {
+        false
+    }
+}
+
+// Quantized blocks read directly from a memory mapped file.
+impl<T: k_quants::GgmlType + Send + Sync> QuantizedType for crate::CpuBuffer<T> {
+    fn matmul_t(&self, mkn: (usize, usize, usize), lhs: &[f32], dst: &mut [f32]) -> Result<()> {
+        k_quants::matmul(mkn, lhs, self.as_slice(), dst)
+    }
+
+    fn size(&self) -> usize {
+        self.len() * core::mem::size_of::<T>()
+    }
+
+    fn from_float(&mut self, xs: &[f32]) -> Result<()> {
+        T::from_float(xs, self)
+    }
+
+    fn from_float_imatrix(
+        &mut self,
+        xs: &[f32],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        T::from_float_imatrix(xs, self, imatrix_weights, n_per_row)
+    }
+
+    fn dtype(&self) -> GgmlDType {
+        T::DTYPE
+    }
+
+    fn block_size(&self) -> usize {
+        T::BLCK_SIZE
+    }
+
+    fn dequantize(&self, elem_count: usize) -> Result<CpuStorage> {
+        let mut ys = vec![0.0f32; elem_count];
+        T::to_float(self.as_slice(), &mut ys)?;
+        Ok(CpuStorage::F32(ys.into()))
+    }
+
+    fn storage_size_in_bytes(&self) -> usize {
+        self.len() * std::mem::size_of::<T>()
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        self.as_slice().as_ptr() as *const u8
+    }
+
+    fn is_mapped(&self) -> bool {
+        crate::CpuBuffer::is_mapped(self)
+    }
 }
 
 impl std::fmt::Debug for QTensor {
@@ -342,6 +476,58 @@ impl QTensor {
         })
     }
 
+    /// Quantizes `src` using per-column importance weights, e.g. the values produced by
+    /// llama.cpp's imatrix tool, to drive the scale search of the k-quants and iq4 types.
+    ///
+    /// `imatrix_weights` must have one entry per element of the last dimension of `src`. Types
+    /// without a weighted search fall back to the plain quantization.
+    pub fn quantize_with_imatrix(
+        src: &Tensor,
+        dtype: GgmlDType,
+        imatrix_weights: &[f32],
+    ) -> Result<Self> {
+        let shape = src.shape();
+        let block_size = dtype.block_size();
+        check_shape(shape, block_size)?;
+        let n_per_row = shape.dims()[shape.rank() - 1];
+        if imatrix_weights.len() != n_per_row {
+            crate::bail!(
+                "imatrix has {} weights but the tensor rows have {n_per_row} elements {shape:?}",
+                imatrix_weights.len()
+            )
+        }
+        let src = src.to_dtype(crate::DType::F32)?.flatten_all()?;
+        let elem_count = shape.elem_count();
+        let mut storage = src.device().qzeros(elem_count, dtype)?;
+        storage.quantize_imatrix(&src.storage(), imatrix_weights, n_per_row)?;
+        Ok(Self {
+            storage,
+            shape: shape.clone(),
+        })
+    }
+
+    /// Compares the dequantized values of this tensor against `src`, the original tensor.
+    pub fn quantization_error(&self, src: &Tensor) -> Result<QuantizationError> {
+        if src.shape() != &self.shape {
+            Err(crate::Error::ShapeMismatchBinaryOp {
+                lhs: self.shape.clone(),
+                rhs: src.shape().clone(),
+                op: "quantization_error",
+            }
+            .bt())?
+        }
+        let dequant = self
+            .dequantize(&Device::Cpu)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        let src = src
+            .to_device(&Device::Cpu)?
+            .to_dtype(crate::DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        Ok(QuantizationError::from_slices(&src, &dequant))
+    }
+
     pub fn dtype(&self) -> GgmlDType {
         self.storage.dtype()
     }
@@ -370,11 +556,57 @@ impl QTensor {
         self.storage.size_in_bytes()
     }
 
+    /// Returns true if the tensor is on the cpu and its blocks are read from a memory mapped
+    /// file, e.g. when loaded with `gguf_file::Content::tensor_mmaped`.
+    pub fn is_mapped(&self) -> bool {
+        self.storage.is_mapped()
+    }
+
     pub fn data(&self) -> Result<Cow<'_, [u8]>> {
         self.storage.data()
     }
 }
 
+/// Error statistics of a quantized tensor against the original values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuantizationError {
+    /// Root mean squared error.
+    pub rmse: f32,
+    /// Largest absolute difference over all the elements.
+    pub max_abs_error: f32,
+    /// Root mean square of the original values, useful to normalize `rmse` across tensors.
+    pub rms: f32,
+}
+
+impl QuantizationError {
+    fn from_slices(src: &[f32], dequant: &[f32]) -> Self {
+        let mut sum_sq_err = 0f64;
+        let mut sum_sq = 0f64;
+        let mut max_abs_error = 0f32;
+        for (&s, &d) in src.iter().zip(dequant.iter()) {
+            let err = (s - d).abs();
+            sum_sq_err += (err as f64) * (err as f64);
+            sum_sq += (s as f64) * (s as f64);
+            max_abs_error = max_abs_error.max(err);
+        }
+        let n = src.len().max(1) as f64;
+        Self {
+            rmse: (sum_sq_err / n).sqrt() as f32,
+            max_abs_error,
+            rms: (sum_sq / n).sqrt() as f32,
+        }
+    }
+
+    /// The rmse divided by the root mean square of the original values.
+    pub fn relative_rmse(&self) -> f32 {
+        if self.rms == 0. {
+            self.rmse
+        } else {
+            self.rmse / self.rms
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum QMatMul {
     QTensor(std::sync::Arc<QTensor>),
@@ -447,7 +679,7 @@ impl crate::CustomOp1 for QTensor {
         let slice = &slice[layout.start_offset()..layout.start_offset() + src_shape.elem_count()];
         let mut dst_storage = vec![0f32; dst_shape.elem_count()];
         self_storage.matmul_t((dst_shape.elem_count() / n, k, n), slice, &mut dst_storage)?;
-        Ok((crate::CpuStorage::F32(dst_storage), dst_shape))
+        Ok((crate::CpuStorage::F32(dst_storage.into()), dst_shape))
     }
 
     fn metal_fwd(
@@ -1,5 +1,6 @@
 use super::k_quants::{
-    BlockQ2K, BlockQ3K, BlockQ4K, BlockQ4_0, BlockQ5K, BlockQ6K, BlockQ8K, BlockQ8_0, QK8_0, QK_K,
+    BlockIQ4NL, BlockIQ4XS, BlockQ2K, BlockQ3K, BlockQ4K, BlockQ4_0, BlockQ5K, BlockQ6K, BlockQ8K,
+    BlockQ8_0, KVALUES_IQ4NL, QK4_NL, QK8_0, QK_K,
 };
 use crate::Result;
 use byteorder::{ByteOrder, LittleEndian};
@@ -611,3 +612,55 @@ unsafe fn multiply_accum_with_scale(
     let p2 = vdotq_s32(q2bytes.1, q8bytes.1);
     vaddvq_s32(p1) * aux[is + index] as i32 + vaddvq_s32(p2) * aux[is + 1 + index] as i32
 }
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4nl_q8_0(n: usize, xs: &[BlockIQ4NL], ys: &[BlockQ8_0]) -> Result<f32> {
+    if n % QK4_NL != 0 {
+        crate::bail!("vec_dot_iq4nl_q8_0: {n} is not divisible by {QK4_NL}")
+    }
+    unsafe {
+        let values = vld1q_s8(KVALUES_IQ4NL.as_ptr());
+        let m4b = vdupq_n_u8(0x0F);
+        let mut sumv0 = vdupq_n_f32(0.0f32);
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let q4 = vld1q_u8(x.qs.as_ptr());
+            let q4l = vqtbl1q_s8(values, vandq_u8(q4, m4b));
+            let q4h = vqtbl1q_s8(values, vshrq_n_u8(q4, 4));
+
+            let q8l = vld1q_s8(y.qs.as_ptr());
+            let q8h = vld1q_s8(y.qs.as_ptr().add(16));
+
+            let p = vaddq_s32(vdotq_s32(q4l, q8l), vdotq_s32(q4h, q8h));
+            sumv0 = vmlaq_n_f32(sumv0, vcvtq_f32_s32(p), x.d.to_f32() * y.d.to_f32());
+        }
+        Ok(vaddvq_f32(sumv0))
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4xs_q8k(n: usize, xs: &[BlockIQ4XS], ys: &[BlockQ8K]) -> Result<f32> {
+    if n % QK_K != 0 {
+        crate::bail!("vec_dot_iq4xs_q8k: {n} is not divisible by {QK_K}")
+    }
+    unsafe {
+        let values = vld1q_s8(KVALUES_IQ4NL.as_ptr());
+        let m4b = vdupq_n_u8(0x0F);
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = 0i32;
+            for ib in 0..QK_K / 32 {
+                let q4 = vld1q_u8(x.qs.as_ptr().add(16 * ib));
+                let q4l = vqtbl1q_s8(values, vandq_u8(q4, m4b));
+                let q4h = vqtbl1q_s8(values, vshrq_n_u8(q4, 4));
+
+                let q8l = vld1q_s8(y.qs.as_ptr().add(32 * ib));
+                let q8h = vld1q_s8(y.qs.as_ptr().add(32 * ib + 16));
+
+                let p = vaddq_s32(vdotq_s32(q4l, q8l), vdotq_s32(q4h, q8h));
+                sumi += vaddvq_s32(p) * (x.scale(ib) - 32);
+            }
+            sumf += sumi as f32 * x.d.to_f32() * y.d;
+        }
+        Ok(sumf)
+    }
+}
@@ -1,4 +1,7 @@
-use super::k_quants::{BlockQ2K, BlockQ4K, BlockQ4_0, BlockQ6K, BlockQ8K, BlockQ8_0, QK8_0, QK_K};
+use super::k_quants::{
+    BlockIQ4NL, BlockIQ4XS, BlockQ2K, BlockQ4K, BlockQ4_0, BlockQ6K, BlockQ8K, BlockQ8_0,
+    KVALUES_IQ4NL, QK4_NL, QK8_0, QK_K,
+};
 use crate::Result;
 use byteorder::{ByteOrder, LittleEndian};
 use half::f16;
@@ -417,3 +420,79 @@ pub(crate) fn vec_dot_q8k_q8k(n: usize, xs: &[BlockQ8K], ys: &[BlockQ8K]) -> Res
         Ok(res)
     }
 }
+
+/// Dot product of 32 packed IQ4_NL indexes with 32 8 bits values.
+#[inline(always)]
+unsafe fn iq4nl_dot_32(values: v128, qs: *const u8, ys: *const i8) -> v128 {
+    let x1234 = v128_load(qs as *const v128);
+    let x12 = i8x16_swizzle(values, v128_and(x1234, u8x16_splat(0x0F)));
+    let x34 = i8x16_swizzle(values, u8x16_shr(x1234, 4));
+
+    let x1 = i16x8_extend_low_i8x16(x12);
+    let y1 = i16x8_load_extend_i8x8(ys);
+    let sum_xy = i32x4_dot_i16x8(x1, y1);
+
+    let x2 = i16x8_extend_high_i8x16(x12);
+    let y2 = i16x8_load_extend_i8x8(ys.add(8));
+    let sum_xy = i32x4_add(sum_xy, i32x4_dot_i16x8(x2, y2));
+
+    let x3 = i16x8_extend_low_i8x16(x34);
+    let y3 = i16x8_load_extend_i8x8(ys.add(16));
+    let sum_xy = i32x4_add(sum_xy, i32x4_dot_i16x8(x3, y3));
+
+    let x4 = i16x8_extend_high_i8x16(x34);
+    let y4 = i16x8_load_extend_i8x8(ys.add(24));
+    i32x4_add(sum_xy, i32x4_dot_i16x8(x4, y4))
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4nl_q8_0(n: usize, xs: &[BlockIQ4NL], ys: &[BlockQ8_0]) -> Result<f32> {
+    if n % QK4_NL != 0 {
+        crate::bail!("vec_dot_iq4nl_q8_0: {n} is not divisible by {QK4_NL}")
+    }
+    unsafe {
+        let values = v128_load(KVALUES_IQ4NL.as_ptr() as *const v128);
+        let mut acc = f32x4_splat(0.0f32);
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let sum_xy = iq4nl_dot_32(values, x.qs.as_ptr(), y.qs.as_ptr());
+            let sum_xy = f32x4_convert_i32x4(sum_xy);
+            let d = f32x4_splat(f16::to_f32(x.d) * f16::to_f32(y.d));
+            acc = f32x4_add(acc, f32x4_mul(sum_xy, d))
+        }
+        let res = f32x4_extract_lane::<0>(acc)
+            + f32x4_extract_lane::<1>(acc)
+            + f32x4_extract_lane::<2>(acc)
+            + f32x4_extract_lane::<3>(acc);
+        Ok(res)
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4xs_q8k(n: usize, xs: &[BlockIQ4XS], ys: &[BlockQ8K]) -> Result<f32> {
+    if n % QK_K != 0 {
+        crate::bail!("vec_dot_iq4xs_q8k: {n} is not divisible by {QK_K}")
+    }
+    unsafe {
+        let values = v128_load(KVALUES_IQ4NL.as_ptr() as *const v128);
+        let mut acc = f32x4_splat(0.0f32);
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = i32x4_splat(0);
+            for ib in 0..QK_K / 32 {
+                let sum_xy = iq4nl_dot_32(
+                    values,
+                    x.qs.as_ptr().add(16 * ib),
+                    y.qs.as_ptr().add(32 * ib),
+                );
+                let ls = i32x4_splat(x.scale(ib) - 32);
+                sumi = i32x4_add(sumi, i32x4_mul(sum_xy, ls));
+            }
+            let d = f32x4_splat(f16::to_f32(x.d) * y.d);
+            acc = f32x4_add(acc, f32x4_mul(f32x4_convert_i32x4(sumi), d))
+        }
+        let res = f32x4_extract_lane::<0>(acc)
+            + f32x4_extract_lane::<1>(acc)
+            + f32x4_extract_lane::<2>(acc)
+            + f32x4_extract_lane::<3>(acc);
+        Ok(res)
+    }
+}
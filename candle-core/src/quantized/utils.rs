@@ -25,6 +25,56 @@ pub(super) fn group_for_quantization<'a, 'b, T: super::k_quants::GgmlType>(
     Ok(ys.iter_mut().zip(xs.chunks_exact(block_size)).collect())
 }
 
+/// Same as `group_for_quantization` but also returns the importance weights of the columns
+/// covered by each block. `xs` is made of rows of `n_per_row` values and `imatrix_weights` holds
+/// one weight per column.
+#[allow(clippy::type_complexity)]
+pub(super) fn group_for_quantization_imatrix<'a, 'b, 'c, T: super::k_quants::GgmlType>(
+    xs: &'b [f32],
+    ys: &'a mut [T],
+    imatrix_weights: &'c [f32],
+    n_per_row: usize,
+) -> Result<Vec<(&'a mut T, &'b [f32], &'c [f32])>> {
+    let block_size = T::BLCK_SIZE;
+    let dtype = T::DTYPE;
+    if n_per_row == 0 || n_per_row % block_size != 0 {
+        crate::bail!("quantize {dtype:?}: row size {n_per_row} is not divisible by {block_size}")
+    }
+    if imatrix_weights.len() != n_per_row {
+        crate::bail!(
+            "quantize {dtype:?}: expected {n_per_row} importance weights, got {}",
+            imatrix_weights.len()
+        )
+    }
+    if xs.len() % n_per_row != 0 {
+        crate::bail!(
+            "quantize {dtype:?}: {} is not divisible by {n_per_row}",
+            xs.len()
+        )
+    }
+    let blocks = group_for_quantization(xs, ys)?;
+    let blocks_per_row = n_per_row / block_size;
+    let blocks = blocks
+        .into_iter()
+        .enumerate()
+        .map(|(i, (y, x))| {
+            let start = (i % blocks_per_row) * block_size;
+            (y, x, &imatrix_weights[start..start + block_size])
+        })
+        .collect();
+    Ok(blocks)
+}
+
+/// Combines the importance weights with the values being quantized in the same way as llama.cpp,
+/// the values are weighted by `qw * sqrt(sigma2 + x^2)` with `sigma2` based on the block variance.
+pub(super) fn imatrix_weights(x: &[f32], qw: &[f32]) -> Vec<f32> {
+    let sigma2 = 2. * x.iter().map(|x| x * x).sum::<f32>() / x.len() as f32;
+    x.iter()
+        .zip(qw.iter())
+        .map(|(&x, &qw)| qw * (sigma2 + x * x).sqrt())
+        .collect()
+}
+
 /// Validates that the input and output are the right size and returns an iterator which maps each
 /// input block `xs` to its corresponding output region in `ys`. Each output region is guaranteed
 /// to be `T::BLCK_SIZE` long.
@@ -64,6 +114,7 @@ pub(super) unsafe fn make_qx_quants(
     x: *const f32,
     ls: *mut i8,
     rmse_type: i32,
+    qw: Option<&[f32]>,
 ) -> f32 {
     let mut max = 0f32;
     let mut amax = 0f32;
@@ -92,6 +143,11 @@ pub(super) unsafe fn make_qx_quants(
         return 1.0 / iscale;
     }
     let weight_type = rmse_type % 2;
+    let weight = |i: usize, x: f32| match qw {
+        Some(qw) => qw[i],
+        None if weight_type == 1 => x * x,
+        None => 1.,
+    };
     let mut sumlx = 0f32;
     let mut suml2 = 0f32;
     for i in 0..n {
@@ -99,7 +155,7 @@ pub(super) unsafe fn make_qx_quants(
         let l = nearest_int(iscale * x);
         let l = l.clamp(-nmax, nmax - 1);
         *ls.add(i) = (l + nmax) as i8;
-        let w = if weight_type == 1 { x * x } else { 1.0 };
+        let w = weight(i, x);
         let l = l as f32;
         sumlx += w * x * l;
         suml2 += w * l * l;
@@ -118,7 +174,7 @@ pub(super) unsafe fn make_qx_quants(
             if l + nmax != *ls.add(i) as i32 {
                 changed = true;
             }
-            let w = if weight_type == 1 { x * x } else { 1f32 };
+            let w = weight(i, x);
             let l = l as f32;
             slx += w * x * l;
             sl2 += w * l * l;
@@ -140,7 +196,7 @@ pub(super) unsafe fn make_qx_quants(
         let mut n_changed = 0;
         for i in 0..n {
             let x = *x.add(i);
-            let w = if weight_type == 1 { x * x } else { 1. };
+            let w = weight(i, x);
             let l = *ls.add(i) as i32 - nmax;
             let mut slx = sumlx - w * x * l as f32;
             if slx > 0. {
@@ -179,7 +235,7 @@ pub(super) unsafe fn make_qx_quants(
             let x = *x.add(i);
             let l = nearest_int(iscale * x);
             let l = l.clamp(-nmax, nmax - 1);
-            let w = if weight_type == 1 { x * x } else { 1. };
+            let w = weight(i, x);
             let l = l as f32;
             sumlx += w * x * l;
             suml2 += w * l * l;
@@ -257,6 +313,85 @@ pub(super) fn make_qkx1_quants(nmax: i32, ntry: usize, x: &[f32]) -> (f32, f32)
     (scale, -min)
 }
 
+/// Weighted version of `make_qkx1_quants`, the scale and min are fitted with a weighted least
+/// squares for a range of candidate scales around `nmax / (max - min)`. Returns the scale and the
+/// negated min.
+// Port of make_qkx2_quants from llama.cpp's ggml-quants.c.
+pub(super) fn make_qkx2_quants(
+    nmax: i32,
+    x: &[f32],
+    weights: &[f32],
+    rmin: f32,
+    rdelta: f32,
+    nstep: usize,
+    use_mad: bool,
+) -> (f32, f32) {
+    let n = x.len();
+    let mut l = vec![0u8; n];
+    let mut laux = vec![0u8; n];
+    let mut min = x[0];
+    let mut max = x[0];
+    let mut sum_w = 0f32;
+    let mut sum_x = 0f32;
+    for (&x, &w) in x.iter().zip(weights.iter()) {
+        min = min.min(x);
+        max = max.max(x);
+        sum_w += w;
+        sum_x += w * x;
+    }
+    if min > 0. {
+        min = 0.
+    }
+    if max == min {
+        return (0., -min);
+    }
+    let error = |scale: f32, min: f32, l: &[u8]| {
+        let mut mad = 0f32;
+        for i in 0..n {
+            let diff = scale * l[i] as f32 + min - x[i];
+            let diff = if use_mad { diff.abs() } else { diff * diff };
+            mad += weights[i] * diff;
+        }
+        mad
+    };
+    let iscale = nmax as f32 / (max - min);
+    let mut scale = 1. / iscale;
+    for i in 0..n {
+        l[i] = nearest_int(iscale * (x[i] - min)).clamp(0, nmax) as u8;
+    }
+    let mut best_mad = error(scale, min, &l);
+    for is in 0..=nstep {
+        let iscale = (rmin + rdelta * is as f32 + nmax as f32) / (max - min);
+        let mut sum_l = 0f32;
+        let mut sum_l2 = 0f32;
+        let mut sum_xl = 0f32;
+        for i in 0..n {
+            let li = nearest_int(iscale * (x[i] - min)).clamp(0, nmax);
+            laux[i] = li as u8;
+            let (li, w) = (li as f32, weights[i]);
+            sum_l += w * li;
+            sum_l2 += w * li * li;
+            sum_xl += w * li * x[i];
+        }
+        let d = sum_w * sum_l2 - sum_l * sum_l;
+        if d > 0. {
+            let mut this_scale = (sum_w * sum_xl - sum_x * sum_l) / d;
+            let mut this_min = (sum_l2 * sum_x - sum_l * sum_xl) / d;
+            if this_min > 0. {
+                this_min = 0.;
+                this_scale = sum_xl / sum_l2;
+            }
+            let mad = error(this_scale, this_min, &laux);
+            if mad < best_mad {
+                best_mad = mad;
+                scale = this_scale;
+                min = this_min;
+            }
+        }
+    }
+    (scale, -min)
+}
+
 // https://github.com/ggerganov/llama.cpp/blob/8183159cf3def112f6d1fe94815fce70e1bffa12/k_quants.c#L165
 pub(super) fn make_q3_quants(x: &[f32], nmax: i32, do_rmse: bool) -> f32 {
     let n = x.len();
@@ -324,3 +459,69 @@ pub(super) fn make_q3_quants(x: &[f32], nmax: i32, do_rmse: bool) -> f32 {
     }
     1.0 / iscale
 }
+
+// Port of best_index_int8 from llama.cpp's ggml-quants.c, `values` has to be sorted.
+pub(super) fn best_index_int8(values: &[i8], x: f32) -> usize {
+    let n = values.len();
+    if x <= values[0] as f32 {
+        return 0;
+    }
+    if x >= values[n - 1] as f32 {
+        return n - 1;
+    }
+    let (mut ml, mut mu) = (0, n - 1);
+    while mu - ml > 1 {
+        let mav = (ml + mu) / 2;
+        if x < values[mav] as f32 {
+            mu = mav
+        } else {
+            ml = mav
+        }
+    }
+    if x - (values[mu - 1] as f32) < values[mu] as f32 - x {
+        mu - 1
+    } else {
+        mu
+    }
+}
+
+/// Finds the scale for a block of non-linear 4 bits quants by searching around `-max / values[0]`
+/// with a least squares fit weighted by `weights`. The returned scale is signed and is zero for an all zero
+/// block.
+// Adapted from quantize_row_iq4_nl_impl in llama.cpp's ggml-quants.c.
+pub(super) fn make_iq4_scale(x: &[f32], weights: &[f32], values: &[i8], ntry: i32) -> f32 {
+    let mut amax = 0f32;
+    let mut max = 0f32;
+    for &v in x.iter() {
+        if amax < v.abs() {
+            amax = v.abs();
+            max = v;
+        }
+    }
+    if amax == 0. {
+        return 0.;
+    }
+    let v0 = values[0] as f32;
+    let weighted_sums = |id: f32| {
+        let mut sumqx = 0f32;
+        let mut sumq2 = 0f32;
+        for (&v, &w) in x.iter().zip(weights.iter()) {
+            let q = values[best_index_int8(values, id * v)] as f32;
+            sumqx += w * q * v;
+            sumq2 += w * q * q;
+        }
+        (sumqx, sumq2)
+    };
+    let d = if ntry > 0 { -max / v0 } else { max / v0 };
+    let (sumqx, sumq2) = weighted_sums(1. / d);
+    let mut d = sumqx / sumq2;
+    let mut best = d * sumqx;
+    for itry in -ntry..=ntry {
+        let (sumqx, sumq2) = weighted_sums((itry as f32 + v0) / max);
+        if sumq2 > 0. && sumqx * sumqx > best * sumq2 {
+            d = sumqx / sumq2;
+            best = d * sumqx;
+        }
+    }
+    d
+}
@@ -0,0 +1,1486 @@
+//! Image resampling: interpolation with bilinear and bicubic filters, and sampling at arbitrary
+//! locations with `grid_sample`.
+//!
+//! These follow the PyTorch semantics of `interpolate`, `grid_sample`, and `affine_grid` and
+//! support backprop. On cpu, cuda, and metal, interpolation and grid sampling use dedicated
+//! kernels that only visit the pixels with non-zero weights, the interpolation weights are
+//! computed on the host and sent to the gpu kernels in compressed sparse row format. There are
+//! no wgpu kernels, on this device interpolation is expressed as matmuls with dense
+//! `(target, source)` weight matrices for each of the two dimensions, and grid sampling with
+//! gather and elementwise ops.
+use crate::backend::BackendStorage;
+use crate::cpu_backend::{Map1, Map2};
+use crate::{
+    bail, CpuStorage, CustomOp1, CustomOp2, CustomOp3, DType, Error, Layout, Result, Shape, Tensor,
+    WithDType,
+};
+use rayon::prelude::*;
+
+/// The interpolation mode used by [`Tensor::interpolate2d_with_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterpolateMode {
+    /// Nearest neighbor interpolation, same as [`Tensor::interpolate2d`].
+    Nearest,
+    /// Bilinear interpolation.
+    Bilinear,
+    /// Bicubic interpolation using the cubic convolution algorithm with `A = -0.75`.
+    Bicubic,
+    /// Bilinear interpolation with antialiasing, when downsampling the filter support is
+    /// scaled so that every input pixel contributes to the output.
+    BilinearAntialias,
+    /// Bicubic interpolation with antialiasing, this uses `A = -0.5` as in PIL.
+    BicubicAntialias,
+}
+
+/// The interpolation mode used by [`Tensor::grid_sample`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridSampleMode {
+    Bilinear,
+    Nearest,
+}
+
+/// How [`Tensor::grid_sample`] handles the locations that are outside of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridSamplePadding {
+    /// Use zero for out of bound locations.
+    Zeros,
+    /// Use the values at the border for out of bound locations.
+    Border,
+    /// Reflect the out of bound locations by the border.
+    Reflection,
+}
+
+fn cubic_convolution1(x: f64, a: f64) -> f64 {
+    ((a + 2.) * x - (a + 3.)) * x * x + 1.
+}
+
+fn cubic_convolution2(x: f64, a: f64) -> f64 {
+    ((a * x - 5. * a) * x + 8. * a) * x - 4. * a
+}
+
+fn cubic_filter(x: f64, a: f64) -> f64 {
+    let x = x.abs();
+    if x < 1. {
+        cubic_convolution1(x, a)
+    } else if x < 2. {
+        cubic_convolution2(x, a)
+    } else {
+        0.
+    }
+}
+
+fn triangle_filter(x: f64) -> f64 {
+    let x = x.abs();
+    if x < 1. {
+        1. - x
+    } else {
+        0.
+    }
+}
+
+// The interpolation weights along a single dimension, row `i` contains the `(src_index, weight)`
+// pairs that contribute to the destination index `i`.
+#[derive(Debug, Clone)]
+struct Weights {
+    src: usize,
+    rows: Vec<Vec<(usize, f64)>>,
+}
+
+impl Weights {
+    fn identity(size: usize) -> Self {
+        let rows = (0..size).map(|i| vec![(i, 1.)]).collect();
+        Self { src: size, rows }
+    }
+
+    fn dst(&self) -> usize {
+        self.rows.len()
+    }
+
+    // The weights of the adjoint operation, this is used for the backward pass.
+    fn transpose(&self) -> Self {
+        let mut rows = vec![vec![]; self.src];
+        for (dst_i, row) in self.rows.iter().enumerate() {
+            for &(src_i, w) in row.iter() {
+                rows[src_i].push((dst_i, w))
+            }
+        }
+        Self {
+            src: self.dst(),
+            rows,
+        }
+    }
+
+    // The weights in compressed sparse row format: the `dst + 1` row offsets, then the source
+    // indexes and the weights of all the rows.
+    #[cfg(any(feature = "cuda", feature = "metal"))]
+    fn to_csr(&self) -> (Vec<u32>, Vec<u32>, Vec<f64>) {
+        let mut offsets = Vec::with_capacity(self.dst() + 1);
+        let mut idxs = vec![];
+        let mut wgts = vec![];
+        offsets.push(0);
+        for row in self.rows.iter() {
+            for &(src_i, w) in row.iter() {
+                idxs.push(src_i as u32);
+                wgts.push(w)
+            }
+            offsets.push(idxs.len() as u32)
+        }
+        (offsets, idxs, wgts)
+    }
+
+    // The (dst, src) dense matrix of the weights.
+    fn to_dense(&self) -> Vec<f64> {
+        let mut dense = vec![0f64; self.dst() * self.src];
+        for (dst_i, row) in self.rows.iter().enumerate() {
+            for &(src_i, w) in row.iter() {
+                dense[dst_i * self.src + src_i] += w
+            }
+        }
+        dense
+    }
+}
+
+// Adds a weight to a row, the source indexes are pushed in increasing order so repeated indexes
+// are always consecutive.
+fn push_weight(row: &mut Vec<(usize, f64)>, src_i: usize, w: f64) {
+    match row.last_mut() {
+        Some((last_i, last_w)) if *last_i == src_i => *last_w += w,
+        _ => row.push((src_i, w)),
+    }
+}
+
+// Returns the interpolation weights along a single dimension, this follows the `UpSample.h` and
+// `UpSampleKernel.cpp` implementations from PyTorch.
+fn interpolation_weights(
+    src: usize,
+    dst: usize,
+    mode: InterpolateMode,
+    align_corners: bool,
+) -> Weights {
+    let scale = if align_corners {
+        if dst > 1 {
+            (src - 1) as f64 / (dst - 1) as f64
+        } else {
+            0.
+        }
+    } else {
+        src as f64 / dst as f64
+    };
+    let source_index = |dst_i: usize, cubic: bool| {
+        if align_corners {
+            scale * dst_i as f64
+        } else {
+            let src_i = scale * (dst_i as f64 + 0.5) - 0.5;
+            if !cubic && src_i < 0. {
+                0.
+            } else {
+                src_i
+            }
+        }
+    };
+    let mut rows = Vec::with_capacity(dst);
+    for dst_i in 0..dst {
+        let mut row = vec![];
+        match mode {
+            InterpolateMode::Nearest => {
+                let src_i = (dst_i as f64 * src as f64 / dst as f64) as usize;
+                row.push((usize::min(src_i, src - 1), 1.))
+            }
+            InterpolateMode::Bilinear => {
+                let src_i = source_index(dst_i, false);
+                let i0 = src_i as usize;
+                let offset = usize::from(i0 < src - 1);
+                let lambda1 = (src_i - i0 as f64).clamp(0., 1.);
+                push_weight(&mut row, i0, 1. - lambda1);
+                push_weight(&mut row, i0 + offset, lambda1);
+            }
+            InterpolateMode::Bicubic => {
+                let src_i = source_index(dst_i, true);
+                let i0 = src_i.floor();
+                let t = src_i - i0;
+                let a = -0.75;
+                let coeffs = [
+                    cubic_convolution2(t + 1., a),
+                    cubic_convolution1(t, a),
+                    cubic_convolution1(1. - t, a),
+                    cubic_convolution2(2. - t, a),
+                ];
+                for (j, coeff) in coeffs.iter().enumerate() {
+                    let idx = (i0 as i64 - 1 + j as i64).clamp(0, src as i64 - 1);
+                    push_weight(&mut row, idx as usize, *coeff)
+                }
+            }
+            InterpolateMode::BilinearAntialias | InterpolateMode::BicubicAntialias => {
+                let (interp_size, filter): (f64, &dyn Fn(f64) -> f64) = match mode {
+                    InterpolateMode::BilinearAntialias => (2., &triangle_filter),
+                    _ => (4., &|x| cubic_filter(x, -0.5)),
+                };
+                let (support, invscale) = if scale >= 1. {
+                    (interp_size * 0.5 * scale, 1. / scale)
+                } else {
+                    (interp_size * 0.5, 1.)
+                };
+                let center = scale * (dst_i as f64 + 0.5);
+                let xmin = ((center - support + 0.5) as i64).max(0) as usize;
+                let xmax = ((center + support + 0.5) as i64).min(src as i64) as usize;
+                let mut total = 0.;
+                for j in xmin..xmax {
+                    let w = filter((j as f64 - center + 0.5) * invscale);
+                    row.push((j, w));
+                    total += w;
+                }
+                if total != 0. {
+                    row.iter_mut().for_each(|(_, w)| *w /= total)
+                }
+            }
+        }
+        rows.push(row)
+    }
+    Weights { src, rows }
+}
+
+// Separable interpolation of the two last dimensions of a contiguous (b, c, h, w) tensor, the
+// cpu kernel only goes through the non-zero weights.
+struct Interpolate2d {
+    h: Weights,
+    w: Weights,
+}
+
+impl Interpolate2d {
+    fn out_shape(&self, layout: &Layout) -> Result<Shape> {
+        let (b, c, h, w) = layout.shape().dims4()?;
+        if h != self.h.src || w != self.w.src {
+            bail!(
+                "interpolate2d: unexpected input shape {:?}, weights for ({}, {})",
+                layout.shape(),
+                self.h.src,
+                self.w.src
+            )
+        }
+        Ok((b, c, self.h.dst(), self.w.dst()).into())
+    }
+
+    // The weights along h then along w in the layout used by the cuda and metal kernels, see
+    // `interpolate2d` in `resample.cu`. This also returns the number of weights along h.
+    #[cfg(any(feature = "cuda", feature = "metal"))]
+    fn packed_weights(&self) -> (Vec<u32>, Vec<f64>, usize) {
+        let (h_offsets, h_idxs, h_wgts) = self.h.to_csr();
+        let (w_offsets, w_idxs, w_wgts) = self.w.to_csr();
+        let nnz_h = h_idxs.len();
+        let idxs = [h_offsets, h_idxs, w_offsets, w_idxs].concat();
+        let wgts = [h_wgts, w_wgts].concat();
+        (idxs, wgts, nnz_h)
+    }
+}
+
+impl Map1 for Interpolate2d {
+    fn f<T: WithDType>(&self, vs: &[T], layout: &Layout) -> Result<Vec<T>> {
+        let (_b, _c, h, w) = layout.shape().dims4()?;
+        let vs = match layout.contiguous_offsets() {
+            Some((o1, o2)) => &vs[o1..o2],
+            None => bail!("interpolate2d: input has to be contiguous"),
+        };
+        let (dst_h, dst_w) = (self.h.dst(), self.w.dst());
+        let mut dst = vec![T::zero(); vs.len() / (h * w) * dst_h * dst_w];
+        dst.par_chunks_mut(dst_h * dst_w)
+            .zip(vs.par_chunks(h * w))
+            .for_each(|(dst, src)| {
+                // Interpolate along the last dimension first, (h, w) -> (h, dst_w).
+                let mut tmp = vec![0f64; h * dst_w];
+                for (src, tmp) in src.chunks(w).zip(tmp.chunks_mut(dst_w)) {
+                    for (tmp, row) in tmp.iter_mut().zip(self.w.rows.iter()) {
+                        *tmp = row.iter().map(|&(i, wgt)| src[i].to_f64() * wgt).sum()
+                    }
+                }
+                for (dst, row) in dst.chunks_mut(dst_w).zip(self.h.rows.iter()) {
+                    for (x, dst) in dst.iter_mut().enumerate() {
+                        let v = row.iter().map(|&(i, wgt)| tmp[i * dst_w + x] * wgt).sum();
+                        *dst = T::from_f64(v)
+                    }
+                }
+            });
+        Ok(dst)
+    }
+}
+
+impl CustomOp1 for Interpolate2d {
+    fn name(&self) -> &'static str {
+        "interpolate2d"
+    }
+
+    fn cpu_fwd(&self, storage: &CpuStorage, layout: &Layout) -> Result<(CpuStorage, Shape)> {
+        let shape = self.out_shape(layout)?;
+        let storage = self.map(storage, layout)?;
+        Ok((storage, shape))
+    }
+
+    #[cfg(feature = "cuda")]
+    fn cuda_fwd(
+        &self,
+        storage: &crate::CudaStorage,
+        layout: &Layout,
+    ) -> Result<(crate::CudaStorage, Shape)> {
+        let shape = self.out_shape(layout)?;
+        let storage = cuda::interpolate2d(self, storage, layout)?;
+        Ok((storage, shape))
+    }
+
+    #[cfg(feature = "metal")]
+    fn metal_fwd(
+        &self,
+        storage: &crate::MetalStorage,
+        layout: &Layout,
+    ) -> Result<(crate::MetalStorage, Shape)> {
+        let shape = self.out_shape(layout)?;
+        let storage = metal::interpolate2d(self, storage, layout)?;
+        Ok((storage, shape))
+    }
+
+    fn bwd(&self, _arg: &Tensor, _res: &Tensor, grad_res: &Tensor) -> Result<Option<Tensor>> {
+        let op = Self {
+            h: self.h.transpose(),
+            w: self.w.transpose(),
+        };
+        Ok(Some(grad_res.contiguous()?.apply_op1(op)?))
+    }
+}
+
+// Converts normalized coordinates in [-1, 1] to pixel coordinates and applies the padding mode,
+// see `grid_sampler_compute_source_index` in PyTorch.
+fn grid_source_index(
+    coords: &Tensor,
+    size: usize,
+    padding: GridSamplePadding,
+    align_corners: bool,
+) -> Result<Tensor> {
+    let size_f = size as f64;
+    let coords = if align_corners {
+        coords.affine((size_f - 1.) / 2., (size_f - 1.) / 2.)?
+    } else {
+        coords.affine(size_f / 2., (size_f - 1.) / 2.)?
+    };
+    match padding {
+        GridSamplePadding::Zeros => Ok(coords),
+        GridSamplePadding::Border => coords.clamp(0., size_f - 1.),
+        GridSamplePadding::Reflection => {
+            let coords = if align_corners {
+                reflect_coordinates(&coords, 0., 2. * (size_f - 1.))?
+            } else {
+                reflect_coordinates(&coords, -1., 2. * size_f - 1.)?
+            };
+            coords.clamp(0., size_f - 1.)
+        }
+    }
+}
+
+fn reflect_coordinates(coords: &Tensor, twice_low: f64, twice_high: f64) -> Result<Tensor> {
+    if twice_low == twice_high {
+        return coords.zeros_like();
+    }
+    let min = twice_low / 2.;
+    let span = (twice_high - twice_low) / 2.;
+    let coords = coords.affine(1., -min)?.abs()?;
+    let flips = (&coords / span)?.floor()?.detach();
+    let extra = (&coords - (&flips * span)?)?;
+    // 0 for an even number of flips, 1 otherwise.
+    let odd = (&flips - ((&flips / 2.)?.floor()? * 2.)?)?.detach();
+    // extra + min if even, span - extra + min if odd.
+    let delta = (extra.affine(-2., span)? * odd)?;
+    (extra + delta)?.affine(1., min)
+}
+
+// Rounds to the nearest integer with ties rounded to even as `std::nearbyint` does.
+fn round_half_to_even(xs: &Tensor) -> Result<Tensor> {
+    let rounded = xs.affine(1., 0.5)?.floor()?;
+    let is_tie = (&rounded - xs)?.eq(0.5)?.to_dtype(xs.dtype())?;
+    let is_odd = (&rounded - ((&rounded / 2.)?.floor()? * 2.)?)?;
+    Ok((rounded - (is_tie * is_odd)?)?.detach())
+}
+
+// Returns the values of `xs` with shape (b, c, h * w) at the integer coordinates `(x, y)`, these
+// coordinates have shape (b, h_out, w_out). The returned tensor has shape (b, c, h_out * w_out)
+// and the out of bound values are set to zero.
+fn gather_2d(xs: &Tensor, x: &Tensor, y: &Tensor, h: usize, w: usize) -> Result<Tensor> {
+    let (b, c, _) = xs.dims3()?;
+    let dtype = xs.dtype();
+    let (h_f, w_f) = ((h - 1) as f64, (w - 1) as f64);
+    let in_bounds = (x.ge(0.)?.to_dtype(dtype)?
+        * x.le(w_f)?.to_dtype(dtype)?
+        * y.ge(0.)?.to_dtype(dtype)?
+        * y.le(h_f)?.to_dtype(dtype)?)?;
+    let x = x.clamp(0., w_f)?;
+    let y = y.clamp(0., h_f)?;
+    let idxs = (y * w as f64)?
+        .add(&x)?
+        .to_dtype(DType::U32)?
+        .flatten_from(1)?
+        .unsqueeze(1)?;
+    let n = idxs.dim(2)?;
+    let idxs = idxs.broadcast_as((b, c, n))?.contiguous()?;
+    let in_bounds = in_bounds.flatten_from(1)?.unsqueeze(1)?;
+    xs.gather(&idxs, 2)?.broadcast_mul(&in_bounds)
+}
+
+// Same as `grid_source_index` for a single coordinate, this also returns the derivative of the
+// pixel coordinate with respect to the normalized one, see
+// `grid_sampler_compute_source_index_set_grad` in PyTorch.
+fn grid_source_index_with_grad(
+    coord: f64,
+    size: usize,
+    padding: GridSamplePadding,
+    align_corners: bool,
+) -> (f64, f64) {
+    let size_f = size as f64;
+    let (coord, grad) = if align_corners {
+        ((coord + 1.) / 2. * (size_f - 1.), (size_f - 1.) / 2.)
+    } else {
+        (((coord + 1.) * size_f - 1.) / 2., size_f / 2.)
+    };
+    let clip = |coord: f64| {
+        if coord <= 0. {
+            (0., 0.)
+        } else if coord >= size_f - 1. {
+            (size_f - 1., 0.)
+        } else {
+            (coord, 1.)
+        }
+    };
+    let reflect = |coord: f64, twice_low: f64, twice_high: f64| {
+        if twice_low == twice_high {
+            return (0., 0.);
+        }
+        let min = twice_low / 2.;
+        let span = (twice_high - twice_low) / 2.;
+        let coord = coord - min;
+        let (coord, sign) = if coord < 0. {
+            (-coord, -1.)
+        } else {
+            (coord, 1.)
+        };
+        let flips = (coord / span).floor();
+        let extra = coord - flips * span;
+        if flips % 2. == 0. {
+            (extra + min, sign)
+        } else {
+            (span - extra + min, -sign)
+        }
+    };
+    match padding {
+        GridSamplePadding::Zeros => (coord, grad),
+        GridSamplePadding::Border => {
+            let (coord, g) = clip(coord);
+            (coord, grad * g)
+        }
+        GridSamplePadding::Reflection => {
+            let (coord, g1) = if align_corners {
+                reflect(coord, 0., 2. * (size_f - 1.))
+            } else {
+                reflect(coord, -1., 2. * size_f - 1.)
+            };
+            let (coord, g2) = clip(coord);
+            (coord, grad * g1 * g2)
+        }
+    }
+}
+
+// The four pixels used by the bilinear interpolation at `(x, y)` as `(x, y, weight, dweight/dx,
+// dweight/dy)`.
+fn bilinear_corners(x: f64, y: f64) -> [(f64, f64, f64, f64, f64); 4] {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (x1, y1) = (x0 + 1., y0 + 1.);
+    let (wx0, wx1, wy0, wy1) = (x1 - x, x - x0, y1 - y, y - y0);
+    [
+        (x0, y0, wx0 * wy0, -wy0, -wx0),
+        (x1, y0, wx1 * wy0, wy0, -wx1),
+        (x0, y1, wx0 * wy1, -wy1, wx0),
+        (x1, y1, wx1 * wy1, wy1, wx1),
+    ]
+}
+
+// The flat index of pixel `(x, y)` in a (h, w) image, `None` when out of bounds.
+fn pixel_index(x: f64, y: f64, h: usize, w: usize) -> Option<usize> {
+    if x >= 0. && y >= 0. && x < w as f64 && y < h as f64 {
+        Some(y as usize * w + x as usize)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GridSample {
+    mode: GridSampleMode,
+    padding: GridSamplePadding,
+    align_corners: bool,
+}
+
+impl GridSample {
+    // The pixel coordinates and their derivatives for the `n_out` grid locations of a batch.
+    fn source_indexes<T: WithDType>(&self, grid: &[T], h: usize, w: usize) -> Vec<[f64; 4]> {
+        grid.chunks(2)
+            .map(|xy| {
+                let (x, gx) = grid_source_index_with_grad(
+                    xy[0].to_f64(),
+                    w,
+                    self.padding,
+                    self.align_corners,
+                );
+                let (y, gy) = grid_source_index_with_grad(
+                    xy[1].to_f64(),
+                    h,
+                    self.padding,
+                    self.align_corners,
+                );
+                [x, gx, y, gy]
+            })
+            .collect()
+    }
+
+    // The mode, padding, and align_corners flag as passed to the cuda and metal kernels, the
+    // values match the defines in `resample.cu`.
+    #[cfg(any(feature = "cuda", feature = "metal"))]
+    fn kernel_params(&self) -> (u32, u32, bool) {
+        let mode = match self.mode {
+            GridSampleMode::Bilinear => 0,
+            GridSampleMode::Nearest => 1,
+        };
+        let padding = match self.padding {
+            GridSamplePadding::Zeros => 0,
+            GridSamplePadding::Border => 1,
+            GridSamplePadding::Reflection => 2,
+        };
+        (mode, padding, self.align_corners)
+    }
+}
+
+fn contiguous_slice<'a, T>(vs: &'a [T], layout: &Layout, op: &'static str) -> Result<&'a [T]> {
+    match layout.contiguous_offsets() {
+        Some((o1, o2)) => Ok(&vs[o1..o2]),
+        None => bail!("{op}: inputs have to be contiguous"),
+    }
+}
+
+impl Map2 for GridSample {
+    const OP: &'static str = "grid_sample";
+
+    fn f<T: WithDType>(
+        &self,
+        xs: &[T],
+        xs_l: &Layout,
+        grid: &[T],
+        grid_l: &Layout,
+    ) -> Result<Vec<T>> {
+        let (_b, c, h, w) = xs_l.shape().dims4()?;
+        let (_b, h_out, w_out, _) = grid_l.shape().dims4()?;
+        let xs = contiguous_slice(xs, xs_l, Self::OP)?;
+        let grid = contiguous_slice(grid, grid_l, Self::OP)?;
+        let n_out = h_out * w_out;
+        let mut dst = vec![T::zero(); xs.len() / (h * w) * n_out];
+        dst.par_chunks_mut(c * n_out)
+            .zip(xs.par_chunks(c * h * w))
+            .zip(grid.par_chunks(2 * n_out))
+            .for_each(|((dst, xs), grid)| {
+                let idxs = self.source_indexes(grid, h, w);
+                for (dst, xs) in dst.chunks_mut(n_out).zip(xs.chunks(h * w)) {
+                    for (dst, &[x, _, y, _]) in dst.iter_mut().zip(idxs.iter()) {
+                        let v = match self.mode {
+                            GridSampleMode::Nearest => {
+                                match pixel_index(x.round_ties_even(), y.round_ties_even(), h, w) {
+                                    Some(i) => xs[i].to_f64(),
+                                    None => 0.,
+                                }
+                            }
+                            GridSampleMode::Bilinear => bilinear_corners(x, y)
+                                .iter()
+                                .filter_map(|&(x, y, wgt, _, _)| {
+                                    pixel_index(x, y, h, w).map(|i| xs[i].to_f64() * wgt)
+                                })
+                                .sum(),
+                        };
+                        *dst = T::from_f64(v)
+                    }
+                }
+            });
+        Ok(dst)
+    }
+}
+
+impl CustomOp2 for GridSample {
+    fn name(&self) -> &'static str {
+        "grid-sample"
+    }
+
+    fn cpu_fwd(
+        &self,
+        s1: &CpuStorage,
+        l1: &Layout,
+        s2: &CpuStorage,
+        l2: &Layout,
+    ) -> Result<(CpuStorage, Shape)> {
+        let (b, c, _h, _w) = l1.shape().dims4()?;
+        let (_b, h_out, w_out, _) = l2.shape().dims4()?;
+        let storage = self.map(s1, l1, s2, l2)?;
+        Ok((storage, (b, c, h_out, w_out).into()))
+    }
+
+    #[cfg(feature = "cuda")]
+    fn cuda_fwd(
+        &self,
+        s1: &crate::CudaStorage,
+        l1: &Layout,
+        s2: &crate::CudaStorage,
+        l2: &Layout,
+    ) -> Result<(crate::CudaStorage, Shape)> {
+        let (b, c, _h, _w) = l1.shape().dims4()?;
+        let (_b, h_out, w_out, _) = l2.shape().dims4()?;
+        let storage = cuda::grid_sample(self, (s1, l1), (s2, l2))?;
+        Ok((storage, (b, c, h_out, w_out).into()))
+    }
+
+    #[cfg(feature = "metal")]
+    fn metal_fwd(
+        &self,
+        s1: &crate::MetalStorage,
+        l1: &Layout,
+        s2: &crate::MetalStorage,
+        l2: &Layout,
+    ) -> Result<(crate::MetalStorage, Shape)> {
+        let (b, c, _h, _w) = l1.shape().dims4()?;
+        let (_b, h_out, w_out, _) = l2.shape().dims4()?;
+        let storage = metal::grid_sample(self, (s1, l1), (s2, l2))?;
+        Ok((storage, (b, c, h_out, w_out).into()))
+    }
+
+    fn bwd(
+        &self,
+        xs: &Tensor,
+        grid: &Tensor,
+        _res: &Tensor,
+        grad_res: &Tensor,
+    ) -> Result<(Option<Tensor>, Option<Tensor>)> {
+        // Both gradients are computed in a single pass and returned concatenated.
+        let grads =
+            xs.apply_op3_no_bwd(grid, &grad_res.contiguous()?, &GridSampleBackward(*self))?;
+        let n = xs.elem_count();
+        let grad_xs = grads.narrow(0, 0, n)?.reshape(xs.shape())?;
+        let grad_grid = grads
+            .narrow(0, n, grid.elem_count())?
+            .reshape(grid.shape())?;
+        Ok((Some(grad_xs), Some(grad_grid)))
+    }
+}
+
+// Returns the flattened gradients of the input followed by the ones of the grid.
+struct GridSampleBackward(GridSample);
+
+impl GridSampleBackward {
+    fn f<T: WithDType>(
+        &self,
+        (xs, xs_l): (&[T], &Layout),
+        (grid, grid_l): (&[T], &Layout),
+        (grad, grad_l): (&[T], &Layout),
+    ) -> Result<Vec<T>> {
+        const OP: &str = "grid_sample backward";
+        let (_b, c, h, w) = xs_l.shape().dims4()?;
+        let (_b, h_out, w_out, _) = grid_l.shape().dims4()?;
+        let xs = contiguous_slice(xs, xs_l, OP)?;
+        let grid = contiguous_slice(grid, grid_l, OP)?;
+        let grad = contiguous_slice(grad, grad_l, OP)?;
+        let n_out = h_out * w_out;
+        let mut grad_xs = vec![0f64; xs.len()];
+        let mut grad_grid = vec![0f64; grid.len()];
+        grad_xs
+            .par_chunks_mut(c * h * w)
+            .zip(grad_grid.par_chunks_mut(2 * n_out))
+            .zip(xs.par_chunks(c * h * w))
+            .zip(grid.par_chunks(2 * n_out).zip(grad.par_chunks(c * n_out)))
+            .for_each(|(((grad_xs, grad_grid), xs), (grid, grad))| {
+                let idxs = self.0.source_indexes(grid, h, w);
+                let channels = grad_xs
+                    .chunks_mut(h * w)
+                    .zip(xs.chunks(h * w))
+                    .zip(grad.chunks(n_out));
+                for ((grad_xs, xs), grad) in channels {
+                    for (p, &[x, _, y, _]) in idxs.iter().enumerate() {
+                        let g = grad[p].to_f64();
+                        match self.0.mode {
+                            GridSampleMode::Nearest => {
+                                let (x, y) = (x.round_ties_even(), y.round_ties_even());
+                                if let Some(i) = pixel_index(x, y, h, w) {
+                                    grad_xs[i] += g
+                                }
+                            }
+                            GridSampleMode::Bilinear => {
+                                for (x, y, wgt, dw_dx, dw_dy) in bilinear_corners(x, y) {
+                                    if let Some(i) = pixel_index(x, y, h, w) {
+                                        let v = xs[i].to_f64();
+                                        grad_xs[i] += wgt * g;
+                                        grad_grid[2 * p] += v * dw_dx * g;
+                                        grad_grid[2 * p + 1] += v * dw_dy * g;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                for (grad_grid, &[_, gx, _, gy]) in grad_grid.chunks_mut(2).zip(idxs.iter()) {
+                    grad_grid[0] *= gx;
+                    grad_grid[1] *= gy;
+                }
+            });
+        Ok(grad_xs
+            .into_iter()
+            .chain(grad_grid)
+            .map(T::from_f64)
+            .collect())
+    }
+}
+
+impl CustomOp3 for GridSampleBackward {
+    fn name(&self) -> &'static str {
+        "grid-sample-backward"
+    }
+
+    fn cpu_fwd(
+        &self,
+        s1: &CpuStorage,
+        l1: &Layout,
+        s2: &CpuStorage,
+        l2: &Layout,
+        s3: &CpuStorage,
+        l3: &Layout,
+    ) -> Result<(CpuStorage, Shape)> {
+        use CpuStorage as C;
+        let n = l1.shape().elem_count() + l2.shape().elem_count();
+        let storage = match (s1, s2, s3) {
+            (C::BF16(s1), C::BF16(s2), C::BF16(s3)) => {
+                C::BF16(self.f((s1, l1), (s2, l2), (s3, l3))?.into())
+            }
+            (C::F16(s1), C::F16(s2), C::F16(s3)) => {
+                C::F16(self.f((s1, l1), (s2, l2), (s3, l3))?.into())
+            }
+            (C::F32(s1), C::F32(s2), C::F32(s3)) => {
+                C::F32(self.f((s1, l1), (s2, l2), (s3, l3))?.into())
+            }
+            (C::F64(s1), C::F64(s2), C::F64(s3)) => {
+                C::F64(self.f((s1, l1), (s2, l2), (s3, l3))?.into())
+            }
+            _ => bail!(
+                "grid_sample backward: unsupported dtypes {:?} {:?} {:?}",
+                s1.dtype(),
+                s2.dtype(),
+                s3.dtype()
+            ),
+        };
+        Ok((storage, n.into()))
+    }
+
+    #[cfg(feature = "cuda")]
+    fn cuda_fwd(
+        &self,
+        s1: &crate::CudaStorage,
+        l1: &Layout,
+        s2: &crate::CudaStorage,
+        l2: &Layout,
+        s3: &crate::CudaStorage,
+        l3: &Layout,
+    ) -> Result<(crate::CudaStorage, Shape)> {
+        let n = l1.shape().elem_count() + l2.shape().elem_count();
+        let storage = cuda::grid_sample_bwd(&self.0, (s1, l1), (s2, l2), (s3, l3))?;
+        Ok((storage, n.into()))
+    }
+
+    #[cfg(feature = "metal")]
+    fn metal_fwd(
+        &self,
+        s1: &crate::MetalStorage,
+        l1: &Layout,
+        s2: &crate::MetalStorage,
+        l2: &Layout,
+        s3: &crate::MetalStorage,
+        l3: &Layout,
+    ) -> Result<(crate::MetalStorage, Shape)> {
+        let n = l1.shape().elem_count() + l2.shape().elem_count();
+        let storage = metal::grid_sample_bwd(&self.0, (s1, l1), (s2, l2), (s3, l3))?;
+        Ok((storage, n.into()))
+    }
+}
+
+impl Tensor {
+    /// Resizes the input tensor to `(target_h, target_w)` using the given interpolation mode.
+    ///
+    /// The input tensor should have four dimensions, `(batch, channels, h, w)`, the returned
+    /// tensor also has four dimensions, `(batch, channels, target_h, target_w)`. When
+    /// `align_corners` is true, the corner pixels of the input and output are aligned, this
+    /// matches the `align_corners` argument of PyTorch `interpolate`. `align_corners` cannot be
+    /// used with the nearest mode.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device, InterpolateMode};
+    /// let t = Tensor::new(&[[[[0f32, 1.], [2., 3.]]]], &Device::Cpu)?;
+    /// let t = t.interpolate2d_with_mode(3, 3, InterpolateMode::Bilinear, true)?;
+    /// assert_eq!(
+    ///     t.squeeze(0)?.squeeze(0)?.to_vec2::<f32>()?,
+    ///     &[[0., 0.5, 1.], [1., 1.5, 2.], [2., 2.5, 3.]]
+    /// );
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn interpolate2d_with_mode(
+        &self,
+        target_h: usize,
+        target_w: usize,
+        mode: InterpolateMode,
+        align_corners: bool,
+    ) -> Result<Self> {
+        let (_b, _c, h, w) = self.dims4()?;
+        if mode == InterpolateMode::Nearest {
+            if align_corners {
+                bail!("align_corners cannot be used with nearest interpolation")
+            }
+            return self.interpolate2d(target_h, target_w);
+        }
+        if !self.dtype().is_float() {
+            Err(Error::UnsupportedDTypeForOp(self.dtype(), "interpolate2d_with_mode").bt())?
+        }
+        let weights = |src, dst| {
+            if src == dst {
+                Weights::identity(src)
+            } else {
+                interpolation_weights(src, dst, mode, align_corners)
+            }
+        };
+        let (h, w) = (weights(h, target_h), weights(w, target_w));
+        if self.device().is_wgpu() {
+            self.interpolate2d_matmul(&h, &w)
+        } else {
+            self.contiguous()?.apply_op1(Interpolate2d { h, w })
+        }
+    }
+
+    // The interpolation expressed as matmuls with the dense weight matrices, this is used on wgpu
+    // which does not have a dedicated kernel.
+    fn interpolate2d_matmul(&self, h: &Weights, w: &Weights) -> Result<Self> {
+        let dense = |weights: &Weights| {
+            let dense = weights.to_dense();
+            Tensor::from_vec(dense, (weights.dst(), weights.src), self.device())?
+                .to_dtype(self.dtype())
+        };
+        let xs = if w.src == w.dst() {
+            self.clone()
+        } else {
+            self.broadcast_matmul(&dense(w)?.t()?)?
+        };
+        if h.src == h.dst() {
+            Ok(xs)
+        } else {
+            dense(h)?.broadcast_matmul(&xs)
+        }
+    }
+
+    /// Samples the input tensor at the locations specified by `grid`.
+    ///
+    /// The input tensor has shape `(batch, channels, h, w)` and `grid` has shape
+    /// `(batch, h_out, w_out, 2)`, the returned tensor has shape `(batch, channels, h_out,
+    /// w_out)`. The last dimension of `grid` contains the `x` and `y` locations normalized to
+    /// `[-1, 1]`, `(-1, -1)` being the top-left pixel and `(1, 1)` the bottom-right one. With
+    /// `align_corners` set to true, these refer to the centers of the corner pixels, otherwise
+    /// they refer to the corner points of these pixels.
+    ///
+    /// This is differentiable with respect to both the input tensor and the grid.
+    pub fn grid_sample(
+        &self,
+        grid: &Tensor,
+        mode: GridSampleMode,
+        padding: GridSamplePadding,
+        align_corners: bool,
+    ) -> Result<Self> {
+        let (b, _c, _h, _w) = self.dims4()?;
+        let (grid_b, _h_out, _w_out, two) = grid.dims4()?;
+        if grid_b != b || two != 2 {
+            bail!(
+                "grid_sample expects a grid of shape ({b}, h_out, w_out, 2), got {:?}",
+                grid.shape()
+            )
+        }
+        if !self.dtype().is_float() {
+            Err(Error::UnsupportedDTypeForOp(self.dtype(), "grid_sample").bt())?
+        }
+        if grid.dtype() != self.dtype() {
+            Err(Error::DTypeMismatchBinaryOp {
+                lhs: self.dtype(),
+                rhs: grid.dtype(),
+                op: "grid_sample",
+            }
+            .bt())?
+        }
+        if self.device().is_wgpu() {
+            self.grid_sample_gather(grid, mode, padding, align_corners)
+        } else {
+            let op = GridSample {
+                mode,
+                padding,
+                align_corners,
+            };
+            self.contiguous()?.apply_op2(&grid.contiguous()?, op)
+        }
+    }
+
+    // The grid sampling expressed with gather and elementwise ops, this is used on wgpu which
+    // does not have a dedicated kernel.
+    fn grid_sample_gather(
+        &self,
+        grid: &Tensor,
+        mode: GridSampleMode,
+        padding: GridSamplePadding,
+        align_corners: bool,
+    ) -> Result<Self> {
+        let (b, c, h, w) = self.dims4()?;
+        let (_, h_out, w_out, _) = grid.dims4()?;
+        let x = grid_source_index(
+            &grid.narrow(3, 0, 1)?.squeeze(3)?,
+            w,
+            padding,
+            align_corners,
+        )?;
+        let y = grid_source_index(
+            &grid.narrow(3, 1, 1)?.squeeze(3)?,
+            h,
+            padding,
+            align_corners,
+        )?;
+        let xs = self.flatten_from(2)?;
+        let ys = match mode {
+            GridSampleMode::Nearest => {
+                let x = round_half_to_even(&x)?;
+                let y = round_half_to_even(&y)?;
+                gather_2d(&xs, &x, &y, h, w)?
+            }
+            GridSampleMode::Bilinear => {
+                let x0 = x.floor()?.detach();
+                let y0 = y.floor()?.detach();
+                let x1 = x0.affine(1., 1.)?;
+                let y1 = y0.affine(1., 1.)?;
+                let (wx0, wx1) = ((&x1 - &x)?, (&x - &x0)?);
+                let (wy0, wy1) = ((&y1 - &y)?, (&y - &y0)?);
+                let corners = [
+                    (&x0, &y0, &wx0, &wy0),
+                    (&x1, &y0, &wx1, &wy0),
+                    (&x0, &y1, &wx0, &wy1),
+                    (&x1, &y1, &wx1, &wy1),
+                ];
+                let mut ys = None;
+                for (x, y, wx, wy) in corners {
+                    let weight = (wx * wy)?.flatten_from(1)?.unsqueeze(1)?;
+                    let vs = gather_2d(&xs, x, y, h, w)?.broadcast_mul(&weight)?;
+                    ys = Some(match ys {
+                        None => vs,
+                        Some(ys) => (ys + vs)?,
+                    })
+                }
+                ys.expect("non-empty corners")
+            }
+        };
+        ys.reshape((b, c, h_out, w_out))
+    }
+
+    /// Generates the sampling grid for the batch of affine transformations `theta` with shape
+    /// `(batch, 2, 3)`. The returned grid has shape `(batch, h, w, 2)` where `(batch, _, h, w)` is
+    /// the size of the output image, it can be used with [`Tensor::grid_sample`].
+    ///
+    /// ```rust
+    /// use candle_core::{test_utils, Device, GridSampleMode, GridSamplePadding, Tensor};
+    /// let img = Tensor::arange(0f32, 6., &Device::Cpu)?.reshape((1, 1, 2, 3))?;
+    /// // Sampling with the identity transformation returns the input image.
+    /// let theta = Tensor::new(&[[[1f32, 0., 0.], [0., 1., 0.]]], &Device::Cpu)?;
+    /// let grid = Tensor::affine_grid(&theta, (1, 1, 2, 3), false)?;
+    /// let ys = img.grid_sample(&grid, GridSampleMode::Bilinear, GridSamplePadding::Zeros, false)?;
+    /// let ys = test_utils::to_vec1_round(&ys.flatten_all()?, 4)?;
+    /// assert_eq!(ys, [0., 1., 2., 3., 4., 5.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn affine_grid(
+        theta: &Tensor,
+        size: (usize, usize, usize, usize),
+        align_corners: bool,
+    ) -> Result<Self> {
+        let (b, _c, h, w) = size;
+        let (theta_b, two, three) = theta.dims3()?;
+        if theta_b != b || two != 2 || three != 3 {
+            bail!(
+                "affine_grid expects theta of shape ({b}, 2, 3), got {:?}",
+                theta.shape()
+            )
+        }
+        let coords = |size: usize| -> Vec<f64> {
+            if size <= 1 {
+                return vec![0.; size];
+            }
+            (0..size)
+                .map(|i| {
+                    if align_corners {
+                        2. * i as f64 / (size - 1) as f64 - 1.
+                    } else {
+                        (2 * i + 1) as f64 / size as f64 - 1.
+                    }
+                })
+                .collect()
+        };
+        let (xs, ys) = (coords(w), coords(h));
+        let mut base = Vec::with_capacity(h * w * 3);
+        for y in ys.iter() {
+            for x in xs.iter() {
+                base.extend_from_slice(&[*x, *y, 1.])
+            }
+        }
+        let base =
+            Tensor::from_vec(base, (1, h * w, 3), theta.device())?.to_dtype(theta.dtype())?;
+        base.broadcast_matmul(&theta.t()?)?.reshape((b, h, w, 2))
+    }
+}
+
+#[cfg(feature = "cuda")]
+mod cuda {
+    use super::{GridSample, Interpolate2d};
+    use crate::backend::BackendStorage;
+    use crate::cuda_backend::cudarc::driver::{
+        CudaSlice, CudaView, DeviceRepr, LaunchAsync, LaunchConfig, ValidAsZeroBits,
+    };
+    use crate::cuda_backend::{kernel_name, kernels, CudaStorageSlice as S, WrapErr};
+    use crate::{bail, CudaDevice, CudaStorage, DType, Layout, Result, WithDType};
+    use half::{bf16, f16};
+
+    fn contiguous_slice<'a, T>(
+        slice: &'a CudaSlice<T>,
+        layout: &Layout,
+        op: &'static str,
+    ) -> Result<CudaView<'a, T>> {
+        if !layout.is_contiguous() {
+            bail!("{op}: inputs have to be contiguous")
+        }
+        Ok(slice.slice(layout.start_offset()..))
+    }
+
+    // `A` is the type used for the weights and the accumulation.
+    fn interpolate2d_<T, A>(
+        op: &Interpolate2d,
+        src: &CudaSlice<T>,
+        layout: &Layout,
+        dev: &CudaDevice,
+    ) -> Result<CudaSlice<T>>
+    where
+        T: DeviceRepr + WithDType,
+        A: DeviceRepr + WithDType + Unpin,
+    {
+        let (b, c, h, w) = layout.shape().dims4()?;
+        let src = &contiguous_slice(src, layout, "interpolate2d")?;
+        let (h_out, w_out) = (op.h.dst(), op.w.dst());
+        let (idxs, wgts, nnz_h) = op.packed_weights();
+        let wgts = wgts.into_iter().map(A::from_f64).collect::<Vec<_>>();
+        let idxs = dev.htod_copy(idxs).w()?;
+        let wgts = dev.htod_copy(wgts).w()?;
+        let dst_el = b * c * h_out * w_out;
+        let cfg = LaunchConfig::for_num_elems(dst_el as u32);
+        let func = dev.get_or_load_func(&kernel_name::<T>("interpolate2d"), kernels::RESAMPLE)?;
+        // SAFETY: Set later by running the kernel.
+        let dst = unsafe { dev.alloc::<T>(dst_el) }.w()?;
+        let params = (dst_el, h, w, h_out, w_out, nnz_h, &idxs, &wgts, src, &dst);
+        // SAFETY: ffi.
+        unsafe { func.launch(cfg, params) }.w()?;
+        Ok(dst)
+    }
+
+    pub(super) fn interpolate2d(
+        op: &Interpolate2d,
+        storage: &CudaStorage,
+        layout: &Layout,
+    ) -> Result<CudaStorage> {
+        let dev = &storage.device;
+        let slice = match &storage.slice {
+            S::BF16(s) => S::BF16(interpolate2d_::<bf16, f32>(op, s, layout, dev)?),
+            S::F16(s) => S::F16(interpolate2d_::<f16, f32>(op, s, layout, dev)?),
+            S::F32(s) => S::F32(interpolate2d_::<f32, f32>(op, s, layout, dev)?),
+            S::F64(s) => S::F64(interpolate2d_::<f64, f64>(op, s, layout, dev)?),
+            _ => bail!("interpolate2d: unsupported dtype {:?}", storage.dtype()),
+        };
+        Ok(CudaStorage {
+            slice,
+            device: dev.clone(),
+        })
+    }
+
+    fn grid_sample_<T: DeviceRepr + WithDType>(
+        op: &GridSample,
+        (xs, xs_l): (&CudaSlice<T>, &Layout),
+        (grid, grid_l): (&CudaSlice<T>, &Layout),
+        dev: &CudaDevice,
+    ) -> Result<CudaSlice<T>> {
+        let (b, c, h, w) = xs_l.shape().dims4()?;
+        let (_b, h_out, w_out, _) = grid_l.shape().dims4()?;
+        let xs = &contiguous_slice(xs, xs_l, "grid_sample")?;
+        let grid = &contiguous_slice(grid, grid_l, "grid_sample")?;
+        let (mode, padding, align_corners) = op.kernel_params();
+        let n_out = h_out * w_out;
+        let dst_el = b * c * n_out;
+        let cfg = LaunchConfig::for_num_elems(dst_el as u32);
+        let func = dev.get_or_load_func(&kernel_name::<T>("grid_sample"), kernels::RESAMPLE)?;
+        // SAFETY: Set later by running the kernel.
+        let dst = unsafe { dev.alloc::<T>(dst_el) }.w()?;
+        let params = (
+            dst_el,
+            c,
+            h,
+            w,
+            n_out,
+            mode,
+            padding,
+            align_corners as u32,
+            xs,
+            grid,
+            &dst,
+        );
+        // SAFETY: ffi.
+        unsafe { func.launch(cfg, params) }.w()?;
+        Ok(dst)
+    }
+
+    pub(super) fn grid_sample(
+        op: &GridSample,
+        (s1, l1): (&CudaStorage, &Layout),
+        (s2, l2): (&CudaStorage, &Layout),
+    ) -> Result<CudaStorage> {
+        let dev = &s1.device;
+        let slice = match (&s1.slice, &s2.slice) {
+            (S::BF16(s1), S::BF16(s2)) => S::BF16(grid_sample_(op, (s1, l1), (s2, l2), dev)?),
+            (S::F16(s1), S::F16(s2)) => S::F16(grid_sample_(op, (s1, l1), (s2, l2), dev)?),
+            (S::F32(s1), S::F32(s2)) => S::F32(grid_sample_(op, (s1, l1), (s2, l2), dev)?),
+            (S::F64(s1), S::F64(s2)) => S::F64(grid_sample_(op, (s1, l1), (s2, l2), dev)?),
+            _ => bail!(
+                "grid_sample: unsupported dtypes {:?} {:?}",
+                s1.dtype(),
+                s2.dtype()
+            ),
+        };
+        Ok(CudaStorage {
+            slice,
+            device: dev.clone(),
+        })
+    }
+
+    // The gradients are accumulated with atomic adds in a buffer of type `A`.
+    fn grid_sample_bwd_<T, A>(
+        op: &GridSample,
+        (xs, xs_l): (&CudaSlice<T>, &Layout),
+        (grid, grid_l): (&CudaSlice<T>, &Layout),
+        (grad, grad_l): (&CudaSlice<T>, &Layout),
+        dev: &CudaDevice,
+    ) -> Result<CudaSlice<A>>
+    where
+        T: DeviceRepr + WithDType,
+        A: DeviceRepr + ValidAsZeroBits,
+    {
+        const OP: &str = "grid_sample backward";
+        let (b, c, h, w) = xs_l.shape().dims4()?;
+        let (_b, h_out, w_out, _) = grid_l.shape().dims4()?;
+        let xs = &contiguous_slice(xs, xs_l, OP)?;
+        let grid = &contiguous_slice(grid, grid_l, OP)?;
+        let grad = &contiguous_slice(grad, grad_l, OP)?;
+        let (mode, padding, align_corners) = op.kernel_params();
+        let n_out = h_out * w_out;
+        let grads = dev
+            .alloc_zeros::<A>(xs_l.shape().elem_count() + grid_l.shape().elem_count())
+            .w()?;
+        let el = b * n_out;
+        let cfg = LaunchConfig::for_num_elems(el as u32);
+        let func = dev.get_or_load_func(&kernel_name::<T>("grid_sample_bwd"), kernels::RESAMPLE)?;
+        let params = (
+            el,
+            c,
+            h,
+            w,
+            n_out,
+            mode,
+            padding,
+            align_corners as u32,
+            xs,
+            grid,
+            grad,
+            &grads,
+        );
+        // SAFETY: ffi.
+        unsafe { func.launch(cfg, params) }.w()?;
+        Ok(grads)
+    }
+
+    pub(super) fn grid_sample_bwd(
+        op: &GridSample,
+        (s1, l1): (&CudaStorage, &Layout),
+        (s2, l2): (&CudaStorage, &Layout),
+        (s3, l3): (&CudaStorage, &Layout),
+    ) -> Result<CudaStorage> {
+        let dev = &s1.device;
+        // The half precision gradients are accumulated in f32 and converted at the end.
+        let slice = match (&s1.slice, &s2.slice, &s3.slice) {
+            (S::BF16(s1), S::BF16(s2), S::BF16(s3)) => S::F32(grid_sample_bwd_::<bf16, f32>(
+                op,
+                (s1, l1),
+                (s2, l2),
+                (s3, l3),
+                dev,
+            )?),
+            (S::F16(s1), S::F16(s2), S::F16(s3)) => S::F32(grid_sample_bwd_::<f16, f32>(
+                op,
+                (s1, l1),
+                (s2, l2),
+                (s3, l3),
+                dev,
+            )?),
+            (S::F32(s1), S::F32(s2), S::F32(s3)) => S::F32(grid_sample_bwd_::<f32, f32>(
+                op,
+                (s1, l1),
+                (s2, l2),
+                (s3, l3),
+                dev,
+            )?),
+            (S::F64(s1), S::F64(s2), S::F64(s3)) => S::F64(grid_sample_bwd_::<f64, f64>(
+                op,
+                (s1, l1),
+                (s2, l2),
+                (s3, l3),
+                dev,
+            )?),
+            _ => bail!(
+                "grid_sample backward: unsupported dtypes {:?} {:?} {:?}",
+                s1.dtype(),
+                s2.dtype(),
+                s3.dtype()
+            ),
+        };
+        let grads = CudaStorage {
+            slice,
+            device: dev.clone(),
+        };
+        let dtype: DType = s1.dtype();
+        if grads.dtype() == dtype {
+            Ok(grads)
+        } else {
+            let n = l1.shape().elem_count() + l2.shape().elem_count();
+            grads.to_dtype(&Layout::contiguous(n), dtype)
+        }
+    }
+}
+
+#[cfg(feature = "metal")]
+mod metal {
+    use super::{GridSample, Interpolate2d};
+    use crate::backend::BackendStorage;
+    use crate::{bail, DType, Layout, MetalError, MetalStorage, Result};
+
+    fn kernel_name(root: &'static str, dtype: DType) -> Result<&'static str> {
+        let name = match (root, dtype) {
+            ("interpolate2d", DType::F32) => "interpolate2d_f32",
+            ("interpolate2d", DType::F16) => "interpolate2d_f16",
+            ("interpolate2d", DType::BF16) => "interpolate2d_bf16",
+            ("grid_sample", DType::F32) => "grid_sample_f32",
+            ("grid_sample", DType::F16) => "grid_sample_f16",
+            ("grid_sample", DType::BF16) => "grid_sample_bf16",
+            ("grid_sample_bwd", DType::F32) => "grid_sample_bwd_f32",
+            ("grid_sample_bwd", DType::F16) => "grid_sample_bwd_f16",
+            ("grid_sample_bwd", DType::BF16) => "grid_sample_bwd_bf16",
+            _ => bail!("Metal {root} {dtype:?} not implemented"),
+        };
+        Ok(name)
+    }
+
+    // The offset of a contiguous layout in bytes.
+    fn offset_in_bytes(layout: &Layout, dtype: DType, op: &'static str) -> Result<usize> {
+        if !layout.is_contiguous() {
+            bail!("{op}: inputs have to be contiguous")
+        }
+        Ok(layout.start_offset() * dtype.size_in_bytes())
+    }
+
+    pub(super) fn interpolate2d(
+        op: &Interpolate2d,
+        storage: &MetalStorage,
+        layout: &Layout,
+    ) -> Result<MetalStorage> {
+        let device = storage.device();
+        let dtype = storage.dtype();
+        let name = kernel_name("interpolate2d", dtype)?;
+        let offset = offset_in_bytes(layout, dtype, "interpolate2d")?;
+        let (b, c, h, w) = layout.shape().dims4()?;
+        let (h_out, w_out) = (op.h.dst(), op.w.dst());
+        let (idxs, wgts, nnz_h) = op.packed_weights();
+        let wgts = wgts.into_iter().map(|w| w as f32).collect::<Vec<_>>();
+        let idxs = device.new_buffer_with_data(&idxs)?;
+        let wgts = device.new_buffer_with_data(&wgts)?;
+        let buffer = device.new_buffer(b * c * h_out * w_out, dtype, "interpolate2d")?;
+        let command_buffer = device.command_buffer()?;
+        candle_metal_kernels::call_interpolate2d(
+            device.metal_device(),
+            &command_buffer,
+            device.kernels(),
+            name,
+            (b * c, h, w),
+            (h_out, w_out),
+            nnz_h,
+            &idxs,
+            &wgts,
+            storage.buffer(),
+            offset,
+            &buffer,
+        )
+        .map_err(MetalError::from)?;
+        Ok(MetalStorage::new(buffer, device.clone(), dtype))
+    }
+
+    pub(super) fn grid_sample(
+        op: &GridSample,
+        (s1, l1): (&MetalStorage, &Layout),
+        (s2, l2): (&MetalStorage, &Layout),
+    ) -> Result<MetalStorage> {
+        let device = s1.device();
+        let dtype = s1.dtype();
+        if s2.dtype() != dtype {
+            bail!("grid_sample: unsupported dtypes {dtype:?} {:?}", s2.dtype())
+        }
+        let name = kernel_name("grid_sample", dtype)?;
+        let (b, c, h, w) = l1.shape().dims4()?;
+        let (_b, h_out, w_out, _) = l2.shape().dims4()?;
+        let n_out = h_out * w_out;
+        let buffer = device.new_buffer(b * c * n_out, dtype, "grid_sample")?;
+        let command_buffer = device.command_buffer()?;
+        candle_metal_kernels::call_grid_sample(
+            device.metal_device(),
+            &command_buffer,
+            device.kernels(),
+            name,
+            (b, c, h, w),
+            n_out,
+            op.kernel_params(),
+            s1.buffer(),
+            offset_in_bytes(l1, dtype, "grid_sample")?,
+            s2.buffer(),
+            offset_in_bytes(l2, dtype, "grid_sample")?,
+            &buffer,
+        )
+        .map_err(MetalError::from)?;
+        Ok(MetalStorage::new(buffer, device.clone(), dtype))
+    }
+
+    pub(super) fn grid_sample_bwd(
+        op: &GridSample,
+        (s1, l1): (&MetalStorage, &Layout),
+        (s2, l2): (&MetalStorage, &Layout),
+        (s3, l3): (&MetalStorage, &Layout),
+    ) -> Result<MetalStorage> {
+        const OP: &str = "grid_sample backward";
+        let device = s1.device();
+        let dtype = s1.dtype();
+        if s2.dtype() != dtype || s3.dtype() != dtype {
+            bail!(
+                "{OP}: unsupported dtypes {dtype:?} {:?} {:?}",
+                s2.dtype(),
+                s3.dtype()
+            )
+        }
+        let name = kernel_name("grid_sample_bwd", dtype)?;
+        let (b, c, h, w) = l1.shape().dims4()?;
+        let (_b, h_out, w_out, _) = l2.shape().dims4()?;
+        let n = l1.shape().elem_count() + l2.shape().elem_count();
+        // The gradients are accumulated in f32 and converted to the input dtype at the end.
+        let buffer = device.allocate_zeros(n * DType::F32.size_in_bytes())?;
+        let command_buffer = device.command_buffer()?;
+        candle_metal_kernels::call_grid_sample_bwd(
+            device.metal_device(),
+            &command_buffer,
+            device.kernels(),
+            name,
+            (b, c, h, w),
+            h_out * w_out,
+            op.kernel_params(),
+            s1.buffer(),
+            offset_in_bytes(l1, dtype, OP)?,
+            s2.buffer(),
+            offset_in_bytes(l2, dtype, OP)?,
+            s3.buffer(),
+            offset_in_bytes(l3, dtype, OP)?,
+            &buffer,
+        )
+        .map_err(MetalError::from)?;
+        let grads = MetalStorage::new(buffer, device.clone(), DType::F32);
+        if dtype == DType::F32 {
+            Ok(grads)
+        } else {
+            grads.to_dtype(&Layout::contiguous(n), dtype)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Device, Var};
+
+    fn assert_close(lhs: &Tensor, rhs: &Tensor) -> Result<()> {
+        let diff = (lhs - rhs)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f64>()?;
+        assert!(diff < 1e-10, "max diff {diff}");
+        Ok(())
+    }
+
+    // The kernels and the composite implementation used on wgpu should agree, both for the
+    // forward pass and for the gradients.
+    #[test]
+    fn kernels_match_composite() -> Result<()> {
+        let dev = &Device::Cpu;
+        let xs = Var::rand_f64(-1., 1., (2, 3, 5, 7), DType::F64, dev)?;
+        let ws = Tensor::rand(-1f64, 1., (2, 3, 4, 9), dev)?;
+        for mode in [
+            InterpolateMode::Bilinear,
+            InterpolateMode::Bicubic,
+            InterpolateMode::BilinearAntialias,
+            InterpolateMode::BicubicAntialias,
+        ] {
+            for align_corners in [false, true] {
+                let ys = xs.interpolate2d_with_mode(4, 9, mode, align_corners)?;
+                let h = interpolation_weights(5, 4, mode, align_corners);
+                let w = interpolation_weights(7, 9, mode, align_corners);
+                let ys_ = xs.interpolate2d_matmul(&h, &w)?;
+                assert_close(&ys, &ys_)?;
+                let grad = (ys * &ws)?.sum_all()?.backward()?;
+                let grad_ = (ys_ * &ws)?.sum_all()?.backward()?;
+                assert_close(grad.get(&xs).unwrap(), grad_.get(&xs).unwrap())?;
+            }
+        }
+
+        let grid = Var::rand_f64(-1.3, 1.3, (2, 4, 9, 2), DType::F64, dev)?;
+        for mode in [GridSampleMode::Bilinear, GridSampleMode::Nearest] {
+            for padding in [
+                GridSamplePadding::Zeros,
+                GridSamplePadding::Border,
+                GridSamplePadding::Reflection,
+            ] {
+                for align_corners in [false, true] {
+                    let ys = xs.grid_sample(&grid, mode, padding, align_corners)?;
+                    let ys_ = xs.grid_sample_gather(&grid, mode, padding, align_corners)?;
+                    assert_close(&ys, &ys_)?;
+                    let grad = (ys * &ws)?.sum_all()?.backward()?;
+                    let grad_ = (ys_ * &ws)?.sum_all()?.backward()?;
+                    for v in [&xs, &grid] {
+                        match (grad.get(v), grad_.get(v)) {
+                            (Some(g), Some(g_)) => assert_close(g, g_)?,
+                            (Some(g), None) | (None, Some(g)) => {
+                                assert_eq!(g.abs()?.flatten_all()?.max(0)?.to_scalar::<f64>()?, 0.)
+                            }
+                            (None, None) => {}
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,13 @@
-use crate::{DType, Device, Error, Result, Tensor, WithDType};
+use crate::op::BackpropOp;
+use crate::{
+    CpuBuffer, CpuStorage, DType, Device, Error, Result, Shape, Storage, Tensor, WithDType,
+};
 use safetensors::tensor as st;
 use safetensors::tensor::SafeTensors;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 
 impl From<DType> for st::Dtype {
     fn from(value: DType) -> Self {
@@ -231,6 +235,40 @@ fn convert_back(tensor: &Tensor) -> Result<Vec<u8>> {
     }
 }
 
+// Creates a cpu tensor that reads its data directly from the memory mapped file rather than
+// copying it. Returns `None` if this is not possible, i.e. when the dtype has to be converted or
+// when the data is not aligned.
+fn load_mmaped(view: &st::TensorView<'_>, mmap: &Arc<memmap2::Mmap>) -> Option<Tensor> {
+    fn buffer<T: WithDType>(
+        view: &st::TensorView<'_>,
+        mmap: &Arc<memmap2::Mmap>,
+    ) -> Option<CpuBuffer<T>> {
+        let data = view.data();
+        let offset = (data.as_ptr() as usize).checked_sub(mmap.as_ptr() as usize)?;
+        let len = data.len() / T::DTYPE.size_in_bytes();
+        // SAFETY: all the bit patterns are valid for the supported dtypes, and the file cannot
+        // be modified per the safety contract of `MmapedSafetensors::new`.
+        unsafe { CpuBuffer::from_mmap(mmap.clone(), offset, len) }
+    }
+    let storage = match view.dtype() {
+        st::Dtype::U8 => CpuStorage::U8(buffer(view, mmap)?),
+        st::Dtype::U32 => CpuStorage::U32(buffer(view, mmap)?),
+        st::Dtype::I64 => CpuStorage::I64(buffer(view, mmap)?),
+        st::Dtype::BF16 => CpuStorage::BF16(buffer(view, mmap)?),
+        st::Dtype::F16 => CpuStorage::F16(buffer(view, mmap)?),
+        st::Dtype::F32 => CpuStorage::F32(buffer(view, mmap)?),
+        st::Dtype::F64 => CpuStorage::F64(buffer(view, mmap)?),
+        _ => return None,
+    };
+    let storage = Storage::Cpu(storage);
+    Some(crate::tensor::from_storage(
+        storage,
+        view.shape(),
+        BackpropOp::none(),
+        false,
+    ))
+}
+
 pub fn load<P: AsRef<Path>>(filename: P, device: &Device) -> Result<HashMap<String, Tensor>> {
     let data = std::fs::read(filename.as_ref())?;
     load_buffer(&data[..], device)
@@ -251,11 +289,401 @@ pub fn save<K: AsRef<str> + Ord + std::fmt::Display, P: AsRef<Path>>(
     Ok(st::serialize_to_file(tensors, &None, filename.as_ref())?)
 }
 
+fn dtype_name(dtype: DType) -> &'static str {
+    match dtype {
+        DType::U8 => "U8",
+        DType::U32 => "U32",
+        DType::I64 => "I64",
+        DType::BF16 => "BF16",
+        DType::F16 => "F16",
+        DType::F32 => "F32",
+        DType::F64 => "F64",
+    }
+}
+
+fn push_json_str(dst: &mut String, s: &str) {
+    dst.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => dst.push_str("\\\""),
+            '\\' => dst.push_str("\\\\"),
+            c if (c as u32) < 0x20 => dst.push_str(&format!("\\u{:04x}", c as u32)),
+            c => dst.push(c),
+        }
+    }
+    dst.push('"')
+}
+
+// Writes the raw bytes of a tensor, cpu tensors that are already contiguous are not copied.
+fn write_tensor_data<W: std::io::Write>(w: &mut W, tensor: &Tensor) -> Result<()> {
+    fn write_<W: std::io::Write, T: WithDType>(w: &mut W, vs: &[T]) -> Result<()> {
+        // SAFETY: the supported dtypes have no padding bytes, the data is written in the native
+        // (little) endianness as done by `convert_back_`.
+        let bytes = unsafe {
+            std::slice::from_raw_parts(vs.as_ptr() as *const u8, std::mem::size_of_val(vs))
+        };
+        w.write_all(bytes)?;
+        Ok(())
+    }
+    let tensor = tensor.to_device(&Device::Cpu)?.contiguous()?;
+    let (storage, layout) = tensor.storage_and_layout();
+    let (start, end) = match layout.contiguous_offsets() {
+        Some(offsets) => offsets,
+        None => crate::bail!("internal error, non-contiguous tensor after contiguous()"),
+    };
+    match &*storage {
+        Storage::Cpu(CpuStorage::U8(vs)) => write_(w, &vs[start..end]),
+        Storage::Cpu(CpuStorage::U32(vs)) => write_(w, &vs[start..end]),
+        Storage::Cpu(CpuStorage::I64(vs)) => write_(w, &vs[start..end]),
+        Storage::Cpu(CpuStorage::BF16(vs)) => write_(w, &vs[start..end]),
+        Storage::Cpu(CpuStorage::F16(vs)) => write_(w, &vs[start..end]),
+        Storage::Cpu(CpuStorage::F32(vs)) => write_(w, &vs[start..end]),
+        Storage::Cpu(CpuStorage::F64(vs)) => write_(w, &vs[start..end]),
+        _ => crate::bail!("internal error, non-cpu tensor after to_device(Cpu)"),
+    }
+}
+
+/// A writer that streams tensors to a safetensors file.
+///
+/// The names, dtypes, and shapes of all the tensors are provided upfront so that the header can
+/// be written first, the tensors are then written one by one in the same order. This avoids
+/// having to hold all the tensors in memory at the same time. The data of a tensor can also be
+/// written in multiple chunks along its first dimension using [`Writer::write_chunk`].
+///
+/// ```no_run
+/// use candle_core::{safetensors::Writer, DType, Device, Tensor};
+/// # fn main() -> candle_core::Result<()> {
+/// let file = std::io::BufWriter::new(std::fs::File::create("model.safetensors")?);
+/// let headers = [("a", DType::F32, (2, 3).into()), ("b", DType::U8, 4.into())];
+/// let mut writer = Writer::new(file, &headers)?;
+/// writer.write("a", &Tensor::zeros((2, 3), DType::F32, &Device::Cpu)?)?;
+/// writer.write("b", &Tensor::zeros(4, DType::U8, &Device::Cpu)?)?;
+/// writer.finish()?;
+/// # Ok(()) }
+/// ```
+pub struct Writer<W: std::io::Write> {
+    w: W,
+    tensors: Vec<(String, DType, Shape)>,
+    current: usize,
+    current_rows: usize,
+}
+
+impl<W: std::io::Write> Writer<W> {
+    /// Creates a writer and writes the header for the tensors described by `tensors`.
+    pub fn new<S: AsRef<str>>(w: W, tensors: &[(S, DType, Shape)]) -> Result<Self> {
+        Self::new_with_metadata(w, tensors, &HashMap::new())
+    }
+
+    /// Creates a writer with some additional string metadata stored in the header.
+    pub fn new_with_metadata<S: AsRef<str>>(
+        mut w: W,
+        tensors: &[(S, DType, Shape)],
+        metadata: &HashMap<String, String>,
+    ) -> Result<Self> {
+        let mut header = String::from("{");
+        if !metadata.is_empty() {
+            let mut metadata = metadata.iter().collect::<Vec<_>>();
+            metadata.sort();
+            header.push_str("\"__metadata__\":{");
+            for (i, (k, v)) in metadata.iter().enumerate() {
+                if i > 0 {
+                    header.push(',')
+                }
+                push_json_str(&mut header, k);
+                header.push(':');
+                push_json_str(&mut header, v);
+            }
+            header.push('}');
+        }
+        let mut names = std::collections::HashSet::new();
+        let mut offset = 0;
+        for (name, dtype, shape) in tensors.iter() {
+            let name = name.as_ref();
+            if name == "__metadata__" || !names.insert(name) {
+                crate::bail!("invalid or duplicate tensor name {name} in safetensors header")
+            }
+            let size = shape.elem_count() * dtype.size_in_bytes();
+            if header.len() > 1 {
+                header.push(',')
+            }
+            push_json_str(&mut header, name);
+            let dims = shape
+                .dims()
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>();
+            header.push_str(&format!(
+                ":{{\"dtype\":\"{}\",\"shape\":[{}],\"data_offsets\":[{offset},{}]}}",
+                dtype_name(*dtype),
+                dims.join(","),
+                offset + size
+            ));
+            offset += size;
+        }
+        header.push('}');
+        // Pad the header with spaces so that the data is 8 bytes aligned.
+        while header.len() % 8 != 0 {
+            header.push(' ')
+        }
+        w.write_all(&(header.len() as u64).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+        let tensors = tensors
+            .iter()
+            .map(|(name, dtype, shape)| (name.as_ref().to_string(), *dtype, shape.clone()))
+            .collect();
+        Ok(Self {
+            w,
+            tensors,
+            current: 0,
+            current_rows: 0,
+        })
+    }
+
+    /// Writes the tensor named `name`, this has to be the next tensor from the header and its
+    /// dtype and shape have to match the header.
+    pub fn write(&mut self, name: &str, tensor: &Tensor) -> Result<()> {
+        let (index, dtype, shape) = self.next(name)?;
+        if dtype != tensor.dtype() {
+            crate::bail!(
+                "dtype mismatch for {name}, expected {dtype:?} got {:?}",
+                tensor.dtype()
+            )
+        }
+        if &shape != tensor.shape() || self.current_rows != 0 {
+            crate::bail!(
+                "shape mismatch for {name}, expected {shape:?} got {:?}",
+                tensor.shape()
+            )
+        }
+        write_tensor_data(&mut self.w, tensor)?;
+        self.current = index + 1;
+        Ok(())
+    }
+
+    /// Writes a chunk of the tensor named `name`. The chunk must have the same dimensions as the
+    /// tensor in the header except for the first one, chunks are concatenated along this first
+    /// dimension and the writer moves to the next tensor once all the rows have been written.
+    /// Tensors with no elements are skipped when writing the tensors that follow them.
+    pub fn write_chunk(&mut self, name: &str, chunk: &Tensor) -> Result<()> {
+        let (index, dtype, shape) = self.next(name)?;
+        let rows = match shape.dims().first() {
+            Some(rows) => *rows,
+            None => return self.write(name, chunk),
+        };
+        if dtype != chunk.dtype() {
+            crate::bail!(
+                "dtype mismatch for {name}, expected {dtype:?} got {:?}",
+                chunk.dtype()
+            )
+        }
+        let dims = chunk.dims();
+        if dims.is_empty() || dims[1..] != shape.dims()[1..] || self.current_rows + dims[0] > rows {
+            crate::bail!(
+                "shape mismatch for {name}, cannot write a chunk of shape {:?} after {} rows of {shape:?}",
+                chunk.shape(),
+                self.current_rows
+            )
+        }
+        write_tensor_data(&mut self.w, chunk)?;
+        self.current = index;
+        self.current_rows += dims[0];
+        if self.current_rows == rows {
+            self.current += 1;
+            self.current_rows = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes the quantized tensor named `name`, the tensor gets dequantized and converted to
+    /// the dtype specified in the header.
+    pub fn write_qtensor(&mut self, name: &str, qtensor: &crate::quantized::QTensor) -> Result<()> {
+        let (_, dtype, _) = self.next(name)?;
+        let tensor = qtensor.dequantize(&Device::Cpu)?.to_dtype(dtype)?;
+        self.write(name, &tensor)
+    }
+
+    // Tensors with no elements have no data so they can be skipped without being written.
+    fn has_no_data(&self, index: usize) -> bool {
+        self.tensors
+            .get(index)
+            .is_some_and(|(_, _, shape)| shape.elem_count() == 0)
+    }
+
+    // Returns the index of the tensor named `name` if it is the next one to be written, skipping
+    // the tensors with no elements that come before it. `current` is left unchanged, the caller
+    // only moves it to the returned index once the tensor has passed its checks.
+    fn next(&self, name: &str) -> Result<(usize, DType, Shape)> {
+        let mut index = self.current;
+        if self.current_rows == 0 {
+            while self.has_no_data(index) && self.tensors[index].0 != name {
+                index += 1
+            }
+        }
+        match self.tensors.get(index) {
+            None => crate::bail!("cannot write {name}, all the tensors have already been written"),
+            Some(next) if next.0 != name => {
+                let expected = &self.tensors[self.current].0;
+                crate::bail!("expected tensor {expected} to be written next, got {name}")
+            }
+            Some((_, dtype, shape)) => Ok((index, *dtype, shape.clone())),
+        }
+    }
+
+    // Whether all the tensors with some data have been written.
+    fn is_done(&self) -> bool {
+        self.current_rows == 0
+            && (self.current..self.tensors.len()).all(|index| self.has_no_data(index))
+    }
+
+    /// Checks that all the tensors have been written and returns the underlying writer after
+    /// flushing it. Trailing tensors with no elements do not have to be written explicitly.
+    pub fn finish(mut self) -> Result<W> {
+        if !self.is_done() {
+            crate::bail!(
+                "tensor {} has not been written",
+                self.tensors[self.current].0
+            )
+        }
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+/// A writer that streams tensors to multiple safetensors shards.
+///
+/// The tensors are split in the order of the header into files named
+/// `model-0000x-of-0000N.safetensors` in the target directory so that each shard is at most
+/// `max_shard_size` bytes, except for tensors that are larger than this on their own. Once all
+/// the tensors have been written, [`ShardedWriter::finish`] creates the
+/// `model.safetensors.index.json` file mapping each tensor to its shard.
+pub struct ShardedWriter {
+    dir: std::path::PathBuf,
+    shards: Vec<Vec<(String, DType, Shape)>>,
+    writer: Option<Writer<std::io::BufWriter<std::fs::File>>>,
+    next_shard: usize,
+}
+
+impl ShardedWriter {
+    pub fn new<P: AsRef<Path>, S: AsRef<str>>(
+        dir: P,
+        tensors: &[(S, DType, Shape)],
+        max_shard_size: usize,
+    ) -> Result<Self> {
+        let mut shards: Vec<Vec<(String, DType, Shape)>> = vec![];
+        let mut shard_size = 0;
+        for (name, dtype, shape) in tensors.iter() {
+            let size = shape.elem_count() * dtype.size_in_bytes();
+            match shards.last_mut() {
+                Some(shard) if shard_size + size <= max_shard_size => {
+                    shard_size += size;
+                    shard.push((name.as_ref().to_string(), *dtype, shape.clone()))
+                }
+                _ => {
+                    shard_size = size;
+                    shards.push(vec![(name.as_ref().to_string(), *dtype, shape.clone())])
+                }
+            }
+        }
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).map_err(|e| Error::from(e).with_path(dir))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            shards,
+            writer: None,
+            next_shard: 0,
+        })
+    }
+
+    /// The file name of the `index`-th shard.
+    pub fn shard_name(&self, index: usize) -> String {
+        format!(
+            "model-{:05}-of-{:05}.safetensors",
+            index + 1,
+            self.shards.len()
+        )
+    }
+
+    // Returns the writer for the current shard, moving to the next shard if all the tensors
+    // from the current one have been written.
+    fn writer(&mut self, name: &str) -> Result<&mut Writer<std::io::BufWriter<std::fs::File>>> {
+        let done = match &self.writer {
+            None => true,
+            Some(w) => w.is_done() && w.tensors[w.current..].iter().all(|t| t.0 != name),
+        };
+        if done {
+            if let Some(w) = self.writer.take() {
+                w.finish()?;
+            }
+            let shard = match self.shards.get(self.next_shard) {
+                None => {
+                    crate::bail!("cannot write {name}, all the tensors have already been written")
+                }
+                Some(shard) => shard,
+            };
+            let path = self.dir.join(self.shard_name(self.next_shard));
+            let file = std::fs::File::create(&path).map_err(|e| Error::from(e).with_path(&path))?;
+            self.writer = Some(Writer::new(std::io::BufWriter::new(file), shard)?);
+            self.next_shard += 1;
+        }
+        match self.writer.as_mut() {
+            Some(w) => Ok(w),
+            None => crate::bail!("internal error, no current shard"),
+        }
+    }
+
+    /// Writes the tensor named `name`, see [`Writer::write`].
+    pub fn write(&mut self, name: &str, tensor: &Tensor) -> Result<()> {
+        self.writer(name)?.write(name, tensor)
+    }
+
+    /// Writes a chunk of the tensor named `name`, see [`Writer::write_chunk`].
+    pub fn write_chunk(&mut self, name: &str, chunk: &Tensor) -> Result<()> {
+        self.writer(name)?.write_chunk(name, chunk)
+    }
+
+    /// Writes the quantized tensor named `name`, see [`Writer::write_qtensor`].
+    pub fn write_qtensor(&mut self, name: &str, qtensor: &crate::quantized::QTensor) -> Result<()> {
+        self.writer(name)?.write_qtensor(name, qtensor)
+    }
+
+    /// Checks that all the tensors have been written and creates the index file.
+    pub fn finish(mut self) -> Result<()> {
+        if let Some(w) = self.writer.take() {
+            w.finish()?;
+        }
+        if let Some(shard) = self.shards.get(self.next_shard) {
+            crate::bail!("tensor {} has not been written", shard[0].0)
+        }
+        let mut weight_map = vec![];
+        let mut total_size = 0;
+        for (index, shard) in self.shards.iter().enumerate() {
+            for (name, dtype, shape) in shard.iter() {
+                total_size += shape.elem_count() * dtype.size_in_bytes();
+                weight_map.push((name.as_str(), self.shard_name(index)))
+            }
+        }
+        weight_map.sort();
+        let mut index = format!("{{\"metadata\":{{\"total_size\":{total_size}}},\"weight_map\":{{");
+        for (i, (name, shard_name)) in weight_map.iter().enumerate() {
+            if i > 0 {
+                index.push(',')
+            }
+            push_json_str(&mut index, name);
+            index.push(':');
+            push_json_str(&mut index, shard_name);
+        }
+        index.push_str("}}");
+        let path = self.dir.join("model.safetensors.index.json");
+        std::fs::write(&path, index).map_err(|e| Error::from(e).with_path(&path))?;
+        Ok(())
+    }
+}
+
 #[derive(yoke::Yokeable)]
 struct SafeTensors_<'a>(SafeTensors<'a>);
 
 pub struct MmapedSafetensors {
-    safetensors: Vec<yoke::Yoke<SafeTensors_<'static>, memmap2::Mmap>>,
+    safetensors: Vec<yoke::Yoke<SafeTensors_<'static>, Arc<memmap2::Mmap>>>,
     routing: Option<HashMap<String, usize>>,
 }
 
@@ -271,14 +699,15 @@ impl MmapedSafetensors {
         let file = memmap2::MmapOptions::new()
             .map(&file)
             .map_err(|e| Error::from(e).with_path(p))?;
-        let safetensors = yoke::Yoke::<SafeTensors_<'static>, memmap2::Mmap>::try_attach_to_cart(
-            file,
-            |data: &[u8]| {
-                let st = safetensors::SafeTensors::deserialize(data)
-                    .map_err(|e| Error::from(e).with_path(p))?;
-                Ok::<_, Error>(SafeTensors_(st))
-            },
-        )?;
+        let safetensors =
+            yoke::Yoke::<SafeTensors_<'static>, Arc<memmap2::Mmap>>::try_attach_to_cart(
+                Arc::new(file),
+                |data: &memmap2::Mmap| {
+                    let st = safetensors::SafeTensors::deserialize(data)
+                        .map_err(|e| Error::from(e).with_path(p))?;
+                    Ok::<_, Error>(SafeTensors_(st))
+                },
+            )?;
         Ok(Self {
             safetensors: vec![safetensors],
             routing: None,
@@ -301,9 +730,9 @@ impl MmapedSafetensors {
             let file = memmap2::MmapOptions::new()
                 .map(&file)
                 .map_err(|e| Error::from(e).with_path(p))?;
-            let data = yoke::Yoke::<SafeTensors_<'static>, memmap2::Mmap>::try_attach_to_cart(
-                file,
-                |data: &[u8]| {
+            let data = yoke::Yoke::<SafeTensors_<'static>, Arc<memmap2::Mmap>>::try_attach_to_cart(
+                Arc::new(file),
+                |data: &memmap2::Mmap| {
                     let st = safetensors::SafeTensors::deserialize(data)
                         .map_err(|e| Error::from(e).with_path(p))?;
                     Ok::<_, Error>(SafeTensors_(st))
@@ -320,8 +749,18 @@ impl MmapedSafetensors {
         })
     }
 
+    /// Loads the tensor named `name` on device `dev`. On the cpu, the returned tensor reads its
+    /// data directly from the memory mapped file when the dtype does not require a conversion
+    /// and the data is properly aligned, the data is only copied if the tensor gets modified.
     pub fn load(&self, name: &str, dev: &Device) -> Result<Tensor> {
-        self.get(name)?.load(dev)
+        let safetensors = &self.safetensors[self.index(name)?];
+        let view = safetensors.get().0.tensor(name)?;
+        if dev.is_cpu() {
+            if let Some(tensor) = load_mmaped(&view, safetensors.backing_cart()) {
+                return Ok(tensor);
+            }
+        }
+        view.load(dev)
     }
 
     pub fn tensors(&self) -> Vec<(String, st::TensorView<'_>)> {
@@ -332,7 +771,7 @@ impl MmapedSafetensors {
         tensors.into_iter().flatten().collect()
     }
 
-    pub fn get(&self, name: &str) -> Result<st::TensorView<'_>> {
+    fn index(&self, name: &str) -> Result<usize> {
         let index = match &self.routing {
             None => 0,
             Some(routing) => {
@@ -345,7 +784,11 @@ impl MmapedSafetensors {
                 *index
             }
         };
-        Ok(self.safetensors[index].get().0.tensor(name)?)
+        Ok(index)
+    }
+
+    pub fn get(&self, name: &str) -> Result<st::TensorView<'_>> {
+        Ok(self.safetensors[self.index(name)?].get().0.tensor(name)?)
     }
 }
 
@@ -438,4 +881,179 @@ mod tests {
         assert_eq!(bytes, b"x\0\0\0\0\0\0\0{\"t\":{\"dtype\":\"F32\",\"shape\":[2,2],\"data_offsets\":[0,16]},\"u\":{\"dtype\":\"F32\",\"shape\":[1,2],\"data_offsets\":[16,24]}}      \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
         std::fs::remove_file("multi.safetensors").unwrap();
     }
+
+    #[test]
+    fn streaming_writer() {
+        let t = Tensor::zeros((2, 2), DType::F32, &Device::Cpu).unwrap();
+        let mut writer = Writer::new(vec![], &[("t", DType::F32, t.shape().clone())]).unwrap();
+        writer.write("t", &t).unwrap();
+        let bytes = writer.finish().unwrap();
+        assert_eq!(bytes, b"@\0\0\0\0\0\0\0{\"t\":{\"dtype\":\"F32\",\"shape\":[2,2],\"data_offsets\":[0,16]}}       \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+
+        let a = Tensor::arange(0f32, 12f32, &Device::Cpu)
+            .unwrap()
+            .reshape((4, 3))
+            .unwrap();
+        let b = Tensor::new(&[1u8, 2, 3], &Device::Cpu).unwrap();
+        let headers = [
+            ("a", DType::F32, Shape::from((4, 3))),
+            ("b", DType::U8, Shape::from(3)),
+        ];
+        let mut writer = Writer::new(vec![], &headers).unwrap();
+        assert!(writer.write("b", &b).is_err());
+        assert!(writer.write("a", &a.t().unwrap()).is_err());
+        writer
+            .write_chunk("a", &a.narrow(0, 0, 1).unwrap())
+            .unwrap();
+        assert!(writer.finish().is_err());
+
+        let mut writer = Writer::new(vec![], &headers).unwrap();
+        writer
+            .write_chunk("a", &a.narrow(0, 0, 1).unwrap())
+            .unwrap();
+        assert!(writer.write_chunk("a", &a).is_err());
+        writer
+            .write_chunk("a", &a.narrow(0, 1, 3).unwrap())
+            .unwrap();
+        assert!(writer.write("b", &b.to_dtype(DType::U32).unwrap()).is_err());
+        writer.write("b", &b).unwrap();
+        assert!(writer.write("b", &b).is_err());
+        let bytes = writer.finish().unwrap();
+        let weights = load_buffer(&bytes, &Device::Cpu).unwrap();
+        assert_eq!(
+            weights["a"].to_vec2::<f32>().unwrap(),
+            a.to_vec2::<f32>().unwrap()
+        );
+        assert_eq!(weights["b"].to_vec1::<u8>().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn streaming_writer_no_rows() {
+        let a = Tensor::arange(0f32, 6f32, &Device::Cpu)
+            .unwrap()
+            .reshape((2, 3))
+            .unwrap();
+        let empty = Tensor::zeros((0, 3), DType::F32, &Device::Cpu).unwrap();
+        let headers = [
+            ("e0", DType::F32, Shape::from((0, 3))),
+            ("a", DType::F32, Shape::from((2, 3))),
+            ("e1", DType::F32, Shape::from((0, 3))),
+            ("e2", DType::U8, Shape::from(0)),
+            ("e3", DType::F32, Shape::from((3, 0))),
+        ];
+
+        // The tensors with no elements do not have to be written.
+        let mut writer = Writer::new(vec![], &headers).unwrap();
+        writer
+            .write_chunk("a", &a.narrow(0, 0, 1).unwrap())
+            .unwrap();
+        assert!(writer.write_chunk("e1", &empty).is_err());
+        writer
+            .write_chunk("a", &a.narrow(0, 1, 1).unwrap())
+            .unwrap();
+        let bytes = writer.finish().unwrap();
+        let weights = load_buffer(&bytes, &Device::Cpu).unwrap();
+        assert_eq!(weights["e0"].dims(), [0, 3]);
+        assert_eq!(weights["e2"].dims(), [0]);
+        assert_eq!(weights["e3"].dims(), [3, 0]);
+        assert_eq!(
+            weights["a"].to_vec2::<f32>().unwrap(),
+            a.to_vec2::<f32>().unwrap()
+        );
+
+        // Writing them explicitly also works, a failed write does not skip them.
+        let mut writer = Writer::new(vec![], &headers).unwrap();
+        assert!(writer.write("a", &a.t().unwrap()).is_err());
+        writer.write_chunk("e0", &empty).unwrap();
+        writer.write("a", &a).unwrap();
+        writer.write("e1", &empty).unwrap();
+        let e3 = Tensor::zeros((3, 0), DType::F32, &Device::Cpu).unwrap();
+        assert!(writer.write("e3", &e3.t().unwrap()).is_err());
+        writer.write("e3", &e3).unwrap();
+        let bytes2 = writer.finish().unwrap();
+        assert_eq!(bytes, bytes2);
+
+        let mut writer = Writer::new(vec![], &headers[..2]).unwrap();
+        assert!(writer.finish().is_err());
+    }
+
+    #[test]
+    fn sharded_writer() {
+        let dir = std::env::temp_dir().join("candle-sharded-writer");
+        let tensors = (0..5)
+            .map(|i| {
+                let t = Tensor::full(i as f32, (2, 4), &Device::Cpu).unwrap();
+                (format!("t{i}"), t)
+            })
+            .collect::<Vec<_>>();
+        let headers = tensors
+            .iter()
+            .map(|(name, t)| (name.as_str(), t.dtype(), t.shape().clone()))
+            .collect::<Vec<_>>();
+        // Each tensor takes 32 bytes so the shards contain two tensors at most.
+        let mut writer = ShardedWriter::new(&dir, &headers, 70).unwrap();
+        assert_eq!(writer.shard_name(1), "model-00002-of-00003.safetensors");
+        for (name, t) in tensors.iter() {
+            writer.write(name, t).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let index = std::fs::read_to_string(dir.join("model.safetensors.index.json")).unwrap();
+        assert_eq!(
+            index,
+            "{\"metadata\":{\"total_size\":160},\"weight_map\":{\
+             \"t0\":\"model-00001-of-00003.safetensors\",\
+             \"t1\":\"model-00001-of-00003.safetensors\",\
+             \"t2\":\"model-00002-of-00003.safetensors\",\
+             \"t3\":\"model-00002-of-00003.safetensors\",\
+             \"t4\":\"model-00003-of-00003.safetensors\"}}"
+        );
+        let shards = (1..=3)
+            .map(|i| dir.join(format!("model-{i:05}-of-00003.safetensors")))
+            .collect::<Vec<_>>();
+        let st = unsafe { MmapedSafetensors::multi(&shards).unwrap() };
+        for (name, t) in tensors.iter() {
+            let u = st.load(name, &Device::Cpu).unwrap();
+            assert_eq!(u.to_vec2::<f32>().unwrap(), t.to_vec2::<f32>().unwrap());
+        }
+        drop(st);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_mmaped_zero_copy() {
+        let t = Tensor::arange(0f32, 6f32, &Device::Cpu)
+            .unwrap()
+            .reshape((2, 3))
+            .unwrap();
+        t.save_safetensors("t", "mmaped.safetensors").unwrap();
+        let bytes = std::fs::read("mmaped.safetensors").unwrap();
+
+        let st = unsafe { MmapedSafetensors::new("mmaped.safetensors").unwrap() };
+        let u = st.load("t", &Device::Cpu).unwrap();
+        {
+            let (storage, _layout) = u.storage_and_layout();
+            match &*storage {
+                Storage::Cpu(CpuStorage::F32(vs)) => assert!(vs.is_mapped()),
+                _ => panic!("unexpected storage"),
+            }
+        }
+        assert_eq!(u.to_vec2::<f32>().unwrap(), t.to_vec2::<f32>().unwrap());
+
+        // Mutating the tensor copies the data rather than writing to the file.
+        {
+            let (mut storage, _layout) = u.storage_mut_and_layout();
+            match &mut *storage {
+                Storage::Cpu(CpuStorage::F32(vs)) => {
+                    vs[0] = 42.;
+                    assert!(!vs.is_mapped())
+                }
+                _ => panic!("unexpected storage"),
+            }
+        }
+        assert_eq!(u.to_vec2::<f32>().unwrap(), [[42., 1., 2.], [3., 4., 5.]]);
+        assert_eq!(std::fs::read("mmaped.safetensors").unwrap(), bytes);
+        drop(st);
+        std::fs::remove_file("mmaped.safetensors").unwrap();
+    }
 }
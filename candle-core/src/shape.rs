@@ -165,13 +165,13 @@ impl Shape {
     }
 
     /// Returns true if the strides are C contiguous (aka row major).
-    pub fn is_contiguous(&self, stride: &[usize]) -> bool {
+    pub fn is_contiguous(&self, stride: &[isize]) -> bool {
         if self.0.len() != stride.len() {
             return false;
         }
         let mut acc = 1;
         for (&stride, &dim) in stride.iter().zip(self.0.iter()).rev() {
-            if stride != acc {
+            if stride != acc as isize {
                 return false;
             }
             acc *= dim;
@@ -180,13 +180,13 @@ impl Shape {
     }
 
     /// Returns true if the strides are Fortran contiguous (aka column major).
-    pub fn is_fortran_contiguous(&self, stride: &[usize]) -> bool {
+    pub fn is_fortran_contiguous(&self, stride: &[isize]) -> bool {
         if self.0.len() != stride.len() {
             return false;
         }
         let mut acc = 1;
         for (&stride, &dim) in stride.iter().zip(self.0.iter()) {
-            if stride != acc {
+            if stride != acc as isize {
                 return false;
             }
             acc *= dim;
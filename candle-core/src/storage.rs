@@ -483,6 +483,76 @@ impl Storage {
         }
     }
 
+    pub(crate) fn conv3d(
+        &self,
+        l: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &crate::conv::ParamsConv3D,
+    ) -> Result<Self> {
+        self.same_device(kernel, "conv3d")?;
+        self.same_dtype(kernel, "conv3d")?;
+        match (self, &kernel) {
+            (Storage::Cpu(inp), Storage::Cpu(kernel)) => {
+                let s = inp.conv3d(l, kernel, kernel_l, params)?;
+                Ok(Self::Cpu(s))
+            }
+            (Storage::Cuda(inp), Storage::Cuda(kernel)) => {
+                let s = inp.conv3d(l, kernel, kernel_l, params)?;
+                Ok(Self::Cuda(s))
+            }
+            (Storage::Metal(inp), Storage::Metal(kernel)) => {
+                let s = inp.conv3d(l, kernel, kernel_l, params)?;
+                Ok(Self::Metal(s))
+            }
+            (Storage::Wgpu(inp), Storage::Wgpu(kernel)) => {
+                let s = inp.conv3d(l, kernel, kernel_l, params)?;
+                Ok(Self::Wgpu(s))
+            }
+            (lhs, rhs) => Err(Error::DeviceMismatchBinaryOp {
+                lhs: lhs.device().location(),
+                rhs: rhs.device().location(),
+                op: "conv3d",
+            }
+            .bt()),
+        }
+    }
+
+    pub(crate) fn conv_transpose3d(
+        &self,
+        l: &Layout,
+        kernel: &Self,
+        kernel_l: &Layout,
+        params: &crate::conv::ParamsConvTranspose3D,
+    ) -> Result<Self> {
+        self.same_device(kernel, "conv_transpose3d")?;
+        self.same_dtype(kernel, "conv_transpose3d")?;
+        match (self, &kernel) {
+            (Storage::Cpu(inp), Storage::Cpu(kernel)) => {
+                let s = inp.conv_transpose3d(l, kernel, kernel_l, params)?;
+                Ok(Self::Cpu(s))
+            }
+            (Storage::Cuda(inp), Storage::Cuda(kernel)) => {
+                let s = inp.conv_transpose3d(l, kernel, kernel_l, params)?;
+                Ok(Self::Cuda(s))
+            }
+            (Storage::Metal(inp), Storage::Metal(kernel)) => {
+                let s = inp.conv_transpose3d(l, kernel, kernel_l, params)?;
+                Ok(Self::Metal(s))
+            }
+            (Storage::Wgpu(inp), Storage::Wgpu(kernel)) => {
+                let s = inp.conv_transpose3d(l, kernel, kernel_l, params)?;
+                Ok(Self::Wgpu(s))
+            }
+            (lhs, rhs) => Err(Error::DeviceMismatchBinaryOp {
+                lhs: lhs.device().location(),
+                rhs: rhs.device().location(),
+                op: "conv_transpose3d",
+            }
+            .bt()),
+        }
+    }
+
     pub(crate) fn avg_pool2d(
         &self,
         layout: &Layout,
@@ -535,6 +605,58 @@ impl Storage {
         }
     }
 
+    pub(crate) fn avg_pool3d(
+        &self,
+        layout: &Layout,
+        kernel_size: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    ) -> Result<Self> {
+        match self {
+            Storage::Cpu(storage) => {
+                let storage = storage.avg_pool3d(layout, kernel_size, stride)?;
+                Ok(Self::Cpu(storage))
+            }
+            Storage::Cuda(storage) => {
+                let storage = storage.avg_pool3d(layout, kernel_size, stride)?;
+                Ok(Self::Cuda(storage))
+            }
+            Storage::Metal(storage) => {
+                let storage = storage.avg_pool3d(layout, kernel_size, stride)?;
+                Ok(Self::Metal(storage))
+            }
+            Storage::Wgpu(storage) => {
+                let storage = storage.avg_pool3d(layout, kernel_size, stride)?;
+                Ok(Self::Wgpu(storage))
+            }
+        }
+    }
+
+    pub(crate) fn max_pool3d(
+        &self,
+        layout: &Layout,
+        kernel_size: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    ) -> Result<Self> {
+        match self {
+            Storage::Cpu(storage) => {
+                let storage = storage.max_pool3d(layout, kernel_size, stride)?;
+                Ok(Self::Cpu(storage))
+            }
+            Storage::Cuda(storage) => {
+                let storage = storage.max_pool3d(layout, kernel_size, stride)?;
+                Ok(Self::Cuda(storage))
+            }
+            Storage::Metal(storage) => {
+                let storage = storage.max_pool3d(layout, kernel_size, stride)?;
+                Ok(Self::Metal(storage))
+            }
+            Storage::Wgpu(storage) => {
+                let storage = storage.max_pool3d(layout, kernel_size, stride)?;
+                Ok(Self::Wgpu(storage))
+            }
+        }
+    }
+
     pub(crate) fn upsample_nearest1d(&self, layout: &Layout, sz: usize) -> Result<Self> {
         match self {
             Storage::Cpu(storage) => {
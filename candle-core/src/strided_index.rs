@@ -7,11 +7,11 @@ pub struct StridedIndex<'a> {
     next_storage_index: Option<usize>,
     multi_index: Vec<usize>,
     dims: &'a [usize],
-    stride: &'a [usize],
+    stride: &'a [isize],
 }
 
 impl<'a> StridedIndex<'a> {
-    pub(crate) fn new(dims: &'a [usize], stride: &'a [usize], start_offset: usize) -> Self {
+    pub(crate) fn new(dims: &'a [usize], stride: &'a [isize], start_offset: usize) -> Self {
         let elem_count: usize = dims.iter().product();
         let next_storage_index = if elem_count == 0 {
             None
@@ -30,6 +30,42 @@ impl<'a> StridedIndex<'a> {
     pub(crate) fn from_layout(l: &'a Layout) -> Self {
         Self::new(l.dims(), l.stride(), l.start_offset())
     }
+
+    /// Returns an iterator over the offsets that come after the first `n` ones, this has the
+    /// same result as `skip(n)` without having to step through the skipped offsets.
+    pub(crate) fn skip_ahead(&self, n: usize) -> Self {
+        let mut multi_index = self.multi_index.clone();
+        let next_storage_index = self.next_storage_index.and_then(|mut storage_index| {
+            let mut carry = n;
+            for ((multi_i, max_i), stride_i) in multi_index
+                .iter_mut()
+                .zip(self.dims.iter())
+                .zip(self.stride.iter())
+                .rev()
+            {
+                if carry == 0 {
+                    break;
+                }
+                let next_i = *multi_i + carry;
+                carry = next_i / max_i;
+                let next_i = next_i % max_i;
+                let delta = (next_i as isize - *multi_i as isize) * stride_i;
+                storage_index = storage_index.wrapping_add_signed(delta);
+                *multi_i = next_i
+            }
+            if carry == 0 {
+                Some(storage_index)
+            } else {
+                None
+            }
+        });
+        StridedIndex {
+            next_storage_index,
+            multi_index,
+            dims: self.dims,
+            stride: self.stride,
+        }
+    }
 }
 
 impl<'a> Iterator for StridedIndex<'a> {
@@ -53,10 +89,11 @@ impl<'a> Iterator for StridedIndex<'a> {
             if next_i < *max_i {
                 *multi_i = next_i;
                 updated = true;
-                next_storage_index += stride_i;
+                next_storage_index = next_storage_index.wrapping_add_signed(*stride_i);
                 break;
             } else {
-                next_storage_index -= *multi_i * stride_i;
+                next_storage_index =
+                    next_storage_index.wrapping_add_signed(-(*multi_i as isize) * stride_i);
                 *multi_i = 0
             }
         }
@@ -524,6 +524,32 @@ impl Tensor {
         (self * mult)?.round()? * (1f64 / mult)
     }
 
+    // Returns the value of `rounded` in the forward pass, and the identity gradient in the
+    // backward pass.
+    fn straight_through(&self, rounded: Self) -> Result<Self> {
+        if self.track_op() {
+            (rounded - self)?.detach() + self
+        } else {
+            Ok(rounded)
+        }
+    }
+
+    /// Rounds to the nearest integer using a straight-through estimator for the gradient, i.e.
+    /// the gradient is propagated as if this was the identity function.
+    pub fn round_ste(&self) -> Result<Self> {
+        self.straight_through(self.round()?)
+    }
+
+    /// Same as `floor` but using a straight-through estimator for the gradient.
+    pub fn floor_ste(&self) -> Result<Self> {
+        self.straight_through(self.floor()?)
+    }
+
+    /// Same as `ceil` but using a straight-through estimator for the gradient.
+    pub fn ceil_ste(&self) -> Result<Self> {
+        self.straight_through(self.ceil()?)
+    }
+
     /// Retrieves the single scalar value hold in the tensor. If the tensor contains multiple
     /// dimensions, an error is returned instead.
     pub fn to_scalar<S: crate::WithDType>(&self) -> Result<S> {
@@ -565,7 +591,7 @@ impl Tensor {
     ///     t2.reshape(Shape::from_dims(&[6 * 4])).unwrap().to_vec1::<f32>().unwrap(),
     ///     [[1f32, 2.0, 3.0]; 2 * 4].concat()
     /// );
-    /// assert_eq!(t2.stride(), &[1usize, 4]);
+    /// assert_eq!(t2.stride(), &[1isize, 4]);
     /// ```
     ///
     pub fn repeat_old<S: Into<Shape>>(&self, shape: S) -> Result<Self> {
@@ -599,7 +625,7 @@ impl Tensor {
     ///     t2.reshape(Shape::from_dims(&[6 * 4])).unwrap().to_vec1::<f32>().unwrap(),
     ///     [[1f32, 2.0, 3.0]; 2 * 4].concat()
     /// );
-    /// assert_eq!(t2.stride(), &[1usize, 4]);
+    /// assert_eq!(t2.stride(), &[1isize, 4]);
     /// ```
     ///
     pub fn repeat<S: Into<Shape>>(&self, shape: S) -> Result<Tensor> {
@@ -808,6 +834,90 @@ impl Tensor {
         }
     }
 
+    /// Returns a new tensor that is a strided view of the input tensor along dimension `dim`.
+    /// The view has `len` elements, the i-th one being at index `start + i * step` of the
+    /// input. The step can be negative in which case the elements are returned in reverse
+    /// order. Similar to `narrow`, this does not copy the data except when the step is negative
+    /// on wgpu, this backend does not support negative strides so the elements get gathered in a
+    /// new tensor.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::arange(0u32, 6, &Device::Cpu)?;
+    /// let b = a.narrow_with_step(0, 1, 3, 2)?;
+    /// assert_eq!(b.to_vec1::<u32>()?, &[1, 3, 5]);
+    /// let b = a.narrow_with_step(0, 4, 3, -2)?;
+    /// assert_eq!(b.to_vec1::<u32>()?, &[4, 2, 0]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn narrow_with_step<D: Dim>(
+        &self,
+        dim: D,
+        start: usize,
+        len: usize,
+        step: isize,
+    ) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "narrow_with_step")?;
+        if step == 1 {
+            return self.narrow(dim, start, len);
+        }
+        let layout = self.layout().narrow_with_step(dim, start, len, step)?;
+        if step < 0 && self.device.is_wgpu() {
+            let ids = (0..len).map(|i| start.wrapping_add_signed(i as isize * step));
+            return self.gather_along(dim, ids);
+        }
+        let op = BackpropOp::new1(self, |arg| Op::NarrowWithStep {
+            arg,
+            dim,
+            start,
+            len,
+            step,
+        });
+        let tensor_ = Tensor_ {
+            id: TensorId::new(),
+            storage: self.storage.clone(),
+            layout,
+            op,
+            is_variable: false,
+            dtype: self.dtype,
+            device: self.device.clone(),
+        };
+        Ok(Tensor(Arc::new(tensor_)))
+    }
+
+    /// Returns a view of the input tensor with all the slices of `size` elements along
+    /// dimension `dim`, consecutive slices starting `step` elements apart. Dimension `dim` of
+    /// the result indexes the slices and an additional last dimension of size `size` indexes the
+    /// elements within each slice. This does not copy the data, the slices can overlap.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::arange(0u32, 7, &Device::Cpu)?;
+    /// let b = a.unfold(0, 3, 2)?;
+    /// assert_eq!(b.to_vec2::<u32>()?, &[[0, 1, 2], [2, 3, 4], [4, 5, 6]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn unfold<D: Dim>(&self, dim: D, size: usize, step: usize) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "unfold")?;
+        let layout = self.layout().unfold(dim, size, step)?;
+        let op = BackpropOp::new1(self, |arg| Op::Unfold {
+            arg,
+            dim,
+            size,
+            step,
+        });
+        let tensor_ = Tensor_ {
+            id: TensorId::new(),
+            storage: self.storage.clone(),
+            layout,
+            op,
+            is_variable: false,
+            dtype: self.dtype,
+            device: self.device.clone(),
+        };
+        Ok(Tensor(Arc::new(tensor_)))
+    }
+
     fn squeeze_dims(self, dims: &[usize]) -> Result<Self> {
         match dims {
             [] => Ok(self),
@@ -1188,6 +1298,88 @@ impl Tensor {
         Ok(from_storage(storage, (n, c, h_out, w_out), op, false))
     }
 
+    /// 3D average pooling over an input tensor with multiple channels.
+    ///
+    /// The input tensor should have five dimensions, `(batch, channels, d, h, w)`, the returned
+    /// tensor also has five dimensions, `(batch, channels, d', h', w')`. The pooling is performed
+    /// on the three last dimensions using a kernel of size `sz`. The returned element is the
+    /// average value over the kernel window.
+    pub fn avg_pool3d<T: crate::ToUsize3>(&self, sz: T) -> Result<Self> {
+        let sz = sz.to_usize3();
+        self.avg_pool3d_with_stride(sz, sz)
+    }
+
+    /// Same as `avg_pool3d` but with a `stride` that can be set to a value different from the
+    /// kernel size.
+    pub fn avg_pool3d_with_stride<T: crate::ToUsize3>(
+        &self,
+        kernel_size: T,
+        stride: T,
+    ) -> Result<Self> {
+        let kernel_size = kernel_size.to_usize3();
+        let stride = stride.to_usize3();
+        let out_dims = self.pool3d_out_dims(kernel_size, stride)?;
+        let op = BackpropOp::new1(self, |arg| Op::AvgPool3D {
+            arg,
+            kernel_size,
+            stride,
+        });
+        let storage = self
+            .storage()
+            .avg_pool3d(self.layout(), kernel_size, stride)?;
+        Ok(from_storage(storage, out_dims, op, false))
+    }
+
+    /// 3D max pooling over an input tensor with multiple channels.
+    ///
+    /// The input tensor should have five dimensions, `(batch, channels, d, h, w)`, the returned
+    /// tensor also has five dimensions, `(batch, channels, d', h', w')`. The pooling is performed
+    /// on the three last dimensions using a kernel of size `sz`, the returned element is the
+    /// maximum value over the kernel window.
+    pub fn max_pool3d<T: crate::ToUsize3>(&self, sz: T) -> Result<Self> {
+        let sz = sz.to_usize3();
+        self.max_pool3d_with_stride(sz, sz)
+    }
+
+    /// Same as `max_pool3d` but with a `stride` that can be set to a value different from the
+    /// kernel size.
+    pub fn max_pool3d_with_stride<T: crate::ToUsize3>(
+        &self,
+        kernel_size: T,
+        stride: T,
+    ) -> Result<Self> {
+        let kernel_size = kernel_size.to_usize3();
+        let stride = stride.to_usize3();
+        let out_dims = self.pool3d_out_dims(kernel_size, stride)?;
+        let op = BackpropOp::new1(self, |arg| Op::MaxPool3D {
+            arg,
+            kernel_size,
+            stride,
+        });
+        let storage = self
+            .storage()
+            .max_pool3d(self.layout(), kernel_size, stride)?;
+        Ok(from_storage(storage, out_dims, op, false))
+    }
+
+    fn pool3d_out_dims(
+        &self,
+        kernel_size: (usize, usize, usize),
+        stride: (usize, usize, usize),
+    ) -> Result<(usize, usize, usize, usize, usize)> {
+        let (n, c, d, h, w) = self.dims5()?;
+        if d < kernel_size.0 || h < kernel_size.1 || w < kernel_size.2 {
+            bail!("kernel-size {kernel_size:?} is larger than the input size {d},{h},{w}")
+        }
+        if stride.0 == 0 || stride.1 == 0 || stride.2 == 0 {
+            bail!("pool3d expects a non-zero stride, got {stride:?}")
+        }
+        let d_out = (d - kernel_size.0) / stride.0 + 1;
+        let h_out = (h - kernel_size.1) / stride.1 + 1;
+        let w_out = (w - kernel_size.2) / stride.2 + 1;
+        Ok((n, c, d_out, h_out, w_out))
+    }
+
     /// Returns the matrix-multiplication of the input tensor with the other provided tensor.
     ///
     /// # Arguments
@@ -1692,7 +1884,7 @@ impl Tensor {
         &self.layout
     }
 
-    pub fn stride(&self) -> &[usize] {
+    pub fn stride(&self) -> &[isize] {
         self.layout.stride()
     }
 
@@ -1918,6 +2110,53 @@ impl Tensor {
         Ok(Tensor(Arc::new(tensor_)))
     }
 
+    /// Reverses the order of the elements along the given dimensions. This returns a view on the
+    /// input data using negative strides so no copy is performed, except on wgpu where the
+    /// elements get gathered in a new tensor as this backend does not support negative strides.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let tensor = Tensor::new(&[[0f32, 1., 2.], [3., 4., 5.]], &Device::Cpu)?;
+    /// let flipped = tensor.flip(1)?;
+    /// assert_eq!(flipped.to_vec2::<f32>()?, &[[2., 1., 0.], [5., 4., 3.]]);
+    /// let flipped = tensor.flip((0, 1))?;
+    /// assert_eq!(flipped.to_vec2::<f32>()?, &[[5., 4., 3.], [2., 1., 0.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn flip<D: Dims>(&self, dims: D) -> Result<Tensor> {
+        let dims = dims.to_indexes(self.shape(), "flip")?;
+        if dims.is_empty() {
+            return Ok(self.clone());
+        }
+        if self.device.is_wgpu() {
+            let mut t = self.clone();
+            for &dim in dims.iter() {
+                t = t.gather_along(dim, (0..self.dim(dim)?).rev())?;
+            }
+            return Ok(t);
+        }
+        let op = BackpropOp::new1(self, |t| Op::Flip(t, dims.clone()));
+        let tensor_ = Tensor_ {
+            id: TensorId::new(),
+            storage: self.storage.clone(),
+            layout: self.layout.flip(&dims)?,
+            op,
+            is_variable: false,
+            dtype: self.dtype,
+            device: self.device.clone(),
+        };
+        Ok(Tensor(Arc::new(tensor_)))
+    }
+
+    // Selects the elements at the given indexes along `dim`, this is used instead of negative
+    // strides on wgpu which does not support them.
+    fn gather_along(&self, dim: usize, ids: impl Iterator<Item = usize>) -> Result<Tensor> {
+        let ids = ids.map(|i| i as u32).collect::<Vec<_>>();
+        let len = ids.len();
+        let ids = Tensor::from_vec(ids, len, &self.device)?;
+        self.index_select(&ids, dim)
+    }
+
     /// Returns true if the data is stored in a C contiguous (aka row major) way.
     pub fn is_contiguous(&self) -> bool {
         self.layout.is_contiguous()
@@ -1928,6 +2167,22 @@ impl Tensor {
         self.layout.is_fortran_contiguous()
     }
 
+    // Creates a view sharing the storage of this tensor with an arbitrary layout, the caller
+    // has to ensure that the layout is within the bounds of the storage. The resulting tensor
+    // is detached from the computation graph.
+    pub(crate) fn with_layout(&self, layout: Layout) -> Result<Tensor> {
+        let tensor_ = Tensor_ {
+            id: TensorId::new(),
+            storage: self.storage.clone(),
+            layout,
+            op: BackpropOp::none(),
+            is_variable: false,
+            dtype: self.dtype,
+            device: self.device.clone(),
+        };
+        Ok(Tensor(Arc::new(tensor_)))
+    }
+
     /// Compared to clone, this copies the actual storage but may fail because of running out of
     /// memory.
     pub fn copy(&self) -> Result<Tensor> {
@@ -1965,6 +2220,21 @@ impl Tensor {
         }
     }
 
+    /// Returns a new tensor sharing the storage and layout of this tensor but with a different
+    /// backprop op.
+    pub(crate) fn share_storage(&self, op: BackpropOp, is_variable: bool) -> Tensor {
+        let tensor_ = Tensor_ {
+            id: TensorId::new(),
+            storage: self.storage.clone(),
+            layout: self.layout.clone(),
+            op,
+            is_variable,
+            dtype: self.dtype,
+            device: self.device.clone(),
+        };
+        Tensor(Arc::new(tensor_))
+    }
+
     /// If the target device is the same as the tensor device, only a shallow copy is performed.
     pub fn to_device(&self, device: &Device) -> Result<Tensor> {
         if self.device().same_device(device) {
@@ -2429,6 +2699,72 @@ impl Tensor {
         }
     }
 
+    /// Pad the input tensor by reflecting its values along dimension `dim`, the border values are
+    /// not repeated. This adds `left` elements before the input tensor values and `right`
+    /// elements after, both have to be smaller than the size of the dimension.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::arange(0u32, 4, &Device::Cpu)?;
+    /// let b = a.pad_with_reflect(0, 2, 3)?;
+    /// assert_eq!(b.to_vec1::<u32>()?, &[2, 1, 0, 1, 2, 3, 2, 1, 0]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn pad_with_reflect<D: Dim>(&self, dim: D, left: usize, right: usize) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "pad_with_reflect")?;
+        let size = self.dim(dim)?;
+        if left >= size || right >= size {
+            bail!(
+                "pad_with_reflect padding ({left}, {right}) should be smaller than the dim size {size}"
+            )
+        }
+        if left == 0 && right == 0 {
+            return Ok(self.clone());
+        }
+        let mut v = vec![];
+        if left > 0 {
+            v.push(self.narrow_with_step(dim, left, left, -1)?)
+        }
+        v.push(self.clone());
+        if right > 0 {
+            v.push(self.narrow_with_step(dim, size - 2, right, -1)?)
+        }
+        Tensor::cat(&v, dim)
+    }
+
+    /// Pad the input tensor by wrapping its values around along dimension `dim`. This adds `left`
+    /// elements before the input tensor values and `right` elements after, both have to be at
+    /// most the size of the dimension.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::arange(0u32, 4, &Device::Cpu)?;
+    /// let b = a.pad_with_circular(0, 2, 3)?;
+    /// assert_eq!(b.to_vec1::<u32>()?, &[2, 3, 0, 1, 2, 3, 0, 1, 2]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn pad_with_circular<D: Dim>(&self, dim: D, left: usize, right: usize) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "pad_with_circular")?;
+        let size = self.dim(dim)?;
+        if left > size || right > size {
+            bail!(
+                "pad_with_circular padding ({left}, {right}) should be at most the dim size {size}"
+            )
+        }
+        if left == 0 && right == 0 {
+            return Ok(self.clone());
+        }
+        let mut v = vec![];
+        if left > 0 {
+            v.push(self.narrow(dim, size - left, left)?)
+        }
+        v.push(self.clone());
+        if right > 0 {
+            v.push(self.narrow(dim, 0, right)?)
+        }
+        Tensor::cat(&v, dim)
+    }
+
     /// Run the `forward` method of `m` on `self`.
     pub fn apply<M: crate::Module>(&self, m: &M) -> Result<Self> {
         m.forward(self)
@@ -31,6 +31,14 @@ pub struct WgpuDevice {
     backend: WgpuBackend,
 }
 
+impl WgpuDevice {
+    /// Blocks until all the work submitted to the device has completed.
+    pub fn synchronize(&self) -> Result<()> {
+        self.backend.synchronize();
+        Ok(())
+    }
+}
+
 impl BackendDevice for WgpuDevice {
     type Storage = WgpuStorage;
 
@@ -163,7 +171,7 @@ impl BackendStorage for WgpuStorage {
             .backend
             .read_buf_as::<f32>(self.id)
             .map_err(|e| WgpuError::WgpuBackendError(e))?;
-        Ok(CpuStorage::F32(data))
+        Ok(CpuStorage::F32(data.into()))
     }
 
     fn affine(&self, layout: &Layout, mul: f64, add: f64) -> Result<Self> {
@@ -382,6 +390,26 @@ impl BackendStorage for WgpuStorage {
         todo!()
     }
 
+    fn conv3d(
+        &self,
+        _l: &crate::Layout,
+        _kernel: &Self,
+        _kernel_l: &crate::Layout,
+        _params: &crate::conv::ParamsConv3D,
+    ) -> crate::Result<Self> {
+        crate::bail!("wgpu conv3d not implemented")
+    }
+
+    fn conv_transpose3d(
+        &self,
+        _l: &crate::Layout,
+        _kernel: &Self,
+        _kernel_l: &crate::Layout,
+        _params: &crate::conv::ParamsConvTranspose3D,
+    ) -> crate::Result<Self> {
+        crate::bail!("wgpu conv_transpose3d not implemented")
+    }
+
     fn avg_pool2d(
         &self,
         _: &crate::Layout,
@@ -400,6 +428,24 @@ impl BackendStorage for WgpuStorage {
         todo!()
     }
 
+    fn avg_pool3d(
+        &self,
+        _: &crate::Layout,
+        _: (usize, usize, usize),
+        _: (usize, usize, usize),
+    ) -> crate::Result<Self> {
+        crate::bail!("wgpu avg_pool3d not implemented")
+    }
+
+    fn max_pool3d(
+        &self,
+        _: &crate::Layout,
+        _: (usize, usize, usize),
+        _: (usize, usize, usize),
+    ) -> crate::Result<Self> {
+        crate::bail!("wgpu max_pool3d not implemented")
+    }
+
     fn upsample_nearest1d(&self, _: &crate::Layout, _: usize) -> crate::Result<Self> {
         todo!()
     }
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use candle_core::{test_device, test_utils, Device, IndexOp, Tensor};
+use candle_core::{test_device, test_utils, DType, Device, IndexOp, Tensor};
 
 /* This test is based on the following script.
 import torch
@@ -577,6 +577,154 @@ fn conv2d_grad(dev: &Device) -> Result<()> {
     Ok(())
 }
 
+// Reference 3D convolution built by summing 2D convolutions over the depth slices.
+fn conv3d_ref(
+    t: &Tensor,
+    w: &Tensor,
+    padding: usize,
+    stride: usize,
+    dilation: usize,
+) -> Result<Tensor> {
+    let (b, _c_in, d, _h, _w) = t.dims5()?;
+    let (_c_out, _, k_d, _, _) = w.dims5()?;
+    let out_d = (d + 2 * padding - dilation * (k_d - 1) - 1) / stride + 1;
+    let mut slices = Vec::with_capacity(out_d);
+    for z in 0..out_d {
+        let mut acc: Option<Tensor> = None;
+        for kd in 0..k_d {
+            let src = (z * stride + kd * dilation) as i64 - padding as i64;
+            if src < 0 || src >= d as i64 {
+                continue;
+            }
+            let ys = t.i((.., .., src as usize))?.conv2d(
+                &w.i((.., .., kd))?,
+                padding,
+                stride,
+                dilation,
+                1,
+            )?;
+            acc = Some(match acc {
+                None => ys,
+                Some(acc) => (acc + ys)?,
+            })
+        }
+        slices.push(acc.expect("empty depth window").unsqueeze(2)?)
+    }
+    let res = Tensor::cat(&slices, 2)?;
+    assert_eq!(res.dim(0)?, b);
+    Ok(res)
+}
+
+fn conv3d(dev: &Device) -> Result<()> {
+    let t = Tensor::rand(-1f32, 1., (2, 4, 5, 6, 5), dev)?;
+    let w = Tensor::rand(-1f32, 1., (3, 4, 3, 2, 3), dev)?;
+    for (padding, stride, dilation) in [(0, 1, 1), (1, 1, 1), (1, 2, 1), (2, 1, 2), (1, 2, 2)] {
+        let res = t.conv3d(&w, padding, stride, dilation, 1)?;
+        let exp = conv3d_ref(&t, &w, padding, stride, dilation)?;
+        assert_eq!(res.dims(), exp.dims());
+        let diff = (res - exp)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f32>()?;
+        assert!(diff < 1e-4, "{padding} {stride} {dilation}: {diff}");
+    }
+
+    // Half precision inputs.
+    let exp = t.conv3d(&w, 1, 2, 1, 1)?;
+    for (dtype, tol) in [(DType::F16, 1e-2), (DType::BF16, 1e-1)] {
+        if dtype == DType::BF16 && dev.is_cpu() {
+            // The cpu matmul does not support bf16.
+            continue;
+        }
+        let res = t
+            .to_dtype(dtype)?
+            .conv3d(&w.to_dtype(dtype)?, 1, 2, 1, 1)?
+            .to_dtype(DType::F32)?;
+        let diff = (res - &exp)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f32>()?;
+        assert!(diff < tol, "{dtype:?}: {diff}");
+    }
+
+    // Grouped convolutions are the concatenation of the per-group results.
+    let w = Tensor::rand(-1f32, 1., (4, 2, 2, 2, 2), dev)?;
+    let res = t.conv3d(&w, 1, 1, 1, 2)?;
+    let exp = Tensor::cat(
+        &[
+            t.narrow(1, 0, 2)?.conv3d(&w.narrow(0, 0, 2)?, 1, 1, 1, 1)?,
+            t.narrow(1, 2, 2)?.conv3d(&w.narrow(0, 2, 2)?, 1, 1, 1, 1)?,
+        ],
+        1,
+    )?;
+    let diff = (res - exp)?
+        .abs()?
+        .flatten_all()?
+        .max(0)?
+        .to_scalar::<f32>()?;
+    assert!(diff < 1e-5, "{diff}");
+    Ok(())
+}
+
+fn conv_transpose3d(dev: &Device) -> Result<()> {
+    // The transposed convolution is the adjoint of the convolution:
+    // <conv3d(x, w), y> == <x, conv_transpose3d(y, w)>
+    let x = Tensor::rand(-1f32, 1., (2, 3, 5, 4, 6), dev)?;
+    let w = Tensor::rand(-1f32, 1., (2, 3, 3, 2, 3), dev)?;
+    for (padding, stride, dilation) in [(0, 1, 1), (1, 1, 1), (1, 2, 1), (1, 1, 2)] {
+        let ys = x.conv3d(&w, padding, stride, dilation, 1)?;
+        let y = Tensor::rand_like(&ys, -1., 1.)?;
+        let (_, _, d, h, wd) = x.dims5()?;
+        let (_, _, o_d, o_h, o_w) = y.dims5()?;
+        let out = |o: usize, k: usize| (o - 1) * stride + dilation * (k - 1) + 1;
+        let output_padding = [(d, o_d, 3), (h, o_h, 2), (wd, o_w, 3)]
+            .iter()
+            .map(|&(i, o, k)| i + 2 * padding - out(o, k))
+            .max()
+            .unwrap();
+        let xt = y.conv_transpose3d(&w, padding, output_padding, stride, dilation)?;
+        let xt = xt.narrow(2, 0, d)?.narrow(3, 0, h)?.narrow(4, 0, wd)?;
+        let lhs = (ys * &y)?.sum_all()?.to_scalar::<f32>()?;
+        let rhs = (x.clone() * xt)?.sum_all()?.to_scalar::<f32>()?;
+        assert!(
+            (lhs - rhs).abs() < 1e-3,
+            "{padding} {stride} {dilation}: {lhs} {rhs}"
+        );
+    }
+
+    // Half precision inputs.
+    let y = Tensor::rand(-1f32, 1., (2, 2, 3, 4, 3), dev)?;
+    let exp = y.conv_transpose3d(&w, 1, 1, 2, 1)?;
+    for (dtype, tol) in [(DType::F16, 1e-2), (DType::BF16, 1e-1)] {
+        let res = y
+            .to_dtype(dtype)?
+            .conv_transpose3d(&w.to_dtype(dtype)?, 1, 1, 2, 1)?
+            .to_dtype(DType::F32)?;
+        let diff = (res - &exp)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f32>()?;
+        assert!(diff < tol, "{dtype:?}: {diff}");
+    }
+
+    let t = Tensor::ones((1, 1, 2, 2, 2), DType::F32, dev)?;
+    let w = Tensor::ones((1, 1, 2, 2, 2), DType::F32, dev)?;
+    let res = t.conv_transpose3d(&w, 0, 0, 1, 1)?;
+    assert_eq!(res.dims(), [1, 1, 3, 3, 3]);
+    assert_eq!(
+        res.i((0, 0))?.to_vec3::<f32>()?,
+        [
+            [[1., 2., 1.], [2., 4., 2.], [1., 2., 1.]],
+            [[2., 4., 2.], [4., 8., 4.], [2., 4., 2.]],
+            [[1., 2., 1.], [2., 4., 2.], [1., 2., 1.]]
+        ]
+    );
+    Ok(())
+}
+
 test_device!(conv1d, conv1d_cpu, conv1d_gpu, conv1d_metal, conv1d_wgpu);
 test_device!(
     conv1d_small,
@@ -614,3 +762,11 @@ test_device!(
     conv2_grad_metal,
     conv2d_grad_wgpu
 );
+test_device!(conv3d, conv3d_cpu, conv3d_gpu, conv3d_metal, conv3d_wgpu);
+test_device!(
+    conv_transpose3d,
+    conv_transpose3d_cpu,
+    conv_transpose3d_gpu,
+    conv_transpose3d_metal,
+    conv_transpose3d_wgpu
+);
@@ -0,0 +1,159 @@
+use candle_core::{test_device, test_utils, DType, Device, Result, Tensor, Var};
+
+fn einsum_matmul(dev: &Device) -> Result<()> {
+    let a = Tensor::arange(0f32, 6., dev)?.reshape((2, 3))?;
+    let b = Tensor::arange(0f32, 12., dev)?.reshape((3, 4))?;
+    let c = Tensor::einsum("ij,jk->ik", &[&a, &b])?;
+    assert_eq!(c.to_vec2::<f32>()?, a.matmul(&b)?.to_vec2::<f32>()?);
+
+    // Implicit output, the labels appearing once are sorted alphabetically.
+    let c = Tensor::einsum("ij,jk", &[&a, &b])?;
+    assert_eq!(c.to_vec2::<f32>()?, a.matmul(&b)?.to_vec2::<f32>()?);
+    let c = Tensor::einsum("ij,jk->ki", &[&a, &b])?;
+    assert_eq!(c.to_vec2::<f32>()?, a.matmul(&b)?.t()?.to_vec2::<f32>()?);
+
+    let q = Tensor::arange(0f32, 48., dev)?.reshape((2, 2, 3, 4))?;
+    let k = Tensor::arange(0f32, 80., dev)?.reshape((2, 2, 5, 4))?;
+    let attn = Tensor::einsum("bhqd,bhkd->bhqk", &[&q, &k])?;
+    let expected = q.matmul(&k.t()?)?;
+    assert_eq!(attn.dims(), &[2, 2, 3, 5]);
+    assert_eq!(
+        attn.flatten_all()?.to_vec1::<f32>()?,
+        expected.flatten_all()?.to_vec1::<f32>()?
+    );
+    Ok(())
+}
+
+fn einsum_reductions(dev: &Device) -> Result<()> {
+    let a = Tensor::new(&[[1f32, 2., 3.], [4., 5., 6.], [7., 8., 9.]], dev)?;
+    // Trace and diagonal.
+    let t = Tensor::einsum("ii", &[&a])?;
+    assert_eq!(t.to_scalar::<f32>()?, 15.);
+    let t = Tensor::einsum("ii->i", &[&a])?;
+    assert_eq!(t.to_vec1::<f32>()?, [1., 5., 9.]);
+    // Sums and transposition.
+    let t = Tensor::einsum("ij->j", &[&a])?;
+    assert_eq!(t.to_vec1::<f32>()?, [12., 15., 18.]);
+    let t = Tensor::einsum("ij->", &[&a])?;
+    assert_eq!(t.to_scalar::<f32>()?, 45.);
+    let t = Tensor::einsum("ij->ji", &[&a])?;
+    assert_eq!(t.to_vec2::<f32>()?, a.t()?.to_vec2::<f32>()?);
+    // Dot product, outer product, and elementwise product.
+    let v = Tensor::new(&[1f32, 2., 3.], dev)?;
+    let t = Tensor::einsum("i,i->", &[&v, &v])?;
+    assert_eq!(t.to_scalar::<f32>()?, 14.);
+    let t = Tensor::einsum("i,j->ij", &[&v, &v])?;
+    assert_eq!(
+        t.to_vec2::<f32>()?,
+        [[1., 2., 3.], [2., 4., 6.], [3., 6., 9.]]
+    );
+    let t = Tensor::einsum("ij,ij->ij", &[&a, &a])?;
+    assert_eq!(t.to_vec2::<f32>()?, a.sqr()?.to_vec2::<f32>()?);
+    Ok(())
+}
+
+fn einsum_ellipsis(dev: &Device) -> Result<()> {
+    let a = Tensor::arange(0f32, 24., dev)?.reshape((2, 3, 4))?;
+    let b = Tensor::arange(0f32, 20., dev)?.reshape((1, 4, 5))?;
+    let c = Tensor::einsum("...ij,...jk->...ik", &[&a, &b])?;
+    let expected = a.broadcast_matmul(&b)?;
+    assert_eq!(c.dims(), &[2, 3, 5]);
+    assert_eq!(c.to_vec3::<f32>()?, expected.to_vec3::<f32>()?);
+
+    let c = Tensor::einsum("i...->...", &[&a])?;
+    assert_eq!(c.to_vec2::<f32>()?, a.sum(0)?.to_vec2::<f32>()?);
+    Ok(())
+}
+
+fn einsum_multi_operands(dev: &Device) -> Result<()> {
+    let a = Tensor::arange(0f32, 6., dev)?.reshape((2, 3))?;
+    let b = Tensor::arange(0f32, 12., dev)?.reshape((3, 4))?;
+    let c = Tensor::arange(0f32, 20., dev)?.reshape((4, 5))?;
+    let d = Tensor::arange(0f32, 10., dev)?.reshape((5, 2))?;
+    let expected = a.matmul(&b)?.matmul(&c)?.matmul(&d)?;
+    let t = Tensor::einsum("ij,jk,kl,lm->im", &[&a, &b, &c, &d])?;
+    assert_eq!(t.to_vec2::<f32>()?, expected.to_vec2::<f32>()?);
+    // Trace of the product.
+    let t = Tensor::einsum("ij,jk,kl,li->", &[&a, &b, &c, &d])?;
+    let trace = Tensor::einsum("ii", &[&expected])?;
+    assert_eq!(t.to_scalar::<f32>()?, trace.to_scalar::<f32>()?);
+    Ok(())
+}
+
+fn einsum_errors(dev: &Device) -> Result<()> {
+    let a = Tensor::zeros((2, 3), DType::F32, dev)?;
+    let b = Tensor::zeros((4, 5), DType::F32, dev)?;
+    let err = Tensor::einsum("ij,jk->ik", &[&a, &b]).unwrap_err();
+    assert!(
+        err.to_string().contains("shape mismatch in einsum"),
+        "{err}"
+    );
+    let err = Tensor::einsum("ijk->ik", &[&a]).unwrap_err();
+    assert!(err.to_string().contains("unexpected rank"), "{err}");
+    assert!(Tensor::einsum("ij,jk->ik", &[&a]).is_err());
+    assert!(Tensor::einsum("ij->iz", &[&a]).is_err());
+    assert!(Tensor::einsum("i1->i", &[&a]).is_err());
+    Ok(())
+}
+
+fn einsum_grad(dev: &Device) -> Result<()> {
+    let a = Var::new(&[[1f32, 2., 3.], [4., 5., 6.]], dev)?;
+    let b = Var::new(&[[1f32, -1.], [2., 0.5], [0., 3.]], dev)?;
+    let c = Tensor::einsum("ij,jk->ik", &[a.as_tensor(), b.as_tensor()])?;
+    let grads = c.sum_all()?.backward()?;
+    let grad_a = grads.get(&a).unwrap();
+    let grad_b = grads.get(&b).unwrap();
+    // d(sum(a @ b))/da = 1 @ b^t, d(sum(a @ b))/db = a^t @ 1
+    assert_eq!(
+        test_utils::to_vec2_round(grad_a, 4)?,
+        [[0., 2.5, 3.], [0., 2.5, 3.]]
+    );
+    assert_eq!(
+        test_utils::to_vec2_round(grad_b, 4)?,
+        [[5., 5.], [7., 7.], [9., 9.]]
+    );
+    Ok(())
+}
+
+test_device!(
+    einsum_matmul,
+    einsum_matmul_cpu,
+    einsum_matmul_gpu,
+    einsum_matmul_metal,
+    einsum_matmul_wgpu
+);
+test_device!(
+    einsum_reductions,
+    einsum_reductions_cpu,
+    einsum_reductions_gpu,
+    einsum_reductions_metal,
+    einsum_reductions_wgpu
+);
+test_device!(
+    einsum_ellipsis,
+    einsum_ellipsis_cpu,
+    einsum_ellipsis_gpu,
+    einsum_ellipsis_metal,
+    einsum_ellipsis_wgpu
+);
+test_device!(
+    einsum_multi_operands,
+    einsum_multi_operands_cpu,
+    einsum_multi_operands_gpu,
+    einsum_multi_operands_metal,
+    einsum_multi_operands_wgpu
+);
+test_device!(
+    einsum_errors,
+    einsum_errors_cpu,
+    einsum_errors_gpu,
+    einsum_errors_metal,
+    einsum_errors_wgpu
+);
+test_device!(
+    einsum_grad,
+    einsum_grad_cpu,
+    einsum_grad_gpu,
+    einsum_grad_metal,
+    einsum_grad_wgpu
+);
@@ -1,5 +1,5 @@
 use anyhow::{Context, Result};
-use candle_core::{test_device, test_utils, Device, Shape, Tensor, Var};
+use candle_core::{autograd, test_device, test_utils, DType, Device, Shape, Tensor, Var};
 
 fn simple_grad(device: &Device) -> Result<()> {
     let x = Var::new(&[3f32, 1., 4.], device)?;
@@ -503,29 +503,590 @@ fn binary_grad(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn checkpoint_grad(device: &Device) -> Result<()> {
+    let w1 = Var::new(&[[1f32, -2.], [0.5, 3.]], device)?;
+    let w2 = Var::new(&[[2f32, 1.], [-1., 0.5]], device)?;
+    let x = Var::new(&[[0.3f32, -0.7], [1.2, 0.4]], device)?;
+    let block = |w1: Tensor, w2: Tensor| {
+        move |xs: &[Tensor]| -> candle_core::Result<Tensor> {
+            let ys = xs[0].matmul(&w1)?.silu()?.matmul(&w2)?;
+            ys + &xs[0]
+        }
+    };
+    let f1 = block(w1.as_tensor().clone(), w2.as_tensor().clone());
+    let f2 = block(w1.as_tensor().clone(), w2.as_tensor().clone());
+
+    // Two stacked blocks, the checkpointed version recomputes the activations on backward.
+    let eager = f1(&[f1(&[x.as_tensor().clone()])?])?.sqr()?.sum_all()?;
+    let ys = candle_core::checkpoint(f1, &[x.as_tensor()])?;
+    let ys = candle_core::checkpoint(f2, &[&ys])?;
+    let ckpt = ys.sqr()?.sum_all()?;
+    assert_eq!(
+        test_utils::to_vec0_round(&eager, 4)?,
+        test_utils::to_vec0_round(&ckpt, 4)?
+    );
+    let eager_grads = eager.backward()?;
+    let ckpt_grads = ckpt.backward()?;
+    for v in [&w1, &w2, &x] {
+        let g1 = eager_grads.get(v).context("no eager grad")?;
+        let g2 = ckpt_grads.get(v).context("no checkpoint grad")?;
+        assert_eq!(
+            test_utils::to_vec2_round(g1, 4)?,
+            test_utils::to_vec2_round(g2, 4)?
+        );
+    }
+
+    // The checkpointed node tracks the captured variables even if the input does not.
+    let w = w1.as_tensor().clone();
+    let xs = x.as_tensor().detach();
+    let ys = candle_core::checkpoint(move |xs: &[Tensor]| xs[0].mul(&w), &[&xs])?;
+    let grads = ys.sum_all()?.backward()?;
+    let grad_w = grads.get(&w1).context("no grad for w1")?;
+    assert_eq!(grad_w.to_vec2::<f32>()?, xs.to_vec2::<f32>()?);
+    assert!(grads.get(&xs).is_none());
+    Ok(())
+}
+
+// Compares the gradients obtained via backprop with a central finite difference approximation.
+// The output of `f` is multiplied by some fixed weights so that the gradients are not trivial.
+fn check_grad<F>(f: F, vars: &[&Var]) -> Result<()>
+where
+    F: Fn(&[&Tensor]) -> candle_core::Result<Tensor>,
+{
+    let eps = 1e-4;
+    let xs = vars.iter().map(|v| v.as_tensor()).collect::<Vec<_>>();
+    let ys = f(&xs)?;
+    let ws = Tensor::arange(0f64, ys.elem_count() as f64, ys.device())?
+        .affine(0.37, 0.1)?
+        .sin()?
+        .reshape(ys.shape())?;
+    let loss = |xs: &[&Tensor]| -> Result<f64> { Ok((f(xs)? * &ws)?.sum_all()?.to_scalar()?) };
+    let grads = (ys * &ws)?.sum_all()?.backward()?;
+    for (idx, var) in vars.iter().enumerate() {
+        let grad = grads.get(var).context("no grad")?.flatten_all()?;
+        let grad = grad.to_vec1::<f64>()?;
+        let values = var.flatten_all()?.to_vec1::<f64>()?;
+        for i in 0..values.len() {
+            let shifted = |delta: f64| -> Result<f64> {
+                let mut values = values.clone();
+                values[i] += delta;
+                let x = Tensor::from_vec(values, var.shape(), var.device())?;
+                let mut xs = xs.clone();
+                xs[idx] = &x;
+                loss(&xs)
+            };
+            let fd = (shifted(eps)? - shifted(-eps)?) / (2. * eps);
+            assert!(
+                (fd - grad[i]).abs() < 1e-4 * (1. + fd.abs()),
+                "var {idx}, index {i}: backprop {} finite-diff {fd}",
+                grad[i]
+            );
+        }
+    }
+    Ok(())
+}
+
+fn conv_transpose_grad(device: &Device) -> Result<()> {
+    let x = Var::rand_f64(-1., 1., (2, 4, 5), DType::F64, device)?;
+    let w = Var::rand_f64(-1., 1., (4, 3, 3), DType::F64, device)?;
+    check_grad(|xs| xs[0].conv_transpose1d(xs[1], 0, 0, 1, 1, 1), &[&x, &w])?;
+    check_grad(|xs| xs[0].conv_transpose1d(xs[1], 1, 1, 2, 2, 1), &[&x, &w])?;
+    let w = Var::rand_f64(-1., 1., (4, 3, 3), DType::F64, device)?;
+    check_grad(|xs| xs[0].conv_transpose1d(xs[1], 1, 0, 2, 1, 2), &[&x, &w])?;
+
+    let x = Var::rand_f64(-1., 1., (2, 2, 4, 3), DType::F64, device)?;
+    let w = Var::rand_f64(-1., 1., (2, 3, 3, 2), DType::F64, device)?;
+    check_grad(|xs| xs[0].conv_transpose2d(xs[1], 0, 0, 1, 1), &[&x, &w])?;
+    check_grad(|xs| xs[0].conv_transpose2d(xs[1], 1, 1, 2, 1), &[&x, &w])?;
+    check_grad(|xs| xs[0].conv_transpose2d(xs[1], 1, 0, 2, 2), &[&x, &w])?;
+    Ok(())
+}
+
+fn pool_grad(device: &Device) -> Result<()> {
+    let x = Var::rand_f64(-1., 1., (1, 2, 7, 6), DType::F64, device)?;
+    // Non-overlapping windows, with some trailing elements not being covered.
+    check_grad(|xs| xs[0].avg_pool2d((2, 2)), &[&x])?;
+    check_grad(|xs| xs[0].max_pool2d((2, 2)), &[&x])?;
+    // Overlapping windows.
+    check_grad(|xs| xs[0].avg_pool2d_with_stride((3, 3), (2, 1)), &[&x])?;
+    check_grad(|xs| xs[0].max_pool2d_with_stride((3, 2), (2, 1)), &[&x])?;
+    // Gaps between windows.
+    check_grad(|xs| xs[0].avg_pool2d_with_stride((2, 1), (3, 2)), &[&x])?;
+    check_grad(|xs| xs[0].max_pool2d_with_stride((2, 2), (3, 3)), &[&x])?;
+
+    // Ties are split evenly between the max values of each window.
+    let x = Var::new(&[[[[1f32, 1., 0.], [1., 0., 0.], [0., 0., 2.]]]], device)?;
+    let ys = x.max_pool2d_with_stride(2, 1)?;
+    let grads = ys.sum_all()?.backward()?;
+    let grad_x = grads.get(&x).context("no grad for x")?;
+    assert_eq!(
+        test_utils::to_vec3_round(&grad_x.squeeze(0)?, 4)?,
+        [[[0.3333, 1.3333, 0.], [1.3333, 0., 0.], [0., 0., 1.]]]
+    );
+    Ok(())
+}
+
+fn conv3d_grad(device: &Device) -> Result<()> {
+    let x = Var::rand_f64(-1., 1., (2, 2, 4, 3, 5), DType::F64, device)?;
+    let w = Var::rand_f64(-1., 1., (3, 2, 2, 3, 2), DType::F64, device)?;
+    check_grad(|xs| xs[0].conv3d(xs[1], 0, 1, 1, 1), &[&x, &w])?;
+    check_grad(|xs| xs[0].conv3d(xs[1], 1, 2, 1, 1), &[&x, &w])?;
+    check_grad(|xs| xs[0].conv3d(xs[1], 1, 1, 2, 1), &[&x, &w])?;
+
+    let w = Var::rand_f64(-1., 1., (2, 3, 2, 2, 3), DType::F64, device)?;
+    check_grad(|xs| xs[0].conv_transpose3d(xs[1], 0, 0, 1, 1), &[&x, &w])?;
+    check_grad(|xs| xs[0].conv_transpose3d(xs[1], 1, 1, 2, 1), &[&x, &w])?;
+    check_grad(|xs| xs[0].conv_transpose3d(xs[1], 1, 0, 1, 2), &[&x, &w])?;
+    Ok(())
+}
+
+fn pool3d_grad(device: &Device) -> Result<()> {
+    let x = Var::rand_f64(-1., 1., (1, 2, 5, 4, 5), DType::F64, device)?;
+    check_grad(|xs| xs[0].avg_pool3d(2), &[&x])?;
+    check_grad(|xs| xs[0].max_pool3d(2), &[&x])?;
+    check_grad(
+        |xs| xs[0].avg_pool3d_with_stride((3, 2, 2), (1, 2, 1)),
+        &[&x],
+    )?;
+    check_grad(
+        |xs| xs[0].max_pool3d_with_stride((2, 2, 3), (2, 1, 1)),
+        &[&x],
+    )?;
+    Ok(())
+}
+
+fn upsample_grad(device: &Device) -> Result<()> {
+    let x = Var::rand_f64(-1., 1., (1, 2, 3), DType::F64, device)?;
+    check_grad(|xs| xs[0].upsample_nearest1d(6), &[&x])?;
+    check_grad(|xs| xs[0].upsample_nearest1d(5), &[&x])?;
+    let x = Var::rand_f64(-1., 1., (1, 2, 3, 4), DType::F64, device)?;
+    check_grad(|xs| xs[0].upsample_nearest2d(6, 8), &[&x])?;
+    check_grad(|xs| xs[0].upsample_nearest2d(6, 12), &[&x])?;
+    check_grad(|xs| xs[0].upsample_nearest2d(5, 7), &[&x])?;
+    Ok(())
+}
+
+fn rounding_grad(device: &Device) -> Result<()> {
+    let x = Var::new(&[-1.7f32, -0.2, 0.4, 1.5, 2.6], device)?;
+    let w = Tensor::new(&[1f32, 2., 3., 4., 5.], device)?;
+    for ys in [x.round()?, x.floor()?, x.ceil()?] {
+        let grads = (ys * &w)?.sum_all()?.backward()?;
+        assert!(grads.get(&x).is_none());
+    }
+    let ys = x.round_ste()?;
+    assert_eq!(ys.to_vec1::<f32>()?, [-2., -0., 0., 2., 3.]);
+    let ys = (x.floor_ste()? + x.ceil_ste()?)?;
+    assert_eq!(ys.to_vec1::<f32>()?, [-3., -1., 1., 3., 5.]);
+    let grads = ((ys + x.round_ste()?)? * &w)?.sum_all()?.backward()?;
+    let grad_x = grads.get(&x).context("no grad for x")?;
+    assert_eq!(grad_x.to_vec1::<f32>()?, [3., 6., 9., 12., 15.]);
+    Ok(())
+}
+
+fn flip_grad(device: &Device) -> Result<()> {
+    let x = Var::rand_f64(-1., 1., (2, 3, 5), DType::F64, device)?;
+    check_grad(|xs| xs[0].flip(2), &[&x])?;
+    check_grad(|xs| xs[0].flip((0, 1))?.sqr(), &[&x])?;
+    check_grad(|xs| xs[0].narrow_with_step(2, 1, 2, 3), &[&x])?;
+    check_grad(|xs| xs[0].narrow_with_step(2, 4, 3, -2), &[&x])?;
+    check_grad(|xs| xs[0].narrow_with_step(1, 2, 2, -1)?.exp(), &[&x])?;
+    // Kernels that do not support negative strides operate on a contiguous copy.
+    let k = Var::rand_f64(-1., 1., (4, 3, 2), DType::F64, device)?;
+    check_grad(
+        |xs| xs[0].flip(2)?.conv1d(&xs[1].flip(0)?, 0, 1, 1, 1),
+        &[&x, &k],
+    )?;
+    Ok(())
+}
+
+fn unfold_grad(device: &Device) -> Result<()> {
+    let x = Var::rand_f64(-1., 1., (2, 7), DType::F64, device)?;
+    check_grad(|xs| xs[0].unfold(1, 3, 2), &[&x])?;
+    check_grad(|xs| xs[0].unfold(1, 3, 1)?.sqr(), &[&x])?;
+    check_grad(|xs| xs[0].pad_with_reflect(1, 3, 2), &[&x])?;
+    check_grad(|xs| xs[0].pad_with_circular(1, 2, 4)?.exp(), &[&x])?;
+    let x = Var::rand_f64(-1., 1., (2, 2, 5, 4), DType::F64, device)?;
+    check_grad(|xs| xs[0].unfold2d((3, 2), 1, 2, 1), &[&x])?;
+    check_grad(|xs| xs[0].unfold2d((2, 2), 0, 1, 2), &[&x])?;
+    let x = Var::rand_f64(-1., 1., (2, 8, 6), DType::F64, device)?;
+    check_grad(|xs| xs[0].fold((4, 3), (2, 2), 0, 1, 1), &[&x])?;
+    check_grad(|xs| xs[0].fold((5, 4), (2, 2), 1, 2, 2), &[&x])?;
+    Ok(())
+}
+
+fn resample_grad(device: &Device) -> Result<()> {
+    use candle_core::{GridSampleMode, GridSamplePadding, InterpolateMode};
+    let x = Var::rand_f64(-1., 1., (1, 2, 4, 5), DType::F64, device)?;
+    for mode in [
+        InterpolateMode::Bilinear,
+        InterpolateMode::Bicubic,
+        InterpolateMode::BilinearAntialias,
+    ] {
+        check_grad(|xs| xs[0].interpolate2d_with_mode(3, 7, mode, false), &[&x])?;
+        check_grad(|xs| xs[0].interpolate2d_with_mode(6, 2, mode, true), &[&x])?;
+    }
+    // Avoid the integer pixel locations where the bilinear interpolation is not differentiable.
+    let grid = Var::new(
+        &[[
+            [[-1.13f64, -0.91], [-0.31, 0.12], [0.47, 0.83]],
+            [[0.92, -0.52], [1.27, 1.08], [0.03, -1.38]],
+        ]],
+        device,
+    )?;
+    for padding in [
+        GridSamplePadding::Zeros,
+        GridSamplePadding::Border,
+        GridSamplePadding::Reflection,
+    ] {
+        for align_corners in [false, true] {
+            let x = x.narrow(1, 0, 1)?;
+            let x = Var::from_tensor(&x)?;
+            check_grad(
+                |xs| xs[0].grid_sample(xs[1], GridSampleMode::Bilinear, padding, align_corners),
+                &[&x, &grid],
+            )?;
+        }
+    }
+    let theta = Var::new(&[[[0.9f64, -0.2, 0.1], [0.3, 1.1, -0.2]]], device)?;
+    let x = Var::from_tensor(&x.narrow(1, 0, 1)?)?;
+    check_grad(
+        |xs| {
+            let grid = Tensor::affine_grid(xs[1], (1, 1, 3, 3), false)?;
+            xs[0].grid_sample(
+                &grid,
+                GridSampleMode::Bilinear,
+                GridSamplePadding::Zeros,
+                false,
+            )
+        },
+        &[&x, &theta],
+    )?;
+    Ok(())
+}
+
+fn functional_grad(device: &Device) -> Result<()> {
+    let x = Var::new(&[1f32, -2., 0.5], device)?;
+    let y = Var::new(&[3f32, 1., 2.], device)?;
+    let z = (x.as_tensor() * y.as_tensor())?;
+    let out = z.sqr()?.sum_all()?;
+    // Gradients with respect to an intermediary node and an unused input.
+    let unused = Var::new(&[1f32, 1.], device)?;
+    let grads = autograd::grad(&out, &[&z, x.as_tensor(), unused.as_tensor()])?;
+    assert_eq!(grads[0].to_vec1::<f32>()?, [6., -4., 2.]);
+    assert_eq!(grads[1].to_vec1::<f32>()?, [18., -4., 4.]);
+    assert_eq!(grads[2].to_vec1::<f32>()?, [0., 0.]);
+
+    // The gradients are tracked, d/dy sum(d out/dx) = d/dy sum(2 x y^2) = 4 x y
+    let grads = autograd::grad(&grads[1].sum_all()?, &[y.as_tensor()])?;
+    assert_eq!(grads[0].to_vec1::<f32>()?, [12., -8., 4.]);
+
+    // Same using `backward_create_graph`.
+    let grads = out.backward_create_graph()?;
+    let grad_x = grads.get(&x).context("no grad for x")?;
+    let grads = grad_x.sum_all()?.backward()?;
+    let grad_y = grads.get(&y).context("no grad for y")?;
+    assert_eq!(grad_y.to_vec1::<f32>()?, [12., -8., 4.]);
+
+    let v = Tensor::new(&[1f32, 0., -1.], device)?;
+    let inputs = [x.as_tensor().clone(), y.as_tensor().clone()];
+    let (out, vjp) = autograd::vjp(|xs| &xs[0] * &xs[1], &inputs, &v)?;
+    assert_eq!(out.to_vec1::<f32>()?, [3., -2., 1.]);
+    assert_eq!(vjp[0].to_vec1::<f32>()?, [3., 0., -2.]);
+    assert_eq!(vjp[1].to_vec1::<f32>()?, [1., 0., -0.5]);
+    Ok(())
+}
+
+fn jvp_hvp_grad(device: &Device) -> Result<()> {
+    let eps = 1e-5;
+    let x = Tensor::new(&[[0.3f64, -0.7, 1.2], [0.5, 0.1, -0.4]], device)?;
+    let w = Tensor::new(&[[0.2f64, -1.], [0.7, 0.3], [-0.5, 0.9]], device)?;
+    let t_x = Tensor::new(&[[1f64, 0.5, -0.3], [0.2, -1., 0.4]], device)?;
+    let t_w = Tensor::new(&[[-0.1f64, 0.3], [0.6, -0.2], [0.1, 0.8]], device)?;
+    let shift =
+        |t: &Tensor, dt: &Tensor, scale: f64| -> candle_core::Result<Tensor> { t + (dt * scale)? };
+
+    // Jacobian-vector product against a finite difference directional derivative.
+    let f = |xs: &[Tensor]| xs[0].matmul(&xs[1])?.tanh()?.sqr();
+    let (out, jvp) = autograd::jvp(f, &[x.clone(), w.clone()], &[t_x.clone(), t_w.clone()])?;
+    let plus = f(&[shift(&x, &t_x, eps)?, shift(&w, &t_w, eps)?])?;
+    let minus = f(&[shift(&x, &t_x, -eps)?, shift(&w, &t_w, -eps)?])?;
+    let fd = ((plus - minus)? / (2. * eps))?;
+    assert_eq!(out.dims(), &[2, 2]);
+    assert_eq!(
+        test_utils::to_vec2_round(&jvp.to_dtype(DType::F32)?, 4)?,
+        test_utils::to_vec2_round(&fd.to_dtype(DType::F32)?, 4)?
+    );
+
+    // Hessian-vector product against a finite difference of the gradients.
+    let f = |xs: &[Tensor]| {
+        xs[0]
+            .matmul(&xs[1])?
+            .sin()?
+            .broadcast_mul(&xs[0].sum_all()?)?
+            .sum_all()
+    };
+    let hvp =
+        autograd::hessian_vector_product(f, &[x.clone(), w.clone()], &[t_x.clone(), t_w.clone()])?;
+    let grads_at = |x: Tensor, w: Tensor| -> Result<Vec<Tensor>> {
+        let (_, grads) = autograd::vjp(f, &[x, w], &Tensor::new(1f64, device)?)?;
+        Ok(grads)
+    };
+    let plus = grads_at(shift(&x, &t_x, eps)?, shift(&w, &t_w, eps)?)?;
+    let minus = grads_at(shift(&x, &t_x, -eps)?, shift(&w, &t_w, -eps)?)?;
+    for i in 0..2 {
+        let fd = ((&plus[i] - &minus[i])? / (2. * eps))?;
+        assert_eq!(
+            test_utils::to_vec2_round(&hvp[i].to_dtype(DType::F32)?, 4)?,
+            test_utils::to_vec2_round(&fd.to_dtype(DType::F32)?, 4)?
+        );
+    }
+    Ok(())
+}
+
+fn gradient_penalty_grad(device: &Device) -> Result<()> {
+    // A WGAN-GP style penalty: the squared norm of the gradient of a critic with respect to its
+    // input, differentiated with respect to the critic weights.
+    let x = Var::new(&[[0.3f64, -0.7], [1.2, 0.5], [0.1, -0.4]], device)?;
+    let penalty = |w: &Tensor| -> Result<Tensor> {
+        let critic = x.matmul(w)?.tanh()?.sum_all()?;
+        let grad_x = autograd::grad(&critic, &[x.as_tensor()])?.remove(0);
+        Ok((grad_x.sqr()?.sum_keepdim(1)?.sqrt()? - 1.)?
+            .sqr()?
+            .sum_all()?)
+    };
+    let w = Var::new(&[[0.4f64], [-0.8]], device)?;
+    let grads = penalty(w.as_tensor())?.backward()?;
+    let grad_w = grads.get(&w).context("no grad for w")?.flatten_all()?;
+    let grad_w = grad_w.to_vec1::<f64>()?;
+    let eps = 1e-5;
+    let values = w.flatten_all()?.to_vec1::<f64>()?;
+    for i in 0..values.len() {
+        let shifted = |delta: f64| -> Result<f64> {
+            let mut values = values.clone();
+            values[i] += delta;
+            let w = Tensor::from_vec(values, w.shape(), device)?;
+            Ok(penalty(&w)?.to_scalar::<f64>()?)
+        };
+        let fd = (shifted(eps)? - shifted(-eps)?) / (2. * eps);
+        assert!((fd - grad_w[i]).abs() < 1e-5, "{fd} {}", grad_w[i]);
+    }
+    Ok(())
+}
+
+fn grad_store_utils(device: &Device) -> Result<()> {
+    use candle_core::backprop::GradAccumulator;
+    let w = Var::new(&[3f32, -4.], device)?;
+    let b = Var::new(&[12f32], device)?;
+    let vars = [w.clone(), b.clone()];
+    // The gradients are [3, -4] and [12], their global norm is 13.
+    let loss = ((w.sqr()? * 0.5)?.sum_all()? + (b.sqr()? * 0.5)?.sum_all()?)?;
+
+    let mut grads = loss.backward()?;
+    assert_eq!(grads.grad_norm(&vars)?, 13.);
+    assert_eq!(grads.clip_grad_norm(&vars, 26.)?, 13.);
+    assert_eq!(
+        grads.get(&w).context("no grad")?.to_vec1::<f32>()?,
+        [3., -4.]
+    );
+    assert_eq!(grads.clip_grad_norm(&vars, 6.5)?, 13.);
+    let grad_w = grads.get(&w).context("no grad")?;
+    assert_eq!(test_utils::to_vec1_round(grad_w, 4)?, [1.5, -2.]);
+    let grad_b = grads.get(&b).context("no grad")?;
+    assert_eq!(test_utils::to_vec1_round(grad_b, 4)?, [6.]);
+    // Only the given variables are taken into account.
+    assert!((grads.grad_norm(&vars[..1])? - 2.5).abs() < 1e-5);
+
+    let mut grads = loss.backward()?;
+    grads.clip_grad_value(&vars, 3.5)?;
+    assert_eq!(
+        grads.get(&w).context("no grad")?.to_vec1::<f32>()?,
+        [3., -3.5]
+    );
+    assert_eq!(grads.get(&b).context("no grad")?.to_vec1::<f32>()?, [3.5]);
+
+    // Non-finite gradients are reported and left untouched by the norm clipping.
+    let loss = (w.as_tensor() * Tensor::new(&[f32::NAN, 1.], device)?)?.sum_all()?;
+    let loss = (loss + (b.as_tensor() * 2.)?.sum_all()?)?;
+    let mut grads = loss.backward()?;
+    let non_finite = grads.non_finite_vars(&vars)?;
+    assert_eq!(non_finite.len(), 1);
+    assert_eq!(non_finite[0].id(), w.id());
+    assert!(grads.clip_grad_norm(&vars, 1.)?.is_nan());
+    assert_eq!(grads.get(&b).context("no grad")?.to_vec1::<f32>()?, [2.]);
+    let loss = (w.as_tensor() * f64::INFINITY)?.sum_all()?;
+    assert_eq!(loss.backward()?.non_finite_vars(&vars)?.len(), 1);
+
+    // The squares of these half precision gradients do not fit in a f16.
+    let w16 = Var::from_tensor(&Tensor::new(&[300f32, -400.], device)?.to_dtype(DType::F16)?)?;
+    let vars16 = [w16.clone()];
+    let grads = (w16.sqr()? * 0.5)?.sum_all()?.backward()?;
+    assert_eq!(grads.grad_norm(&vars16)?, 500.);
+    assert!(grads.non_finite_vars(&vars16)?.is_empty());
+
+    // Accumulate the gradients over micro-batches.
+    let mut acc = GradAccumulator::new(&vars);
+    for scale in [1f64, 2., 6.] {
+        let loss = ((w.as_tensor() * scale)?.sum_all()? + b.sum_all()?)?;
+        acc.accumulate(&loss.backward()?)?;
+    }
+    assert_eq!(acc.steps(), 3);
+    let grads = acc.mean()?;
+    assert_eq!(acc.steps(), 0);
+    assert_eq!(
+        grads.get(&w).context("no grad")?.to_vec1::<f32>()?,
+        [3., 3.]
+    );
+    assert_eq!(grads.get(&b).context("no grad")?.to_vec1::<f32>()?, [1.]);
+    let loss = (w.as_tensor() * 2.)?.sum_all()?;
+    acc.accumulate(&loss.backward()?)?;
+    acc.accumulate(&loss.backward()?)?;
+    let grads = acc.sum();
+    assert_eq!(
+        grads.get(&w).context("no grad")?.to_vec1::<f32>()?,
+        [4., 4.]
+    );
+    // b was not part of the loss so it has no gradient.
+    assert!(grads.get(&b).is_none());
+    Ok(())
+}
+
 test_device!(
     simple_grad,
     simple_grad_cpu,
     simple_grad_gpu,
-    simple_grad_metal
+    simple_grad_metal,
+    simple_grad_wgpu
+);
+test_device!(
+    sum_grad,
+    sum_grad_cpu,
+    sum_grad_gpu,
+    sum_grad_metal,
+    sum_grad_wgpu
 );
-test_device!(sum_grad, sum_grad_cpu, sum_grad_gpu, sum_grad_metal);
 test_device!(
     matmul_grad,
     matmul_grad_cpu,
     matmul_grad_gpu,
-    matmul_grad_metal
+    matmul_grad_metal,
+    matmul_grad_wgpu
 );
 test_device!(
     grad_descent,
     grad_descent_cpu,
     grad_descent_gpu,
-    grad_descent_metal
+    grad_descent_metal,
+    grad_descent_wgpu
+);
+test_device!(
+    unary_grad,
+    unary_grad_cpu,
+    unary_grad_gpu,
+    unary_grad_metal,
+    unary_grad_wgpu
 );
-test_device!(unary_grad, unary_grad_cpu, unary_grad_gpu, unary_grad_metal);
 test_device!(
     binary_grad,
     binary_grad_cpu,
     binary_grad_gpu,
-    binary_grad_metal
+    binary_grad_metal,
+    binary_grad_wgpu
+);
+test_device!(
+    checkpoint_grad,
+    checkpoint_grad_cpu,
+    checkpoint_grad_gpu,
+    checkpoint_grad_metal,
+    checkpoint_grad_wgpu
+);
+test_device!(
+    conv_transpose_grad,
+    conv_transpose_grad_cpu,
+    conv_transpose_grad_gpu,
+    conv_transpose_grad_metal,
+    conv_transpose_grad_wgpu
+);
+test_device!(
+    pool_grad,
+    pool_grad_cpu,
+    pool_grad_gpu,
+    pool_grad_metal,
+    pool_grad_wgpu
+);
+test_device!(
+    conv3d_grad,
+    conv3d_grad_cpu,
+    conv3d_grad_gpu,
+    conv3d_grad_metal,
+    conv3d_grad_wgpu
+);
+test_device!(
+    pool3d_grad,
+    pool3d_grad_cpu,
+    pool3d_grad_gpu,
+    pool3d_grad_metal,
+    pool3d_grad_wgpu
+);
+test_device!(
+    upsample_grad,
+    upsample_grad_cpu,
+    upsample_grad_gpu,
+    upsample_grad_metal,
+    upsample_grad_wgpu
+);
+test_device!(
+    rounding_grad,
+    rounding_grad_cpu,
+    rounding_grad_gpu,
+    rounding_grad_metal,
+    rounding_grad_wgpu
+);
+test_device!(
+    functional_grad,
+    functional_grad_cpu,
+    functional_grad_gpu,
+    functional_grad_metal,
+    functional_grad_wgpu
+);
+test_device!(
+    jvp_hvp_grad,
+    jvp_hvp_grad_cpu,
+    jvp_hvp_grad_gpu,
+    jvp_hvp_grad_metal,
+    jvp_hvp_grad_wgpu
+);
+test_device!(
+    gradient_penalty_grad,
+    gradient_penalty_grad_cpu,
+    gradient_penalty_grad_gpu,
+    gradient_penalty_grad_metal,
+    gradient_penalty_grad_wgpu
+);
+test_device!(
+    flip_grad,
+    flip_grad_cpu,
+    flip_grad_gpu,
+    flip_grad_metal,
+    flip_grad_wgpu
+);
+test_device!(
+    resample_grad,
+    resample_grad_cpu,
+    resample_grad_gpu,
+    resample_grad_metal,
+    resample_grad_wgpu
+);
+test_device!(
+    unfold_grad,
+    unfold_grad_cpu,
+    unfold_grad_gpu,
+    unfold_grad_metal,
+    unfold_grad_wgpu
+);
+test_device!(
+    grad_store_utils,
+    grad_store_utils_cpu,
+    grad_store_utils_gpu,
+    grad_store_utils_metal,
+    grad_store_utils_wgpu
 );
@@ -1,4 +1,4 @@
-use candle::{test_device, Device, IndexOp, Result, Tensor};
+use candle::{test_device, DType, Device, IndexOp, Result, Tensor};
 use candle_core as candle;
 
 fn contiguous(device: &Device) -> Result<()> {
@@ -49,7 +49,230 @@ fn contiguous(device: &Device) -> Result<()> {
     Ok(())
 }
 
-test_device!(contiguous, contiguous_cpu, contiguous_gpu, contiguous_metal);
+fn flip(device: &Device) -> Result<()> {
+    let tensor = Tensor::arange(0u32, 24u32, device)?.reshape((2, 3, 4))?;
+    let flipped = tensor.flip(2)?;
+    if device.is_wgpu() {
+        // The wgpu backend does not support negative strides.
+        assert!(!flipped.layout().has_negative_stride());
+    } else {
+        assert_eq!(flipped.stride(), &[12, 4, -1]);
+        assert!(!flipped.is_contiguous());
+    }
+    assert_eq!(
+        flipped.contiguous()?.to_vec3::<u32>()?,
+        &[
+            [[3, 2, 1, 0], [7, 6, 5, 4], [11, 10, 9, 8]],
+            [[15, 14, 13, 12], [19, 18, 17, 16], [23, 22, 21, 20]]
+        ]
+    );
+    let flipped = tensor.flip((0, 1))?;
+    assert_eq!(
+        flipped.contiguous()?.to_vec3::<u32>()?,
+        &[
+            [[20, 21, 22, 23], [16, 17, 18, 19], [12, 13, 14, 15]],
+            [[8, 9, 10, 11], [4, 5, 6, 7], [0, 1, 2, 3]]
+        ]
+    );
+    // Flipping twice returns the original layout.
+    let twice = tensor.flip(1)?.flip(1)?;
+    assert!(twice.is_contiguous());
+    assert_eq!(twice.to_vec3::<u32>()?, tensor.to_vec3::<u32>()?);
+    // Combining with other views.
+    assert_eq!(
+        tensor
+            .i((1, 1..))?
+            .flip(0)?
+            .t()?
+            .contiguous()?
+            .to_vec2::<u32>()?,
+        &[[20, 16], [21, 17], [22, 18], [23, 19]]
+    );
+    // Ops on flipped views.
+    let flipped = tensor.flip(2)?;
+    assert_eq!(
+        (&flipped + &tensor)?.sum_all()?.to_vec0::<u32>()?,
+        2 * (0..24).sum::<u32>()
+    );
+    assert_eq!(
+        Tensor::arange(0f32, 4., device)?
+            .flip(0)?
+            .cumsum(0)?
+            .to_vec1::<f32>()?,
+        &[3., 5., 6., 6.]
+    );
+    assert_eq!(
+        flipped.max_keepdim(2)?.flatten_all()?.to_vec1::<u32>()?,
+        &[3, 7, 11, 15, 19, 23]
+    );
+    assert_eq!(
+        flipped.argmax_keepdim(2)?.flatten_all()?.to_vec1::<u32>()?,
+        &[0, 0, 0, 0, 0, 0]
+    );
+    let ids = Tensor::new(&[0u32, 2, 3], device)?.flip(0)?;
+    assert_eq!(
+        flipped.i(0)?.index_select(&ids, 1)?.to_vec2::<u32>()?,
+        &[[0, 1, 3], [4, 5, 7], [8, 9, 11]]
+    );
+    Ok(())
+}
+
+fn narrow_with_step(device: &Device) -> Result<()> {
+    let tensor = Tensor::arange(0f32, 20., device)?.reshape((4, 5))?;
+    let t = tensor.narrow_with_step(1, 0, 3, 2)?;
+    assert_eq!(
+        t.to_vec2::<f32>()?,
+        &[[0., 2., 4.], [5., 7., 9.], [10., 12., 14.], [15., 17., 19.]]
+    );
+    let t = tensor.narrow_with_step(0, 3, 2, -2)?;
+    assert_eq!(
+        t.contiguous()?.to_vec2::<f32>()?,
+        &[[15., 16., 17., 18., 19.], [5., 6., 7., 8., 9.]]
+    );
+    let t = tensor.narrow_with_step(1, 4, 5, -1)?;
+    assert_eq!(t.to_vec2::<f32>()?, tensor.flip(1)?.to_vec2::<f32>()?);
+    // Negative strides get normalized before the kernels that index the storage directly.
+    let t = tensor.narrow_with_step(1, 4, 2, -3)?;
+    assert_eq!(
+        t.matmul(&t.t()?)?.to_vec2::<f32>()?,
+        tensor
+            .index_select(&Tensor::new(&[4u32, 1], device)?, 1)?
+            .matmul(&t.t()?.contiguous()?)?
+            .to_vec2::<f32>()?
+    );
+    assert!(tensor.narrow_with_step(1, 0, 2, 0).is_err());
+    assert!(tensor.narrow_with_step(1, 1, 2, -2).is_err());
+    assert!(tensor.narrow_with_step(1, 0, 3, 3).is_err());
+    Ok(())
+}
+
+test_device!(
+    contiguous,
+    contiguous_cpu,
+    contiguous_gpu,
+    contiguous_metal,
+    contiguous_wgpu
+);
+test_device!(flip, flip_cpu, flip_gpu, flip_metal, flip_wgpu);
+test_device!(
+    narrow_with_step,
+    narrow_with_step_cpu,
+    narrow_with_step_gpu,
+    narrow_with_step_metal,
+    narrow_with_step_wgpu
+);
+
+fn unfold(device: &Device) -> Result<()> {
+    let t = Tensor::arange(0u32, 7u32, device)?;
+    let u = t.unfold(0, 3, 2)?;
+    assert_eq!(u.dims(), &[3, 3]);
+    assert_eq!(u.to_vec2::<u32>()?, &[[0, 1, 2], [2, 3, 4], [4, 5, 6]]);
+    // Trailing elements that do not fit in a window are dropped.
+    let u = t.unfold(0, 2, 3)?;
+    assert_eq!(u.to_vec2::<u32>()?, &[[0, 1], [3, 4]]);
+    let t = Tensor::arange(0u32, 12u32, device)?.reshape((3, 4))?;
+    let u = t.unfold(1, 2, 1)?;
+    assert_eq!(u.dims(), &[3, 3, 2]);
+    assert_eq!(u.i(1)?.to_vec2::<u32>()?, &[[4, 5], [5, 6], [6, 7]]);
+    let u = t.unfold(0, 2, 1)?;
+    assert_eq!(u.dims(), &[2, 4, 2]);
+    assert_eq!(
+        u.i(1)?.to_vec2::<u32>()?,
+        &[[4, 8], [5, 9], [6, 10], [7, 11]]
+    );
+    assert!(t.unfold(0, 4, 1).is_err());
+    assert!(t.unfold(1, 2, 0).is_err());
+    Ok(())
+}
+
+fn pad_reflect_circular(device: &Device) -> Result<()> {
+    let t = Tensor::arange(0f32, 4f32, device)?;
+    assert_eq!(
+        t.pad_with_reflect(0, 2, 3)?.to_vec1::<f32>()?,
+        &[2., 1., 0., 1., 2., 3., 2., 1., 0.]
+    );
+    assert_eq!(
+        t.pad_with_circular(0, 2, 3)?.to_vec1::<f32>()?,
+        &[2., 3., 0., 1., 2., 3., 0., 1., 2.]
+    );
+    assert_eq!(
+        t.pad_with_reflect(0, 0, 0)?.to_vec1::<f32>()?,
+        &[0., 1., 2., 3.]
+    );
+    assert!(t.pad_with_reflect(0, 4, 0).is_err());
+    assert!(t.pad_with_circular(0, 0, 5).is_err());
+    let t = Tensor::arange(0f32, 6f32, device)?.reshape((2, 3))?;
+    assert_eq!(
+        t.pad_with_reflect(1, 1, 1)?.to_vec2::<f32>()?,
+        &[[1., 0., 1., 2., 1.], [4., 3., 4., 5., 4.]]
+    );
+    assert_eq!(
+        t.pad_with_circular(0, 1, 0)?.to_vec2::<f32>()?,
+        &[[3., 4., 5.], [0., 1., 2.], [3., 4., 5.]]
+    );
+    Ok(())
+}
+
+fn unfold2d_fold(device: &Device) -> Result<()> {
+    let xs = Tensor::arange(0f32, 2. * 3. * 5. * 4., device)?.reshape((2, 3, 5, 4))?;
+    let ws = Tensor::arange(0f32, 6. * 3. * 3. * 2., device)?
+        .affine(0.1, -1.)?
+        .reshape((6, 3, 3, 2))?;
+    for (padding, stride, dilation) in [(0, 1, 1), (1, 2, 1), (1, 1, 2)] {
+        let cols = xs.unfold2d((3, 2), padding, stride, dilation)?;
+        let ys = ws.reshape((6, 18))?.broadcast_matmul(&cols)?;
+        let expected = xs.conv2d(&ws, padding, stride, dilation, 1)?;
+        let (b, c, h, w) = expected.dims4()?;
+        assert_eq!(cols.dims(), &[2, 18, h * w]);
+        let diff = (ys.reshape((b, c, h, w))? - expected)?
+            .abs()?
+            .sum_all()?
+            .to_scalar::<f32>()?;
+        assert!(diff < 1e-2, "{padding} {stride} {dilation}: {diff}");
+    }
+    // Non-overlapping blocks, folding is the inverse of unfolding.
+    let xs = xs.narrow(2, 0, 4)?;
+    let cols = xs.unfold2d((2, 2), 0, 2, 1)?;
+    assert_eq!(cols.dims(), &[2, 12, 4]);
+    let ys = cols.fold((4, 4), (2, 2), 0, 2, 1)?;
+    assert_eq!(
+        ys.flatten_all()?.to_vec1::<f32>()?,
+        xs.flatten_all()?.to_vec1::<f32>()?
+    );
+    // Overlapping blocks are summed.
+    let cols = xs.unfold2d((3, 3), 1, 1, 1)?;
+    let ones = Tensor::ones((1, 1, 4, 4), DType::F32, device)?;
+    let counts = ones
+        .unfold2d((3, 3), 1, 1, 1)?
+        .fold((4, 4), (3, 3), 1, 1, 1)?;
+    assert_eq!(
+        counts.flatten_all()?.to_vec1::<f32>()?,
+        &[4., 6., 6., 4., 6., 9., 9., 6., 6., 9., 9., 6., 4., 6., 6., 4.]
+    );
+    let ys = cols.fold((4, 4), (3, 3), 1, 1, 1)?;
+    assert_eq!(
+        ys.flatten_all()?.to_vec1::<f32>()?,
+        xs.broadcast_mul(&counts)?.flatten_all()?.to_vec1::<f32>()?
+    );
+    assert!(cols.fold((4, 5), (3, 3), 1, 1, 1).is_err());
+    Ok(())
+}
+
+test_device!(unfold, unfold_cpu, unfold_gpu, unfold_metal, unfold_wgpu);
+test_device!(
+    pad_reflect_circular,
+    pad_reflect_circular_cpu,
+    pad_reflect_circular_gpu,
+    pad_reflect_circular_metal,
+    pad_reflect_circular_wgpu
+);
+test_device!(
+    unfold2d_fold,
+    unfold2d_fold_cpu,
+    unfold2d_fold_gpu,
+    unfold2d_fold_metal,
+    unfold2d_fold_wgpu
+);
 
 #[test]
 fn strided_blocks() -> Result<()> {
@@ -134,5 +357,21 @@ fn strided_blocks() -> Result<()> {
             )
         }
     };
+    let tensor = Tensor::arange(0u32, 24u32, &Cpu)?.reshape((2, 3, 4))?;
+    match tensor.flip(1)?.strided_blocks() {
+        candle::StridedBlocks::SingleBlock { .. } => {
+            panic!("unexpected block structure")
+        }
+        candle::StridedBlocks::MultipleBlocks {
+            block_start_index,
+            block_len,
+        } => {
+            assert_eq!(block_len, 4);
+            assert_eq!(
+                block_start_index.collect::<Vec<_>>(),
+                &[8, 4, 0, 20, 16, 12]
+            )
+        }
+    };
     Ok(())
 }
@@ -0,0 +1,186 @@
+use candle_core::{test_device, test_utils, DType, Device, IndexOp, Result, Tensor, Var};
+
+// Fusion is available on cpu, cuda and metal, wgpu replays the ops eagerly.
+fn fused_count(dev: &Device, count: usize) -> usize {
+    if !dev.is_wgpu() {
+        count
+    } else {
+        0
+    }
+}
+
+fn lazy_chain(dev: &Device) -> Result<()> {
+    let x = Tensor::new(&[[-2f32, -0.5, 0.], [0.5, 1., 3.]], dev)?;
+    let y = Tensor::new(&[[1f32, 2., 3.], [4., 5., 6.]], dev)?;
+    let z = Tensor::new(&[[0.1f32, 0.2, 0.3], [0.4, 0.5, 0.6]], dev)?;
+    let (lx, ly, lz) = (x.lazy(), y.lazy(), z.lazy());
+    let fused = lx.silu()?.mul(&ly)?.add(&lz)?;
+    assert_eq!(fused.dims(), &[2, 3]);
+    assert_eq!(fused.fused_op_count(), fused_count(dev, 3));
+    assert_eq!(fused.is_fusable(), !dev.is_wgpu());
+    assert!(!fused.is_realized());
+    let eager = ((x.silu()? * &y)? + &z)?;
+    assert_eq!(
+        test_utils::to_vec2_round(&fused.realize()?, 5)?,
+        test_utils::to_vec2_round(&eager, 5)?
+    );
+    assert!(fused.is_realized());
+    assert_eq!(
+        test_utils::to_vec2_round(&fused.realize_eager()?, 5)?,
+        test_utils::to_vec2_round(&eager, 5)?
+    );
+
+    let fused = lx
+        .affine(2., -1.)?
+        .tanh()?
+        .maximum(&lz)?
+        .sub(&ly.sqrt()?.exp()?)?
+        .div(&ly)?;
+    let eager = x
+        .affine(2., -1.)?
+        .tanh()?
+        .maximum(&z)?
+        .sub(&y.sqrt()?.exp()?)?
+        .div(&y)?;
+    assert_eq!(
+        test_utils::to_vec2_round(&fused.realize()?, 4)?,
+        test_utils::to_vec2_round(&eager, 4)?
+    );
+    Ok(())
+}
+
+fn lazy_broadcast(dev: &Device) -> Result<()> {
+    let x = Tensor::arange(0f32, 6., dev)?.reshape((2, 3))?;
+    let b = Tensor::new(&[10f32, 20., 30.], dev)?;
+    let s = Tensor::new(&[[2f32], [3.]], dev)?;
+    let fused = x.lazy().add(&b.lazy())?.mul(&s.lazy())?.realize()?;
+    let eager = x.broadcast_add(&b)?.broadcast_mul(&s)?;
+    assert_eq!(fused.dims(), &[2, 3]);
+    assert_eq!(fused.to_vec2::<f32>()?, eager.to_vec2::<f32>()?);
+
+    // Both sides get broadcast.
+    let fused = s.lazy().sub(&b.lazy())?.realize()?;
+    assert_eq!(
+        fused.to_vec2::<f32>()?,
+        [[-8., -18., -28.], [-7., -17., -27.]]
+    );
+
+    let err = x.lazy().add(&s.t()?.lazy()).unwrap_err();
+    assert!(err.to_string().contains("shape mismatch"), "{err}");
+    let err = x.lazy().add(&x.to_dtype(DType::F64)?.lazy()).unwrap_err();
+    assert!(err.to_string().contains("dtype mismatch"), "{err}");
+    Ok(())
+}
+
+fn lazy_shared_and_strided(dev: &Device) -> Result<()> {
+    // Large enough to span multiple blocks in the fused cpu kernel.
+    let x = Tensor::arange(0f32, 10000., dev)?
+        .affine(1e-3, -5.)?
+        .reshape((100, 100))?;
+    let xt = x.t()?;
+    let sub = x.i((.., 10..60))?;
+    let lx = xt.lazy();
+    // `shared` is used twice but only evaluated once.
+    let shared = lx.sqr()?.affine(0.5, 1.)?;
+    let fused = shared.mul(&shared)?.add(&lx.cos()?)?;
+    assert_eq!(fused.fused_op_count(), fused_count(dev, 5));
+    let shared_e = xt.sqr()?.affine(0.5, 1.)?;
+    let eager = ((&shared_e * &shared_e)? + xt.cos()?)?;
+    assert_eq!(
+        test_utils::to_vec2_round(&fused.realize()?, 3)?,
+        test_utils::to_vec2_round(&eager, 3)?
+    );
+
+    let fused = sub.lazy().gelu()?.neg()?.realize()?;
+    let eager = sub.gelu()?.neg()?;
+    assert_eq!(
+        test_utils::to_vec2_round(&fused, 4)?,
+        test_utils::to_vec2_round(&eager, 4)?
+    );
+
+    // Nodes that have already been realized are used as inputs.
+    let shared_t = shared.realize()?;
+    assert_eq!(shared.add(&lx)?.fused_op_count(), fused_count(dev, 1));
+    let fused = shared.add(&lx)?.realize()?;
+    let eager = (shared_t + &xt)?;
+    assert_eq!(
+        test_utils::to_vec2_round(&fused, 3)?,
+        test_utils::to_vec2_round(&eager, 3)?
+    );
+    Ok(())
+}
+
+fn lazy_dtypes(dev: &Device) -> Result<()> {
+    let x = Tensor::new(&[-1.5f32, -0.25, 0.75, 2.], dev)?;
+    let y = Tensor::new(&[0.5f32, 1., 2., 4.], dev)?;
+    for dtype in [DType::F64, DType::F32, DType::BF16, DType::F16] {
+        // Metal has no f64 support.
+        if dtype == DType::F64 && dev.is_metal() {
+            continue;
+        }
+        let x = x.to_dtype(dtype)?;
+        let y = y.to_dtype(dtype)?;
+        let fused = x.lazy().relu()?.add(&y.lazy().recip()?)?.abs()?.realize()?;
+        let eager = (x.relu()? + y.recip()?)?.abs()?;
+        assert_eq!(fused.dtype(), dtype);
+        assert_eq!(
+            fused.to_dtype(DType::F32)?.to_vec1::<f32>()?,
+            eager.to_dtype(DType::F32)?.to_vec1::<f32>()?
+        );
+    }
+    let x = Tensor::new(&[1u32, 2, 3], dev)?;
+    let y = Tensor::new(&[4u32, 5, 6], dev)?;
+    let fused = x.lazy().mul(&y.lazy())?.affine(2., 1.)?.realize()?;
+    assert_eq!(fused.to_vec1::<u32>()?, [9, 21, 37]);
+    assert!(x.lazy().exp().is_err());
+    Ok(())
+}
+
+fn lazy_grad(dev: &Device) -> Result<()> {
+    let x = Var::new(&[1f32, 2., 3.], dev)?;
+    let y = Tensor::new(&[2f32, 0., -1.], dev)?;
+    let z = x.lazy().sqr()?.mul(&y.lazy())?;
+    assert!(!z.is_fusable());
+    assert_eq!(z.fused_op_count(), 0);
+    let z = z.realize()?;
+    let grads = z.sum_all()?.backward()?;
+    let grad_x = grads.get(&x).unwrap();
+    assert_eq!(grad_x.to_vec1::<f32>()?, [4., 0., -6.]);
+    Ok(())
+}
+
+test_device!(
+    lazy_chain,
+    lazy_chain_cpu,
+    lazy_chain_gpu,
+    lazy_chain_metal,
+    lazy_chain_wgpu
+);
+test_device!(
+    lazy_broadcast,
+    lazy_broadcast_cpu,
+    lazy_broadcast_gpu,
+    lazy_broadcast_metal,
+    lazy_broadcast_wgpu
+);
+test_device!(
+    lazy_shared_and_strided,
+    lazy_shared_and_strided_cpu,
+    lazy_shared_and_strided_gpu,
+    lazy_shared_and_strided_metal,
+    lazy_shared_and_strided_wgpu
+);
+test_device!(
+    lazy_dtypes,
+    lazy_dtypes_cpu,
+    lazy_dtypes_gpu,
+    lazy_dtypes_metal,
+    lazy_dtypes_wgpu
+);
+test_device!(
+    lazy_grad,
+    lazy_grad_cpu,
+    lazy_grad_gpu,
+    lazy_grad_metal,
+    lazy_grad_wgpu
+);
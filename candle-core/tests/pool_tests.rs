@@ -1,4 +1,4 @@
-use candle_core::{test_device, test_utils, Device, IndexOp, Result, Tensor};
+use candle_core::{test_device, test_utils, DType, Device, IndexOp, Result, Tensor};
 
 // https://github.com/huggingface/candle/issues/364
 fn avg_pool2d(dev: &Device) -> Result<()> {
@@ -98,17 +98,101 @@ fn upsample_nearest2d(dev: &Device) -> Result<()> {
     Ok(())
 }
 
-test_device!(avg_pool2d, avg_pool2d_cpu, avg_pool2d_gpu, avg_pool2d_metal);
+fn avg_pool3d(dev: &Device) -> Result<()> {
+    let t = Tensor::arange(0f32, 64., dev)?.reshape((1, 1, 4, 4, 4))?;
+    let pool = t.avg_pool3d(2)?.squeeze(0)?.squeeze(0)?;
+    assert_eq!(
+        pool.to_vec3::<f32>()?,
+        [[[10.5, 12.5], [18.5, 20.5]], [[42.5, 44.5], [50.5, 52.5]]]
+    );
+    for dtype in [DType::F16, DType::BF16] {
+        let pool = t.to_dtype(dtype)?.avg_pool3d(2)?.to_dtype(DType::F32)?;
+        assert_eq!(
+            pool.flatten_all()?.to_vec1::<f32>()?,
+            [10.5, 12.5, 18.5, 20.5, 42.5, 44.5, 50.5, 52.5]
+        );
+    }
+
+    // Each depth slice of a pool with a depth-1 kernel matches the 2d pool.
+    let t = Tensor::rand(0f32, 1., (2, 3, 4, 7, 6), dev)?;
+    let pool = t.avg_pool3d_with_stride((1, 3, 2), (2, 2, 1))?;
+    assert_eq!(pool.dims(), [2, 3, 2, 3, 5]);
+    for z in 0..2 {
+        let exp = t
+            .i((.., .., 2 * z))?
+            .avg_pool2d_with_stride((3, 2), (2, 1))?;
+        let diff = (pool.i((.., .., z))? - exp)?.abs()?.flatten_all()?.max(0)?;
+        assert!(diff.to_scalar::<f32>()? < 1e-6);
+    }
+    Ok(())
+}
+
+fn max_pool3d(dev: &Device) -> Result<()> {
+    let t = Tensor::arange(0f32, 64., dev)?.reshape((1, 1, 4, 4, 4))?;
+    let pool = t.max_pool3d(2)?.squeeze(0)?.squeeze(0)?;
+    assert_eq!(
+        pool.to_vec3::<f32>()?,
+        [[[21., 23.], [29., 31.]], [[53., 55.], [61., 63.]]]
+    );
+    for dtype in [DType::F16, DType::BF16] {
+        let pool = t.to_dtype(dtype)?.max_pool3d(2)?.to_dtype(DType::F32)?;
+        assert_eq!(
+            pool.flatten_all()?.to_vec1::<f32>()?,
+            [21., 23., 29., 31., 53., 55., 61., 63.]
+        );
+    }
+    let pool = t.max_pool3d_with_stride((3, 2, 2), (1, 2, 1))?;
+    assert_eq!(pool.dims(), [1, 1, 2, 2, 3]);
+    assert_eq!(
+        pool.squeeze(0)?.squeeze(0)?.to_vec3::<f32>()?,
+        [
+            [[37., 38., 39.], [45., 46., 47.]],
+            [[53., 54., 55.], [61., 62., 63.]]
+        ]
+    );
+    assert!(t.max_pool3d(5).is_err());
+    Ok(())
+}
+
+test_device!(
+    avg_pool2d,
+    avg_pool2d_cpu,
+    avg_pool2d_gpu,
+    avg_pool2d_metal,
+    avg_pool2d_wgpu
+);
 test_device!(
     avg_pool2d_pytorch,
     avg_pool2d_pytorch_cpu,
     avg_pool2d_pytorch_gpu,
-    avg_pool2d_pytorch_metal
+    avg_pool2d_pytorch_metal,
+    avg_pool2d_pytorch_wgpu
+);
+test_device!(
+    max_pool2d,
+    max_pool2d_cpu,
+    max_pool2d_gpu,
+    max_pool2d_metal,
+    max_pool2d_wgpu
 );
-test_device!(max_pool2d, max_pool2d_cpu, max_pool2d_gpu, max_pool2d_metal);
 test_device!(
     upsample_nearest2d,
     upsample_nearest2d_cpu,
     upsample_nearest2d_gpu,
-    upsample_nearest2d_metal
+    upsample_nearest2d_metal,
+    upsample_nearest2d_wgpu
+);
+test_device!(
+    avg_pool3d,
+    avg_pool3d_cpu,
+    avg_pool3d_gpu,
+    avg_pool3d_metal,
+    avg_pool3d_wgpu
+);
+test_device!(
+    max_pool3d,
+    max_pool3d_cpu,
+    max_pool3d_gpu,
+    max_pool3d_metal,
+    max_pool3d_wgpu
 );
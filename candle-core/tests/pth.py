@@ -35,3 +35,20 @@ print("Tensor stride:", tensor_fortran.stride())  # Stride will reflect the Fort
 torch.save({"tensor_fortran": tensor_fortran}, 'fortran_tensor_3d.pth')
 
 print("3D Tensor saved with Fortran layout.")
+
+############################################################################################################
+# Tensors sharing a storage with offsets and non-contiguous strides, nested in an OrderedDict, together
+# with bool and bf16 tensors, saved with a more recent pickle protocol.
+base = torch.arange(12, dtype=torch.float32)
+layer = torch.nn.Module()
+layer.register_buffer("view", base[2:8].view(2, 3))
+layer.register_buffer("strided", base.view(3, 4)[:, ::2])
+layer.register_buffer("transposed", base.view(3, 4).t())
+obj = {
+    "layer": layer.state_dict(keep_vars=True),
+    "mask": torch.tensor([True, False, True]),
+    "bf16": torch.tensor([1.5, -2.0], dtype=torch.bfloat16),
+    "epoch": 2**40,
+    "tag": b"xyz",
+}
+torch.save(obj, "test_strided.pt", pickle_protocol=4)
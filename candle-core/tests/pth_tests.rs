@@ -29,3 +29,76 @@ fn test_pth_fortran_congiguous() {
         ]
     );
 }
+
+#[test]
+fn test_pth_write_read() {
+    use candle_core::{DType, Device, Tensor};
+    let dev = &Device::Cpu;
+    let a = Tensor::arange(0f32, 6f32, dev)
+        .unwrap()
+        .reshape((2, 3))
+        .unwrap();
+    let tensors = vec![
+        ("a".to_string(), a.t().unwrap()),
+        ("b".to_string(), Tensor::new(&[1u8, 0, 1], dev).unwrap()),
+        ("c".to_string(), Tensor::new(70000i64, dev).unwrap()),
+        ("d".to_string(), a.to_dtype(DType::BF16).unwrap()),
+    ];
+    let path = std::env::temp_dir().join("candle_write_read.pt");
+    candle_core::pickle::write_pth(&path, &tensors).unwrap();
+    let pth = candle_core::pickle::PthTensors::new(&path, None).unwrap();
+    assert_eq!(pth.tensor_infos().len(), 4);
+    let a = pth.get("a").unwrap().unwrap();
+    assert_eq!(a.to_vec2::<f32>().unwrap(), [[0., 3.], [1., 4.], [2., 5.]]);
+    let b = pth.get("b").unwrap().unwrap();
+    assert_eq!(b.to_vec1::<u8>().unwrap(), [1, 0, 1]);
+    let c = pth.get("c").unwrap().unwrap();
+    assert_eq!(c.to_scalar::<i64>().unwrap(), 70000);
+    let d = pth.get("d").unwrap().unwrap();
+    assert_eq!(d.dtype(), DType::BF16);
+    assert_eq!(
+        d.to_dtype(DType::F32).unwrap().to_vec2::<f32>().unwrap(),
+        [[0., 1., 2.], [3., 4., 5.]]
+    );
+    let u = vec![("u", Tensor::new(&[1u32], dev).unwrap())];
+    assert!(candle_core::pickle::write_pth(&path, &u).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_pth_strided_shared_storage() {
+    use candle_core::DType;
+    let tensors = candle_core::pickle::PthTensors::new("tests/test_strided.pt", None).unwrap();
+    let mut names = tensors.tensor_infos().keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "bf16",
+            "layer.strided",
+            "layer.transposed",
+            "layer.view",
+            "mask"
+        ]
+    );
+    let view = tensors.get("layer.view").unwrap().unwrap();
+    assert_eq!(view.to_vec2::<f32>().unwrap(), [[2., 3., 4.], [5., 6., 7.]]);
+    let strided = tensors.get("layer.strided").unwrap().unwrap();
+    assert_eq!(
+        strided.to_vec2::<f32>().unwrap(),
+        [[0., 2.], [4., 6.], [8., 10.]]
+    );
+    let transposed = tensors.get("layer.transposed").unwrap().unwrap();
+    assert_eq!(
+        transposed.to_vec2::<f32>().unwrap(),
+        [[0., 4., 8.], [1., 5., 9.], [2., 6., 10.], [3., 7., 11.]]
+    );
+    let mask = tensors.get("mask").unwrap().unwrap();
+    assert_eq!(mask.to_vec1::<u8>().unwrap(), [1, 0, 1]);
+    let bf16 = tensors.get("bf16").unwrap().unwrap();
+    assert_eq!(bf16.dtype(), DType::BF16);
+    assert_eq!(
+        bf16.to_dtype(DType::F32).unwrap().to_vec1::<f32>().unwrap(),
+        [1.5, -2.0]
+    );
+}
@@ -3,7 +3,7 @@ use candle_core::{
     quantized::{self, GgmlDType},
     test_device,
     test_utils::to_vec2_round,
-    Device, Module, Result, Tensor,
+    DType, Device, Module, Result, Tensor,
 };
 use quantized::{k_quants, GgmlType};
 use rand::prelude::*;
@@ -168,13 +168,15 @@ test_device!(
     quantized_matmul,
     quantized_matmul_cpu,
     quantized_matmul_cuda,
-    quantized_matmul_metal
+    quantized_matmul_metal,
+    quantized_matmul_wgpu
 );
 test_device!(
     quantized_matmul_neg,
     quantized_matmul_neg_cpu,
     quantized_matmul_neg_cuda,
-    quantized_matmul_neg_metal
+    quantized_matmul_neg_metal,
+    quantized_matmul_neg_wgpu
 );
 
 fn quantize_q4_0(device: &Device) -> Result<()> {
@@ -555,65 +557,145 @@ fn quantize_q8k(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn quantize_iq4_nl(device: &Device) -> Result<()> {
+    let dtype = GgmlDType::IQ4NL;
+    let src = get_test_vector2(0.5, 1024, device)?;
+    let quant = quantized::QTensor::quantize(&src, dtype)?;
+    let dst = quant.dequantize(device)?;
+
+    let src = src.to_vec1::<f32>()?;
+    let dst = dst.to_vec1::<f32>()?;
+    compare_with_error(dst.as_slice(), src.as_slice(), 0.017);
+
+    let dst = round_vector(&dst);
+    assert_eq!(
+        [dst[0], dst[128], dst[256], dst[512], dst[800], dst[1023]],
+        [-0.485, -0.36, -0.236, -0.0, 0.297, 0.484]
+    );
+
+    let src_big = get_test_vector2(128.0, 1024, device)?;
+    let quant_big = quantized::QTensor::quantize(&src_big, dtype)?;
+    let dst_big = quant_big.dequantize(device)?;
+
+    let src_big = src_big.to_vec1::<f32>()?;
+    let dst_big = dst_big.to_vec1::<f32>()?;
+    compare_with_error(dst_big.as_slice(), src_big.as_slice(), 4.5);
+
+    ggml_quantization_error_test(dtype, device, GGML_MAX_QUANTIZATION_TOTAL_ERROR)?;
+    Ok(())
+}
+
+fn quantize_iq4_xs(device: &Device) -> Result<()> {
+    let dtype = GgmlDType::IQ4XS;
+    let src = get_test_vector2(0.5, 1024, device)?;
+    let quant = quantized::QTensor::quantize(&src, dtype)?;
+    let dst = quant.dequantize(device)?;
+
+    let src = src.to_vec1::<f32>()?;
+    let dst = dst.to_vec1::<f32>()?;
+    compare_with_error(dst.as_slice(), src.as_slice(), 0.025);
+
+    let dst = round_vector(&dst);
+    assert_eq!(
+        [dst[0], dst[128], dst[256], dst[512], dst[800], dst[1023]],
+        [-0.485, -0.364, -0.236, -0.0, 0.303, 0.484]
+    );
+
+    let src_big = get_test_vector2(128.0, 1024, device)?;
+    let quant_big = quantized::QTensor::quantize(&src_big, dtype)?;
+    let dst_big = quant_big.dequantize(device)?;
+
+    let src_big = src_big.to_vec1::<f32>()?;
+    let dst_big = dst_big.to_vec1::<f32>()?;
+    compare_with_error(dst_big.as_slice(), src_big.as_slice(), 6.5);
+
+    ggml_quantization_error_test(dtype, device, GGML_MAX_QUANTIZATION_TOTAL_ERROR)?;
+    Ok(())
+}
+
 test_device!(
     quantize_q4_0,
     quantize_q4_0_cpu,
     quantize_q4_0_cuda,
-    quantize_q4_0_metal
+    quantize_q4_0_metal,
+    quantize_q4_0_wgpu
 );
 test_device!(
     quantize_q4_1,
     quantize_q4_1_cpu,
     quantize_q4_1_cuda,
-    quantize_q4_1_metal
+    quantize_q4_1_metal,
+    quantize_q4_1_wgpu
 );
 test_device!(
     quantize_q5_0,
     quantize_q5_0_cpu,
     quantize_q5_0_cuda,
-    quantize_q5_0_metal
+    quantize_q5_0_metal,
+    quantize_q5_0_wgpu
 );
 test_device!(
     quantize_q5_1,
     quantize_q5_1_cpu,
     quantize_q5_1_cuda,
-    quantize_q5_1_metal
+    quantize_q5_1_metal,
+    quantize_q5_1_wgpu
 );
 test_device!(
     quantize_q2k,
     quantize_q2k_cpu,
     quantize_q2k_cuda,
-    quantize_q2k_metal
+    quantize_q2k_metal,
+    quantize_q2k_wgpu
 );
 test_device!(
     quantize_q3k,
     quantize_q3k_cpu,
     quantize_q3k_cuda,
-    quantize_q3k_metal
+    quantize_q3k_metal,
+    quantize_q3k_wgpu
 );
 test_device!(
     quantize_q4k,
     quantize_q4k_cpu,
     quantize_q4k_cuda,
-    quantize_q4k_metal
+    quantize_q4k_metal,
+    quantize_q4k_wgpu
 );
 test_device!(
     quantize_q5k,
     quantize_q5k_cpu,
     quantize_q5k_cuda,
-    quantize_q5k_metal
+    quantize_q5k_metal,
+    quantize_q5k_wgpu
 );
 test_device!(
     quantize_q6k,
     quantize_q6k_cpu,
     quantize_q6k_cuda,
-    quantize_q6k_metal
+    quantize_q6k_metal,
+    quantize_q6k_wgpu
 );
 test_device!(
     quantize_q8k,
     quantize_q8k_cpu,
     quantize_q8k_cuda,
-    quantize_q8k_metal
+    quantize_q8k_metal,
+    quantize_q8k_wgpu
+);
+test_device!(
+    quantize_iq4_nl,
+    quantize_iq4_nl_cpu,
+    quantize_iq4_nl_cuda,
+    quantize_iq4_nl_metal,
+    quantize_iq4_nl_wgpu
+);
+test_device!(
+    quantize_iq4_xs,
+    quantize_iq4_xs_cpu,
+    quantize_iq4_xs_cuda,
+    quantize_iq4_xs_metal,
+    quantize_iq4_xs_wgpu
 );
 
 /// Very simple dot product implementation
@@ -638,6 +720,8 @@ fn ggml_reference_matmul_error(dtype: GgmlDType) -> Result<f32> {
 
         // Not from the ggml repo.
         GgmlDType::Q8K => 0.00065,
+        GgmlDType::IQ4NL => 0.002716,
+        GgmlDType::IQ4XS => 0.001904,
         _ => bail!("No GGML results for quantization type {dtype:?}",),
     };
     Ok(err)
@@ -736,13 +820,19 @@ fn get_random_tensors(
 macro_rules! quantized_matmul {
     // TODO: Switch to generating the two last arguments automatically once concat_idents is
     // stable. https://github.com/rust-lang/rust/issues/29599
-    ($fn_name: ident, $fn_name_cpu: ident, $fn_name_cuda: ident, $fn_name_metal: ident, $dtype: expr) => {
+    ($fn_name: ident, $fn_name_cpu: ident, $fn_name_cuda: ident, $fn_name_metal: ident, $fn_name_wgpu: ident, $dtype: expr) => {
         fn $fn_name(device: &Device) -> Result<()> {
             test_matmul(device, (1, 3, 4, 256), $dtype)?;
             Ok(())
         }
 
-        test_device!($fn_name, $fn_name_cpu, $fn_name_cuda, $fn_name_metal);
+        test_device!(
+            $fn_name,
+            $fn_name_cpu,
+            $fn_name_cuda,
+            $fn_name_metal,
+            $fn_name_wgpu
+        );
     };
 }
 
@@ -751,6 +841,7 @@ quantized_matmul!(
     quantized_matmul_q4_0_cpu,
     quantized_matmul_q4_0_cuda,
     quantized_matmul_q4_0_metal,
+    quantized_matmul_q4_0_wgpu,
     GgmlDType::Q4_0
 );
 quantized_matmul!(
@@ -758,6 +849,7 @@ quantized_matmul!(
     quantized_matmul_q4_1_cpu,
     quantized_matmul_q4_1_cuda,
     quantized_matmul_q4_1_metal,
+    quantized_matmul_q4_1_wgpu,
     GgmlDType::Q4_1
 );
 quantized_matmul!(
@@ -765,6 +857,7 @@ quantized_matmul!(
     quantized_matmul_q5_0_cpu,
     quantized_matmul_q5_0_cuda,
     quantized_matmul_q5_0_metal,
+    quantized_matmul_q5_0_wgpu,
     GgmlDType::Q5_0
 );
 quantized_matmul!(
@@ -772,6 +865,7 @@ quantized_matmul!(
     quantized_matmul_q5_1_cpu,
     quantized_matmul_q5_1_cuda,
     quantized_matmul_q5_1_metal,
+    quantized_matmul_q5_1_wgpu,
     GgmlDType::Q5_1
 );
 quantized_matmul!(
@@ -779,6 +873,7 @@ quantized_matmul!(
     quantized_matmul_q8_0_cpu,
     quantized_matmul_q8_0_cuda,
     quantized_matmul_q8_0_metal,
+    quantized_matmul_q8_0_wgpu,
     GgmlDType::Q8_0
 );
 // Not implemented in Ggml
@@ -787,6 +882,7 @@ quantized_matmul!(
 //     quantized_matmul_q8_1_cpu,
 //     quantized_matmul_q8_1_cuda,
 //     quantized_matmul_q8_1_metal,
+//     quantized_matmul_q8_1_wgpu,
 //     GgmlDType::Q8_1
 // );
 // TODO This is bugged (also bugged in GGML
@@ -795,6 +891,7 @@ quantized_matmul!(
     quantized_matmul_q2k_cpu,
     quantized_matmul_q2k_cuda,
     quantized_matmul_q2k_metal,
+    quantized_matmul_q2k_wgpu,
     GgmlDType::Q2K
 );
 quantized_matmul!(
@@ -802,6 +899,7 @@ quantized_matmul!(
     quantized_matmul_q3k_cpu,
     quantized_matmul_q3k_cuda,
     quantized_matmul_q3k_metal,
+    quantized_matmul_q3k_wgpu,
     GgmlDType::Q3K
 );
 quantized_matmul!(
@@ -809,6 +907,7 @@ quantized_matmul!(
     quantized_matmul_q4k_cpu,
     quantized_matmul_q4k_cuda,
     quantized_matmul_q4k_metal,
+    quantized_matmul_q4k_wgpu,
     GgmlDType::Q4K
 );
 quantized_matmul!(
@@ -816,6 +915,7 @@ quantized_matmul!(
     quantized_matmul_q5k_cpu,
     quantized_matmul_q5k_cuda,
     quantized_matmul_q5k_metal,
+    quantized_matmul_q5k_wgpu,
     GgmlDType::Q5K
 );
 quantized_matmul!(
@@ -823,14 +923,48 @@ quantized_matmul!(
     quantized_matmul_q6k_cpu,
     quantized_matmul_q6k_cuda,
     quantized_matmul_q6k_metal,
+    quantized_matmul_q6k_wgpu,
     GgmlDType::Q6K
 );
+quantized_matmul!(
+    quantized_matmul_iq4nl_bis,
+    quantized_matmul_iq4nl_cpu,
+    quantized_matmul_iq4nl_cuda,
+    quantized_matmul_iq4nl_metal,
+    quantized_matmul_iq4nl_wgpu,
+    GgmlDType::IQ4NL
+);
+quantized_matmul!(
+    quantized_matmul_iq4xs_bis,
+    quantized_matmul_iq4xs_cpu,
+    quantized_matmul_iq4xs_cuda,
+    quantized_matmul_iq4xs_metal,
+    quantized_matmul_iq4xs_wgpu,
+    GgmlDType::IQ4XS
+);
+
+// A single row lhs goes through the fused dequantize-mul-mat-vec kernels on cuda.
+fn quantized_matmul_vec_iq4(device: &Device) -> Result<()> {
+    test_matmul(device, (1, 1, 4, 512), GgmlDType::IQ4NL)?;
+    test_matmul(device, (1, 1, 4, 512), GgmlDType::IQ4XS)?;
+    Ok(())
+}
+
+test_device!(
+    quantized_matmul_vec_iq4,
+    quantized_matmul_vec_iq4_cpu,
+    quantized_matmul_vec_iq4_cuda,
+    quantized_matmul_vec_iq4_metal,
+    quantized_matmul_vec_iq4_wgpu
+);
+
 // Not implemented on metal
 // quantized_matmul!(
 //     quantized_matmul_q8k_bis,
 //     quantized_matmul_q8k_cpu,
 //     quantized_matmul_q8k_cuda,
 //     quantized_matmul_q8k_metal,
+//     quantized_matmul_q8k_wgpu,
 //     GgmlDType::Q8K
 // );
 
@@ -988,3 +1122,288 @@ fn quantized_matmul_q8k() -> Result<()> {
     ggml_matmul_error_test::<BlockQ8K>()?;
     Ok(())
 }
+
+#[test]
+fn quantized_matmul_iq4nl() -> Result<()> {
+    use k_quants::BlockIQ4NL;
+
+    let cpu = &Device::Cpu;
+    let (m, k, n) = (11, 512, 21);
+    let (lhs, rhs, mm) = get_random_tensors(m, k, n, cpu)?;
+    assert_eq!(mm.dims(), [m, n]);
+    let dst = mm.flatten_all()?.to_vec1::<f32>()?;
+    let dst = round_vector(&[dst[0], dst[m * n / 3], dst[m * n * 2 / 3], dst[m * n - 1]]);
+    assert_eq!(dst, [1.262, 1.513, -0.208, 1.702]);
+
+    let rhs = quantized::QTensor::quantize(&rhs, GgmlDType::IQ4NL)?;
+    let rhs = quantized::QMatMul::from_qtensor(rhs)?;
+    let mm = rhs.forward(&lhs)?;
+
+    assert_eq!(mm.dims(), [m, n]);
+    let dst = mm.flatten_all()?.to_vec1::<f32>()?;
+    let dst = round_vector(&[dst[0], dst[m * n / 3], dst[m * n * 2 / 3], dst[m * n - 1]]);
+    assert_eq!(dst, [1.432, 1.469, -0.312, 1.602]);
+
+    ggml_matmul_error_test::<BlockIQ4NL>()?;
+    Ok(())
+}
+
+#[test]
+fn quantized_matmul_iq4xs() -> Result<()> {
+    use k_quants::BlockIQ4XS;
+
+    let cpu = &Device::Cpu;
+    let (m, k, n) = (11, 512, 21);
+    let (lhs, rhs, mm) = get_random_tensors(m, k, n, cpu)?;
+    assert_eq!(mm.dims(), [m, n]);
+    let dst = mm.flatten_all()?.to_vec1::<f32>()?;
+    let dst = round_vector(&[dst[0], dst[m * n / 3], dst[m * n * 2 / 3], dst[m * n - 1]]);
+    assert_eq!(dst, [1.262, 1.513, -0.208, 1.702]);
+
+    let rhs = quantized::QTensor::quantize(&rhs, GgmlDType::IQ4XS)?;
+    let rhs = quantized::QMatMul::from_qtensor(rhs)?;
+    let mm = rhs.forward(&lhs)?;
+
+    assert_eq!(mm.dims(), [m, n]);
+    let dst = mm.flatten_all()?.to_vec1::<f32>()?;
+    let dst = round_vector(&[dst[0], dst[m * n / 3], dst[m * n * 2 / 3], dst[m * n - 1]]);
+    assert_eq!(dst, [1.442, 1.509, -0.293, 1.631]);
+
+    ggml_matmul_error_test::<BlockIQ4XS>()?;
+    Ok(())
+}
+
+#[test]
+fn gguf_mmaped() -> Result<()> {
+    use quantized::gguf_file;
+    let cpu = &Device::Cpu;
+    let src = Tensor::randn(0f32, 1f32, (4, 256), cpu)?;
+    let q4k = quantized::QTensor::quantize(&src, GgmlDType::Q4K)?;
+    let f32 = quantized::QTensor::quantize(&src, GgmlDType::F32)?;
+    let path = std::env::temp_dir().join("candle-gguf-mmaped.gguf");
+    {
+        let mut file = std::fs::File::create(&path)?;
+        gguf_file::write(&mut file, &[], &[("q4k", &q4k), ("f32", &f32)])?;
+    }
+    let mut file = std::fs::File::open(&path)?;
+    let content = gguf_file::Content::read(&mut file)?;
+    let mmap = std::sync::Arc::new(unsafe { memmap2::MmapOptions::new().map(&file)? });
+    for name in ["q4k", "f32"] {
+        let read = content.tensor(&mut file, name, cpu)?;
+        let mmaped = unsafe { content.tensor_mmaped(&mmap, name, cpu)? };
+        assert!(mmaped.is_mapped());
+        assert!(!read.is_mapped());
+        assert_eq!(mmaped.dtype(), read.dtype());
+        assert_eq!(mmaped.shape(), read.shape());
+        assert_eq!(
+            mmaped.dequantize(cpu)?.to_vec2::<f32>()?,
+            read.dequantize(cpu)?.to_vec2::<f32>()?
+        );
+    }
+    assert!(unsafe { content.tensor_mmaped(&mmap, "missing", cpu) }.is_err());
+    drop(mmap);
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn gguf_iquants() -> Result<()> {
+    use quantized::gguf_file;
+    let cpu = &Device::Cpu;
+    let src = Tensor::randn(0f32, 1f32, (4, 256), cpu)?;
+    let iq4nl = quantized::QTensor::quantize(&src, GgmlDType::IQ4NL)?;
+    let iq4xs = quantized::QTensor::quantize(&src, GgmlDType::IQ4XS)?;
+    let mut bytes = std::io::Cursor::new(vec![]);
+    gguf_file::write(&mut bytes, &[], &[("iq4nl", &iq4nl), ("iq4xs", &iq4xs)])?;
+    bytes.set_position(0);
+    let content = gguf_file::Content::read(&mut bytes)?;
+    for (name, expected) in [("iq4nl", &iq4nl), ("iq4xs", &iq4xs)] {
+        let read = content.tensor(&mut bytes, name, cpu)?;
+        assert_eq!(read.dtype(), expected.dtype());
+        assert_eq!(
+            read.dequantize(cpu)?.to_vec2::<f32>()?,
+            expected.dequantize(cpu)?.to_vec2::<f32>()?
+        );
+    }
+
+    // The lattice based i-quants are reported as unsupported rather than unknown.
+    let mut bytes = bytes.into_inner();
+    let pos = bytes.windows(5).position(|w| w == b"iq4nl").unwrap();
+    // name, n_dims (u32) and two dims (u64) come before the dtype.
+    let dtype_pos = pos + 5 + 4 + 2 * 8;
+    bytes[dtype_pos..dtype_pos + 4].copy_from_slice(&16u32.to_le_bytes());
+    let err = gguf_file::Content::read(&mut std::io::Cursor::new(bytes)).unwrap_err();
+    assert!(err.to_string().contains("IQ2_XXS"), "{err}");
+    Ok(())
+}
+
+#[test]
+fn gguf_builder() -> Result<()> {
+    use quantized::gguf_file::{self, Value};
+    let cpu = &Device::Cpu;
+    let src = Tensor::randn(0f32, 1f32, (3, 256), cpu)?;
+    let norm = Tensor::randn(0f32, 1f32, 256, cpu)?;
+    let mut builder = gguf_file::Builder::new();
+    builder
+        .set_metadata("general.architecture", Value::String("llama".to_string()))?
+        .set_metadata("llama.block_count", Value::U32(1))?
+        .set_metadata("llama.block_count", Value::U32(2))?
+        .set_metadata(
+            "tokenizer.ggml.tokens",
+            Value::Array(vec![Value::String("a".to_string()); 3]),
+        )?
+        .set_metadata("general.alignment", Value::U32(64))?
+        .add_tensor(
+            "norm.weight",
+            quantized::QTensor::quantize(&norm, GgmlDType::F32)?,
+        )?
+        .add_tensor(
+            "q.weight",
+            quantized::QTensor::quantize(&src, GgmlDType::Q4K)?,
+        )?;
+    assert_eq!(builder.alignment(), 64);
+    assert_eq!(builder.metadata().len(), 3);
+    assert!(builder.set_alignment(12).is_err());
+    let q = quantized::QTensor::quantize(&src, GgmlDType::Q4K)?;
+    assert!(builder.add_tensor("q.weight", q).is_err());
+    let q = quantized::QTensor::quantize(&src.reshape((1, 1, 1, 3, 256))?, GgmlDType::F32)?;
+    assert!(builder.add_tensor("rank5", q).is_err());
+
+    let mut file = std::io::Cursor::new(vec![]);
+    builder.write(&mut file)?;
+    file.set_position(0);
+    let content = gguf_file::Content::read(&mut file)?;
+    assert_eq!(content.metadata.len(), 4);
+    assert_eq!(content.metadata["general.alignment"].to_u32()?, 64);
+    assert_eq!(content.metadata["llama.block_count"].to_u32()?, 2);
+    assert_eq!(
+        content.metadata["general.architecture"].to_string()?,
+        "llama"
+    );
+    assert_eq!(content.metadata["tokenizer.ggml.tokens"].to_vec()?.len(), 3);
+    assert_eq!(content.tensor_data_offset % 64, 0);
+    for (name, info) in content.tensor_infos.iter() {
+        assert_eq!(info.offset % 64, 0, "{name}");
+    }
+    let q = content.tensor(&mut file, "q.weight", cpu)?;
+    assert_eq!(q.dtype(), GgmlDType::Q4K);
+    assert_eq!(
+        q.dequantize(cpu)?.to_vec2::<f32>()?,
+        builder.tensors()[1].1.dequantize(cpu)?.to_vec2::<f32>()?
+    );
+    let norm_read = content.tensor(&mut file, "norm.weight", cpu)?;
+    assert_eq!(
+        norm_read.dequantize(cpu)?.to_vec1::<f32>()?,
+        norm.to_vec1::<f32>()?
+    );
+    Ok(())
+}
+
+#[test]
+fn quantize_with_imatrix() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let (rows, cols) = (8, 512);
+    let src = Tensor::randn(0f32, 1f32, (rows, cols), cpu)?;
+    // Put most of the importance on a handful of columns.
+    let imatrix: Vec<f32> = (0..cols)
+        .map(|i| if i % 16 == 0 { 100. } else { 0.01 })
+        .collect();
+    let src_v = src.flatten_all()?.to_vec1::<f32>()?;
+    let weighted_err = |q: &quantized::QTensor| -> Result<f32> {
+        let d = q.dequantize(cpu)?.flatten_all()?.to_vec1::<f32>()?;
+        let err = src_v
+            .iter()
+            .zip(d.iter())
+            .enumerate()
+            .map(|(i, (s, d))| imatrix[i % cols] * (s - d) * (s - d))
+            .sum();
+        Ok(err)
+    };
+    for dtype in [
+        GgmlDType::Q2K,
+        GgmlDType::Q3K,
+        GgmlDType::Q4K,
+        GgmlDType::Q5K,
+        GgmlDType::Q6K,
+        GgmlDType::IQ4NL,
+        GgmlDType::IQ4XS,
+    ] {
+        let plain = quantized::QTensor::quantize(&src, dtype)?;
+        let weighted = quantized::QTensor::quantize_with_imatrix(&src, dtype, &imatrix)?;
+        assert_eq!(weighted.shape(), plain.shape());
+        let (plain_err, weighted_err) = (weighted_err(&plain)?, weighted_err(&weighted)?);
+        assert!(
+            weighted_err < plain_err,
+            "{dtype:?} plain {plain_err} imatrix {weighted_err}"
+        );
+    }
+
+    // Types without a weighted search are quantized as usual.
+    let plain = quantized::QTensor::quantize(&src, GgmlDType::Q8_0)?;
+    let weighted = quantized::QTensor::quantize_with_imatrix(&src, GgmlDType::Q8_0, &imatrix)?;
+    assert_eq!(plain.data()?, weighted.data()?);
+
+    // The weights must match the row size.
+    let res = quantized::QTensor::quantize_with_imatrix(&src, GgmlDType::Q4K, &imatrix[..256]);
+    assert!(res.is_err());
+    Ok(())
+}
+
+#[test]
+fn quantization_error() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let src = Tensor::new(&[[1f32, -2., 3., 0.5]; 8], cpu)?.reshape((1, 32))?;
+    let q = quantized::QTensor::quantize(&src, GgmlDType::F32)?;
+    let err = q.quantization_error(&src)?;
+    assert_eq!(err.rmse, 0.);
+    assert_eq!(err.max_abs_error, 0.);
+    assert!((err.rms - 1.8875).abs() < 1e-4, "{err:?}");
+
+    let src = Tensor::randn(0f32, 1f32, (4, 256), cpu)?;
+    let mut prev = 0f32;
+    for dtype in [
+        GgmlDType::Q8_0,
+        GgmlDType::Q5K,
+        GgmlDType::Q4K,
+        GgmlDType::Q2K,
+    ] {
+        let q = quantized::QTensor::quantize(&src, dtype)?;
+        let err = q.quantization_error(&src)?;
+        let diff = (q.dequantize(cpu)? - &src)?;
+        let rmse = diff.sqr()?.mean_all()?.sqrt()?.to_scalar::<f32>()?;
+        let max = diff.abs()?.max_keepdim(1)?.max(0)?.to_vec1::<f32>()?[0];
+        assert!((err.rmse - rmse).abs() < 1e-5, "{dtype:?} {err:?} {rmse}");
+        assert!(
+            (err.max_abs_error - max).abs() < 1e-6,
+            "{dtype:?} {err:?} {max}"
+        );
+        assert!(err.max_abs_error >= err.rmse);
+        // Fewer bits mean larger errors.
+        assert!(err.relative_rmse() > prev, "{dtype:?} {err:?}");
+        prev = err.relative_rmse();
+    }
+    let q = quantized::QTensor::quantize(&src, GgmlDType::Q8_0)?;
+    assert!(q.quantization_error(&src.t()?).is_err());
+    Ok(())
+}
+
+#[test]
+fn imatrix_file() -> Result<()> {
+    use quantized::imatrix_file;
+    let a = [1f32, 2., 3., 4.];
+    let b = [0.5f32; 32];
+    let mut bytes = std::io::Cursor::new(vec![]);
+    imatrix_file::write(
+        &mut bytes,
+        &[("blk.0.attn_q.weight", &a), ("output.weight", &b)],
+    )?;
+    let mut bytes = bytes.into_inner();
+    // Bump the call count of the first entry, the values get averaged on load.
+    let ncall_pos = 4 + 4 + "blk.0.attn_q.weight".len();
+    bytes[ncall_pos..ncall_pos + 4].copy_from_slice(&2i32.to_le_bytes());
+    let imatrix = imatrix_file::read(&mut std::io::Cursor::new(bytes))?;
+    assert_eq!(imatrix.len(), 2);
+    assert_eq!(imatrix["blk.0.attn_q.weight"], [0.5, 1., 1.5, 2.]);
+    assert_eq!(imatrix["output.weight"], b);
+    Ok(())
+}
@@ -0,0 +1,295 @@
+use candle_core::{
+    test_device, test_utils, Device, GridSampleMode, GridSamplePadding, IndexOp, InterpolateMode,
+    Result, Tensor, Var,
+};
+
+// A (1, 1, 4, 5) image, the expected values in the tests below match the PyTorch implementation.
+fn image(dev: &Device) -> Result<Tensor> {
+    let data = (0..4)
+        .flat_map(|i| (0..5).map(move |j| ((i * 7 + j * 3) % 5) as f32 + 0.5 * i as f32))
+        .collect::<Vec<_>>();
+    Tensor::from_vec(data, (1, 1, 4, 5), dev)
+}
+
+fn interpolate(
+    xs: &Tensor,
+    h: usize,
+    w: usize,
+    mode: InterpolateMode,
+    align_corners: bool,
+) -> Result<Vec<Vec<f32>>> {
+    let ys = xs.interpolate2d_with_mode(h, w, mode, align_corners)?;
+    assert_eq!(ys.dims(), &[1, 1, h, w]);
+    test_utils::to_vec2_round(&ys.i((0, 0))?, 4)
+}
+
+fn interpolate_bilinear(dev: &Device) -> Result<()> {
+    let xs = image(dev)?;
+    assert_eq!(
+        interpolate(&xs, 3, 7, InterpolateMode::Bilinear, false)?,
+        [
+            [0.4167, 1.6548, 2.25, 1.4167, 2.9643, 3.0833, 2.4167],
+            [3.75, 2.6071, 1.8929, 2.25, 2.6071, 2.9643, 3.25],
+            [2.9167, 4.1548, 4.5119, 3.0833, 2.25, 2.8452, 4.0833]
+        ]
+    );
+    assert_eq!(
+        interpolate(&xs, 6, 3, InterpolateMode::Bilinear, true)?,
+        [
+            [0.0, 1.0, 2.0],
+            [1.5, 2.5, 3.5],
+            [3.0, 3.0, 4.0],
+            [4.5, 1.5, 2.5],
+            [4.0, 2.0, 3.0],
+            [2.5, 3.5, 4.5]
+        ]
+    );
+    let xs = Tensor::new(&[[[[0f32, 1.], [2., 3.]]]], dev)?;
+    assert_eq!(
+        interpolate(&xs, 4, 4, InterpolateMode::Bilinear, false)?,
+        [
+            [0.0, 0.25, 0.75, 1.0],
+            [0.5, 0.75, 1.25, 1.5],
+            [1.5, 1.75, 2.25, 2.5],
+            [2.0, 2.25, 2.75, 3.0]
+        ]
+    );
+    Ok(())
+}
+
+fn interpolate_bicubic(dev: &Device) -> Result<()> {
+    let xs = image(dev)?;
+    assert_eq!(
+        interpolate(&xs, 3, 7, InterpolateMode::Bicubic, false)?,
+        [
+            [0.1357, 1.7651, 2.4359, 1.4022, 3.0969, 3.3263, 2.3081],
+            [4.45, 2.4128, 1.1938, 2.25, 2.6058, 3.0124, 3.2894],
+            [2.7225, 4.4454, 4.9358, 3.0978, 1.8864, 2.6985, 4.2706]
+        ]
+    );
+    assert_eq!(
+        interpolate(&xs, 6, 3, InterpolateMode::Bicubic, true)?,
+        [
+            [0.0, 1.0, 2.0],
+            [1.26, 2.8, 3.8],
+            [3.24, 3.24, 4.24],
+            [4.86, 1.26, 2.26],
+            [4.3, 1.7, 2.7],
+            [2.5, 3.5, 4.5]
+        ]
+    );
+    // Nearest uses the same semantics as interpolate2d.
+    let ys = xs.interpolate2d_with_mode(2, 3, InterpolateMode::Nearest, false)?;
+    assert_eq!(
+        ys.flatten_all()?.to_vec1::<f32>()?,
+        xs.interpolate2d(2, 3)?.flatten_all()?.to_vec1::<f32>()?
+    );
+    assert!(xs
+        .interpolate2d_with_mode(2, 3, InterpolateMode::Nearest, true)
+        .is_err());
+    Ok(())
+}
+
+fn interpolate_antialias(dev: &Device) -> Result<()> {
+    let xs = Tensor::arange(0f32, 16., dev)?.reshape((1, 1, 4, 4))?;
+    assert_eq!(
+        interpolate(&xs, 2, 2, InterpolateMode::BilinearAntialias, false)?,
+        [[3.5714, 5.1429], [9.8571, 11.4286]]
+    );
+    let xs = image(dev)?;
+    assert_eq!(
+        interpolate(&xs, 2, 2, InterpolateMode::BilinearAntialias, false)?,
+        [[1.961, 2.7338], [3.3247, 2.8636]]
+    );
+    assert_eq!(
+        interpolate(&xs, 2, 3, InterpolateMode::BicubicAntialias, false)?,
+        [[1.661, 2.1791, 3.0456], [3.8241, 2.7328, 3.0357]]
+    );
+    // When upsampling, the antialiased version matches the bilinear one.
+    assert_eq!(
+        interpolate(&xs, 6, 8, InterpolateMode::BilinearAntialias, false)?,
+        interpolate(&xs, 6, 8, InterpolateMode::Bilinear, false)?,
+    );
+    Ok(())
+}
+
+fn grid_sample(dev: &Device) -> Result<()> {
+    let xs = image(dev)?;
+    let grid = Tensor::new(
+        &[[
+            [[-1.2f32, -0.9], [-0.3, 0.1], [0.45, 0.8]],
+            [[0.9, -0.5], [1.3, 1.1], [0.0, -1.4]],
+        ]],
+        dev,
+    )?;
+    let sample = |mode, padding, align_corners| -> Result<Vec<Vec<f32>>> {
+        let ys = xs.grid_sample(&grid, mode, padding, align_corners)?;
+        assert_eq!(ys.dims(), &[1, 1, 2, 3]);
+        test_utils::to_vec2_round(&ys.i((0, 0))?, 4)
+    };
+    use GridSampleMode::{Bilinear, Nearest};
+    use GridSamplePadding::{Border, Reflection, Zeros};
+    assert_eq!(
+        sample(Bilinear, Zeros, false)?,
+        [[0.0, 2.125, 1.6875], [2.4375, 0.0, 0.0]]
+    );
+    assert_eq!(
+        sample(Bilinear, Zeros, true)?,
+        [[0.225, 2.025, 2.3], [3.525, 1.53, 0.4]]
+    );
+    assert_eq!(
+        sample(Bilinear, Border, false)?,
+        [[0.0, 2.125, 1.875], [3.25, 4.5, 1.0]]
+    );
+    assert_eq!(
+        sample(Bilinear, Border, true)?,
+        [[0.375, 2.025, 2.3], [3.525, 4.5, 1.0]]
+    );
+    assert_eq!(
+        sample(Bilinear, Reflection, false)?,
+        [[0.0, 2.125, 1.875], [3.25, 3.75, 1.75]]
+    );
+    assert_eq!(
+        sample(Bilinear, Reflection, true)?,
+        [[1.275, 2.025, 2.3], [3.525, 2.775, 2.5]]
+    );
+    assert_eq!(
+        sample(Nearest, Zeros, false)?,
+        [[0.0, 3.0, 1.5], [2.0, 0.0, 0.0]]
+    );
+    assert_eq!(
+        sample(Nearest, Border, true)?,
+        [[0.0, 3.0, 1.5], [4.5, 4.5, 1.0]]
+    );
+    assert_eq!(
+        sample(Nearest, Reflection, true)?,
+        [[0.0, 3.0, 1.5], [4.5, 1.5, 3.5]]
+    );
+    Ok(())
+}
+
+fn affine_grid(dev: &Device) -> Result<()> {
+    let theta = Tensor::new(&[[[1f32, 0., 0.], [0., 1., 0.]]], dev)?;
+    let grid = Tensor::affine_grid(&theta, (1, 1, 2, 3), false)?;
+    assert_eq!(
+        test_utils::to_vec3_round(&grid.i(0)?, 4)?,
+        [
+            [[-0.6667, -0.5], [0.0, -0.5], [0.6667, -0.5]],
+            [[-0.6667, 0.5], [0.0, 0.5], [0.6667, 0.5]]
+        ]
+    );
+    let grid = Tensor::affine_grid(&theta, (1, 1, 2, 3), true)?;
+    assert_eq!(
+        test_utils::to_vec3_round(&grid.i(0)?, 4)?,
+        [
+            [[-1.0, -1.0], [0.0, -1.0], [1.0, -1.0]],
+            [[-1.0, 1.0], [0.0, 1.0], [1.0, 1.0]]
+        ]
+    );
+    // A horizontal flip combined with a translation.
+    let theta = Tensor::new(&[[[-1f32, 0., 0.5], [0., 1., 0.]]], dev)?;
+    let grid = Tensor::affine_grid(&theta, (1, 1, 1, 2), true)?;
+    assert_eq!(
+        test_utils::to_vec3_round(&grid.i(0)?, 4)?,
+        [[[1.5, 0.0], [-0.5, 0.0]]]
+    );
+    // Sampling with the identity transformation returns the input.
+    let xs = image(dev)?;
+    let theta = Tensor::new(&[[[1f32, 0., 0.], [0., 1., 0.]]], dev)?;
+    for align_corners in [false, true] {
+        let grid = Tensor::affine_grid(&theta, (1, 1, 4, 5), align_corners)?;
+        let ys = xs.grid_sample(
+            &grid,
+            GridSampleMode::Bilinear,
+            GridSamplePadding::Zeros,
+            align_corners,
+        )?;
+        assert_eq!(
+            test_utils::to_vec2_round(&ys.i((0, 0))?, 4)?,
+            xs.i((0, 0))?.to_vec2::<f32>()?
+        );
+    }
+    Ok(())
+}
+
+// The gradients on the device should match the ones computed on cpu, the grid locations
+// and channels overlap so that the atomic accumulation of the input gradients is exercised.
+fn resample_grads(dev: &Device) -> Result<()> {
+    let cpu = &Device::Cpu;
+    let xs = Tensor::rand(-1f32, 1., (2, 3, 5, 7), cpu)?;
+    let grid = Tensor::rand(-1.3f32, 1.3, (2, 6, 9, 2), cpu)?;
+    let ws = Tensor::rand(-1f32, 1., (2, 3, 6, 9), cpu)?;
+    let max_diff = |lhs: &Tensor, rhs: &Tensor| -> Result<f32> {
+        (lhs.to_device(cpu)? - rhs)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f32>()
+    };
+    let grads = |dev: &Device, mode| -> Result<Vec<Tensor>> {
+        let xs = Var::from_tensor(&xs.to_device(dev)?)?;
+        let grid = Var::from_tensor(&grid.to_device(dev)?)?;
+        let ws = ws.to_device(dev)?;
+        let ys = xs.grid_sample(&grid, mode, GridSamplePadding::Reflection, false)?;
+        let zs = xs.interpolate2d_with_mode(6, 9, InterpolateMode::BicubicAntialias, false)?;
+        let loss = ((&ys * &ws)?.sum_all()? + (&zs * &ws)?.sum_all()?)?;
+        let g = loss.backward()?;
+        Ok(vec![
+            ys,
+            zs,
+            g.get(&xs).unwrap().clone(),
+            g.get(&grid).unwrap().clone(),
+        ])
+    };
+    for mode in [GridSampleMode::Bilinear, GridSampleMode::Nearest] {
+        let expected = grads(cpu, mode)?;
+        for (v, e) in grads(dev, mode)?.iter().zip(expected.iter()) {
+            let diff = max_diff(v, e)?;
+            assert!(diff < 1e-4, "{mode:?} max diff {diff}");
+        }
+    }
+    Ok(())
+}
+
+test_device!(
+    interpolate_bilinear,
+    interpolate_bilinear_cpu,
+    interpolate_bilinear_gpu,
+    interpolate_bilinear_metal,
+    interpolate_bilinear_wgpu
+);
+test_device!(
+    interpolate_bicubic,
+    interpolate_bicubic_cpu,
+    interpolate_bicubic_gpu,
+    interpolate_bicubic_metal,
+    interpolate_bicubic_wgpu
+);
+test_device!(
+    interpolate_antialias,
+    interpolate_antialias_cpu,
+    interpolate_antialias_gpu,
+    interpolate_antialias_metal,
+    interpolate_antialias_wgpu
+);
+test_device!(
+    grid_sample,
+    grid_sample_cpu,
+    grid_sample_gpu,
+    grid_sample_metal,
+    grid_sample_wgpu
+);
+test_device!(
+    affine_grid,
+    affine_grid_cpu,
+    affine_grid_gpu,
+    affine_grid_metal,
+    affine_grid_wgpu
+);
+test_device!(
+    resample_grads,
+    resample_grads_cpu,
+    resample_grads_gpu,
+    resample_grads_metal,
+    resample_grads_wgpu
+);
@@ -1,4 +1,7 @@
-use candle_core::{DType, Result, Tensor};
+use candle_core::{
+    quantized::{self, GgmlDType},
+    DType, Device, Result, Tensor,
+};
 
 #[test]
 fn npy() -> Result<()> {
@@ -22,3 +25,22 @@ fn npz() -> Result<()> {
     );
     Ok(())
 }
+
+#[test]
+fn safetensors_write_qtensor() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let src = Tensor::randn(0f32, 1f32, (2, 256), cpu)?;
+    let q = quantized::QTensor::quantize(&src, GgmlDType::Q8_0)?;
+    let headers = [("q", DType::F16, q.shape().clone())];
+    let mut writer = candle_core::safetensors::Writer::new(vec![], &headers)?;
+    writer.write_qtensor("q", &q)?;
+    let bytes = writer.finish()?;
+    let weights = candle_core::safetensors::load_buffer(&bytes, cpu)?;
+    let expected = q.dequantize(cpu)?.to_dtype(DType::F16)?;
+    assert_eq!(weights["q"].dtype(), DType::F16);
+    assert_eq!(
+        weights["q"].to_vec2::<half::f16>()?,
+        expected.to_vec2::<half::f16>()?
+    );
+    Ok(())
+}
@@ -1085,61 +1085,161 @@ fn randn(device: &Device) -> Result<()> {
     Ok(())
 }
 
-test_device!(zeros, zeros_cpu, zeros_gpu, zeros_metal);
-test_device!(ones, ones_cpu, ones_gpu, ones_metal);
-test_device!(full, full_cpu, full_gpu, full_metal);
-test_device!(arange, arange_cpu, arange_gpu, arange_metal);
-test_device!(add_mul, add_mul_cpu, add_mul_gpu, add_mul_metal);
-test_device!(tensor_2d, tensor_2d_cpu, tensor_2d_gpu, tensor_2d_metal);
-test_device!(narrow, narrow_cpu, narrow_gpu, narrow_metal);
-test_device!(broadcast, broadcast_cpu, broadcast_gpu, broadcast_metal);
-test_device!(cat, cat_cpu, cat_gpu, cat_metal);
-test_device!(sum, sum_cpu, sum_gpu, sum_metal);
-test_device!(min, min_cpu, min_gpu, min_metal);
-test_device!(max, max_cpu, max_gpu, max_metal);
-test_device!(argmax, argmax_cpu, argmax_gpu, argmax_metal);
-test_device!(argmin, argmin_cpu, argmin_gpu, argmin_metal);
-test_device!(transpose, transpose_cpu, transpose_gpu, transpose_metal);
-test_device!(unary_op, unary_op_cpu, unary_op_gpu, unary_op_metal);
-test_device!(binary_op, binary_op_cpu, binary_op_gpu, binary_op_metal);
-test_device!(embeddings, embeddings_cpu, embeddings_gpu, embeddings_metal);
-test_device!(cmp, cmp_cpu, cmp_gpu, cmp_metal);
-test_device!(matmul, matmul_cpu, matmul_gpu, matmul_metal);
+// These tensors are large enough for the cpu elementwise kernels to be split between threads.
+fn large_elementwise(device: &Device) -> Result<()> {
+    let xs = Tensor::arange(0u32, 300 * 257, device)?
+        .to_dtype(DType::F32)?
+        .affine(1., -20000.)?
+        .reshape((300, 257))?;
+    let expected = (0..300 * 257)
+        .map(|v| v as f32 - 20000.)
+        .collect::<Vec<_>>();
+    assert_eq!(xs.flatten_all()?.to_vec1::<f32>()?, expected);
+    let views = [
+        xs.clone(),
+        xs.t()?,
+        xs.narrow(1, 3, 200)?,
+        xs.narrow_with_step(1, 0, 128, 2)?,
+        xs.flip(0)?,
+    ];
+    for v in views {
+        let (d0, d1) = v.dims2()?;
+        let vs = v.contiguous()?.flatten_all()?.to_vec1::<f32>()?;
+        let ws = (0..d0 * d1).map(|v| v as f32).collect::<Vec<_>>();
+        let w = Tensor::new(ws.as_slice(), device)?.reshape((d0, d1))?;
+        let row = Tensor::arange(0f32, d1 as f32, device)?.reshape((1, d1))?;
+        let col = Tensor::arange(0f32, d0 as f32, device)?.reshape((d0, 1))?;
+        let check = |ys: Tensor, f: &dyn Fn(usize, f32) -> f32| -> Result<()> {
+            let ys = ys.flatten_all()?.to_vec1::<f32>()?;
+            let expected = vs.iter().enumerate().map(|(i, &v)| f(i, v));
+            assert_eq!(ys, expected.collect::<Vec<_>>());
+            Ok(())
+        };
+        check(v.affine(2., 1.)?, &|_, v| v * 2. + 1.)?;
+        check(v.abs()?, &|_, v| v.abs())?;
+        check(v.sqr()?, &|_, v| v * v)?;
+        check((&v - &w)?, &|i, v| v - ws[i])?;
+        check((&w * &v)?, &|i, v| ws[i] * v)?;
+        check((&v * &v)?, &|_, v| v * v)?;
+        check(v.broadcast_add(&row)?, &|i, v| v + (i % d1) as f32)?;
+        check(row.broadcast_sub(&v)?, &|i, v| (i % d1) as f32 - v)?;
+        check(v.broadcast_mul(&col)?, &|i, v| v * (i / d1) as f32)?;
+        check(col.broadcast_add(&v)?, &|i, v| (i / d1) as f32 + v)?;
+        check(v.ge(&w)?.to_dtype(DType::F32)?, &|i, v| {
+            u8::from(v >= ws[i]) as f32
+        })?;
+    }
+    Ok(())
+}
+
+test_device!(zeros, zeros_cpu, zeros_gpu, zeros_metal, zeros_wgpu);
+test_device!(ones, ones_cpu, ones_gpu, ones_metal, ones_wgpu);
+test_device!(full, full_cpu, full_gpu, full_metal, full_wgpu);
+test_device!(arange, arange_cpu, arange_gpu, arange_metal, arange_wgpu);
+test_device!(
+    add_mul,
+    add_mul_cpu,
+    add_mul_gpu,
+    add_mul_metal,
+    add_mul_wgpu
+);
+test_device!(
+    tensor_2d,
+    tensor_2d_cpu,
+    tensor_2d_gpu,
+    tensor_2d_metal,
+    tensor_2d_wgpu
+);
+test_device!(narrow, narrow_cpu, narrow_gpu, narrow_metal, narrow_wgpu);
+test_device!(
+    broadcast,
+    broadcast_cpu,
+    broadcast_gpu,
+    broadcast_metal,
+    broadcast_wgpu
+);
+test_device!(cat, cat_cpu, cat_gpu, cat_metal, cat_wgpu);
+test_device!(sum, sum_cpu, sum_gpu, sum_metal, sum_wgpu);
+test_device!(min, min_cpu, min_gpu, min_metal, min_wgpu);
+test_device!(max, max_cpu, max_gpu, max_metal, max_wgpu);
+test_device!(argmax, argmax_cpu, argmax_gpu, argmax_metal, argmax_wgpu);
+test_device!(argmin, argmin_cpu, argmin_gpu, argmin_metal, argmin_wgpu);
+test_device!(
+    transpose,
+    transpose_cpu,
+    transpose_gpu,
+    transpose_metal,
+    transpose_wgpu
+);
+test_device!(
+    unary_op,
+    unary_op_cpu,
+    unary_op_gpu,
+    unary_op_metal,
+    unary_op_wgpu
+);
+test_device!(
+    binary_op,
+    binary_op_cpu,
+    binary_op_gpu,
+    binary_op_metal,
+    binary_op_wgpu
+);
+test_device!(
+    embeddings,
+    embeddings_cpu,
+    embeddings_gpu,
+    embeddings_metal,
+    embeddings_wgpu
+);
+test_device!(cmp, cmp_cpu, cmp_gpu, cmp_metal, cmp_wgpu);
+test_device!(matmul, matmul_cpu, matmul_gpu, matmul_metal, matmul_wgpu);
 test_device!(
     broadcast_matmul,
     broadcast_matmul_cpu,
     broadcast_matmul_gpu,
-    broadcast_matmul_metal
+    broadcast_matmul_metal,
+    broadcast_matmul_wgpu
 );
 test_device!(
     broadcasting,
     broadcasting_cpu,
     broadcasting_gpu,
-    broadcasting_metal
+    broadcasting_metal,
+    broadcasting_wgpu
 );
 test_device!(
     index_select,
     index_select_cpu,
     index_select_gpu,
-    index_select_metal
+    index_select_metal,
+    index_select_wgpu
+);
+test_device!(
+    index_add,
+    index_add_cpu,
+    index_add_gpu,
+    index_add_metal,
+    index_add_wgpu
 );
-test_device!(index_add, index_add_cpu, index_add_gpu, index_add_metal);
-test_device!(gather, gather_cpu, gather_gpu, gather_metal);
+test_device!(gather, gather_cpu, gather_gpu, gather_metal, gather_wgpu);
 test_device!(
     scatter_add,
     scatter_add_cpu,
     scatter_add_gpu,
-    scatter_add_metal
+    scatter_add_metal,
+    scatter_add_wgpu
 );
 test_device!(
     slice_scatter,
     slice_scatter_cpu,
     slice_scatter_gpu,
-    slice_scatter_metal
+    slice_scatter_metal,
+    slice_scatter_wgpu
 );
-test_device!(randn, randn_cpu, randn_gpu, randn_metal);
-test_device!(clamp, clamp_cpu, clamp_gpu, clamp_metal);
-test_device!(var, var_cpu, var_gpu, var_metal);
+test_device!(randn, randn_cpu, randn_gpu, randn_metal, randn_wgpu);
+test_device!(clamp, clamp_cpu, clamp_gpu, clamp_metal, clamp_wgpu);
+test_device!(var, var_cpu, var_gpu, var_metal, var_wgpu);
 
 // There was originally a bug on the CPU implementation for randn
 // https://github.com/huggingface/candle/issues/381
@@ -1272,3 +1372,10 @@ fn pow() -> Result<()> {
     );
     Ok(())
 }
+test_device!(
+    large_elementwise,
+    large_elementwise_cpu,
+    large_elementwise_gpu,
+    large_elementwise_metal,
+    large_elementwise_wgpu
+);
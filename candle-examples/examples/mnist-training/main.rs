@@ -8,8 +8,11 @@ extern crate accelerate_src;
 use clap::{Parser, ValueEnum};
 use rand::prelude::*;
 
-use candle::{DType, Result, Tensor, D};
-use candle_nn::{loss, ops, Conv2d, Linear, Module, ModuleT, Optimizer, VarBuilder, VarMap};
+use candle::{DType, Result, Tensor, Var, D};
+use candle_nn::{
+    loss, ops, Conv2d, Linear, MixedPrecision, Module, ModuleT, Optimizer, ParamsGradScaler,
+    VarBuilder, VarMap,
+};
 
 const IMAGE_DIM: usize = 784;
 const LABELS: usize = 10;
@@ -101,6 +104,7 @@ impl ConvNet {
 
 struct TrainingArgs {
     learning_rate: f64,
+    dtype: DType,
     load: Option<String>,
     save: Option<String>,
     epochs: usize,
@@ -115,11 +119,11 @@ fn training_loop_cnn(
     let dev = candle::Device::cuda_if_available(0)?;
 
     let train_labels = m.train_labels;
-    let train_images = m.train_images.to_device(&dev)?;
+    let train_images = m.train_images.to_dtype(args.dtype)?.to_device(&dev)?;
     let train_labels = train_labels.to_dtype(DType::U32)?.to_device(&dev)?;
 
     let mut varmap = VarMap::new();
-    let vs = VarBuilder::from_varmap(&varmap, DType::F32, &dev);
+    let vs = VarBuilder::from_varmap(&varmap, args.dtype, &dev);
     let model = ConvNet::new(vs.clone())?;
 
     if let Some(load) = &args.load {
@@ -131,8 +135,8 @@ fn training_loop_cnn(
         lr: args.learning_rate,
         ..Default::default()
     };
-    let mut opt = candle_nn::AdamW::new(varmap.all_vars(), adamw_params)?;
-    let test_images = m.test_images.to_device(&dev)?;
+    let mut opt: Trainer<candle_nn::AdamW> = Trainer::new(varmap.all_vars(), adamw_params, args)?;
+    let test_images = m.test_images.to_dtype(args.dtype)?.to_device(&dev)?;
     let test_labels = m.test_labels.to_dtype(DType::U32)?.to_device(&dev)?;
     let n_batches = train_images.dim(0)? / BSIZE;
     let mut batch_idxs = (0..n_batches).collect::<Vec<usize>>();
@@ -143,7 +147,7 @@ fn training_loop_cnn(
             let train_images = train_images.narrow(0, batch_idx * BSIZE, BSIZE)?;
             let train_labels = train_labels.narrow(0, batch_idx * BSIZE, BSIZE)?;
             let logits = model.forward(&train_images, true)?;
-            let log_sm = ops::log_softmax(&logits, D::Minus1)?;
+            let log_sm = ops::log_softmax(&logits.to_dtype(DType::F32)?, D::Minus1)?;
             let loss = loss::nll(&log_sm, &train_labels)?;
             opt.backward_step(&loss)?;
             sum_loss += loss.to_vec0::<f32>()?;
@@ -159,9 +163,10 @@ fn training_loop_cnn(
             .to_scalar::<f32>()?;
         let test_accuracy = sum_ok / test_labels.dims1()? as f32;
         println!(
-            "{epoch:4} train loss {:8.5} test acc: {:5.2}%",
+            "{epoch:4} train loss {:8.5} test acc: {:5.2}% skipped steps: {}",
             avg_loss,
-            100. * test_accuracy
+            100. * test_accuracy,
+            opt.skipped_steps(),
         );
     }
     if let Some(save) = &args.save {
@@ -178,11 +183,11 @@ fn training_loop<M: Model>(
     let dev = candle::Device::cuda_if_available(0)?;
 
     let train_labels = m.train_labels;
-    let train_images = m.train_images.to_device(&dev)?;
+    let train_images = m.train_images.to_dtype(args.dtype)?.to_device(&dev)?;
     let train_labels = train_labels.to_dtype(DType::U32)?.to_device(&dev)?;
 
     let mut varmap = VarMap::new();
-    let vs = VarBuilder::from_varmap(&varmap, DType::F32, &dev);
+    let vs = VarBuilder::from_varmap(&varmap, args.dtype, &dev);
     let model = M::new(vs.clone())?;
 
     if let Some(load) = &args.load {
@@ -190,12 +195,13 @@ fn training_loop<M: Model>(
         varmap.load(load)?
     }
 
-    let mut sgd = candle_nn::SGD::new(varmap.all_vars(), args.learning_rate)?;
-    let test_images = m.test_images.to_device(&dev)?;
+    let mut sgd: Trainer<candle_nn::SGD> =
+        Trainer::new(varmap.all_vars(), args.learning_rate, args)?;
+    let test_images = m.test_images.to_dtype(args.dtype)?.to_device(&dev)?;
     let test_labels = m.test_labels.to_dtype(DType::U32)?.to_device(&dev)?;
     for epoch in 1..args.epochs {
         let logits = model.forward(&train_images)?;
-        let log_sm = ops::log_softmax(&logits, D::Minus1)?;
+        let log_sm = ops::log_softmax(&logits.to_dtype(DType::F32)?, D::Minus1)?;
         let loss = loss::nll(&log_sm, &train_labels)?;
         sgd.backward_step(&loss)?;
 
@@ -208,9 +214,10 @@ fn training_loop<M: Model>(
             .to_scalar::<f32>()?;
         let test_accuracy = sum_ok / test_labels.dims1()? as f32;
         println!(
-            "{epoch:4} train loss: {:8.5} test acc: {:5.2}%",
+            "{epoch:4} train loss: {:8.5} test acc: {:5.2}% skipped steps: {}",
             loss.to_scalar::<f32>()?,
-            100. * test_accuracy
+            100. * test_accuracy,
+            sgd.skipped_steps(),
         );
     }
     if let Some(save) = &args.save {
@@ -220,6 +227,49 @@ fn training_loop<M: Model>(
     Ok(())
 }
 
+// Mixed precision keeps F32 master copies of the weights and checks the gradients for overflows
+// on each step, this is only worth it when the model runs in half precision.
+enum Trainer<O: Optimizer> {
+    Full(O),
+    Mixed(MixedPrecision<O>),
+}
+
+impl<O: Optimizer> Trainer<O> {
+    fn new(vars: Vec<Var>, config: O::Config, args: &TrainingArgs) -> Result<Self> {
+        match args.dtype {
+            DType::F16 | DType::BF16 => {
+                let opt = MixedPrecision::new(vars, config, args.grad_scaler_params())?;
+                Ok(Self::Mixed(opt))
+            }
+            _ => Ok(Self::Full(O::new(vars, config)?)),
+        }
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> Result<()> {
+        match self {
+            Self::Full(opt) => opt.backward_step(loss),
+            Self::Mixed(opt) => opt.backward_step(loss).map(|_| ()),
+        }
+    }
+
+    fn skipped_steps(&self) -> usize {
+        match self {
+            Self::Full(_) => 0,
+            Self::Mixed(opt) => opt.skipped_steps(),
+        }
+    }
+}
+
+impl TrainingArgs {
+    // Loss scaling is only needed for F16, BF16 has a large enough range.
+    fn grad_scaler_params(&self) -> ParamsGradScaler {
+        match self.dtype {
+            DType::F16 => ParamsGradScaler::default(),
+            _ => ParamsGradScaler::no_scaling(),
+        }
+    }
+}
+
 #[derive(ValueEnum, Clone)]
 enum WhichModel {
     Linear,
@@ -227,6 +277,13 @@ enum WhichModel {
     Cnn,
 }
 
+#[derive(ValueEnum, Clone, Copy)]
+enum WhichDType {
+    F32,
+    F16,
+    Bf16,
+}
+
 #[derive(Parser)]
 struct Args {
     #[clap(value_enum, default_value_t = WhichModel::Linear)]
@@ -238,6 +295,11 @@ struct Args {
     #[arg(long, default_value_t = 200)]
     epochs: usize,
 
+    /// The dtype used for the model weights and the forward pass, for F16 and BF16 the optimizer
+    /// updates F32 copies of the weights.
+    #[arg(long, value_enum, default_value_t = WhichDType::F32)]
+    dtype: WhichDType,
+
     /// The file where to save the trained weights, in safetensors format.
     #[arg(long)]
     save: Option<String>,
@@ -272,6 +334,11 @@ pub fn main() -> anyhow::Result<()> {
     let training_args = TrainingArgs {
         epochs: args.epochs,
         learning_rate: args.learning_rate.unwrap_or(default_learning_rate),
+        dtype: match args.dtype {
+            WhichDType::F32 => DType::F32,
+            WhichDType::F16 => DType::F16,
+            WhichDType::Bf16 => DType::BF16,
+        },
         load: args.load,
         save: args.save,
     };
@@ -66,6 +66,12 @@ impl FlashAttn {
         if v_stride[v_rank - 1] != 1 {
             candle::bail!("the last dim of v must be contiguous {v_stride:?}")
         }
+        // The strides are passed to the kernel as u32 so negative strides are not supported.
+        if q_l.has_negative_stride() || k_l.has_negative_stride() || v_l.has_negative_stride() {
+            candle::bail!(
+                "flash-attn does not support negative strides (q: {q_stride:?}, k: {k_stride:?}, v: {v_stride:?})"
+            )
+        }
 
         let (b_sz, seqlen_q, num_heads, head_size_og) = q_l.shape().dims4()?;
         let (_b_sz, seqlen_k, num_heads_k, _head_size_og) = k_l.shape().dims4()?;
@@ -463,6 +469,12 @@ impl FlashAttnVarLen {
         if v_stride[v_rank - 1] != 1 {
             candle::bail!("the last dim of v must be contiguous {v_stride:?}")
         }
+        // The strides are passed to the kernel as u32 so negative strides are not supported.
+        if q_l.has_negative_stride() || k_l.has_negative_stride() || v_l.has_negative_stride() {
+            candle::bail!(
+                "flash-attn does not support negative strides (q: {q_stride:?}, k: {k_stride:?}, v: {v_stride:?})"
+            )
+        }
 
         let (_total_q, num_heads, head_size_og) = q_l.shape().dims3()?;
         let (total_k, num_heads_k, _head_size_og) = k_l.shape().dims3()?;
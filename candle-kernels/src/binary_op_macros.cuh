@@ -22,41 +22,22 @@ extern "C" __global__ void FN_NAME( \
         } \
     } else if (lhs_cont) { \
         for (unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; i < numel; i += blockDim.x * gridDim.x) { \
-            unsigned int tmp_i = i; \
-            unsigned int rhs_i = 0; \
-            for (int d = num_dims - 1; d >= 0; d--) { \
-                unsigned int i_dim = tmp_i % dims[d]; \
-                rhs_i += i_dim * rhs_strides[d]; \
-                tmp_i /= dims[d]; \
-            } \
+            ptrdiff_t rhs_i = get_strided_index(i, num_dims, dims, rhs_strides); \
             TYPENAME x = lhs[i]; \
             TYPENAME y = rhs[rhs_i]; \
             out[i] = FUNC; \
         } \
     } else if (rhs_cont) { \
         for (unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; i < numel; i += blockDim.x * gridDim.x) { \
-            unsigned int tmp_i = i; \
-            unsigned int lhs_i = 0; \
-            for (int d = num_dims - 1; d >= 0; d--) { \
-                unsigned int i_dim = tmp_i % dims[d]; \
-                lhs_i += i_dim * lhs_strides[d]; \
-                tmp_i /= dims[d]; \
-            } \
+            ptrdiff_t lhs_i = get_strided_index(i, num_dims, dims, lhs_strides); \
             TYPENAME x = lhs[lhs_i]; \
             TYPENAME y = rhs[i]; \
             out[i] = FUNC; \
         } \
     } else { \
         for (unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; i < numel; i += blockDim.x * gridDim.x) { \
-            unsigned int tmp_i = i; \
-            unsigned int lhs_i = 0; \
-            unsigned int rhs_i = 0; \
-            for (int d = num_dims - 1; d >= 0; d--) { \
-                unsigned int i_dim = tmp_i % dims[d]; \
-                lhs_i += i_dim * lhs_strides[d]; \
-                rhs_i += i_dim * rhs_strides[d]; \
-                tmp_i /= dims[d]; \
-            } \
+            ptrdiff_t lhs_i = get_strided_index(i, num_dims, dims, lhs_strides); \
+            ptrdiff_t rhs_i = get_strided_index(i, num_dims, dims, rhs_strides); \
             TYPENAME x = lhs[lhs_i]; \
             TYPENAME y = rhs[rhs_i]; \
             out[i] = FUNC; \
@@ -18,7 +18,7 @@ __device__ void cast_(
     }
     else {
         for (unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; i < numel; i += blockDim.x * gridDim.x) {
-            unsigned strided_i = get_strided_index(i, num_dims, dims, strides);
+            ptrdiff_t strided_i = get_strided_index(i, num_dims, dims, strides);
             out[i] = inp[strided_i];
         }
     }
@@ -41,7 +41,7 @@ __device__ void cast_through(
     }
     else {
         for (unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; i < numel; i += blockDim.x * gridDim.x) {
-            unsigned strided_i = get_strided_index(i, num_dims, dims, strides);
+            ptrdiff_t strided_i = get_strided_index(i, num_dims, dims, strides);
             out[i] = static_cast<T>(static_cast<I>(inp[strided_i]));
         }
     }
@@ -453,6 +453,247 @@ __device__ void max_pool2d(
   dst[dst_i] = d;
 }
 
+template <typename T>
+__device__ void im2col3d(
+    const size_t dst_numel,
+    const size_t d_out,
+    const size_t h_out,
+    const size_t w_out,
+    const size_t stride,
+    const size_t padding,
+    const size_t dilation,
+    const size_t *info,
+    const T *src,
+    T *dst
+) {
+  const size_t dst_i = blockIdx.x * blockDim.x + threadIdx.x;
+  // dst: (b_size, d_out, h_out, w_out, c_in, d_k, h_k, w_k)
+  // src: (b_size, c_in, d_in, h_in, w_in)
+  if (dst_i >= dst_numel) {
+    return;
+  }
+  const size_t *src_dims = info;
+  const size_t *src_s = info + 5;
+  const size_t d_k = info[10];
+  const size_t h_k = info[11];
+  const size_t w_k = info[12];
+  const size_t c_in = src_dims[1];
+  const size_t d_in = src_dims[2];
+  const size_t h_in = src_dims[3];
+  const size_t w_in = src_dims[4];
+
+  const size_t dst_s6 = w_k;
+  const size_t dst_s5 = h_k * dst_s6;
+  const size_t dst_s4 = d_k * dst_s5;
+  const size_t dst_s3 = c_in * dst_s4;
+  const size_t dst_s2 = w_out * dst_s3;
+  const size_t dst_s1 = h_out * dst_s2;
+  const size_t dst_s0 = d_out * dst_s1;
+
+  size_t tmp_dst_i = dst_i;
+  const size_t b_idx = tmp_dst_i / dst_s0;
+  tmp_dst_i -= b_idx * dst_s0;
+  const size_t d_idx = tmp_dst_i / dst_s1;
+  tmp_dst_i -= d_idx * dst_s1;
+  const size_t h_idx = tmp_dst_i / dst_s2;
+  tmp_dst_i -= h_idx * dst_s2;
+  const size_t w_idx = tmp_dst_i / dst_s3;
+  tmp_dst_i -= w_idx * dst_s3;
+  const size_t c_idx = tmp_dst_i / dst_s4;
+  tmp_dst_i -= c_idx * dst_s4;
+  const size_t d_k_idx = tmp_dst_i / dst_s5;
+  tmp_dst_i -= d_k_idx * dst_s5;
+  const size_t h_k_idx = tmp_dst_i / dst_s6;
+  tmp_dst_i -= h_k_idx * dst_s6;
+  const size_t w_k_idx = tmp_dst_i;
+  size_t src_d_idx = d_idx * stride + d_k_idx * dilation;
+  size_t src_h_idx = h_idx * stride + h_k_idx * dilation;
+  size_t src_w_idx = w_idx * stride + w_k_idx * dilation;
+  if (src_d_idx < padding || src_d_idx >= d_in + padding
+      || src_h_idx < padding || src_h_idx >= h_in + padding
+      || src_w_idx < padding || src_w_idx >= w_in + padding) {
+    dst[dst_i] = static_cast<T>(0);
+  }
+  else {
+    src_d_idx -= padding;
+    src_h_idx -= padding;
+    src_w_idx -= padding;
+    const size_t src_i =
+      b_idx * src_s[0]
+      + c_idx * src_s[1]
+      + src_d_idx * src_s[2]
+      + src_h_idx * src_s[3]
+      + src_w_idx * src_s[4];
+    dst[dst_i] = src[src_i];
+  }
+}
+
+// Naive implementation of conv_transpose3d.
+template <typename T, typename A>
+__device__ void conv_transpose3d(
+    const size_t src_numel,
+    const size_t stride,
+    const size_t padding,
+    const size_t dilation,
+    const size_t *info,
+    const T *src,
+    const T *kernel,
+    T *dst
+) {
+  const size_t dst_i = blockIdx.x * blockDim.x + threadIdx.x;
+  // src: (b_size, c_in, d_in, h_in, w_in)
+  // k: (c_in, c_out, d_k, h_k, w_k)
+  const size_t *src_dims = info;
+  const size_t *src_s = info + 5;
+  const size_t *k_dims = info + 10;
+  const size_t *k_s = info + 15;
+  const size_t d_out = info[20];
+  const size_t h_out = info[21];
+  const size_t w_out = info[22];
+  const size_t d_k = k_dims[2];
+  const size_t h_k = k_dims[3];
+  const size_t w_k = k_dims[4];
+  const size_t c_out = k_dims[1];
+  const size_t c_in = src_dims[1];
+  const size_t d_in = src_dims[2];
+  const size_t h_in = src_dims[3];
+  const size_t w_in = src_dims[4];
+  if (dst_i >= src_dims[0] * c_out * d_out * h_out * w_out) {
+    return;
+  }
+
+  const size_t b_idx = dst_i / (w_out * h_out * d_out * c_out);
+  const size_t dst_c_idx = (dst_i / (w_out * h_out * d_out)) % c_out;
+  // NCDHW layout.
+  const size_t out_z = (dst_i / (w_out * h_out)) % d_out;
+  const size_t out_y = (dst_i / w_out) % h_out;
+  const size_t out_x = dst_i % w_out;
+
+  const size_t src_idx0 = b_idx * src_s[0];
+  A d = 0;
+  for (int k_z = 0; k_z < (int)d_k; ++k_z) {
+    int inp_z_stride = (int)(out_z + padding) - k_z * dilation;
+    if (inp_z_stride < 0 || inp_z_stride % stride) {
+      continue;
+    }
+    int inp_z = inp_z_stride / stride;
+    if (inp_z >= d_in) continue;
+    for (int k_y = 0; k_y < (int)h_k; ++k_y) {
+      int inp_y_stride = (int)(out_y + padding) - k_y * dilation;
+      if (inp_y_stride < 0 || inp_y_stride % stride) {
+        continue;
+      }
+      int inp_y = inp_y_stride / stride;
+      if (inp_y >= h_in) continue;
+      for (int k_x = 0; k_x < (int)w_k; ++k_x) {
+        int inp_x_stride = (int)(out_x + padding) - k_x * dilation;
+        if (inp_x_stride < 0 || inp_x_stride % stride) {
+          continue;
+        }
+        int inp_x = inp_x_stride / stride;
+        if (inp_x >= w_in) continue;
+        for (size_t src_c_idx = 0; src_c_idx < c_in; ++src_c_idx) {
+          const size_t src_idx = src_idx0 + src_c_idx * src_s[1] + inp_z * src_s[2] + inp_y * src_s[3] + inp_x * src_s[4];
+          const size_t k_idx = src_c_idx * k_s[0] + dst_c_idx * k_s[1] + k_z * k_s[2] + k_y * k_s[3] + k_x * k_s[4];
+          d += static_cast<A>(src[src_idx]) * static_cast<A>(kernel[k_idx]);
+        }
+      }
+    }
+  }
+  dst[dst_i] = static_cast<T>(d);
+}
+
+// The info buffer for the 3d pooling kernels contains the source dims and strides followed by the
+// kernel size and the stride for each of the d, h, w dimensions.
+template <typename T, typename A>
+__device__ void avg_pool3d(
+    const size_t src_numel,
+    const size_t *info,
+    const T *src,
+    T *dst
+) {
+  const size_t dst_i = blockIdx.x * blockDim.x + threadIdx.x;
+  // src: (b_size, c_in, d_in, h_in, w_in)
+  const size_t *src_dims = info;
+  const size_t *src_s = info + 5;
+  const size_t *k = info + 10;
+  const size_t *k_s = info + 13;
+
+  const size_t c = src_dims[1];
+  const size_t d_out = (src_dims[2] - k[0]) / k_s[0] + 1;
+  const size_t h_out = (src_dims[3] - k[1]) / k_s[1] + 1;
+  const size_t w_out = (src_dims[4] - k[2]) / k_s[2] + 1;
+  if (dst_i >= src_dims[0] * c * d_out * h_out * w_out) {
+    return;
+  }
+
+  const size_t b_idx = dst_i / (w_out * h_out * d_out * c);
+  const size_t c_idx = (dst_i / (w_out * h_out * d_out)) % c;
+  const size_t dst_d = (dst_i / (w_out * h_out)) % d_out;
+  const size_t dst_h = (dst_i / w_out) % h_out;
+  const size_t dst_w = dst_i % w_out;
+
+  const size_t src_idx0 = b_idx * src_s[0] + c_idx * src_s[1];
+  const float scale = 1.0 / (k[0] * k[1] * k[2]);
+  A d = 0;
+  for (size_t d_offset = 0; d_offset < k[0]; ++d_offset) {
+    const size_t src_d = k_s[0] * dst_d + d_offset;
+    for (size_t h_offset = 0; h_offset < k[1]; ++h_offset) {
+      const size_t src_h = k_s[1] * dst_h + h_offset;
+      for (size_t w_offset = 0; w_offset < k[2]; ++w_offset) {
+        const size_t src_w = k_s[2] * dst_w + w_offset;
+        const size_t src_idx = src_idx0 + src_d * src_s[2] + src_h * src_s[3] + src_w * src_s[4];
+        d += static_cast<A>(src[src_idx]);
+      }
+    }
+  }
+  dst[dst_i] = static_cast<T>(d * scale);
+}
+
+template <typename T>
+__device__ void max_pool3d(
+    const size_t src_numel,
+    const size_t *info,
+    const T *src,
+    T *dst
+) {
+  const size_t dst_i = blockIdx.x * blockDim.x + threadIdx.x;
+  // src: (b_size, c_in, d_in, h_in, w_in)
+  const size_t *src_dims = info;
+  const size_t *src_s = info + 5;
+  const size_t *k = info + 10;
+  const size_t *k_s = info + 13;
+
+  const size_t c = src_dims[1];
+  const size_t d_out = (src_dims[2] - k[0]) / k_s[0] + 1;
+  const size_t h_out = (src_dims[3] - k[1]) / k_s[1] + 1;
+  const size_t w_out = (src_dims[4] - k[2]) / k_s[2] + 1;
+  if (dst_i >= src_dims[0] * c * d_out * h_out * w_out) {
+    return;
+  }
+
+  const size_t b_idx = dst_i / (w_out * h_out * d_out * c);
+  const size_t c_idx = (dst_i / (w_out * h_out * d_out)) % c;
+  const size_t dst_d = (dst_i / (w_out * h_out)) % d_out;
+  const size_t dst_h = (dst_i / w_out) % h_out;
+  const size_t dst_w = dst_i % w_out;
+
+  const size_t src_idx0 = b_idx * src_s[0] + c_idx * src_s[1];
+  T d = src[src_idx0 + k_s[0] * dst_d * src_s[2] + k_s[1] * dst_h * src_s[3] + k_s[2] * dst_w * src_s[4]];
+  for (size_t d_offset = 0; d_offset < k[0]; ++d_offset) {
+    const size_t src_d = k_s[0] * dst_d + d_offset;
+    for (size_t h_offset = 0; h_offset < k[1]; ++h_offset) {
+      const size_t src_h = k_s[1] * dst_h + h_offset;
+      for (size_t w_offset = 0; w_offset < k[2]; ++w_offset) {
+        const size_t src_w = k_s[2] * dst_w + w_offset;
+        const size_t src_idx = src_idx0 + src_d * src_s[2] + src_h * src_s[3] + src_w * src_s[4];
+        d = maxg(d, src[src_idx]);
+      }
+    }
+  }
+  dst[dst_i] = d;
+}
+
 template <typename T>
 __device__ void upsample_nearest2d(
     const size_t w_out,
@@ -620,6 +861,56 @@ extern "C" __global__ void FN_NAME(  \
   max_pool2d<TYPENAME>(src_numel, w_k, h_k, w_stride, h_stride, info, src, dst); \
 } \
 
+#define IM2COL3D_OP(TYPENAME, FN_NAME) \
+extern "C" __global__ void FN_NAME(  \
+    const size_t dst_numel, \
+    const size_t d_out, \
+    const size_t h_out, \
+    const size_t w_out, \
+    const size_t stride, \
+    const size_t padding, \
+    const size_t dilation, \
+    const size_t *info, \
+    const TYPENAME *src, \
+    TYPENAME *dst \
+) {  \
+  im2col3d<TYPENAME>(dst_numel, d_out, h_out, w_out, stride, padding, dilation, info, src, dst); \
+} \
+
+#define CONVT3D_OP(TYPENAME, TYPEACC, FN_NAME) \
+extern "C" __global__ void FN_NAME(  \
+    const size_t src_numel, \
+    const size_t stride, \
+    const size_t padding, \
+    const size_t dilation, \
+    const size_t *info, \
+    const TYPENAME *src, \
+    const TYPENAME *kernel, \
+    TYPENAME *dst \
+) {  \
+  conv_transpose3d<TYPENAME, TYPEACC>(src_numel, stride, padding, dilation, info, src, kernel, dst); \
+} \
+
+#define AVG_POOL3D_OP(TYPENAME, TYPEACC, FN_NAME) \
+extern "C" __global__ void FN_NAME(  \
+    const size_t src_numel, \
+    const size_t *info, \
+    const TYPENAME *src, \
+    TYPENAME *dst \
+) {  \
+  avg_pool3d<TYPENAME, TYPEACC>(src_numel, info, src, dst); \
+} \
+
+#define MAX_POOL3D_OP(TYPENAME, FN_NAME) \
+extern "C" __global__ void FN_NAME(  \
+    const size_t src_numel, \
+    const size_t *info, \
+    const TYPENAME *src, \
+    TYPENAME *dst \
+) {  \
+  max_pool3d<TYPENAME>(src_numel, info, src, dst); \
+} \
+
 #define UPSAMPLE_NEAREST2D_OP(TYPENAME, FN_NAME) \
 extern "C" __global__ void FN_NAME(  \
     const size_t w_out, \
@@ -643,6 +934,10 @@ MAX_POOL2D_OP(__nv_bfloat16, max_pool2d_bf16)
 UPSAMPLE_NEAREST2D_OP(__nv_bfloat16, upsample_nearest2d_bf16)
 IM2COL_OP(__nv_bfloat16, im2col_bf16)
 IM2COL1D_OP(__nv_bfloat16, im2col1d_bf16)
+IM2COL3D_OP(__nv_bfloat16, im2col3d_bf16)
+CONVT3D_OP(__nv_bfloat16, float, conv_transpose3d_bf16)
+AVG_POOL3D_OP(__nv_bfloat16, float, avg_pool3d_bf16)
+MAX_POOL3D_OP(__nv_bfloat16, max_pool3d_bf16)
 #endif
 
 #if __CUDA_ARCH__ >= 530
@@ -655,6 +950,10 @@ MAX_POOL2D_OP(__half, max_pool2d_f16)
 UPSAMPLE_NEAREST2D_OP(__half, upsample_nearest2d_f16)
 IM2COL_OP(__half, im2col_f16)
 IM2COL1D_OP(__half, im2col1d_f16)
+IM2COL3D_OP(__half, im2col3d_f16)
+CONVT3D_OP(__half, float, conv_transpose3d_f16)
+AVG_POOL3D_OP(__half, float, avg_pool3d_f16)
+MAX_POOL3D_OP(__half, max_pool3d_f16)
 #endif
 
 CONV1D_OP(float, float, conv1d_f32)
@@ -701,3 +1000,23 @@ IM2COL1D_OP(float, im2col1d_f32)
 IM2COL1D_OP(double, im2col1d_f64)
 IM2COL1D_OP(uint8_t, im2col1d_u8)
 IM2COL1D_OP(uint32_t, im2col1d_u32)
+
+IM2COL3D_OP(float, im2col3d_f32)
+IM2COL3D_OP(double, im2col3d_f64)
+IM2COL3D_OP(uint8_t, im2col3d_u8)
+IM2COL3D_OP(uint32_t, im2col3d_u32)
+
+CONVT3D_OP(float, float, conv_transpose3d_f32)
+CONVT3D_OP(double, double, conv_transpose3d_f64)
+CONVT3D_OP(uint8_t, uint8_t, conv_transpose3d_u8)
+CONVT3D_OP(uint32_t, uint32_t, conv_transpose3d_u32)
+
+AVG_POOL3D_OP(float, float, avg_pool3d_f32)
+AVG_POOL3D_OP(double, double, avg_pool3d_f64)
+AVG_POOL3D_OP(uint8_t, uint8_t, avg_pool3d_u8)
+AVG_POOL3D_OP(uint32_t, uint32_t, avg_pool3d_u32)
+
+MAX_POOL3D_OP(float, max_pool3d_f32)
+MAX_POOL3D_OP(double, max_pool3d_f64)
+MAX_POOL3D_OP(uint8_t, max_pool3d_u8)
+MAX_POOL3D_OP(uint32_t, max_pool3d_u32)
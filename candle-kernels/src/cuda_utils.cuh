@@ -1,5 +1,6 @@
 #include "compatibility.cuh"
 #include<stdint.h>
+#include<cstddef>
 #include<cmath>
 
 // TODO: This is often used to check that the data is contiguous so that
@@ -22,16 +23,20 @@ __device__ bool is_contiguous(
     return true;
 }
 
-__device__ unsigned int get_strided_index(
+// The strides are signed, negative strides are used by reversed views. They are passed in the
+// same size_t buffer as the dims and converted back to signed values here. The returned offset
+// is relative to the start of the view and is negative for the elements that come before it in
+// memory.
+__device__ ptrdiff_t get_strided_index(
     unsigned int idx,
     const size_t num_dims,
     const size_t *dims,
     const size_t *strides
 ) {
-    unsigned int strided_i = 0;
+    ptrdiff_t strided_i = 0;
     for (unsigned int d = 0; d < num_dims; d++) {
         unsigned int dim_idx = num_dims - 1 - d;
-        strided_i += (idx % dims[dim_idx]) * strides[dim_idx];
+        strided_i += (ptrdiff_t)(idx % dims[dim_idx]) * (ptrdiff_t)strides[dim_idx];
         idx /= dims[dim_idx];
     }
     return strided_i;
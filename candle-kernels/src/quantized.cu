@@ -232,6 +232,25 @@ typedef struct {
 } block_q8_K;
 static_assert(sizeof(block_q8_K) == sizeof(float) + QK_K + QK_K/16*sizeof(int16_t), "wrong q8_K block size/padding");
 
+// Non-linear 4 bits quants, the 4 bits values index into kvalues_iq4nl.
+#define QK4_NL 32
+#define QR4_NL 2
+typedef struct {
+    half    d;
+    uint8_t qs[QK4_NL/2];
+} block_iq4_nl;
+static_assert(sizeof(block_iq4_nl) == sizeof(ggml_fp16_t) + QK4_NL/2, "wrong iq4_nl block size/padding");
+
+typedef struct {
+    half     d;
+    uint16_t scales_h;
+    uint8_t  scales_l[QK_K/64];
+    uint8_t  qs[QK_K/2];
+} block_iq4_xs;
+static_assert(sizeof(block_iq4_xs) == sizeof(ggml_fp16_t) + sizeof(uint16_t) + QK_K/64 + QK_K/2, "wrong iq4_xs block size/padding");
+
+static __device__ __constant__ const int8_t kvalues_iq4nl[16] = {-127, -104, -83, -65, -49, -35, -22, -10, 1, 13, 25, 38, 53, 69, 89, 113};
+
 
 // VDR = vec dot ratio, how many contiguous integers each thread processes when the vec dot kernel is called
 // MMVQ = mul_mat_vec_q, MMQ = mul_mat_q
@@ -490,6 +509,24 @@ static __device__ __forceinline__ void dequantize_q4_0(const void * vx, const in
 #endif // GGML_CUDA_F16
 }
 
+static __device__ __forceinline__ void dequantize_iq4_nl(const void * vx, const int ib, const int iqs, dfloat2 & v){
+    const block_iq4_nl * x = (const block_iq4_nl *) vx;
+
+    const dfloat d = x[ib].d;
+
+    const int vui = x[ib].qs[iqs];
+
+    v.x = kvalues_iq4nl[vui & 0xF];
+    v.y = kvalues_iq4nl[vui >> 4];
+
+#ifdef GGML_CUDA_F16
+    v = __hmul2(v, {d, d});
+#else
+    v.x *= d;
+    v.y *= d;
+#endif // GGML_CUDA_F16
+}
+
 static __device__ __forceinline__ void dequantize_q4_1(const void * vx, const int ib, const int iqs, dfloat2 & v){
     const block_q4_1 * x = (const block_q4_1 *) vx;
 
@@ -928,6 +965,52 @@ extern "C" __global__ void dequantize_block_q8_K(const void * __restrict__ vx, f
 #endif
 }
 
+extern "C" __global__ void dequantize_block_iq4_nl(const void * __restrict__ vx, float * __restrict__ yy, int nb32) {
+    const int i = blockIdx.x;
+
+    // assume 32 threads
+    const int tid = threadIdx.x;
+    const int il  = tid/8;
+    const int ir  = tid%8;
+    const int ib = 8*i + ir;
+    if (ib >= nb32) {
+        return;
+    }
+
+    float * y = yy + 256*i + 32*ir + 4*il;
+
+    const block_iq4_nl * x = (const block_iq4_nl *)vx + ib;
+    const float d = __half2float(x->d);
+
+    const uint8_t * q = x->qs + 4*il;
+
+    for (int l = 0; l < 4; ++l) {
+        y[l+ 0] = d * kvalues_iq4nl[q[l] & 0xF];
+        y[l+16] = d * kvalues_iq4nl[q[l] >>  4];
+    }
+}
+
+extern "C" __global__ void dequantize_block_iq4_xs(const void * __restrict__ vx, float * __restrict__ yy) {
+    const int i = blockIdx.x;
+    const block_iq4_xs * x = (const block_iq4_xs *)vx;
+
+    // assume 32 threads
+    const int tid = threadIdx.x;
+    const int il  = tid/8; // 0...3
+    const int ib  = tid%8; // 0...7
+
+    float * y = yy + i*QK_K + 32*ib + 4*il;
+
+    const uint8_t * q = x[i].qs + 16*ib + 4*il;
+    const int ls = ((x[i].scales_l[ib/2] >> 4*(ib%2)) & 0xF) | (((x[i].scales_h >> 2*ib) & 3) << 4);
+    const float d = __half2float(x[i].d) * (ls - 32);
+
+    for (int l = 0; l < 4; ++l) {
+        y[l+ 0] = d * kvalues_iq4nl[q[l] & 0xF];
+        y[l+16] = d * kvalues_iq4nl[q[l] >>  4];
+    }
+}
+
 extern "C" __global__ void dequantize_block_q5_0(const void * __restrict__ vx, float * __restrict__ yy, int nb32) {
   return dequantize_block<QK5_0, QR5_0, dequantize_q5_0>(vx, yy, nb32);
 }
@@ -1024,6 +1107,52 @@ extern "C" __global__ void dequantize_mul_mat_vec_q8_0_cuda(const void * vx, con
     dequantize_mul_mat_vec<QK8_0, QR8_0, dequantize_q8_0>(vx, y, dst, ncols, nrows);
 }
 
+extern "C" __global__ void dequantize_mul_mat_vec_iq4_nl_cuda(const void * vx, const dfloat * y, float * dst, const int ncols, const int nrows) {
+    dequantize_mul_mat_vec<QK4_NL, QR4_NL, dequantize_iq4_nl>(vx, y, dst, ncols, nrows);
+}
+
+extern "C" __global__ void dequantize_mul_mat_vec_iq4_xs(const void * __restrict__ vx, const float * __restrict__ yy, float * __restrict__ dst, const int ncols, int nrows) {
+
+    const int row = blockIdx.x*blockDim.y + threadIdx.y;
+    if (row >= nrows) return;
+
+    const int num_blocks_per_row = ncols / QK_K;
+    const int ib0 = row*num_blocks_per_row;
+
+    const block_iq4_xs * x = (const block_iq4_xs *)vx + ib0;
+
+    const int ix = threadIdx.x/(QK_K/32);  // 0...3, super-block offset
+    const int ib = threadIdx.x%(QK_K/32);  // 0...7, 32 values sub-block within the super-block
+
+    float tmp = 0; // partial sum for thread in warp
+
+    for (int i = ix; i < num_blocks_per_row; i += WARP_SIZE/(QK_K/32)) {
+
+        const float   * y = yy + i*QK_K + 32*ib;
+        const uint8_t * q = x[i].qs + 16*ib;
+
+        const int ls = ((x[i].scales_l[ib/2] >> 4*(ib%2)) & 0xf) | (((x[i].scales_h >> 2*ib) & 3) << 4);
+        const float d = __half2float(x[i].d) * (ls - 32);
+
+        float sum = 0;
+        for (int l = 0; l < 16; ++l) {
+            sum += y[l+ 0] * kvalues_iq4nl[q[l] & 0xF]
+                 + y[l+16] * kvalues_iq4nl[q[l] >>  4];
+        }
+        tmp += d * sum;
+    }
+
+    // sum up partial sums and write back result
+#pragma unroll
+    for (int mask = 16; mask > 0; mask >>= 1) {
+        tmp += __shfl_xor_sync(0xffffffff, tmp, mask, 32);
+    }
+
+    if (threadIdx.x == 0) {
+        dst[row] = tmp;
+    }
+}
+
 extern "C" __global__ void dequantize_mul_mat_vec_q2_k(const void * __restrict__ vx, const float * __restrict__ yy, float * __restrict__ dst, const int ncols, int nrows) {
 
     static_assert(16%K_QUANTS_PER_ITERATION == 0, "16 must be divisible by K_QUANTS_PER_ITERATION");
@@ -31,7 +31,7 @@ fast_sum(const size_t src_numel, const size_t el_to_sum_per_block,
 
   while (idx < stop_idx) {
     // TODO: Fast version for the contiguous case.
-    size_t strided_i = get_strided_index(idx, num_dims, dims, strides);
+    ptrdiff_t strided_i = get_strided_index(idx, num_dims, dims, strides);
     shr[tid] += src[strided_i];
     idx += blockDim.x;
   }
@@ -113,7 +113,7 @@ fast_max(const size_t src_numel, const size_t el_to_sum_per_block,
 
   while (idx < stop_idx) {
     // TODO: Fast version for the contiguous case.
-    size_t strided_i = get_strided_index(idx, num_dims, dims, strides);
+    ptrdiff_t strided_i = get_strided_index(idx, num_dims, dims, strides);
     shr[tid] = maxg(shr[tid], src[strided_i]);
     idx += blockDim.x;
   }
@@ -151,7 +151,7 @@ fast_min(const size_t src_numel, const size_t el_to_sum_per_block,
 
   while (idx < stop_idx) {
     // TODO: Fast version for the contiguous case.
-    size_t strided_i = get_strided_index(idx, num_dims, dims, strides);
+    ptrdiff_t strided_i = get_strided_index(idx, num_dims, dims, strides);
     shr[tid] = ming(shr[tid], src[strided_i]);
     idx += blockDim.x;
   }
@@ -193,7 +193,7 @@ fast_argmin(const size_t src_numel, const size_t el_to_sum_per_block,
 
   while (idx < stop_idx) {
     // TODO: Fast version for the contiguous case.
-    size_t strided_i = get_strided_index(idx, num_dims, dims, strides);
+    ptrdiff_t strided_i = get_strided_index(idx, num_dims, dims, strides);
     if (not_set || src[strided_i] < shr[tid]) {
       shr[tid] = src[strided_i];
       // Assume that the reduction takes place over the last dimension which is contiguous.
@@ -241,7 +241,7 @@ fast_argmax(const size_t src_numel, const size_t el_to_sum_per_block,
 
   while (idx < stop_idx) {
     // TODO: Fast version for the contiguous case.
-    size_t strided_i = get_strided_index(idx, num_dims, dims, strides);
+    ptrdiff_t strided_i = get_strided_index(idx, num_dims, dims, strides);
     if (not_set || src[strided_i] > shr[tid]) {
       shr[tid] = src[strided_i];
       // Assume that the reduction takes place over the last dimension which is contiguous.
@@ -321,7 +321,7 @@ fast_argmax(const size_t src_numel, const size_t el_to_sum_per_block,
     } else {                                                                   \
       for (unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; i < numel;  \
            i += blockDim.x * gridDim.x) {                                      \
-        unsigned strided_i = get_strided_index(i, num_dims, dims, strides);    \
+        ptrdiff_t strided_i = get_strided_index(i, num_dims, dims, strides);   \
         size_t dst_index = i;                                                  \
         for (unsigned int nd = 0; nd < num_sum_dims; ++nd) {                   \
           size_t stride = sum_dims_s[nd];                                      \
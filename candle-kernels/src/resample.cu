@@ -0,0 +1,329 @@
+#include "cuda_utils.cuh"
+#include<stdint.h>
+
+// The grid sampling modes and paddings, these have to match the values used in
+// candle-core/src/resample.rs.
+#define GRID_SAMPLE_BILINEAR 0
+#define GRID_SAMPLE_NEAREST 1
+#define PADDING_ZEROS 0
+#define PADDING_BORDER 1
+#define PADDING_REFLECTION 2
+
+__device__ __forceinline__ float rintg(float a) { return rintf(a); }
+__device__ __forceinline__ double rintg(double a) { return rint(a); }
+
+// Separable interpolation of the two last dimensions of a contiguous (b, c, h_in, w_in) tensor.
+// The weights along each dimension are in compressed sparse row format, `idxs` contains the
+// row offsets for h (h_out + 1 values), the source indexes for h (nnz_h values), then the row
+// offsets and source indexes for w. `wgts` contains the nnz_h weights for h followed by the ones
+// for w.
+template <typename T, typename A>
+__device__ void interpolate2d(
+    const size_t numel,
+    const size_t h_in,
+    const size_t w_in,
+    const size_t h_out,
+    const size_t w_out,
+    const size_t nnz_h,
+    const uint32_t *idxs,
+    const A *wgts,
+    const T *src,
+    T *dst
+) {
+  const size_t dst_i = blockIdx.x * blockDim.x + threadIdx.x;
+  if (dst_i >= numel) {
+    return;
+  }
+  const uint32_t *h_offsets = idxs;
+  const uint32_t *h_idxs = h_offsets + h_out + 1;
+  const uint32_t *w_offsets = h_idxs + nnz_h;
+  const uint32_t *w_idxs = w_offsets + w_out + 1;
+  const A *h_wgts = wgts;
+  const A *w_wgts = wgts + nnz_h;
+
+  const size_t x = dst_i % w_out;
+  const size_t y = (dst_i / w_out) % h_out;
+  const size_t bc = dst_i / (w_out * h_out);
+  const T *s = src + bc * h_in * w_in;
+  A d = 0;
+  for (uint32_t k = h_offsets[y]; k < h_offsets[y + 1]; ++k) {
+    const T *row = s + h_idxs[k] * w_in;
+    A r = 0;
+    for (uint32_t l = w_offsets[x]; l < w_offsets[x + 1]; ++l) {
+      r += static_cast<A>(row[w_idxs[l]]) * w_wgts[l];
+    }
+    d += r * h_wgts[k];
+  }
+  dst[dst_i] = static_cast<T>(d);
+}
+
+template <typename A>
+__device__ A clip_coordinates(const A coord, const A size, A *grad) {
+  if (coord <= 0) {
+    *grad = 0;
+    return 0;
+  }
+  if (coord >= size - 1) {
+    *grad = 0;
+    return size - 1;
+  }
+  return coord;
+}
+
+template <typename A>
+__device__ A reflect_coordinates(A coord, const A twice_low, const A twice_high, A *grad) {
+  if (twice_low == twice_high) {
+    *grad = 0;
+    return 0;
+  }
+  const A min = twice_low / 2;
+  const A span = (twice_high - twice_low) / 2;
+  coord -= min;
+  A sign = 1;
+  if (coord < 0) {
+    coord = -coord;
+    sign = -1;
+  }
+  const A flips = floorg(coord / span);
+  const A extra = coord - flips * span;
+  if (flips - 2 * floorg(flips / 2) == 0) {
+    *grad *= sign;
+    return extra + min;
+  }
+  *grad *= -sign;
+  return span - extra + min;
+}
+
+// Converts a normalized coordinate in [-1, 1] to a pixel coordinate and applies the padding
+// mode, the derivative of the pixel coordinate with respect to the normalized one is written
+// to `grad`. See `grid_sampler_compute_source_index_set_grad` in PyTorch.
+template <typename A>
+__device__ A grid_source_index(
+    A coord,
+    const size_t size,
+    const uint32_t padding,
+    const uint32_t align_corners,
+    A *grad
+) {
+  const A size_f = static_cast<A>(size);
+  if (align_corners) {
+    coord = (coord + 1) / 2 * (size_f - 1);
+    *grad = (size_f - 1) / 2;
+  } else {
+    coord = ((coord + 1) * size_f - 1) / 2;
+    *grad = size_f / 2;
+  }
+  if (padding == PADDING_BORDER) {
+    coord = clip_coordinates(coord, size_f, grad);
+  } else if (padding == PADDING_REFLECTION) {
+    if (align_corners) {
+      coord = reflect_coordinates<A>(coord, 0, 2 * (size_f - 1), grad);
+    } else {
+      coord = reflect_coordinates<A>(coord, -1, 2 * size_f - 1, grad);
+    }
+    coord = clip_coordinates(coord, size_f, grad);
+  }
+  return coord;
+}
+
+// Sets `idx` to the flat index of pixel (x, y) in a (h, w) image, returns false when out of
+// bounds.
+template <typename A>
+__device__ bool pixel_index(const A x, const A y, const size_t h, const size_t w, size_t *idx) {
+  if (x >= 0 && y >= 0 && x < static_cast<A>(w) && y < static_cast<A>(h)) {
+    *idx = static_cast<size_t>(y) * w + static_cast<size_t>(x);
+    return true;
+  }
+  return false;
+}
+
+// xs: (b, c, h, w), grid: (b, n_out, 2), dst: (b, c, n_out), all contiguous.
+template <typename T, typename A>
+__device__ void grid_sample(
+    const size_t numel,
+    const size_t c,
+    const size_t h,
+    const size_t w,
+    const size_t n_out,
+    const uint32_t mode,
+    const uint32_t padding,
+    const uint32_t align_corners,
+    const T *xs,
+    const T *grid,
+    T *dst
+) {
+  const size_t dst_i = blockIdx.x * blockDim.x + threadIdx.x;
+  if (dst_i >= numel) {
+    return;
+  }
+  const size_t p = dst_i % n_out;
+  const size_t bc = dst_i / n_out;
+  const size_t b = bc / c;
+  const T *g = grid + (b * n_out + p) * 2;
+  A gx, gy;
+  const A x = grid_source_index<A>(static_cast<A>(g[0]), w, padding, align_corners, &gx);
+  const A y = grid_source_index<A>(static_cast<A>(g[1]), h, padding, align_corners, &gy);
+  const T *src = xs + bc * h * w;
+  A d = 0;
+  size_t idx;
+  if (mode == GRID_SAMPLE_NEAREST) {
+    if (pixel_index<A>(rintg(x), rintg(y), h, w, &idx)) {
+      d = static_cast<A>(src[idx]);
+    }
+  } else {
+    const A x0 = floorg(x);
+    const A y0 = floorg(y);
+    const A x1 = x0 + 1;
+    const A y1 = y0 + 1;
+    if (pixel_index<A>(x0, y0, h, w, &idx)) {
+      d += static_cast<A>(src[idx]) * (x1 - x) * (y1 - y);
+    }
+    if (pixel_index<A>(x1, y0, h, w, &idx)) {
+      d += static_cast<A>(src[idx]) * (x - x0) * (y1 - y);
+    }
+    if (pixel_index<A>(x0, y1, h, w, &idx)) {
+      d += static_cast<A>(src[idx]) * (x1 - x) * (y - y0);
+    }
+    if (pixel_index<A>(x1, y1, h, w, &idx)) {
+      d += static_cast<A>(src[idx]) * (x - x0) * (y - y0);
+    }
+  }
+  dst[dst_i] = static_cast<T>(d);
+}
+
+// One thread per (b, p) grid location, the gradients of the input are accumulated with atomic
+// adds in the first b * c * h * w elements of `grads` which has to be zero initialized, the
+// gradients of the grid are written in the remaining b * n_out * 2 elements.
+template <typename T, typename A>
+__device__ void grid_sample_bwd(
+    const size_t numel,
+    const size_t c,
+    const size_t h,
+    const size_t w,
+    const size_t n_out,
+    const uint32_t mode,
+    const uint32_t padding,
+    const uint32_t align_corners,
+    const T *xs,
+    const T *grid,
+    const T *grad,
+    A *grads
+) {
+  const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+  if (i >= numel) {
+    return;
+  }
+  const size_t b = i / n_out;
+  const size_t p = i % n_out;
+  const T *g = grid + i * 2;
+  A gx, gy;
+  const A x = grid_source_index<A>(static_cast<A>(g[0]), w, padding, align_corners, &gx);
+  const A y = grid_source_index<A>(static_cast<A>(g[1]), h, padding, align_corners, &gy);
+  const A x0 = floorg(x);
+  const A y0 = floorg(y);
+  const A x1 = x0 + 1;
+  const A y1 = y0 + 1;
+  const A cx[4] = {x0, x1, x0, x1};
+  const A cy[4] = {y0, y0, y1, y1};
+  const A wgt[4] = {(x1 - x) * (y1 - y), (x - x0) * (y1 - y), (x1 - x) * (y - y0), (x - x0) * (y - y0)};
+  const A dw_dx[4] = {-(y1 - y), y1 - y, -(y - y0), y - y0};
+  const A dw_dy[4] = {-(x1 - x), -(x - x0), x1 - x, x - x0};
+  A grad_x = 0;
+  A grad_y = 0;
+  size_t idx;
+  for (size_t ch = 0; ch < c; ++ch) {
+    const size_t bc = b * c + ch;
+    const A go = static_cast<A>(grad[bc * n_out + p]);
+    const T *src = xs + bc * h * w;
+    A *grad_src = grads + bc * h * w;
+    if (mode == GRID_SAMPLE_NEAREST) {
+      if (pixel_index<A>(rintg(x), rintg(y), h, w, &idx)) {
+        atomicAdd(grad_src + idx, go);
+      }
+    } else {
+      for (int k = 0; k < 4; ++k) {
+        if (pixel_index<A>(cx[k], cy[k], h, w, &idx)) {
+          const A v = static_cast<A>(src[idx]);
+          atomicAdd(grad_src + idx, wgt[k] * go);
+          grad_x += v * dw_dx[k] * go;
+          grad_y += v * dw_dy[k] * go;
+        }
+      }
+    }
+  }
+  A *grad_grid = grads + numel / n_out * c * h * w;
+  grad_grid[2 * i] = grad_x * gx;
+  grad_grid[2 * i + 1] = grad_y * gy;
+}
+
+#define INTERPOLATE2D_OP(TYPENAME, TYPEACC, FN_NAME) \
+extern "C" __global__ void FN_NAME(  \
+    const size_t numel, \
+    const size_t h_in, \
+    const size_t w_in, \
+    const size_t h_out, \
+    const size_t w_out, \
+    const size_t nnz_h, \
+    const uint32_t *idxs, \
+    const TYPEACC *wgts, \
+    const TYPENAME *src, \
+    TYPENAME *dst \
+) {  \
+  interpolate2d<TYPENAME, TYPEACC>(numel, h_in, w_in, h_out, w_out, nnz_h, idxs, wgts, src, dst); \
+} \
+
+#define GRID_SAMPLE_OP(TYPENAME, TYPEACC, FN_NAME) \
+extern "C" __global__ void FN_NAME(  \
+    const size_t numel, \
+    const size_t c, \
+    const size_t h, \
+    const size_t w, \
+    const size_t n_out, \
+    const uint32_t mode, \
+    const uint32_t padding, \
+    const uint32_t align_corners, \
+    const TYPENAME *xs, \
+    const TYPENAME *grid, \
+    TYPENAME *dst \
+) {  \
+  grid_sample<TYPENAME, TYPEACC>(numel, c, h, w, n_out, mode, padding, align_corners, xs, grid, dst); \
+} \
+
+#define GRID_SAMPLE_BWD_OP(TYPENAME, TYPEACC, FN_NAME) \
+extern "C" __global__ void FN_NAME(  \
+    const size_t numel, \
+    const size_t c, \
+    const size_t h, \
+    const size_t w, \
+    const size_t n_out, \
+    const uint32_t mode, \
+    const uint32_t padding, \
+    const uint32_t align_corners, \
+    const TYPENAME *xs, \
+    const TYPENAME *grid, \
+    const TYPENAME *grad, \
+    TYPEACC *grads \
+) {  \
+  grid_sample_bwd<TYPENAME, TYPEACC>(numel, c, h, w, n_out, mode, padding, align_corners, xs, grid, grad, grads); \
+} \
+
+#if __CUDA_ARCH__ >= 800
+INTERPOLATE2D_OP(__nv_bfloat16, float, interpolate2d_bf16)
+GRID_SAMPLE_OP(__nv_bfloat16, float, grid_sample_bf16)
+GRID_SAMPLE_BWD_OP(__nv_bfloat16, float, grid_sample_bwd_bf16)
+#endif
+
+#if __CUDA_ARCH__ >= 530
+INTERPOLATE2D_OP(__half, float, interpolate2d_f16)
+GRID_SAMPLE_OP(__half, float, grid_sample_f16)
+GRID_SAMPLE_BWD_OP(__half, float, grid_sample_bwd_f16)
+#endif
+
+INTERPOLATE2D_OP(float, float, interpolate2d_f32)
+INTERPOLATE2D_OP(double, double, interpolate2d_f64)
+
+GRID_SAMPLE_OP(float, float, grid_sample_f32)
+GRID_SAMPLE_OP(double, double, grid_sample_f64)
+
+GRID_SAMPLE_BWD_OP(float, float, grid_sample_bwd_f32)
+GRID_SAMPLE_BWD_OP(double, double, grid_sample_bwd_f64)
@@ -24,9 +24,9 @@ extern "C" __global__ void FN_NAME(  \
     } \
     else { \
         for (unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; i < numel; i += blockDim.x * gridDim.x) { \
-            unsigned strided_i = get_strided_index(i, num_dims, dims, strides); \
-            unsigned strided_i_t = get_strided_index(i, num_dims, dims, strides_t); \
-            unsigned strided_i_f = get_strided_index(i, num_dims, dims, strides_f); \
+            ptrdiff_t strided_i = get_strided_index(i, num_dims, dims, strides); \
+            ptrdiff_t strided_i_t = get_strided_index(i, num_dims, dims, strides_t); \
+            ptrdiff_t strided_i_f = get_strided_index(i, num_dims, dims, strides_f); \
             out[i] = ids[strided_i] ? t[strided_i_t] : f[strided_i_f]; \
         } \
     } \
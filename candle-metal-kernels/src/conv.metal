@@ -1,3 +1,6 @@
+#include <metal_stdlib>
+using namespace metal;
+
 template <typename T>
 METAL_FUNC void im2col(
     constant size_t &dst_numel,
@@ -62,6 +65,80 @@ METAL_FUNC void im2col(
   }
 }
 
+template <typename T>
+METAL_FUNC void im2col3d(
+    constant size_t &dst_numel,
+    constant size_t &d_out,
+    constant size_t &h_out,
+    constant size_t &w_out,
+    constant size_t &d_k,
+    constant size_t &h_k,
+    constant size_t &w_k,
+    constant size_t &stride,
+    constant size_t &padding,
+    constant size_t &dilation,
+    constant size_t *src_dims,
+    constant size_t *src_strides,
+    device const T *src,
+    device T *dst,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  // dst: (b_size, d_out, h_out, w_out, c_in, d_k, h_k, w_k)
+  // src: (b_size, c_in, d_in, h_in, w_in)
+  if (tid >= dst_numel) {
+    return;
+  }
+  const size_t c_in = src_dims[1];
+  const size_t d_in = src_dims[2];
+  const size_t h_in = src_dims[3];
+  const size_t w_in = src_dims[4];
+
+  const size_t dst_s6 = w_k;
+  const size_t dst_s5 = h_k * dst_s6;
+  const size_t dst_s4 = d_k * dst_s5;
+  const size_t dst_s3 = c_in * dst_s4;
+  const size_t dst_s2 = w_out * dst_s3;
+  const size_t dst_s1 = h_out * dst_s2;
+  const size_t dst_s0 = d_out * dst_s1;
+
+  size_t tmp_tid = tid;
+  const size_t b_idx = tmp_tid / dst_s0;
+  tmp_tid -= b_idx * dst_s0;
+  const size_t d_idx = tmp_tid / dst_s1;
+  tmp_tid -= d_idx * dst_s1;
+  const size_t h_idx = tmp_tid / dst_s2;
+  tmp_tid -= h_idx * dst_s2;
+  const size_t w_idx = tmp_tid / dst_s3;
+  tmp_tid -= w_idx * dst_s3;
+  const size_t c_idx = tmp_tid / dst_s4;
+  tmp_tid -= c_idx * dst_s4;
+  const size_t d_k_idx = tmp_tid / dst_s5;
+  tmp_tid -= d_k_idx * dst_s5;
+  const size_t h_k_idx = tmp_tid / dst_s6;
+  tmp_tid -= h_k_idx * dst_s6;
+  const size_t w_k_idx = tmp_tid;
+  size_t src_d_idx = d_idx * stride + d_k_idx * dilation;
+  size_t src_h_idx = h_idx * stride + h_k_idx * dilation;
+  size_t src_w_idx = w_idx * stride + w_k_idx * dilation;
+  if (src_d_idx < padding || src_d_idx >= d_in + padding
+      || src_h_idx < padding || src_h_idx >= h_in + padding
+      || src_w_idx < padding || src_w_idx >= w_in + padding) {
+    dst[tid] = static_cast<T>(0);
+  }
+  else {
+    src_d_idx -= padding;
+    src_h_idx -= padding;
+    src_w_idx -= padding;
+    const size_t src_i =
+      b_idx * src_strides[0]
+      + c_idx * src_strides[1]
+      + src_d_idx * src_strides[2]
+      + src_h_idx * src_strides[3]
+      + src_w_idx * src_strides[4];
+    dst[tid] = src[src_i];
+  }
+}
+
 template <typename T>
 METAL_FUNC void im2col1d(
     constant size_t &dst_numel,
@@ -149,6 +226,365 @@ METAL_FUNC void upsample_nearest2d(
   dst[tid] = src[src_i];
 }
 
+template <typename T>
+METAL_FUNC T max_value(T a, T b) {
+  return a > b ? a : b;
+}
+
+// Naive implementation of conv_transpose1d.
+template <typename T, typename A>
+METAL_FUNC void conv_transpose1d(
+    constant size_t &l_out,
+    constant size_t &stride,
+    constant size_t &padding,
+    constant size_t &out_padding,
+    constant size_t &dilation,
+    constant size_t *src_dims,
+    constant size_t *src_strides,
+    constant size_t *k_dims,
+    constant size_t *k_strides,
+    device const T *src,
+    device const T *k,
+    device T *dst,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  // src: (b_size, c_in, l_in)
+  // k: (c_in, c_out, l_k)
+  const size_t l_k = k_dims[2];
+  const size_t c_out = k_dims[1];
+  const size_t c_in = src_dims[1];
+  const size_t l_in = src_dims[2];
+  if (tid >= src_dims[0] * c_out * l_out) {
+    return;
+  }
+
+  const size_t b_idx = tid / (l_out * c_out);
+  const size_t dst_c_idx = (tid / l_out) % c_out;
+  // NCL layout.
+  const size_t out_x = tid % l_out;
+
+  const size_t src_idx0 = b_idx * src_strides[0];
+  A d = 0;
+  for (int k_x = 0; k_x < (int)l_k; ++k_x) {
+    // let out_x = inp_x * p.stride + k_x * p.dilation - p.padding;
+    int inp_x_stride = (int)(out_x + padding) - k_x * (int)dilation;
+    if (inp_x_stride < 0 || inp_x_stride % stride) {
+      continue;
+    }
+    int inp_x = inp_x_stride / stride;
+    if (inp_x >= (int)l_in) continue;
+    for (size_t src_c_idx = 0; src_c_idx < c_in; ++src_c_idx) {
+      const size_t src_idx = src_idx0 + src_c_idx * src_strides[1] + inp_x * src_strides[2];
+      const size_t k_idx = src_c_idx * k_strides[0] + dst_c_idx * k_strides[1] + k_x * k_strides[2];
+      d += static_cast<A>(src[src_idx]) * static_cast<A>(k[k_idx]);
+    }
+  }
+  dst[tid] = static_cast<T>(d);
+}
+
+// Naive implementation of conv_transpose2d.
+template <typename T, typename A>
+METAL_FUNC void conv_transpose2d(
+    constant size_t &w_out,
+    constant size_t &h_out,
+    constant size_t &stride,
+    constant size_t &padding,
+    constant size_t &out_padding,
+    constant size_t &dilation,
+    constant size_t *src_dims,
+    constant size_t *src_strides,
+    constant size_t *k_dims,
+    constant size_t *k_strides,
+    device const T *src,
+    device const T *k,
+    device T *dst,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  // src: (b_size, c_in, h_in, w_in)
+  // k: (c_in, c_out, h_k, w_k)
+  const size_t h_k = k_dims[2];
+  const size_t w_k = k_dims[3];
+  const size_t c_out = k_dims[1];
+  const size_t c_in = src_dims[1];
+  const size_t h_in = src_dims[2];
+  const size_t w_in = src_dims[3];
+  if (tid >= src_dims[0] * c_out * w_out * h_out) {
+    return;
+  }
+
+  const size_t b_idx = tid / (w_out * h_out * c_out);
+  const size_t dst_c_idx = (tid / (w_out * h_out)) % c_out;
+  // NCHW layout.
+  const size_t out_y = (tid / w_out) % h_out;
+  const size_t out_x = tid % w_out;
+
+  const size_t src_idx0 = b_idx * src_strides[0];
+  A d = 0;
+  for (int k_x = 0; k_x < (int)w_k; ++k_x) {
+    // let out_x = inp_x * p.stride + k_x * p.dilation - p.padding;
+    int inp_x_stride = (int)(out_x + padding) - k_x * (int)dilation;
+    if (inp_x_stride < 0 || inp_x_stride % stride) {
+      continue;
+    }
+    int inp_x = inp_x_stride / stride;
+    if (inp_x >= (int)w_in) continue;
+    for (int k_y = 0; k_y < (int)h_k; ++k_y) {
+      int inp_y_stride = (int)(out_y + padding) - k_y * (int)dilation;
+      if (inp_y_stride < 0 || inp_y_stride % stride) {
+        continue;
+      }
+      int inp_y = inp_y_stride / stride;
+      if (inp_y >= (int)h_in) continue;
+      for (size_t src_c_idx = 0; src_c_idx < c_in; ++src_c_idx) {
+        const size_t src_idx = src_idx0 + src_c_idx * src_strides[1] + inp_y * src_strides[2] + inp_x * src_strides[3];
+        const size_t k_idx = src_c_idx * k_strides[0] + dst_c_idx * k_strides[1] + k_y * k_strides[2] + k_x * k_strides[3];
+        d += static_cast<A>(src[src_idx]) * static_cast<A>(k[k_idx]);
+      }
+    }
+  }
+  dst[tid] = static_cast<T>(d);
+}
+
+// Naive implementation of conv_transpose3d.
+template <typename T, typename A>
+METAL_FUNC void conv_transpose3d(
+    constant size_t &d_out,
+    constant size_t &h_out,
+    constant size_t &w_out,
+    constant size_t &stride,
+    constant size_t &padding,
+    constant size_t &dilation,
+    constant size_t *src_dims,
+    constant size_t *src_strides,
+    constant size_t *k_dims,
+    constant size_t *k_strides,
+    device const T *src,
+    device const T *k,
+    device T *dst,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  // src: (b_size, c_in, d_in, h_in, w_in)
+  // k: (c_in, c_out, d_k, h_k, w_k)
+  const size_t d_k = k_dims[2];
+  const size_t h_k = k_dims[3];
+  const size_t w_k = k_dims[4];
+  const size_t c_out = k_dims[1];
+  const size_t c_in = src_dims[1];
+  const size_t d_in = src_dims[2];
+  const size_t h_in = src_dims[3];
+  const size_t w_in = src_dims[4];
+  if (tid >= src_dims[0] * c_out * d_out * h_out * w_out) {
+    return;
+  }
+
+  const size_t b_idx = tid / (w_out * h_out * d_out * c_out);
+  const size_t dst_c_idx = (tid / (w_out * h_out * d_out)) % c_out;
+  // NCDHW layout.
+  const size_t out_z = (tid / (w_out * h_out)) % d_out;
+  const size_t out_y = (tid / w_out) % h_out;
+  const size_t out_x = tid % w_out;
+
+  const size_t src_idx0 = b_idx * src_strides[0];
+  A d = 0;
+  for (int k_z = 0; k_z < (int)d_k; ++k_z) {
+    int inp_z_stride = (int)(out_z + padding) - k_z * (int)dilation;
+    if (inp_z_stride < 0 || inp_z_stride % stride) {
+      continue;
+    }
+    int inp_z = inp_z_stride / stride;
+    if (inp_z >= (int)d_in) continue;
+    for (int k_y = 0; k_y < (int)h_k; ++k_y) {
+      int inp_y_stride = (int)(out_y + padding) - k_y * (int)dilation;
+      if (inp_y_stride < 0 || inp_y_stride % stride) {
+        continue;
+      }
+      int inp_y = inp_y_stride / stride;
+      if (inp_y >= (int)h_in) continue;
+      for (int k_x = 0; k_x < (int)w_k; ++k_x) {
+        int inp_x_stride = (int)(out_x + padding) - k_x * (int)dilation;
+        if (inp_x_stride < 0 || inp_x_stride % stride) {
+          continue;
+        }
+        int inp_x = inp_x_stride / stride;
+        if (inp_x >= (int)w_in) continue;
+        for (size_t src_c_idx = 0; src_c_idx < c_in; ++src_c_idx) {
+          const size_t src_idx = src_idx0 + src_c_idx * src_strides[1] + inp_z * src_strides[2] + inp_y * src_strides[3] + inp_x * src_strides[4];
+          const size_t k_idx = src_c_idx * k_strides[0] + dst_c_idx * k_strides[1] + k_z * k_strides[2] + k_y * k_strides[3] + k_x * k_strides[4];
+          d += static_cast<A>(src[src_idx]) * static_cast<A>(k[k_idx]);
+        }
+      }
+    }
+  }
+  dst[tid] = static_cast<T>(d);
+}
+
+template <typename T, typename A>
+METAL_FUNC void avg_pool2d(
+    constant size_t &w_k,
+    constant size_t &h_k,
+    constant size_t &w_stride,
+    constant size_t &h_stride,
+    constant size_t *src_dims,
+    constant size_t *src_strides,
+    device const T *src,
+    device T *dst,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  // src: (b_size, c_in, w_in, h_in)
+  const size_t c = src_dims[1];
+  const size_t w_in = src_dims[2];
+  const size_t h_in = src_dims[3];
+
+  const size_t w_out = (w_in - w_k) / w_stride + 1;
+  const size_t h_out = (h_in - h_k) / h_stride + 1;
+  if (tid >= src_dims[0] * c * w_out * h_out) {
+    return;
+  }
+
+  const size_t b_idx = tid / (w_out * h_out * c);
+  const size_t c_idx = (tid / (w_out * h_out)) % c;
+  const size_t dst_w = (tid / h_out) % w_out;
+  const size_t dst_h = tid % h_out;
+
+  const size_t src_idx0 = b_idx * src_strides[0] + c_idx * src_strides[1];
+  const float scale = 1.0 / (w_k * h_k);
+  A d = 0;
+  for (size_t w_offset = 0; w_offset < w_k; ++w_offset) {
+    const size_t src_w = w_stride * dst_w + w_offset;
+    for (size_t h_offset = 0; h_offset < h_k; ++h_offset) {
+      const size_t src_h = h_stride * dst_h + h_offset;
+      const size_t src_idx = src_idx0 + src_w * src_strides[2] + src_h * src_strides[3];
+      d += static_cast<A>(src[src_idx]);
+    }
+  }
+  dst[tid] = static_cast<T>(d * scale);
+}
+
+template <typename T>
+METAL_FUNC void max_pool2d(
+    constant size_t &w_k,
+    constant size_t &h_k,
+    constant size_t &w_stride,
+    constant size_t &h_stride,
+    constant size_t *src_dims,
+    constant size_t *src_strides,
+    device const T *src,
+    device T *dst,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  // src: (b_size, c_in, w_in, h_in)
+  const size_t c = src_dims[1];
+  const size_t w_in = src_dims[2];
+  const size_t h_in = src_dims[3];
+
+  const size_t w_out = (w_in - w_k) / w_stride + 1;
+  const size_t h_out = (h_in - h_k) / h_stride + 1;
+  if (tid >= src_dims[0] * c * w_out * h_out) {
+    return;
+  }
+
+  const size_t b_idx = tid / (w_out * h_out * c);
+  const size_t c_idx = (tid / (w_out * h_out)) % c;
+  const size_t dst_w = (tid / h_out) % w_out;
+  const size_t dst_h = tid % h_out;
+
+  const size_t src_idx0 = b_idx * src_strides[0] + c_idx * src_strides[1];
+  T d = src[src_idx0 + w_stride * dst_w * src_strides[2] + h_stride * dst_h * src_strides[3]];
+  for (size_t w_offset = 0; w_offset < w_k; ++w_offset) {
+    const size_t src_w = w_stride * dst_w + w_offset;
+    for (size_t h_offset = 0; h_offset < h_k; ++h_offset) {
+      const size_t src_h = h_stride * dst_h + h_offset;
+      const size_t src_idx = src_idx0 + src_w * src_strides[2] + src_h * src_strides[3];
+      d = max_value(d, src[src_idx]);
+    }
+  }
+  dst[tid] = d;
+}
+
+// The 3d pooling kernels get the kernel size and the stride for each of the d, h, w dimensions.
+template <typename T, typename A>
+METAL_FUNC void avg_pool3d(
+    constant size_t *k,
+    constant size_t *k_stride,
+    constant size_t *src_dims,
+    constant size_t *src_strides,
+    device const T *src,
+    device T *dst,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  // src: (b_size, c_in, d_in, h_in, w_in)
+  const size_t c = src_dims[1];
+  const size_t d_out = (src_dims[2] - k[0]) / k_stride[0] + 1;
+  const size_t h_out = (src_dims[3] - k[1]) / k_stride[1] + 1;
+  const size_t w_out = (src_dims[4] - k[2]) / k_stride[2] + 1;
+  if (tid >= src_dims[0] * c * d_out * h_out * w_out) {
+    return;
+  }
+
+  const size_t b_idx = tid / (w_out * h_out * d_out * c);
+  const size_t c_idx = (tid / (w_out * h_out * d_out)) % c;
+  const size_t dst_d = (tid / (w_out * h_out)) % d_out;
+  const size_t dst_h = (tid / w_out) % h_out;
+  const size_t dst_w = tid % w_out;
+
+  const size_t src_idx0 = b_idx * src_strides[0] + c_idx * src_strides[1];
+  const float scale = 1.0 / (k[0] * k[1] * k[2]);
+  A d = 0;
+  for (size_t d_offset = 0; d_offset < k[0]; ++d_offset) {
+    const size_t src_d = k_stride[0] * dst_d + d_offset;
+    for (size_t h_offset = 0; h_offset < k[1]; ++h_offset) {
+      const size_t src_h = k_stride[1] * dst_h + h_offset;
+      for (size_t w_offset = 0; w_offset < k[2]; ++w_offset) {
+        const size_t src_w = k_stride[2] * dst_w + w_offset;
+        const size_t src_idx = src_idx0 + src_d * src_strides[2] + src_h * src_strides[3] + src_w * src_strides[4];
+        d += static_cast<A>(src[src_idx]);
+      }
+    }
+  }
+  dst[tid] = static_cast<T>(d * scale);
+}
+
+template <typename T>
+METAL_FUNC void max_pool3d(
+    constant size_t *k,
+    constant size_t *k_stride,
+    constant size_t *src_dims,
+    constant size_t *src_strides,
+    device const T *src,
+    device T *dst,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  // src: (b_size, c_in, d_in, h_in, w_in)
+  const size_t c = src_dims[1];
+  const size_t d_out = (src_dims[2] - k[0]) / k_stride[0] + 1;
+  const size_t h_out = (src_dims[3] - k[1]) / k_stride[1] + 1;
+  const size_t w_out = (src_dims[4] - k[2]) / k_stride[2] + 1;
+  if (tid >= src_dims[0] * c * d_out * h_out * w_out) {
+    return;
+  }
+
+  const size_t b_idx = tid / (w_out * h_out * d_out * c);
+  const size_t c_idx = (tid / (w_out * h_out * d_out)) % c;
+  const size_t dst_d = (tid / (w_out * h_out)) % d_out;
+  const size_t dst_h = (tid / w_out) % h_out;
+  const size_t dst_w = tid % w_out;
+
+  const size_t src_idx0 = b_idx * src_strides[0] + c_idx * src_strides[1];
+  T d = src[src_idx0 + k_stride[0] * dst_d * src_strides[2] + k_stride[1] * dst_h * src_strides[3] + k_stride[2] * dst_w * src_strides[4]];
+  for (size_t d_offset = 0; d_offset < k[0]; ++d_offset) {
+    const size_t src_d = k_stride[0] * dst_d + d_offset;
+    for (size_t h_offset = 0; h_offset < k[1]; ++h_offset) {
+      const size_t src_h = k_stride[1] * dst_h + h_offset;
+      for (size_t w_offset = 0; w_offset < k[2]; ++w_offset) {
+        const size_t src_w = k_stride[2] * dst_w + w_offset;
+        const size_t src_idx = src_idx0 + src_d * src_strides[2] + src_h * src_strides[3] + src_w * src_strides[4];
+        d = max_value(d, src[src_idx]);
+      }
+    }
+  }
+  dst[tid] = d;
+}
+
 #define IM2COL_OP(T, FN_NAME) \
 kernel void FN_NAME(  \
     constant size_t &dst_numel, \
@@ -168,6 +604,27 @@ kernel void FN_NAME(  \
   im2col<T>(dst_numel, h_out, w_out, h_k, w_k, stride, padding, dilation, src_dims, src_strides, src, dst, tid); \
 } \
 
+#define IM2COL3D_OP(T, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t &dst_numel, \
+    constant size_t &d_out, \
+    constant size_t &h_out, \
+    constant size_t &w_out, \
+    constant size_t &d_k, \
+    constant size_t &h_k, \
+    constant size_t &w_k, \
+    constant size_t &stride, \
+    constant size_t &padding, \
+    constant size_t &dilation, \
+    constant size_t *src_dims, \
+    constant size_t *src_strides, \
+    device const T *src, \
+    device T *dst, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  im2col3d<T>(dst_numel, d_out, h_out, w_out, d_k, h_k, w_k, stride, padding, dilation, src_dims, src_strides, src, dst, tid); \
+} \
+
 #define IM2COL1D_OP(T, FN_NAME) \
 kernel void FN_NAME(  \
     constant size_t &dst_numel, \
@@ -200,10 +657,129 @@ kernel void FN_NAME(  \
   upsample_nearest2d<TYPENAME>(w_out, h_out, w_scale, h_scale, dims, strides, src, dst, tid); \
 } \
 
+#define CONVT1D_OP(TYPENAME, TYPEACC, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t &l_out, \
+    constant size_t &stride, \
+    constant size_t &padding, \
+    constant size_t &out_padding, \
+    constant size_t &dilation, \
+    constant size_t *src_dims, \
+    constant size_t *src_strides, \
+    constant size_t *k_dims, \
+    constant size_t *k_strides, \
+    device const TYPENAME *src, \
+    device const TYPENAME *k, \
+    device TYPENAME *dst, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  conv_transpose1d<TYPENAME, TYPEACC>(l_out, stride, padding, out_padding, dilation, src_dims, src_strides, k_dims, k_strides, src, k, dst, tid); \
+} \
+
+#define CONVT2D_OP(TYPENAME, TYPEACC, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t &w_out, \
+    constant size_t &h_out, \
+    constant size_t &stride, \
+    constant size_t &padding, \
+    constant size_t &out_padding, \
+    constant size_t &dilation, \
+    constant size_t *src_dims, \
+    constant size_t *src_strides, \
+    constant size_t *k_dims, \
+    constant size_t *k_strides, \
+    device const TYPENAME *src, \
+    device const TYPENAME *k, \
+    device TYPENAME *dst, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  conv_transpose2d<TYPENAME, TYPEACC>(w_out, h_out, stride, padding, out_padding, dilation, src_dims, src_strides, k_dims, k_strides, src, k, dst, tid); \
+} \
+
+#define CONVT3D_OP(TYPENAME, TYPEACC, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t &d_out, \
+    constant size_t &h_out, \
+    constant size_t &w_out, \
+    constant size_t &stride, \
+    constant size_t &padding, \
+    constant size_t &dilation, \
+    constant size_t *src_dims, \
+    constant size_t *src_strides, \
+    constant size_t *k_dims, \
+    constant size_t *k_strides, \
+    device const TYPENAME *src, \
+    device const TYPENAME *k, \
+    device TYPENAME *dst, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  conv_transpose3d<TYPENAME, TYPEACC>(d_out, h_out, w_out, stride, padding, dilation, src_dims, src_strides, k_dims, k_strides, src, k, dst, tid); \
+} \
+
+#define AVG_POOL2D_OP(TYPENAME, TYPEACC, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t &w_k, \
+    constant size_t &h_k, \
+    constant size_t &w_s, \
+    constant size_t &h_s, \
+    constant size_t *src_dims, \
+    constant size_t *src_s, \
+    device const TYPENAME *src, \
+    device TYPENAME *dst, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  avg_pool2d<TYPENAME, TYPEACC>(w_k, h_k, w_s, h_s, src_dims, src_s, src, dst, tid); \
+} \
+
+#define MAX_POOL2D_OP(TYPENAME, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t &w_k, \
+    constant size_t &h_k, \
+    constant size_t &w_s, \
+    constant size_t &h_s, \
+    constant size_t *src_dims, \
+    constant size_t *src_s, \
+    device const TYPENAME *src, \
+    device TYPENAME *dst, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  max_pool2d<TYPENAME>(w_k, h_k, w_s, h_s, src_dims, src_s, src, dst, tid); \
+} \
+
+#define AVG_POOL3D_OP(TYPENAME, TYPEACC, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t *k, \
+    constant size_t *k_s, \
+    constant size_t *src_dims, \
+    constant size_t *src_s, \
+    device const TYPENAME *src, \
+    device TYPENAME *dst, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  avg_pool3d<TYPENAME, TYPEACC>(k, k_s, src_dims, src_s, src, dst, tid); \
+} \
+
+#define MAX_POOL3D_OP(TYPENAME, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t *k, \
+    constant size_t *k_s, \
+    constant size_t *src_dims, \
+    constant size_t *src_s, \
+    device const TYPENAME *src, \
+    device TYPENAME *dst, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  max_pool3d<TYPENAME>(k, k_s, src_dims, src_s, src, dst, tid); \
+} \
+
 IM2COL_OP(float, im2col_f32)
 IM2COL_OP(uint8_t, im2col_u8)
 IM2COL_OP(uint32_t, im2col_u32)
 
+IM2COL3D_OP(float, im2col3d_f32)
+IM2COL3D_OP(uint8_t, im2col3d_u8)
+IM2COL3D_OP(uint32_t, im2col3d_u32)
+
 IM2COL1D_OP(float, im2col1d_f32)
 IM2COL1D_OP(uint8_t, im2col1d_u8)
 IM2COL1D_OP(uint32_t, im2col1d_u32)
@@ -211,3 +787,36 @@ IM2COL1D_OP(uint32_t, im2col1d_u32)
 UPSAMPLE_NEAREST2D_OP(float, upsample_nearest2d_f32)
 UPSAMPLE_NEAREST2D_OP(uint8_t, upsample_nearest2d_u8)
 UPSAMPLE_NEAREST2D_OP(uint32_t, upsample_nearest2d_u32)
+
+IM2COL_OP(half, im2col_f16)
+IM2COL1D_OP(half, im2col1d_f16)
+IM2COL3D_OP(half, im2col3d_f16)
+
+CONVT1D_OP(float, float, conv_transpose1d_f32)
+CONVT1D_OP(half, float, conv_transpose1d_f16)
+CONVT2D_OP(float, float, conv_transpose2d_f32)
+CONVT2D_OP(half, float, conv_transpose2d_f16)
+CONVT3D_OP(float, float, conv_transpose3d_f32)
+CONVT3D_OP(half, float, conv_transpose3d_f16)
+
+AVG_POOL2D_OP(float, float, avg_pool2d_f32)
+AVG_POOL2D_OP(half, float, avg_pool2d_f16)
+MAX_POOL2D_OP(float, max_pool2d_f32)
+MAX_POOL2D_OP(half, max_pool2d_f16)
+AVG_POOL3D_OP(float, float, avg_pool3d_f32)
+AVG_POOL3D_OP(half, float, avg_pool3d_f16)
+MAX_POOL3D_OP(float, max_pool3d_f32)
+MAX_POOL3D_OP(half, max_pool3d_f16)
+
+#if defined(__HAVE_BFLOAT__)
+IM2COL_OP(bfloat, im2col_bf16)
+IM2COL1D_OP(bfloat, im2col1d_bf16)
+IM2COL3D_OP(bfloat, im2col3d_bf16)
+CONVT1D_OP(bfloat, float, conv_transpose1d_bf16)
+CONVT2D_OP(bfloat, float, conv_transpose2d_bf16)
+CONVT3D_OP(bfloat, float, conv_transpose3d_bf16)
+AVG_POOL2D_OP(bfloat, float, avg_pool2d_bf16)
+MAX_POOL2D_OP(bfloat, max_pool2d_bf16)
+AVG_POOL3D_OP(bfloat, float, avg_pool3d_bf16)
+MAX_POOL3D_OP(bfloat, max_pool3d_bf16)
+#endif
@@ -14,6 +14,7 @@ const TERNARY: &str = include_str!("ternary.metal");
 const CAST: &str = include_str!("cast.metal");
 const CONV: &str = include_str!("conv.metal");
 const REDUCE: &str = include_str!("reduce.metal");
+const RESAMPLE: &str = include_str!("resample.metal");
 const RANDOM: &str = include_str!("random.metal");
 const MFA: &[u8] = include_bytes!("libMetalFlashAttention.metallib");
 const QUANTIZED: &str = include_str!("quantized.metal");
@@ -125,6 +126,7 @@ pub enum Source {
     Conv,
     Random,
     Quantized,
+    Resample,
 }
 
 macro_rules! ops{
@@ -218,20 +220,24 @@ impl<T> From<std::sync::PoisonError<T>> for MetalKernelError {
 
 type Libraries = HashMap<Source, Library>;
 type Pipelines = HashMap<(&'static str, Option<ConstantValues>), ComputePipelineState>;
+type GeneratedPipelines = HashMap<String, ComputePipelineState>;
 
 #[derive(Debug)]
 pub struct Kernels {
     libraries: RwLock<Libraries>,
     pipelines: RwLock<Pipelines>,
+    generated: RwLock<GeneratedPipelines>,
 }
 
 impl Kernels {
     pub fn new() -> Self {
         let libraries = RwLock::new(Libraries::new());
         let pipelines = RwLock::new(Pipelines::new());
+        let generated = RwLock::new(GeneratedPipelines::new());
         Self {
             libraries,
             pipelines,
+            generated,
         }
     }
 
@@ -247,6 +253,7 @@ impl Kernels {
             Source::Conv => CONV,
             Source::Random => RANDOM,
             Source::Quantized => QUANTIZED,
+            Source::Resample => RESAMPLE,
             Source::Mfa => panic!("Invalid lib"),
         }
     }
@@ -339,6 +346,33 @@ impl Kernels {
     ) -> Result<ComputePipelineState, MetalKernelError> {
         self.load_pipeline_with_constants(device, source, name, None)
     }
+
+    /// Load the pipeline for the function [`name`] of a kernel generated at runtime, the
+    /// [`source`] is only compiled the first time that [`name`] is requested so the name has
+    /// to identify the source, e.g. by including a hash of it.
+    pub fn load_pipeline_from_source(
+        &self,
+        device: &Device,
+        source: &str,
+        name: &str,
+    ) -> Result<ComputePipelineState, MetalKernelError> {
+        let mut pipelines = self.generated.write()?;
+        if let Some(pipeline) = pipelines.get(name) {
+            Ok(pipeline.clone())
+        } else {
+            let func = device
+                .new_library_with_source(source, &CompileOptions::new())
+                .map_err(|e| MetalKernelError::LoadLibraryError(e.to_string()))?
+                .get_function(name, None)
+                .map_err(|e| MetalKernelError::LoadFunctionError(e.to_string()))?;
+            let pipeline = device
+                .new_compute_pipeline_state_with_function(&func)
+                .map_err(|e| MetalKernelError::FailedToCreatePipeline(e.to_string()))?;
+            pipelines.insert(name.to_string(), pipeline.clone());
+
+            Ok(pipeline)
+        }
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -373,7 +407,7 @@ pub fn call_unary_strided(
     name: unary::strided::Kernel,
     shape: &[usize],
     input: &Buffer,
-    strides: &[usize],
+    strides: &[isize],
     offset: usize,
     output: &Buffer,
     output_offset: usize,
@@ -443,10 +477,10 @@ pub fn call_binary_strided(
     name: binary::strided::Kernel,
     shape: &[usize],
     left_input: &Buffer,
-    left_strides: &[usize],
+    left_strides: &[isize],
     left_offset: usize,
     right_input: &Buffer,
-    right_strides: &[usize],
+    right_strides: &[isize],
     right_offset: usize,
     output: &Buffer,
 ) -> Result<(), MetalKernelError> {
@@ -517,7 +551,7 @@ pub fn call_cast_strided(
     kernel_name: &'static str,
     shape: &[usize],
     input: &Buffer,
-    input_strides: &[usize],
+    input_strides: &[isize],
     input_offset: usize,
     output: &Buffer,
 ) -> Result<(), MetalKernelError> {
@@ -602,7 +636,7 @@ pub fn call_reduce_strided(
     kernels: &Kernels,
     kernel_name: &'static str,
     shape: &[usize],
-    strides: &[usize],
+    strides: &[isize],
     out_length: usize,
     input: &Buffer,
     input_offset: usize,
@@ -735,7 +769,7 @@ pub fn call_affine_strided(
     name: &'static str,
     shape: &[usize],
     input: &Buffer,
-    input_stride: &[usize],
+    input_stride: &[isize],
     input_offset: usize,
     output: &Buffer,
     mul: f32,
@@ -803,7 +837,7 @@ pub fn call_powf_strided(
     name: &'static str,
     shape: &[usize],
     input: &Buffer,
-    input_stride: &[usize],
+    input_stride: &[isize],
     input_offset: usize,
     output: &Buffer,
     mul: f32,
@@ -869,7 +903,7 @@ pub fn call_elu_strided(
     name: &'static str,
     shape: &[usize],
     input: &Buffer,
-    input_stride: &[usize],
+    input_stride: &[isize],
     input_offset: usize,
     output: &Buffer,
     mul: f32,
@@ -908,11 +942,11 @@ pub fn call_where_cond_strided(
     name: &'static str,
     shape: &[usize],
     cond: &Buffer,
-    (cond_stride, cond_offset): (&[usize], usize),
+    (cond_stride, cond_offset): (&[isize], usize),
     left: &Buffer,
-    (left_stride, left_offset): (&[usize], usize),
+    (left_stride, left_offset): (&[isize], usize),
     right: &Buffer,
-    (right_stride, right_offset): (&[usize], usize),
+    (right_stride, right_offset): (&[isize], usize),
     output: &Buffer,
 ) -> Result<(), MetalKernelError> {
     let pipeline = kernels.load_pipeline(device, Source::Ternary, name)?;
@@ -1489,6 +1523,58 @@ pub fn call_im2col_strided(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
+pub fn call_im2col3d_strided(
+    device: &Device,
+    command_buffer: &CommandBufferRef,
+    kernels: &Kernels,
+    name: &'static str,
+    shape: &[usize],
+    strides: &[usize],
+    (d_k, h_k, w_k, stride, padding, dilation): (usize, usize, usize, usize, usize, usize),
+    input: &Buffer,
+    input_offset: usize,
+    output: &Buffer,
+) -> Result<(), MetalKernelError> {
+    let pipeline = kernels.load_pipeline(device, Source::Conv, name)?;
+
+    let out = |i: usize, k: usize| (i + 2 * padding - dilation * (k - 1) - 1) / stride + 1;
+    let d_out = out(shape[2], d_k);
+    let h_out = out(shape[3], h_k);
+    let w_out = out(shape[4], w_k);
+
+    let dst_el = shape[0] * d_out * h_out * w_out * shape[1] * d_k * h_k * w_k;
+
+    let encoder = command_buffer.new_compute_command_encoder();
+    let (thread_group_count, thread_group_size) = linear_split(&pipeline, dst_el);
+    encoder.set_compute_pipeline_state(&pipeline);
+    set_params!(
+        encoder,
+        (
+            dst_el,
+            d_out,
+            h_out,
+            w_out,
+            d_k,
+            h_k,
+            w_k,
+            stride,
+            padding,
+            dilation,
+            shape,
+            strides,
+            (input, input_offset),
+            output
+        )
+    );
+    encoder.use_resource(input, metal::MTLResourceUsage::Read);
+    encoder.use_resource(output, metal::MTLResourceUsage::Write);
+    encoder.dispatch_thread_groups(thread_group_count, thread_group_size);
+    encoder.end_encoding();
+
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn call_upsample_nearest_2d(
     device: &Device,
@@ -1531,6 +1617,401 @@ pub fn call_upsample_nearest_2d(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
+pub fn call_conv_transpose1d(
+    device: &Device,
+    command_buffer: &CommandBufferRef,
+    kernels: &Kernels,
+    name: &'static str,
+    l_out: usize,
+    (stride, padding, out_padding, dilation): (usize, usize, usize, usize),
+    (shape, strides): (&[usize], &[usize]),
+    (k_shape, k_strides): (&[usize], &[usize]),
+    (input, input_offset): (&Buffer, usize),
+    (kernel, kernel_offset): (&Buffer, usize),
+    output: &Buffer,
+) -> Result<(), MetalKernelError> {
+    let pipeline = kernels.load_pipeline(device, Source::Conv, name)?;
+    let dst_el = shape[0] * k_shape[1] * l_out;
+    let (thread_group_count, thread_group_size) = linear_split(&pipeline, dst_el);
+    let encoder = command_buffer.new_compute_command_encoder();
+    encoder.set_compute_pipeline_state(&pipeline);
+    set_params!(
+        encoder,
+        (
+            l_out,
+            stride,
+            padding,
+            out_padding,
+            dilation,
+            shape,
+            strides,
+            k_shape,
+            k_strides,
+            (input, input_offset),
+            (kernel, kernel_offset),
+            output
+        )
+    );
+    encoder.use_resource(input, metal::MTLResourceUsage::Read);
+    encoder.use_resource(kernel, metal::MTLResourceUsage::Read);
+    encoder.use_resource(output, metal::MTLResourceUsage::Write);
+    encoder.dispatch_thread_groups(thread_group_count, thread_group_size);
+    encoder.end_encoding();
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn call_conv_transpose2d(
+    device: &Device,
+    command_buffer: &CommandBufferRef,
+    kernels: &Kernels,
+    name: &'static str,
+    (w_out, h_out): (usize, usize),
+    (stride, padding, out_padding, dilation): (usize, usize, usize, usize),
+    (shape, strides): (&[usize], &[usize]),
+    (k_shape, k_strides): (&[usize], &[usize]),
+    (input, input_offset): (&Buffer, usize),
+    (kernel, kernel_offset): (&Buffer, usize),
+    output: &Buffer,
+) -> Result<(), MetalKernelError> {
+    let pipeline = kernels.load_pipeline(device, Source::Conv, name)?;
+    let dst_el = shape[0] * k_shape[1] * w_out * h_out;
+    let (thread_group_count, thread_group_size) = linear_split(&pipeline, dst_el);
+    let encoder = command_buffer.new_compute_command_encoder();
+    encoder.set_compute_pipeline_state(&pipeline);
+    set_params!(
+        encoder,
+        (
+            w_out,
+            h_out,
+            stride,
+            padding,
+            out_padding,
+            dilation,
+            shape,
+            strides,
+            k_shape,
+            k_strides,
+            (input, input_offset),
+            (kernel, kernel_offset),
+            output
+        )
+    );
+    encoder.use_resource(input, metal::MTLResourceUsage::Read);
+    encoder.use_resource(kernel, metal::MTLResourceUsage::Read);
+    encoder.use_resource(output, metal::MTLResourceUsage::Write);
+    encoder.dispatch_thread_groups(thread_group_count, thread_group_size);
+    encoder.end_encoding();
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn call_conv_transpose3d(
+    device: &Device,
+    command_buffer: &CommandBufferRef,
+    kernels: &Kernels,
+    name: &'static str,
+    (d_out, h_out, w_out): (usize, usize, usize),
+    (stride, padding, dilation): (usize, usize, usize),
+    (shape, strides): (&[usize], &[usize]),
+    (k_shape, k_strides): (&[usize], &[usize]),
+    (input, input_offset): (&Buffer, usize),
+    (kernel, kernel_offset): (&Buffer, usize),
+    output: &Buffer,
+) -> Result<(), MetalKernelError> {
+    let pipeline = kernels.load_pipeline(device, Source::Conv, name)?;
+    let dst_el = shape[0] * k_shape[1] * d_out * h_out * w_out;
+    let (thread_group_count, thread_group_size) = linear_split(&pipeline, dst_el);
+    let encoder = command_buffer.new_compute_command_encoder();
+    encoder.set_compute_pipeline_state(&pipeline);
+    set_params!(
+        encoder,
+        (
+            d_out,
+            h_out,
+            w_out,
+            stride,
+            padding,
+            dilation,
+            shape,
+            strides,
+            k_shape,
+            k_strides,
+            (input, input_offset),
+            (kernel, kernel_offset),
+            output
+        )
+    );
+    encoder.use_resource(input, metal::MTLResourceUsage::Read);
+    encoder.use_resource(kernel, metal::MTLResourceUsage::Read);
+    encoder.use_resource(output, metal::MTLResourceUsage::Write);
+    encoder.dispatch_thread_groups(thread_group_count, thread_group_size);
+    encoder.end_encoding();
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn call_pool2d(
+    device: &Device,
+    command_buffer: &CommandBufferRef,
+    kernels: &Kernels,
+    name: &'static str,
+    shape: &[usize],
+    strides: &[usize],
+    (w_k, h_k): (usize, usize),
+    (w_stride, h_stride): (usize, usize),
+    input: &Buffer,
+    input_offset: usize,
+    output: &Buffer,
+) -> Result<(), MetalKernelError> {
+    let pipeline = kernels.load_pipeline(device, Source::Conv, name)?;
+    let w_out = (shape[2] - w_k) / w_stride + 1;
+    let h_out = (shape[3] - h_k) / h_stride + 1;
+    let dst_el = shape[0] * shape[1] * w_out * h_out;
+    let (thread_group_count, thread_group_size) = linear_split(&pipeline, dst_el);
+    let encoder = command_buffer.new_compute_command_encoder();
+    encoder.set_compute_pipeline_state(&pipeline);
+    set_params!(
+        encoder,
+        (
+            w_k,
+            h_k,
+            w_stride,
+            h_stride,
+            shape,
+            strides,
+            (input, input_offset),
+            output
+        )
+    );
+    encoder.use_resource(input, metal::MTLResourceUsage::Read);
+    encoder.use_resource(output, metal::MTLResourceUsage::Write);
+    encoder.dispatch_thread_groups(thread_group_count, thread_group_size);
+    encoder.end_encoding();
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn call_pool3d(
+    device: &Device,
+    command_buffer: &CommandBufferRef,
+    kernels: &Kernels,
+    name: &'static str,
+    shape: &[usize],
+    strides: &[usize],
+    k: (usize, usize, usize),
+    stride: (usize, usize, usize),
+    input: &Buffer,
+    input_offset: usize,
+    output: &Buffer,
+) -> Result<(), MetalKernelError> {
+    let pipeline = kernels.load_pipeline(device, Source::Conv, name)?;
+    let d_out = (shape[2] - k.0) / stride.0 + 1;
+    let h_out = (shape[3] - k.1) / stride.1 + 1;
+    let w_out = (shape[4] - k.2) / stride.2 + 1;
+    let dst_el = shape[0] * shape[1] * d_out * h_out * w_out;
+    let (thread_group_count, thread_group_size) = linear_split(&pipeline, dst_el);
+    let encoder = command_buffer.new_compute_command_encoder();
+    encoder.set_compute_pipeline_state(&pipeline);
+    let k = [k.0, k.1, k.2];
+    let stride = [stride.0, stride.1, stride.2];
+    set_params!(
+        encoder,
+        (
+            &k[..],
+            &stride[..],
+            shape,
+            strides,
+            (input, input_offset),
+            output
+        )
+    );
+    encoder.use_resource(input, metal::MTLResourceUsage::Read);
+    encoder.use_resource(output, metal::MTLResourceUsage::Write);
+    encoder.dispatch_thread_groups(thread_group_count, thread_group_size);
+    encoder.end_encoding();
+    Ok(())
+}
+
+/// Separable interpolation of the two last dimensions of a contiguous `(b, c, h_in, w_in)`
+/// input. `idxs` and `wgts` contain the interpolation weights along `h` then `w` in compressed
+/// sparse row format, see `resample.metal` for the exact layout.
+#[allow(clippy::too_many_arguments)]
+pub fn call_interpolate2d(
+    device: &Device,
+    command_buffer: &CommandBufferRef,
+    kernels: &Kernels,
+    name: &'static str,
+    (b_c, h_in, w_in): (usize, usize, usize),
+    (h_out, w_out): (usize, usize),
+    nnz_h: usize,
+    idxs: &Buffer,
+    wgts: &Buffer,
+    input: &Buffer,
+    input_offset: usize,
+    output: &Buffer,
+) -> Result<(), MetalKernelError> {
+    let pipeline = kernels.load_pipeline(device, Source::Resample, name)?;
+    let dst_el = b_c * h_out * w_out;
+    let (thread_group_count, thread_group_size) = linear_split(&pipeline, dst_el);
+    let encoder = command_buffer.new_compute_command_encoder();
+    encoder.set_compute_pipeline_state(&pipeline);
+    set_params!(
+        encoder,
+        (
+            dst_el,
+            h_in,
+            w_in,
+            h_out,
+            w_out,
+            nnz_h,
+            idxs,
+            wgts,
+            (input, input_offset),
+            output
+        )
+    );
+    encoder.use_resource(idxs, metal::MTLResourceUsage::Read);
+    encoder.use_resource(wgts, metal::MTLResourceUsage::Read);
+    encoder.use_resource(input, metal::MTLResourceUsage::Read);
+    encoder.use_resource(output, metal::MTLResourceUsage::Write);
+    encoder.dispatch_thread_groups(thread_group_count, thread_group_size);
+    encoder.end_encoding();
+    Ok(())
+}
+
+/// Samples a contiguous `(b, c, h, w)` input at the locations of a contiguous
+/// `(b, h_out, w_out, 2)` grid, `n_out` being `h_out * w_out`. `mode` and `padding` use the
+/// values defined in `resample.metal`.
+#[allow(clippy::too_many_arguments)]
+pub fn call_grid_sample(
+    device: &Device,
+    command_buffer: &CommandBufferRef,
+    kernels: &Kernels,
+    name: &'static str,
+    (b, c, h, w): (usize, usize, usize, usize),
+    n_out: usize,
+    (mode, padding, align_corners): (u32, u32, bool),
+    input: &Buffer,
+    input_offset: usize,
+    grid: &Buffer,
+    grid_offset: usize,
+    output: &Buffer,
+) -> Result<(), MetalKernelError> {
+    let pipeline = kernels.load_pipeline(device, Source::Resample, name)?;
+    let dst_el = b * c * n_out;
+    let (thread_group_count, thread_group_size) = linear_split(&pipeline, dst_el);
+    let encoder = command_buffer.new_compute_command_encoder();
+    encoder.set_compute_pipeline_state(&pipeline);
+    set_params!(
+        encoder,
+        (
+            dst_el,
+            c,
+            h,
+            w,
+            n_out,
+            mode,
+            padding,
+            align_corners as u32,
+            (input, input_offset),
+            (grid, grid_offset),
+            output
+        )
+    );
+    encoder.use_resource(input, metal::MTLResourceUsage::Read);
+    encoder.use_resource(grid, metal::MTLResourceUsage::Read);
+    encoder.use_resource(output, metal::MTLResourceUsage::Write);
+    encoder.dispatch_thread_groups(thread_group_count, thread_group_size);
+    encoder.end_encoding();
+    Ok(())
+}
+
+/// The backward pass of [`call_grid_sample`], `output` is a zero initialized f32 buffer that
+/// receives the gradients of the input followed by the gradients of the grid.
+#[allow(clippy::too_many_arguments)]
+pub fn call_grid_sample_bwd(
+    device: &Device,
+    command_buffer: &CommandBufferRef,
+    kernels: &Kernels,
+    name: &'static str,
+    (b, c, h, w): (usize, usize, usize, usize),
+    n_out: usize,
+    (mode, padding, align_corners): (u32, u32, bool),
+    input: &Buffer,
+    input_offset: usize,
+    grid: &Buffer,
+    grid_offset: usize,
+    grad: &Buffer,
+    grad_offset: usize,
+    output: &Buffer,
+) -> Result<(), MetalKernelError> {
+    let pipeline = kernels.load_pipeline(device, Source::Resample, name)?;
+    let el = b * n_out;
+    let (thread_group_count, thread_group_size) = linear_split(&pipeline, el);
+    let encoder = command_buffer.new_compute_command_encoder();
+    encoder.set_compute_pipeline_state(&pipeline);
+    set_params!(
+        encoder,
+        (
+            el,
+            c,
+            h,
+            w,
+            n_out,
+            mode,
+            padding,
+            align_corners as u32,
+            (input, input_offset),
+            (grid, grid_offset),
+            (grad, grad_offset),
+            output
+        )
+    );
+    encoder.use_resource(input, metal::MTLResourceUsage::Read);
+    encoder.use_resource(grid, metal::MTLResourceUsage::Read);
+    encoder.use_resource(grad, metal::MTLResourceUsage::Read);
+    encoder.use_resource(output, metal::MTLResourceUsage::Write);
+    encoder.dispatch_thread_groups(thread_group_count, thread_group_size);
+    encoder.end_encoding();
+    Ok(())
+}
+
+/// Runs an elementwise kernel generated at runtime, as used for the fused operations of lazy
+/// tensors. The kernel gets the number of elements, the `info` array, one buffer per input and
+/// finally the output buffer.
+#[allow(clippy::too_many_arguments)]
+pub fn call_generated_elementwise(
+    device: &Device,
+    command_buffer: &CommandBufferRef,
+    kernels: &Kernels,
+    source: &str,
+    name: &str,
+    length: usize,
+    info: &[i64],
+    inputs: &[(&Buffer, usize)],
+    output: &Buffer,
+) -> Result<(), MetalKernelError> {
+    let pipeline = kernels.load_pipeline_from_source(device, source, name)?;
+    let encoder = command_buffer.new_compute_command_encoder();
+    encoder.set_compute_pipeline_state(&pipeline);
+
+    set_param(encoder, 0, length);
+    set_param(encoder, 1, info);
+    for (index, &input) in inputs.iter().enumerate() {
+        set_param(encoder, 2 + index as u64, input);
+        encoder.use_resource(input.0, metal::MTLResourceUsage::Read);
+    }
+    set_param(encoder, 2 + inputs.len() as u64, output);
+
+    let (thread_group_count, thread_group_size) = linear_split(&pipeline, length);
+    encoder.use_resource(output, metal::MTLResourceUsage::Write);
+    encoder.dispatch_thread_groups(thread_group_count, thread_group_size);
+    encoder.end_encoding();
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn call_random_uniform(
     device: &Device,
@@ -1612,6 +2093,8 @@ pub enum GgmlDType {
     Q5K,
     Q6K,
     Q8K,
+    IQ4NL,
+    IQ4XS,
     F16,
     F32,
 }
@@ -1657,7 +2140,9 @@ pub fn call_quantized_matmul_t(
         | GgmlDType::Q5_0
         | GgmlDType::Q5_1
         | GgmlDType::Q8_0
-        | GgmlDType::Q8_1 => {
+        | GgmlDType::Q8_1
+        | GgmlDType::IQ4NL
+        | GgmlDType::IQ4XS => {
             let nth0 = 8;
             let nth1 = 8;
             let align = 8;
@@ -1725,6 +2210,8 @@ pub fn call_quantized_matmul_t(
         GgmlDType::Q5K => "kernel_mul_mv_q5_K_f32",
         GgmlDType::Q6K => "kernel_mul_mv_q6_K_f32",
         GgmlDType::Q8K => "kernel_mul_mv_q8_K_f32",
+        GgmlDType::IQ4NL => "kernel_mul_mv_iq4_nl_f32",
+        GgmlDType::IQ4XS => "kernel_mul_mv_iq4_xs_f32",
         GgmlDType::F16 => "kernel_mul_mv_f16_f32",
         GgmlDType::F32 => "kernel_mul_mv_f32_f32",
     };
@@ -4,16 +4,18 @@ using namespace metal;
 #define MAX(x, y) ((x) > (y) ? (x) : (y))
 #define MIN(x, y) ((x) < (y) ? (x) : (y))
 
-METAL_FUNC uint get_strided_index(
+// Strides are signed, a negative stride walks the dimension backwards. They are
+// passed through a size_t buffer and reinterpreted here.
+METAL_FUNC long get_strided_index(
     uint idx,
     constant size_t &num_dims,
     constant size_t *dims,
     constant size_t *strides
 ) {
-    uint strided_i = 0;
+    long strided_i = 0;
     for (uint d = 0; d < num_dims; d++) {
         uint dim_idx = num_dims - 1 - d;
-        strided_i += (idx % dims[dim_idx]) * strides[dim_idx];
+        strided_i += long(idx % dims[dim_idx]) * long(strides[dim_idx]);
         idx /= dims[dim_idx];
     }
     return strided_i;
@@ -53,7 +55,7 @@ kernel void NAME( \
      /*  \
      // TODO: Fast version for the contiguous case.  \
      */  \
-     size_t strided_i = get_strided_index(idx, num_dims, dims, strides);  \
+     long strided_i = get_strided_index(idx, num_dims, dims, strides);  \
      if (notset || src[strided_i] < shared_memory[tid]) {  \
          shared_memory[tid] = src[strided_i];  \
           /* Assume that the reduction takes place over the last dimension which is contiguous. */ \
@@ -113,7 +115,7 @@ kernel void NAME( \
      /*  \
      // TODO: Fast version for the contiguous case.  \
      */  \
-     size_t strided_i = get_strided_index(idx, num_dims, dims, strides);  \
+     long strided_i = get_strided_index(idx, num_dims, dims, strides);  \
      if (notset || shared_memory[tid] < src[strided_i]) {  \
          shared_memory[tid] = src[strided_i];  \
          shared_indices[tid] = idx % dims[num_dims - 1]; \
@@ -168,7 +170,7 @@ kernel void NAME( \
      /* \
      // TODO: Fast version for the contiguous case. \
      */ \
-     size_t strided_i = get_strided_index(idx, num_dims, dims, strides); \
+     long strided_i = get_strided_index(idx, num_dims, dims, strides); \
      T x = shared_memory[tid]; \
      T y = src[strided_i]; \
      shared_memory[tid] = FN; \
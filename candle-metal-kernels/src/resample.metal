@@ -0,0 +1,332 @@
+#include <metal_stdlib>
+#include <metal_atomic>
+using namespace metal;
+
+// The grid sampling modes and paddings, these have to match the values used in
+// candle-core/src/resample.rs.
+#define GRID_SAMPLE_BILINEAR 0
+#define GRID_SAMPLE_NEAREST 1
+#define PADDING_ZEROS 0
+#define PADDING_BORDER 1
+#define PADDING_REFLECTION 2
+
+// Separable interpolation of the two last dimensions of a contiguous (b, c, h_in, w_in) tensor.
+// The weights along each dimension are in compressed sparse row format, `idxs` contains the
+// row offsets for h (h_out + 1 values), the source indexes for h (nnz_h values), then the row
+// offsets and source indexes for w. `wgts` contains the nnz_h weights for h followed by the ones
+// for w.
+template <typename T>
+METAL_FUNC void interpolate2d(
+    constant size_t &numel,
+    constant size_t &h_in,
+    constant size_t &w_in,
+    constant size_t &h_out,
+    constant size_t &w_out,
+    constant size_t &nnz_h,
+    device const uint *idxs,
+    device const float *wgts,
+    device const T *src,
+    device T *dst,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  if (tid >= numel) {
+    return;
+  }
+  device const uint *h_offsets = idxs;
+  device const uint *h_idxs = h_offsets + h_out + 1;
+  device const uint *w_offsets = h_idxs + nnz_h;
+  device const uint *w_idxs = w_offsets + w_out + 1;
+  device const float *h_wgts = wgts;
+  device const float *w_wgts = wgts + nnz_h;
+
+  const size_t x = tid % w_out;
+  const size_t y = (tid / w_out) % h_out;
+  const size_t bc = tid / (w_out * h_out);
+  device const T *s = src + bc * h_in * w_in;
+  float d = 0;
+  for (uint k = h_offsets[y]; k < h_offsets[y + 1]; ++k) {
+    device const T *row = s + h_idxs[k] * w_in;
+    float r = 0;
+    for (uint l = w_offsets[x]; l < w_offsets[x + 1]; ++l) {
+      r += static_cast<float>(row[w_idxs[l]]) * w_wgts[l];
+    }
+    d += r * h_wgts[k];
+  }
+  dst[tid] = static_cast<T>(d);
+}
+
+METAL_FUNC float clip_coordinates(const float coord, const float size, thread float &grad) {
+  if (coord <= 0) {
+    grad = 0;
+    return 0;
+  }
+  if (coord >= size - 1) {
+    grad = 0;
+    return size - 1;
+  }
+  return coord;
+}
+
+METAL_FUNC float reflect_coordinates(
+    float coord,
+    const float twice_low,
+    const float twice_high,
+    thread float &grad
+) {
+  if (twice_low == twice_high) {
+    grad = 0;
+    return 0;
+  }
+  const float min = twice_low / 2;
+  const float span = (twice_high - twice_low) / 2;
+  coord -= min;
+  float sign = 1;
+  if (coord < 0) {
+    coord = -coord;
+    sign = -1;
+  }
+  const float flips = floor(coord / span);
+  const float extra = coord - flips * span;
+  if (flips - 2 * floor(flips / 2) == 0) {
+    grad *= sign;
+    return extra + min;
+  }
+  grad *= -sign;
+  return span - extra + min;
+}
+
+// Converts a normalized coordinate in [-1, 1] to a pixel coordinate and applies the padding
+// mode, the derivative of the pixel coordinate with respect to the normalized one is written
+// to `grad`. See `grid_sampler_compute_source_index_set_grad` in PyTorch.
+METAL_FUNC float grid_source_index(
+    float coord,
+    const size_t size,
+    const uint padding,
+    const uint align_corners,
+    thread float &grad
+) {
+  const float size_f = static_cast<float>(size);
+  if (align_corners) {
+    coord = (coord + 1) / 2 * (size_f - 1);
+    grad = (size_f - 1) / 2;
+  } else {
+    coord = ((coord + 1) * size_f - 1) / 2;
+    grad = size_f / 2;
+  }
+  if (padding == PADDING_BORDER) {
+    coord = clip_coordinates(coord, size_f, grad);
+  } else if (padding == PADDING_REFLECTION) {
+    if (align_corners) {
+      coord = reflect_coordinates(coord, 0, 2 * (size_f - 1), grad);
+    } else {
+      coord = reflect_coordinates(coord, -1, 2 * size_f - 1, grad);
+    }
+    coord = clip_coordinates(coord, size_f, grad);
+  }
+  return coord;
+}
+
+// Sets `idx` to the flat index of pixel (x, y) in a (h, w) image, returns false when out of
+// bounds.
+METAL_FUNC bool pixel_index(const float x, const float y, const size_t h, const size_t w, thread size_t &idx) {
+  if (x >= 0 && y >= 0 && x < static_cast<float>(w) && y < static_cast<float>(h)) {
+    idx = static_cast<size_t>(y) * w + static_cast<size_t>(x);
+    return true;
+  }
+  return false;
+}
+
+// There is no atomic add on floats before metal 3, use a compare and swap loop instead.
+METAL_FUNC void atomic_add_float(device float *addr, const float val) {
+  device atomic_uint *a = (device atomic_uint *)addr;
+  uint old = atomic_load_explicit(a, memory_order_relaxed);
+  while (!atomic_compare_exchange_weak_explicit(
+      a, &old, as_type<uint>(as_type<float>(old) + val), memory_order_relaxed, memory_order_relaxed)) {
+  }
+}
+
+// xs: (b, c, h, w), grid: (b, n_out, 2), dst: (b, c, n_out), all contiguous.
+template <typename T>
+METAL_FUNC void grid_sample(
+    constant size_t &numel,
+    constant size_t &c,
+    constant size_t &h,
+    constant size_t &w,
+    constant size_t &n_out,
+    constant uint &mode,
+    constant uint &padding,
+    constant uint &align_corners,
+    device const T *xs,
+    device const T *grid,
+    device T *dst,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  if (tid >= numel) {
+    return;
+  }
+  const size_t p = tid % n_out;
+  const size_t bc = tid / n_out;
+  const size_t b = bc / c;
+  device const T *g = grid + (b * n_out + p) * 2;
+  float gx, gy;
+  const float x = grid_source_index(static_cast<float>(g[0]), w, padding, align_corners, gx);
+  const float y = grid_source_index(static_cast<float>(g[1]), h, padding, align_corners, gy);
+  device const T *src = xs + bc * h * w;
+  float d = 0;
+  size_t idx;
+  if (mode == GRID_SAMPLE_NEAREST) {
+    if (pixel_index(rint(x), rint(y), h, w, idx)) {
+      d = static_cast<float>(src[idx]);
+    }
+  } else {
+    const float x0 = floor(x);
+    const float y0 = floor(y);
+    const float x1 = x0 + 1;
+    const float y1 = y0 + 1;
+    if (pixel_index(x0, y0, h, w, idx)) {
+      d += static_cast<float>(src[idx]) * (x1 - x) * (y1 - y);
+    }
+    if (pixel_index(x1, y0, h, w, idx)) {
+      d += static_cast<float>(src[idx]) * (x - x0) * (y1 - y);
+    }
+    if (pixel_index(x0, y1, h, w, idx)) {
+      d += static_cast<float>(src[idx]) * (x1 - x) * (y - y0);
+    }
+    if (pixel_index(x1, y1, h, w, idx)) {
+      d += static_cast<float>(src[idx]) * (x - x0) * (y - y0);
+    }
+  }
+  dst[tid] = static_cast<T>(d);
+}
+
+// One thread per (b, p) grid location, the gradients of the input are accumulated with atomic
+// adds in the first b * c * h * w elements of `grads` which has to be zero initialized, the
+// gradients of the grid are written in the remaining b * n_out * 2 elements.
+template <typename T>
+METAL_FUNC void grid_sample_bwd(
+    constant size_t &numel,
+    constant size_t &c,
+    constant size_t &h,
+    constant size_t &w,
+    constant size_t &n_out,
+    constant uint &mode,
+    constant uint &padding,
+    constant uint &align_corners,
+    device const T *xs,
+    device const T *grid,
+    device const T *grad,
+    device float *grads,
+    uint tid [[ thread_position_in_grid ]]
+) {
+  if (tid >= numel) {
+    return;
+  }
+  const size_t b = tid / n_out;
+  const size_t p = tid % n_out;
+  device const T *g = grid + tid * 2;
+  float gx, gy;
+  const float x = grid_source_index(static_cast<float>(g[0]), w, padding, align_corners, gx);
+  const float y = grid_source_index(static_cast<float>(g[1]), h, padding, align_corners, gy);
+  const float x0 = floor(x);
+  const float y0 = floor(y);
+  const float x1 = x0 + 1;
+  const float y1 = y0 + 1;
+  const float cx[4] = {x0, x1, x0, x1};
+  const float cy[4] = {y0, y0, y1, y1};
+  const float wgt[4] = {(x1 - x) * (y1 - y), (x - x0) * (y1 - y), (x1 - x) * (y - y0), (x - x0) * (y - y0)};
+  const float dw_dx[4] = {-(y1 - y), y1 - y, -(y - y0), y - y0};
+  const float dw_dy[4] = {-(x1 - x), -(x - x0), x1 - x, x - x0};
+  float grad_x = 0;
+  float grad_y = 0;
+  size_t idx;
+  for (size_t ch = 0; ch < c; ++ch) {
+    const size_t bc = b * c + ch;
+    const float go = static_cast<float>(grad[bc * n_out + p]);
+    device const T *src = xs + bc * h * w;
+    device float *grad_src = grads + bc * h * w;
+    if (mode == GRID_SAMPLE_NEAREST) {
+      if (pixel_index(rint(x), rint(y), h, w, idx)) {
+        atomic_add_float(grad_src + idx, go);
+      }
+    } else {
+      for (int k = 0; k < 4; ++k) {
+        if (pixel_index(cx[k], cy[k], h, w, idx)) {
+          const float v = static_cast<float>(src[idx]);
+          atomic_add_float(grad_src + idx, wgt[k] * go);
+          grad_x += v * dw_dx[k] * go;
+          grad_y += v * dw_dy[k] * go;
+        }
+      }
+    }
+  }
+  device float *grad_grid = grads + numel / n_out * c * h * w;
+  grad_grid[2 * tid] = grad_x * gx;
+  grad_grid[2 * tid + 1] = grad_y * gy;
+}
+
+#define INTERPOLATE2D_OP(TYPENAME, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t &numel, \
+    constant size_t &h_in, \
+    constant size_t &w_in, \
+    constant size_t &h_out, \
+    constant size_t &w_out, \
+    constant size_t &nnz_h, \
+    device const uint *idxs, \
+    device const float *wgts, \
+    device const TYPENAME *src, \
+    device TYPENAME *dst, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  interpolate2d<TYPENAME>(numel, h_in, w_in, h_out, w_out, nnz_h, idxs, wgts, src, dst, tid); \
+} \
+
+#define GRID_SAMPLE_OP(TYPENAME, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t &numel, \
+    constant size_t &c, \
+    constant size_t &h, \
+    constant size_t &w, \
+    constant size_t &n_out, \
+    constant uint &mode, \
+    constant uint &padding, \
+    constant uint &align_corners, \
+    device const TYPENAME *xs, \
+    device const TYPENAME *grid, \
+    device TYPENAME *dst, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  grid_sample<TYPENAME>(numel, c, h, w, n_out, mode, padding, align_corners, xs, grid, dst, tid); \
+} \
+
+#define GRID_SAMPLE_BWD_OP(TYPENAME, FN_NAME) \
+kernel void FN_NAME(  \
+    constant size_t &numel, \
+    constant size_t &c, \
+    constant size_t &h, \
+    constant size_t &w, \
+    constant size_t &n_out, \
+    constant uint &mode, \
+    constant uint &padding, \
+    constant uint &align_corners, \
+    device const TYPENAME *xs, \
+    device const TYPENAME *grid, \
+    device const TYPENAME *grad, \
+    device float *grads, \
+    uint tid [[ thread_position_in_grid ]] \
+) {  \
+  grid_sample_bwd<TYPENAME>(numel, c, h, w, n_out, mode, padding, align_corners, xs, grid, grad, grads, tid); \
+} \
+
+INTERPOLATE2D_OP(float, interpolate2d_f32)
+INTERPOLATE2D_OP(half, interpolate2d_f16)
+GRID_SAMPLE_OP(float, grid_sample_f32)
+GRID_SAMPLE_OP(half, grid_sample_f16)
+GRID_SAMPLE_BWD_OP(float, grid_sample_bwd_f32)
+GRID_SAMPLE_BWD_OP(half, grid_sample_bwd_f16)
+
+#if defined(__HAVE_BFLOAT__)
+INTERPOLATE2D_OP(bfloat, interpolate2d_bf16)
+GRID_SAMPLE_OP(bfloat, grid_sample_bf16)
+GRID_SAMPLE_BWD_OP(bfloat, grid_sample_bwd_bf16)
+#endif
@@ -2,16 +2,18 @@
 #
 using namespace metal;
 
-METAL_FUNC uint get_strided_index(
+// Strides are signed, a negative stride walks the dimension backwards. They are
+// passed through a size_t buffer and reinterpreted here.
+METAL_FUNC long get_strided_index(
     uint idx,
     constant size_t &num_dims,
     constant size_t *dims,
     constant size_t *strides
 ) {
-    uint strided_i = 0;
+    long strided_i = 0;
     for (uint d = 0; d < num_dims; d++) {
         uint dim_idx = num_dims - 1 - d;
-        strided_i += (idx % dims[dim_idx]) * strides[dim_idx];
+        strided_i += long(idx % dims[dim_idx]) * long(strides[dim_idx]);
         idx /= dims[dim_idx];
     }
     return strided_i;
@@ -34,9 +36,9 @@ METAL_FUNC void where_cond(
     if (i >= numel){
        return;
     }
-    uint strided_i = get_strided_index(i, num_dims, dims, strides);
-    uint strided_i_t = get_strided_index(i, num_dims, dims, strides_t);
-    uint strided_i_f = get_strided_index(i, num_dims, dims, strides_f);
+    long strided_i = get_strided_index(i, num_dims, dims, strides);
+    long strided_i_t = get_strided_index(i, num_dims, dims, strides_t);
+    long strided_i_f = get_strided_index(i, num_dims, dims, strides_f);
     out[i] = ids[strided_i] ? t[strided_i_t] : f[strided_i_f];
 }
 
@@ -86,7 +86,7 @@ fn run_strided<T: Clone>(
     v: &[T],
     kernel: unary::strided::Kernel,
     shape: &[usize],
-    strides: &[usize],
+    strides: &[isize],
     offset: usize,
 ) -> Vec<T> {
     let device = device();
@@ -177,6 +177,17 @@ fn cos_f32_strided() {
         vec![0.5403, -0.4161, -0.99, -0.6536, 0.2837, 0.9602]
     );
 
+    // Flipped
+    let v = vec![1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0];
+    let shape = vec![3, 2];
+    let strides = vec![-2, 1];
+    let offset = 4 * std::mem::size_of::<f32>();
+    let results = run_strided(&v, unary::strided::cos::FLOAT, &shape, &strides, offset);
+    assert_eq!(
+        approx(results, 4),
+        vec![0.2837, 0.9602, -0.99, -0.6536, 0.5403, -0.4161]
+    );
+
     // Very large
     let v = vec![1.0f32; 10_000];
     let shape = vec![2, 5_000];
@@ -454,7 +465,7 @@ fn run_affine<T: Clone>(v: &[T], mul: f64, add: f64) -> Vec<T> {
 fn run_affine_strided<T: Clone>(
     v: &[T],
     shape: &[usize],
-    strides: &[usize],
+    strides: &[isize],
     mul: f64,
     add: f64,
 ) -> Vec<T> {
@@ -783,9 +794,9 @@ fn softmax() {
 fn run_where_cond<I: Clone, T: Clone>(
     shape: &[usize],
     cond: &[I],
-    (cond_stride, cond_offset): (Vec<usize>, usize),
+    (cond_stride, cond_offset): (Vec<isize>, usize),
     left_true: &[T],
-    (left_stride, left_offset): (Vec<usize>, usize),
+    (left_stride, left_offset): (Vec<isize>, usize),
     right_false: &[T],
     (_right_stride, _right_offset): (Vec<usize>, usize),
     name: &'static str,
@@ -3,16 +3,18 @@
 #
 using namespace metal;
 
-METAL_FUNC uint get_strided_index(
+// Strides are signed, a negative stride walks the dimension backwards. They are
+// passed through a size_t buffer and reinterpreted here.
+METAL_FUNC long get_strided_index(
     uint idx,
     constant size_t &num_dims,
     constant size_t *dims,
     constant size_t *strides
 ) {
-    uint strided_i = 0;
+    long strided_i = 0;
     for (uint d = 0; d < num_dims; d++) {
         uint dim_idx = num_dims - 1 - d;
-        strided_i += (idx % dims[dim_idx]) * strides[dim_idx];
+        strided_i += long(idx % dims[dim_idx]) * long(strides[dim_idx]);
         idx /= dims[dim_idx];
     }
     return strided_i;
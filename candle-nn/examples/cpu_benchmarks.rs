@@ -56,7 +56,10 @@ impl candle::CustomOp1 for Im2Col {
         let src = &slice[layout.start_offset()..];
         let mut dst = vec![0f32; b * h_out * w_out * c * h_k * w_k];
         let (src_s0, src_s1, src_s2, src_s3) = {
-            let s = layout.stride();
+            let s = match layout.unsigned_stride() {
+                Some(s) => s,
+                None => candle::bail!("negative strides are not supported"),
+            };
             (s[0], s[1], s[2], s[3])
         };
         // TODO: provide specialized kernels for the common use cases.
@@ -0,0 +1,440 @@
+//! Multi-head attention.
+//!
+//! [`MultiHeadAttention`] supports self and cross-attention, grouped-query and multi-query
+//! attention through a number of key/value heads smaller than the number of query heads, rotary
+//! or ALiBi position encodings, additive masks built with [`causal_mask`] and [`padding_mask`],
+//! and incremental decoding with a [`KvCache`].
+//!
+//! The inputs are batch first, i.e. of shape `(batch, seq_len, dim)`. The weights use the same
+//! names as PyTorch's `nn.MultiheadAttention`: `in_proj_weight` and `in_proj_bias` hold the packed
+//! query, key and value projections when the key and value dimensions match the embedding
+//! dimension, otherwise `q_proj_weight`, `k_proj_weight` and `v_proj_weight` are used, and the
+//! output projection is `out_proj`.
+//!
+//! ```rust
+//! use candle::{DType, Device, Tensor};
+//! use candle_nn::attention::{causal_mask, multi_head_attention, MultiHeadAttentionConfig};
+//! use candle_nn::VarBuilder;
+//! # fn main() -> candle::Result<()> {
+//! let dev = Device::Cpu;
+//! let vb = VarBuilder::zeros(DType::F32, &dev);
+//! let mha = multi_head_attention(16, MultiHeadAttentionConfig::new(4), vb)?;
+//! let xs = Tensor::randn(0f32, 1., (2, 5, 16), &dev)?;
+//! let mask = causal_mask(5, 5, DType::F32, &dev)?;
+//! let ys = mha.forward(&xs, &xs, &xs, Some(&mask))?;
+//! assert_eq!(ys.dims(), &[2, 5, 16]);
+//! # Ok(()) }
+//! ```
+use crate::{Linear, Module};
+use candle::{DType, Device, Result, Tensor, D};
+
+/// Returns an additive mask of shape `(q_len, kv_len)` that prevents the queries from attending
+/// to future keys. The queries are assumed to be the last `q_len` positions of the `kv_len` keys,
+/// so this can be used together with a [`KvCache`].
+pub fn causal_mask(q_len: usize, kv_len: usize, dtype: DType, device: &Device) -> Result<Tensor> {
+    if q_len > kv_len {
+        candle::bail!("causal mask with more queries {q_len} than keys {kv_len}")
+    }
+    let offset = kv_len - q_len;
+    let mask: Vec<f32> = (0..q_len)
+        .flat_map(|i| {
+            (0..kv_len).map(move |j| {
+                if j > i + offset {
+                    f32::NEG_INFINITY
+                } else {
+                    0.
+                }
+            })
+        })
+        .collect();
+    Tensor::from_vec(mask, (q_len, kv_len), device)?.to_dtype(dtype)
+}
+
+/// Converts a key padding mask of shape `(batch, kv_len)`, where non-zero values mark the padding
+/// positions as in PyTorch's `key_padding_mask`, to an additive mask of shape
+/// `(batch, 1, 1, kv_len)`.
+pub fn padding_mask(key_padding_mask: &Tensor, dtype: DType) -> Result<Tensor> {
+    let (b_sz, kv_len) = key_padding_mask.dims2()?;
+    let device = key_padding_mask.device();
+    let zeros = Tensor::zeros((b_sz, kv_len), dtype, device)?;
+    let neg_inf = Tensor::full(f32::NEG_INFINITY, (b_sz, kv_len), device)?.to_dtype(dtype)?;
+    key_padding_mask
+        .ne(0u32)?
+        .where_cond(&neg_inf, &zeros)?
+        .reshape((b_sz, 1, 1, kv_len))
+}
+
+/// The ALiBi slopes for `num_heads` heads, following the reference implementation when the number
+/// of heads is not a power of two.
+pub fn alibi_slopes(num_heads: usize) -> Vec<f32> {
+    let closest = 1usize << (usize::BITS - 1 - num_heads.leading_zeros());
+    let base = 2f32.powf(-8. / closest as f32);
+    let mut slopes = (1..=closest)
+        .map(|i| base.powi(i as i32))
+        .collect::<Vec<_>>();
+    if closest < num_heads {
+        let extra_base = 2f32.powf(-4. / closest as f32);
+        let extra = (0..num_heads - closest).map(|i| extra_base.powi(2 * i as i32 + 1));
+        slopes.extend(extra)
+    }
+    slopes
+}
+
+/// Rotary position embeddings, using the "rotate half" layout of GPT-NeoX and Llama.
+#[derive(Clone, Debug)]
+pub struct RotaryEmbedding {
+    sin: Tensor,
+    cos: Tensor,
+}
+
+impl RotaryEmbedding {
+    pub fn new(head_dim: usize, max_seq_len: usize, theta: f64, device: &Device) -> Result<Self> {
+        if head_dim % 2 != 0 {
+            candle::bail!("rotary embeddings require an even head dim, got {head_dim}")
+        }
+        let inv_freq: Vec<f32> = (0..head_dim / 2)
+            .map(|i| 1. / theta.powf(2. * i as f64 / head_dim as f64) as f32)
+            .collect();
+        let inv_freq = Tensor::from_vec(inv_freq, (1, head_dim / 2), device)?;
+        let positions = Tensor::arange(0u32, max_seq_len as u32, device)?
+            .to_dtype(DType::F32)?
+            .reshape((max_seq_len, 1))?;
+        let freqs = positions.broadcast_mul(&inv_freq)?;
+        let freqs = Tensor::cat(&[&freqs, &freqs], D::Minus1)?;
+        Ok(Self {
+            sin: freqs.sin()?,
+            cos: freqs.cos()?,
+        })
+    }
+
+    /// Applies the embeddings to `xs` of shape `(batch, num_heads, seq_len, head_dim)`, the first
+    /// element of the sequence being at position `offset`.
+    pub fn apply(&self, xs: &Tensor, offset: usize) -> Result<Tensor> {
+        let (_b_sz, _num_heads, seq_len, head_dim) = xs.dims4()?;
+        let cos = self.cos.narrow(0, offset, seq_len)?.to_dtype(xs.dtype())?;
+        let sin = self.sin.narrow(0, offset, seq_len)?.to_dtype(xs.dtype())?;
+        let x1 = xs.narrow(D::Minus1, 0, head_dim / 2)?;
+        let x2 = xs.narrow(D::Minus1, head_dim / 2, head_dim / 2)?;
+        let rotated = Tensor::cat(&[&x2.neg()?, &x1], D::Minus1)?;
+        xs.broadcast_mul(&cos)? + rotated.broadcast_mul(&sin)?
+    }
+}
+
+/// The keys and values of the previous positions, used for incremental decoding with
+/// self-attention. The tensors have shape `(batch, num_kv_heads, seq_len, head_dim)`.
+#[derive(Clone, Debug, Default)]
+pub struct KvCache {
+    kv: Option<(Tensor, Tensor)>,
+}
+
+impl KvCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of cached positions.
+    pub fn current_seq_len(&self) -> usize {
+        match &self.kv {
+            None => 0,
+            Some((k, _)) => k.dims()[2],
+        }
+    }
+
+    pub fn kv(&self) -> Option<&(Tensor, Tensor)> {
+        self.kv.as_ref()
+    }
+
+    pub fn reset(&mut self) {
+        self.kv = None
+    }
+
+    /// Appends the new keys and values and returns the keys and values for all the positions.
+    pub fn append(&mut self, k: &Tensor, v: &Tensor) -> Result<(Tensor, Tensor)> {
+        let (k, v) = match &self.kv {
+            None => (k.clone(), v.clone()),
+            Some((prev_k, prev_v)) => {
+                (Tensor::cat(&[prev_k, k], 2)?, Tensor::cat(&[prev_v, v], 2)?)
+            }
+        };
+        self.kv = Some((k.clone(), v.clone()));
+        Ok((k, v))
+    }
+}
+
+/// The position encoding applied by the attention layer.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PositionEncoding {
+    /// No position encoding, e.g. when positions are added to the input embeddings.
+    #[default]
+    None,
+    /// Rotary embeddings applied to the queries and keys.
+    Rotary { theta: f64, max_seq_len: usize },
+    /// Linear biases added to the attention scores, based on the distance between positions.
+    Alibi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MultiHeadAttentionConfig {
+    pub num_heads: usize,
+    /// The number of key and value heads, smaller than `num_heads` for grouped-query attention
+    /// and 1 for multi-query attention.
+    pub num_kv_heads: usize,
+    /// The dimension of the key input, defaults to the embedding dimension.
+    pub kdim: Option<usize>,
+    /// The dimension of the value input, defaults to the embedding dimension.
+    pub vdim: Option<usize>,
+    pub bias: bool,
+    pub position: PositionEncoding,
+}
+
+impl MultiHeadAttentionConfig {
+    pub fn new(num_heads: usize) -> Self {
+        Self {
+            num_heads,
+            num_kv_heads: num_heads,
+            kdim: None,
+            vdim: None,
+            bias: true,
+            position: PositionEncoding::None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MultiHeadAttention {
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    out_proj: Linear,
+    num_heads: usize,
+    num_kv_heads: usize,
+    head_dim: usize,
+    rotary: Option<RotaryEmbedding>,
+    alibi_slopes: Option<Tensor>,
+}
+
+impl MultiHeadAttention {
+    /// Creates the layer from its projections, `q_proj` maps to `num_heads * head_dim` and
+    /// `k_proj`, `v_proj` map to `num_kv_heads * head_dim` dimensions.
+    pub fn new(
+        q_proj: Linear,
+        k_proj: Linear,
+        v_proj: Linear,
+        out_proj: Linear,
+        config: MultiHeadAttentionConfig,
+    ) -> Result<Self> {
+        let MultiHeadAttentionConfig {
+            num_heads,
+            num_kv_heads,
+            ..
+        } = config;
+        if num_kv_heads == 0 || num_heads % num_kv_heads != 0 {
+            candle::bail!("num_heads {num_heads} is not a multiple of num_kv_heads {num_kv_heads}")
+        }
+        let embed_dim = q_proj.weight().dim(0)?;
+        if embed_dim % num_heads != 0 {
+            candle::bail!("embed_dim {embed_dim} is not a multiple of num_heads {num_heads}")
+        }
+        let head_dim = embed_dim / num_heads;
+        let device = q_proj.weight().device();
+        let (rotary, alibi_slopes) = match config.position {
+            PositionEncoding::None => (None, None),
+            PositionEncoding::Rotary { theta, max_seq_len } => {
+                let rotary = RotaryEmbedding::new(head_dim, max_seq_len, theta, device)?;
+                (Some(rotary), None)
+            }
+            PositionEncoding::Alibi => {
+                let slopes = alibi_slopes(num_heads);
+                let slopes = Tensor::from_vec(slopes, (num_heads, 1, 1), device)?;
+                (None, Some(slopes))
+            }
+        };
+        Ok(Self {
+            q_proj,
+            k_proj,
+            v_proj,
+            out_proj,
+            num_heads,
+            num_kv_heads,
+            head_dim,
+            rotary,
+            alibi_slopes,
+        })
+    }
+
+    pub fn num_heads(&self) -> usize {
+        self.num_heads
+    }
+
+    pub fn num_kv_heads(&self) -> usize {
+        self.num_kv_heads
+    }
+
+    pub fn head_dim(&self) -> usize {
+        self.head_dim
+    }
+
+    // (b, seq_len, num_heads * head_dim) -> (b, num_heads, seq_len, head_dim)
+    fn split_heads(&self, xs: &Tensor, num_heads: usize) -> Result<Tensor> {
+        let (b_sz, seq_len, _) = xs.dims3()?;
+        xs.reshape((b_sz, seq_len, num_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()
+    }
+
+    // Repeats the key/value heads so that each query head has its own key/value head.
+    fn repeat_kv(&self, xs: Tensor) -> Result<Tensor> {
+        let n_rep = self.num_heads / self.num_kv_heads;
+        if n_rep == 1 {
+            return Ok(xs);
+        }
+        let (b_sz, num_kv_heads, seq_len, head_dim) = xs.dims4()?;
+        xs.unsqueeze(2)?
+            .broadcast_as((b_sz, num_kv_heads, n_rep, seq_len, head_dim))?
+            .reshape((b_sz, num_kv_heads * n_rep, seq_len, head_dim))
+    }
+
+    // The ALiBi biases of shape (num_heads, q_len, kv_len), -slope * |i - j|. The queries are
+    // aligned with the end of the keys, the offset is negative for cross-attention when the
+    // query is longer than the key/value sequence.
+    fn alibi_bias(&self, slopes: &Tensor, q_len: usize, kv_len: usize) -> Result<Tensor> {
+        let device = slopes.device();
+        let offset = kv_len as i64 - q_len as i64;
+        let q_pos = Tensor::arange(offset, offset + q_len as i64, device)?
+            .to_dtype(DType::F32)?
+            .reshape((q_len, 1))?;
+        let k_pos = Tensor::arange(0i64, kv_len as i64, device)?
+            .to_dtype(DType::F32)?
+            .reshape((1, kv_len))?;
+        let distance = q_pos.broadcast_sub(&k_pos)?.abs()?;
+        slopes.broadcast_mul(&distance.unsqueeze(0)?)?.neg()
+    }
+
+    fn attend(
+        &self,
+        query: &Tensor,
+        key: &Tensor,
+        value: &Tensor,
+        mask: Option<&Tensor>,
+        cache: Option<&mut KvCache>,
+    ) -> Result<Tensor> {
+        let (b_sz, q_len, _) = query.dims3()?;
+        let q = self.split_heads(&self.q_proj.forward(query)?, self.num_heads)?;
+        let k = self.split_heads(&self.k_proj.forward(key)?, self.num_kv_heads)?;
+        let v = self.split_heads(&self.v_proj.forward(value)?, self.num_kv_heads)?;
+        let offset = cache.as_ref().map_or(0, |c| c.current_seq_len());
+        let (q, k) = match &self.rotary {
+            None => (q, k),
+            Some(rotary) => (rotary.apply(&q, offset)?, rotary.apply(&k, offset)?),
+        };
+        let (k, v) = match cache {
+            None => (k, v),
+            Some(cache) => cache.append(&k, &v)?,
+        };
+        let kv_len = k.dim(2)?;
+        let k = self.repeat_kv(k)?;
+        let v = self.repeat_kv(v)?;
+
+        let scale = 1. / (self.head_dim as f64).sqrt();
+        let mut scores = (q.matmul(&k.t()?)? * scale)?;
+        if let Some(slopes) = &self.alibi_slopes {
+            let bias = self.alibi_bias(slopes, q_len, kv_len)?;
+            scores = scores.broadcast_add(&bias.to_dtype(scores.dtype())?)?
+        }
+        if let Some(mask) = mask {
+            scores = scores.broadcast_add(mask)?
+        }
+        let weights = crate::ops::softmax_last_dim(&scores)?;
+        let ys = weights.matmul(&v)?.transpose(1, 2)?.reshape((
+            b_sz,
+            q_len,
+            self.num_heads * self.head_dim,
+        ))?;
+        self.out_proj.forward(&ys)
+    }
+
+    /// Attends from `query` of shape `(batch, q_len, embed_dim)` to `key` and `value` of shape
+    /// `(batch, kv_len, kdim)` and `(batch, kv_len, vdim)`. The optional `mask` is added to the
+    /// attention scores of shape `(batch, num_heads, q_len, kv_len)` and must be broadcastable to
+    /// this shape.
+    pub fn forward(
+        &self,
+        query: &Tensor,
+        key: &Tensor,
+        value: &Tensor,
+        mask: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        self.attend(query, key, value, mask, None)
+    }
+
+    /// Same as [`Self::forward`] but the keys and values are appended to `cache` and the
+    /// attention covers all the cached positions. The position encodings of the new elements
+    /// start after the cached positions, the mask should cover `cache.current_seq_len()` plus
+    /// `kv_len` keys, see [`causal_mask`].
+    pub fn forward_with_cache(
+        &self,
+        query: &Tensor,
+        key: &Tensor,
+        value: &Tensor,
+        mask: Option<&Tensor>,
+        cache: &mut KvCache,
+    ) -> Result<Tensor> {
+        self.attend(query, key, value, mask, Some(cache))
+    }
+}
+
+/// Creates or loads a multi-head attention layer using the PyTorch weight names.
+pub fn multi_head_attention(
+    embed_dim: usize,
+    config: MultiHeadAttentionConfig,
+    vb: crate::VarBuilder,
+) -> Result<MultiHeadAttention> {
+    let num_heads = config.num_heads;
+    if num_heads == 0 || embed_dim % num_heads != 0 {
+        candle::bail!("embed_dim {embed_dim} is not a multiple of num_heads {num_heads}")
+    }
+    let kv_dim = embed_dim / num_heads * config.num_kv_heads;
+    let kdim = config.kdim.unwrap_or(embed_dim);
+    let vdim = config.vdim.unwrap_or(embed_dim);
+    let init_ws = crate::init::DEFAULT_KAIMING_NORMAL;
+    let (q_w, k_w, v_w) = if kdim == embed_dim && vdim == embed_dim {
+        let in_proj = vb.get_with_hints(
+            (embed_dim + 2 * kv_dim, embed_dim),
+            "in_proj_weight",
+            init_ws,
+        )?;
+        (
+            in_proj.narrow(0, 0, embed_dim)?,
+            in_proj.narrow(0, embed_dim, kv_dim)?,
+            in_proj.narrow(0, embed_dim + kv_dim, kv_dim)?,
+        )
+    } else {
+        (
+            vb.get_with_hints((embed_dim, embed_dim), "q_proj_weight", init_ws)?,
+            vb.get_with_hints((kv_dim, kdim), "k_proj_weight", init_ws)?,
+            vb.get_with_hints((kv_dim, vdim), "v_proj_weight", init_ws)?,
+        )
+    };
+    let (q_b, k_b, v_b) = if config.bias {
+        let in_proj_bias = vb.get_with_hints(
+            embed_dim + 2 * kv_dim,
+            "in_proj_bias",
+            crate::Init::Const(0.),
+        )?;
+        (
+            Some(in_proj_bias.narrow(0, 0, embed_dim)?),
+            Some(in_proj_bias.narrow(0, embed_dim, kv_dim)?),
+            Some(in_proj_bias.narrow(0, embed_dim + kv_dim, kv_dim)?),
+        )
+    } else {
+        (None, None, None)
+    };
+    let out_proj = crate::linear_b(embed_dim, embed_dim, config.bias, vb.pp("out_proj"))?;
+    MultiHeadAttention::new(
+        Linear::new(q_w, q_b),
+        Linear::new(k_w, k_b),
+        Linear::new(v_w, v_b),
+        out_proj,
+        config,
+    )
+}
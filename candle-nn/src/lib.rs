@@ -1,4 +1,5 @@
 pub mod activation;
+pub mod attention;
 pub mod batch_norm;
 pub mod conv;
 pub mod embedding;
@@ -8,20 +9,27 @@ pub mod group_norm;
 pub mod init;
 pub mod layer_norm;
 pub mod linear;
+pub mod lora;
 pub mod loss;
+pub mod lr_scheduler;
+pub mod mixed_precision;
 pub mod ops;
 pub mod optim;
 pub mod rnn;
 pub mod sequential;
+pub mod transformer;
 pub mod var_builder;
 pub mod var_map;
 
 pub use activation::{prelu, Activation, PReLU};
+pub use attention::{multi_head_attention, MultiHeadAttention, MultiHeadAttentionConfig};
 pub use batch_norm::{batch_norm, BatchNorm, BatchNormConfig};
 pub use conv::{
-    conv1d, conv1d_no_bias, conv2d, conv2d_no_bias, conv_transpose1d, conv_transpose1d_no_bias,
-    conv_transpose2d, conv_transpose2d_no_bias, Conv1d, Conv1dConfig, Conv2d, Conv2dConfig,
+    conv1d, conv1d_no_bias, conv2d, conv2d_no_bias, conv3d, conv3d_no_bias, conv_transpose1d,
+    conv_transpose1d_no_bias, conv_transpose2d, conv_transpose2d_no_bias, conv_transpose3d,
+    conv_transpose3d_no_bias, Conv1d, Conv1dConfig, Conv2d, Conv2dConfig, Conv3d, Conv3dConfig,
     ConvTranspose1d, ConvTranspose1dConfig, ConvTranspose2d, ConvTranspose2dConfig,
+    ConvTranspose3d, ConvTranspose3dConfig,
 };
 pub use embedding::{embedding, Embedding};
 pub use func::{func, func_t, Func, FuncT};
@@ -29,10 +37,22 @@ pub use group_norm::{group_norm, GroupNorm};
 pub use init::Init;
 pub use layer_norm::{layer_norm, rms_norm, LayerNorm, LayerNormConfig, RmsNorm};
 pub use linear::{linear, linear_b, linear_no_bias, Linear};
+pub use mixed_precision::{GradScaler, MixedPrecision, ParamsGradScaler};
 pub use ops::Dropout;
-pub use optim::{AdamW, Optimizer, ParamsAdamW, SGD};
-pub use rnn::{gru, lstm, GRUConfig, LSTMConfig, GRU, LSTM, RNN};
+pub use optim::{
+    Adafactor, Adagrad, Adam, AdamW, Lion, Optimizer, ParamGroup, ParamGroupConfig,
+    ParamsAdafactor, ParamsAdagrad, ParamsAdam, ParamsAdamW, ParamsLion, ParamsRMSprop, ParamsSGD,
+    RMSprop, SGD,
+};
+pub use rnn::{
+    gru, gru_stacked, lstm, lstm_stacked, GRUConfig, LSTMConfig, RNNLayer, StackedRNN,
+    StackedRNNConfig, GRU, LSTM, RNN,
+};
 pub use sequential::{seq, Sequential};
+pub use transformer::{
+    transformer_decoder_layer, transformer_encoder_layer, TransformerDecoderLayer,
+    TransformerEncoderLayer, TransformerLayerConfig,
+};
 pub use var_builder::VarBuilder;
 pub use var_map::VarMap;
 
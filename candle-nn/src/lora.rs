@@ -0,0 +1,644 @@
+//! Low-rank adaptation (LoRA) layers.
+//!
+//! A LoRA layer wraps a frozen base layer and learns a low-rank update of its weight, computing
+//! `y = base(x) + scale * B(A(dropout(x)))` where `A` projects the input down to `rank`
+//! dimensions, `B` projects it back up and `scale = alpha / rank`. `B` starts at zero so that the
+//! adapted layer initially returns the same outputs as the base layer.
+//!
+//! The adapter weights are retrieved from their own `VarBuilder` using the prefix of the base
+//! layer, e.g. `model.layers.0.self_attn.q_proj.lora_A.weight`, which is the naming used by PEFT.
+//! Only the layers whose prefix matches one of the configured target modules get an adapter. The
+//! base weights are detached so that only the adapter variables receive gradients.
+//!
+//! ```rust
+//! use candle::{DType, Device, Module, Tensor};
+//! use candle_nn::lora::{Lora, LoraConfig};
+//! use candle_nn::{VarBuilder, VarMap};
+//! # fn main() -> candle::Result<()> {
+//! let dev = Device::Cpu;
+//! let base = VarMap::new();
+//! let vb = VarBuilder::from_varmap(&base, DType::F32, &dev);
+//! let adapters = VarMap::new();
+//! let config = LoraConfig::new(4, 8., &["q_proj", "v_proj"]);
+//! let lora = Lora::new(config, VarBuilder::from_varmap(&adapters, DType::F32, &dev));
+//!
+//! let q_proj = lora.linear_no_bias(16, 16, vb.pp("attn.q_proj"))?;
+//! let o_proj = lora.linear_no_bias(16, 16, vb.pp("attn.o_proj"))?;
+//! assert!(q_proj.is_adapted() && !o_proj.is_adapted());
+//! // Only the `lora_A` and `lora_B` weights of `q_proj` are trainable.
+//! assert_eq!(adapters.all_vars().len(), 2);
+//!
+//! let xs = Tensor::randn(0f32, 1., (2, 16), &dev)?;
+//! let ys = q_proj.forward(&xs)?;
+//! assert_eq!(ys.dims(), &[2, 16]);
+//! # Ok(()) }
+//! ```
+use crate::{Conv2d, Conv2dConfig, Embedding, Linear, Module, VarBuilder, VarMap};
+use candle::{DType, Device, Result, Tensor};
+use std::collections::HashMap;
+
+/// The prefix used by PEFT for the tensor names in `adapter_model.safetensors`.
+pub const PEFT_PREFIX: &str = "base_model.model.";
+
+/// The LoRA hyper-parameters, serialized with the field names of PEFT's `adapter_config.json`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LoraConfig {
+    #[serde(rename = "r")]
+    pub rank: usize,
+    #[serde(rename = "lora_alpha")]
+    pub alpha: f64,
+    #[serde(rename = "lora_dropout", default)]
+    pub dropout: f32,
+    /// The modules to adapt. A module matches when its prefix is equal to the pattern or ends
+    /// with `.` followed by the pattern, `*` matching any sequence of characters.
+    pub target_modules: Vec<String>,
+}
+
+impl LoraConfig {
+    pub fn new(rank: usize, alpha: f64, target_modules: &[&str]) -> Self {
+        Self {
+            rank,
+            alpha,
+            dropout: 0.,
+            target_modules: target_modules.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// The factor applied to the output of the adapter, `alpha / rank`.
+    pub fn scale(&self) -> f64 {
+        self.alpha / self.rank as f64
+    }
+
+    /// Returns true if the module with the given prefix should be adapted.
+    pub fn is_target(&self, prefix: &str) -> bool {
+        self.target_modules.iter().any(|pattern| {
+            crate::var_map::glob_match(pattern, prefix)
+                || (0..prefix.len())
+                    .filter(|&i| prefix.as_bytes()[i] == b'.')
+                    .any(|i| crate::var_map::glob_match(pattern, &prefix[i + 1..]))
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Adapter {
+    scale: f64,
+    dropout: f32,
+}
+
+impl Adapter {
+    fn dropout(&self, xs: &Tensor, train: bool) -> Result<Tensor> {
+        if train && self.dropout > 0. {
+            crate::ops::dropout(xs, self.dropout)
+        } else {
+            Ok(xs.clone())
+        }
+    }
+}
+
+fn add_delta(weight: &Tensor, delta: &Tensor, sign: f64) -> Result<Tensor> {
+    let delta = delta.to_dtype(weight.dtype())?;
+    weight.add(&(delta * sign)?)
+}
+
+/// A linear layer with a LoRA adapter, `lora_A` has shape `(rank, in_dim)` and `lora_B` has
+/// shape `(out_dim, rank)`.
+#[derive(Clone, Debug)]
+pub struct LoraLinear {
+    base: Linear,
+    adapter: Option<(Linear, Linear, Adapter)>,
+    merged: bool,
+}
+
+impl LoraLinear {
+    pub fn new(base: Linear, lora_a: Tensor, lora_b: Tensor, config: &LoraConfig) -> Self {
+        let adapter = Adapter {
+            scale: config.scale(),
+            dropout: config.dropout,
+        };
+        Self {
+            base,
+            adapter: Some((
+                Linear::new(lora_a, None),
+                Linear::new(lora_b, None),
+                adapter,
+            )),
+            merged: false,
+        }
+    }
+
+    /// A layer without adapter, this is used for the modules that are not targeted.
+    pub fn frozen(base: Linear) -> Self {
+        Self {
+            base,
+            adapter: None,
+            merged: false,
+        }
+    }
+
+    pub fn base(&self) -> &Linear {
+        &self.base
+    }
+
+    pub fn is_adapted(&self) -> bool {
+        self.adapter.is_some()
+    }
+
+    pub fn is_merged(&self) -> bool {
+        self.merged
+    }
+
+    // The weight update `scale * B @ A` of shape `(out_dim, in_dim)`.
+    fn delta_weight(&self) -> Result<Option<Tensor>> {
+        match &self.adapter {
+            None => Ok(None),
+            Some((a, b, adapter)) => {
+                let delta = (b.weight().matmul(a.weight())? * adapter.scale)?;
+                Ok(Some(delta))
+            }
+        }
+    }
+
+    /// Adds the adapter update to the base weight so that the forward pass only uses the base
+    /// layer. This is a no-op if the layer is already merged or has no adapter.
+    pub fn merge(&mut self) -> Result<()> {
+        if self.merged {
+            return Ok(());
+        }
+        if let Some(delta) = self.delta_weight()? {
+            let weight = add_delta(self.base.weight(), &delta, 1.)?;
+            self.base = Linear::new(weight, self.base.bias().cloned());
+            self.merged = true;
+        }
+        Ok(())
+    }
+
+    /// Removes the adapter update from the base weight, reverting [`Self::merge`].
+    pub fn unmerge(&mut self) -> Result<()> {
+        if !self.merged {
+            return Ok(());
+        }
+        if let Some(delta) = self.delta_weight()? {
+            let weight = add_delta(self.base.weight(), &delta, -1.)?;
+            self.base = Linear::new(weight, self.base.bias().cloned());
+        }
+        self.merged = false;
+        Ok(())
+    }
+
+    /// The forward pass, dropout is applied to the adapter input when `train` is true.
+    pub fn forward_t(&self, xs: &Tensor, train: bool) -> Result<Tensor> {
+        let ys = self.base.forward(xs)?;
+        match &self.adapter {
+            Some((a, b, adapter)) if !self.merged => {
+                let xs = adapter.dropout(xs, train)?;
+                let delta = b.forward(&a.forward(&xs)?)?;
+                ys + (delta * adapter.scale)?
+            }
+            _ => Ok(ys),
+        }
+    }
+}
+
+impl Module for LoraLinear {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        self.forward_t(xs, false)
+    }
+}
+
+/// An embedding layer with a LoRA adapter, `lora_embedding_A` has shape `(rank, num_embeddings)`
+/// and `lora_embedding_B` has shape `(hidden_size, rank)`. No dropout is applied to embeddings.
+#[derive(Clone, Debug)]
+pub struct LoraEmbedding {
+    base: Embedding,
+    adapter: Option<(Embedding, Linear, Adapter)>,
+    merged: bool,
+}
+
+impl LoraEmbedding {
+    pub fn new(
+        base: Embedding,
+        lora_a: Tensor,
+        lora_b: Tensor,
+        config: &LoraConfig,
+    ) -> Result<Self> {
+        let adapter = Adapter {
+            scale: config.scale(),
+            dropout: 0.,
+        };
+        let rank = lora_a.dim(0)?;
+        let lora_a = Embedding::new(lora_a.t()?.contiguous()?, rank);
+        Ok(Self {
+            base,
+            adapter: Some((lora_a, Linear::new(lora_b, None), adapter)),
+            merged: false,
+        })
+    }
+
+    pub fn frozen(base: Embedding) -> Self {
+        Self {
+            base,
+            adapter: None,
+            merged: false,
+        }
+    }
+
+    pub fn base(&self) -> &Embedding {
+        &self.base
+    }
+
+    pub fn is_adapted(&self) -> bool {
+        self.adapter.is_some()
+    }
+
+    pub fn is_merged(&self) -> bool {
+        self.merged
+    }
+
+    // The update of the embedding table, `scale * (B @ A).t()` of shape
+    // `(num_embeddings, hidden_size)`.
+    fn delta_weight(&self) -> Result<Option<Tensor>> {
+        match &self.adapter {
+            None => Ok(None),
+            Some((a, b, adapter)) => {
+                let delta = a.embeddings().matmul(&b.weight().t()?)?;
+                Ok(Some((delta * adapter.scale)?))
+            }
+        }
+    }
+
+    pub fn merge(&mut self) -> Result<()> {
+        if self.merged {
+            return Ok(());
+        }
+        if let Some(delta) = self.delta_weight()? {
+            let embeddings = add_delta(self.base.embeddings(), &delta, 1.)?;
+            self.base = Embedding::new(embeddings, self.base.hidden_size());
+            self.merged = true;
+        }
+        Ok(())
+    }
+
+    pub fn unmerge(&mut self) -> Result<()> {
+        if !self.merged {
+            return Ok(());
+        }
+        if let Some(delta) = self.delta_weight()? {
+            let embeddings = add_delta(self.base.embeddings(), &delta, -1.)?;
+            self.base = Embedding::new(embeddings, self.base.hidden_size());
+        }
+        self.merged = false;
+        Ok(())
+    }
+}
+
+impl Module for LoraEmbedding {
+    fn forward(&self, indexes: &Tensor) -> Result<Tensor> {
+        let ys = self.base.forward(indexes)?;
+        match &self.adapter {
+            Some((a, b, adapter)) if !self.merged => {
+                let delta = b.forward(&a.forward(indexes)?)?;
+                ys + (delta * adapter.scale)?
+            }
+            _ => Ok(ys),
+        }
+    }
+}
+
+/// A 2d convolution with a LoRA adapter. `lora_A` is a convolution with the same kernel and
+/// config as the base layer and `rank` output channels, `lora_B` is a `1x1` convolution from
+/// `rank` to the output channels. Grouped convolutions are not supported.
+#[derive(Clone, Debug)]
+pub struct LoraConv2d {
+    base: Conv2d,
+    adapter: Option<(Conv2d, Conv2d, Adapter)>,
+    merged: bool,
+}
+
+impl LoraConv2d {
+    pub fn new(base: Conv2d, lora_a: Tensor, lora_b: Tensor, config: &LoraConfig) -> Result<Self> {
+        let cfg = *base.config();
+        if cfg.groups != 1 {
+            candle::bail!("lora is not supported for grouped convolutions")
+        }
+        let adapter = Adapter {
+            scale: config.scale(),
+            dropout: config.dropout,
+        };
+        let lora_a = Conv2d::new(lora_a, None, cfg);
+        let lora_b = Conv2d::new(lora_b, None, Conv2dConfig::default());
+        Ok(Self {
+            base,
+            adapter: Some((lora_a, lora_b, adapter)),
+            merged: false,
+        })
+    }
+
+    pub fn frozen(base: Conv2d) -> Self {
+        Self {
+            base,
+            adapter: None,
+            merged: false,
+        }
+    }
+
+    pub fn base(&self) -> &Conv2d {
+        &self.base
+    }
+
+    pub fn is_adapted(&self) -> bool {
+        self.adapter.is_some()
+    }
+
+    pub fn is_merged(&self) -> bool {
+        self.merged
+    }
+
+    // The kernel update, `B` is a 1x1 convolution so this is a matmul over the rank dimension.
+    fn delta_weight(&self) -> Result<Option<Tensor>> {
+        match &self.adapter {
+            None => Ok(None),
+            Some((a, b, adapter)) => {
+                let (out_c, rank, _, _) = b.weight().dims4()?;
+                let (_, in_c, k1, k2) = a.weight().dims4()?;
+                let delta = b
+                    .weight()
+                    .reshape((out_c, rank))?
+                    .matmul(&a.weight().reshape((rank, in_c * k1 * k2))?)?
+                    .reshape((out_c, in_c, k1, k2))?;
+                Ok(Some((delta * adapter.scale)?))
+            }
+        }
+    }
+
+    pub fn merge(&mut self) -> Result<()> {
+        if self.merged {
+            return Ok(());
+        }
+        if let Some(delta) = self.delta_weight()? {
+            let weight = add_delta(self.base.weight(), &delta, 1.)?;
+            let bias = self.base.bias().cloned();
+            self.base = Conv2d::new(weight, bias, *self.base.config());
+            self.merged = true;
+        }
+        Ok(())
+    }
+
+    pub fn unmerge(&mut self) -> Result<()> {
+        if !self.merged {
+            return Ok(());
+        }
+        if let Some(delta) = self.delta_weight()? {
+            let weight = add_delta(self.base.weight(), &delta, -1.)?;
+            let bias = self.base.bias().cloned();
+            self.base = Conv2d::new(weight, bias, *self.base.config());
+        }
+        self.merged = false;
+        Ok(())
+    }
+
+    /// The forward pass, dropout is applied to the adapter input when `train` is true.
+    pub fn forward_t(&self, xs: &Tensor, train: bool) -> Result<Tensor> {
+        let ys = self.base.forward(xs)?;
+        match &self.adapter {
+            Some((a, b, adapter)) if !self.merged => {
+                let xs = adapter.dropout(xs, train)?;
+                let delta = b.forward(&a.forward(&xs)?)?;
+                ys + (delta * adapter.scale)?
+            }
+            _ => Ok(ys),
+        }
+    }
+}
+
+impl Module for LoraConv2d {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        self.forward_t(xs, false)
+    }
+}
+
+/// Builds LoRA layers, the base weights come from the `VarBuilder` passed to each method and the
+/// adapter weights from the `VarBuilder` of the `Lora`.
+#[derive(Clone)]
+pub struct Lora<'a> {
+    config: LoraConfig,
+    vb: VarBuilder<'a>,
+}
+
+impl<'a> Lora<'a> {
+    pub fn new(config: LoraConfig, vb: VarBuilder<'a>) -> Self {
+        Self { config, vb }
+    }
+
+    pub fn config(&self) -> &LoraConfig {
+        &self.config
+    }
+
+    // The adapter `VarBuilder` for the module at the prefix of `vb`, `None` if this module is
+    // not targeted.
+    fn adapter_vb(&self, vb: &VarBuilder) -> Option<VarBuilder<'a>> {
+        let prefix = vb.prefix();
+        if self.config.is_target(&prefix) {
+            Some(self.vb.set_prefix(prefix))
+        } else {
+            None
+        }
+    }
+
+    /// Wraps an existing linear layer, `vb` is only used for its prefix.
+    pub fn wrap_linear(&self, base: Linear, vb: &VarBuilder) -> Result<LoraLinear> {
+        let base = Linear::new(base.weight().detach(), base.bias().map(|b| b.detach()));
+        let lvb = match self.adapter_vb(vb) {
+            None => return Ok(LoraLinear::frozen(base)),
+            Some(lvb) => lvb,
+        };
+        let (out_dim, in_dim) = base.weight().dims2()?;
+        let rank = self.config.rank;
+        let bound = 1. / (in_dim as f64).sqrt();
+        let init_a = crate::Init::Uniform {
+            lo: -bound,
+            up: bound,
+        };
+        let lora_a = lvb
+            .pp("lora_A")
+            .get_with_hints((rank, in_dim), "weight", init_a)?;
+        let lora_b =
+            lvb.pp("lora_B")
+                .get_with_hints((out_dim, rank), "weight", crate::Init::Const(0.))?;
+        Ok(LoraLinear::new(base, lora_a, lora_b, &self.config))
+    }
+
+    pub fn linear(&self, in_dim: usize, out_dim: usize, vb: VarBuilder) -> Result<LoraLinear> {
+        let base = crate::linear(in_dim, out_dim, vb.clone())?;
+        self.wrap_linear(base, &vb)
+    }
+
+    pub fn linear_no_bias(
+        &self,
+        in_dim: usize,
+        out_dim: usize,
+        vb: VarBuilder,
+    ) -> Result<LoraLinear> {
+        let base = crate::linear_no_bias(in_dim, out_dim, vb.clone())?;
+        self.wrap_linear(base, &vb)
+    }
+
+    pub fn linear_b(
+        &self,
+        in_dim: usize,
+        out_dim: usize,
+        bias: bool,
+        vb: VarBuilder,
+    ) -> Result<LoraLinear> {
+        let base = crate::linear_b(in_dim, out_dim, bias, vb.clone())?;
+        self.wrap_linear(base, &vb)
+    }
+
+    /// Wraps an existing embedding layer, `vb` is only used for its prefix.
+    pub fn wrap_embedding(&self, base: Embedding, vb: &VarBuilder) -> Result<LoraEmbedding> {
+        let base = Embedding::new(base.embeddings().detach(), base.hidden_size());
+        let lvb = match self.adapter_vb(vb) {
+            None => return Ok(LoraEmbedding::frozen(base)),
+            Some(lvb) => lvb,
+        };
+        let (in_size, out_size) = base.embeddings().dims2()?;
+        let rank = self.config.rank;
+        // PEFT initializes `A` to zero and `B` with a normal distribution for embeddings.
+        let lora_a =
+            lvb.get_with_hints((rank, in_size), "lora_embedding_A", crate::Init::Const(0.))?;
+        let init_b = crate::Init::Randn {
+            mean: 0.,
+            stdev: 1.,
+        };
+        let lora_b = lvb.get_with_hints((out_size, rank), "lora_embedding_B", init_b)?;
+        LoraEmbedding::new(base, lora_a, lora_b, &self.config)
+    }
+
+    pub fn embedding(
+        &self,
+        in_size: usize,
+        out_size: usize,
+        vb: VarBuilder,
+    ) -> Result<LoraEmbedding> {
+        let base = crate::embedding(in_size, out_size, vb.clone())?;
+        self.wrap_embedding(base, &vb)
+    }
+
+    /// Wraps an existing 2d convolution, `vb` is only used for its prefix.
+    pub fn wrap_conv2d(&self, base: Conv2d, vb: &VarBuilder) -> Result<LoraConv2d> {
+        let base = Conv2d::new(
+            base.weight().detach(),
+            base.bias().map(|b| b.detach()),
+            *base.config(),
+        );
+        let lvb = match self.adapter_vb(vb) {
+            None => return Ok(LoraConv2d::frozen(base)),
+            Some(lvb) => lvb,
+        };
+        let (out_c, in_c, k1, k2) = base.weight().dims4()?;
+        let rank = self.config.rank;
+        let bound = 1. / ((in_c * k1 * k2) as f64).sqrt();
+        let init_a = crate::Init::Uniform {
+            lo: -bound,
+            up: bound,
+        };
+        let lora_a = lvb
+            .pp("lora_A")
+            .get_with_hints((rank, in_c, k1, k2), "weight", init_a)?;
+        let lora_b = lvb.pp("lora_B").get_with_hints(
+            (out_c, rank, 1, 1),
+            "weight",
+            crate::Init::Const(0.),
+        )?;
+        LoraConv2d::new(base, lora_a, lora_b, &self.config)
+    }
+
+    pub fn conv2d(
+        &self,
+        in_channels: usize,
+        out_channels: usize,
+        kernel_size: usize,
+        cfg: Conv2dConfig,
+        vb: VarBuilder,
+    ) -> Result<LoraConv2d> {
+        let base = crate::conv2d(in_channels, out_channels, kernel_size, cfg, vb.clone())?;
+        self.wrap_conv2d(base, &vb)
+    }
+
+    pub fn conv2d_no_bias(
+        &self,
+        in_channels: usize,
+        out_channels: usize,
+        kernel_size: usize,
+        cfg: Conv2dConfig,
+        vb: VarBuilder,
+    ) -> Result<LoraConv2d> {
+        let base = crate::conv2d_no_bias(in_channels, out_channels, kernel_size, cfg, vb.clone())?;
+        self.wrap_conv2d(base, &vb)
+    }
+}
+
+impl Lora<'static> {
+    /// Creates a `Lora` from a PEFT adapter directory, see [`load_adapter`].
+    pub fn from_adapter<P: AsRef<std::path::Path>>(
+        dir: P,
+        dtype: DType,
+        dev: &Device,
+    ) -> Result<Self> {
+        let (config, tensors) = load_adapter(dir, dev)?;
+        Ok(Self::new(
+            config,
+            VarBuilder::from_tensors(tensors, dtype, dev),
+        ))
+    }
+}
+
+/// Saves the adapter variables in the PEFT layout, i.e. `adapter_model.safetensors` with the
+/// tensor names prefixed by [`PEFT_PREFIX`] and `adapter_config.json`, in the directory `dir`.
+pub fn save_adapter<P: AsRef<std::path::Path>>(
+    varmap: &VarMap,
+    config: &LoraConfig,
+    dir: P,
+) -> Result<()> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir).map_err(|e| candle::Error::from(e).with_path(dir))?;
+    let tensors = varmap
+        .data()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, var)| (format!("{PEFT_PREFIX}{name}"), var.as_tensor().clone()))
+        .collect::<HashMap<_, _>>();
+    candle::safetensors::save(&tensors, dir.join("adapter_model.safetensors"))?;
+
+    let mut json = serde_json::to_value(config).map_err(candle::Error::wrap)?;
+    if let serde_json::Value::Object(map) = &mut json {
+        map.insert("peft_type".to_string(), "LORA".into());
+        map.insert("bias".to_string(), "none".into());
+    }
+    let path = dir.join("adapter_config.json");
+    let json = serde_json::to_string_pretty(&json).map_err(candle::Error::wrap)?;
+    std::fs::write(&path, json).map_err(|e| candle::Error::from(e).with_path(path))?;
+    Ok(())
+}
+
+/// Loads a PEFT adapter directory, returning the config and the adapter tensors with the
+/// [`PEFT_PREFIX`] stripped from their names. The tensors can be used with
+/// `VarBuilder::from_tensors` for inference, or with `VarMap::set` to resume training.
+pub fn load_adapter<P: AsRef<std::path::Path>>(
+    dir: P,
+    dev: &Device,
+) -> Result<(LoraConfig, HashMap<String, Tensor>)> {
+    let dir = dir.as_ref();
+    let path = dir.join("adapter_config.json");
+    let json = std::fs::read(&path).map_err(|e| candle::Error::from(e).with_path(&path))?;
+    let config: LoraConfig = serde_json::from_slice(&json).map_err(candle::Error::wrap)?;
+    let tensors = candle::safetensors::load(dir.join("adapter_model.safetensors"), dev)?
+        .into_iter()
+        .map(|(name, tensor)| match name.strip_prefix(PEFT_PREFIX) {
+            Some(name) => (name.to_string(), tensor),
+            None => (name, tensor),
+        })
+        .collect();
+    Ok((config, tensors))
+}
@@ -1,4 +1,34 @@
-use candle::{Result, Tensor};
+use candle::{CpuStorage, Layout, Result, Shape, Tensor, D};
+use rayon::prelude::*;
+
+/// How the per-element losses are combined into the returned value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reduction {
+    /// No reduction, the losses are returned as is.
+    None,
+    /// The average of the losses.
+    #[default]
+    Mean,
+    /// The sum of the losses.
+    Sum,
+    /// The sum of the losses divided by the size of the first dimension, this is the
+    /// mathematically correct reduction for the KL divergence.
+    BatchMean,
+}
+
+impl Reduction {
+    pub fn reduce(&self, loss: &Tensor) -> Result<Tensor> {
+        match self {
+            Self::None => Ok(loss.clone()),
+            Self::Mean => loss.mean_all(),
+            Self::Sum => loss.sum_all(),
+            Self::BatchMean => {
+                let b_sz = loss.dims().first().copied().unwrap_or(1);
+                loss.sum_all()?.affine(1f64 / b_sz as f64, 0.)
+            }
+        }
+    }
+}
 
 /// The negative log likelihood loss.
 ///
@@ -70,3 +100,626 @@ pub fn binary_cross_entropy_with_logit(inp: &Tensor, target: &Tensor) -> Result<
 
     Ok(loss)
 }
+
+#[derive(Debug, Clone)]
+pub struct CrossEntropyConfig {
+    /// A manual rescaling weight given to each class, a tensor of dimension `C`.
+    pub weight: Option<Tensor>,
+    /// Targets with this value do not contribute to the loss nor to the gradients.
+    pub ignore_index: Option<u32>,
+    /// The amount of smoothing in `[0, 1]`, the targets become a mixture of the ground truth and
+    /// of the uniform distribution over the classes.
+    pub label_smoothing: f64,
+    pub reduction: Reduction,
+}
+
+impl Default for CrossEntropyConfig {
+    fn default() -> Self {
+        Self {
+            weight: None,
+            ignore_index: None,
+            label_smoothing: 0.,
+            reduction: Reduction::Mean,
+        }
+    }
+}
+
+/// The cross-entropy loss with class weights, ignored targets and label smoothing.
+///
+/// Arguments
+///
+/// * [inp]: The input tensor of dimensions `N, C` where `N` is the batch size and `C` the number
+///   of categories. This is expected to raw logits.
+/// * [target]: The ground truth labels as a tensor of u32 of dimension `N`.
+///
+/// Similar to PyTorch, the mean reduction divides the sum of the losses by the total weight of
+/// the non-ignored targets.
+pub fn cross_entropy_with_config(
+    inp: &Tensor,
+    target: &Tensor,
+    cfg: &CrossEntropyConfig,
+) -> Result<Tensor> {
+    let (b_sz, n_classes) = match inp.dims() {
+        &[b_sz, n_classes] => (b_sz, n_classes),
+        dims => candle::bail!("cross_entropy expects an input tensor of rank 2 ({dims:?})"),
+    };
+    let target_b_sz = target.dims1()?;
+    if target_b_sz != b_sz {
+        candle::bail!("batch size mismatch between inp ({b_sz}) and target ({target_b_sz})")
+    }
+    if !(0.0..=1.0).contains(&cfg.label_smoothing) {
+        candle::bail!(
+            "label_smoothing should be in [0, 1], got {}",
+            cfg.label_smoothing
+        )
+    }
+    let log_probs = crate::ops::log_softmax(inp, 1)?;
+    // Ignored targets may be out of range so they get replaced with a valid class and masked.
+    let (target, mask) = match cfg.ignore_index {
+        None => (target.clone(), None),
+        Some(ignore_index) => {
+            let mask = target.ne(ignore_index)?;
+            let target = mask.where_cond(target, &target.zeros_like()?)?;
+            (target, Some(mask.to_dtype(inp.dtype())?))
+        }
+    };
+    let weight = match &cfg.weight {
+        None => None,
+        Some(weight) => {
+            if weight.dims1()? != n_classes {
+                candle::bail!(
+                    "cross_entropy expects a weight of dimension {n_classes} ({:?})",
+                    weight.shape()
+                )
+            }
+            Some(weight.to_dtype(inp.dtype())?)
+        }
+    };
+    let sample_weight = match &weight {
+        None => None,
+        Some(weight) => Some(weight.index_select(&target, 0)?),
+    };
+    let nll = log_probs
+        .gather(&target.unsqueeze(1)?, 1)?
+        .squeeze(1)?
+        .neg()?;
+    let mut loss = match &sample_weight {
+        None => nll,
+        Some(w) => (nll * w)?,
+    };
+    if cfg.label_smoothing > 0. {
+        let smooth = match &weight {
+            None => log_probs.sum(1)?,
+            Some(weight) => log_probs.broadcast_mul(&weight.unsqueeze(0)?)?.sum(1)?,
+        };
+        let smooth = smooth.affine(-cfg.label_smoothing / n_classes as f64, 0.)?;
+        loss = (loss.affine(1. - cfg.label_smoothing, 0.)? + smooth)?;
+    }
+    if let Some(mask) = &mask {
+        loss = (loss * mask)?
+    }
+    match cfg.reduction {
+        Reduction::Mean => {
+            let total_weight = match (sample_weight, mask) {
+                (None, None) => return loss.mean_all(),
+                (Some(w), None) | (None, Some(w)) => w.sum_all()?,
+                (Some(w), Some(mask)) => (w * mask)?.sum_all()?,
+            };
+            loss.sum_all()? / total_weight
+        }
+        reduction => reduction.reduce(&loss),
+    }
+}
+
+/// The Kullback-Leibler divergence loss.
+///
+/// Arguments
+///
+/// * [inp]: The input tensor, this is expected to contain log probabilities.
+/// * [target]: The target distribution with the same shape as `inp`, this contains log
+///   probabilities when `log_target` is set and probabilities otherwise.
+///
+/// Note that [`Reduction::BatchMean`] has to be used to get the actual KL divergence, the mean
+/// reduction averages over all the elements.
+pub fn kl_div(
+    inp: &Tensor,
+    target: &Tensor,
+    log_target: bool,
+    reduction: Reduction,
+) -> Result<Tensor> {
+    let loss = if log_target {
+        (target.exp()? * (target - inp)?)?
+    } else {
+        // Zero probabilities do not contribute to the loss, this avoids the 0 * log(0) nans.
+        let loss = (target * (target.log()? - inp)?)?;
+        target.gt(0f64)?.where_cond(&loss, &loss.zeros_like()?)?
+    };
+    reduction.reduce(&loss)
+}
+
+/// The Huber loss, quadratic for errors smaller than `delta` and linear above.
+pub fn huber(inp: &Tensor, target: &Tensor, delta: f64, reduction: Reduction) -> Result<Tensor> {
+    let diff = (inp - target)?;
+    let abs_diff = diff.abs()?;
+    let quadratic = diff.sqr()?.affine(0.5, 0.)?;
+    let linear = abs_diff.affine(delta, -0.5 * delta * delta)?;
+    let loss = abs_diff.lt(delta)?.where_cond(&quadratic, &linear)?;
+    reduction.reduce(&loss)
+}
+
+/// The smooth L1 loss, this is the Huber loss divided by `beta`. A `beta` of zero results in the
+/// L1 loss.
+pub fn smooth_l1(inp: &Tensor, target: &Tensor, beta: f64, reduction: Reduction) -> Result<Tensor> {
+    let diff = (inp - target)?;
+    let abs_diff = diff.abs()?;
+    let loss = if beta == 0. {
+        abs_diff
+    } else {
+        let quadratic = diff.sqr()?.affine(0.5 / beta, 0.)?;
+        let linear = abs_diff.affine(1., -0.5 * beta)?;
+        abs_diff.lt(beta)?.where_cond(&quadratic, &linear)?
+    };
+    reduction.reduce(&loss)
+}
+
+/// The cosine embedding loss.
+///
+/// Arguments
+///
+/// * [x1], [x2]: The input tensors of dimensions `N, D`.
+/// * [target]: A tensor of dimension `N` containing 1 for the pairs that should be similar and -1
+///   for the pairs that should be dissimilar.
+/// * [margin]: The cosine similarity under which dissimilar pairs do not contribute to the loss.
+pub fn cosine_embedding(
+    x1: &Tensor,
+    x2: &Tensor,
+    target: &Tensor,
+    margin: f64,
+    reduction: Reduction,
+) -> Result<Tensor> {
+    const EPS: f64 = 1e-12;
+    let dot = (x1 * x2)?.sum(D::Minus1)?;
+    let norm1 = (x1.sqr()?.sum(D::Minus1)? + EPS)?;
+    let norm2 = (x2.sqr()?.sum(D::Minus1)? + EPS)?;
+    let cos = (dot / (norm1 * norm2)?.sqrt()?)?;
+    let target = target.to_dtype(cos.dtype())?;
+    let zeros = cos.zeros_like()?;
+    let pos = target.eq(1f64)?.where_cond(&cos.affine(-1., 1.)?, &zeros)?;
+    let neg = target
+        .eq(-1f64)?
+        .where_cond(&(cos - margin)?.relu()?, &zeros)?;
+    reduction.reduce(&(pos + neg)?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TripletMarginConfig {
+    pub margin: f64,
+    /// The norm degree used for the pairwise distances.
+    pub p: f64,
+    /// Small value added to the differences to avoid the non-differentiable point at zero.
+    pub eps: f64,
+    /// Use the distance between the positive and the negative samples when it is smaller than
+    /// the distance between the anchor and the negative samples.
+    pub swap: bool,
+    pub reduction: Reduction,
+}
+
+impl Default for TripletMarginConfig {
+    fn default() -> Self {
+        Self {
+            margin: 1.,
+            p: 2.,
+            eps: 1e-6,
+            swap: false,
+            reduction: Reduction::Mean,
+        }
+    }
+}
+
+fn pairwise_distance(x1: &Tensor, x2: &Tensor, p: f64, eps: f64) -> Result<Tensor> {
+    let diff = (x1 - x2)?.affine(1., eps)?;
+    if p == 2. {
+        diff.sqr()?.sum(D::Minus1)?.sqrt()
+    } else {
+        diff.abs()?.powf(p)?.sum(D::Minus1)?.powf(1. / p)
+    }
+}
+
+/// The triplet margin loss, `max(d(a, p) - d(a, n) + margin, 0)` where `d` is the p-norm
+/// distance.
+///
+/// Arguments
+///
+/// * [anchor], [positive], [negative]: The input tensors of dimensions `N, D`.
+pub fn triplet_margin(
+    anchor: &Tensor,
+    positive: &Tensor,
+    negative: &Tensor,
+    cfg: &TripletMarginConfig,
+) -> Result<Tensor> {
+    let d_pos = pairwise_distance(anchor, positive, cfg.p, cfg.eps)?;
+    let mut d_neg = pairwise_distance(anchor, negative, cfg.p, cfg.eps)?;
+    if cfg.swap {
+        let d_swap = pairwise_distance(positive, negative, cfg.p, cfg.eps)?;
+        d_neg = d_neg.minimum(&d_swap)?;
+    }
+    let loss = (d_pos - d_neg)?.affine(1., cfg.margin)?.relu()?;
+    cfg.reduction.reduce(&loss)
+}
+
+fn l2_normalize(xs: &Tensor) -> Result<Tensor> {
+    let norm = xs.sqr()?.sum_keepdim(D::Minus1)?.sqrt()?;
+    xs.broadcast_div(&norm.clamp(1e-12f64, f64::INFINITY)?)
+}
+
+/// The InfoNCE contrastive loss using the other samples of the batch as negatives.
+///
+/// Arguments
+///
+/// * [query]: The query embeddings, a tensor of dimensions `N, D`.
+/// * [positive_key]: The matching key embeddings, a tensor of dimensions `N, D`.
+/// * [temperature]: The temperature applied to the cosine similarities.
+///
+/// Each query is classified among the `N` keys with the matching key being the correct class.
+pub fn info_nce(
+    query: &Tensor,
+    positive_key: &Tensor,
+    temperature: f64,
+    reduction: Reduction,
+) -> Result<Tensor> {
+    let (b_sz, dim) = query.dims2()?;
+    if positive_key.dims() != [b_sz, dim] {
+        candle::bail!(
+            "info_nce shape mismatch between query {:?} and positive_key {:?}",
+            query.shape(),
+            positive_key.shape()
+        )
+    }
+    let query = l2_normalize(query)?;
+    let keys = l2_normalize(positive_key)?;
+    let logits = query.matmul(&keys.t()?)?.affine(1. / temperature, 0.)?;
+    let labels = Tensor::arange(0u32, b_sz as u32, logits.device())?;
+    let cfg = CrossEntropyConfig {
+        reduction,
+        ..Default::default()
+    };
+    cross_entropy_with_config(&logits, &labels, &cfg)
+}
+
+/// The sigmoid focal loss from [Focal Loss for Dense Object
+/// Detection](https://arxiv.org/abs/1708.02002).
+///
+/// Arguments
+///
+/// * [inp]: The input tensor of raw logits, of arbitrary shape.
+/// * [target]: The binary labels as a float tensor with the same shape as `inp`.
+/// * [alpha]: The optional weight of the positive examples, negative examples are weighted by
+///   `1 - alpha`.
+/// * [gamma]: The focusing parameter that down-weights the well classified examples, a value of 0
+///   results in the binary cross-entropy loss.
+pub fn focal(
+    inp: &Tensor,
+    target: &Tensor,
+    alpha: Option<f64>,
+    gamma: f64,
+    reduction: Reduction,
+) -> Result<Tensor> {
+    // Numerically stable version of the binary cross-entropy with logits:
+    // max(x, 0) - x * t + log(1 + exp(-|x|))
+    let log_exp = (inp.abs()?.neg()?.exp()? + 1.)?.log()?;
+    let ce = ((inp.relu()? - (inp * target)?)? + log_exp)?;
+    let mut loss = if gamma == 0. {
+        ce
+    } else {
+        // 1 - p_t where p_t is the probability of the ground truth class.
+        let p = crate::ops::sigmoid(inp)?;
+        let one_minus_p_t = ((&p + target)? - (p * target)?.affine(2., 0.)?)?;
+        (ce * one_minus_p_t.powf(gamma)?)?
+    };
+    if let Some(alpha) = alpha {
+        let alpha_t = target.affine(2. * alpha - 1., 1. - alpha)?;
+        loss = (loss * alpha_t)?
+    }
+    reduction.reduce(&loss)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CtcConfig {
+    /// The index of the blank label.
+    pub blank: usize,
+    /// Zero out the infinite losses and their gradients, these happen when the inputs are too
+    /// short to be aligned to the targets.
+    pub zero_infinity: bool,
+    pub reduction: Reduction,
+}
+
+impl Default for CtcConfig {
+    fn default() -> Self {
+        Self {
+            blank: 0,
+            zero_infinity: false,
+            reduction: Reduction::Mean,
+        }
+    }
+}
+
+fn log_add(a: f64, b: f64) -> f64 {
+    if a == f64::NEG_INFINITY {
+        return b;
+    }
+    if b == f64::NEG_INFINITY {
+        return a;
+    }
+    let max = a.max(b);
+    max + ((a - max).exp() + (b - max).exp()).ln()
+}
+
+struct Ctc {
+    targets: Vec<Vec<u32>>,
+    input_lengths: Vec<usize>,
+    blank: usize,
+    zero_infinity: bool,
+}
+
+impl Ctc {
+    // Runs the forward-backward algorithm in log space on the log probabilities of dimensions
+    // `T, N, C`. This returns the negative log likelihood of each sample and, when `with_grad` is
+    // set, its gradient with respect to the log probabilities of this sample, of dimensions `T, C`.
+    fn forward_backward(
+        &self,
+        log_probs: &[f64],
+        (seq_len, b_sz, n_classes): (usize, usize, usize),
+        with_grad: bool,
+    ) -> Vec<(f64, Vec<f64>)> {
+        (0..b_sz)
+            .into_par_iter()
+            .map(|b| {
+                let lp = |t: usize, c: usize| log_probs[(t * b_sz + b) * n_classes + c];
+                let target = &self.targets[b];
+                let t_len = self.input_lengths[b];
+                // The extended target interleaves the labels with blanks.
+                let s_len = 2 * target.len() + 1;
+                let label = |s: usize| {
+                    if s % 2 == 0 {
+                        self.blank
+                    } else {
+                        target[s / 2] as usize
+                    }
+                };
+                // Transitions skipping a blank are allowed between distinct labels.
+                let can_skip = |s: usize| s >= 2 && s % 2 == 1 && label(s) != label(s - 2);
+                let mut grad = if with_grad {
+                    vec![0f64; seq_len * n_classes]
+                } else {
+                    vec![]
+                };
+                if t_len == 0 {
+                    let nll = if target.is_empty() { 0. } else { f64::INFINITY };
+                    let nll = if self.zero_infinity && nll.is_infinite() {
+                        0.
+                    } else {
+                        nll
+                    };
+                    return (nll, grad);
+                }
+
+                let mut alpha = vec![f64::NEG_INFINITY; t_len * s_len];
+                alpha[0] = lp(0, self.blank);
+                if s_len > 1 {
+                    alpha[1] = lp(0, label(1));
+                }
+                for t in 1..t_len {
+                    let (prev, cur) = alpha.split_at_mut(t * s_len);
+                    let prev = &prev[(t - 1) * s_len..];
+                    for s in 0..s_len {
+                        let mut v = prev[s];
+                        if s >= 1 {
+                            v = log_add(v, prev[s - 1])
+                        }
+                        if can_skip(s) {
+                            v = log_add(v, prev[s - 2])
+                        }
+                        cur[s] = v + lp(t, label(s))
+                    }
+                }
+                let last = &alpha[(t_len - 1) * s_len..];
+                let log_likelihood = if s_len > 1 {
+                    log_add(last[s_len - 1], last[s_len - 2])
+                } else {
+                    last[0]
+                };
+                let nll = -log_likelihood;
+                if nll.is_infinite() && self.zero_infinity {
+                    return (0., grad);
+                }
+                if !with_grad {
+                    return (nll, grad);
+                }
+
+                let mut beta = vec![f64::NEG_INFINITY; t_len * s_len];
+                let t = t_len - 1;
+                beta[t * s_len + s_len - 1] = lp(t, label(s_len - 1));
+                if s_len > 1 {
+                    beta[t * s_len + s_len - 2] = lp(t, label(s_len - 2));
+                }
+                for t in (0..t_len - 1).rev() {
+                    let (cur, next) = beta.split_at_mut((t + 1) * s_len);
+                    let cur = &mut cur[t * s_len..];
+                    for s in 0..s_len {
+                        let mut v = next[s];
+                        if s + 1 < s_len {
+                            v = log_add(v, next[s + 1])
+                        }
+                        if s + 2 < s_len && can_skip(s + 2) {
+                            v = log_add(v, next[s + 2])
+                        }
+                        cur[s] = v + lp(t, label(s))
+                    }
+                }
+
+                // alpha * beta counts the emission at t twice, hence the division by the
+                // probability of the label. The derivative with respect to the log probability
+                // of label c at time t is then -sum_{s: l(s) = c} alpha * beta / (y * p).
+                let mut acc = vec![f64::NEG_INFINITY; n_classes];
+                for t in 0..t_len {
+                    acc.fill(f64::NEG_INFINITY);
+                    for s in 0..s_len {
+                        let v = alpha[t * s_len + s] + beta[t * s_len + s];
+                        acc[label(s)] = log_add(acc[label(s)], v);
+                    }
+                    for (c, &acc) in acc.iter().enumerate() {
+                        if acc != f64::NEG_INFINITY {
+                            grad[t * n_classes + c] = -(acc - lp(t, c) + nll).exp()
+                        }
+                    }
+                }
+                (nll, grad)
+            })
+            .collect()
+    }
+}
+
+impl candle::CustomOp1 for Ctc {
+    fn name(&self) -> &'static str {
+        "ctc"
+    }
+
+    fn cpu_fwd(&self, storage: &CpuStorage, layout: &Layout) -> Result<(CpuStorage, Shape)> {
+        fn fwd<T: candle::WithDType>(
+            ctc: &Ctc,
+            src: &[T],
+            layout: &Layout,
+        ) -> Result<(CpuStorage, Shape)> {
+            let src = match layout.contiguous_offsets() {
+                None => candle::bail!("input has to be contiguous"),
+                Some((o1, o2)) => &src[o1..o2],
+            };
+            let dims = layout.shape().dims3()?;
+            let src = src.iter().map(|v| v.to_f64()).collect::<Vec<_>>();
+            let dst = ctc
+                .forward_backward(&src, dims, false)
+                .into_iter()
+                .map(|(nll, _)| T::from_f64(nll))
+                .collect::<Vec<_>>();
+            let storage = candle::WithDType::to_cpu_storage_owned(dst);
+            Ok((storage, Shape::from(dims.1)))
+        }
+
+        match storage {
+            CpuStorage::BF16(slice) => fwd::<half::bf16>(self, slice, layout),
+            CpuStorage::F16(slice) => fwd::<half::f16>(self, slice, layout),
+            CpuStorage::F32(slice) => fwd::<f32>(self, slice, layout),
+            CpuStorage::F64(slice) => fwd::<f64>(self, slice, layout),
+            _ => candle::bail!("unsupported dtype for ctc {:?}", storage),
+        }
+    }
+
+    fn bwd(&self, arg: &Tensor, _res: &Tensor, grad_res: &Tensor) -> Result<Option<Tensor>> {
+        let (seq_len, b_sz, n_classes) = arg.dims3()?;
+        let log_probs = arg
+            .to_dtype(candle::DType::F64)?
+            .flatten_all()?
+            .to_vec1::<f64>()?;
+        let grads = self.forward_backward(&log_probs, (seq_len, b_sz, n_classes), true);
+        let mut grad = vec![0f64; seq_len * b_sz * n_classes];
+        for (b, (_, g)) in grads.iter().enumerate() {
+            for t in 0..seq_len {
+                let dst = (t * b_sz + b) * n_classes;
+                grad[dst..dst + n_classes].copy_from_slice(&g[t * n_classes..(t + 1) * n_classes])
+            }
+        }
+        let grad = Tensor::from_vec(grad, (seq_len, b_sz, n_classes), arg.device())?
+            .to_dtype(arg.dtype())?
+            .broadcast_mul(&grad_res.reshape((1, b_sz, 1))?)?;
+        Ok(Some(grad))
+    }
+}
+
+/// The Connectionist Temporal Classification loss.
+///
+/// Arguments
+///
+/// * [log_probs]: The input tensor of dimensions `T, N, C` where `T` is the input length, `N` the
+///   batch size and `C` the number of classes including the blank. This is expected to
+///   contain log probabilities, e.g. the output of a log-softmax.
+/// * [targets]: The target labels as a tensor of u32 of dimensions `N, S`, padded to the maximum
+///   target length `S`. The targets should not contain the blank label.
+/// * [input_lengths]: The number of valid time steps for each sample.
+/// * [target_lengths]: The number of valid labels for each sample.
+///
+/// The loss is computed on the cpu with the forward-backward algorithm in log space. Similar to
+/// PyTorch, the mean reduction divides the loss of each sample by its target length before
+/// averaging over the batch.
+pub fn ctc(
+    log_probs: &Tensor,
+    targets: &Tensor,
+    input_lengths: &[usize],
+    target_lengths: &[usize],
+    cfg: &CtcConfig,
+) -> Result<Tensor> {
+    let (seq_len, b_sz, n_classes) = log_probs.dims3()?;
+    let (target_b_sz, max_target_len) = targets.dims2()?;
+    if target_b_sz != b_sz || input_lengths.len() != b_sz || target_lengths.len() != b_sz {
+        candle::bail!(
+            "ctc batch size mismatch, log_probs {b_sz}, targets {target_b_sz}, input_lengths {}, target_lengths {}",
+            input_lengths.len(),
+            target_lengths.len()
+        )
+    }
+    if cfg.blank >= n_classes {
+        candle::bail!(
+            "ctc blank {} is out of range for {n_classes} classes",
+            cfg.blank
+        )
+    }
+    let targets = targets.to_vec2::<u32>()?;
+    let mut ctc_targets = Vec::with_capacity(b_sz);
+    for (b, targets) in targets.into_iter().enumerate() {
+        let (input_len, target_len) = (input_lengths[b], target_lengths[b]);
+        if input_len > seq_len {
+            candle::bail!("ctc input length {input_len} is larger than the input ({seq_len})")
+        }
+        if target_len > max_target_len {
+            candle::bail!(
+                "ctc target length {target_len} is larger than the targets ({max_target_len})"
+            )
+        }
+        let targets = targets[..target_len].to_vec();
+        if let Some(&t) = targets
+            .iter()
+            .find(|&&t| t as usize >= n_classes || t as usize == cfg.blank)
+        {
+            candle::bail!("ctc invalid target {t} for sample {b}")
+        }
+        ctc_targets.push(targets)
+    }
+    let op = Ctc {
+        targets: ctc_targets,
+        input_lengths: input_lengths.to_vec(),
+        blank: cfg.blank,
+        zero_infinity: cfg.zero_infinity,
+    };
+    let loss = log_probs
+        .to_device(&candle::Device::Cpu)?
+        .contiguous()?
+        .apply_op1(op)?;
+    // The division by the target lengths happens on the cpu as f64 tensors are not supported
+    // on all the devices.
+    let loss = match cfg.reduction {
+        Reduction::Mean => {
+            let target_lengths = target_lengths
+                .iter()
+                .map(|&l| l.max(1) as f64)
+                .collect::<Vec<_>>();
+            let target_lengths =
+                Tensor::new(target_lengths, &candle::Device::Cpu)?.to_dtype(loss.dtype())?;
+            (loss / target_lengths)?
+        }
+        _ => loss,
+    };
+    let loss = loss.to_device(log_probs.device())?;
+    match cfg.reduction {
+        Reduction::Mean => loss.mean_all(),
+        reduction => reduction.reduce(&loss),
+    }
+}
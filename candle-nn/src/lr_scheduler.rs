@@ -0,0 +1,391 @@
+//! Learning rate schedules.
+//!
+//! A schedule maps a step index to a learning rate, an [`LrScheduler`] keeps track of the
+//! current step and updates the learning rate of an [`Optimizer`]. Schedules can be chained,
+//! e.g. a linear warmup followed by cosine annealing:
+//!
+//! ```rust
+//! use candle_nn::lr_scheduler::{CosineAnnealing, LinearWarmup, LrSchedule, LrScheduler};
+//! let schedule = LinearWarmup::new(1e-3, 100).then(100, CosineAnnealing::new(1e-3, 1e-5, 900));
+//! let mut scheduler = LrScheduler::new(schedule);
+//! # use candle_nn::Optimizer;
+//! # let mut opt = candle_nn::SGD::empty(0.)?;
+//! for _step in 0..1000 {
+//!     scheduler.step(&mut opt);
+//!     // opt.backward_step(&loss)?;
+//! }
+//! # Ok::<(), candle::Error>(())
+//! ```
+//!
+//! The schedulers implement `serde::Serialize` and `serde::Deserialize` so that they can be
+//! saved together with the optimizer state when checkpointing.
+use crate::Optimizer;
+use serde::{Deserialize, Serialize};
+
+/// A learning rate schedule.
+pub trait LrSchedule {
+    /// The learning rate for step `step`, starting from 0.
+    fn lr_at(&self, step: usize) -> f64;
+
+    /// Uses this schedule for the first `steps` steps and `next` afterwards, the step index of
+    /// `next` starts from 0 when switching.
+    fn then<S: LrSchedule>(self, steps: usize, next: S) -> Chain<Self, S>
+    where
+        Self: Sized,
+    {
+        Chain {
+            first: self,
+            first_steps: steps,
+            second: next,
+        }
+    }
+}
+
+/// A constant learning rate.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Constant {
+    pub lr: f64,
+}
+
+impl Constant {
+    pub fn new(lr: f64) -> Self {
+        Self { lr }
+    }
+}
+
+impl LrSchedule for Constant {
+    fn lr_at(&self, _step: usize) -> f64 {
+        self.lr
+    }
+}
+
+/// Linearly increases the learning rate from `start_factor * lr` to `lr` over `warmup_steps`
+/// steps, then keeps it constant.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinearWarmup {
+    pub lr: f64,
+    pub warmup_steps: usize,
+    pub start_factor: f64,
+}
+
+impl LinearWarmup {
+    pub fn new(lr: f64, warmup_steps: usize) -> Self {
+        Self {
+            lr,
+            warmup_steps,
+            start_factor: 0.,
+        }
+    }
+}
+
+impl LrSchedule for LinearWarmup {
+    fn lr_at(&self, step: usize) -> f64 {
+        if step >= self.warmup_steps {
+            return self.lr;
+        }
+        let pct = step as f64 / self.warmup_steps as f64;
+        self.lr * (self.start_factor + (1. - self.start_factor) * pct)
+    }
+}
+
+/// Cosine annealing with warm restarts, "SGDR: Stochastic Gradient Descent with Warm Restarts"
+/// <https://arxiv.org/abs/1608.03983>.
+///
+/// The learning rate goes from `lr` to `min_lr` following a half cosine over `period` steps, then
+/// restarts from `lr`. Each period is `period_mult` times longer than the previous one.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CosineAnnealing {
+    pub lr: f64,
+    pub min_lr: f64,
+    pub period: usize,
+    pub period_mult: usize,
+}
+
+impl CosineAnnealing {
+    pub fn new(lr: f64, min_lr: f64, period: usize) -> Self {
+        Self {
+            lr,
+            min_lr,
+            period,
+            period_mult: 1,
+        }
+    }
+}
+
+impl LrSchedule for CosineAnnealing {
+    fn lr_at(&self, step: usize) -> f64 {
+        let mut t_cur = step;
+        let mut t_i = self.period.max(1);
+        if self.period_mult <= 1 {
+            t_cur %= t_i
+        } else {
+            while t_cur >= t_i {
+                t_cur -= t_i;
+                t_i *= self.period_mult;
+            }
+        }
+        let cos = (std::f64::consts::PI * t_cur as f64 / t_i as f64).cos();
+        self.min_lr + (self.lr - self.min_lr) * (1. + cos) / 2.
+    }
+}
+
+/// The one-cycle policy, "Super-Convergence: Very Fast Training of Neural Networks Using Large
+/// Learning Rates" <https://arxiv.org/abs/1708.07120>, with the same defaults as PyTorch.
+///
+/// The learning rate increases from `max_lr / div_factor` to `max_lr` over the first
+/// `pct_start` fraction of the `total_steps` steps, then decreases to
+/// `max_lr / (div_factor * final_div_factor)`, both phases using cosine annealing.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OneCycle {
+    pub max_lr: f64,
+    pub total_steps: usize,
+    pub pct_start: f64,
+    pub div_factor: f64,
+    pub final_div_factor: f64,
+}
+
+impl OneCycle {
+    pub fn new(max_lr: f64, total_steps: usize) -> Self {
+        Self {
+            max_lr,
+            total_steps,
+            pct_start: 0.3,
+            div_factor: 25.,
+            final_div_factor: 1e4,
+        }
+    }
+}
+
+fn cosine_anneal(start: f64, end: f64, pct: f64) -> f64 {
+    end + (start - end) / 2. * ((std::f64::consts::PI * pct).cos() + 1.)
+}
+
+impl LrSchedule for OneCycle {
+    fn lr_at(&self, step: usize) -> f64 {
+        let initial_lr = self.max_lr / self.div_factor;
+        let min_lr = initial_lr / self.final_div_factor;
+        // Steps past the end of the cycle keep the final learning rate.
+        let step = step.min(self.total_steps.saturating_sub(1)) as f64;
+        let warmup_end = self.pct_start * self.total_steps as f64 - 1.;
+        let total_end = self.total_steps as f64 - 1.;
+        if step <= warmup_end {
+            let pct = if warmup_end > 0. {
+                step / warmup_end
+            } else {
+                1.
+            };
+            cosine_anneal(initial_lr, self.max_lr, pct)
+        } else {
+            let pct = (step - warmup_end) / (total_end - warmup_end);
+            cosine_anneal(self.max_lr, min_lr, pct)
+        }
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every `step_size` steps.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StepDecay {
+    pub lr: f64,
+    pub step_size: usize,
+    pub gamma: f64,
+}
+
+impl StepDecay {
+    pub fn new(lr: f64, step_size: usize, gamma: f64) -> Self {
+        Self {
+            lr,
+            step_size,
+            gamma,
+        }
+    }
+}
+
+impl LrSchedule for StepDecay {
+    fn lr_at(&self, step: usize) -> f64 {
+        self.lr * self.gamma.powi((step / self.step_size.max(1)) as i32)
+    }
+}
+
+/// Multiplies the learning rate by `gamma` at every step.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExponentialDecay {
+    pub lr: f64,
+    pub gamma: f64,
+}
+
+impl ExponentialDecay {
+    pub fn new(lr: f64, gamma: f64) -> Self {
+        Self { lr, gamma }
+    }
+}
+
+impl LrSchedule for ExponentialDecay {
+    fn lr_at(&self, step: usize) -> f64 {
+        self.lr * self.gamma.powi(step as i32)
+    }
+}
+
+/// Two schedules applied one after the other, see [`LrSchedule::then`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Chain<A, B> {
+    pub first: A,
+    pub first_steps: usize,
+    pub second: B,
+}
+
+impl<A: LrSchedule, B: LrSchedule> LrSchedule for Chain<A, B> {
+    fn lr_at(&self, step: usize) -> f64 {
+        if step < self.first_steps {
+            self.first.lr_at(step)
+        } else {
+            self.second.lr_at(step - self.first_steps)
+        }
+    }
+}
+
+/// Drives the learning rate of an optimizer using a schedule.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LrScheduler<S> {
+    schedule: S,
+    step: usize,
+}
+
+impl<S: LrSchedule> LrScheduler<S> {
+    pub fn new(schedule: S) -> Self {
+        Self { schedule, step: 0 }
+    }
+
+    /// Sets the learning rate of `opt` for its next step and advances the schedule, this should
+    /// be called before each optimizer step.
+    pub fn step<O: Optimizer>(&mut self, opt: &mut O) {
+        opt.set_learning_rate(self.lr());
+        self.step += 1
+    }
+
+    /// The learning rate for the next call to [`LrScheduler::step`].
+    pub fn lr(&self) -> f64 {
+        self.schedule.lr_at(self.step)
+    }
+
+    /// The number of steps taken so far.
+    pub fn current_step(&self) -> usize {
+        self.step
+    }
+
+    /// Moves the schedule to `step`, e.g. when resuming training without a saved scheduler.
+    pub fn set_current_step(&mut self, step: usize) {
+        self.step = step
+    }
+
+    pub fn schedule(&self) -> &S {
+        &self.schedule
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlateauMode {
+    /// The monitored metric should decrease, e.g. a validation loss.
+    Min,
+    /// The monitored metric should increase, e.g. an accuracy.
+    Max,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParamsReduceLrOnPlateau {
+    pub mode: PlateauMode,
+    /// The factor applied to the learning rate when reducing it.
+    pub factor: f64,
+    /// The number of evaluations without improvement after which the learning rate is reduced.
+    pub patience: usize,
+    /// The relative improvement over the best value required to count as an improvement.
+    pub threshold: f64,
+    /// The number of evaluations to wait after a reduction before resuming normal operation.
+    pub cooldown: usize,
+    pub min_lr: f64,
+    /// Reductions smaller than `eps` are ignored.
+    pub eps: f64,
+}
+
+impl Default for ParamsReduceLrOnPlateau {
+    fn default() -> Self {
+        Self {
+            mode: PlateauMode::Min,
+            factor: 0.1,
+            patience: 10,
+            threshold: 1e-4,
+            cooldown: 0,
+            min_lr: 0.,
+            eps: 1e-8,
+        }
+    }
+}
+
+/// Reduces the learning rate when a metric has stopped improving, with the same behavior as
+/// PyTorch's `ReduceLROnPlateau` using a relative threshold.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReduceLrOnPlateau {
+    params: ParamsReduceLrOnPlateau,
+    lr: f64,
+    // None until the first evaluation, this avoids serializing infinite values.
+    best: Option<f64>,
+    num_bad_evals: usize,
+    cooldown_counter: usize,
+}
+
+impl ReduceLrOnPlateau {
+    pub fn new(lr: f64, params: ParamsReduceLrOnPlateau) -> Self {
+        Self {
+            params,
+            lr,
+            best: None,
+            num_bad_evals: 0,
+            cooldown_counter: 0,
+        }
+    }
+
+    fn is_better(&self, metric: f64) -> bool {
+        match (self.best, self.params.mode) {
+            (None, _) => true,
+            (Some(best), PlateauMode::Min) => metric < best * (1. - self.params.threshold),
+            (Some(best), PlateauMode::Max) => metric > best * (1. + self.params.threshold),
+        }
+    }
+
+    /// Records a new value of the monitored metric, typically after each evaluation, and updates
+    /// the learning rate of `opt`.
+    pub fn step<O: Optimizer>(&mut self, metric: f64, opt: &mut O) {
+        if self.is_better(metric) {
+            self.best = Some(metric);
+            self.num_bad_evals = 0;
+        } else {
+            self.num_bad_evals += 1;
+        }
+        if self.cooldown_counter > 0 {
+            self.cooldown_counter -= 1;
+            self.num_bad_evals = 0;
+        }
+        if self.num_bad_evals > self.params.patience {
+            let lr = f64::max(self.lr * self.params.factor, self.params.min_lr);
+            if self.lr - lr > self.params.eps {
+                self.lr = lr
+            }
+            self.cooldown_counter = self.params.cooldown;
+            self.num_bad_evals = 0;
+        }
+        opt.set_learning_rate(self.lr)
+    }
+
+    /// The current learning rate.
+    pub fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    /// The best value of the monitored metric so far.
+    pub fn best(&self) -> Option<f64> {
+        self.best
+    }
+
+    pub fn params(&self) -> &ParamsReduceLrOnPlateau {
+        &self.params
+    }
+}
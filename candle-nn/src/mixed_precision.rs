@@ -0,0 +1,222 @@
+//! Mixed-precision training with dynamic loss scaling.
+//!
+//! The model variables are stored in a low-precision dtype such as F16 or BF16 and used for the
+//! forward and backward passes, while the optimizer updates F32 master copies of these variables
+//! that are cast back to the model after each step.
+//!
+//! Small gradients underflow in F16, so the loss is multiplied by a scale factor before running
+//! the backward pass and the gradients are divided by this factor in F32 before the optimizer
+//! step. When the gradients contain NaN or infinite values, the step is skipped and the scale is
+//! reduced. The scale grows again after a number of consecutive steps without overflow.
+//!
+//! ```rust
+//! use candle::{DType, Device, Module, Tensor};
+//! use candle_nn::{linear, MixedPrecision, ParamsGradScaler, VarBuilder, VarMap, SGD};
+//! # fn main() -> candle::Result<()> {
+//! let dev = Device::Cpu;
+//! let varmap = VarMap::new();
+//! let vb = VarBuilder::from_varmap(&varmap, DType::F16, &dev);
+//! let model = linear(4, 2, vb)?;
+//! let mut opt: MixedPrecision<SGD> =
+//!     MixedPrecision::new(varmap.all_vars(), 0.1, ParamsGradScaler::default())?;
+//!
+//! let xs = Tensor::ones((8, 4), DType::F16, &dev)?;
+//! for _step in 0..10 {
+//!     let loss = model.forward(&xs)?.to_dtype(DType::F32)?.sqr()?.mean_all()?;
+//!     // The step is skipped, and the scale reduced, if the scaled gradients overflow.
+//!     let _stepped = opt.backward_step(&loss)?;
+//! }
+//! # Ok(()) }
+//! ```
+use crate::Optimizer;
+use candle::backprop::GradStore;
+use candle::{DType, Result, Tensor, Var};
+
+/// The parameters of the [`GradScaler`], the defaults match PyTorch's `GradScaler`.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ParamsGradScaler {
+    pub init_scale: f64,
+    /// The factor applied to the scale after `growth_interval` steps without overflow.
+    pub growth_factor: f64,
+    /// The factor applied to the scale when the gradients overflow.
+    pub backoff_factor: f64,
+    pub growth_interval: usize,
+}
+
+impl Default for ParamsGradScaler {
+    fn default() -> Self {
+        Self {
+            init_scale: 65536.,
+            growth_factor: 2.,
+            backoff_factor: 0.5,
+            growth_interval: 2000,
+        }
+    }
+}
+
+impl ParamsGradScaler {
+    /// A constant scale of one, BF16 has the same range as F32 so it usually does not need loss
+    /// scaling. Steps with non-finite gradients are still skipped.
+    pub fn no_scaling() -> Self {
+        Self {
+            init_scale: 1.,
+            growth_factor: 1.,
+            backoff_factor: 1.,
+            growth_interval: usize::MAX,
+        }
+    }
+}
+
+/// Dynamic loss scaling, the state can be serialized to resume training from a checkpoint.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GradScaler {
+    params: ParamsGradScaler,
+    scale: f64,
+    growth_tracker: usize,
+}
+
+impl GradScaler {
+    pub fn new(params: ParamsGradScaler) -> Self {
+        Self {
+            params,
+            scale: params.init_scale,
+            growth_tracker: 0,
+        }
+    }
+
+    /// The current scale factor.
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Multiplies the loss by the current scale factor.
+    pub fn scale_loss(&self, loss: &Tensor) -> Result<Tensor> {
+        loss.affine(self.scale, 0.)
+    }
+
+    /// Converts the gradients of `vars` to F32 and divides them by the scale factor. Returns
+    /// false if some of these gradients contain NaN or infinite values.
+    pub fn unscale(&self, grads: &mut GradStore, vars: &[Var]) -> Result<bool> {
+        let inv_scale = 1. / self.scale;
+        for var in vars.iter() {
+            if let Some(grad) = grads.remove(var) {
+                let grad = grad.to_dtype(DType::F32)?.affine(inv_scale, 0.)?;
+                grads.insert(var, grad);
+            }
+        }
+        Ok(grads.non_finite_vars(vars)?.is_empty())
+    }
+
+    /// Updates the scale factor after a step, `found_overflow` should be true when the step was
+    /// skipped because of non-finite gradients.
+    pub fn update(&mut self, found_overflow: bool) {
+        if found_overflow {
+            self.scale *= self.params.backoff_factor;
+            self.growth_tracker = 0;
+        } else {
+            self.growth_tracker += 1;
+            if self.growth_tracker >= self.params.growth_interval {
+                self.scale *= self.params.growth_factor;
+                self.growth_tracker = 0;
+            }
+        }
+    }
+}
+
+/// Wraps an optimizer to train low-precision variables, see the [module level
+/// documentation](self).
+#[derive(Debug)]
+pub struct MixedPrecision<O: Optimizer> {
+    optimizer: O,
+    vars: Vec<Var>,
+    master_vars: Vec<Var>,
+    scaler: GradScaler,
+    skipped_steps: usize,
+}
+
+impl<O: Optimizer> MixedPrecision<O> {
+    /// Creates F32 master copies of the floating point variables of `vars` and an optimizer of
+    /// type `O` over these copies.
+    pub fn new(vars: Vec<Var>, config: O::Config, params: ParamsGradScaler) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .collect::<Vec<_>>();
+        let master_vars = vars
+            .iter()
+            .map(|var| Var::from_tensor(&var.to_dtype(DType::F32)?))
+            .collect::<Result<Vec<_>>>()?;
+        let optimizer = O::new(master_vars.clone(), config)?;
+        Ok(Self {
+            optimizer,
+            vars,
+            master_vars,
+            scaler: GradScaler::new(params),
+            skipped_steps: 0,
+        })
+    }
+
+    /// Applies an optimizer step using the gradients of the scaled loss. Returns false if the
+    /// step was skipped because the gradients overflowed.
+    pub fn step(&mut self, grads: &mut GradStore) -> Result<bool> {
+        let finite = self.scaler.unscale(grads, &self.vars)?;
+        self.scaler.update(!finite);
+        if !finite {
+            self.skipped_steps += 1;
+            return Ok(false);
+        }
+        for (var, master_var) in self.vars.iter().zip(self.master_vars.iter()) {
+            if let Some(grad) = grads.remove(var) {
+                grads.insert(master_var, grad);
+            }
+        }
+        self.optimizer.step(grads)?;
+        for (var, master_var) in self.vars.iter().zip(self.master_vars.iter()) {
+            var.set(&master_var.to_dtype(var.dtype())?)?
+        }
+        Ok(true)
+    }
+
+    /// Scales the loss, runs the backward pass and applies an optimizer step. Returns false if
+    /// the step was skipped because the gradients overflowed.
+    pub fn backward_step(&mut self, loss: &Tensor) -> Result<bool> {
+        let mut grads = self.scaler.scale_loss(loss)?.backward()?;
+        self.step(&mut grads)
+    }
+
+    pub fn optimizer(&self) -> &O {
+        &self.optimizer
+    }
+
+    /// The wrapped optimizer, e.g. to be passed to a learning rate scheduler.
+    pub fn optimizer_mut(&mut self) -> &mut O {
+        &mut self.optimizer
+    }
+
+    pub fn learning_rate(&self) -> f64 {
+        self.optimizer.learning_rate()
+    }
+
+    pub fn set_learning_rate(&mut self, lr: f64) {
+        self.optimizer.set_learning_rate(lr)
+    }
+
+    /// The F32 copies of the variables that are updated by the optimizer.
+    pub fn master_vars(&self) -> &[Var] {
+        &self.master_vars
+    }
+
+    pub fn scaler(&self) -> &GradScaler {
+        &self.scaler
+    }
+
+    /// The scaler, e.g. to restore its state when resuming from a checkpoint.
+    pub fn scaler_mut(&mut self) -> &mut GradScaler {
+        &mut self.scaler
+    }
+
+    /// The number of steps that have been skipped because of overflowing gradients.
+    pub fn skipped_steps(&self) -> usize {
+        self.skipped_steps
+    }
+}
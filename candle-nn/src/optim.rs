@@ -1,5 +1,6 @@
 //! Various optimization algorithms.
 use candle::{Result, Tensor, Var};
+use std::collections::HashMap;
 
 /// The interface optimizers should implement.
 pub trait Optimizer: Sized {
@@ -26,56 +27,408 @@ pub trait Optimizer: Sized {
         let vars: Vec<_> = vars.iter().map(|&v| v.clone()).collect();
         Self::new(vars, config)
     }
+
+    /// The variables updated by this optimizer. This is used by the gradient clipping helpers,
+    /// the default implementation returns no variables, optimizers have to override it to
+    /// support [`Optimizer::backward_step_clip_grad_norm`].
+    fn vars(&self) -> Vec<Var> {
+        vec![]
+    }
+
+    /// Similar to [`Optimizer::backward_step`] but the gradients are first rescaled so that
+    /// their global norm is at most `max_norm`. Returns the norm before clipping, when it is not
+    /// finite the optimizer step is skipped.
+    ///
+    /// This returns an error if [`Optimizer::vars`] does not return any variable.
+    fn backward_step_clip_grad_norm(&mut self, loss: &Tensor, max_norm: f64) -> Result<f64> {
+        let vars = self.vars();
+        if vars.is_empty() {
+            candle::bail!("gradient clipping requires the optimizer to implement Optimizer::vars")
+        }
+        let mut grads = loss.backward()?;
+        let norm = grads.clip_grad_norm(&vars, max_norm)?;
+        if norm.is_finite() {
+            self.step(&grads)?;
+        }
+        Ok(norm)
+    }
+
+    /// Creates an optimizer for some named parameter groups, e.g. as returned by
+    /// [`crate::VarMap::param_groups`]. The variable names are used as keys in the state dict.
+    ///
+    /// The default implementation only supports groups that do not override any hyper-parameter.
+    fn from_param_groups(groups: Vec<ParamGroup>, config: Self::Config) -> Result<Self> {
+        let mut vars = vec![];
+        for group in groups.into_iter() {
+            if group.config != ParamGroupConfig::default() {
+                candle::bail!("this optimizer does not support parameter group overrides")
+            }
+            vars.extend(group.vars.into_iter().map(|(_, var)| var))
+        }
+        Self::new(vars, config)
+    }
+
+    /// Returns the optimizer state, e.g. the moment estimates, so that it can be restored with
+    /// [`Optimizer::load_state_dict`] when resuming training.
+    ///
+    /// The keys are `{name}.{state}` where `name` is the name of the variable in its parameter
+    /// group, or its index when the optimizer was created from a list of variables, together
+    /// with a `step` entry for optimizers that count steps.
+    fn state_dict(&self) -> Result<HashMap<String, Tensor>> {
+        Ok(HashMap::new())
+    }
+
+    /// Restores the state returned by [`Optimizer::state_dict`]. The variables have to be the
+    /// same as when the state was saved.
+    ///
+    /// When the optimizer was created from a list of variables, the state is matched by index so
+    /// the variables must also be passed in the same order. Resuming training is more robust with
+    /// an optimizer created by [`Optimizer::from_param_groups`], e.g. with
+    /// `from_param_groups(varmap.param_groups(&[]), config)`, as the state is then matched by
+    /// variable name.
+    fn load_state_dict(&mut self, state_dict: &HashMap<String, Tensor>) -> Result<()> {
+        let _ = state_dict;
+        Ok(())
+    }
+
+    /// Saves the optimizer state in the safetensors format.
+    fn save_state<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+        candle::safetensors::save(&self.state_dict()?, path)
+    }
+
+    /// Loads the optimizer state from a safetensors file written by [`Optimizer::save_state`].
+    fn load_state<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
+        let state_dict = candle::safetensors::load(path, &candle::Device::Cpu)?;
+        self.load_state_dict(&state_dict)
+    }
+}
+
+/// Hyper-parameter overrides shared by the variables of a [`ParamGroup`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParamGroupConfig {
+    /// Multiplier applied to the optimizer learning rate. Using a multiplier rather than an
+    /// absolute value lets learning rate schedules apply to all the groups.
+    pub lr_scale: f64,
+    /// The weight decay for this group, the optimizer one is used when `None`.
+    pub weight_decay: Option<f64>,
+}
+
+impl Default for ParamGroupConfig {
+    fn default() -> Self {
+        Self {
+            lr_scale: 1.,
+            weight_decay: None,
+        }
+    }
+}
+
+impl ParamGroupConfig {
+    /// A group that is not subject to weight decay, e.g. for biases and normalization layers.
+    pub fn no_weight_decay() -> Self {
+        Self {
+            weight_decay: Some(0.),
+            ..Self::default()
+        }
+    }
+}
+
+/// A set of named variables optimized with the same hyper-parameters.
+#[derive(Clone, Debug)]
+pub struct ParamGroup {
+    pub vars: Vec<(String, Var)>,
+    pub config: ParamGroupConfig,
+}
+
+impl ParamGroup {
+    pub fn new(vars: Vec<(String, Var)>, config: ParamGroupConfig) -> Self {
+        Self { vars, config }
+    }
+}
+
+// A variable tracked by an optimizer together with its name, the overrides of its group and its
+// optimizer specific state.
+#[derive(Debug)]
+struct Param<S> {
+    name: String,
+    var: Var,
+    config: ParamGroupConfig,
+    state: S,
+}
+
+impl<S> Param<S> {
+    fn lr(&self, lr: f64) -> f64 {
+        lr * self.config.lr_scale
+    }
+
+    fn weight_decay(&self, weight_decay: f64) -> f64 {
+        self.config.weight_decay.unwrap_or(weight_decay)
+    }
+}
+
+// The variables passed to `Optimizer::new` are named after their index.
+fn unnamed_group(vars: Vec<Var>) -> Vec<ParamGroup> {
+    let vars = vars
+        .into_iter()
+        .enumerate()
+        .map(|(i, var)| (i.to_string(), var))
+        .collect();
+    vec![ParamGroup::new(vars, ParamGroupConfig::default())]
+}
+
+// Creates the optimizer parameters, skipping the non-float variables.
+fn make_params<S>(
+    groups: Vec<ParamGroup>,
+    mut state: impl FnMut(&Var) -> Result<S>,
+) -> Result<Vec<Param<S>>> {
+    let mut params = vec![];
+    for group in groups.into_iter() {
+        for (name, var) in group.vars.into_iter() {
+            if !var.dtype().is_float() {
+                continue;
+            }
+            let state = state(&var)?;
+            params.push(Param {
+                name,
+                var,
+                config: group.config,
+                state,
+            })
+        }
+    }
+    Ok(params)
+}
+
+// Gathers the state variables returned by `state_vars` for each parameter.
+fn params_state_dict<'a, S: 'a>(
+    params: &'a [Param<S>],
+    step_t: Option<usize>,
+    state_vars: impl Fn(&'a S) -> Vec<(&'static str, &'a Var)>,
+) -> Result<HashMap<String, Tensor>> {
+    let mut state_dict = HashMap::new();
+    if let Some(step_t) = step_t {
+        let step_t = Tensor::new(step_t as f64, &candle::Device::Cpu)?;
+        state_dict.insert("step".to_string(), step_t);
+    }
+    for param in params.iter() {
+        for (key, var) in state_vars(&param.state) {
+            let key = format!("{}.{key}", param.name);
+            state_dict.insert(key, var.as_tensor().copy()?);
+        }
+    }
+    Ok(state_dict)
+}
+
+fn load_state_step(state_dict: &HashMap<String, Tensor>) -> Result<usize> {
+    match state_dict.get("step") {
+        None => candle::bail!("missing step in optimizer state dict"),
+        Some(step_t) => {
+            let step_t = step_t.to_dtype(candle::DType::F64)?.to_scalar::<f64>()?;
+            Ok(step_t as usize)
+        }
+    }
+}
+
+// Sets `var` from the state dict entry `key`, returns false if there is no such entry.
+fn load_state_var(state_dict: &HashMap<String, Tensor>, key: &str, var: &Var) -> Result<bool> {
+    let value = match state_dict.get(key) {
+        None => return Ok(false),
+        Some(value) => value,
+    };
+    if value.shape() != var.shape() {
+        candle::bail!(
+            "shape mismatch for {key} in optimizer state dict: {:?} <> {:?}",
+            value.shape(),
+            var.shape()
+        )
+    }
+    let value = value.to_dtype(var.dtype())?.to_device(var.device())?;
+    var.set(&value)?;
+    Ok(true)
+}
+
+// Sets the state variables returned by `state_vars` for each parameter, all of them have to be
+// present in the state dict.
+fn params_load_state_dict<S>(
+    params: &[Param<S>],
+    state_dict: &HashMap<String, Tensor>,
+    state_vars: impl Fn(&S) -> Vec<(&'static str, &Var)>,
+) -> Result<()> {
+    for param in params.iter() {
+        for (key, var) in state_vars(&param.state) {
+            let key = format!("{}.{key}", param.name);
+            if !load_state_var(state_dict, &key, var)? {
+                candle::bail!("missing {key} in optimizer state dict")
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct ParamsSGD {
+    pub lr: f64,
+    pub momentum: f64,
+    pub dampening: f64,
+    pub weight_decay: f64,
+    pub nesterov: bool,
+}
+
+impl Default for ParamsSGD {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            momentum: 0.,
+            dampening: 0.,
+            weight_decay: 0.,
+            nesterov: false,
+        }
+    }
 }
 
 /// Optimizer for Stochastic Gradient Descent.
 ///
-/// Contrary to the PyTorch implementation of SGD, this version does not support momentum.
+/// The optimizer created through the [`Optimizer`] trait only uses a learning rate, momentum,
+/// dampening, weight decay and Nesterov momentum can be enabled with [`SGD::new_with_params`].
+/// The update rule is the same as the PyTorch one.
 #[derive(Debug)]
 pub struct SGD {
-    vars: Vec<Var>,
-    learning_rate: f64,
+    // The momentum buffers are lazily created on the first step as they are initialized with the
+    // gradient.
+    vars: Vec<Param<Option<Var>>>,
+    params: ParamsSGD,
 }
 
 impl Optimizer for SGD {
     type Config = f64;
 
     fn new(vars: Vec<Var>, learning_rate: f64) -> Result<Self> {
-        let vars = vars
-            .into_iter()
-            .filter(|var| var.dtype().is_float())
-            .collect();
-        Ok(Self {
-            vars,
-            learning_rate,
-        })
+        Self::from_param_groups(unnamed_group(vars), learning_rate)
+    }
+
+    fn from_param_groups(groups: Vec<ParamGroup>, learning_rate: f64) -> Result<Self> {
+        let params = ParamsSGD {
+            lr: learning_rate,
+            ..ParamsSGD::default()
+        };
+        Self::from_param_groups_with_params(groups, params)
     }
 
     fn learning_rate(&self) -> f64 {
-        self.learning_rate
+        self.params.lr
     }
 
     fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
-        for var in self.vars.iter() {
-            if let Some(grad) = grads.get(var) {
-                var.set(&var.sub(&(grad * self.learning_rate)?)?)?;
+        let ParamsSGD {
+            lr,
+            momentum,
+            dampening,
+            weight_decay,
+            nesterov,
+        } = self.params;
+        for param in self.vars.iter_mut() {
+            let theta = &param.var;
+            if let Some(grad) = grads.get(theta) {
+                let mut grad = grad.clone();
+                let weight_decay = param.weight_decay(weight_decay);
+                if weight_decay != 0. {
+                    grad = (grad + (theta.as_tensor() * weight_decay)?)?;
+                }
+                if momentum != 0. {
+                    let buf = match &param.state {
+                        None => {
+                            param.state = Some(Var::from_tensor(&grad)?);
+                            grad.clone()
+                        }
+                        Some(buf) => {
+                            let next_buf =
+                                ((buf.as_tensor() * momentum)? + (&grad * (1. - dampening))?)?;
+                            buf.set(&next_buf)?;
+                            next_buf
+                        }
+                    };
+                    grad = if nesterov {
+                        (grad + (buf * momentum)?)?
+                    } else {
+                        buf
+                    };
+                }
+                theta.set(&theta.sub(&(grad * param.lr(lr))?)?)?;
             }
         }
         Ok(())
     }
 
     fn set_learning_rate(&mut self, lr: f64) {
-        self.learning_rate = lr
+        self.params.lr = lr
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.vars.iter().map(|p| p.var.clone()).collect()
+    }
+
+    fn state_dict(&self) -> Result<HashMap<String, Tensor>> {
+        params_state_dict(&self.vars, None, |buf| match buf {
+            None => vec![],
+            Some(buf) => vec![("momentum_buffer", buf)],
+        })
+    }
+
+    fn load_state_dict(&mut self, state_dict: &HashMap<String, Tensor>) -> Result<()> {
+        // The momentum buffers are optional as they only exist after the first step.
+        for param in self.vars.iter_mut() {
+            let key = format!("{}.momentum_buffer", param.name);
+            if param.state.is_none() && state_dict.contains_key(&key) {
+                param.state = Some(Var::zeros(
+                    param.var.shape(),
+                    param.var.dtype(),
+                    param.var.device(),
+                )?);
+            }
+            if let Some(buf) = &param.state {
+                if !load_state_var(state_dict, &key, buf)? {
+                    param.state = None
+                }
+            }
+        }
+        Ok(())
     }
 }
 
 impl SGD {
+    pub fn new_with_params(vars: Vec<Var>, params: ParamsSGD) -> Result<Self> {
+        Self::from_param_groups_with_params(unnamed_group(vars), params)
+    }
+
+    pub fn from_param_groups_with_params(
+        groups: Vec<ParamGroup>,
+        params: ParamsSGD,
+    ) -> Result<Self> {
+        if params.nesterov && (params.momentum <= 0. || params.dampening != 0.) {
+            candle::bail!("nesterov momentum requires a momentum and zero dampening")
+        }
+        let vars = make_params(groups, |_| Ok(None))?;
+        Ok(Self { vars, params })
+    }
+
     pub fn into_inner(self) -> Vec<Var> {
-        self.vars
+        self.vars.into_iter().map(|v| v.var).collect()
     }
 
     pub fn push(&mut self, var: &Var) {
-        self.vars.push(var.clone())
+        self.vars.push(Param {
+            name: self.vars.len().to_string(),
+            var: var.clone(),
+            config: ParamGroupConfig::default(),
+            state: None,
+        })
+    }
+
+    pub fn params(&self) -> &ParamsSGD {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: ParamsSGD) {
+        self.params = params;
     }
 }
 
@@ -102,14 +455,34 @@ impl Default for ParamsAdamW {
 
 #[derive(Debug)]
 struct VarAdamW {
-    var: Var,
     first_moment: Var,
     second_moment: Var,
 }
 
+impl VarAdamW {
+    fn new(var: &Var) -> Result<Self> {
+        let dtype = var.dtype();
+        let shape = var.shape();
+        let device = var.device();
+        let first_moment = Var::zeros(shape, dtype, device)?;
+        let second_moment = Var::zeros(shape, dtype, device)?;
+        Ok(Self {
+            first_moment,
+            second_moment,
+        })
+    }
+
+    fn state_vars(&self) -> Vec<(&'static str, &Var)> {
+        vec![
+            ("first_moment", &self.first_moment),
+            ("second_moment", &self.second_moment),
+        ]
+    }
+}
+
 #[derive(Debug)]
 pub struct AdamW {
-    vars: Vec<VarAdamW>,
+    vars: Vec<Param<VarAdamW>>,
     step_t: usize,
     params: ParamsAdamW,
 }
@@ -118,22 +491,11 @@ impl Optimizer for AdamW {
     type Config = ParamsAdamW;
 
     fn new(vars: Vec<Var>, params: ParamsAdamW) -> Result<Self> {
-        let vars = vars
-            .into_iter()
-            .filter(|var| var.dtype().is_float())
-            .map(|var| {
-                let dtype = var.dtype();
-                let shape = var.shape();
-                let device = var.device();
-                let first_moment = Var::zeros(shape, dtype, device)?;
-                let second_moment = Var::zeros(shape, dtype, device)?;
-                Ok(VarAdamW {
-                    var,
-                    first_moment,
-                    second_moment,
-                })
-            })
-            .collect::<Result<Vec<_>>>()?;
+        Self::from_param_groups(unnamed_group(vars), params)
+    }
+
+    fn from_param_groups(groups: Vec<ParamGroup>, params: ParamsAdamW) -> Result<Self> {
+        let vars = make_params(groups, VarAdamW::new)?;
         Ok(Self {
             vars,
             params,
@@ -149,19 +511,22 @@ impl Optimizer for AdamW {
         self.params.lr = lr
     }
 
+    fn vars(&self) -> Vec<Var> {
+        self.vars.iter().map(|p| p.var.clone()).collect()
+    }
+
     fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
         self.step_t += 1;
-        let lr = self.params.lr;
-        let lambda = self.params.weight_decay;
-        let lr_lambda = lr * lambda;
         let beta1 = self.params.beta1;
         let beta2 = self.params.beta2;
         let scale_m = 1f64 / (1f64 - beta1.powi(self.step_t as i32));
         let scale_v = 1f64 / (1f64 - beta2.powi(self.step_t as i32));
-        for var in self.vars.iter() {
-            let theta = &var.var;
-            let m = &var.first_moment;
-            let v = &var.second_moment;
+        for param in self.vars.iter() {
+            let lr = param.lr(self.params.lr);
+            let lr_lambda = lr * param.weight_decay(self.params.weight_decay);
+            let theta = &param.var;
+            let m = &param.state.first_moment;
+            let v = &param.state.second_moment;
             if let Some(g) = grads.get(theta) {
                 // This involves locking 3 RWLocks per params, if the parameters are large this
                 // should not be an issue but this may be problematic with models with lots of
@@ -180,6 +545,16 @@ impl Optimizer for AdamW {
         }
         Ok(())
     }
+
+    fn state_dict(&self) -> Result<HashMap<String, Tensor>> {
+        params_state_dict(&self.vars, Some(self.step_t), VarAdamW::state_vars)
+    }
+
+    fn load_state_dict(&mut self, state_dict: &HashMap<String, Tensor>) -> Result<()> {
+        params_load_state_dict(&self.vars, state_dict, VarAdamW::state_vars)?;
+        self.step_t = load_state_step(state_dict)?;
+        Ok(())
+    }
 }
 
 impl AdamW {
@@ -199,3 +574,702 @@ impl AdamW {
         self.params = params;
     }
 }
+
+/// Adam with an L2 penalty added to the gradients, contrary to [`AdamW`] which decouples the
+/// weight decay from the gradient based update.
+#[derive(Clone, Debug)]
+pub struct ParamsAdam {
+    pub lr: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub eps: f64,
+    pub weight_decay: f64,
+}
+
+impl Default for ParamsAdam {
+    fn default() -> Self {
+        Self {
+            lr: 0.001,
+            beta1: 0.9,
+            beta2: 0.999,
+            eps: 1e-8,
+            weight_decay: 0.,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Adam {
+    vars: Vec<Param<VarAdamW>>,
+    step_t: usize,
+    params: ParamsAdam,
+}
+
+impl Optimizer for Adam {
+    type Config = ParamsAdam;
+
+    fn new(vars: Vec<Var>, params: ParamsAdam) -> Result<Self> {
+        Self::from_param_groups(unnamed_group(vars), params)
+    }
+
+    fn from_param_groups(groups: Vec<ParamGroup>, params: ParamsAdam) -> Result<Self> {
+        let vars = make_params(groups, VarAdamW::new)?;
+        Ok(Self {
+            vars,
+            params,
+            step_t: 0,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.vars.iter().map(|p| p.var.clone()).collect()
+    }
+
+    fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
+        self.step_t += 1;
+        let ParamsAdam {
+            lr,
+            beta1,
+            beta2,
+            eps,
+            weight_decay,
+        } = self.params;
+        let scale_m = 1f64 / (1f64 - beta1.powi(self.step_t as i32));
+        let scale_v = 1f64 / (1f64 - beta2.powi(self.step_t as i32));
+        for param in self.vars.iter() {
+            let theta = &param.var;
+            let m = &param.state.first_moment;
+            let v = &param.state.second_moment;
+            if let Some(g) = grads.get(theta) {
+                let weight_decay = param.weight_decay(weight_decay);
+                let g = if weight_decay != 0. {
+                    (g + (theta.as_tensor() * weight_decay)?)?
+                } else {
+                    g.clone()
+                };
+                let next_m = ((m.as_tensor() * beta1)? + (&g * (1.0 - beta1))?)?;
+                let next_v = ((v.as_tensor() * beta2)? + (g.sqr()? * (1.0 - beta2))?)?;
+                let m_hat = (&next_m * scale_m)?;
+                let v_hat = (&next_v * scale_v)?;
+                let adjusted_grad = (m_hat / (v_hat.sqrt()? + eps)?)?;
+                let next_theta = (theta.as_tensor() - (adjusted_grad * param.lr(lr))?)?;
+                m.set(&next_m)?;
+                v.set(&next_v)?;
+                theta.set(&next_theta)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn state_dict(&self) -> Result<HashMap<String, Tensor>> {
+        params_state_dict(&self.vars, Some(self.step_t), VarAdamW::state_vars)
+    }
+
+    fn load_state_dict(&mut self, state_dict: &HashMap<String, Tensor>) -> Result<()> {
+        params_load_state_dict(&self.vars, state_dict, VarAdamW::state_vars)?;
+        self.step_t = load_state_step(state_dict)?;
+        Ok(())
+    }
+}
+
+impl Adam {
+    pub fn new_lr(vars: Vec<Var>, learning_rate: f64) -> Result<Self> {
+        let params = ParamsAdam {
+            lr: learning_rate,
+            ..ParamsAdam::default()
+        };
+        Self::new(vars, params)
+    }
+
+    pub fn params(&self) -> &ParamsAdam {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: ParamsAdam) {
+        self.params = params;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParamsRMSprop {
+    pub lr: f64,
+    /// Smoothing constant for the running average of the squared gradients.
+    pub alpha: f64,
+    pub eps: f64,
+    pub weight_decay: f64,
+    pub momentum: f64,
+    /// When set, the gradients are normalized by an estimate of their variance rather than by
+    /// their uncentered second moment.
+    pub centered: bool,
+}
+
+impl Default for ParamsRMSprop {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            alpha: 0.99,
+            eps: 1e-8,
+            weight_decay: 0.,
+            momentum: 0.,
+            centered: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct VarRMSprop {
+    square_avg: Var,
+    grad_avg: Option<Var>,
+    momentum_buffer: Option<Var>,
+}
+
+impl VarRMSprop {
+    fn state_vars(&self) -> Vec<(&'static str, &Var)> {
+        let mut vars = vec![("square_avg", &self.square_avg)];
+        if let Some(grad_avg) = &self.grad_avg {
+            vars.push(("grad_avg", grad_avg))
+        }
+        if let Some(momentum_buffer) = &self.momentum_buffer {
+            vars.push(("momentum_buffer", momentum_buffer))
+        }
+        vars
+    }
+}
+
+#[derive(Debug)]
+pub struct RMSprop {
+    vars: Vec<Param<VarRMSprop>>,
+    params: ParamsRMSprop,
+}
+
+impl Optimizer for RMSprop {
+    type Config = ParamsRMSprop;
+
+    fn new(vars: Vec<Var>, params: ParamsRMSprop) -> Result<Self> {
+        Self::from_param_groups(unnamed_group(vars), params)
+    }
+
+    fn from_param_groups(groups: Vec<ParamGroup>, params: ParamsRMSprop) -> Result<Self> {
+        let vars = make_params(groups, |var| {
+            let zeros = || Var::zeros(var.shape(), var.dtype(), var.device());
+            let square_avg = zeros()?;
+            let grad_avg = if params.centered {
+                Some(zeros()?)
+            } else {
+                None
+            };
+            let momentum_buffer = if params.momentum > 0. {
+                Some(zeros()?)
+            } else {
+                None
+            };
+            Ok(VarRMSprop {
+                square_avg,
+                grad_avg,
+                momentum_buffer,
+            })
+        })?;
+        Ok(Self { vars, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.vars.iter().map(|p| p.var.clone()).collect()
+    }
+
+    fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
+        let ParamsRMSprop {
+            lr,
+            alpha,
+            eps,
+            weight_decay,
+            momentum,
+            centered: _,
+        } = self.params;
+        for param in self.vars.iter() {
+            let theta = &param.var;
+            let state = &param.state;
+            if let Some(g) = grads.get(theta) {
+                let weight_decay = param.weight_decay(weight_decay);
+                let g = if weight_decay != 0. {
+                    (g + (theta.as_tensor() * weight_decay)?)?
+                } else {
+                    g.clone()
+                };
+                let square_avg =
+                    ((state.square_avg.as_tensor() * alpha)? + (g.sqr()? * (1. - alpha))?)?;
+                state.square_avg.set(&square_avg)?;
+                let avg = match &state.grad_avg {
+                    Some(grad_avg) => {
+                        let next_grad_avg =
+                            ((grad_avg.as_tensor() * alpha)? + (&g * (1. - alpha))?)?;
+                        grad_avg.set(&next_grad_avg)?;
+                        (square_avg - next_grad_avg.sqr()?)?.sqrt()?
+                    }
+                    None => square_avg.sqrt()?,
+                };
+                let update = (g / (avg + eps)?)?;
+                let update = match &state.momentum_buffer {
+                    Some(buf) => {
+                        let next_buf = ((buf.as_tensor() * momentum)? + update)?;
+                        buf.set(&next_buf)?;
+                        next_buf
+                    }
+                    None => update,
+                };
+                theta.set(&(theta.as_tensor() - (update * param.lr(lr))?)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn state_dict(&self) -> Result<HashMap<String, Tensor>> {
+        params_state_dict(&self.vars, None, VarRMSprop::state_vars)
+    }
+
+    fn load_state_dict(&mut self, state_dict: &HashMap<String, Tensor>) -> Result<()> {
+        params_load_state_dict(&self.vars, state_dict, VarRMSprop::state_vars)
+    }
+}
+
+impl RMSprop {
+    pub fn params(&self) -> &ParamsRMSprop {
+        &self.params
+    }
+
+    /// Updates the hyper-parameters. Enabling `centered` or `momentum` on an optimizer created
+    /// without them has no effect as the corresponding state is only created in `new`.
+    pub fn set_params(&mut self, params: ParamsRMSprop) {
+        self.params = params;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParamsAdagrad {
+    pub lr: f64,
+    pub lr_decay: f64,
+    pub weight_decay: f64,
+    pub initial_accumulator_value: f64,
+    pub eps: f64,
+}
+
+impl Default for ParamsAdagrad {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            lr_decay: 0.,
+            weight_decay: 0.,
+            initial_accumulator_value: 0.,
+            eps: 1e-10,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Adagrad {
+    // The state is the sum of the squared gradients.
+    vars: Vec<Param<Var>>,
+    step_t: usize,
+    params: ParamsAdagrad,
+}
+
+impl Optimizer for Adagrad {
+    type Config = ParamsAdagrad;
+
+    fn new(vars: Vec<Var>, params: ParamsAdagrad) -> Result<Self> {
+        Self::from_param_groups(unnamed_group(vars), params)
+    }
+
+    fn from_param_groups(groups: Vec<ParamGroup>, params: ParamsAdagrad) -> Result<Self> {
+        let vars = make_params(groups, |var| {
+            let sum = Tensor::full(params.initial_accumulator_value, var.shape(), var.device())?
+                .to_dtype(var.dtype())?;
+            Var::from_tensor(&sum)
+        })?;
+        Ok(Self {
+            vars,
+            params,
+            step_t: 0,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.vars.iter().map(|p| p.var.clone()).collect()
+    }
+
+    fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
+        self.step_t += 1;
+        let ParamsAdagrad {
+            lr,
+            lr_decay,
+            weight_decay,
+            initial_accumulator_value: _,
+            eps,
+        } = self.params;
+        for param in self.vars.iter() {
+            let theta = &param.var;
+            if let Some(g) = grads.get(theta) {
+                let weight_decay = param.weight_decay(weight_decay);
+                let g = if weight_decay != 0. {
+                    (g + (theta.as_tensor() * weight_decay)?)?
+                } else {
+                    g.clone()
+                };
+                let clr = param.lr(lr) / (1. + (self.step_t - 1) as f64 * lr_decay);
+                let sum = (param.state.as_tensor() + g.sqr()?)?;
+                let update = (g / (sum.sqrt()? + eps)?)?;
+                param.state.set(&sum)?;
+                theta.set(&(theta.as_tensor() - (update * clr)?)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn state_dict(&self) -> Result<HashMap<String, Tensor>> {
+        params_state_dict(&self.vars, Some(self.step_t), |sum| vec![("sum", sum)])
+    }
+
+    fn load_state_dict(&mut self, state_dict: &HashMap<String, Tensor>) -> Result<()> {
+        params_load_state_dict(&self.vars, state_dict, |sum| vec![("sum", sum)])?;
+        self.step_t = load_state_step(state_dict)?;
+        Ok(())
+    }
+}
+
+impl Adagrad {
+    pub fn params(&self) -> &ParamsAdagrad {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: ParamsAdagrad) {
+        self.params = params;
+    }
+}
+
+/// The Lion optimizer, "Symbolic Discovery of Optimization Algorithms"
+/// <https://arxiv.org/abs/2302.06675>. The update only uses the sign of an interpolation between
+/// the momentum and the gradient so the learning rate is typically 3-10x smaller than for AdamW.
+#[derive(Clone, Debug)]
+pub struct ParamsLion {
+    pub lr: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub weight_decay: f64,
+}
+
+impl Default for ParamsLion {
+    fn default() -> Self {
+        Self {
+            lr: 1e-4,
+            beta1: 0.9,
+            beta2: 0.99,
+            weight_decay: 0.,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Lion {
+    // The state is the momentum.
+    vars: Vec<Param<Var>>,
+    params: ParamsLion,
+}
+
+impl Optimizer for Lion {
+    type Config = ParamsLion;
+
+    fn new(vars: Vec<Var>, params: ParamsLion) -> Result<Self> {
+        Self::from_param_groups(unnamed_group(vars), params)
+    }
+
+    fn from_param_groups(groups: Vec<ParamGroup>, params: ParamsLion) -> Result<Self> {
+        let vars = make_params(groups, |var| {
+            Var::zeros(var.shape(), var.dtype(), var.device())
+        })?;
+        Ok(Self { vars, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.vars.iter().map(|p| p.var.clone()).collect()
+    }
+
+    fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
+        let ParamsLion {
+            lr,
+            beta1,
+            beta2,
+            weight_decay,
+        } = self.params;
+        for param in self.vars.iter() {
+            let theta = &param.var;
+            let m = &param.state;
+            if let Some(g) = grads.get(theta) {
+                let lr = param.lr(lr);
+                let weight_decay = param.weight_decay(weight_decay);
+                let interp = ((m.as_tensor() * beta1)? + (g * (1. - beta1))?)?;
+                // sign(x), with sign(0) = 0.
+                let dtype = interp.dtype();
+                let sign = (interp.gt(0.)?.to_dtype(dtype)? - interp.lt(0.)?.to_dtype(dtype)?)?;
+                let next_theta = (theta.as_tensor() * (1. - lr * weight_decay))?;
+                let next_theta = (next_theta - (sign * lr)?)?;
+                let next_m = ((m.as_tensor() * beta2)? + (g * (1. - beta2))?)?;
+                m.set(&next_m)?;
+                theta.set(&next_theta)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn state_dict(&self) -> Result<HashMap<String, Tensor>> {
+        params_state_dict(&self.vars, None, |m| vec![("momentum", m)])
+    }
+
+    fn load_state_dict(&mut self, state_dict: &HashMap<String, Tensor>) -> Result<()> {
+        params_load_state_dict(&self.vars, state_dict, |m| vec![("momentum", m)])
+    }
+}
+
+impl Lion {
+    pub fn params(&self) -> &ParamsLion {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: ParamsLion) {
+        self.params = params;
+    }
+}
+
+/// Adafactor, "Adafactor: Adaptive Learning Rates with Sublinear Memory Cost"
+/// <https://arxiv.org/abs/1804.04235>, using the same variant as `torch.optim.Adafactor`.
+///
+/// The second moment of variables with at least two dimensions is factored into running
+/// averages over the rows and the columns of the last two dimensions, so the optimizer state is
+/// much smaller than for Adam. There is no first moment.
+#[derive(Clone, Debug)]
+pub struct ParamsAdafactor {
+    /// The maximum relative step size, the actual step size is `min(lr, 1/sqrt(t))` scaled by
+    /// the root mean square of the variable.
+    pub lr: f64,
+    /// Exponent of the decay rate of the second moment, `beta2_t = 1 - t^beta2_decay`.
+    pub beta2_decay: f64,
+    /// Regularization constant for the second moment, defaults to the dtype epsilon.
+    pub eps1: Option<f64>,
+    /// Lower bound on the root mean square of the variables when computing the step size.
+    pub eps2: f64,
+    /// Clipping threshold for the root mean square of the update.
+    pub d: f64,
+    pub weight_decay: f64,
+}
+
+impl Default for ParamsAdafactor {
+    fn default() -> Self {
+        Self {
+            lr: 1e-2,
+            beta2_decay: -0.8,
+            eps1: None,
+            eps2: 1e-3,
+            d: 1.0,
+            weight_decay: 0.,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum VarAdafactor {
+    Factored { row_var: Var, col_var: Var },
+    Full { variance: Var },
+}
+
+impl VarAdafactor {
+    fn new(var: &Var) -> Result<Self> {
+        let (dtype, device) = (var.dtype(), var.device());
+        let dims = var.dims();
+        let state = if dims.len() >= 2 {
+            let mut row_dims = dims.to_vec();
+            let mut col_dims = dims.to_vec();
+            row_dims[dims.len() - 1] = 1;
+            col_dims[dims.len() - 2] = 1;
+            Self::Factored {
+                row_var: Var::zeros(row_dims, dtype, device)?,
+                col_var: Var::zeros(col_dims, dtype, device)?,
+            }
+        } else {
+            Self::Full {
+                variance: Var::zeros(var.shape(), dtype, device)?,
+            }
+        };
+        Ok(state)
+    }
+
+    fn state_vars(&self) -> Vec<(&'static str, &Var)> {
+        match self {
+            Self::Factored { row_var, col_var } => vec![("row_var", row_var), ("col_var", col_var)],
+            Self::Full { variance } => vec![("variance", variance)],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Adafactor {
+    vars: Vec<Param<VarAdafactor>>,
+    step_t: usize,
+    params: ParamsAdafactor,
+}
+
+// The root mean square of a tensor, the reduction is done in f32 for the non-f64 tensors as
+// f64 is not supported on all devices, e.g. metal.
+fn rms(t: &Tensor) -> Result<f64> {
+    let mean_sqr = match t.dtype() {
+        candle::DType::F64 => t.sqr()?.mean_all()?.to_scalar::<f64>()?,
+        _ => {
+            let t = t.to_dtype(candle::DType::F32)?;
+            t.sqr()?.mean_all()?.to_scalar::<f32>()? as f64
+        }
+    };
+    Ok(mean_sqr.sqrt())
+}
+
+impl Optimizer for Adafactor {
+    type Config = ParamsAdafactor;
+
+    fn new(vars: Vec<Var>, params: ParamsAdafactor) -> Result<Self> {
+        Self::from_param_groups(unnamed_group(vars), params)
+    }
+
+    fn from_param_groups(groups: Vec<ParamGroup>, params: ParamsAdafactor) -> Result<Self> {
+        let vars = make_params(groups, VarAdafactor::new)?;
+        Ok(Self {
+            vars,
+            params,
+            step_t: 0,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.vars.iter().map(|p| p.var.clone()).collect()
+    }
+
+    fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
+        use candle::D;
+        self.step_t += 1;
+        let ParamsAdafactor {
+            lr,
+            beta2_decay,
+            eps1,
+            eps2,
+            d,
+            weight_decay,
+        } = self.params;
+        let step_t = self.step_t as f64;
+        let one_minus_beta2_t = step_t.powf(beta2_decay);
+        for param in self.vars.iter() {
+            let theta = &param.var;
+            if let Some(g) = grads.get(theta) {
+                let lr = param.lr(lr);
+                let weight_decay = param.weight_decay(weight_decay);
+                let rho_t = f64::min(lr, 1. / step_t.sqrt());
+                let eps1 = match eps1 {
+                    Some(eps1) => eps1,
+                    None => match theta.dtype() {
+                        candle::DType::F16 => half::f16::EPSILON.to_f64(),
+                        candle::DType::BF16 => half::bf16::EPSILON.to_f64(),
+                        candle::DType::F64 => f64::EPSILON,
+                        _ => f32::EPSILON as f64,
+                    },
+                };
+                let alpha = f64::max(eps2, rms(theta.as_tensor())?) * rho_t;
+                let lerp = |state: &Var, target: &Tensor| -> Result<Tensor> {
+                    let state = state.as_tensor();
+                    let next = (state + ((target - state)? * one_minus_beta2_t)?)?;
+                    Ok(next)
+                };
+                let var_estimate = match &param.state {
+                    VarAdafactor::Factored { row_var, col_var } => {
+                        let g2 = g.sqr()?;
+                        let next_row_var = lerp(row_var, &g2.mean_keepdim(D::Minus1)?)?;
+                        let next_col_var = lerp(col_var, &g2.mean_keepdim(D::Minus2)?)?;
+                        row_var.set(&next_row_var)?;
+                        col_var.set(&next_col_var)?;
+                        let row_var_mean = next_row_var.mean_keepdim(D::Minus2)?.maximum(eps1)?;
+                        next_row_var
+                            .broadcast_mul(&next_col_var)?
+                            .broadcast_div(&row_var_mean)?
+                    }
+                    VarAdafactor::Full { variance } => {
+                        let next_variance = lerp(variance, &g.sqr()?)?;
+                        variance.set(&next_variance)?;
+                        next_variance
+                    }
+                };
+                let update = (var_estimate.maximum(eps1 * eps1)?.sqrt()?.recip()? * g)?;
+                let denom = f64::max(1.0, rms(&update)? / d);
+                let mut next_theta = theta.as_tensor().clone();
+                if weight_decay != 0. {
+                    next_theta = (next_theta * (1. - lr * weight_decay))?;
+                }
+                let next_theta = (next_theta - (update * (alpha / denom))?)?;
+                theta.set(&next_theta)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn state_dict(&self) -> Result<HashMap<String, Tensor>> {
+        params_state_dict(&self.vars, Some(self.step_t), VarAdafactor::state_vars)
+    }
+
+    fn load_state_dict(&mut self, state_dict: &HashMap<String, Tensor>) -> Result<()> {
+        params_load_state_dict(&self.vars, state_dict, VarAdafactor::state_vars)?;
+        self.step_t = load_state_step(state_dict)?;
+        Ok(())
+    }
+}
+
+impl Adafactor {
+    pub fn params(&self) -> &ParamsAdafactor {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: ParamsAdafactor) {
+        self.params = params;
+    }
+}
@@ -1,4 +1,15 @@
 //! Recurrent Neural Networks
+//!
+//! [`LSTM`] and [`GRU`] are single recurrent layers using the PyTorch weight names. They can be
+//! stepped one timestep at a time through the [`RNN`] trait, or run over full sequences with
+//! [`RNNLayer::seq_with_lengths`] which computes the input projection of all the timesteps in a
+//! single matmul. [`StackedRNN`] combines several layers into a multi-layer, optionally
+//! bidirectional, network similar to PyTorch's `nn.LSTM` and `nn.GRU` with `batch_first=True`.
+//!
+//! Variable-length batches are handled with the padded batch and the length of each sequence, as
+//! with PyTorch's `pack_padded_sequence`: the state of a sequence is not updated past its
+//! length, the outputs there are zero, and the reverse direction starts at the last valid
+//! timestep of each sequence.
 use candle::{DType, Device, IndexOp, Result, Tensor};
 
 /// Trait for Recurrent Neural Networks.
@@ -46,6 +57,152 @@ pub trait RNN {
     fn states_to_tensor(&self, states: &[Self::State]) -> Result<Tensor>;
 }
 
+/// The direction in which a recurrent layer processes its input sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    #[default]
+    Forward,
+    /// The reverse direction of a bidirectional network, the weight names get a `_reverse`
+    /// suffix.
+    Backward,
+}
+
+impl Direction {
+    fn weight_name(&self, name: &str, layer_idx: usize) -> String {
+        match self {
+            Self::Forward => format!("{name}_l{layer_idx}"),
+            Self::Backward => format!("{name}_l{layer_idx}_reverse"),
+        }
+    }
+}
+
+/// A recurrent layer that can process full sequences of variable lengths, this is what
+/// [`StackedRNN`] is built from.
+pub trait RNNLayer: RNN {
+    fn hidden_dim(&self) -> usize;
+
+    fn direction(&self) -> Direction;
+
+    /// Runs the layer over `input` of shape `(batch, seq_len, features)` in the direction of the
+    /// layer, starting from `init_state`.
+    ///
+    /// `lengths` holds the number of valid timesteps of each sequence of the batch, all the
+    /// timesteps are valid when it is `None`. Returns the outputs of shape
+    /// `(batch, seq_len, hidden_dim)`, which are zero past the length of each sequence, and the
+    /// final state of each sequence.
+    fn seq_with_lengths(
+        &self,
+        input: &Tensor,
+        init_state: &Self::State,
+        lengths: Option<&[usize]>,
+    ) -> Result<(Tensor, Self::State)>;
+}
+
+/// Returns a `u8` mask of shape `(batch, seq_len)` which is one on the first `lengths[i]`
+/// timesteps of the sequence `i` and zero elsewhere.
+pub fn sequence_mask(lengths: &[usize], seq_len: usize, device: &Device) -> Result<Tensor> {
+    let mut mask = Vec::with_capacity(lengths.len() * seq_len);
+    for &length in lengths {
+        if length > seq_len {
+            candle::bail!("sequence length {length} is larger than the input length {seq_len}")
+        }
+        mask.extend((0..seq_len).map(|t| u8::from(t < length)))
+    }
+    Tensor::from_vec(mask, (lengths.len(), seq_len), device)
+}
+
+// Picks the rows of `new` where `mask` of shape (batch, 1) is one and the rows of `old`
+// elsewhere.
+fn select_rows(mask: &Tensor, new: &Tensor, old: &Tensor) -> Result<Tensor> {
+    mask.broadcast_as(new.shape())?.where_cond(new, old)
+}
+
+// Computes `input @ w_ih^T + b_ih` for the input of shape (batch, seq_len, features) using a
+// single matmul over all the timesteps.
+fn input_projection(input: &Tensor, w_ih: &Tensor, b_ih: Option<&Tensor>) -> Result<Tensor> {
+    let (b_size, seq_len, features) = input.dims3()?;
+    let ih = input
+        .reshape((b_size * seq_len, features))?
+        .matmul(&w_ih.t()?)?;
+    let ih = match b_ih {
+        None => ih,
+        Some(b_ih) => ih.broadcast_add(b_ih)?,
+    };
+    ih.reshape((b_size, seq_len, ()))
+}
+
+// Steps through the projected inputs `ih` of shape (batch, seq_len, gates * hidden_dim) in the
+// given direction and returns the states in timestep order. When `mask` is set, the state of a
+// sequence is carried over unchanged on its padded timesteps.
+fn run_seq<S: Clone>(
+    ih: &Tensor,
+    init_state: &S,
+    direction: Direction,
+    mask: Option<&Tensor>,
+    step: impl Fn(&Tensor, &S) -> Result<S>,
+    select: impl Fn(&Tensor, &S, &S) -> Result<S>,
+) -> Result<Vec<S>> {
+    let seq_len = ih.dim(1)?;
+    let timesteps: Vec<usize> = match direction {
+        Direction::Forward => (0..seq_len).collect(),
+        Direction::Backward => (0..seq_len).rev().collect(),
+    };
+    let mut states = vec![None; seq_len];
+    let mut state = init_state.clone();
+    for t in timesteps {
+        let next = step(&ih.i((.., t, ..))?, &state)?;
+        state = match mask {
+            None => next,
+            Some(mask) => select(&mask.i((.., t..t + 1))?, &next, &state)?,
+        };
+        states[t] = Some(state.clone());
+    }
+    Ok(states.into_iter().flatten().collect())
+}
+
+// Shared implementation of `RNNLayer::seq_with_lengths`, `hidden` extracts the output of the
+// layer from a state.
+fn seq_with_lengths<S: Clone>(
+    ih: &Tensor,
+    init_state: &S,
+    direction: Direction,
+    lengths: Option<&[usize]>,
+    step: impl Fn(&Tensor, &S) -> Result<S>,
+    select: impl Fn(&Tensor, &S, &S) -> Result<S>,
+    hidden: impl Fn(&S) -> &Tensor,
+) -> Result<(Tensor, S)> {
+    let (b_size, seq_len, _) = ih.dims3()?;
+    if seq_len == 0 {
+        candle::bail!("empty input sequence")
+    }
+    let mask = match lengths {
+        None => None,
+        Some(lengths) => {
+            if lengths.len() != b_size {
+                candle::bail!("got {} lengths for a batch of {b_size}", lengths.len())
+            }
+            Some(sequence_mask(lengths, seq_len, ih.device())?)
+        }
+    };
+    let states = run_seq(ih, init_state, direction, mask.as_ref(), step, select)?;
+    let outputs = states
+        .iter()
+        .enumerate()
+        .map(|(t, state)| {
+            let h = hidden(state);
+            match &mask {
+                None => Ok(h.clone()),
+                Some(mask) => select_rows(&mask.i((.., t..t + 1))?, h, &h.zeros_like()?),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let final_state = match direction {
+        Direction::Forward => states[seq_len - 1].clone(),
+        Direction::Backward => states[0].clone(),
+    };
+    Ok((Tensor::stack(&outputs, 1)?, final_state))
+}
+
 /// The state for a LSTM network, this contains two tensors.
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone)]
@@ -74,6 +231,7 @@ pub struct LSTMConfig {
     pub b_ih_init: Option<super::Init>,
     pub b_hh_init: Option<super::Init>,
     pub layer_idx: usize,
+    pub direction: Direction,
 }
 
 impl Default for LSTMConfig {
@@ -84,6 +242,7 @@ impl Default for LSTMConfig {
             b_ih_init: Some(super::Init::Const(0.)),
             b_hh_init: Some(super::Init::Const(0.)),
             layer_idx: 0,
+            direction: Direction::Forward,
         }
     }
 }
@@ -96,6 +255,7 @@ impl LSTMConfig {
             b_ih_init: None,
             b_hh_init: None,
             layer_idx: 0,
+            direction: Direction::Forward,
         }
     }
 }
@@ -123,27 +283,23 @@ pub fn lstm(
     config: LSTMConfig,
     vb: crate::VarBuilder,
 ) -> Result<LSTM> {
-    let layer_idx = config.layer_idx;
+    let name = |name| config.direction.weight_name(name, config.layer_idx);
     let w_ih = vb.get_with_hints(
         (4 * hidden_dim, in_dim),
-        &format!("weight_ih_l{layer_idx}"), // Only a single layer is supported.
+        &name("weight_ih"),
         config.w_ih_init,
     )?;
     let w_hh = vb.get_with_hints(
         (4 * hidden_dim, hidden_dim),
-        &format!("weight_hh_l{layer_idx}"), // Only a single layer is supported.
+        &name("weight_hh"),
         config.w_hh_init,
     )?;
     let b_ih = match config.b_ih_init {
-        Some(init) => {
-            Some(vb.get_with_hints(4 * hidden_dim, &format!("bias_ih_l{layer_idx}"), init)?)
-        }
+        Some(init) => Some(vb.get_with_hints(4 * hidden_dim, &name("bias_ih"), init)?),
         None => None,
     };
     let b_hh = match config.b_hh_init {
-        Some(init) => {
-            Some(vb.get_with_hints(4 * hidden_dim, &format!("bias_hh_l{layer_idx}"), init)?)
-        }
+        Some(init) => Some(vb.get_with_hints(4 * hidden_dim, &name("bias_hh"), init)?),
         None => None,
     };
     Ok(LSTM {
@@ -172,16 +328,46 @@ impl RNN for LSTM {
 
     fn step(&self, input: &Tensor, in_state: &Self::State) -> Result<Self::State> {
         let w_ih = input.matmul(&self.w_ih.t()?)?;
-        let w_hh = in_state.h.matmul(&self.w_hh.t()?)?;
         let w_ih = match &self.b_ih {
             None => w_ih,
             Some(b_ih) => w_ih.broadcast_add(b_ih)?,
         };
+        self.step_projected(&w_ih, in_state)
+    }
+
+    /// Applies the steps in the direction of the layer, the input projection is computed for all
+    /// the timesteps at once. The states are returned in timestep order.
+    fn seq_init(&self, input: &Tensor, init_state: &Self::State) -> Result<Vec<Self::State>> {
+        let w_ih = input_projection(input, &self.w_ih, self.b_ih.as_ref())?;
+        run_seq(
+            &w_ih,
+            init_state,
+            self.config.direction,
+            None,
+            |w_ih, state| self.step_projected(w_ih, state),
+            Self::select_state,
+        )
+    }
+
+    fn states_to_tensor(&self, states: &[Self::State]) -> Result<Tensor> {
+        let states = states.iter().map(|s| s.h.clone()).collect::<Vec<_>>();
+        Tensor::stack(&states, 1)
+    }
+}
+
+impl LSTM {
+    pub fn config(&self) -> &LSTMConfig {
+        &self.config
+    }
+
+    // A step where the input projection `w_ih` has already been computed.
+    fn step_projected(&self, w_ih: &Tensor, in_state: &LSTMState) -> Result<LSTMState> {
+        let w_hh = in_state.h.matmul(&self.w_hh.t()?)?;
         let w_hh = match &self.b_hh {
             None => w_hh,
             Some(b_hh) => w_hh.broadcast_add(b_hh)?,
         };
-        let chunks = (&w_ih + &w_hh)?.chunk(4, 1)?;
+        let chunks = (w_ih + &w_hh)?.chunk(4, 1)?;
         let in_gate = crate::ops::sigmoid(&chunks[0])?;
         let forget_gate = crate::ops::sigmoid(&chunks[1])?;
         let cell_gate = chunks[2].tanh()?;
@@ -195,9 +381,39 @@ impl RNN for LSTM {
         })
     }
 
-    fn states_to_tensor(&self, states: &[Self::State]) -> Result<Tensor> {
-        let states = states.iter().map(|s| s.h.clone()).collect::<Vec<_>>();
-        Tensor::stack(&states, 1)
+    fn select_state(mask: &Tensor, new: &LSTMState, old: &LSTMState) -> Result<LSTMState> {
+        Ok(LSTMState {
+            h: select_rows(mask, &new.h, &old.h)?,
+            c: select_rows(mask, &new.c, &old.c)?,
+        })
+    }
+}
+
+impl RNNLayer for LSTM {
+    fn hidden_dim(&self) -> usize {
+        self.hidden_dim
+    }
+
+    fn direction(&self) -> Direction {
+        self.config.direction
+    }
+
+    fn seq_with_lengths(
+        &self,
+        input: &Tensor,
+        init_state: &Self::State,
+        lengths: Option<&[usize]>,
+    ) -> Result<(Tensor, Self::State)> {
+        let w_ih = input_projection(input, &self.w_ih, self.b_ih.as_ref())?;
+        seq_with_lengths(
+            &w_ih,
+            init_state,
+            self.config.direction,
+            lengths,
+            |w_ih, state| self.step_projected(w_ih, state),
+            Self::select_state,
+            |state| &state.h,
+        )
     }
 }
 
@@ -222,6 +438,8 @@ pub struct GRUConfig {
     pub w_hh_init: super::Init,
     pub b_ih_init: Option<super::Init>,
     pub b_hh_init: Option<super::Init>,
+    pub layer_idx: usize,
+    pub direction: Direction,
 }
 
 impl Default for GRUConfig {
@@ -231,6 +449,8 @@ impl Default for GRUConfig {
             w_hh_init: super::init::DEFAULT_KAIMING_UNIFORM,
             b_ih_init: Some(super::Init::Const(0.)),
             b_hh_init: Some(super::Init::Const(0.)),
+            layer_idx: 0,
+            direction: Direction::Forward,
         }
     }
 }
@@ -242,6 +462,8 @@ impl GRUConfig {
             w_hh_init: super::init::DEFAULT_KAIMING_UNIFORM,
             b_ih_init: None,
             b_hh_init: None,
+            layer_idx: 0,
+            direction: Direction::Forward,
         }
     }
 }
@@ -269,22 +491,23 @@ pub fn gru(
     config: GRUConfig,
     vb: crate::VarBuilder,
 ) -> Result<GRU> {
+    let name = |name| config.direction.weight_name(name, config.layer_idx);
     let w_ih = vb.get_with_hints(
         (3 * hidden_dim, in_dim),
-        "weight_ih_l0", // Only a single layer is supported.
+        &name("weight_ih"),
         config.w_ih_init,
     )?;
     let w_hh = vb.get_with_hints(
         (3 * hidden_dim, hidden_dim),
-        "weight_hh_l0", // Only a single layer is supported.
+        &name("weight_hh"),
         config.w_hh_init,
     )?;
     let b_ih = match config.b_ih_init {
-        Some(init) => Some(vb.get_with_hints(3 * hidden_dim, "bias_ih_l0", init)?),
+        Some(init) => Some(vb.get_with_hints(3 * hidden_dim, &name("bias_ih"), init)?),
         None => None,
     };
     let b_hh = match config.b_hh_init {
-        Some(init) => Some(vb.get_with_hints(3 * hidden_dim, "bias_hh_l0", init)?),
+        Some(init) => Some(vb.get_with_hints(3 * hidden_dim, &name("bias_hh"), init)?),
         None => None,
     };
     Ok(GRU {
@@ -310,11 +533,41 @@ impl RNN for GRU {
 
     fn step(&self, input: &Tensor, in_state: &Self::State) -> Result<Self::State> {
         let w_ih = input.matmul(&self.w_ih.t()?)?;
-        let w_hh = in_state.h.matmul(&self.w_hh.t()?)?;
         let w_ih = match &self.b_ih {
             None => w_ih,
             Some(b_ih) => w_ih.broadcast_add(b_ih)?,
         };
+        self.step_projected(&w_ih, in_state)
+    }
+
+    /// Applies the steps in the direction of the layer, the input projection is computed for all
+    /// the timesteps at once. The states are returned in timestep order.
+    fn seq_init(&self, input: &Tensor, init_state: &Self::State) -> Result<Vec<Self::State>> {
+        let w_ih = input_projection(input, &self.w_ih, self.b_ih.as_ref())?;
+        run_seq(
+            &w_ih,
+            init_state,
+            self.config.direction,
+            None,
+            |w_ih, state| self.step_projected(w_ih, state),
+            Self::select_state,
+        )
+    }
+
+    fn states_to_tensor(&self, states: &[Self::State]) -> Result<Tensor> {
+        let states = states.iter().map(|s| s.h.clone()).collect::<Vec<_>>();
+        Tensor::cat(&states, 1)
+    }
+}
+
+impl GRU {
+    pub fn config(&self) -> &GRUConfig {
+        &self.config
+    }
+
+    // A step where the input projection `w_ih` has already been computed.
+    fn step_projected(&self, w_ih: &Tensor, in_state: &GRUState) -> Result<GRUState> {
+        let w_hh = in_state.h.matmul(&self.w_hh.t()?)?;
         let w_hh = match &self.b_hh {
             None => w_hh,
             Some(b_hh) => w_hh.broadcast_add(b_hh)?,
@@ -329,8 +582,252 @@ impl RNN for GRU {
         Ok(GRUState { h: next_h })
     }
 
-    fn states_to_tensor(&self, states: &[Self::State]) -> Result<Tensor> {
-        let states = states.iter().map(|s| s.h.clone()).collect::<Vec<_>>();
-        Tensor::cat(&states, 1)
+    fn select_state(mask: &Tensor, new: &GRUState, old: &GRUState) -> Result<GRUState> {
+        Ok(GRUState {
+            h: select_rows(mask, &new.h, &old.h)?,
+        })
+    }
+}
+
+impl RNNLayer for GRU {
+    fn hidden_dim(&self) -> usize {
+        self.hidden_dim
+    }
+
+    fn direction(&self) -> Direction {
+        self.config.direction
+    }
+
+    fn seq_with_lengths(
+        &self,
+        input: &Tensor,
+        init_state: &Self::State,
+        lengths: Option<&[usize]>,
+    ) -> Result<(Tensor, Self::State)> {
+        let w_ih = input_projection(input, &self.w_ih, self.b_ih.as_ref())?;
+        seq_with_lengths(
+            &w_ih,
+            init_state,
+            self.config.direction,
+            lengths,
+            |w_ih, state| self.step_projected(w_ih, state),
+            Self::select_state,
+            |state| &state.h,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StackedRNNConfig {
+    pub num_layers: usize,
+    pub bidirectional: bool,
+    /// The dropout probability applied to the outputs of each layer except the last one when
+    /// training.
+    pub dropout: f32,
+}
+
+impl Default for StackedRNNConfig {
+    fn default() -> Self {
+        Self {
+            num_layers: 1,
+            bidirectional: false,
+            dropout: 0.,
+        }
+    }
+}
+
+impl StackedRNNConfig {
+    pub fn num_directions(&self) -> usize {
+        if self.bidirectional {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+/// A multi-layer, optionally bidirectional, recurrent network.
+///
+/// Each layer gets as input the outputs of the previous layer, the outputs of the forward and
+/// backward directions of a bidirectional layer are concatenated on the feature dimension.
+#[derive(Clone, Debug)]
+pub struct StackedRNN<R> {
+    layers: Vec<Vec<R>>,
+    dropout: f32,
+}
+
+impl<R: RNNLayer> StackedRNN<R> {
+    /// Creates a network from the layers, each element of `layers` holds the forward layer and
+    /// optionally the backward layer.
+    pub fn new(layers: Vec<Vec<R>>, dropout: f32) -> Result<Self> {
+        let num_directions = layers.first().map_or(1, |l| l.len());
+        for layer in layers.iter() {
+            let directions = layer.iter().map(|l| l.direction()).collect::<Vec<_>>();
+            let valid = match num_directions {
+                1 => directions == [Direction::Forward],
+                2 => directions == [Direction::Forward, Direction::Backward],
+                _ => false,
+            };
+            if !valid {
+                candle::bail!("unexpected layer directions {directions:?}")
+            }
+        }
+        Ok(Self { layers, dropout })
+    }
+
+    pub fn layers(&self) -> &[Vec<R>] {
+        &self.layers
+    }
+
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn num_directions(&self) -> usize {
+        self.layers.first().map_or(1, |l| l.len())
+    }
+
+    /// Zero initial states for all the layers and directions.
+    pub fn zero_states(&self, batch_dim: usize) -> Result<Vec<R::State>> {
+        self.layers
+            .iter()
+            .flatten()
+            .map(|l| l.zero_state(batch_dim))
+            .collect()
     }
+
+    /// Runs the network over `input` of shape `(batch, seq_len, features)` from zero states, see
+    /// [`StackedRNN::forward_init_t`].
+    pub fn forward_t(
+        &self,
+        input: &Tensor,
+        lengths: Option<&[usize]>,
+        train: bool,
+    ) -> Result<(Tensor, Vec<R::State>)> {
+        let init_states = self.zero_states(input.dim(0)?)?;
+        self.forward_init_t(input, &init_states, lengths, train)
+    }
+
+    pub fn forward(
+        &self,
+        input: &Tensor,
+        lengths: Option<&[usize]>,
+    ) -> Result<(Tensor, Vec<R::State>)> {
+        self.forward_t(input, lengths, false)
+    }
+
+    /// Runs the network over `input` of shape `(batch, seq_len, features)`.
+    ///
+    /// `init_states` and the returned final states are ordered like PyTorch's `h_0` and `h_n`,
+    /// i.e. the state of direction `d` of layer `l` is at index `l * num_directions + d`.
+    /// `lengths` holds the valid length of each sequence of the batch, see
+    /// [`RNNLayer::seq_with_lengths`]. The output of the last layer is returned with shape
+    /// `(batch, seq_len, num_directions * hidden_dim)`.
+    pub fn forward_init_t(
+        &self,
+        input: &Tensor,
+        init_states: &[R::State],
+        lengths: Option<&[usize]>,
+        train: bool,
+    ) -> Result<(Tensor, Vec<R::State>)> {
+        let num_directions = self.num_directions();
+        if init_states.len() != self.num_layers() * num_directions {
+            candle::bail!(
+                "expected {} initial states, got {}",
+                self.num_layers() * num_directions,
+                init_states.len()
+            )
+        }
+        let mut xs = input.clone();
+        let mut final_states = Vec::with_capacity(init_states.len());
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+            if layer_idx > 0 && train && self.dropout > 0. {
+                xs = crate::ops::dropout(&xs, self.dropout)?
+            }
+            let mut outputs = Vec::with_capacity(num_directions);
+            for (dir_idx, rnn) in layer.iter().enumerate() {
+                let init_state = &init_states[layer_idx * num_directions + dir_idx];
+                let (output, state) = rnn.seq_with_lengths(&xs, init_state, lengths)?;
+                outputs.push(output);
+                final_states.push(state)
+            }
+            xs = Tensor::cat(&outputs, 2)?
+        }
+        Ok((xs, final_states))
+    }
+}
+
+fn stacked<R: RNNLayer>(
+    in_dim: usize,
+    hidden_dim: usize,
+    config: StackedRNNConfig,
+    layer: impl Fn(usize, usize, Direction) -> Result<R>,
+) -> Result<StackedRNN<R>> {
+    let directions: &[Direction] = if config.bidirectional {
+        &[Direction::Forward, Direction::Backward]
+    } else {
+        &[Direction::Forward]
+    };
+    let layers = (0..config.num_layers)
+        .map(|layer_idx| {
+            let in_dim = if layer_idx == 0 {
+                in_dim
+            } else {
+                hidden_dim * directions.len()
+            };
+            directions
+                .iter()
+                .map(|&direction| layer(in_dim, layer_idx, direction))
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    StackedRNN::new(layers, config.dropout)
+}
+
+/// Creates a multi-layer LSTM with the same weight names as PyTorch's `nn.LSTM`. The
+/// `layer_idx` and `direction` of `config` are ignored.
+pub fn lstm_stacked(
+    in_dim: usize,
+    hidden_dim: usize,
+    config: LSTMConfig,
+    stacked_config: StackedRNNConfig,
+    vb: crate::VarBuilder,
+) -> Result<StackedRNN<LSTM>> {
+    stacked(
+        in_dim,
+        hidden_dim,
+        stacked_config,
+        |in_dim, layer_idx, direction| {
+            let config = LSTMConfig {
+                layer_idx,
+                direction,
+                ..config
+            };
+            lstm(in_dim, hidden_dim, config, vb.clone())
+        },
+    )
+}
+
+/// Creates a multi-layer GRU with the same weight names as PyTorch's `nn.GRU`. The `layer_idx`
+/// and `direction` of `config` are ignored.
+pub fn gru_stacked(
+    in_dim: usize,
+    hidden_dim: usize,
+    config: GRUConfig,
+    stacked_config: StackedRNNConfig,
+    vb: crate::VarBuilder,
+) -> Result<StackedRNN<GRU>> {
+    stacked(
+        in_dim,
+        hidden_dim,
+        stacked_config,
+        |in_dim, layer_idx, direction| {
+            let config = GRUConfig {
+                layer_idx,
+                direction,
+                ..config
+            };
+            gru(in_dim, hidden_dim, config, vb.clone())
+        },
+    )
 }
@@ -0,0 +1,273 @@
+//! Transformer encoder and decoder layers.
+//!
+//! These layers follow PyTorch's `nn.TransformerEncoderLayer` and `nn.TransformerDecoderLayer`
+//! with `batch_first=True` and use the same weight names, so checkpoints trained with PyTorch can
+//! be loaded through a `VarBuilder`. Dropout is applied to the outputs of the attention and
+//! feed-forward blocks when training, but not to the attention weights.
+//!
+//! ```rust
+//! use candle::{DType, Device, Tensor};
+//! use candle_nn::transformer::{transformer_encoder_layer, TransformerLayerConfig};
+//! use candle_nn::VarBuilder;
+//! # fn main() -> candle::Result<()> {
+//! let dev = Device::Cpu;
+//! let vb = VarBuilder::zeros(DType::F32, &dev);
+//! let config = TransformerLayerConfig {
+//!     dim_feedforward: 64,
+//!     ..TransformerLayerConfig::new(4)
+//! };
+//! let layer = transformer_encoder_layer(16, config, vb)?;
+//! let xs = Tensor::randn(0f32, 1., (2, 5, 16), &dev)?;
+//! let ys = layer.forward(&xs, None, false)?;
+//! assert_eq!(ys.dims(), &[2, 5, 16]);
+//! # Ok(()) }
+//! ```
+use crate::attention::{
+    causal_mask, multi_head_attention, KvCache, MultiHeadAttention, MultiHeadAttentionConfig,
+    PositionEncoding,
+};
+use crate::{Activation, Dropout, LayerNorm, Linear, Module};
+use candle::{Result, Tensor};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransformerLayerConfig {
+    /// The config of the self-attention. The cross-attention of the decoder uses the same number
+    /// of heads but no position encoding. The `bias` setting also applies to the feed-forward
+    /// linear layers.
+    pub attention: MultiHeadAttentionConfig,
+    pub dim_feedforward: usize,
+    pub dropout: f32,
+    pub activation: Activation,
+    pub layer_norm_eps: f64,
+    /// Applies the layer norms before the attention and feed-forward blocks rather than after
+    /// the residual connections.
+    pub norm_first: bool,
+}
+
+impl TransformerLayerConfig {
+    /// A config with the PyTorch defaults.
+    pub fn new(num_heads: usize) -> Self {
+        Self {
+            attention: MultiHeadAttentionConfig::new(num_heads),
+            dim_feedforward: 2048,
+            dropout: 0.1,
+            activation: Activation::Relu,
+            layer_norm_eps: 1e-5,
+            norm_first: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct FeedForward {
+    linear1: Linear,
+    linear2: Linear,
+    activation: Activation,
+    dropout: Dropout,
+}
+
+impl FeedForward {
+    fn new(
+        d_model: usize,
+        config: &TransformerLayerConfig,
+        vb: &crate::VarBuilder,
+    ) -> Result<Self> {
+        let bias = config.attention.bias;
+        let linear1 = crate::linear_b(d_model, config.dim_feedforward, bias, vb.pp("linear1"))?;
+        let linear2 = crate::linear_b(config.dim_feedforward, d_model, bias, vb.pp("linear2"))?;
+        Ok(Self {
+            linear1,
+            linear2,
+            activation: config.activation,
+            dropout: Dropout::new(config.dropout),
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, train: bool) -> Result<Tensor> {
+        let xs = self.linear1.forward(xs)?.apply(&self.activation)?;
+        self.linear2.forward(&self.dropout.forward(&xs, train)?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TransformerEncoderLayer {
+    self_attn: MultiHeadAttention,
+    feed_forward: FeedForward,
+    norm1: LayerNorm,
+    norm2: LayerNorm,
+    dropout: Dropout,
+    norm_first: bool,
+}
+
+impl TransformerEncoderLayer {
+    pub fn self_attn(&self) -> &MultiHeadAttention {
+        &self.self_attn
+    }
+
+    /// Applies the layer to `src` of shape `(batch, seq_len, d_model)`, `mask` is an additive
+    /// attention mask, see [`MultiHeadAttention::forward`].
+    pub fn forward(&self, src: &Tensor, mask: Option<&Tensor>, train: bool) -> Result<Tensor> {
+        let self_attn = |xs: &Tensor| {
+            let xs = self.self_attn.forward(xs, xs, xs, mask)?;
+            self.dropout.forward(&xs, train)
+        };
+        let feed_forward = |xs: &Tensor| {
+            let xs = self.feed_forward.forward(xs, train)?;
+            self.dropout.forward(&xs, train)
+        };
+        if self.norm_first {
+            let xs = (src + self_attn(&self.norm1.forward(src)?)?)?;
+            &xs + feed_forward(&self.norm2.forward(&xs)?)?
+        } else {
+            let xs = self.norm1.forward(&(src + self_attn(src)?)?)?;
+            self.norm2.forward(&(&xs + feed_forward(&xs)?)?)
+        }
+    }
+}
+
+pub fn transformer_encoder_layer(
+    d_model: usize,
+    config: TransformerLayerConfig,
+    vb: crate::VarBuilder,
+) -> Result<TransformerEncoderLayer> {
+    let self_attn = multi_head_attention(d_model, config.attention, vb.pp("self_attn"))?;
+    let feed_forward = FeedForward::new(d_model, &config, &vb)?;
+    let norm1 = crate::layer_norm(d_model, config.layer_norm_eps, vb.pp("norm1"))?;
+    let norm2 = crate::layer_norm(d_model, config.layer_norm_eps, vb.pp("norm2"))?;
+    Ok(TransformerEncoderLayer {
+        self_attn,
+        feed_forward,
+        norm1,
+        norm2,
+        dropout: Dropout::new(config.dropout),
+        norm_first: config.norm_first,
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct TransformerDecoderLayer {
+    self_attn: MultiHeadAttention,
+    multihead_attn: MultiHeadAttention,
+    feed_forward: FeedForward,
+    norm1: LayerNorm,
+    norm2: LayerNorm,
+    norm3: LayerNorm,
+    dropout: Dropout,
+    norm_first: bool,
+}
+
+impl TransformerDecoderLayer {
+    pub fn self_attn(&self) -> &MultiHeadAttention {
+        &self.self_attn
+    }
+
+    pub fn multihead_attn(&self) -> &MultiHeadAttention {
+        &self.multihead_attn
+    }
+
+    fn forward_impl(
+        &self,
+        tgt: &Tensor,
+        memory: &Tensor,
+        tgt_mask: Option<&Tensor>,
+        memory_mask: Option<&Tensor>,
+        mut cache: Option<&mut KvCache>,
+        train: bool,
+    ) -> Result<Tensor> {
+        let mut self_attn = |xs: &Tensor| {
+            let xs = match cache.as_deref_mut() {
+                None => self.self_attn.forward(xs, xs, xs, tgt_mask)?,
+                Some(cache) => self
+                    .self_attn
+                    .forward_with_cache(xs, xs, xs, tgt_mask, cache)?,
+            };
+            self.dropout.forward(&xs, train)
+        };
+        let cross_attn = |xs: &Tensor| {
+            let xs = self
+                .multihead_attn
+                .forward(xs, memory, memory, memory_mask)?;
+            self.dropout.forward(&xs, train)
+        };
+        let feed_forward = |xs: &Tensor| {
+            let xs = self.feed_forward.forward(xs, train)?;
+            self.dropout.forward(&xs, train)
+        };
+        if self.norm_first {
+            let xs = (tgt + self_attn(&self.norm1.forward(tgt)?)?)?;
+            let xs = (&xs + cross_attn(&self.norm2.forward(&xs)?)?)?;
+            &xs + feed_forward(&self.norm3.forward(&xs)?)?
+        } else {
+            let xs = self.norm1.forward(&(tgt + self_attn(tgt)?)?)?;
+            let xs = self.norm2.forward(&(&xs + cross_attn(&xs)?)?)?;
+            self.norm3.forward(&(&xs + feed_forward(&xs)?)?)
+        }
+    }
+
+    /// Applies the layer to `tgt` of shape `(batch, tgt_len, d_model)` attending to `memory` of
+    /// shape `(batch, src_len, d_model)`. The masks are additive attention masks, typically a
+    /// [`causal_mask`] for `tgt_mask`.
+    pub fn forward(
+        &self,
+        tgt: &Tensor,
+        memory: &Tensor,
+        tgt_mask: Option<&Tensor>,
+        memory_mask: Option<&Tensor>,
+        train: bool,
+    ) -> Result<Tensor> {
+        self.forward_impl(tgt, memory, tgt_mask, memory_mask, None, train)
+    }
+
+    /// Incremental decoding: `tgt` holds the new positions, the self-attention keys and values
+    /// of the previous positions come from `cache` and a causal mask is applied.
+    pub fn forward_with_cache(
+        &self,
+        tgt: &Tensor,
+        memory: &Tensor,
+        memory_mask: Option<&Tensor>,
+        cache: &mut KvCache,
+    ) -> Result<Tensor> {
+        let tgt_len = tgt.dim(1)?;
+        let tgt_mask = if tgt_len > 1 {
+            let kv_len = cache.current_seq_len() + tgt_len;
+            Some(causal_mask(tgt_len, kv_len, tgt.dtype(), tgt.device())?)
+        } else {
+            None
+        };
+        self.forward_impl(
+            tgt,
+            memory,
+            tgt_mask.as_ref(),
+            memory_mask,
+            Some(cache),
+            false,
+        )
+    }
+}
+
+pub fn transformer_decoder_layer(
+    d_model: usize,
+    config: TransformerLayerConfig,
+    vb: crate::VarBuilder,
+) -> Result<TransformerDecoderLayer> {
+    let self_attn = multi_head_attention(d_model, config.attention, vb.pp("self_attn"))?;
+    let cross_config = MultiHeadAttentionConfig {
+        position: PositionEncoding::None,
+        ..config.attention
+    };
+    let multihead_attn = multi_head_attention(d_model, cross_config, vb.pp("multihead_attn"))?;
+    let feed_forward = FeedForward::new(d_model, &config, &vb)?;
+    let norm1 = crate::layer_norm(d_model, config.layer_norm_eps, vb.pp("norm1"))?;
+    let norm2 = crate::layer_norm(d_model, config.layer_norm_eps, vb.pp("norm2"))?;
+    let norm3 = crate::layer_norm(d_model, config.layer_norm_eps, vb.pp("norm3"))?;
+    Ok(TransformerDecoderLayer {
+        self_attn,
+        multihead_attn,
+        feed_forward,
+        norm1,
+        norm2,
+        norm3,
+        dropout: Dropout::new(config.dropout),
+        norm_first: config.norm_first,
+    })
+}
@@ -1,7 +1,21 @@
+use crate::optim::{ParamGroup, ParamGroupConfig};
 use candle::{DType, Device, Result, Shape, Tensor, Var};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+// Matches a variable name against a pattern where `*` matches any sequence of characters.
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => match name.strip_prefix(prefix) {
+            None => false,
+            Some(name) => (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| glob_match(rest, &name[i..])),
+        },
+    }
+}
+
 /// A `VarMap` is a store that holds named variables. Variables can be retrieved from the stores
 /// and new variables can be added by providing some initialization config in case they are
 /// missing.
@@ -19,21 +33,89 @@ impl VarMap {
         Self { data }
     }
 
-    /// Retrieve all the variables currently stored in the map.
+    /// Retrieve all the variables currently stored in the map, sorted by name so that the order
+    /// does not change from one process to another.
     pub fn all_vars(&self) -> Vec<Var> {
+        self.sorted_vars().into_iter().map(|(_, var)| var).collect()
+    }
+
+    // The variables sorted by name, the lock is only held while cloning the variables.
+    fn sorted_vars(&self) -> Vec<(String, Var)> {
         let tensor_data = self.data.lock().unwrap();
-        #[allow(clippy::map_clone)]
-        tensor_data.values().map(|c| c.clone()).collect::<Vec<_>>()
+        let mut vars = tensor_data
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        vars.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+        vars
+    }
+
+    /// Split the variables in parameter groups for [`crate::Optimizer::from_param_groups`].
+    ///
+    /// Each variable goes to the group of the first rule whose pattern matches its name, `*`
+    /// matching any sequence of characters, e.g. `*.bias` or `*lora*`. The variables that match
+    /// none of the patterns are put in a last group without overrides. Empty groups are skipped
+    /// and the variables are sorted by name so that the optimizer state dict keys are stable.
+    pub fn param_groups(&self, rules: &[(&str, ParamGroupConfig)]) -> Vec<ParamGroup> {
+        let mut groups = vec![vec![]; rules.len() + 1];
+        for (name, var) in self.sorted_vars() {
+            let idx = rules
+                .iter()
+                .position(|(pattern, _)| glob_match(pattern, &name))
+                .unwrap_or(rules.len());
+            groups[idx].push((name, var))
+        }
+        let configs = rules
+            .iter()
+            .map(|(_, config)| *config)
+            .chain(std::iter::once(ParamGroupConfig::default()));
+        groups
+            .into_iter()
+            .zip(configs)
+            .filter(|(vars, _)| !vars.is_empty())
+            .map(|(vars, config)| ParamGroup::new(vars, config))
+            .collect()
     }
 
     /// Save the map in the safetensors format.
+    ///
+    /// The variables are streamed to the file one by one, the map is not locked while writing.
     pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
-        let tensor_data = self.data.lock().unwrap();
-        let data = tensor_data.iter().map(|(k, v)| (k, v.as_tensor()));
-        safetensors::tensor::serialize_to_file(data, &None, path.as_ref())?;
+        let path = path.as_ref();
+        let vars = self.sorted_vars();
+        let headers = vars
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.dtype(), v.shape().clone()))
+            .collect::<Vec<_>>();
+        let file =
+            std::fs::File::create(path).map_err(|e| candle::Error::from(e).with_path(path))?;
+        let mut writer = candle::safetensors::Writer::new(std::io::BufWriter::new(file), &headers)?;
+        for (name, var) in vars.iter() {
+            writer.write(name, var.as_tensor())?
+        }
+        writer.finish()?;
         Ok(())
     }
 
+    /// Save the map in multiple safetensors shards of at most `max_shard_size` bytes in the
+    /// directory `dir`, together with a `model.safetensors.index.json` index file.
+    pub fn save_sharded<P: AsRef<std::path::Path>>(
+        &self,
+        dir: P,
+        max_shard_size: usize,
+    ) -> Result<()> {
+        let vars = self.sorted_vars();
+        let headers = vars
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.dtype(), v.shape().clone()))
+            .collect::<Vec<_>>();
+        let mut writer = candle::safetensors::ShardedWriter::new(dir, &headers, max_shard_size)?;
+        for (name, var) in vars.iter() {
+            writer.write(name, var.as_tensor())?
+        }
+        writer.finish()
+    }
+
     /// Load some values from a safetensors file and modify the existing variables to have these
     /// values.
     ///
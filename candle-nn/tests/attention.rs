@@ -0,0 +1,174 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::{DType, Device, Tensor, D};
+use candle_nn::attention::{alibi_slopes, causal_mask, padding_mask, KvCache, PositionEncoding};
+use candle_nn::{multi_head_attention, MultiHeadAttentionConfig, VarBuilder, VarMap};
+
+fn max_diff(a: &Tensor, b: &Tensor) -> Result<f32> {
+    Ok((a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?)
+}
+
+#[test]
+fn mha_single_head() -> Result<()> {
+    let dev = &Device::Cpu;
+    // With identity projections, a single head computes softmax(x x^T / sqrt(d)) x.
+    let eye = Tensor::new(&[[1f32, 0.], [0., 1.]], dev)?;
+    let ts = [
+        (
+            "in_proj_weight".to_string(),
+            Tensor::cat(&[&eye, &eye, &eye], 0)?,
+        ),
+        ("out_proj.weight".to_string(), eye.clone()),
+    ];
+    let vb = VarBuilder::from_tensors(ts.into_iter().collect(), DType::F32, dev);
+    let config = MultiHeadAttentionConfig {
+        bias: false,
+        ..MultiHeadAttentionConfig::new(1)
+    };
+    let mha = multi_head_attention(2, config, vb)?;
+    let xs = Tensor::new(&[[[1f32, 0.], [0., 2.], [1., 1.]]], dev)?;
+    let ys = mha.forward(&xs, &xs, &xs, None)?;
+    let scores = (xs.matmul(&xs.t()?)? / 2f64.sqrt())?;
+    let expected = candle_nn::ops::softmax(&scores, D::Minus1)?.matmul(&xs)?;
+    assert!(max_diff(&ys, &expected)? < 1e-6);
+
+    // The causal mask restricts the first query to the first key.
+    let mask = causal_mask(3, 3, DType::F32, dev)?;
+    let ys = mha.forward(&xs, &xs, &xs, Some(&mask))?;
+    assert_eq!(ys.get(0)?.get(0)?.to_vec1::<f32>()?, [1., 0.]);
+    Ok(())
+}
+
+// Decoding one position at a time with a kv cache gives the same result as attending over the
+// full sequence with a causal mask.
+fn check_kv_cache(position: PositionEncoding) -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let config = MultiHeadAttentionConfig {
+        num_kv_heads: 2,
+        position,
+        ..MultiHeadAttentionConfig::new(4)
+    };
+    let mha = multi_head_attention(16, config, vb)?;
+    let xs = Tensor::randn(0f32, 1., (2, 6, 16), dev)?;
+    let mask = causal_mask(6, 6, DType::F32, dev)?;
+    let expected = mha.forward(&xs, &xs, &xs, Some(&mask))?;
+
+    let mut cache = KvCache::new();
+    let prompt = xs.narrow(1, 0, 2)?;
+    let mask = causal_mask(2, 2, DType::F32, dev)?;
+    let mut ys =
+        vec![mha.forward_with_cache(&prompt, &prompt, &prompt, Some(&mask), &mut cache)?];
+    for pos in 2..6 {
+        let x = xs.narrow(1, pos, 1)?;
+        ys.push(mha.forward_with_cache(&x, &x, &x, None, &mut cache)?);
+    }
+    assert_eq!(cache.current_seq_len(), 6);
+    let ys = Tensor::cat(&ys, 1)?;
+    assert!(max_diff(&ys, &expected)? < 1e-5);
+    Ok(())
+}
+
+#[test]
+fn mha_kv_cache() -> Result<()> {
+    check_kv_cache(PositionEncoding::None)?;
+    check_kv_cache(PositionEncoding::Rotary {
+        theta: 10000.,
+        max_seq_len: 32,
+    })?;
+    check_kv_cache(PositionEncoding::Alibi)?;
+    Ok(())
+}
+
+#[test]
+fn mha_padding_mask() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let config = MultiHeadAttentionConfig {
+        num_kv_heads: 1,
+        kdim: Some(6),
+        vdim: Some(4),
+        ..MultiHeadAttentionConfig::new(2)
+    };
+    let mha = multi_head_attention(8, config, vb)?;
+    let mut names = varmap
+        .data()
+        .lock()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "in_proj_bias",
+            "k_proj_weight",
+            "out_proj.bias",
+            "out_proj.weight",
+            "q_proj_weight",
+            "v_proj_weight"
+        ]
+    );
+
+    // Masking the last key is the same as removing it.
+    let query = Tensor::randn(0f32, 1., (1, 3, 8), dev)?;
+    let key = Tensor::randn(0f32, 1., (1, 5, 6), dev)?;
+    let value = Tensor::randn(0f32, 1., (1, 5, 4), dev)?;
+    let padding = Tensor::new(&[[0u8, 0, 0, 0, 1]], dev)?;
+    let mask = padding_mask(&padding, DType::F32)?;
+    assert_eq!(mask.dims(), &[1, 1, 1, 5]);
+    let ys = mha.forward(&query, &key, &value, Some(&mask))?;
+    let expected = mha.forward(&query, &key.narrow(1, 0, 4)?, &value.narrow(1, 0, 4)?, None)?;
+    assert!(max_diff(&ys, &expected)? < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn mha_alibi_cross_attention() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let config = MultiHeadAttentionConfig {
+        position: PositionEncoding::Alibi,
+        ..MultiHeadAttentionConfig::new(2)
+    };
+    let mha = multi_head_attention(8, config, vb)?;
+    // The query is longer than the key/value sequence.
+    let query = Tensor::randn(0f32, 1., (1, 5, 8), dev)?;
+    let kv = Tensor::randn(0f32, 1., (1, 3, 8), dev)?;
+    let ys = mha.forward(&query, &kv, &kv, None)?;
+    assert_eq!(ys.dims(), &[1, 5, 8]);
+    // The last two queries are aligned with the last two keys as in the self-attention case.
+    let expected = mha.forward(&query.narrow(1, 3, 2)?, &kv, &kv, None)?;
+    assert!(max_diff(&ys.narrow(1, 3, 2)?, &expected)? < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn alibi() -> Result<()> {
+    let slopes = alibi_slopes(8);
+    assert_eq!(
+        slopes,
+        [0.5, 0.25, 0.125, 0.0625, 0.03125, 0.015625, 0.0078125, 0.00390625]
+    );
+    let slopes = alibi_slopes(12);
+    assert_eq!(slopes.len(), 12);
+    let extra = [
+        0.5f32.powf(0.5),
+        0.5f32.powf(1.5),
+        0.5f32.powf(2.5),
+        0.5f32.powf(3.5),
+    ];
+    for (s, e) in slopes[8..].iter().zip(extra.iter()) {
+        assert!((s - e).abs() < 1e-6)
+    }
+    Ok(())
+}
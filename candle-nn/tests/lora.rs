@@ -0,0 +1,152 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::{DType, Device, Module, Tensor};
+use candle_nn::lora::{load_adapter, save_adapter, Lora, LoraConfig};
+use candle_nn::{Conv2dConfig, Optimizer, VarBuilder, VarMap, SGD};
+
+fn max_diff(a: &Tensor, b: &Tensor) -> Result<f32> {
+    Ok((a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?)
+}
+
+// Sets all the `lora_B` weights to some non-zero values so that the adapters have an effect.
+fn randomize_lora_b(adapters: &VarMap) -> Result<()> {
+    for (name, var) in adapters.data().lock().unwrap().iter() {
+        if name.contains("lora_B") {
+            var.set(&Tensor::randn(0f32, 1., var.shape(), &Device::Cpu)?)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn lora_targets() -> Result<()> {
+    let config = LoraConfig::new(4, 8., &["q_proj", "mlp.*_proj"]);
+    assert_eq!(config.scale(), 2.);
+    assert!(config.is_target("q_proj"));
+    assert!(config.is_target("model.layers.0.self_attn.q_proj"));
+    assert!(config.is_target("model.layers.0.mlp.up_proj"));
+    assert!(!config.is_target("model.layers.0.self_attn.k_proj"));
+    assert!(!config.is_target("model.layers.0.self_attn.qq_proj"));
+    Ok(())
+}
+
+#[test]
+fn lora_linear() -> Result<()> {
+    let dev = &Device::Cpu;
+    let base = VarMap::new();
+    let vb = VarBuilder::from_varmap(&base, DType::F32, dev);
+    let adapters = VarMap::new();
+    let lvb = VarBuilder::from_varmap(&adapters, DType::F32, dev);
+    let lora = Lora::new(LoraConfig::new(2, 4., &["q_proj"]), lvb);
+    let mut layer = lora.linear(8, 6, vb.pp("attn.q_proj"))?;
+    let xs = Tensor::randn(0f32, 1., (3, 8), dev)?;
+
+    // `lora_B` is initialized to zero so the adapter starts as a no-op.
+    let base_ys = layer.base().forward(&xs)?;
+    assert_eq!(max_diff(&layer.forward(&xs)?, &base_ys)?, 0.);
+
+    randomize_lora_b(&adapters)?;
+    let ys = layer.forward(&xs)?;
+    assert!(max_diff(&ys, &base_ys)? > 1e-3);
+
+    // Merging gives the same outputs and unmerging restores the base weight.
+    let weight = layer.base().weight().clone();
+    layer.merge()?;
+    assert!(layer.is_merged());
+    assert!(max_diff(&layer.forward(&xs)?, &ys)? < 1e-5);
+    layer.unmerge()?;
+    assert!(!layer.is_merged());
+    assert!(max_diff(layer.base().weight(), &weight)? < 1e-6);
+    assert!(max_diff(&layer.forward(&xs)?, &ys)? < 1e-5);
+
+    // Only the adapter weights are trained, the base weights stay frozen.
+    let weight = layer.base().weight().clone();
+    let mut sgd = SGD::new(adapters.all_vars(), 1e-3)?;
+    let base_vars = base.all_vars();
+    let loss = layer.forward(&xs)?.sqr()?.sum_all()?;
+    let grads = loss.backward()?;
+    assert!(base_vars.iter().all(|v| grads.get(v).is_none()));
+    sgd.step(&grads)?;
+    assert_eq!(max_diff(layer.base().weight(), &weight)?, 0.);
+    let new_loss = layer.forward(&xs)?.sqr()?.sum_all()?;
+    assert!(new_loss.to_scalar::<f32>()? < loss.to_scalar::<f32>()?);
+    Ok(())
+}
+
+#[test]
+fn lora_embedding_conv2d() -> Result<()> {
+    let dev = &Device::Cpu;
+    let vb = VarBuilder::from_varmap(&VarMap::new(), DType::F32, dev);
+    let adapters = VarMap::new();
+    let lvb = VarBuilder::from_varmap(&adapters, DType::F32, dev);
+    let lora = Lora::new(LoraConfig::new(2, 2., &["embed_tokens", "conv"]), lvb);
+
+    let mut emb = lora.embedding(10, 4, vb.pp("embed_tokens"))?;
+    let mut conv = lora.conv2d(3, 5, 3, Conv2dConfig::default(), vb.pp("conv"))?;
+    for name in [
+        "embed_tokens.lora_embedding_A",
+        "embed_tokens.lora_embedding_B",
+        "conv.lora_A.weight",
+        "conv.lora_B.weight",
+    ] {
+        let var = adapters.data().lock().unwrap().get(name).unwrap().clone();
+        var.set(&Tensor::randn(0f32, 1., var.shape(), dev)?)?;
+    }
+
+    let ids = Tensor::new(&[[1u32, 2, 7], [0, 9, 9]], dev)?;
+    let ys = emb.forward(&ids)?;
+    assert_eq!(ys.dims(), &[2, 3, 4]);
+    emb.merge()?;
+    assert!(max_diff(&emb.forward(&ids)?, &ys)? < 1e-5);
+
+    let xs = Tensor::randn(0f32, 1., (2, 3, 6, 6), dev)?;
+    let ys = conv.forward(&xs)?;
+    assert_eq!(ys.dims(), &[2, 5, 4, 4]);
+    assert!(max_diff(&ys, &conv.base().forward(&xs)?)? > 1e-3);
+    conv.merge()?;
+    assert!(max_diff(&conv.forward(&xs)?, &ys)? < 1e-4);
+    Ok(())
+}
+
+#[test]
+fn lora_save_load() -> Result<()> {
+    let dev = &Device::Cpu;
+    let base = VarMap::new();
+    let vb = VarBuilder::from_varmap(&base, DType::F32, dev);
+    let adapters = VarMap::new();
+    let lvb = VarBuilder::from_varmap(&adapters, DType::F32, dev);
+    let mut config = LoraConfig::new(4, 16., &["v_proj"]);
+    config.dropout = 0.1;
+    let lora = Lora::new(config.clone(), lvb);
+    let layer = lora.linear_no_bias(8, 8, vb.pp("model.layers.0.self_attn.v_proj"))?;
+    randomize_lora_b(&adapters)?;
+
+    let dir = std::env::temp_dir().join("candle-lora-adapter");
+    save_adapter(&adapters, &config, &dir)?;
+    let (loaded_config, tensors) = load_adapter(&dir, dev)?;
+    assert_eq!(loaded_config, config);
+    let mut names = tensors.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "model.layers.0.self_attn.v_proj.lora_A.weight",
+            "model.layers.0.self_attn.v_proj.lora_B.weight"
+        ]
+    );
+    let raw = candle::safetensors::load(dir.join("adapter_model.safetensors"), dev)?;
+    assert!(raw.contains_key("base_model.model.model.layers.0.self_attn.v_proj.lora_A.weight"));
+
+    // Rebuilding the layer from the saved adapter gives the same outputs.
+    let lora = Lora::from_adapter(&dir, DType::F32, dev)?;
+    let loaded = lora.linear_no_bias(8, 8, vb.pp("model.layers.0.self_attn.v_proj"))?;
+    let xs = Tensor::randn(0f32, 1., (2, 8), dev)?;
+    assert!(max_diff(&loaded.forward(&xs)?, &layer.forward(&xs)?)? < 1e-6);
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
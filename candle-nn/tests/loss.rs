@@ -4,8 +4,9 @@ extern crate intel_mkl_src;
 #[cfg(feature = "accelerate")]
 extern crate accelerate_src;
 
-use candle::test_utils::to_vec0_round;
-use candle::{Device, Result, Tensor};
+use candle::test_utils::{to_vec0_round, to_vec1_round};
+use candle::{DType, Device, Result, Tensor, Var};
+use candle_nn::loss::{CrossEntropyConfig, CtcConfig, Reduction, TripletMarginConfig};
 
 /* Equivalent python code:
 import torch
@@ -86,3 +87,300 @@ fn binary_cross_entropy_with_logit() -> Result<()> {
     assert_eq!(to_vec0_round(&loss, 4)?, 0.8224);
     Ok(())
 }
+
+#[test]
+fn cross_entropy_with_config() -> Result<()> {
+    use candle_nn::loss::cross_entropy_with_config as ce;
+    let cpu = Device::Cpu;
+    let input = Tensor::new(
+        &[
+            [1.1050f32, 0.3013, -1.5394, -2.1528, -0.8634],
+            [1.0730, -0.9419, -0.1670, -0.6582, 0.5061],
+            [0.8318, 1.1154, -0.3610, 0.5351, 1.0830],
+        ],
+        &cpu,
+    )?;
+    let target = Tensor::new(&[1u32, 0, 4], &cpu)?;
+    let weight = Tensor::new(&[0.5f32, 2.0, 1.0, 1.5, 0.3], &cpu)?;
+
+    let loss = ce(&input, &target, &CrossEntropyConfig::default())?;
+    assert_eq!(to_vec0_round(&loss, 4)?, 1.1312);
+    let cfg = CrossEntropyConfig {
+        weight: Some(weight.clone()),
+        ..Default::default()
+    };
+    assert_eq!(to_vec0_round(&ce(&input, &target, &cfg)?, 4)?, 1.2279);
+    let cfg = CrossEntropyConfig {
+        label_smoothing: 0.1,
+        ..Default::default()
+    };
+    assert_eq!(to_vec0_round(&ce(&input, &target, &cfg)?, 4)?, 1.2140);
+    let cfg = CrossEntropyConfig {
+        label_smoothing: 0.1,
+        reduction: Reduction::None,
+        ..Default::default()
+    };
+    assert_eq!(
+        to_vec1_round(&ce(&input, &target, &cfg)?, 4)?,
+        [1.4256, 0.8844, 1.3321]
+    );
+
+    // The ignored target is out of the class range.
+    let target = Tensor::new(&[1u32, 7, 4], &cpu)?;
+    let cfg = CrossEntropyConfig {
+        ignore_index: Some(7),
+        ..Default::default()
+    };
+    assert_eq!(to_vec0_round(&ce(&input, &target, &cfg)?, 4)?, 1.3102);
+    let cfg = CrossEntropyConfig {
+        weight: Some(weight),
+        ignore_index: Some(7),
+        label_smoothing: 0.2,
+        reduction: Reduction::Mean,
+    };
+    assert_eq!(to_vec0_round(&ce(&input, &target, &cfg)?, 4)?, 1.4384);
+    Ok(())
+}
+
+#[test]
+fn kl_div() -> Result<()> {
+    let cpu = Device::Cpu;
+    let inp = Tensor::new(&[[0.2f32, 0.3, 0.5], [0.6, 0.3, 0.1]], &cpu)?.log()?;
+    let target = Tensor::new(&[[0.1f32, 0.0, 0.9], [0.4, 0.4, 0.2]], &cpu)?;
+    let loss = candle_nn::loss::kl_div(&inp, &target, false, Reduction::BatchMean)?;
+    assert_eq!(to_vec0_round(&loss, 4)?, 0.2756);
+    let loss = candle_nn::loss::kl_div(&inp, &target, false, Reduction::Mean)?;
+    assert_eq!(to_vec0_round(&loss, 4)?, 0.0919);
+    // Zero probabilities have to be avoided for log targets.
+    let target = Tensor::new(&[[0.1f32, 0.3, 0.6], [0.4, 0.4, 0.2]], &cpu)?;
+    let loss1 = candle_nn::loss::kl_div(&inp, &target, false, Reduction::Sum)?;
+    let loss2 = candle_nn::loss::kl_div(&inp, &target.log()?, true, Reduction::Sum)?;
+    assert_eq!(to_vec0_round(&loss1, 4)?, to_vec0_round(&loss2, 4)?);
+    Ok(())
+}
+
+#[test]
+fn huber_and_smooth_l1() -> Result<()> {
+    let cpu = Device::Cpu;
+    let inp = Tensor::new(&[0.5f32, -2.0, 3.0, 0.1], &cpu)?;
+    let target = Tensor::new(&[0.0f32, 0.0, 1.0, 1.5], &cpu)?;
+    let loss = candle_nn::loss::huber(&inp, &target, 1.5, Reduction::None)?;
+    assert_eq!(to_vec1_round(&loss, 4)?, [0.125, 1.875, 1.875, 0.98]);
+    let loss = candle_nn::loss::smooth_l1(&inp, &target, 0.5, Reduction::Sum)?;
+    assert_eq!(to_vec0_round(&loss, 4)?, 4.9);
+    // A beta of zero is the l1 loss.
+    let loss = candle_nn::loss::smooth_l1(&inp, &target, 0., Reduction::Mean)?;
+    assert_eq!(to_vec0_round(&loss, 4)?, 1.475);
+    Ok(())
+}
+
+#[test]
+fn cosine_embedding() -> Result<()> {
+    let cpu = Device::Cpu;
+    let x1 = Tensor::new(&[[1f32, 2., 3.], [0.5, -1., 2.], [1., 0., 0.]], &cpu)?;
+    let x2 = Tensor::new(&[[1f32, 0., -1.], [0.4, -1.2, 2.2], [0., 1., 0.2]], &cpu)?;
+    let target = Tensor::new(&[1f32, -1., -1.], &cpu)?;
+    let loss = candle_nn::loss::cosine_embedding(&x1, &x2, &target, 0.1, Reduction::None)?;
+    assert_eq!(to_vec1_round(&loss, 4)?, [1.378, 0.8975, 0.]);
+    let loss = candle_nn::loss::cosine_embedding(&x1, &x2, &target, 0.1, Reduction::Mean)?;
+    assert_eq!(to_vec0_round(&loss, 4)?, 0.7585);
+    Ok(())
+}
+
+#[test]
+fn triplet_margin_and_info_nce() -> Result<()> {
+    let cpu = Device::Cpu;
+    let anchor = Tensor::new(&[[1f32, 2., 3.], [0., 0., 1.]], &cpu)?;
+    let positive = Tensor::new(&[[1.5f32, 2., 2.], [0., 1., 1.]], &cpu)?;
+    let negative = Tensor::new(&[[1f32, 1., 3.], [2., 0., 1.]], &cpu)?;
+    let cfg = TripletMarginConfig {
+        reduction: Reduction::None,
+        ..Default::default()
+    };
+    let loss = candle_nn::loss::triplet_margin(&anchor, &positive, &negative, &cfg)?;
+    assert_eq!(to_vec1_round(&loss, 4)?, [1.118, 0.]);
+    let cfg = TripletMarginConfig {
+        p: 1.,
+        swap: true,
+        reduction: Reduction::None,
+        ..Default::default()
+    };
+    let loss = candle_nn::loss::triplet_margin(&anchor, &positive, &negative, &cfg)?;
+    assert_eq!(to_vec1_round(&loss, 4)?, [1.5, 0.]);
+
+    let loss = candle_nn::loss::info_nce(&anchor, &positive, 0.5, Reduction::Mean)?;
+    assert_eq!(to_vec0_round(&loss, 4)?, 0.6461);
+    Ok(())
+}
+
+#[test]
+fn focal() -> Result<()> {
+    let cpu = Device::Cpu;
+    let inp = Tensor::new(
+        &[[2.3611f32, -0.8813], [0.0419, 0.0763], [-1.0494, 0.8111]],
+        &cpu,
+    )?;
+    let target = Tensor::new(&[[0f32, 1.], [1., 0.], [0., 1.]], &cpu)?;
+    let loss = candle_nn::loss::focal(&inp, &target, Some(0.25), 2., Reduction::Mean)?;
+    assert_eq!(to_vec0_round(&loss, 4)?, 0.3168);
+    // Without focusing this is the binary cross-entropy.
+    let loss = candle_nn::loss::focal(&inp, &target, None, 0., Reduction::Mean)?;
+    let bce = candle_nn::loss::binary_cross_entropy_with_logit(&inp, &target)?;
+    assert_eq!(to_vec0_round(&loss, 4)?, to_vec0_round(&bce, 4)?);
+    Ok(())
+}
+
+// The expected values were obtained by enumerating all the alignments of the targets.
+#[test]
+fn ctc() -> Result<()> {
+    let cpu = Device::Cpu;
+    let logits = Tensor::new(
+        &[
+            [[0.6888f32, 0.5159, -0.1589], [-0.4822, 0.0225, -0.1901]],
+            [[0.5676, -0.3934, -0.0468], [0.1668, 0.8162, 0.0094]],
+            [[-0.4363, 0.5116, 0.2367], [-0.499, 0.8195, 0.9656]],
+            [[0.6204, 0.8043, -0.3797], [0.4597, 0.7977, 0.368]],
+        ],
+        &cpu,
+    )?;
+    let log_probs = candle_nn::ops::log_softmax(&logits, 2)?;
+    let targets = Tensor::new(&[[1u32, 2], [1, 1]], &cpu)?;
+    let ctc = |input_lengths: &[usize], cfg: &CtcConfig| {
+        candle_nn::loss::ctc(&log_probs, &targets, input_lengths, &[2, 2], cfg)
+    };
+    let cfg = CtcConfig {
+        reduction: Reduction::None,
+        ..Default::default()
+    };
+    assert_eq!(to_vec1_round(&ctc(&[4, 3], &cfg)?, 4)?, [2.0496, 3.093]);
+    assert_eq!(
+        to_vec0_round(&ctc(&[4, 3], &Default::default())?, 4)?,
+        1.2856
+    );
+
+    // Repeated labels require a blank in between so two steps are not enough.
+    let loss = ctc(&[4, 2], &cfg)?.to_vec1::<f32>()?;
+    assert!(loss[1].is_infinite());
+    let cfg = CtcConfig {
+        zero_infinity: true,
+        reduction: Reduction::Sum,
+        ..Default::default()
+    };
+    assert_eq!(to_vec0_round(&ctc(&[4, 2], &cfg)?, 4)?, 2.0496);
+
+    // Blank labels are not valid targets.
+    let cfg = CtcConfig {
+        blank: 1,
+        ..Default::default()
+    };
+    assert!(ctc(&[4, 3], &cfg).is_err());
+    Ok(())
+}
+
+// Compares the gradients obtained via backprop with a central finite difference approximation.
+fn check_grad<F>(f: F, var: &Var) -> Result<()>
+where
+    F: Fn(&Tensor) -> Result<Tensor>,
+{
+    let eps = 1e-5;
+    let grads = f(var.as_tensor())?.backward()?;
+    let grad = grads
+        .get(var)
+        .expect("no grad")
+        .flatten_all()?
+        .to_vec1::<f64>()?;
+    let values = var.flatten_all()?.to_vec1::<f64>()?;
+    for i in 0..values.len() {
+        let shifted = |delta: f64| -> Result<f64> {
+            let mut values = values.clone();
+            values[i] += delta;
+            let xs = Tensor::from_vec(values, var.shape(), var.device())?;
+            f(&xs)?.to_scalar::<f64>()
+        };
+        let fd = (shifted(eps)? - shifted(-eps)?) / (2. * eps);
+        assert!(
+            (fd - grad[i]).abs() < 1e-5 * (1. + fd.abs()),
+            "index {i}: backprop {} finite-diff {fd}",
+            grad[i]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn loss_grads() -> Result<()> {
+    use candle_nn::loss;
+    let cpu = Device::Cpu;
+    let x = Var::rand_f64(-2., 2., (4, 5), DType::F64, &cpu)?;
+    let y = Tensor::rand(-2f64, 2., (4, 5), &cpu)?;
+
+    let target = Tensor::new(&[1u32, 9, 4, 0], &cpu)?;
+    let cfg = CrossEntropyConfig {
+        weight: Some(Tensor::new(&[0.5f64, 2.0, 1.0, 1.5, 0.3], &cpu)?),
+        ignore_index: Some(9),
+        label_smoothing: 0.2,
+        reduction: Reduction::Mean,
+    };
+    check_grad(|x| loss::cross_entropy_with_config(x, &target, &cfg), &x)?;
+
+    let probs = candle_nn::ops::softmax(&y, 1)?;
+    check_grad(
+        |x| {
+            let x = candle_nn::ops::log_softmax(x, 1)?;
+            loss::kl_div(&x, &probs, false, Reduction::BatchMean)
+        },
+        &x,
+    )?;
+    check_grad(|x| loss::huber(x, &y, 0.8, Reduction::Mean), &x)?;
+    check_grad(|x| loss::smooth_l1(x, &y, 0.5, Reduction::Sum), &x)?;
+    check_grad(
+        |x| {
+            loss::focal(
+                x,
+                &y.ge(0f64)?.to_dtype(DType::F64)?,
+                Some(0.25),
+                2.,
+                Reduction::Sum,
+            )
+        },
+        &x,
+    )?;
+
+    let target = Tensor::new(&[1f64, -1., -1., 1.], &cpu)?;
+    check_grad(
+        |x| loss::cosine_embedding(x, &y, &target, -0.5, Reduction::Sum),
+        &x,
+    )?;
+    let negative = Tensor::rand(-2f64, 2., (4, 5), &cpu)?;
+    let cfg = TripletMarginConfig {
+        margin: 10.,
+        p: 3.,
+        ..Default::default()
+    };
+    check_grad(|x| loss::triplet_margin(x, &y, &negative, &cfg), &x)?;
+    check_grad(|x| loss::info_nce(x, &y, 0.3, Reduction::Mean), &x)?;
+
+    // ctc, the gradients flow through the log-softmax.
+    let x = Var::rand_f64(-2., 2., (6, 3, 4), DType::F64, &cpu)?;
+    let targets = Tensor::new(&[[1u32, 2, 2], [3, 1, 0], [2, 0, 0]], &cpu)?;
+    check_grad(
+        |x| {
+            let log_probs = candle_nn::ops::log_softmax(x, 2)?;
+            let cfg = CtcConfig::default();
+            loss::ctc(&log_probs, &targets, &[6, 5, 4], &[3, 2, 1], &cfg)
+        },
+        &x,
+    )?;
+    // The gradient is also correct with respect to unnormalized inputs.
+    check_grad(
+        |x| {
+            let cfg = CtcConfig {
+                reduction: Reduction::Sum,
+                ..Default::default()
+            };
+            loss::ctc(x, &targets, &[6, 5, 4], &[3, 2, 1], &cfg)
+        },
+        &x,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,156 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::{Device, Var};
+use candle_nn::lr_scheduler::{
+    Constant, CosineAnnealing, ExponentialDecay, LinearWarmup, LrSchedule, LrScheduler, OneCycle,
+    ParamsReduceLrOnPlateau, PlateauMode, ReduceLrOnPlateau, StepDecay,
+};
+use candle_nn::{Optimizer, SGD};
+
+fn lrs<S: LrSchedule>(schedule: &S, steps: usize) -> Vec<f64> {
+    (0..steps)
+        .map(|step| (schedule.lr_at(step) * 1e6).round() / 1e6)
+        .collect()
+}
+
+// The expected values match the PyTorch schedulers: LinearLR, CosineAnnealingWarmRestarts,
+// OneCycleLR, StepLR and ExponentialLR.
+#[test]
+fn schedules() -> Result<()> {
+    let warmup = LinearWarmup {
+        lr: 0.01,
+        warmup_steps: 4,
+        start_factor: 0.1,
+    };
+    assert_eq!(
+        lrs(&warmup, 6),
+        [0.001, 0.00325, 0.0055, 0.00775, 0.01, 0.01]
+    );
+
+    let cosine = CosineAnnealing::new(0.1, 0., 5);
+    assert_eq!(
+        lrs(&cosine, 11),
+        [
+            0.1, 0.090451, 0.065451, 0.034549, 0.009549, 0.1, 0.090451, 0.065451, 0.034549,
+            0.009549, 0.1
+        ]
+    );
+    let cosine = CosineAnnealing {
+        lr: 0.1,
+        min_lr: 0.001,
+        period: 4,
+        period_mult: 2,
+    };
+    assert_eq!(
+        lrs(&cosine, 14),
+        [
+            0.1, 0.085502, 0.0505, 0.015498, 0.1, 0.096232, 0.085502, 0.069443, 0.0505, 0.031557,
+            0.015498, 0.004768, 0.1, 0.099049
+        ]
+    );
+
+    let one_cycle = OneCycle::new(0.1, 10);
+    assert_eq!(
+        lrs(&one_cycle, 9),
+        [0.004, 0.052, 0.1, 0.095048, 0.081175, 0.061126, 0.038874, 0.018826, 0.004952]
+    );
+    // The final learning rate is max_lr / div_factor / final_div_factor.
+    assert!((one_cycle.lr_at(9) - 4e-7).abs() < 1e-12);
+    assert_eq!(one_cycle.lr_at(9), one_cycle.lr_at(100));
+
+    assert_eq!(
+        lrs(&StepDecay::new(0.1, 2, 0.5), 6),
+        [0.1, 0.1, 0.05, 0.05, 0.025, 0.025]
+    );
+    assert_eq!(
+        lrs(&ExponentialDecay::new(0.1, 0.5), 4),
+        [0.1, 0.05, 0.025, 0.0125]
+    );
+    Ok(())
+}
+
+#[test]
+fn chained_schedules() -> Result<()> {
+    let schedule = LinearWarmup::new(0.1, 2)
+        .then(2, CosineAnnealing::new(0.1, 0., 4))
+        .then(6, Constant::new(0.));
+    assert_eq!(
+        lrs(&schedule, 8),
+        [0., 0.05, 0.1, 0.085355, 0.05, 0.014645, 0., 0.]
+    );
+    Ok(())
+}
+
+#[test]
+fn scheduler_drives_optimizer() -> Result<()> {
+    let x = Var::new(0f32, &Device::Cpu)?;
+    let mut sgd = SGD::new(vec![x.clone()], 0.)?;
+    let mut scheduler = LrScheduler::new(StepDecay::new(0.1, 2, 0.5));
+    let mut seen = vec![];
+    for _step in 0..5 {
+        scheduler.step(&mut sgd);
+        seen.push(sgd.learning_rate());
+        sgd.backward_step(&x.as_tensor().affine(1., 0.)?)?;
+    }
+    assert_eq!(seen, [0.1, 0.1, 0.05, 0.05, 0.025]);
+    assert_eq!(x.to_scalar::<f32>()?, -0.32500002);
+
+    // Resuming from a checkpoint continues the schedule where it stopped.
+    assert_eq!(scheduler.current_step(), 5);
+    let json = serde_json::to_string(&scheduler)?;
+    let mut resumed: LrScheduler<StepDecay> = serde_json::from_str(&json)?;
+    assert_eq!(resumed, scheduler);
+    resumed.step(&mut sgd);
+    assert_eq!(sgd.learning_rate(), 0.025);
+    assert_eq!(resumed.lr(), 0.0125);
+    Ok(())
+}
+
+#[test]
+fn reduce_lr_on_plateau() -> Result<()> {
+    let mut sgd = SGD::empty(0.1)?;
+    let params = ParamsReduceLrOnPlateau {
+        patience: 1,
+        factor: 0.5,
+        cooldown: 1,
+        min_lr: 0.02,
+        ..Default::default()
+    };
+    let mut scheduler = ReduceLrOnPlateau::new(0.1, params);
+    let mut seen = vec![];
+    for loss in [
+        1.0, 0.9, 0.9, 0.95, 0.9, 0.91, 0.92, 0.8, 0.85, 0.85, 0.85, 0.85, 0.85,
+    ] {
+        scheduler.step(loss, &mut sgd);
+        seen.push(sgd.learning_rate());
+    }
+    assert_eq!(
+        seen,
+        [0.1, 0.1, 0.1, 0.05, 0.05, 0.05, 0.025, 0.025, 0.025, 0.02, 0.02, 0.02, 0.02]
+    );
+    assert_eq!(scheduler.best(), Some(0.8));
+
+    let json = serde_json::to_string(&scheduler)?;
+    let resumed: ReduceLrOnPlateau = serde_json::from_str(&json)?;
+    assert_eq!(resumed, scheduler);
+
+    // In max mode, improvements are increases of the metric.
+    let params = ParamsReduceLrOnPlateau {
+        mode: PlateauMode::Max,
+        patience: 0,
+        ..Default::default()
+    };
+    let mut scheduler = ReduceLrOnPlateau::new(1.0, params);
+    for accuracy in [0.5, 0.6, 0.7] {
+        scheduler.step(accuracy, &mut sgd);
+    }
+    assert_eq!(sgd.learning_rate(), 1.0);
+    scheduler.step(0.7, &mut sgd);
+    assert!((sgd.learning_rate() - 0.1).abs() < 1e-12);
+    Ok(())
+}
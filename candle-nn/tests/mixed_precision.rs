@@ -0,0 +1,92 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::{DType, Device, Tensor, Var};
+use candle_nn::{GradScaler, MixedPrecision, Optimizer, ParamsGradScaler, SGD};
+
+fn f16_var(v: f32) -> Result<Var> {
+    let t = Tensor::new(&[v], &Device::Cpu)?.to_dtype(DType::F16)?;
+    Ok(Var::from_tensor(&t)?)
+}
+
+fn f32_value(var: &Var) -> Result<f32> {
+    Ok(var.to_dtype(DType::F32)?.to_vec1::<f32>()?[0])
+}
+
+#[test]
+fn grad_scaler() -> Result<()> {
+    let params = ParamsGradScaler {
+        init_scale: 1024.,
+        growth_interval: 2,
+        ..Default::default()
+    };
+    let mut scaler = GradScaler::new(params);
+    let mut scales = vec![];
+    for overflow in [true, false, false, false, true, false] {
+        scaler.update(overflow);
+        scales.push(scaler.scale());
+    }
+    assert_eq!(scales, [512., 512., 1024., 1024., 512., 512.]);
+
+    let x = f16_var(3.)?;
+    let loss = scaler.scale_loss(&x.as_tensor().sum_all()?)?;
+    let mut grads = loss.backward()?;
+    assert!(scaler.unscale(&mut grads, std::slice::from_ref(&x))?);
+    let grad = grads.get(&x).unwrap();
+    assert_eq!(grad.dtype(), DType::F32);
+    assert_eq!(grad.to_vec1::<f32>()?, [1.]);
+    Ok(())
+}
+
+#[test]
+fn mixed_precision_underflow() -> Result<()> {
+    // The gradients of this loss are about 4e-9, they underflow to zero in F16 unless the loss is
+    // scaled.
+    let train = |params| -> Result<(f32, f32)> {
+        let x = f16_var(2.)?;
+        let mut opt: MixedPrecision<SGD> = MixedPrecision::new(vec![x.clone()], 1e8, params)?;
+        for _step in 0..5 {
+            let xs = x.as_tensor().to_dtype(DType::F32)?;
+            let loss = (xs.sqr()? * 1e-9)?.sum_all()?;
+            assert!(opt.backward_step(&loss)?);
+        }
+        Ok((f32_value(&x)?, opt.master_vars()[0].to_vec1::<f32>()?[0]))
+    };
+    assert_eq!(train(ParamsGradScaler::no_scaling())?, (2., 2.));
+    let (x, master_x) = train(ParamsGradScaler::default())?;
+    // Each step multiplies x by 0.8, 2 * 0.8^5 = 0.65536.
+    assert!((master_x - 0.65536).abs() < 1e-3, "{master_x}");
+    assert!((x - master_x).abs() < 1e-3, "{x} {master_x}");
+    Ok(())
+}
+
+#[test]
+fn mixed_precision_overflow() -> Result<()> {
+    // With a scale of 2^20 the gradient of x^2 at x = 2 overflows in F16, the steps get skipped
+    // until the scale has been reduced to 2^13.
+    let x = f16_var(2.)?;
+    let params = ParamsGradScaler {
+        init_scale: 1048576.,
+        ..Default::default()
+    };
+    let mut opt: MixedPrecision<SGD> = MixedPrecision::new(vec![x.clone()], 0.1, params)?;
+    let mut stepped = vec![];
+    for _step in 0..10 {
+        let loss = x.as_tensor().to_dtype(DType::F32)?.sqr()?.sum_all()?;
+        stepped.push(opt.backward_step(&loss)?);
+    }
+    assert_eq!(
+        stepped,
+        [false, false, false, false, false, false, false, true, true, true]
+    );
+    assert_eq!(opt.skipped_steps(), 7);
+    assert_eq!(opt.scaler().scale(), 8192.);
+    // The three applied steps multiply x by 0.8.
+    assert!((f32_value(&x)? - 1.024).abs() < 1e-3);
+    assert_eq!(opt.learning_rate(), opt.optimizer().learning_rate());
+    Ok(())
+}
@@ -54,6 +54,22 @@ fn softmax() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn softmax_last_dim_grad() -> Result<()> {
+    let device = &Device::Cpu;
+    let data = &[[[3f32, 1., 4.], [1., 5., 9.]], [[2., 1., 7.], [8., 2., 8.]]];
+    let xs = candle::Var::new(data, device)?;
+    let weights = Tensor::new(&[1f32, -2., 3.], device)?;
+    let loss = |ys: Tensor| ys.broadcast_mul(&weights)?.sum_all();
+    let grads = loss(candle_nn::ops::softmax_last_dim(&xs)?)?.backward()?;
+    let expected = loss(candle_nn::ops::softmax(&xs, 2)?)?.backward()?;
+    assert_eq!(
+        to_vec3_round(grads.get(&xs).unwrap(), 4)?,
+        to_vec3_round(expected.get(&xs).unwrap(), 4)?
+    );
+    Ok(())
+}
+
 #[test]
 fn softmax_numerical_stability() -> Result<()> {
     let dev = &Device::Cpu;
@@ -4,11 +4,14 @@ extern crate intel_mkl_src;
 #[cfg(feature = "accelerate")]
 extern crate accelerate_src;
 
-use candle::test_utils::{to_vec0_round, to_vec2_round};
+use candle::test_utils::{to_vec0_round, to_vec1_round, to_vec2_round};
 
 use anyhow::Result;
 use candle::{DType, Device, Tensor, Var};
-use candle_nn::{AdamW, Linear, Module, Optimizer, ParamsAdamW, SGD};
+use candle_nn::{
+    Adafactor, Adagrad, Adam, AdamW, Linear, Lion, Module, Optimizer, ParamsAdafactor,
+    ParamsAdagrad, ParamsAdam, ParamsAdamW, ParamsLion, ParamsRMSprop, ParamsSGD, RMSprop, SGD,
+};
 
 #[test]
 fn sgd_optim() -> Result<()> {
@@ -158,3 +161,497 @@ fn adamw_linear_regression_varmap() -> Result<()> {
     assert_eq!(to_vec0_round(lin.bias().unwrap(), 4)?, 1.);
     Ok(())
 }
+
+// Runs the linear regression from the tests above, starting from zero weights, and returns the
+// rounded weight and bias.
+fn linear_regression<O: Optimizer>(
+    opt: impl FnOnce(Vec<Var>) -> candle::Result<O>,
+    steps: usize,
+) -> Result<(Vec<Vec<f32>>, f32)> {
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+
+    let w = Var::new(&[[0f32, 0.]], &Device::Cpu)?;
+    let b = Var::new(0f32, &Device::Cpu)?;
+    let mut opt = opt(vec![w.clone(), b.clone()])?;
+    let lin = Linear::new(w.as_tensor().clone(), Some(b.as_tensor().clone()));
+    for _step in 0..steps {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        opt.backward_step(&loss)?;
+    }
+    Ok((
+        to_vec2_round(w.as_tensor(), 4)?,
+        to_vec0_round(b.as_tensor(), 4)?,
+    ))
+}
+
+/* The expected values in the tests below are the ones printed by the following script, it uses
+   `torch.optim` (torch >= 2.5 for Adafactor) and `lion-pytorch` for Lion. Rerun it to regenerate
+   the constants.
+import torch
+from torch import optim
+from lion_pytorch import Lion
+
+w_gen = torch.tensor([[3., 1.]])
+b_gen = torch.tensor([-2.])
+
+sample_xs = torch.tensor([[2., 1.], [7., 4.], [-4., 12.], [5., 8.]])
+sample_ys = sample_xs.matmul(w_gen.t()) + b_gen
+
+def linear_regression(name, make_optimizer, steps):
+    m = torch.nn.Linear(2, 1)
+    with torch.no_grad():
+        m.weight.zero_()
+        m.bias.zero_()
+    optimizer = make_optimizer(m.parameters())
+    for _step in range(steps):
+        optimizer.zero_grad()
+        ys = m(sample_xs)
+        loss = ((ys - sample_ys)**2).sum()
+        loss.backward()
+        optimizer.step()
+    print(name, m.weight.detach().numpy().round(4), m.bias.detach().numpy().round(4))
+
+linear_regression("sgd", lambda ps: optim.SGD(ps, lr=0.001, momentum=0.9), 100)
+linear_regression("sgd", lambda ps: optim.SGD(ps, lr=0.001, momentum=0.9, nesterov=True), 100)
+linear_regression(
+    "sgd",
+    lambda ps: optim.SGD(ps, lr=0.002, momentum=0.5, dampening=0.1, weight_decay=0.1),
+    100,
+)
+linear_regression("adam", lambda ps: optim.Adam(ps, lr=0.1), 100)
+linear_regression("adam", lambda ps: optim.Adam(ps, lr=0.1, weight_decay=0.1), 100)
+linear_regression("rmsprop", lambda ps: optim.RMSprop(ps, lr=0.1), 100)
+linear_regression(
+    "rmsprop",
+    lambda ps: optim.RMSprop(ps, lr=0.01, momentum=0.9, centered=True, weight_decay=0.01),
+    100,
+)
+linear_regression("adagrad", lambda ps: optim.Adagrad(ps, lr=0.1), 100)
+linear_regression(
+    "adagrad",
+    lambda ps: optim.Adagrad(
+        ps, lr=0.5, lr_decay=0.01, weight_decay=0.01, initial_accumulator_value=0.1
+    ),
+    100,
+)
+linear_regression("lion", lambda ps: Lion(ps, lr=0.02), 300)
+linear_regression("lion", lambda ps: Lion(ps, lr=0.02, weight_decay=0.5), 300)
+linear_regression("adafactor", lambda ps: optim.Adafactor(ps, lr=0.1), 100)
+linear_regression("adafactor", lambda ps: optim.Adafactor(ps, lr=0.1, weight_decay=0.01), 200)
+*/
+#[test]
+fn sgd_momentum_linear_regression() -> Result<()> {
+    let params = ParamsSGD {
+        lr: 0.001,
+        momentum: 0.9,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| SGD::new_with_params(vars, params), 100)?;
+    assert_eq!(w, &[[2.9299, 0.9352]]);
+    assert_eq!(b, -1.3058);
+
+    let params = ParamsSGD {
+        lr: 0.001,
+        momentum: 0.9,
+        nesterov: true,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| SGD::new_with_params(vars, params), 100)?;
+    assert_eq!(w, &[[2.9424, 0.9281]]);
+    assert_eq!(b, -1.304);
+
+    let params = ParamsSGD {
+        lr: 0.002,
+        momentum: 0.5,
+        dampening: 0.1,
+        weight_decay: 0.1,
+        nesterov: false,
+    };
+    let (w, b) = linear_regression(|vars| SGD::new_with_params(vars, params), 100)?;
+    assert_eq!(w, &[[2.8723, 0.8424]]);
+    assert_eq!(b, -0.4749);
+
+    let params = ParamsSGD {
+        momentum: 0.9,
+        dampening: 0.1,
+        nesterov: true,
+        ..Default::default()
+    };
+    assert!(SGD::new_with_params(vec![], params).is_err());
+    Ok(())
+}
+
+#[test]
+fn adam_linear_regression() -> Result<()> {
+    let (w, b) = linear_regression(|vars| Adam::new_lr(vars, 0.1), 100)?;
+    assert_eq!(w, &[[2.7518, 0.7139]]);
+    assert_eq!(b, 0.7406);
+
+    let params = ParamsAdam {
+        lr: 0.1,
+        weight_decay: 0.1,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| Adam::new(vars, params), 100)?;
+    assert_eq!(w, &[[2.7524, 0.7164]]);
+    assert_eq!(b, 0.7152);
+    Ok(())
+}
+
+#[test]
+fn rmsprop_linear_regression() -> Result<()> {
+    let params = ParamsRMSprop {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| RMSprop::new(vars, params), 100)?;
+    assert_eq!(w, &[[2.9213, 0.9045]]);
+    assert_eq!(b, -1.0817);
+
+    let params = ParamsRMSprop {
+        lr: 0.01,
+        momentum: 0.9,
+        centered: true,
+        weight_decay: 0.01,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| RMSprop::new(vars, params), 100)?;
+    assert_eq!(w, &[[2.8258, 0.8072]]);
+    assert_eq!(b, -0.0855);
+    Ok(())
+}
+
+#[test]
+fn adagrad_linear_regression() -> Result<()> {
+    let params = ParamsAdagrad {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| Adagrad::new(vars, params), 100)?;
+    assert_eq!(w, &[[1.5401, 0.8028]]);
+    assert_eq!(b, 1.2208);
+
+    let params = ParamsAdagrad {
+        lr: 0.5,
+        lr_decay: 0.01,
+        weight_decay: 0.01,
+        initial_accumulator_value: 0.1,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| Adagrad::new(vars, params), 100)?;
+    assert_eq!(w, &[[2.7381, 0.6917]]);
+    assert_eq!(b, 0.9779);
+    Ok(())
+}
+
+#[test]
+fn lion_linear_regression() -> Result<()> {
+    let params = ParamsLion {
+        lr: 0.02,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| Lion::new(vars, params), 300)?;
+    assert_eq!(w, &[[2.56, 0.72]]);
+    assert_eq!(b, 0.84);
+
+    let params = ParamsLion {
+        lr: 0.02,
+        weight_decay: 0.5,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| Lion::new(vars, params), 300)?;
+    assert_eq!(w, &[[1.9019, 0.6743]]);
+    assert_eq!(b, 1.9019);
+    Ok(())
+}
+
+#[test]
+fn adafactor_linear_regression() -> Result<()> {
+    // The weight uses the factored second moment, the bias the full one.
+    let params = ParamsAdafactor {
+        lr: 0.1,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| Adafactor::new(vars, params), 100)?;
+    assert_eq!(w, &[[1.7267, 0.792]]);
+    assert_eq!(b, 1.175);
+
+    let params = ParamsAdafactor {
+        lr: 0.1,
+        weight_decay: 0.01,
+        ..Default::default()
+    };
+    let (w, b) = linear_regression(|vars| Adafactor::new(vars, params), 200)?;
+    assert_eq!(w, &[[2.7505, 0.7158]]);
+    assert_eq!(b, 0.7533);
+    Ok(())
+}
+
+// Creates the linear regression variables in a VarMap, or retrieves them if they already exist.
+fn varmap_linear(var_map: &candle_nn::VarMap) -> Result<Linear> {
+    use candle_nn::Init::Const;
+    let w = var_map.get((1, 2), "lin.weight", Const(0.), DType::F32, &Device::Cpu)?;
+    let b = var_map.get((), "lin.bias", Const(0.), DType::F32, &Device::Cpu)?;
+    Ok(Linear::new(w, Some(b)))
+}
+
+fn varmap_linear_regression<O: Optimizer>(
+    var_map: &candle_nn::VarMap,
+    opt: &mut O,
+    steps: usize,
+) -> Result<()> {
+    let w_gen = Tensor::new(&[[3f32, 1.]], &Device::Cpu)?;
+    let b_gen = Tensor::new(-2f32, &Device::Cpu)?;
+    let gen = Linear::new(w_gen, Some(b_gen));
+    let sample_xs = Tensor::new(&[[2f32, 1.], [7., 4.], [-4., 12.], [5., 8.]], &Device::Cpu)?;
+    let sample_ys = gen.forward(&sample_xs)?;
+    let lin = varmap_linear(var_map)?;
+    for _step in 0..steps {
+        let ys = lin.forward(&sample_xs)?;
+        let loss = ys.sub(&sample_ys)?.sqr()?.sum_all()?;
+        opt.backward_step(&loss)?;
+    }
+    Ok(())
+}
+
+fn varmap_values(var_map: &candle_nn::VarMap) -> Result<(Vec<Vec<f32>>, f32)> {
+    let data = var_map.data().lock().unwrap();
+    Ok((
+        data["lin.weight"].to_vec2::<f32>()?,
+        data["lin.bias"].to_scalar::<f32>()?,
+    ))
+}
+
+fn resume_training<O: Optimizer>(config: impl Fn() -> O::Config, name: &str) -> Result<()> {
+    use candle_nn::VarMap;
+    let dir = std::env::temp_dir();
+    let vars_path = dir.join(format!("candle-optim-{name}-vars.safetensors"));
+    let state_path = dir.join(format!("candle-optim-{name}-state.safetensors"));
+
+    // Reference run, 20 steps without interruption.
+    let var_map = VarMap::new();
+    varmap_linear(&var_map)?;
+    let mut opt = O::from_param_groups(var_map.param_groups(&[]), config())?;
+    varmap_linear_regression(&var_map, &mut opt, 20)?;
+    let expected = varmap_values(&var_map)?;
+
+    // Same run stopped after 10 steps, the variables and optimizer state are saved to disk and
+    // restored in a new VarMap and optimizer.
+    let var_map = VarMap::new();
+    varmap_linear(&var_map)?;
+    let mut opt = O::from_param_groups(var_map.param_groups(&[]), config())?;
+    varmap_linear_regression(&var_map, &mut opt, 10)?;
+    var_map.save(&vars_path)?;
+    opt.save_state(&state_path)?;
+
+    let mut var_map = VarMap::new();
+    varmap_linear(&var_map)?;
+    var_map.load(&vars_path)?;
+    let mut opt = O::from_param_groups(var_map.param_groups(&[]), config())?;
+    opt.load_state(&state_path)?;
+    varmap_linear_regression(&var_map, &mut opt, 10)?;
+    assert_eq!(varmap_values(&var_map)?, expected, "{name}");
+
+    // Without the optimizer state, the trajectory differs.
+    var_map.load(&vars_path)?;
+    let mut opt = O::from_param_groups(var_map.param_groups(&[]), config())?;
+    varmap_linear_regression(&var_map, &mut opt, 10)?;
+    assert_ne!(varmap_values(&var_map)?, expected, "{name}");
+
+    std::fs::remove_file(vars_path)?;
+    std::fs::remove_file(state_path)?;
+    Ok(())
+}
+
+#[test]
+fn optimizer_state_dict() -> Result<()> {
+    resume_training::<AdamW>(
+        || ParamsAdamW {
+            lr: 0.1,
+            ..Default::default()
+        },
+        "adamw",
+    )?;
+    resume_training::<Adam>(
+        || ParamsAdam {
+            lr: 0.1,
+            ..Default::default()
+        },
+        "adam",
+    )?;
+    resume_training::<RMSprop>(
+        || ParamsRMSprop {
+            lr: 0.01,
+            momentum: 0.9,
+            centered: true,
+            ..Default::default()
+        },
+        "rmsprop",
+    )?;
+    resume_training::<Adagrad>(
+        || ParamsAdagrad {
+            lr: 0.1,
+            lr_decay: 0.1,
+            ..Default::default()
+        },
+        "adagrad",
+    )?;
+    resume_training::<Lion>(
+        || ParamsLion {
+            lr: 0.3,
+            ..Default::default()
+        },
+        "lion",
+    )?;
+    resume_training::<Adafactor>(
+        || ParamsAdafactor {
+            lr: 0.1,
+            ..Default::default()
+        },
+        "adafactor",
+    )?;
+
+    // The SGD momentum buffers only exist after the first step.
+    let w = Var::new(&[[1f32, 2.]], &Device::Cpu)?;
+    let params = ParamsSGD {
+        lr: 0.1,
+        momentum: 0.9,
+        ..Default::default()
+    };
+    let mut sgd = SGD::new_with_params(vec![w.clone()], params.clone())?;
+    assert!(sgd.state_dict()?.is_empty());
+    sgd.backward_step(&w.as_tensor().sum_all()?)?;
+    let state_dict = sgd.state_dict()?;
+    assert_eq!(
+        to_vec2_round(&state_dict["0.momentum_buffer"], 4)?,
+        &[[1., 1.]]
+    );
+    let mut sgd = SGD::new_with_params(vec![w.clone()], params)?;
+    sgd.load_state_dict(&state_dict)?;
+    sgd.backward_step(&w.as_tensor().sum_all()?)?;
+    assert_eq!(to_vec2_round(w.as_tensor(), 4)?, &[[0.71, 1.71]]);
+
+    // Mismatched shapes or missing entries are reported.
+    let mut opt = AdamW::new(vec![w.clone()], Default::default())?;
+    let mut state_dict = opt.state_dict()?;
+    assert!(opt.load_state_dict(&state_dict).is_ok());
+    state_dict.insert(
+        "0.first_moment".to_string(),
+        Tensor::zeros(3, DType::F32, &Device::Cpu)?,
+    );
+    assert!(opt.load_state_dict(&state_dict).is_err());
+    state_dict.remove("0.first_moment");
+    assert!(opt.load_state_dict(&state_dict).is_err());
+    Ok(())
+}
+
+#[test]
+fn optimizer_param_groups() -> Result<()> {
+    use candle_nn::{ParamGroupConfig, VarMap};
+    let var_map = VarMap::new();
+    varmap_linear(&var_map)?;
+    var_map.get(
+        3,
+        "norm.weight",
+        candle_nn::Init::Const(1.),
+        DType::F32,
+        &Device::Cpu,
+    )?;
+
+    let groups = var_map.param_groups(&[
+        ("*.bias", ParamGroupConfig::no_weight_decay()),
+        ("norm.*", ParamGroupConfig::no_weight_decay()),
+        ("missing.*", ParamGroupConfig::default()),
+    ]);
+    let names = groups
+        .iter()
+        .map(|g| g.vars.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [vec!["lin.bias"], vec!["norm.weight"], vec!["lin.weight"]]
+    );
+    assert_eq!(groups[0].config.weight_decay, Some(0.));
+    assert_eq!(groups[2].config, ParamGroupConfig::default());
+    // all_vars also sorts the variables by name so that index based state dicts are stable.
+    let dims = var_map
+        .all_vars()
+        .iter()
+        .map(|v| v.dims().to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(dims, [vec![], vec![1, 2], vec![3]]);
+
+    // The norm weights get no gradient, they are only subject to weight decay which is disabled.
+    let params = ParamsAdamW {
+        lr: 0.1,
+        weight_decay: 0.5,
+        ..Default::default()
+    };
+    let mut opt = AdamW::from_param_groups(groups, params.clone())?;
+    varmap_linear_regression(&var_map, &mut opt, 10)?;
+    let norm = var_map.data().lock().unwrap()["norm.weight"].to_vec1::<f32>()?;
+    assert_eq!(norm, [1., 1., 1.]);
+
+    // A zero learning rate scale freezes the weights, e.g. to only train some adapters.
+    let var_map = VarMap::new();
+    varmap_linear(&var_map)?;
+    let frozen = ParamGroupConfig {
+        lr_scale: 0.,
+        ..Default::default()
+    };
+    let groups = var_map.param_groups(&[("*weight", frozen)]);
+    let mut opt = AdamW::from_param_groups(groups, params.clone())?;
+    varmap_linear_regression(&var_map, &mut opt, 10)?;
+    let (w, b) = varmap_values(&var_map)?;
+    assert_eq!(w, &[[0., 0.]]);
+    assert!(b > 0.5, "{b}");
+
+    let groups = var_map.param_groups(&[("*weight", frozen)]);
+    let mut sgd = SGD::from_param_groups(groups, 0.01)?;
+    varmap_linear_regression(&var_map, &mut sgd, 1)?;
+    let (w, _b) = varmap_values(&var_map)?;
+    assert_eq!(w, &[[0., 0.]]);
+    Ok(())
+}
+
+#[test]
+fn optimizer_clip_grad_norm() -> Result<()> {
+    let x = Var::new(&[3f32, 4.], &Device::Cpu)?;
+    let mut sgd = SGD::new(vec![x.clone()], 0.1)?;
+    assert_eq!(sgd.vars().len(), 1);
+    // The gradient is [3, 4] with a norm of 5, it gets rescaled to [0.6, 0.8].
+    let loss = (x.as_tensor().sqr()? * 0.5)?.sum_all()?;
+    let norm = sgd.backward_step_clip_grad_norm(&loss, 1.)?;
+    assert_eq!(norm, 5.);
+    assert_eq!(to_vec1_round(x.as_tensor(), 4)?, [2.94, 3.92]);
+
+    // The step is skipped on non-finite gradients.
+    let loss = (x.as_tensor() * f64::NAN)?.sum_all()?;
+    let norm = sgd.backward_step_clip_grad_norm(&loss, 1.)?;
+    assert!(norm.is_nan());
+    assert_eq!(to_vec1_round(x.as_tensor(), 4)?, [2.94, 3.92]);
+
+    // Optimizers that do not expose their variables cannot clip the gradients.
+    struct NoVars;
+    impl Optimizer for NoVars {
+        type Config = ();
+        fn new(_vars: Vec<Var>, _config: ()) -> candle::Result<Self> {
+            Ok(Self)
+        }
+        fn step(&mut self, _grads: &candle::backprop::GradStore) -> candle::Result<()> {
+            Ok(())
+        }
+        fn learning_rate(&self) -> f64 {
+            0.
+        }
+        fn set_learning_rate(&mut self, _lr: f64) {}
+    }
+    let loss = (x.as_tensor().sqr()? * 0.5)?.sum_all()?;
+    assert!(NoVars.backward_step_clip_grad_norm(&loss, 1.).is_err());
+    Ok(())
+}
@@ -5,7 +5,8 @@ extern crate intel_mkl_src;
 extern crate accelerate_src;
 
 use candle::{test_utils::to_vec2_round, DType, Device, Result, Tensor};
-use candle_nn::RNN;
+use candle_nn::rnn::Direction;
+use candle_nn::{RNNLayer, StackedRNNConfig, VarBuilder, VarMap, RNN};
 
 /* The following test can be verified against PyTorch using the following snippet.
 import torch
@@ -99,3 +100,165 @@ fn gru() -> Result<()> {
     assert_eq!(to_vec2_round(h, 4)?, &[[0.0579, 0.8836, -0.9991]]);
     Ok(())
 }
+
+fn max_diff(a: &Tensor, b: &Tensor) -> Result<f32> {
+    (a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()
+}
+
+// Reverses a (batch, seq_len, features) tensor along the time dimension.
+fn flip_time(xs: &Tensor) -> Result<Tensor> {
+    let seq_len = xs.dim(1)? as u32;
+    let indexes = Tensor::new((0..seq_len).rev().collect::<Vec<_>>(), xs.device())?;
+    xs.contiguous()?.index_select(&indexes, 1)
+}
+
+fn sorted_names(varmap: &VarMap) -> Vec<String> {
+    let mut names = varmap
+        .data()
+        .lock()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn stacked_weight_names() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, cpu);
+    let config = StackedRNNConfig {
+        num_layers: 2,
+        bidirectional: true,
+        dropout: 0.,
+    };
+    let lstm = candle_nn::lstm_stacked(3, 4, Default::default(), config, vb)?;
+    assert_eq!(lstm.num_layers(), 2);
+    assert_eq!(lstm.num_directions(), 2);
+    let names = sorted_names(&varmap);
+    assert_eq!(names.len(), 16);
+    for name in ["weight_ih_l0", "bias_hh_l0_reverse", "weight_hh_l1_reverse"] {
+        assert!(names.iter().any(|n| n == name), "{name}")
+    }
+    // The second layer gets the outputs of both directions as input.
+    let w_ih = varmap.data().lock().unwrap()["weight_ih_l1"].clone();
+    assert_eq!(w_ih.dims(), &[16, 8]);
+
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, cpu);
+    let config = StackedRNNConfig {
+        num_layers: 3,
+        ..Default::default()
+    };
+    candle_nn::gru_stacked(3, 4, candle_nn::GRUConfig::default_no_bias(), config, vb)?;
+    assert_eq!(
+        sorted_names(&varmap),
+        [
+            "weight_hh_l0",
+            "weight_hh_l1",
+            "weight_hh_l2",
+            "weight_ih_l0",
+            "weight_ih_l1",
+            "weight_ih_l2"
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn lstm_fused_seq() -> Result<()> {
+    // Projecting all the timesteps at once gives the same states as stepping through the input.
+    let cpu = &Device::Cpu;
+    let vb = VarBuilder::from_varmap(&VarMap::new(), DType::F32, cpu);
+    let lstm = candle_nn::lstm(3, 4, Default::default(), vb)?;
+    let xs = Tensor::randn(0f32, 1., (2, 5, 3), cpu)?;
+    let states = lstm.seq(&xs)?;
+    let mut state = lstm.zero_state(2)?;
+    for (t, expected) in states.iter().enumerate() {
+        state = lstm.step(&xs.narrow(1, t, 1)?.squeeze(1)?, &state)?;
+        assert!(max_diff(state.h(), expected.h())? < 1e-6);
+        assert!(max_diff(state.c(), expected.c())? < 1e-6);
+    }
+    let (ys, last) = lstm.seq_with_lengths(&xs, &lstm.zero_state(2)?, None)?;
+    assert!(max_diff(&ys, &lstm.states_to_tensor(&states)?)? < 1e-6);
+    assert!(max_diff(last.h(), state.h())? < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn bidirectional_lstm() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, cpu);
+    let config = StackedRNNConfig {
+        bidirectional: true,
+        ..Default::default()
+    };
+    let bilstm = candle_nn::lstm_stacked(3, 4, Default::default(), config, vb)?;
+    let xs = Tensor::randn(0f32, 1., (2, 6, 3), cpu)?;
+    let (ys, states) = bilstm.forward(&xs, None)?;
+    assert_eq!(ys.dims(), &[2, 6, 8]);
+    assert_eq!(states.len(), 2);
+
+    // The backward direction is a forward LSTM with the reverse weights applied to the flipped
+    // sequence.
+    let tensors = varmap
+        .data()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(name, var)| {
+            let name = name.strip_suffix("_reverse")?;
+            Some((name.to_string(), var.as_tensor().clone()))
+        })
+        .collect();
+    let vb = VarBuilder::from_tensors(tensors, DType::F32, cpu);
+    let lstm = candle_nn::lstm(3, 4, Default::default(), vb)?;
+    let states_rev = lstm.seq(&flip_time(&xs)?)?;
+    let expected = flip_time(&lstm.states_to_tensor(&states_rev)?)?;
+    assert!(max_diff(&ys.narrow(2, 4, 4)?, &expected)? < 1e-6);
+    assert!(max_diff(states[1].h(), states_rev[5].h())? < 1e-6);
+    assert_eq!(bilstm.layers()[0][1].direction(), Direction::Backward);
+    Ok(())
+}
+
+#[test]
+fn stacked_gru_lengths() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let vb = VarBuilder::from_varmap(&VarMap::new(), DType::F32, cpu);
+    let config = StackedRNNConfig {
+        num_layers: 2,
+        bidirectional: true,
+        dropout: 0.5,
+    };
+    let gru = candle_nn::gru_stacked(3, 4, Default::default(), config, vb)?;
+    let xs = Tensor::randn(0f32, 1., (2, 5, 3), cpu)?;
+    let (ys, states) = gru.forward(&xs, Some(&[5, 3]))?;
+    assert_eq!(ys.dims(), &[2, 5, 8]);
+    assert_eq!(states.len(), 4);
+
+    // The padded sequence gives the same results as running it on its own.
+    let (ys1, states1) = gru.forward(&xs.narrow(0, 1, 1)?.narrow(1, 0, 3)?, None)?;
+    assert!(max_diff(&ys.narrow(0, 1, 1)?.narrow(1, 0, 3)?, &ys1)? < 1e-6);
+    for (state, state1) in states.iter().zip(states1.iter()) {
+        assert!(max_diff(&state.h().narrow(0, 1, 1)?, state1.h())? < 1e-6);
+    }
+    assert_eq!(
+        ys.narrow(0, 1, 1)?
+            .narrow(1, 3, 2)?
+            .abs()?
+            .sum_all()?
+            .to_scalar::<f32>()?,
+        0.
+    );
+    let (ys0, _) = gru.forward(&xs.narrow(0, 0, 1)?, None)?;
+    assert!(max_diff(&ys.narrow(0, 0, 1)?, &ys0)? < 1e-6);
+
+    // Dropout is only applied between the layers when training.
+    let (ys_train, _) = gru.forward_t(&xs, Some(&[5, 3]), true)?;
+    assert!(max_diff(&ys, &ys_train)? > 0.);
+    assert!(gru.forward(&xs, Some(&[6, 3])).is_err());
+    Ok(())
+}
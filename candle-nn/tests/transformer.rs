@@ -0,0 +1,138 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::{DType, Device, Tensor};
+use candle_nn::attention::{causal_mask, KvCache, PositionEncoding};
+use candle_nn::{
+    transformer_decoder_layer, transformer_encoder_layer, Optimizer, TransformerLayerConfig,
+    VarBuilder, VarMap, SGD,
+};
+
+fn max_diff(a: &Tensor, b: &Tensor) -> Result<f32> {
+    Ok((a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?)
+}
+
+fn sorted_names(varmap: &VarMap) -> Vec<String> {
+    let mut names = varmap
+        .data()
+        .lock()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn transformer_weight_names() -> Result<()> {
+    let dev = &Device::Cpu;
+    let config = TransformerLayerConfig {
+        dim_feedforward: 32,
+        ..TransformerLayerConfig::new(2)
+    };
+    // These are the state dict keys of PyTorch's TransformerEncoderLayer and
+    // TransformerDecoderLayer.
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    transformer_encoder_layer(8, config, vb)?;
+    assert_eq!(
+        sorted_names(&varmap),
+        [
+            "linear1.bias",
+            "linear1.weight",
+            "linear2.bias",
+            "linear2.weight",
+            "norm1.bias",
+            "norm1.weight",
+            "norm2.bias",
+            "norm2.weight",
+            "self_attn.in_proj_bias",
+            "self_attn.in_proj_weight",
+            "self_attn.out_proj.bias",
+            "self_attn.out_proj.weight",
+        ]
+    );
+
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    transformer_decoder_layer(8, config, vb)?;
+    let names = sorted_names(&varmap);
+    assert_eq!(names.len(), 18);
+    for name in [
+        "multihead_attn.in_proj_weight",
+        "multihead_attn.out_proj.weight",
+        "norm3.weight",
+    ] {
+        assert!(names.iter().any(|n| n == name), "{name}")
+    }
+    Ok(())
+}
+
+#[test]
+fn transformer_decoder_kv_cache() -> Result<()> {
+    let dev = &Device::Cpu;
+    let mut config = TransformerLayerConfig {
+        dim_feedforward: 32,
+        norm_first: true,
+        ..TransformerLayerConfig::new(4)
+    };
+    config.attention.num_kv_heads = 2;
+    config.attention.position = PositionEncoding::Rotary {
+        theta: 10000.,
+        max_seq_len: 16,
+    };
+    let vb = VarBuilder::from_varmap(&VarMap::new(), DType::F32, dev);
+    let layer = transformer_decoder_layer(16, config, vb)?;
+    let tgt = Tensor::randn(0f32, 1., (2, 5, 16), dev)?;
+    let memory = Tensor::randn(0f32, 1., (2, 7, 16), dev)?;
+    let mask = causal_mask(5, 5, DType::F32, dev)?;
+    let expected = layer.forward(&tgt, &memory, Some(&mask), None, false)?;
+
+    let mut cache = KvCache::new();
+    let mut ys =
+        vec![layer.forward_with_cache(&tgt.narrow(1, 0, 3)?, &memory, None, &mut cache)?];
+    for pos in 3..5 {
+        let tgt = tgt.narrow(1, pos, 1)?;
+        ys.push(layer.forward_with_cache(&tgt, &memory, None, &mut cache)?)
+    }
+    let ys = Tensor::cat(&ys, 1)?;
+    assert!(max_diff(&ys, &expected)? < 1e-5);
+    Ok(())
+}
+
+#[test]
+fn transformer_encoder_training() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let config = TransformerLayerConfig {
+        dim_feedforward: 32,
+        dropout: 0.,
+        ..TransformerLayerConfig::new(2)
+    };
+    let layer = transformer_encoder_layer(8, config, vb)?;
+    let xs = Tensor::randn(0f32, 1., (4, 3, 8), dev)?;
+    let target = Tensor::randn(0f32, 1., (4, 3, 8), dev)?;
+    let mut sgd = SGD::new(varmap.all_vars(), 0.05)?;
+    let loss = |layer: &candle_nn::TransformerEncoderLayer| -> Result<Tensor> {
+        Ok((layer.forward(&xs, None, true)? - &target)?
+            .sqr()?
+            .mean_all()?)
+    };
+    let initial_loss = loss(&layer)?.to_scalar::<f32>()?;
+    for _step in 0..20 {
+        let loss = loss(&layer)?;
+        let grads = loss.backward()?;
+        // The gradients flow through the attention softmax to the input projections.
+        let in_proj = varmap.data().lock().unwrap()["self_attn.in_proj_weight"].clone();
+        assert!(grads.get(&in_proj).is_some());
+        sgd.step(&grads)?;
+    }
+    assert!(loss(&layer)?.to_scalar::<f32>()? < initial_loss);
+    Ok(())
+}
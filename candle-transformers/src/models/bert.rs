@@ -13,6 +13,7 @@ pub enum HiddenAct {
     Relu,
 }
 
+#[derive(Clone)]
 struct HiddenActLayer {
     act: HiddenAct,
     span: tracing::Span,
@@ -112,6 +113,7 @@ impl Config {
     }
 }
 
+#[derive(Clone)]
 struct Dropout {
     #[allow(dead_code)]
     pr: f64,
@@ -190,6 +192,7 @@ impl BertEmbeddings {
     }
 }
 
+#[derive(Clone)]
 struct BertSelfAttention {
     query: Linear,
     key: Linear,
@@ -258,6 +261,7 @@ impl Module for BertSelfAttention {
     }
 }
 
+#[derive(Clone)]
 struct BertSelfOutput {
     dense: Linear,
     layer_norm: LayerNorm,
@@ -291,6 +295,7 @@ impl BertSelfOutput {
 }
 
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L392
+#[derive(Clone)]
 struct BertAttention {
     self_attention: BertSelfAttention,
     self_output: BertSelfOutput,
@@ -319,6 +324,7 @@ impl Module for BertAttention {
 }
 
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L441
+#[derive(Clone)]
 struct BertIntermediate {
     dense: Linear,
     intermediate_act: HiddenActLayer,
@@ -346,6 +352,7 @@ impl Module for BertIntermediate {
 }
 
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L456
+#[derive(Clone)]
 struct BertOutput {
     dense: Linear,
     layer_norm: LayerNorm,
@@ -379,6 +386,7 @@ impl BertOutput {
 }
 
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L470
+#[derive(Clone)]
 struct BertLayer {
     attention: BertAttention,
     intermediate: BertIntermediate,
@@ -416,8 +424,10 @@ impl Module for BertLayer {
 }
 
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L556
+#[derive(Clone)]
 struct BertEncoder {
     layers: Vec<BertLayer>,
+    gradient_checkpointing: bool,
     span: tracing::Span,
 }
 
@@ -427,7 +437,11 @@ impl BertEncoder {
             .map(|index| BertLayer::load(vb.pp(&format!("layer.{index}")), config))
             .collect::<Result<Vec<_>>>()?;
         let span = tracing::span!(tracing::Level::TRACE, "encoder");
-        Ok(BertEncoder { layers, span })
+        Ok(BertEncoder {
+            layers,
+            gradient_checkpointing: false,
+            span,
+        })
     }
 }
 
@@ -437,7 +451,12 @@ impl Module for BertEncoder {
         let mut hidden_states = hidden_states.clone();
         // Use a loop rather than a fold as it's easier to modify when adding debug/...
         for layer in self.layers.iter() {
-            hidden_states = layer.forward(&hidden_states)?
+            hidden_states = if self.gradient_checkpointing {
+                let layer = layer.clone();
+                candle::checkpoint(move |xs| layer.forward(&xs[0]), &[&hidden_states])?
+            } else {
+                layer.forward(&hidden_states)?
+            }
         }
         Ok(hidden_states)
     }
@@ -481,6 +500,12 @@ impl BertModel {
         })
     }
 
+    /// When enabled, the activations of each layer are not kept alive during the forward pass
+    /// but recomputed on the backward pass, this reduces the memory usage when training.
+    pub fn set_gradient_checkpointing(&mut self, enabled: bool) {
+        self.encoder.gradient_checkpointing = enabled
+    }
+
     pub fn forward(&self, input_ids: &Tensor, token_type_ids: &Tensor) -> Result<Tensor> {
         let _enter = self.span.enter();
         let embedding_output = self.embeddings.forward(input_ids, token_type_ids)?;
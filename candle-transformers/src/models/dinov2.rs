@@ -1,4 +1,4 @@
-use candle::{IndexOp, Result, Tensor, D};
+use candle::{IndexOp, InterpolateMode, Result, Tensor, D};
 use candle_nn::{layer_norm, LayerNorm, Linear, Module, VarBuilder};
 
 const IMG_SIZE: usize = 518;
@@ -241,8 +241,12 @@ impl DinoVisionTransformer {
             .reshape((1, sqrt_n as usize, sqrt_n as usize, dim))?
             .transpose(2, 3)?
             .transpose(1, 2)?;
-        // This uses bicubic interpolation in the original implementation.
-        let patch_pos_embed = patch_pos_embed.upsample_nearest2d(h0 as usize, w0 as usize)?;
+        let patch_pos_embed = patch_pos_embed.interpolate2d_with_mode(
+            h0 as usize,
+            w0 as usize,
+            InterpolateMode::Bicubic,
+            false,
+        )?;
         let el_count = patch_pos_embed.shape().elem_count();
         let patch_pos_embed =
             patch_pos_embed
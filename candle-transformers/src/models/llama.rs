@@ -399,14 +399,34 @@ pub struct Llama {
     blocks: Vec<Block>,
     ln_f: RmsNorm,
     lm_head: Linear,
+    gradient_checkpointing: bool,
 }
 
 impl Llama {
+    /// When enabled, the activations of each block are not kept alive during the forward pass
+    /// but recomputed on the backward pass, this reduces the memory usage when training. The
+    /// kv cache is neither used nor updated in this mode.
+    pub fn set_gradient_checkpointing(&mut self, enabled: bool) {
+        self.gradient_checkpointing = enabled
+    }
+
     pub fn forward(&self, x: &Tensor, index_pos: usize, cache: &mut Cache) -> Result<Tensor> {
         let (_b_sz, seq_len) = x.dims2()?;
         let mut x = self.wte.forward(x)?;
         for (block_idx, block) in self.blocks.iter().enumerate() {
-            x = block.forward(&x, index_pos, block_idx, cache)?;
+            x = if self.gradient_checkpointing {
+                let block = block.clone();
+                let cache = Cache {
+                    use_kv_cache: false,
+                    ..cache.clone()
+                };
+                candle::checkpoint(
+                    move |xs| block.forward(&xs[0], index_pos, block_idx, &mut cache.clone()),
+                    &[&x],
+                )?
+            } else {
+                block.forward(&x, index_pos, block_idx, cache)?
+            };
         }
         let x = self.ln_f.forward(&x)?;
         let x = x.i((.., seq_len - 1, ..))?;
@@ -427,6 +447,7 @@ impl Llama {
             blocks,
             ln_f,
             lm_head,
+            gradient_checkpointing: false,
         })
     }
 }
@@ -377,6 +377,7 @@ pub struct Model {
     sliding_window: usize,
     device: Device,
     dtype: DType,
+    gradient_checkpointing: bool,
 }
 
 impl Model {
@@ -401,9 +402,17 @@ impl Model {
             sliding_window: cfg.sliding_window,
             device: vb.device().clone(),
             dtype: vb.dtype(),
+            gradient_checkpointing: false,
         })
     }
 
+    /// When enabled, the activations of each decoder layer are not kept alive during the forward
+    /// pass but recomputed on the backward pass, this reduces the memory usage when training.
+    /// The kv cache is neither used nor updated in this mode.
+    pub fn set_gradient_checkpointing(&mut self, enabled: bool) {
+        self.gradient_checkpointing = enabled
+    }
+
     fn prepare_decoder_attention_mask(
         &self,
         b_size: usize,
@@ -443,7 +452,20 @@ impl Model {
         };
         let mut xs = self.embed_tokens.forward(input_ids)?;
         for layer in self.layers.iter_mut() {
-            xs = layer.forward(&xs, attention_mask.as_ref(), seqlen_offset)?
+            xs = if self.gradient_checkpointing {
+                let mut layer = layer.clone();
+                layer.clear_kv_cache();
+                let attention_mask = attention_mask.clone();
+                candle::checkpoint(
+                    move |xs| {
+                        let mut layer = layer.clone();
+                        layer.forward(&xs[0], attention_mask.as_ref(), seqlen_offset)
+                    },
+                    &[&xs],
+                )?
+            } else {
+                layer.forward(&xs, attention_mask.as_ref(), seqlen_offset)?
+            }
         }
         xs.narrow(1, seq_len - 1, 1)?
             .apply(&self.norm)?
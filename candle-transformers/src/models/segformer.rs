@@ -1,5 +1,5 @@
 use crate::models::with_tracing::{conv2d, linear, Conv2d, Linear};
-use candle::{Module, ModuleT, Result, Tensor, D};
+use candle::{InterpolateMode, Module, ModuleT, Result, Tensor, D};
 use candle_nn::{conv2d_no_bias, layer_norm, Activation, Conv2dConfig, VarBuilder};
 use serde::Deserialize;
 use std::collections::HashMap;
@@ -557,7 +557,12 @@ impl SegformerDecodeHead {
                 height,
                 width,
             ))?;
-            let hidden_state = hidden_state.upsample_nearest2d(upsample_height, upsample_width)?;
+            let hidden_state = hidden_state.interpolate2d_with_mode(
+                upsample_height,
+                upsample_width,
+                InterpolateMode::Bilinear,
+                false,
+            )?;
             hidden_states.push(hidden_state);
         }
         hidden_states.reverse();
@@ -1,4 +1,4 @@
-use candle::{DType, IndexOp, Result, Tensor};
+use candle::{DType, IndexOp, InterpolateMode, Result, Tensor};
 use candle_nn::{Module, VarBuilder};
 
 use super::image_encoder::ImageEncoderViT;
@@ -144,7 +144,7 @@ impl Sam {
             multimask_output,
         )?;
         let mask = low_res_mask
-            .upsample_nearest2d(IMAGE_SIZE, IMAGE_SIZE)?
+            .interpolate2d_with_mode(IMAGE_SIZE, IMAGE_SIZE, InterpolateMode::Bilinear, false)?
             .get(0)?
             .i((.., ..original_h, ..original_w))?;
         Ok((mask, iou))
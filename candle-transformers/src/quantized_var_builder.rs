@@ -25,6 +25,31 @@ impl VarBuilder {
         })
     }
 
+    /// Creates a `VarBuilder` from a memory mapped gguf file. On the cpu, the tensors read their
+    /// quantized blocks directly from the memory mapped file so that the weights are not copied.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`memmap2::MmapOptions`].
+    pub unsafe fn from_mmaped_gguf<P: AsRef<std::path::Path>>(
+        p: P,
+        device: &Device,
+    ) -> Result<Self> {
+        let mut file = std::fs::File::open(p)?;
+        let mmap = Arc::new(memmap2::MmapOptions::new().map(&file)?);
+        let content = candle::quantized::gguf_file::Content::read(&mut file)?;
+        let mut data = std::collections::HashMap::new();
+        for tensor_name in content.tensor_infos.keys() {
+            let tensor = content.tensor_mmaped(&mmap, tensor_name, device)?;
+            data.insert(tensor_name.to_string(), Arc::new(tensor));
+        }
+        Ok(Self {
+            data: Arc::new(data),
+            path: Vec::new(),
+            device: device.clone(),
+        })
+    }
+
     pub fn from_gguf_buffer(buffer: &[u8], device: &Device) -> Result<Self> {
         let mut cursor = std::io::Cursor::new(buffer);
         let content = candle::quantized::gguf_file::Content::read(&mut cursor)?;
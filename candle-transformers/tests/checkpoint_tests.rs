@@ -0,0 +1,45 @@
+use candle::{DType, Device, Result, Tensor};
+use candle_nn::{VarBuilder, VarMap};
+use candle_transformers::models::llama;
+
+#[test]
+fn llama_gradient_checkpointing() -> Result<()> {
+    let dev = &Device::Cpu;
+    let cfg = llama::Config {
+        hidden_size: 16,
+        intermediate_size: 32,
+        vocab_size: 10,
+        num_hidden_layers: 2,
+        num_attention_heads: 4,
+        num_key_value_heads: 2,
+        use_flash_attn: false,
+        rms_norm_eps: 1e-5,
+        rope_theta: 10_000.,
+    };
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let mut model = llama::Llama::load(vb, &cfg)?;
+    let input = Tensor::new(&[[1u32, 4, 2, 7, 3]], dev)?;
+
+    let mut grads = vec![];
+    for enabled in [false, true] {
+        model.set_gradient_checkpointing(enabled);
+        let mut cache = llama::Cache::new(false, DType::F32, &cfg, dev)?;
+        let logits = model.forward(&input, 0, &mut cache)?;
+        let loss = logits.sqr()?.sum_all()?;
+        let store = loss.backward()?;
+        let vars = varmap.all_vars();
+        let vars = vars
+            .iter()
+            .map(|v| store.get(v).unwrap().flatten_all()?.to_vec1::<f32>())
+            .collect::<Result<Vec<_>>>()?;
+        grads.push((loss.to_scalar::<f32>()?, vars))
+    }
+    assert_eq!(grads[0].0, grads[1].0);
+    for (g1, g2) in grads[0].1.iter().zip(grads[1].1.iter()) {
+        for (v1, v2) in g1.iter().zip(g2.iter()) {
+            assert!((v1 - v2).abs() < 1e-5, "{v1} {v2}")
+        }
+    }
+    Ok(())
+}
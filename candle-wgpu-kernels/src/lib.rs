@@ -125,6 +125,11 @@ impl WgpuBackend {
         }
     }
 
+    /// Blocks until all the submitted work has completed.
+    pub fn synchronize(&self) {
+        self.device.poll(wgpu::Maintain::wait()).panic_on_timeout();
+    }
+
     pub fn create_buffer(&self, size: u64) -> WgpuBackendResult<Id<Buffer>> {
         let buffer = self.device.create_buffer(&BufferDescriptor {
             label: None,